@@ -73,6 +73,9 @@ constnamedbitmap! {
 
         /// Realm.Allocate allows viewing, creating, modifying and deleting realms
         PRIV_REALM_ALLOCATE("Realm.Allocate");
+
+        /// Permissions.Audit allows reading the ACLs of a datastore or namespace and below
+        PRIV_PERMISSIONS_AUDIT("Permissions.Audit");
     }
 }
 
@@ -190,6 +193,27 @@ pub const ROLE_TAPE_READER: u64 = 0
     | PRIV_TAPE_AUDIT
     | PRIV_TAPE_READ;
 
+#[rustfmt::skip]
+#[allow(clippy::identity_op)]
+/// Namespace.Admin can do anything on the namespace it is assigned to and below, including
+/// managing the ACLs there.
+pub const ROLE_NAMESPACE_ADMIN: u64 = 0
+    | PRIV_DATASTORE_AUDIT
+    | PRIV_DATASTORE_MODIFY
+    | PRIV_DATASTORE_READ
+    | PRIV_DATASTORE_VERIFY
+    | PRIV_DATASTORE_BACKUP
+    | PRIV_DATASTORE_PRUNE
+    | PRIV_PERMISSIONS_AUDIT
+    | PRIV_PERMISSIONS_MODIFY;
+
+#[rustfmt::skip]
+#[allow(clippy::identity_op)]
+/// Namespace.Audit can audit the namespace it is assigned to and below, including its ACLs.
+pub const ROLE_NAMESPACE_AUDIT: u64 = 0
+    | PRIV_DATASTORE_AUDIT
+    | PRIV_PERMISSIONS_AUDIT;
+
 /// NoAccess can be used to remove privileges from specific (sub-)paths
 pub const ROLE_NAME_NO_ACCESS: &str = "NoAccess";
 
@@ -233,6 +257,10 @@ pub enum Role {
     TapeOperator = ROLE_TAPE_OPERATOR,
     /// Tape Reader
     TapeReader = ROLE_TAPE_READER,
+    /// Namespace Administrator (including ACLs below the namespace)
+    NamespaceAdmin = ROLE_NAMESPACE_ADMIN,
+    /// Namespace Auditor (including ACLs below the namespace)
+    NamespaceAudit = ROLE_NAMESPACE_AUDIT,
 }
 
 impl FromStr for Role {
@@ -15,7 +15,7 @@ use crate::{
     BACKUP_ID_RE, BACKUP_NS_RE, BACKUP_TIME_RE, BACKUP_TYPE_RE, DATASTORE_NOTIFY_STRING_SCHEMA,
     GC_SCHEDULE_SCHEMA, GROUP_OR_SNAPSHOT_PATH_REGEX_STR, PROXMOX_SAFE_ID_FORMAT,
     PROXMOX_SAFE_ID_REGEX_STR, PRUNE_SCHEDULE_SCHEMA, SHA256_HEX_REGEX, SINGLE_LINE_COMMENT_SCHEMA,
-    SNAPSHOT_PATH_REGEX_STR, UPID,
+    SNAPSHOT_PATH_REGEX_STR, UPID, UUID_FORMAT,
 };
 
 const_regex! {
@@ -38,6 +38,8 @@ const_regex! {
     pub GROUP_OR_SNAPSHOT_PATH_REGEX = concatcp!(r"^", GROUP_OR_SNAPSHOT_PATH_REGEX_STR, r"$");
 
     pub DATASTORE_MAP_REGEX = concatcp!(r"^(?:", PROXMOX_SAFE_ID_REGEX_STR, r"=)?", PROXMOX_SAFE_ID_REGEX_STR, r"$");
+
+    pub CLIENT_VERSION_REGEX = r"^\d{1,9}\.\d{1,9}(?:\.\d{1,9})?$";
 }
 
 pub const CHUNK_DIGEST_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&SHA256_HEX_REGEX);
@@ -72,10 +74,28 @@ pub const BACKUP_TIME_SCHEMA: Schema = IntegerSchema::new("Backup time (Unix epo
     .minimum(1)
     .schema();
 
+pub const PREFETCH_WINDOW_SCHEMA: Schema =
+    IntegerSchema::new("Number of chunks read ahead when reading image archives.")
+        .minimum(0)
+        .maximum(64)
+        .default(0)
+        .schema();
+
 pub const BACKUP_GROUP_SCHEMA: Schema = StringSchema::new("Backup Group")
     .format(&BACKUP_GROUP_FORMAT)
     .schema();
 
+pub const SNAPSHOT_TAG_SCHEMA: Schema = StringSchema::new("Snapshot tag.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(1)
+    .max_length(64)
+    .schema();
+
+pub const SNAPSHOT_TAG_LIST_SCHEMA: Schema =
+    ArraySchema::new("List of snapshot tags.", &SNAPSHOT_TAG_SCHEMA)
+        .max_length(32)
+        .schema();
+
 /// The maximal, inclusive depth for namespaces from the root ns downwards
 ///
 /// The datastore root name space is at depth zero (0), so we have in total eight (8) levels
@@ -203,12 +223,98 @@ pub enum DatastoreFSyncLevel {
     Filesystem,
 }
 
+/// Compression used for chunks stored on a datastore.
+///
+/// The algorithm is recorded in the blob magic, so chunks using different algorithms can be
+/// mixed on a datastore.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkCompression {
+    /// Store chunks uncompressed.
+    None,
+    /// zstd with the given compression level.
+    Zstd(i32),
+    /// lz4, faster but with less compression than zstd.
+    Lz4,
+}
+
+/// Minimum supported zstd compression level.
+pub const CHUNK_COMPRESSION_ZSTD_MIN_LEVEL: i32 = 1;
+/// Maximum supported zstd compression level.
+pub const CHUNK_COMPRESSION_ZSTD_MAX_LEVEL: i32 = 19;
+
+impl Default for ChunkCompression {
+    /// zstd level 1, as used by the client.
+    fn default() -> Self {
+        Self::Zstd(1)
+    }
+}
+
+impl std::str::FromStr for ChunkCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.split_once(':') {
+            None if s == "none" => ChunkCompression::None,
+            None if s == "lz4" => ChunkCompression::Lz4,
+            None if s == "zstd" => ChunkCompression::default(),
+            Some(("zstd", level)) => {
+                let level: i32 = level
+                    .parse()
+                    .map_err(|_| format_err!("invalid zstd compression level '{level}'"))?;
+                if !(CHUNK_COMPRESSION_ZSTD_MIN_LEVEL..=CHUNK_COMPRESSION_ZSTD_MAX_LEVEL)
+                    .contains(&level)
+                {
+                    bail!(
+                        "zstd compression level {level} out of range ({}..{})",
+                        CHUNK_COMPRESSION_ZSTD_MIN_LEVEL,
+                        CHUNK_COMPRESSION_ZSTD_MAX_LEVEL,
+                    );
+                }
+                ChunkCompression::Zstd(level)
+            }
+            _ => bail!("expected 'none', 'lz4', 'zstd' or 'zstd:<level>', got '{s}'"),
+        })
+    }
+}
+
+// used for serializing below, caution!
+impl fmt::Display for ChunkCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkCompression::None => f.write_str("none"),
+            ChunkCompression::Zstd(level) => write!(f, "zstd:{level}"),
+            ChunkCompression::Lz4 => f.write_str("lz4"),
+        }
+    }
+}
+
+proxmox_serde::forward_deserialize_to_from_str!(ChunkCompression);
+proxmox_serde::forward_serialize_to_display!(ChunkCompression);
+
+pub const CHUNK_COMPRESSION_SCHEMA: Schema = StringSchema::new(
+    "Compression for chunks (re)compressed by the server. Encrypted chunks are always stored \
+    as uploaded.",
+)
+.format(&ApiStringFormat::VerifyFn(|s| {
+    s.parse::<ChunkCompression>().map(drop)
+}))
+.type_text("none|lz4|zstd[:<level>]")
+.schema();
+
 #[api(
     properties: {
         "chunk-order": {
             type: ChunkOrder,
             optional: true,
         },
+        compression: {
+            schema: CHUNK_COMPRESSION_SCHEMA,
+            optional: true,
+        },
+        "chunk-cache-size": {
+            optional: true,
+            minimum: 0,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Default)]
@@ -220,6 +326,14 @@ pub struct DatastoreTuning {
     pub chunk_order: Option<ChunkOrder>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sync_level: Option<DatastoreFSyncLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<ChunkCompression>,
+    /// Size of the in-memory chunk cache in MiB, used for restore and verify. 0 disables it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_cache_size: Option<u64>,
+    /// Verify the CRC of uploaded chunks and reject corrupt ones, instead of recomputing it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_upload: Option<bool>,
 }
 
 pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore tuning options")
@@ -228,6 +342,187 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
     ))
     .schema();
 
+// placeholders in archive name patterns, replaced by the group of the backup
+fn expand_naming_template(pattern: &str, group: &BackupGroup) -> String {
+    pattern
+        .replace("{type}", group.ty.as_str())
+        .replace("{id}", &regex::escape(&group.id))
+}
+
+fn compile_naming_pattern(pattern: &str, group: &BackupGroup) -> Result<regex::Regex, Error> {
+    // always match the whole name
+    let pattern = format!("^(?:{})$", expand_naming_template(pattern, group));
+    regex::Regex::new(&pattern).map_err(|err| format_err!("invalid naming pattern - {err}"))
+}
+
+fn verify_naming_pattern(pattern: &str) -> Result<(), Error> {
+    let group = BackupGroup::new(BackupType::Vm, "100");
+    compile_naming_pattern(pattern, &group).map(drop)
+}
+
+pub const NAMING_POLICY_ID_SCHEMA: Schema =
+    StringSchema::new("Regular expression the backup IDs of new groups must match.")
+        .format(&ApiStringFormat::VerifyFn(verify_naming_pattern))
+        .max_length(256)
+        .schema();
+
+pub const NAMING_POLICY_ARCHIVE_SCHEMA: Schema = StringSchema::new(
+    "Regular expression the archive names (without server-side extension) of new backups \
+    must match. '{type}' and '{id}' are replaced by the backup type and ID.",
+)
+.format(&ApiStringFormat::VerifyFn(verify_naming_pattern))
+.max_length(256)
+.schema();
+
+#[api(
+    properties: {
+        "vm-id": {
+            schema: NAMING_POLICY_ID_SCHEMA,
+            optional: true,
+        },
+        "ct-id": {
+            schema: NAMING_POLICY_ID_SCHEMA,
+            optional: true,
+        },
+        "host-id": {
+            schema: NAMING_POLICY_ID_SCHEMA,
+            optional: true,
+        },
+        archive: {
+            schema: NAMING_POLICY_ARCHIVE_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+/// Naming policy for new backups, checked when a backup session starts
+pub struct DatastoreNamingPolicy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vm_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ct_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+}
+
+impl DatastoreNamingPolicy {
+    /// Check that the ID of `group` matches the policy of its backup type.
+    pub fn check_group(&self, group: &BackupGroup) -> Result<(), Error> {
+        let pattern = match group.ty {
+            BackupType::Vm => &self.vm_id,
+            BackupType::Ct => &self.ct_id,
+            BackupType::Host => &self.host_id,
+        };
+        if let Some(pattern) = pattern {
+            if !compile_naming_pattern(pattern, group)?.is_match(&group.id) {
+                bail!(
+                    "backup ID '{}' violates the naming policy of the datastore ('{pattern}')",
+                    group.id,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that `archive_name` (without server-side extension) of a backup
+    /// of `group` matches the policy.
+    pub fn check_archive(&self, group: &BackupGroup, archive_name: &str) -> Result<(), Error> {
+        if let Some(ref pattern) = self.archive {
+            if !compile_naming_pattern(pattern, group)?.is_match(archive_name) {
+                bail!(
+                    "archive name '{archive_name}' violates the naming policy of the datastore \
+                    ('{pattern}')",
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+pub const DATASTORE_NAMING_POLICY_STRING_SCHEMA: Schema =
+    StringSchema::new("Datastore naming policy")
+        .format(&ApiStringFormat::PropertyString(
+            &DatastoreNamingPolicy::API_SCHEMA,
+        ))
+        .schema();
+
+pub const EPHEMERAL_TTL_SCHEMA: Schema =
+    StringSchema::new("Time span after which snapshots are removed, for example '3d' or '1w 2d'.")
+        .format(&ApiStringFormat::VerifyFn(verify_ephemeral_ttl))
+        .schema();
+
+fn verify_ephemeral_ttl(ttl: &str) -> Result<(), Error> {
+    let _: proxmox_time::TimeSpan = ttl.parse()?;
+    Ok(())
+}
+
+#[api(
+    properties: {
+        ttl: {
+            schema: EPHEMERAL_TTL_SCHEMA,
+        },
+        "remove-empty": {
+            description: "Remove the datastore, including its data, once it stayed empty for the TTL.",
+            optional: true,
+            type: bool,
+            default: false,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Expiry of an ephemeral datastore, used for scratch areas like migration staging or restore
+/// tests
+pub struct DatastoreEphemeral {
+    pub ttl: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remove_empty: Option<bool>,
+}
+
+impl DatastoreEphemeral {
+    /// The TTL in seconds.
+    pub fn ttl_seconds(&self) -> Result<i64, Error> {
+        let span: proxmox_time::TimeSpan = self
+            .ttl
+            .parse()
+            .map_err(|err| format_err!("invalid ttl '{}' - {}", self.ttl, err))?;
+        Ok(f64::from(span) as i64)
+    }
+}
+
+pub const DATASTORE_EPHEMERAL_STRING_SCHEMA: Schema =
+    StringSchema::new("Mark the datastore as ephemeral, snapshots expire after a TTL.")
+        .format(&ApiStringFormat::PropertyString(
+            &DatastoreEphemeral::API_SCHEMA,
+        ))
+        .schema();
+
+pub const MIN_CLIENT_VERSION_SCHEMA: Schema = StringSchema::new(
+    "Minimum version of the client required to create backups, as 'major.minor[.release]'.",
+)
+.format(&ApiStringFormat::Pattern(&CLIENT_VERSION_REGEX))
+.schema();
+
+/// Parse a client version ('major.minor[.release]') into a comparable tuple.
+pub fn parse_client_version(version: &str) -> Result<(u32, u32, u32), Error> {
+    if !CLIENT_VERSION_REGEX.is_match(version) {
+        bail!("invalid client version '{version}'");
+    }
+    let mut parts = version.split('.').map(|part| part.parse::<u32>());
+    let major = parts.next().unwrap()?;
+    let minor = parts.next().unwrap()?;
+    let release = parts.next().transpose()?.unwrap_or(0);
+    Ok((major, minor, release))
+}
+
+pub const BACKING_DEVICE_SCHEMA: Schema =
+    StringSchema::new("Filesystem UUID of the removable device backing the datastore.")
+        .format(&UUID_FORMAT)
+        .schema();
+
 #[api(
     properties: {
         name: {
@@ -236,6 +531,10 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
         path: {
             schema: DIR_NAME_SCHEMA,
         },
+        "backing-device": {
+            optional: true,
+            schema: BACKING_DEVICE_SCHEMA,
+        },
         "notify-user": {
             optional: true,
             type: Userid,
@@ -252,6 +551,10 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
             optional: true,
             schema: GC_SCHEDULE_SCHEMA,
         },
+        "gc-splay": {
+            optional: true,
+            schema: crate::JOB_SPLAY_SCHEMA,
+        },
         "prune-schedule": {
             optional: true,
             schema: PRUNE_SCHEDULE_SCHEMA,
@@ -264,10 +567,28 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
             optional: true,
             type: bool,
         },
+        "known-chunk-query": {
+            description: "Allow backup clients with Datastore.Read on the datastore to query which chunks exist anywhere in it.",
+            optional: true,
+            type: bool,
+            default: false,
+        },
         tuning: {
             optional: true,
             schema: DATASTORE_TUNING_STRING_SCHEMA,
         },
+        "naming-policy": {
+            optional: true,
+            schema: DATASTORE_NAMING_POLICY_STRING_SCHEMA,
+        },
+        "min-client-version": {
+            optional: true,
+            schema: MIN_CLIENT_VERSION_SCHEMA,
+        },
+        ephemeral: {
+            optional: true,
+            schema: DATASTORE_EPHEMERAL_STRING_SCHEMA,
+        },
         "maintenance-mode": {
             optional: true,
             format: &ApiStringFormat::PropertyString(&MaintenanceMode::API_SCHEMA),
@@ -291,6 +612,10 @@ pub struct DataStoreConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub gc_schedule: Option<String>,
 
+    /// Maximum random delay of scheduled garbage collection runs in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gc_splay: Option<u64>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub prune_schedule: Option<String>,
 
@@ -301,6 +626,9 @@ pub struct DataStoreConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub verify_new: Option<bool>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub known_chunk_query: Option<bool>,
+
     /// Send job email notification to this user
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notify_user: Option<Userid>,
@@ -317,9 +645,26 @@ pub struct DataStoreConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tuning: Option<String>,
 
+    /// Naming policy for new backups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub naming_policy: Option<String>,
+
+    /// Minimum client version allowed to create backups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_client_version: Option<String>,
+
+    /// Expiry of snapshots, if the datastore is ephemeral
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ephemeral: Option<String>,
+
     /// Maintenance mode, type is either 'offline' or 'read-only', message should be enclosed in "
     #[serde(skip_serializing_if = "Option::is_none")]
     pub maintenance_mode: Option<String>,
+
+    /// The datastore is located on a removable device, mounted on demand on `path`.
+    #[updater(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backing_device: Option<String>,
 }
 
 #[api]
@@ -346,17 +691,40 @@ impl DataStoreConfig {
             path,
             comment: None,
             gc_schedule: None,
+            gc_splay: None,
             prune_schedule: None,
             keep: Default::default(),
             verify_new: None,
+            known_chunk_query: None,
             notify_user: None,
             notify: None,
             notification_mode: None,
             tuning: None,
+            naming_policy: None,
+            min_client_version: None,
+            ephemeral: None,
             maintenance_mode: None,
+            backing_device: None,
         }
     }
 
+    /// Returns true if the datastore is located on a removable device.
+    pub fn is_removable(&self) -> bool {
+        self.backing_device.is_some()
+    }
+
+    /// Returns the expiry settings if the datastore is ephemeral.
+    pub fn get_ephemeral(&self) -> Result<Option<DatastoreEphemeral>, Error> {
+        let ephemeral = match self.ephemeral.as_deref() {
+            Some(ephemeral) => ephemeral,
+            None => return Ok(None),
+        };
+        let ephemeral = DatastoreEphemeral::deserialize(
+            proxmox_schema::de::SchemaDeserializer::new(ephemeral, &DatastoreEphemeral::API_SCHEMA),
+        )?;
+        Ok(Some(ephemeral))
+    }
+
     pub fn get_maintenance_mode(&self) -> Option<MaintenanceMode> {
         self.maintenance_mode.as_ref().and_then(|str| {
             MaintenanceMode::deserialize(proxmox_schema::de::SchemaDeserializer::new(
@@ -374,6 +742,7 @@ impl DataStoreConfig {
         match current_type {
             Some(MaintenanceType::ReadOnly) => { /* always OK  */ }
             Some(MaintenanceType::Offline) => { /* always OK  */ }
+            Some(MaintenanceType::Unmount) => { /* always OK  */ }
             Some(MaintenanceType::Delete) => {
                 match new_type {
                     Some(MaintenanceType::Delete) => { /* allow to delete a deleted storage */ }
@@ -480,6 +849,29 @@ pub struct SnapshotVerifyState {
     pub state: VerifyState,
 }
 
+#[api(
+    properties: {
+        upid: {
+            type: UPID,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A signed report of a verification task.
+pub struct VerifyAttestationListItem {
+    /// Time the report was created
+    pub time: i64,
+    /// UPID of the verify task, also used to identify the report
+    pub upid: UPID,
+    /// Number of verified snapshots
+    pub snapshots: u64,
+    /// Number of snapshots which failed verification
+    pub failed: u64,
+    /// Fingerprint of the certificate whose key signed the report
+    pub fingerprint: String,
+}
+
 /// A namespace provides a logical separation between backup groups from different domains
 /// (cluster, sites, ...) where uniqueness cannot be guaranteed anymore. It allows users to share a
 /// datastore (i.e., one deduplication domain (chunk store)) with multiple (trusted) sites and
@@ -1102,6 +1494,76 @@ impl fmt::Display for BackupDir {
     }
 }
 
+pub const BACKUP_PART_SCHEMA: Schema = StringSchema::new(
+    "Backup group or snapshot path, for example 'vm/100' or 'vm/100/2024-01-01T00:00:00Z'.",
+)
+.format(&ApiStringFormat::Pattern(&GROUP_OR_SNAPSHOT_PATH_REGEX))
+.schema();
+
+pub const BACKUP_PART_LIST_SCHEMA: Schema =
+    ArraySchema::new("List of backup groups and snapshots.", &BACKUP_PART_SCHEMA)
+        .min_length(1)
+        .schema();
+
+#[api]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Action applied to a list of backup groups and snapshots.
+pub enum BulkAction {
+    /// Remove the snapshots, or groups with all their snapshots
+    Delete,
+    /// Protect the snapshots, or all snapshots of the groups
+    Protect,
+    /// Remove the protection of the snapshots, or all snapshots of the groups
+    Unprotect,
+    /// Verify the snapshots, or all snapshots of the groups
+    Verify,
+}
+serde_plain::derive_display_from_serialize!(BulkAction);
+
+#[api(
+    properties: {
+        item: {
+            schema: BACKUP_PART_SCHEMA,
+        },
+    },
+)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Result of a bulk action for a single backup group or snapshot.
+pub struct BulkActionItemResult {
+    pub item: String,
+    /// Error message, if the action failed for this item
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[api(
+    properties: {
+        upid: {
+            type: UPID,
+        },
+        action: {
+            type: BulkAction,
+        },
+        items: {
+            type: Array,
+            items: {
+                type: BulkActionItemResult,
+            },
+        },
+    },
+)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Per item results of a bulk action task.
+pub struct BulkActionResult {
+    pub upid: String,
+    pub action: BulkAction,
+    /// Processed items, in order. Items not processed because the task was aborted are missing.
+    pub items: Vec<BulkActionItemResult>,
+}
+
 /// Used when both a backup group or a directory can be valid.
 pub enum BackupPart {
     Group(BackupGroup),
@@ -1127,6 +1589,53 @@ impl std::str::FromStr for BackupPart {
     }
 }
 
+#[api]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Where a snapshot comes from, stored in the unprotected part of its manifest.
+pub struct SnapshotLineage {
+    /// The previous snapshot of the group, used as base for an incremental backup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_snapshot: Option<String>,
+    /// Version of the client which created the snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<String>,
+    /// Chunk size used by the client's chunker in bytes (average size for
+    /// dynamically chunked archives)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u64>,
+}
+
+#[api(
+    properties: {
+        "verify-state": {
+            type: SnapshotVerifyState,
+        },
+        "remote-verify-state": {
+            type: VerifyState,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Replication state of a snapshot synced with `verify-synced` set, stored in the unprotected
+/// part of its manifest.
+pub struct SnapshotReplication {
+    /// Source of the snapshot, `<remote>/<store>` or the local datastore name
+    pub source: String,
+    /// ID of the sync job, not set for manual pulls
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<String>,
+    /// Time of the sync (epoch)
+    pub time: i64,
+    /// Verification of the synced copy
+    pub verify_state: SnapshotVerifyState,
+    /// Verification of the source snapshot on the remote, for syncs from a remote
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_verify_state: Option<VerifyState>,
+}
+
 #[api(
     properties: {
         "backup": { type: BackupDir },
@@ -1151,6 +1660,18 @@ impl std::str::FromStr for BackupPart {
             type: Authid,
             optional: true,
         },
+        tags: {
+            schema: SNAPSHOT_TAG_LIST_SCHEMA,
+            optional: true,
+        },
+        lineage: {
+            type: SnapshotLineage,
+            optional: true,
+        },
+        replication: {
+            type: SnapshotReplication,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -1179,6 +1700,15 @@ pub struct SnapshotListItem {
     /// Protection from prunes
     #[serde(default)]
     pub protected: bool,
+    /// Tags set by the client at backup time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// Where the snapshot comes from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lineage: Option<SnapshotLineage>,
+    /// Replication and verification by a sync job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replication: Option<SnapshotReplication>,
 }
 
 #[api(
@@ -1329,6 +1859,145 @@ pub struct GarbageCollectionStatus {
     pub still_bad: usize,
 }
 
+#[api(
+    properties: {
+        backup: {
+            type: BackupGroup,
+        },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Deduplication statistics of a single backup group.
+pub struct GroupDedupStats {
+    /// The namespace of the group, unset for the root namespace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    #[serde(flatten)]
+    pub backup: BackupGroup,
+    /// Number of snapshots in the group.
+    pub snapshot_count: u64,
+    /// Sum of the (uncompressed) sizes of all index files of all snapshots.
+    pub logical_size: u64,
+    /// Bytes on disk of all chunks referenced by the group.
+    pub referenced_size: u64,
+    /// Bytes on disk of chunks referenced only by this group.
+    pub unique_size: u64,
+    /// Bytes on disk of chunks also referenced by other groups.
+    pub shared_size: u64,
+}
+
+#[api(
+    properties: {
+        "upid": {
+            optional: true,
+            type: UPID,
+        },
+        groups: {
+            type: Array,
+            items: {
+                type: GroupDedupStats,
+            },
+        },
+    },
+)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Deduplication statistics of a datastore.
+pub struct DedupStats {
+    /// The task which computed the statistics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+    /// Time the statistics were computed at.
+    pub time: i64,
+    /// Sum of the logical sizes of all groups.
+    pub logical_size: u64,
+    /// Bytes on disk of all referenced chunks.
+    pub disk_size: u64,
+    /// Number of referenced chunks.
+    pub disk_chunks: usize,
+    /// Number of referenced chunks which are missing.
+    pub missing_chunks: usize,
+    /// Per group statistics.
+    pub groups: Vec<GroupDedupStats>,
+}
+
+pub const COMPRESSION_SAMPLE_SCHEMA: Schema =
+    IntegerSchema::new("Percentage of the chunks of each group to sample.")
+        .minimum(1)
+        .maximum(100)
+        .default(10)
+        .schema();
+
+#[api(
+    properties: {
+        backup: {
+            type: BackupGroup,
+        },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Estimated compressibility of the chunks of a single backup group.
+pub struct GroupCompressionStats {
+    /// The namespace of the group, unset for the root namespace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    #[serde(flatten)]
+    pub backup: BackupGroup,
+    /// Number of sampled chunks.
+    pub sampled_chunks: u64,
+    /// Sum of the uncompressed sizes of the sampled chunks.
+    pub logical_size: u64,
+    /// Bytes on disk of the sampled chunks.
+    pub stored_size: u64,
+    /// Number of sampled chunks which are encrypted.
+    pub encrypted_chunks: u64,
+    /// Number of sampled chunks stored without compression.
+    pub uncompressed_chunks: u64,
+    /// Estimated ratio of logical to stored size.
+    pub compression_ratio: f64,
+    /// Estimated fraction of encrypted chunks.
+    pub encrypted_fraction: f64,
+}
+
+#[api(
+    properties: {
+        "upid": {
+            optional: true,
+            type: UPID,
+        },
+        groups: {
+            type: Array,
+            items: {
+                type: GroupCompressionStats,
+            },
+        },
+    },
+)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Compressibility report of a datastore, estimated from a sample of chunks.
+pub struct CompressionStats {
+    /// The task which computed the statistics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+    /// Time the statistics were computed at.
+    pub time: i64,
+    /// Percentage of the chunks of each group which were sampled.
+    pub sample: u64,
+    /// Per group statistics.
+    pub groups: Vec<GroupCompressionStats>,
+}
+
 #[api(
     properties: {
         "status": {
@@ -1361,6 +2030,19 @@ pub struct GarbageCollectionJobStatus {
     pub duration: Option<i64>,
 }
 
+#[api()]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Chunk cache statistics.
+pub struct ChunkCacheStats {
+    /// Maximum number of cached chunks.
+    pub capacity: u64,
+    /// Number of chunks served from the cache.
+    pub hits: u64,
+    /// Number of chunks which had to be loaded from disk.
+    pub misses: u64,
+}
+
 #[api(
     properties: {
         "gc-status": {
@@ -1371,6 +2053,10 @@ pub struct GarbageCollectionJobStatus {
             type: Counts,
             optional: true,
         },
+        "chunk-cache": {
+            type: ChunkCacheStats,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize)]
@@ -1389,6 +2075,9 @@ pub struct DataStoreStatus {
     /// Group/Snapshot counts
     #[serde(skip_serializing_if = "Option::is_none")]
     pub counts: Option<Counts>,
+    /// Statistics of the chunk cache, if enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_cache: Option<ChunkCacheStats>,
 }
 
 #[api(
@@ -1460,6 +2149,113 @@ impl DataStoreStatusListItem {
     }
 }
 
+#[api]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+/// Health state of a datastore, ordered by severity.
+pub enum DataStoreHealthState {
+    /// Nothing to worry about.
+    Ok,
+    /// The datastore is offline for maintenance, so its health is unknown.
+    Maintenance,
+    /// Something needs attention.
+    Warning,
+    /// Backups are at risk.
+    Critical,
+}
+
+#[api]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// The part of a datastore a health check looks at.
+pub enum DataStoreHealthCheck {
+    /// Access to the datastore and its storage.
+    Io,
+    /// Maintenance mode.
+    Maintenance,
+    /// Free space on the underlying storage.
+    Space,
+    /// Result and schedule of the garbage collection.
+    GarbageCollection,
+    /// Results and schedules of the verification jobs.
+    Verify,
+    /// Results and schedules of the sync jobs.
+    Sync,
+    /// Results and schedules of the prune jobs.
+    Prune,
+}
+
+#[api(
+    properties: {
+        check: {
+            type: DataStoreHealthCheck,
+        },
+        state: {
+            type: DataStoreHealthState,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A reason why a datastore is not healthy.
+pub struct DataStoreHealthReason {
+    pub check: DataStoreHealthCheck,
+    pub state: DataStoreHealthState,
+    /// Description of the problem.
+    pub message: String,
+}
+
+#[api(
+    properties: {
+        store: {
+            schema: DATASTORE_SCHEMA,
+        },
+        state: {
+            type: DataStoreHealthState,
+        },
+        reasons: {
+            type: Array,
+            items: {
+                type: DataStoreHealthReason,
+            },
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Aggregated health status of a datastore
+pub struct DataStoreHealth {
+    pub store: String,
+    /// The most severe state of all reasons, `ok` if there are none.
+    pub state: DataStoreHealthState,
+    pub reasons: Vec<DataStoreHealthReason>,
+}
+
+impl DataStoreHealth {
+    pub fn new(store: &str) -> Self {
+        Self {
+            store: store.to_owned(),
+            state: DataStoreHealthState::Ok,
+            reasons: Vec::new(),
+        }
+    }
+
+    /// Add a reason, raises the state if needed.
+    pub fn add(
+        &mut self,
+        check: DataStoreHealthCheck,
+        state: DataStoreHealthState,
+        message: String,
+    ) {
+        self.state = self.state.max(state);
+        self.reasons.push(DataStoreHealthReason {
+            check,
+            state,
+            message,
+        });
+    }
+}
+
 pub const ADMIN_DATASTORE_LIST_SNAPSHOTS_RETURN_TYPE: ReturnType = ReturnType {
     optional: false,
     schema: &ArraySchema::new(
@@ -1569,3 +2365,86 @@ pub fn print_store_and_ns(store: &str, ns: &BackupNamespace) -> String {
         format!("datastore '{}', namespace '{}'", store, ns)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_naming_policy_check_group() {
+        let policy = DatastoreNamingPolicy {
+            vm_id: Some("[0-9]+".to_string()),
+            host_id: Some("web-[a-z]+".to_string()),
+            ..Default::default()
+        };
+
+        assert!(policy
+            .check_group(&BackupGroup::new(BackupType::Vm, "100"))
+            .is_ok());
+        assert!(policy
+            .check_group(&BackupGroup::new(BackupType::Vm, "vm100"))
+            .is_err());
+        // the whole ID has to match
+        assert!(policy
+            .check_group(&BackupGroup::new(BackupType::Host, "web-a"))
+            .is_ok());
+        assert!(policy
+            .check_group(&BackupGroup::new(BackupType::Host, "web-a1"))
+            .is_err());
+        assert!(policy
+            .check_group(&BackupGroup::new(BackupType::Host, "my-web-a"))
+            .is_err());
+        // no pattern for the type
+        assert!(policy
+            .check_group(&BackupGroup::new(BackupType::Ct, "anything"))
+            .is_ok());
+
+        let policy = DatastoreNamingPolicy::default();
+        assert!(policy
+            .check_group(&BackupGroup::new(BackupType::Vm, "vm100"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_naming_policy_check_archive() {
+        let policy = DatastoreNamingPolicy {
+            archive: Some(r"{id}-[a-z]+\.pxar|.*\.conf".to_string()),
+            ..Default::default()
+        };
+        let group = BackupGroup::new(BackupType::Host, "web1");
+
+        assert!(policy.check_archive(&group, "web1-root.pxar").is_ok());
+        assert!(policy.check_archive(&group, "qemu-server.conf").is_ok());
+        assert!(policy.check_archive(&group, "web2-root.pxar").is_err());
+        assert!(policy.check_archive(&group, "web1-root.pxar.bak").is_err());
+
+        // the ID is matched literally
+        let group = BackupGroup::new(BackupType::Host, "web.1");
+        assert!(policy.check_archive(&group, "web.1-root.pxar").is_ok());
+        assert!(policy.check_archive(&group, "webx1-root.pxar").is_err());
+
+        let policy = DatastoreNamingPolicy {
+            archive: Some("{type}-[0-9]+\\.img".to_string()),
+            ..Default::default()
+        };
+        let group = BackupGroup::new(BackupType::Vm, "100");
+        assert!(policy.check_archive(&group, "vm-0.img").is_ok());
+        assert!(policy.check_archive(&group, "ct-0.img").is_err());
+    }
+
+    #[test]
+    fn test_parse_client_version() {
+        assert_eq!(parse_client_version("3.2").unwrap(), (3, 2, 0));
+        assert_eq!(parse_client_version("3.2.7").unwrap(), (3, 2, 7));
+        assert_eq!(parse_client_version("10.0.12").unwrap(), (10, 0, 12));
+
+        // compared numerically, not as strings
+        assert!(parse_client_version("3.10").unwrap() > parse_client_version("3.9.9").unwrap());
+
+        for invalid in [
+            "", "3", "3.", "3.2.", "3.2.1.0", "3.x", "v3.2", " 3.2", "3.2-1",
+        ] {
+            assert!(parse_client_version(invalid).is_err(), "{invalid:?}");
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, ApiStringFormat, Schema, StringSchema, Updater};
+use proxmox_time::TimeSpan;
+
+use crate::{
+    BackupGroup, BackupNamespace, BackupType, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
+    DATASTORE_SCHEMA, JOB_ID_SCHEMA, SINGLE_LINE_COMMENT_SCHEMA,
+};
+
+pub const EXPECTATION_MAX_AGE_SCHEMA: Schema = StringSchema::new(
+    "Maximum age of the newest snapshot of a group, as time span (for example '24h' or '1d 12h').",
+)
+.format(&ApiStringFormat::VerifyFn(verify_time_span))
+.schema();
+
+fn verify_time_span(span: &str) -> Result<(), Error> {
+    let _: TimeSpan = span.parse()?;
+    Ok(())
+}
+
+#[api(
+    properties: {
+        id: {
+            schema: JOB_ID_SCHEMA,
+        },
+        store: {
+            schema: DATASTORE_SCHEMA,
+        },
+        ns: {
+            optional: true,
+            schema: BACKUP_NAMESPACE_SCHEMA,
+        },
+        "backup-type": {
+            type: BackupType,
+            optional: true,
+        },
+        "backup-id": {
+            schema: BACKUP_ID_SCHEMA,
+            optional: true,
+        },
+        "max-age": {
+            schema: EXPECTATION_MAX_AGE_SCHEMA,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Expected backup frequency of backup groups
+pub struct BackupExpectationConfig {
+    #[updater(skip)]
+    pub id: String,
+    pub store: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    /// Namespace of the groups, not recursive (default: root namespace)
+    pub ns: Option<BackupNamespace>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Only check groups of this type (default: all)
+    pub backup_type: Option<BackupType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Only check the group with this ID, it is stale if it does not exist (default: all)
+    pub backup_id: Option<String>,
+    pub max_age: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Disable this expectation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable: Option<bool>,
+}
+
+impl BackupExpectationConfig {
+    pub fn acl_path(&self) -> Vec<&str> {
+        match self.ns.as_ref() {
+            Some(ns) => ns.acl_path(&self.store),
+            None => vec!["datastore", &self.store],
+        }
+    }
+
+    /// The maximum age in seconds.
+    pub fn max_age_seconds(&self) -> Result<i64, Error> {
+        let span: TimeSpan = self
+            .max_age
+            .parse()
+            .map_err(|err| format_err!("invalid max-age '{}' - {}", self.max_age, err))?;
+        Ok(f64::from(span) as i64)
+    }
+
+    /// Whether `group` is covered by this expectation.
+    pub fn matches(&self, group: &BackupGroup) -> bool {
+        self.backup_type.map_or(true, |ty| ty == group.ty)
+            && self.backup_id.as_ref().map_or(true, |id| *id == group.id)
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, ApiStringFormat, IntegerSchema, Schema, StringSchema, Updater};
+
+use crate::{DATASTORE_SCHEMA, PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA};
+
+pub const HOOK_ID_SCHEMA: Schema = StringSchema::new("Hook ID.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(3)
+    .max_length(32)
+    .schema();
+
+/// Default time limit for hook scripts in seconds
+pub const HOOK_DEFAULT_TIMEOUT: u64 = 300;
+
+pub const HOOK_TIMEOUT_SCHEMA: Schema = IntegerSchema::new(
+    "Time limit in seconds, the script is killed and considered failed if it runs longer.",
+)
+.minimum(1)
+.maximum(24 * 3600)
+.default(HOOK_DEFAULT_TIMEOUT as isize)
+.schema();
+
+pub const HOOK_SCRIPT_SCHEMA: Schema =
+    StringSchema::new("Absolute path to an executable hook script.")
+        .format(&ApiStringFormat::VerifyFn(verify_hook_script_path))
+        .max_length(1024)
+        .schema();
+
+fn verify_hook_script_path(path: &str) -> Result<(), Error> {
+    if !path.starts_with('/') {
+        bail!("hook script path must be absolute");
+    }
+    Ok(())
+}
+
+#[api]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Task types hook scripts can be run for
+pub enum HookJobType {
+    /// Backup sessions
+    Backup,
+    /// Sync jobs (pull)
+    Sync,
+    /// Garbage collection
+    GarbageCollection,
+    /// Prune jobs
+    Prune,
+}
+
+serde_plain::derive_display_from_serialize!(HookJobType);
+serde_plain::derive_fromstr_from_deserialize!(HookJobType);
+
+#[api]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// When a hook script is run
+pub enum HookPhase {
+    /// Before the task does any work, a failing hook aborts the task
+    Pre,
+    /// After the task finished (successful or not)
+    Post,
+}
+
+serde_plain::derive_display_from_serialize!(HookPhase);
+
+#[api(
+    properties: {
+        id: {
+            schema: HOOK_ID_SCHEMA,
+        },
+        script: {
+            schema: HOOK_SCRIPT_SCHEMA,
+        },
+        "job-type": {
+            type: Array,
+            optional: true,
+            items: {
+                type: HookJobType,
+            },
+        },
+        store: {
+            schema: DATASTORE_SCHEMA,
+            optional: true,
+        },
+        timeout: {
+            schema: HOOK_TIMEOUT_SCHEMA,
+            optional: true,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Hook script configuration
+pub struct HookConfig {
+    #[updater(skip)]
+    pub id: String,
+    pub script: String,
+    /// Only run for these task types (default: all)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_type: Option<Vec<HookJobType>>,
+    /// Only run for tasks on this datastore (default: all)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Disable this hook
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable: Option<bool>,
+}
+
+impl HookConfig {
+    /// Returns true if the hook should run for a task of `job_type` on `store`.
+    pub fn applies_to(&self, job_type: HookJobType, store: &str) -> bool {
+        if self.disable.unwrap_or(false) {
+            return false;
+        }
+        if let Some(ref job_types) = self.job_type {
+            if !job_types.contains(&job_type) {
+                return false;
+            }
+        }
+        match self.store {
+            Some(ref hook_store) => hook_store == store,
+            None => true,
+        }
+    }
+}
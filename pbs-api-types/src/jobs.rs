@@ -50,6 +50,15 @@ pub const PRUNE_SCHEDULE_SCHEMA: Schema = StringSchema::new("Run prune job at sp
     .type_text("<calendar-event>")
     .schema();
 
+pub const JOB_SPLAY_SCHEMA: Schema = IntegerSchema::new(
+    "Delay scheduled runs by a random time of up to this many seconds, to spread out jobs \
+    with the same schedule.",
+)
+.minimum(0)
+.maximum(24 * 3600)
+.default(0)
+.schema();
+
 pub const VERIFICATION_SCHEDULE_SCHEMA: Schema =
     StringSchema::new("Run verify job at specified schedule.")
         .format(&ApiStringFormat::VerifyFn(
@@ -64,6 +73,13 @@ pub const REMOVE_VANISHED_BACKUPS_SCHEMA: Schema = BooleanSchema::new(
 .default(false)
 .schema();
 
+pub const VERIFY_SYNCED_SCHEMA: Schema = BooleanSchema::new(
+    "Verify the synced snapshots once the sync is done, locally and on the remote the snapshots \
+    were synced from.",
+)
+.default(false)
+.schema();
+
 #[api(
     properties: {
         "next-run": {
@@ -102,6 +118,58 @@ pub struct JobScheduleStatus {
     pub last_run_endtime: Option<i64>,
 }
 
+#[api()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Result of a job run
+pub enum JobResult {
+    /// Finished successfully
+    Ok,
+    /// Finished with warnings
+    Warning,
+    /// Failed
+    Error,
+    /// State could not be determined
+    Unknown,
+}
+
+#[api(
+    properties: {
+        store: {
+            schema: DATASTORE_SCHEMA,
+            optional: true,
+        },
+        upid: {
+            type: String,
+            description: "Task UPID of the run.",
+        },
+        result: {
+            type: JobResult,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Result of a finished job run
+pub struct JobHistoryEntry {
+    /// Job type, e.g. 'syncjob' or 'garbage_collection'
+    pub job_type: String,
+    /// Job ID
+    pub job_id: String,
+    /// Datastore the job ran on, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    pub upid: String,
+    /// Start time (UNIX epoch)
+    pub starttime: i64,
+    /// End time (UNIX epoch)
+    pub endtime: i64,
+    pub result: JobResult,
+    /// Task status, for warnings and errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
 #[api()]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -171,6 +239,12 @@ pub const VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
         .minimum(0)
         .schema();
 
+pub const VERIFY_ATTEST_SCHEMA: Schema = BooleanSchema::new(
+    "Store a report of the verified snapshots, signed with the server's key, in the datastore.",
+)
+.default(false)
+.schema();
+
 #[api(
     properties: {
         id: {
@@ -195,6 +269,10 @@ pub const VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
             optional: true,
             schema: VERIFICATION_SCHEDULE_SCHEMA,
         },
+        splay: {
+            optional: true,
+            schema: JOB_SPLAY_SCHEMA,
+        },
         ns: {
             optional: true,
             schema: BACKUP_NAMESPACE_SCHEMA,
@@ -203,6 +281,10 @@ pub const VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
             optional: true,
             schema: crate::NS_MAX_DEPTH_SCHEMA,
         },
+        attest: {
+            optional: true,
+            schema: VERIFY_ATTEST_SCHEMA,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Updater, Clone, PartialEq)]
@@ -226,6 +308,9 @@ pub struct VerificationJobConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     /// when to schedule this job in calendar event notation
     pub schedule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// maximum random delay of scheduled runs in seconds
+    pub splay: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     /// on which backup namespace to run the verification recursively
     pub ns: Option<BackupNamespace>,
@@ -233,6 +318,8 @@ pub struct VerificationJobConfig {
     /// how deep the verify should go from the `ns` level downwards. Passing 0 verifies only the
     /// snapshots on the same level as the passed `ns`, or the datastore root if none.
     pub max_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attest: Option<bool>,
 }
 
 impl VerificationJobConfig {
@@ -290,6 +377,11 @@ pub struct VerificationJobStatus {
             type: bool,
             optional: true,
         },
+        "since-last-run": {
+            description: "Only backup snapshots which are not on any media of the pool yet.",
+            type: bool,
+            optional: true,
+        },
         "notify-user": {
             optional: true,
             type: Userid,
@@ -321,6 +413,8 @@ pub struct TapeBackupJobSetup {
     pub export_media_set: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub latest_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since_last_run: Option<bool>,
     /// Send job email notification to this user
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notify_user: Option<Userid>,
@@ -350,6 +444,10 @@ pub struct TapeBackupJobSetup {
             optional: true,
             schema: SYNC_SCHEDULE_SCHEMA,
         },
+        splay: {
+            optional: true,
+            schema: JOB_SPLAY_SCHEMA,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
@@ -364,6 +462,8 @@ pub struct TapeBackupJobConfig {
     pub comment: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub schedule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub splay: Option<u64>,
 }
 
 #[api(
@@ -544,6 +644,10 @@ pub const TRANSFER_LAST_SCHEMA: Schema =
             optional: true,
             schema: SYNC_SCHEDULE_SCHEMA,
         },
+        splay: {
+            optional: true,
+            schema: JOB_SPLAY_SCHEMA,
+        },
         "group-filter": {
             schema: GROUP_FILTER_LIST_SCHEMA,
             optional: true,
@@ -552,6 +656,10 @@ pub const TRANSFER_LAST_SCHEMA: Schema =
             schema: TRANSFER_LAST_SCHEMA,
             optional: true,
         },
+        "verify-synced": {
+            schema: VERIFY_SYNCED_SCHEMA,
+            optional: true,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
@@ -580,11 +688,15 @@ pub struct SyncJobConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub schedule: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub splay: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub group_filter: Option<Vec<GroupFilter>>,
     #[serde(flatten)]
     pub limit: RateLimitConfig,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub transfer_last: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_synced: Option<bool>,
 }
 
 impl SyncJobConfig {
@@ -735,6 +847,10 @@ impl PruneJobOptions {
         schedule: {
             schema: PRUNE_SCHEDULE_SCHEMA,
         },
+        splay: {
+            optional: true,
+            schema: JOB_SPLAY_SCHEMA,
+        },
         comment: {
             optional: true,
             schema: SINGLE_LINE_COMMENT_SCHEMA,
@@ -761,6 +877,10 @@ pub struct PruneJobConfig {
 
     pub schedule: String,
 
+    /// Maximum random delay of scheduled runs in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub splay: Option<u64>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
 
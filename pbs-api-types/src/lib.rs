@@ -89,6 +89,15 @@ pub use acl::*;
 mod datastore;
 pub use datastore::*;
 
+mod expectation;
+pub use expectation::*;
+
+mod hook;
+pub use hook::*;
+
+mod log_forward;
+pub use log_forward::*;
+
 mod jobs;
 pub use jobs::*;
 
@@ -139,6 +148,9 @@ pub use remote::*;
 mod tape;
 pub use tape::*;
 
+mod webhook;
+pub use webhook::*;
+
 mod traffic_control;
 pub use traffic_control::*;
 
@@ -148,6 +160,8 @@ pub use zfs::*;
 mod metrics;
 pub use metrics::*;
 
+const FIELD_PATH_REGEX_STR: &str = r"[A-Za-z0-9_-]+(?:\.[A-Za-z0-9_-]+)*";
+
 const_regex! {
     // just a rough check - dummy acceptor is used before persisting
     pub OPENSSL_CIPHERS_REGEX = r"^[0-9A-Za-z_:, +!\-@=.]+$";
@@ -157,10 +171,12 @@ const_regex! {
         USER_ID_REGEX_STR, "|", APITOKEN_ID_REGEX_STR,
         ")@)?(",
         DNS_NAME_STR, "|",  IPRE_BRACKET_STR,
-        "):)?(?:([0-9]{1,5}):)?(", PROXMOX_SAFE_ID_REGEX_STR, r")$"
+        "):)?(?:([0-9]{1,5}):)?(", PROXMOX_SAFE_ID_REGEX_STR, r")(?::(", BACKUP_NS_RE, r"))?$"
     );
 
      pub SUBSCRIPTION_KEY_REGEX = concat!(r"^pbs(?:[cbsp])-[0-9a-f]{10}$");
+
+    pub FIELD_MASK_REGEX = concatcp!(r"^", FIELD_PATH_REGEX_STR, r"(?:,", FIELD_PATH_REGEX_STR, r")*$");
 }
 
 pub const PVE_CONFIG_DIGEST_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&SHA256_HEX_REGEX);
@@ -171,6 +187,8 @@ pub const SUBSCRIPTION_KEY_FORMAT: ApiStringFormat =
 pub const OPENSSL_CIPHERS_TLS_FORMAT: ApiStringFormat =
     ApiStringFormat::Pattern(&OPENSSL_CIPHERS_REGEX);
 
+pub const FIELD_MASK_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&FIELD_MASK_REGEX);
+
 pub const DAILY_DURATION_FORMAT: ApiStringFormat =
     ApiStringFormat::VerifyFn(|s| parse_daily_duration(s).map(drop));
 
@@ -226,6 +244,14 @@ pub const PROXMOX_CONFIG_DIGEST_SCHEMA: Schema = StringSchema::new(
 .format(&PVE_CONFIG_DIGEST_FORMAT)
 .schema();
 
+pub const FIELD_MASK_SCHEMA: Schema = StringSchema::new(
+    "Comma separated list of fields to include in the result, nested fields are \
+    separated by dots. All fields are returned if not set.",
+)
+.format(&FIELD_MASK_FORMAT)
+.max_length(1024)
+.schema();
+
 /// API schema format definition for repository URLs
 pub const BACKUP_REPO_URL: ApiStringFormat = ApiStringFormat::Pattern(&BACKUP_REPO_URL_REGEX);
 
@@ -0,0 +1,182 @@
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, ApiStringFormat, Schema, StringSchema, Updater};
+
+use crate::{HookJobType, DATASTORE_SCHEMA, PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA};
+
+pub const LOG_FORWARD_ID_SCHEMA: Schema = StringSchema::new("Log forwarder ID.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(3)
+    .max_length(32)
+    .schema();
+
+pub const LOG_FORWARD_TARGET_SCHEMA: Schema =
+    StringSchema::new("Collector address, 'host[:port]' for syslog or an http(s) URL for HTTP.")
+        .format(&ApiStringFormat::VerifyFn(verify_log_forward_target))
+        .max_length(1024)
+        .schema();
+
+fn verify_log_forward_target(target: &str) -> Result<(), Error> {
+    if target.is_empty() || target.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        bail!("invalid collector address");
+    }
+    Ok(())
+}
+
+#[api]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// How events are sent to the collector
+pub enum LogForwardProtocol {
+    /// Syslog (RFC 5424) over UDP
+    #[default]
+    Udp,
+    /// Syslog (RFC 5424) over TCP, with octet counting framing (RFC 6587)
+    Tcp,
+    /// HTTP POST request per event
+    Http,
+}
+
+serde_plain::derive_display_from_serialize!(LogForwardProtocol);
+
+#[api]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Format of the forwarded events
+pub enum LogForwardFormat {
+    /// One JSON object per event
+    #[default]
+    Json,
+    /// ArcSight Common Event Format
+    Cef,
+}
+
+serde_plain::derive_display_from_serialize!(LogForwardFormat);
+
+#[api]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Type of the task an event is forwarded for
+pub enum LogForwardEventType {
+    /// Backup sessions
+    Backup,
+    /// Sync jobs (pull)
+    Sync,
+    /// Garbage collection
+    GarbageCollection,
+    /// Prune jobs
+    Prune,
+    /// Verification jobs
+    Verify,
+    /// Tape backup jobs
+    TapeBackup,
+}
+
+serde_plain::derive_display_from_serialize!(LogForwardEventType);
+
+impl From<HookJobType> for LogForwardEventType {
+    fn from(job_type: HookJobType) -> Self {
+        match job_type {
+            HookJobType::Backup => Self::Backup,
+            HookJobType::Sync => Self::Sync,
+            HookJobType::GarbageCollection => Self::GarbageCollection,
+            HookJobType::Prune => Self::Prune,
+        }
+    }
+}
+
+#[api(
+    properties: {
+        name: {
+            schema: LOG_FORWARD_ID_SCHEMA,
+        },
+        protocol: {
+            type: LogForwardProtocol,
+            optional: true,
+        },
+        target: {
+            schema: LOG_FORWARD_TARGET_SCHEMA,
+        },
+        format: {
+            type: LogForwardFormat,
+            optional: true,
+        },
+        "job-type": {
+            type: Array,
+            optional: true,
+            items: {
+                type: LogForwardEventType,
+            },
+        },
+        store: {
+            schema: DATASTORE_SCHEMA,
+            optional: true,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Forwarding of task results to a remote syslog or HTTP collector
+pub struct LogForwardConfig {
+    #[updater(skip)]
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<LogForwardProtocol>,
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<LogForwardFormat>,
+    /// Only forward results of these task types (default: all)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_type: Option<Vec<LogForwardEventType>>,
+    /// Only forward failed tasks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failures_only: Option<bool>,
+    /// Only forward results of tasks on this datastore (default: all)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Disable this log forwarder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable: Option<bool>,
+}
+
+impl LogForwardConfig {
+    /// Returns true if the result of a task of `job_type` on `store` should be forwarded.
+    pub fn applies_to(&self, job_type: LogForwardEventType, store: &str, success: bool) -> bool {
+        if self.disable.unwrap_or(false) {
+            return false;
+        }
+        if success && self.failures_only.unwrap_or(false) {
+            return false;
+        }
+        if let Some(ref job_types) = self.job_type {
+            if !job_types.contains(&job_type) {
+                return false;
+            }
+        }
+        match self.store {
+            Some(ref forward_store) => forward_store == store,
+            None => true,
+        }
+    }
+
+    /// Check that the target matches the protocol.
+    pub fn check_target(&self) -> Result<(), Error> {
+        let is_url = self.target.starts_with("http://") || self.target.starts_with("https://");
+        match self.protocol.unwrap_or_default() {
+            LogForwardProtocol::Http if !is_url => {
+                bail!("protocol 'http' requires an http(s) URL as target")
+            }
+            LogForwardProtocol::Udp | LogForwardProtocol::Tcp if is_url => {
+                bail!("syslog protocols require 'host[:port]' as target")
+            }
+            _ => Ok(()),
+        }
+    }
+}
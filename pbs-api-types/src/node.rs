@@ -3,7 +3,7 @@ use std::ffi::OsStr;
 use proxmox_schema::*;
 use serde::{Deserialize, Serialize};
 
-use crate::StorageStatus;
+use crate::{Authid, StorageStatus};
 
 #[api]
 #[derive(Serialize, Deserialize, Default)]
@@ -160,3 +160,103 @@ pub struct NodeStatus {
     /// Current boot mode
     pub boot_info: BootModeInformation,
 }
+
+/// All available languages in Proxmox. Taken from proxmox-i18n repository.
+/// pt_BR, zh_CN, and zh_TW use the same case in the translation files.
+// TODO: auto-generate from available translations
+#[api]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Translation {
+    /// Arabic
+    Ar,
+    /// Catalan
+    Ca,
+    /// Danish
+    Da,
+    /// German
+    De,
+    /// English
+    En,
+    /// Spanish
+    Es,
+    /// Euskera
+    Eu,
+    /// Persian (Farsi)
+    Fa,
+    /// French
+    Fr,
+    /// Galician
+    Gl,
+    /// Hebrew
+    He,
+    /// Hungarian
+    Hu,
+    /// Italian
+    It,
+    /// Japanese
+    Ja,
+    /// Korean
+    Kr,
+    /// Norwegian (Bokmal)
+    Nb,
+    /// Dutch
+    Nl,
+    /// Norwegian (Nynorsk)
+    Nn,
+    /// Polish
+    Pl,
+    /// Portuguese (Brazil)
+    #[serde(rename = "pt_BR")]
+    PtBr,
+    /// Russian
+    Ru,
+    /// Slovenian
+    Sl,
+    /// Swedish
+    Sv,
+    /// Turkish
+    Tr,
+    /// Chinese (simplified)
+    #[serde(rename = "zh_CN")]
+    ZhCn,
+    /// Chinese (traditional)
+    #[serde(rename = "zh_TW")]
+    ZhTw,
+}
+
+pub const READER_QUEUE_PRIORITY_SCHEMA: Schema = IntegerSchema::new(
+    "Priority of a queued reader session, sessions with higher priority start first.",
+)
+.minimum(-100)
+.maximum(100)
+.default(0)
+.schema();
+
+#[api(
+    properties: {
+        "auth-id": {
+            type: Authid,
+        },
+        priority: {
+            schema: READER_QUEUE_PRIORITY_SCHEMA,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+/// A reader session (restore) waiting for a free session slot
+pub struct QueuedReaderSession {
+    /// Queue ID of the session
+    pub id: u64,
+    /// Position in the queue, starting at 1
+    pub position: usize,
+    /// Datastore
+    pub store: String,
+    /// Snapshot, prefixed with its namespace
+    pub snapshot: String,
+    pub auth_id: Authid,
+    pub priority: i64,
+    /// Time the session was queued (epoch)
+    pub queued_since: i64,
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, const_regex, ApiStringFormat, IntegerSchema, Schema, StringSchema};
+
+const_regex! {
+    pub BARCODE_LABEL_PREFIX_REGEX = r"^[A-Z0-9]{0,5}$";
+    pub BARCODE_MEDIA_TYPE_REGEX = r"^(L[3-9TUVWXYZ]|CU)$";
+}
+
+pub const BARCODE_LABEL_PREFIX_FORMAT: ApiStringFormat =
+    ApiStringFormat::Pattern(&BARCODE_LABEL_PREFIX_REGEX);
+
+pub const BARCODE_MEDIA_TYPE_FORMAT: ApiStringFormat =
+    ApiStringFormat::Pattern(&BARCODE_MEDIA_TYPE_REGEX);
+
+pub const BARCODE_LABEL_PREFIX_SCHEMA: Schema = StringSchema::new(
+    "Label prefix (uppercase letters and digits). The rest of the 6 character \
+     volume serial is filled with the zero-padded label number.",
+)
+.format(&BARCODE_LABEL_PREFIX_FORMAT)
+.max_length(5)
+.schema();
+
+pub const BARCODE_MEDIA_TYPE_SCHEMA: Schema = StringSchema::new(
+    "Media type identifier appended to the volume serial (e.g. 'L8' for LTO-8, \
+     'LY' for LTO-8 WORM, 'CU' for cleaning cartridges).",
+)
+.format(&BARCODE_MEDIA_TYPE_FORMAT)
+.schema();
+
+pub const BARCODE_LABEL_START_SCHEMA: Schema = IntegerSchema::new("Number of the first label.")
+    .minimum(0)
+    .default(1)
+    .schema();
+
+pub const BARCODE_LABEL_COUNT_SCHEMA: Schema = IntegerSchema::new("Number of labels to generate.")
+    .minimum(1)
+    .maximum(1000)
+    .schema();
+
+#[api()]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Barcode symbology
+pub enum BarcodeSymbology {
+    /// Code 39 (as required by the LTO cartridge label specification)
+    #[default]
+    Code39,
+    /// Code 128 (code set B)
+    Code128,
+}
+
+#[api()]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Label sheet layout
+pub enum LabelSheetLayout {
+    /// Avery Zweckform 3420 (A4, 3x17 labels)
+    #[default]
+    Avery3420,
+    /// Plain A4 paper (with cutting borders)
+    A4,
+    /// Plain Letter paper (with cutting borders)
+    Letter,
+}
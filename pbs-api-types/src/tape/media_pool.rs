@@ -2,11 +2,11 @@
 //!
 //! Note: Both MediaSetPolicy and RetentionPolicy are complex enums,
 //! so we cannot use them directly for the API. Instead, we represent
-//! them as String.
+//! them as String. The same applies to MediaSetSlots.
 
 use std::str::FromStr;
 
-use anyhow::Error;
+use anyhow::{bail, Error};
 use serde::{Deserialize, Serialize};
 
 use proxmox_schema::{api, ApiStringFormat, Schema, StringSchema, Updater};
@@ -69,6 +69,55 @@ impl std::str::FromStr for MediaSetPolicy {
     }
 }
 
+pub const MEDIA_SET_SLOTS_FORMAT: ApiStringFormat = ApiStringFormat::VerifyFn(|s| {
+    MediaSetSlots::from_str(s)?;
+    Ok(())
+});
+
+pub const MEDIA_SET_SLOTS_SCHEMA: Schema = StringSchema::new(
+    "Keep separate media sets per slot ('weekday', 'backup-type', or both, comma separated).",
+)
+.format(&MEDIA_SET_SLOTS_FORMAT)
+.schema();
+
+/// Media set slots
+///
+/// Each slot has its own current media set, and the allocation and
+/// retention policies are applied per slot. This allows classic tape
+/// rotation schemes, for example a separate set for each weekday.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MediaSetSlots {
+    /// Separate media sets per weekday of the backup start
+    pub weekday: bool,
+    /// Separate media sets per backup type (vm, ct, host)
+    pub backup_type: bool,
+}
+
+impl MediaSetSlots {
+    /// Returns true if the pool does not use slots at all
+    pub fn is_empty(&self) -> bool {
+        !(self.weekday || self.backup_type)
+    }
+}
+
+impl std::str::FromStr for MediaSetSlots {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut slots = MediaSetSlots::default();
+
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            match part {
+                "weekday" => slots.weekday = true,
+                "backup-type" => slots.backup_type = true,
+                _ => bail!("unknown media set slot '{part}'"),
+            }
+        }
+
+        Ok(slots)
+    }
+}
+
 pub const MEDIA_RETENTION_POLICY_FORMAT: ApiStringFormat = ApiStringFormat::VerifyFn(|s| {
     RetentionPolicy::from_str(s)?;
     Ok(())
@@ -127,6 +176,10 @@ impl std::str::FromStr for RetentionPolicy {
             schema: TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
             optional: true,
         },
+        slots: {
+            schema: MEDIA_SET_SLOTS_SCHEMA,
+            optional: true,
+        },
         comment: {
             optional: true,
             schema: SINGLE_LINE_COMMENT_SCHEMA,
@@ -156,6 +209,9 @@ pub struct MediaPoolConfig {
     /// If set, encrypt all data using the specified key.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encrypt: Option<String>,
+    /// Media set slots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slots: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
 }
@@ -1,5 +1,8 @@
 //! Types for tape backup API
 
+mod barcode_label;
+pub use barcode_label::*;
+
 mod device;
 pub use device::*;
 
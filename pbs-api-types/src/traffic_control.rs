@@ -4,7 +4,8 @@ use proxmox_human_byte::HumanByte;
 use proxmox_schema::{api, IntegerSchema, Schema, StringSchema, Updater};
 
 use crate::{
-    CIDR_SCHEMA, DAILY_DURATION_FORMAT, PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
+    Authid, CIDR_SCHEMA, DAILY_DURATION_FORMAT, DATASTORE_SCHEMA, PROXMOX_SAFE_ID_FORMAT,
+    SINGLE_LINE_COMMENT_SCHEMA,
 };
 
 pub const TRAFFIC_CONTROL_TIMEFRAME_SCHEMA: Schema =
@@ -28,6 +29,14 @@ pub const TRAFFIC_CONTROL_BURST_SCHEMA: Schema =
         .minimum(1000)
         .schema();
 
+pub const TRAFFIC_CONTROL_RESTORE_SHARE_SCHEMA: Schema = IntegerSchema::new(
+    "Bandwidth share (percent) of restore sessions while backup and restore \
+    sessions are active at the same time. Backup sessions get the remaining share.",
+)
+.minimum(1)
+.maximum(99)
+.schema();
+
 #[api(
     properties: {
         "rate-in": {
@@ -91,6 +100,20 @@ impl RateLimitConfig {
                 schema: CIDR_SCHEMA,
             },
         },
+        users: {
+            type: Array,
+            items: {
+                type: Authid,
+            },
+            optional: true,
+        },
+        datastores: {
+            type: Array,
+            items: {
+                schema: DATASTORE_SCHEMA,
+            },
+            optional: true,
+        },
         timeframe: {
             type: Array,
             items: {
@@ -98,6 +121,10 @@ impl RateLimitConfig {
             },
             optional: true,
         },
+        "restore-share": {
+            schema: TRAFFIC_CONTROL_RESTORE_SHARE_SCHEMA,
+            optional: true,
+        },
     },
 )]
 #[derive(Clone, Serialize, Deserialize, PartialEq, Updater)]
@@ -110,6 +137,12 @@ pub struct TrafficControlRule {
     pub comment: Option<String>,
     /// Rule applies to Source IPs within this networks
     pub network: Vec<String>,
+    /// Rule only applies to backup/restore sessions of these users or tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users: Option<Vec<Authid>>,
+    /// Rule only applies to backup/restore sessions on these datastores
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datastores: Option<Vec<String>>,
     #[serde(flatten)]
     pub limit: RateLimitConfig,
     // fixme: expose this?
@@ -119,6 +152,18 @@ pub struct TrafficControlRule {
     /// Enable the rule at specific times
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeframe: Option<Vec<String>>,
+    /// Weight restore against backup sessions (only for rules restricted to
+    /// users or datastores)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restore_share: Option<u8>,
+}
+
+impl TrafficControlRule {
+    /// Rules restricted to users or datastores are applied per backup or
+    /// restore session instead of per connection.
+    pub fn is_session_rule(&self) -> bool {
+        self.users.is_some() || self.datastores.is_some()
+    }
 }
 
 #[api(
@@ -1,9 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use proxmox_schema::{api, BooleanSchema, IntegerSchema, Schema, StringSchema, Updater};
+use proxmox_schema::{api, ApiType, BooleanSchema, IntegerSchema, Schema, StringSchema, Updater};
 
 use super::userid::{Authid, Userid, PROXMOX_TOKEN_ID_SCHEMA};
-use super::{SINGLE_LINE_COMMENT_FORMAT, SINGLE_LINE_COMMENT_SCHEMA};
+use super::{Translation, SINGLE_LINE_COMMENT_FORMAT, SINGLE_LINE_COMMENT_SCHEMA};
 
 pub const ENABLE_USER_SCHEMA: Schema = BooleanSchema::new(
     "Enable the account (default). You can set this to '0' to disable the account.",
@@ -18,6 +18,13 @@ pub const EXPIRE_USER_SCHEMA: Schema = IntegerSchema::new(
 .minimum(0)
 .schema();
 
+pub const APPEND_ONLY_SCHEMA: Schema = BooleanSchema::new(
+    "Only allow adding new backups. Removing or modifying existing snapshots \
+    (forget, prune, changing the owner or protection) is refused, regardless of privileges.",
+)
+.default(false)
+.schema();
+
 pub const FIRST_NAME_SCHEMA: Schema = StringSchema::new("First name.")
     .format(&SINGLE_LINE_COMMENT_FORMAT)
     .min_length(2)
@@ -53,6 +60,10 @@ pub const EMAIL_SCHEMA: Schema = StringSchema::new("E-Mail Address.")
             optional: true,
             schema: EXPIRE_USER_SCHEMA,
         },
+        "append-only": {
+            optional: true,
+            schema: APPEND_ONLY_SCHEMA,
+        },
         firstname: {
             optional: true,
             schema: FIRST_NAME_SCHEMA,
@@ -65,6 +76,10 @@ pub const EMAIL_SCHEMA: Schema = StringSchema::new("E-Mail Address.")
             schema: EMAIL_SCHEMA,
             optional: true,
         },
+        lang: {
+            schema: Translation::API_SCHEMA,
+            optional: true,
+        },
         tokens: {
             type: Array,
             optional: true,
@@ -97,11 +112,15 @@ pub struct UserWithTokens {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub expire: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub append_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub firstname: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub lastname: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub tokens: Vec<ApiToken>,
     #[serde(skip_serializing_if = "bool_is_false", default)]
@@ -131,6 +150,10 @@ fn bool_is_false(b: &bool) -> bool {
             optional: true,
             schema: EXPIRE_USER_SCHEMA,
         },
+        "append-only": {
+            optional: true,
+            schema: APPEND_ONLY_SCHEMA,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -143,9 +166,15 @@ pub struct ApiToken {
     pub enable: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub expire: Option<i64>,
+    #[serde(rename = "append-only", skip_serializing_if = "Option::is_none")]
+    pub append_only: Option<bool>,
 }
 
 impl ApiToken {
+    pub fn is_append_only(&self) -> bool {
+        self.append_only.unwrap_or(false)
+    }
+
     pub fn is_active(&self) -> bool {
         if !self.enable.unwrap_or(true) {
             return false;
@@ -177,6 +206,10 @@ impl ApiToken {
             optional: true,
             schema: EXPIRE_USER_SCHEMA,
         },
+        "append-only": {
+            optional: true,
+            schema: APPEND_ONLY_SCHEMA,
+        },
         firstname: {
             optional: true,
             schema: FIRST_NAME_SCHEMA,
@@ -189,6 +222,10 @@ impl ApiToken {
             schema: EMAIL_SCHEMA,
             optional: true,
         },
+        lang: {
+            schema: Translation::API_SCHEMA,
+            optional: true,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Updater, PartialEq, Eq)]
@@ -202,15 +239,23 @@ pub struct User {
     pub enable: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub expire: Option<i64>,
+    #[serde(rename = "append-only", skip_serializing_if = "Option::is_none")]
+    pub append_only: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub firstname: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub lastname: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
 }
 
 impl User {
+    pub fn is_append_only(&self) -> bool {
+        self.append_only.unwrap_or(false)
+    }
+
     pub fn is_active(&self) -> bool {
         if !self.enable.unwrap_or(true) {
             return false;
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{
+    api, const_regex, ApiStringFormat, IntegerSchema, Schema, StringSchema, Updater,
+};
+
+use crate::{
+    DATASTORE_SCHEMA, HTTP_URL_SCHEMA, PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
+};
+
+pub const WEBHOOK_ID_SCHEMA: Schema = StringSchema::new("Webhook ID.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(3)
+    .max_length(32)
+    .schema();
+
+const_regex! {
+    /// Regex for HTTP headers ('Name: value')
+    pub WEBHOOK_HEADER_REGEX = r"^[A-Za-z0-9!#$%&'*+.^_`|~-]+:[^\x00-\x08\x0A-\x1F\x7F]*$";
+}
+
+pub const WEBHOOK_HEADER_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&WEBHOOK_HEADER_REGEX);
+
+pub const WEBHOOK_HEADER_SCHEMA: Schema =
+    StringSchema::new("Additional HTTP header ('Name: value').")
+        .format(&WEBHOOK_HEADER_FORMAT)
+        .max_length(4096)
+        .schema();
+
+pub const WEBHOOK_RETRIES_SCHEMA: Schema =
+    IntegerSchema::new("Number of retries if sending the request fails.")
+        .minimum(0)
+        .maximum(10)
+        .default(3)
+        .schema();
+
+pub const WEBHOOK_RETRY_DELAY_SCHEMA: Schema =
+    IntegerSchema::new("Delay between retries in seconds.")
+        .minimum(1)
+        .maximum(3600)
+        .default(30)
+        .schema();
+
+#[api]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// When to send a webhook request
+pub enum WebhookTrigger {
+    /// For finished and failed backups
+    #[default]
+    Always,
+    /// For failed backups only
+    Failure,
+}
+
+#[api(
+    properties: {
+        name: {
+            schema: WEBHOOK_ID_SCHEMA,
+        },
+        url: {
+            schema: HTTP_URL_SCHEMA,
+        },
+        header: {
+            type: Array,
+            optional: true,
+            items: {
+                schema: WEBHOOK_HEADER_SCHEMA,
+            },
+        },
+        trigger: {
+            type: WebhookTrigger,
+            optional: true,
+        },
+        retries: {
+            schema: WEBHOOK_RETRIES_SCHEMA,
+            optional: true,
+        },
+        "retry-delay": {
+            schema: WEBHOOK_RETRY_DELAY_SCHEMA,
+            optional: true,
+        },
+        store: {
+            schema: DATASTORE_SCHEMA,
+            optional: true,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Webhook sent when a backup snapshot finishes or fails
+pub struct WebhookConfig {
+    #[updater(skip)]
+    pub name: String,
+    /// Target URL of the HTTP POST request
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<WebhookTrigger>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<u64>,
+    /// Only send for backups to this datastore (default: all)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Disable this webhook
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable: Option<bool>,
+}
+
+impl WebhookConfig {
+    /// Returns true if the webhook should be sent for a backup on `store`.
+    pub fn applies_to(&self, store: &str, success: bool) -> bool {
+        if self.disable.unwrap_or(false) {
+            return false;
+        }
+        if success && self.trigger.unwrap_or_default() == WebhookTrigger::Failure {
+            return false;
+        }
+        match self.store {
+            Some(ref webhook_store) => webhook_store == store,
+            None => true,
+        }
+    }
+}
@@ -57,6 +57,7 @@ impl BackupReader {
             "backup-time": backup.time,
             "store": datastore,
             "debug": debug,
+            "lz4": true,
         });
 
         if !ns.is_root() {
@@ -2,7 +2,7 @@ use std::fmt;
 
 use anyhow::{format_err, Error};
 
-use pbs_api_types::{Authid, Userid, BACKUP_REPO_URL_REGEX, IP_V6_REGEX};
+use pbs_api_types::{Authid, BackupNamespace, Userid, BACKUP_REPO_URL_REGEX, IP_V6_REGEX};
 
 /// Reference remote backup locations
 ///
@@ -17,6 +17,8 @@ pub struct BackupRepository {
     port: Option<u16>,
     /// The name of the datastore
     store: String,
+    /// The namespace inside the datastore
+    ns: Option<BackupNamespace>,
 }
 
 impl BackupRepository {
@@ -35,6 +37,7 @@ impl BackupRepository {
             host,
             port,
             store,
+            ns: None,
         }
     }
 
@@ -71,10 +74,23 @@ impl BackupRepository {
     pub fn store(&self) -> &str {
         &self.store
     }
+
+    /// The namespace given in the repository, if any
+    pub fn ns(&self) -> Option<&BackupNamespace> {
+        self.ns.as_ref()
+    }
 }
 
 impl fmt::Display for BackupRepository {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ns) = &self.ns {
+            // the namespace is only recognized with an explicit host and port
+            if let Some(auth_id) = &self.auth_id {
+                write!(f, "{}@", auth_id)?;
+            }
+            return write!(f, "{}:{}:{}:{}", self.host(), self.port(), self.store, ns);
+        }
+
         match (&self.auth_id, &self.host, self.port) {
             (Some(auth_id), _, _) => write!(
                 f,
@@ -99,6 +115,10 @@ impl std::str::FromStr for BackupRepository {
     /// This parses strings like `user@host:datastore`. The `user` and
     /// `host` parts are optional, where `host` defaults to the local
     /// host, and `user` defaults to `root@pam`.
+    ///
+    /// A namespace can be appended to the datastore, as in
+    /// `user@host:datastore:ns/sub`. This requires the `host` part, since
+    /// `a:b` always denotes host `a` and datastore `b`.
     fn from_str(url: &str) -> Result<Self, Self::Err> {
         let cap = (BACKUP_REPO_URL_REGEX.regex_obj)()
             .captures(url)
@@ -112,6 +132,69 @@ impl std::str::FromStr for BackupRepository {
             host: cap.get(2).map(|m| m.as_str().to_owned()),
             port: cap.get(3).map(|m| m.as_str().parse::<u16>()).transpose()?,
             store: cap[4].to_owned(),
+            ns: cap
+                .get(5)
+                .filter(|m| !m.as_str().is_empty())
+                .map(|m| m.as_str().parse::<BackupNamespace>())
+                .transpose()?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(url: &str) -> BackupRepository {
+        url.parse()
+            .unwrap_or_else(|err| panic!("failed to parse {url:?} - {err}"))
+    }
+
+    fn assert_same(a: &BackupRepository, b: &BackupRepository) {
+        assert_eq!(a.auth_id(), b.auth_id());
+        assert_eq!(a.host(), b.host());
+        assert_eq!(a.port(), b.port());
+        assert_eq!(a.store(), b.store());
+        assert_eq!(a.ns(), b.ns());
+    }
+
+    #[test]
+    fn test_repository_roundtrip() {
+        for url in [
+            "store",
+            "host:store",
+            "host:8008:store",
+            "root@pam@host:store",
+            "host:8007:store:ns",
+            "root@pam@host:8007:store:ns/sub",
+        ] {
+            let repo = parse(url);
+            let reparsed = parse(&repo.to_string());
+            assert_same(&repo, &reparsed);
+        }
+
+        assert_eq!(
+            parse("root@pam@host:8007:store:ns/sub").to_string(),
+            "root@pam@host:8007:store:ns/sub"
+        );
+    }
+
+    #[test]
+    fn test_repository_namespace() {
+        let repo = parse("host:store:ns/sub");
+        assert_eq!(repo.host(), "host");
+        assert_eq!(repo.store(), "store");
+        assert_eq!(repo.ns(), Some(&"ns/sub".parse().unwrap()));
+
+        // two parts are always host and datastore
+        let repo = parse("store:ns");
+        assert_eq!(repo.host(), "store");
+        assert_eq!(repo.store(), "ns");
+        assert_eq!(repo.ns(), None);
+
+        // a namespace is always printed with host and port, so it stays unambiguous
+        let repo = parse("localhost:store:ns");
+        assert_eq!(repo.to_string(), "localhost:8007:store:ns");
+        assert_same(&repo, &parse(&repo.to_string()));
+    }
+}
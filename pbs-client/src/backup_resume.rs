@@ -0,0 +1,302 @@
+//! Local state for resuming interrupted backup runs
+//!
+//! While a backup runs, the client records every chunk it uploads, and the
+//! chunk list of every index archive it finished, in a per-group state
+//! directory (usually `$HOME/.cache/proxmox-backup/resume/<id>/`). The state
+//! is removed once the backup finished successfully.
+//!
+//! If a run gets aborted, the next run with `--resume` asks the server which
+//! of the recorded chunks still exist in the datastore. Those get registered
+//! for the new session and are not uploaded again. Finished image archives
+//! whose source did not change can be re-created from their chunk list
+//! without reading the source again.
+//!
+//! The state directory contains:
+//!
+//! * `state.json`: key fingerprint and the list of finished archives
+//! * `chunks`: log of uploaded chunks
+//! * `<archive-name>.list`: chunk list of a finished archive
+//!
+//! Chunk logs and lists are a sequence of 32 byte digests, each followed by
+//! the chunk size as little endian `u32`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{file_get_optional_contents, replace_file, CreateOptions};
+
+use pbs_api_types::{BackupGroup, BackupNamespace};
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::{BackupRepository, BackupStats};
+
+const STATE_FILE_NAME: &str = "state.json";
+const CHUNK_LOG_NAME: &str = "chunks";
+const CHUNK_ENTRY_SIZE: usize = 36;
+
+/// Chunk digest and (unencoded) chunk size
+pub type ResumeChunk = ([u8; 32], u32);
+
+/// Index archive finished in an earlier run
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResumeArchive {
+    /// Source path the archive was created from
+    pub source: String,
+    /// Modification time of the source when it was read
+    pub mtime: i64,
+    /// Archive size
+    pub size: u64,
+    /// Index checksum
+    #[serde(with = "hex::serde")]
+    pub csum: [u8; 32],
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ResumeStateFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_fingerprint: Option<String>,
+    #[serde(default)]
+    archives: HashMap<String, ResumeArchive>,
+}
+
+/// Resume state of a single backup group
+pub struct BackupResumeState {
+    dir: PathBuf,
+    state: Mutex<ResumeStateFile>,
+    chunk_log: Mutex<File>,
+    previous_chunks: Vec<ResumeChunk>,
+    known_chunks: Mutex<HashSet<[u8; 32]>>,
+    pending_indices: Mutex<HashMap<String, Vec<ResumeChunk>>>,
+}
+
+fn state_dir(
+    repo: &BackupRepository,
+    ns: &BackupNamespace,
+    group: &BackupGroup,
+) -> Result<PathBuf, Error> {
+    let id = openssl::sha::sha256(format!("{repo}\n{ns}\n{group}").as_bytes());
+    let base = crate::tools::base_directories()?;
+    let dir = base.create_cache_directory(format!("resume/{}", hex::encode(id)))?;
+    Ok(dir)
+}
+
+fn parse_chunk_list(data: &[u8]) -> Vec<ResumeChunk> {
+    // a partially written entry at the end is ignored
+    data.chunks_exact(CHUNK_ENTRY_SIZE)
+        .map(|entry| {
+            let mut digest = [0u8; 32];
+            digest.copy_from_slice(&entry[..32]);
+            let size = u32::from_le_bytes(entry[32..].try_into().unwrap());
+            (digest, size)
+        })
+        .collect()
+}
+
+fn encode_chunk_list(chunks: &[ResumeChunk]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(chunks.len() * CHUNK_ENTRY_SIZE);
+    for (digest, size) in chunks {
+        data.extend_from_slice(digest);
+        data.extend_from_slice(&size.to_le_bytes());
+    }
+    data
+}
+
+fn chunk_list_path(dir: &Path, archive_name: &str) -> PathBuf {
+    dir.join(format!("{archive_name}.list"))
+}
+
+/// Modification time of a backup source, used to detect changes between runs.
+///
+/// Returns `None` for block devices, writing to them does not update the modification time, so
+/// their archives are never re-created from the recorded chunk list.
+pub fn source_mtime(path: &str) -> Result<Option<i64>, Error> {
+    let stat = nix::sys::stat::stat(path)
+        .map_err(|err| format_err!("fstat {:?} failed - {}", path, err))?;
+    if (stat.st_mode & libc::S_IFMT) == libc::S_IFBLK {
+        return Ok(None);
+    }
+    Ok(Some(stat.st_mtime))
+}
+
+impl BackupResumeState {
+    /// Open the resume state of a backup group.
+    ///
+    /// If `resume` is false, or the state was recorded with another
+    /// encryption key, any existing state is discarded.
+    pub fn open(
+        repo: &BackupRepository,
+        ns: &BackupNamespace,
+        group: &BackupGroup,
+        crypt_config: Option<&CryptConfig>,
+        resume: bool,
+    ) -> Result<Self, Error> {
+        let dir = state_dir(repo, ns, group)?;
+        let key_fingerprint = crypt_config.map(|config| hex::encode(config.fingerprint()));
+
+        let mut state = ResumeStateFile::default();
+        let mut previous_chunks = Vec::new();
+
+        if resume {
+            if let Some(data) = file_get_optional_contents(dir.join(STATE_FILE_NAME))? {
+                match serde_json::from_slice::<ResumeStateFile>(&data) {
+                    Ok(old) if old.key_fingerprint == key_fingerprint => state = old,
+                    Ok(_) => log::warn!("resume state was recorded with another key, ignoring"),
+                    Err(err) => log::warn!("unable to parse resume state, ignoring - {err}"),
+                }
+            }
+            if let Some(data) = file_get_optional_contents(dir.join(CHUNK_LOG_NAME))? {
+                previous_chunks = parse_chunk_list(&data);
+            }
+        }
+
+        if !resume || state.key_fingerprint != key_fingerprint {
+            std::fs::remove_dir_all(&dir)?;
+            std::fs::create_dir_all(&dir)?;
+            state = ResumeStateFile {
+                key_fingerprint,
+                ..Default::default()
+            };
+            previous_chunks.clear();
+        }
+
+        let chunk_log = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(CHUNK_LOG_NAME))
+            .map_err(|err| format_err!("unable to open resume chunk log - {err}"))?;
+
+        let this = Self {
+            dir,
+            state: Mutex::new(state),
+            chunk_log: Mutex::new(chunk_log),
+            previous_chunks,
+            known_chunks: Mutex::new(HashSet::new()),
+            pending_indices: Mutex::new(HashMap::new()),
+        };
+        this.save_state()?;
+
+        Ok(this)
+    }
+
+    /// Chunks uploaded in the interrupted run.
+    pub fn previous_chunks(&self) -> &[ResumeChunk] {
+        &self.previous_chunks
+    }
+
+    /// Add chunks the server confirmed to exist, they are not uploaded again.
+    pub fn add_known_chunks(&self, digests: impl IntoIterator<Item = [u8; 32]>) {
+        self.known_chunks.lock().unwrap().extend(digests);
+    }
+
+    /// Chunks which do not need to be uploaded again.
+    pub fn known_chunks(&self) -> HashSet<[u8; 32]> {
+        self.known_chunks.lock().unwrap().clone()
+    }
+
+    /// Record an uploaded chunk.
+    pub(crate) fn log_chunk(&self, digest: &[u8; 32], size: u32) {
+        let entry = encode_chunk_list(&[(*digest, size)]);
+        if let Err(err) = self.chunk_log.lock().unwrap().write_all(&entry) {
+            log::debug!("unable to record uploaded chunk for resume - {err}");
+        }
+    }
+
+    /// Remember the chunk list of an uploaded index, until
+    /// [finish_archive](Self::finish_archive) is called for it.
+    pub(crate) fn record_index(&self, archive_name: &str, chunks: Vec<ResumeChunk>) {
+        self.pending_indices
+            .lock()
+            .unwrap()
+            .insert(archive_name.to_string(), chunks);
+    }
+
+    /// Mark an index archive as finished, so that a resumed run can re-create
+    /// it without reading `source` again.
+    pub fn finish_archive(
+        &self,
+        archive_name: &str,
+        source: &str,
+        mtime: i64,
+        stats: &BackupStats,
+    ) -> Result<(), Error> {
+        let chunks = match self.pending_indices.lock().unwrap().remove(archive_name) {
+            Some(chunks) => chunks,
+            None => return Ok(()),
+        };
+
+        replace_file(
+            chunk_list_path(&self.dir, archive_name),
+            &encode_chunk_list(&chunks),
+            CreateOptions::new(),
+            false,
+        )?;
+
+        let archive = ResumeArchive {
+            source: source.to_string(),
+            mtime,
+            size: stats.size,
+            csum: stats.csum,
+        };
+        self.state
+            .lock()
+            .unwrap()
+            .archives
+            .insert(archive_name.to_string(), archive);
+
+        self.save_state()
+    }
+
+    /// Returns the chunk list of an archive finished in the interrupted run,
+    /// if it was created from the unchanged `source`.
+    pub fn finished_archive(
+        &self,
+        archive_name: &str,
+        source: &str,
+        mtime: i64,
+    ) -> Result<Option<(ResumeArchive, Vec<ResumeChunk>)>, Error> {
+        let archive = match self.state.lock().unwrap().archives.get(archive_name) {
+            Some(archive) if archive.source == source && archive.mtime == mtime => archive.clone(),
+            _ => return Ok(None),
+        };
+
+        match file_get_optional_contents(chunk_list_path(&self.dir, archive_name))? {
+            Some(data) => Ok(Some((archive, parse_chunk_list(&data)))),
+            None => Ok(None),
+        }
+    }
+
+    fn save_state(&self) -> Result<(), Error> {
+        let data = serde_json::to_vec(&*self.state.lock().unwrap())?;
+        replace_file(
+            self.dir.join(STATE_FILE_NAME),
+            &data,
+            CreateOptions::new(),
+            false,
+        )
+    }
+
+    /// Remove the state after a successful backup.
+    pub fn remove(&self) -> Result<(), Error> {
+        std::fs::remove_dir_all(&self.dir)
+            .map_err(|err| format_err!("unable to remove resume state {:?} - {err}", self.dir))
+    }
+}
+
+#[test]
+fn test_chunk_list_encoding() {
+    let chunks = vec![([1u8; 32], 4096), ([0xffu8; 32], 4 * 1024 * 1024)];
+    let mut data = encode_chunk_list(&chunks);
+    assert_eq!(data.len(), 2 * CHUNK_ENTRY_SIZE);
+
+    // incomplete trailing entries are ignored
+    data.extend_from_slice(&[0u8; 10]);
+    assert_eq!(parse_chunk_list(&data), chunks);
+}
@@ -3,7 +3,7 @@ use anyhow::{bail, Error};
 use proxmox_schema::*;
 
 const_regex! {
-    BACKUPSPEC_REGEX = r"^([a-zA-Z0-9_-]+\.(pxar|img|conf|log)):(.+)$";
+    BACKUPSPEC_REGEX = r"^([a-zA-Z0-9_-]+\.(pxar|img|conf|log|dat)):(.+)$";
 }
 
 pub const BACKUP_SOURCE_SCHEMA: Schema =
@@ -16,6 +16,8 @@ pub enum BackupSpecificationType {
     IMAGE,
     CONFIG,
     LOGFILE,
+    /// Data read from a pipe or stdin ('-'), stored with dynamic chunking
+    STREAM,
 }
 
 pub struct BackupSpecification {
@@ -34,6 +36,7 @@ pub fn parse_backup_specification(value: &str) -> Result<BackupSpecification, Er
             "img" => BackupSpecificationType::IMAGE,
             "conf" => BackupSpecificationType::CONFIG,
             "log" => BackupSpecificationType::LOGFILE,
+            "dat" => BackupSpecificationType::STREAM,
             _ => bail!("unknown backup source type '{}'", extension),
         };
         return Ok(BackupSpecification {
@@ -1,7 +1,8 @@
 use std::collections::HashSet;
 use std::future::Future;
 use std::os::unix::fs::OpenOptionsExt;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
@@ -13,17 +14,18 @@ use tokio::sync::{mpsc, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
 
 use pbs_api_types::{BackupDir, BackupNamespace};
-use pbs_datastore::data_blob::{ChunkInfo, DataBlob, DataChunkBuilder};
+use pbs_datastore::data_blob::{ChunkInfo, DataBlob};
 use pbs_datastore::dynamic_index::DynamicIndexReader;
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{ArchiveType, BackupManifest, MANIFEST_BLOB_NAME};
-use pbs_datastore::{CATALOG_NAME, PROXMOX_BACKUP_PROTOCOL_ID_V1};
+use pbs_datastore::{CATALOG_NAME, CLIENT_VERSION_HEADER, PROXMOX_BACKUP_PROTOCOL_ID_V1};
 use pbs_tools::crypt_config::CryptConfig;
 
 use proxmox_human_byte::HumanByte;
 
 use super::merge_known_chunks::{MergeKnownChunks, MergedChunkInfo};
+use super::{BackupResumeState, KnownChunkCache, Progress, ResumeChunk, StreamChunk};
 
 use super::{H2Client, HttpClient};
 
@@ -51,6 +53,56 @@ pub struct UploadOptions {
     pub compress: bool,
     pub encrypt: bool,
     pub fixed_size: Option<u64>,
+    /// Ask the server which new chunks already exist anywhere in the datastore, instead of only
+    /// deduplicating against the previous snapshot.
+    pub query_known_chunks: bool,
+    /// Record uploaded chunks for resuming an interrupted run, and skip chunks the server
+    /// already confirmed for this session.
+    pub resume: Option<Arc<BackupResumeState>>,
+    /// Number of chunks hashed, encoded and uploaded in parallel (default 1)
+    pub threads: Option<usize>,
+    /// Count read and uploaded bytes for progress reports
+    pub progress: Option<Arc<Progress>>,
+    /// Ask the server about new chunks found in the cache, and record the chunks it confirmed
+    pub known_chunk_cache: Option<Arc<KnownChunkCache>>,
+    /// Chunks of the previous index of the archive, if the caller already downloaded it in this
+    /// session. The previous index is then not downloaded again.
+    pub previous_chunks: Option<Arc<Mutex<HashSet<[u8; 32]>>>>,
+}
+
+/// Number of chunks queried at once with `UploadOptions::query_known_chunks`
+const KNOWN_CHUNKS_QUERY_BATCH: usize = 256;
+
+/// Maximum number of chunks the server accepts in a single known chunks query
+const KNOWN_CHUNKS_QUERY_MAX: usize = 1024;
+
+/// Number of chunks appended at once when replaying an index
+const REPLAY_APPEND_BATCH: usize = 128;
+
+/// Interval for keep-alive messages, so that the server does not consider the session stale
+/// while we are busy reading unchanged data.
+const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Interval for querying the running deduplication statistics of an archive for progress reports
+const UPLOAD_STATS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Aborts a spawned task when dropped, e.g. together with an upload future which is cancelled.
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// chunk whose data was not encoded yet, since the server might already know it
+enum PendingChunk {
+    Known(u64, [u8; 32]),
+    New {
+        data: bytes::BytesMut,
+        offset: u64,
+        digest: [u8; 32],
+    },
 }
 
 struct UploadStats {
@@ -61,9 +113,13 @@ struct UploadStats {
     size_compressed: usize,
     duration: std::time::Duration,
     csum: [u8; 32],
+    chunk_list: Option<Vec<ResumeChunk>>,
 }
 
-type UploadQueueSender = mpsc::Sender<(MergedChunkInfo, Option<h2::client::ResponseFuture>)>;
+/// Response of a chunk upload, which retries the upload on transient errors.
+type PendingResponse = Pin<Box<dyn Future<Output = Result<Value, Error>> + Send>>;
+
+type UploadQueueSender = mpsc::Sender<(MergedChunkInfo, Option<PendingResponse>)>;
 type UploadResultReceiver = oneshot::Receiver<Result<(), Error>>;
 
 impl BackupWriter {
@@ -92,14 +148,15 @@ impl BackupWriter {
             "backup-time": backup.time,
             "store": datastore,
             "debug": debug,
-            "benchmark": benchmark
+            "benchmark": benchmark,
+            "heartbeat": true,
         });
 
         if !ns.is_root() {
             param["ns"] = serde_json::to_value(ns)?;
         }
 
-        let req = HttpClient::request_builder(
+        let mut req = HttpClient::request_builder(
             client.server(),
             client.port(),
             "GET",
@@ -108,13 +165,59 @@ impl BackupWriter {
         )
         .unwrap();
 
+        // lets the server enforce a minimum client version per datastore
+        let version = format!(
+            "{}.{}",
+            pbs_buildcfg::PROXMOX_PKG_VERSION,
+            pbs_buildcfg::PROXMOX_PKG_RELEASE,
+        );
+        req.headers_mut()
+            .insert(CLIENT_VERSION_HEADER, version.parse().unwrap());
+
         let (h2, abort) = client
             .start_h2_connection(req, String::from(PROXMOX_BACKUP_PROTOCOL_ID_V1!()))
             .await?;
 
+        tokio::spawn(Self::heartbeat(h2.clone()));
+
         Ok(BackupWriter::new(h2, abort, crypt_config))
     }
 
+    // runs until the connection gets closed (or the server does not support heartbeats)
+    async fn heartbeat(h2: H2Client) {
+        loop {
+            tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+            if let Err(err) = h2.post("heartbeat", None).await {
+                log::debug!("stopping backup session heartbeat - {err}");
+                break;
+            }
+        }
+    }
+
+    // runs until aborted (or the server does not report running upload statistics)
+    async fn poll_upload_stats(h2: H2Client, wid: u64, progress: Arc<Progress>) {
+        loop {
+            tokio::time::sleep(UPLOAD_STATS_INTERVAL).await;
+            let list = match h2.get("upload_stats", None).await {
+                Ok(list) => list,
+                Err(err) => {
+                    log::debug!("stopping upload statistics polling - {err}");
+                    break;
+                }
+            };
+            let status = list
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|status| status["wid"].as_u64() == Some(wid));
+            if let Some(status) = status {
+                if let Some(deduplicated) = status["deduplicated"].as_f64() {
+                    progress.set_upload_stats(deduplicated, status["compression"].as_f64());
+                }
+            }
+        }
+    }
+
     pub async fn get(&self, path: &str, param: Option<Value>) -> Result<Value, Error> {
         self.h2.get(path, param).await
     }
@@ -168,9 +271,26 @@ impl BackupWriter {
     }
 
     pub async fn finish(self: Arc<Self>) -> Result<(), Error> {
+        self.finish_with_notes(None, &[]).await
+    }
+
+    /// Finish the backup and store notes and tags in the snapshot manifest.
+    pub async fn finish_with_notes(
+        self: Arc<Self>,
+        notes: Option<&str>,
+        tags: &[String],
+    ) -> Result<(), Error> {
+        let mut param = json!({});
+        if let Some(notes) = notes {
+            param["notes"] = notes.into();
+        }
+        if !tags.is_empty() {
+            param["tags"] = tags.into();
+        }
+
         let h2 = self.h2.clone();
 
-        h2.post("finish", None)
+        h2.post("finish", Some(param))
             .map_ok(move |_| {
                 self.abort.abort();
             })
@@ -260,13 +380,16 @@ impl BackupWriter {
             .await
     }
 
-    pub async fn upload_stream(
+    pub async fn upload_stream<T: Into<StreamChunk>>(
         &self,
         archive_name: &str,
-        stream: impl Stream<Item = Result<bytes::BytesMut, Error>>,
+        stream: impl Stream<Item = Result<T, Error>>,
         options: UploadOptions,
     ) -> Result<BackupStats, Error> {
-        let known_chunks = Arc::new(Mutex::new(HashSet::new()));
+        let known_chunks = match options.resume {
+            Some(ref resume) => Arc::new(Mutex::new(resume.known_chunks())),
+            None => Arc::new(Mutex::new(HashSet::new())),
+        };
 
         let mut param = json!({ "archive-name": archive_name });
         let prefix = if let Some(size) = options.fixed_size {
@@ -283,7 +406,19 @@ impl BackupWriter {
         let index_path = format!("{}_index", prefix);
         let close_path = format!("{}_close", prefix);
 
-        if let Some(manifest) = options.previous_manifest {
+        let previous_manifest = match options.previous_chunks {
+            Some(ref previous_chunks) => {
+                let previous_chunks = previous_chunks.lock().unwrap();
+                known_chunks
+                    .lock()
+                    .unwrap()
+                    .extend(previous_chunks.iter().copied());
+                None
+            }
+            None => options.previous_manifest,
+        };
+
+        if let Some(manifest) = previous_manifest {
             if !manifest
                 .files()
                 .iter()
@@ -329,10 +464,19 @@ impl BackupWriter {
             .as_u64()
             .unwrap();
 
+        let stats_poller = options.progress.as_ref().map(|progress| {
+            progress.start_archive(archive_name, options.fixed_size);
+            AbortOnDrop(tokio::spawn(Self::poll_upload_stats(
+                self.h2.clone(),
+                wid,
+                Arc::clone(progress),
+            )))
+        });
+
         let upload_stats = Self::upload_chunk_info_stream(
             self.h2.clone(),
             wid,
-            stream,
+            stream.map_ok(Into::into),
             prefix,
             known_chunks.clone(),
             if options.encrypt {
@@ -341,8 +485,22 @@ impl BackupWriter {
                 None
             },
             options.compress,
+            options.query_known_chunks,
+            options.resume.clone(),
+            options.threads.unwrap_or(1).max(1),
+            options.progress.clone(),
+            options.known_chunk_cache.clone(),
         )
-        .await?;
+        .await;
+
+        drop(stats_poller);
+        let upload_stats = upload_stats?;
+
+        if let Some(ref cache) = options.known_chunk_cache {
+            if let Err(err) = cache.save() {
+                log::warn!("{err}");
+            }
+        }
 
         let size_dirty = upload_stats.size - upload_stats.size_reused;
         let size: HumanByte = upload_stats.size.into();
@@ -406,12 +564,128 @@ impl BackupWriter {
             "csum": hex::encode(upload_stats.csum),
         });
         let _value = self.h2.post(&close_path, Some(param)).await?;
+
+        if let (Some(resume), Some(chunk_list)) = (options.resume, upload_stats.chunk_list) {
+            resume.record_index(archive_name, chunk_list);
+        }
+
         Ok(BackupStats {
             size: upload_stats.size as u64,
             csum: upload_stats.csum,
         })
     }
 
+    /// Create an index archive from the chunk list recorded in an interrupted run, without
+    /// reading the source again.
+    ///
+    /// Returns `None` if some of the chunks do not exist on the server anymore.
+    pub async fn replay_index(
+        &self,
+        archive_name: &str,
+        chunks: &[ResumeChunk],
+        fixed_size: Option<u64>,
+        size: u64,
+        csum: &[u8; 32],
+    ) -> Result<Option<BackupStats>, Error> {
+        let mut unique = HashSet::new();
+        let query_list: Vec<ResumeChunk> = chunks
+            .iter()
+            .filter(|(digest, _)| unique.insert(*digest))
+            .copied()
+            .collect();
+
+        let known = match self.register_known_chunks(&query_list).await {
+            Ok(known) => known,
+            Err(err) => {
+                log::warn!("{archive_name}: unable to query chunks, cannot resume - {err}");
+                return Ok(None);
+            }
+        };
+        if known.len() != query_list.len() {
+            log::info!(
+                "{archive_name}: {} chunks missing on the server, cannot resume",
+                query_list.len() - known.len()
+            );
+            return Ok(None);
+        }
+
+        let mut param = json!({ "archive-name": archive_name });
+        let prefix = if let Some(size) = fixed_size {
+            param["size"] = size.into();
+            "fixed"
+        } else {
+            "dynamic"
+        };
+        let index_path = format!("{}_index", prefix);
+        let close_path = format!("{}_close", prefix);
+
+        let wid = self
+            .h2
+            .post(&index_path, Some(param))
+            .await?
+            .as_u64()
+            .unwrap();
+
+        let mut offset = 0u64;
+        for batch in chunks.chunks(REPLAY_APPEND_BATCH) {
+            let mut digest_list = Vec::with_capacity(batch.len());
+            let mut offset_list = Vec::with_capacity(batch.len());
+            for (digest, chunk_size) in batch {
+                digest_list.push(hex::encode(digest));
+                offset_list.push(offset);
+                offset += *chunk_size as u64;
+            }
+            let param =
+                json!({ "wid": wid, "digest-list": digest_list, "offset-list": offset_list });
+            self.h2
+                .upload(
+                    "PUT",
+                    &index_path,
+                    None,
+                    "application/json",
+                    param.to_string().into_bytes(),
+                )
+                .await?;
+        }
+
+        if offset != size {
+            bail!("{archive_name}: recorded chunk list does not match archive size");
+        }
+
+        let param = json!({
+            "wid": wid ,
+            "chunk-count": chunks.len(),
+            "size": size,
+            "csum": hex::encode(csum),
+        });
+        self.h2.post(&close_path, Some(param)).await?;
+
+        log::info!(
+            "{}: re-used {} from interrupted backup run",
+            pbs_tools::format::strip_server_file_extension(archive_name),
+            HumanByte::from(size),
+        );
+
+        Ok(Some(BackupStats { size, csum: *csum }))
+    }
+
+    /// Ask the server which of the given chunks exist in the datastore, and register them for
+    /// this session.
+    pub async fn register_known_chunks(
+        &self,
+        chunks: &[ResumeChunk],
+    ) -> Result<HashSet<[u8; 32]>, Error> {
+        let mut known = HashSet::new();
+        for batch in chunks.chunks(KNOWN_CHUNKS_QUERY_MAX) {
+            let batch: Vec<([u8; 32], usize)> = batch
+                .iter()
+                .map(|(digest, size)| (*digest, *size as usize))
+                .collect();
+            known.extend(Self::query_known_chunks(&self.h2, &batch).await?);
+        }
+        Ok(known)
+    }
+
     fn response_queue() -> (
         mpsc::Sender<h2::client::ResponseFuture>,
         oneshot::Receiver<Result<(), Error>>,
@@ -463,13 +737,11 @@ impl BackupWriter {
         tokio::spawn(
             ReceiverStream::new(verify_queue_rx)
                 .map(Ok::<_, Error>)
-                .and_then(move |(merged_chunk_info, response): (MergedChunkInfo, Option<h2::client::ResponseFuture>)| {
+                .and_then(move |(merged_chunk_info, response): (MergedChunkInfo, Option<PendingResponse>)| {
                     match (response, merged_chunk_info) {
                         (Some(response), MergedChunkInfo::Known(list)) => {
                             Either::Left(
                                 response
-                                    .map_err(Error::from)
-                                    .and_then(H2Client::h2api_response)
                                     .and_then(move |_result| {
                                         future::ok(MergedChunkInfo::Known(list))
                                     })
@@ -631,11 +903,16 @@ impl BackupWriter {
     fn upload_chunk_info_stream(
         h2: H2Client,
         wid: u64,
-        stream: impl Stream<Item = Result<bytes::BytesMut, Error>>,
+        stream: impl Stream<Item = Result<StreamChunk, Error>>,
         prefix: &str,
         known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
         crypt_config: Option<Arc<CryptConfig>>,
         compress: bool,
+        query_known_chunks: bool,
+        resume: Option<Arc<BackupResumeState>>,
+        threads: usize,
+        progress: Option<Arc<Progress>>,
+        known_chunk_cache: Option<Arc<KnownChunkCache>>,
     ) -> impl Future<Output = Result<UploadStats, Error>> {
         let total_chunks = Arc::new(AtomicUsize::new(0));
         let total_chunks2 = total_chunks.clone();
@@ -661,54 +938,209 @@ impl BackupWriter {
         let index_csum = Arc::new(Mutex::new(Some(openssl::sha::Sha256::new())));
         let index_csum_2 = index_csum.clone();
 
-        stream
-            .and_then(move |data| {
-                let chunk_len = data.len();
+        // only image archives can be re-created without reading the source, directory archives
+        // are needed to rebuild the catalog anyway
+        let chunk_list = resume
+            .as_ref()
+            .filter(|_| is_fixed_chunk_size)
+            .map(|_| Arc::new(Mutex::new(Vec::new())));
+        let chunk_list2 = chunk_list.clone();
+
+        let crypt_config2 = crypt_config.clone();
+        let known_chunk_count3 = known_chunk_count.clone();
+        let reused_len3 = reused_len.clone();
+        let compressed_stream_len3 = compressed_stream_len.clone();
+        let progress2 = progress.clone();
+
+        // the digests of several chunks are computed in parallel on the blocking thread pool,
+        // `try_buffered` keeps the order needed for the index checksum below
+        let digests = stream
+            .map_ok(move |chunk| {
+                let crypt_config = crypt_config.clone();
+                async move {
+                    match chunk {
+                        StreamChunk::Data(data) => tokio::task::spawn_blocking(move || {
+                            let digest = match crypt_config {
+                                Some(ref config) => config.compute_digest(&data),
+                                None => openssl::sha::sha256(&data),
+                            };
+                            (StreamChunk::Data(data), digest)
+                        })
+                        .await
+                        .map_err(Error::from),
+                        StreamChunk::Reused { digest, size } => {
+                            Ok((StreamChunk::Reused { digest, size }, digest))
+                        }
+                    }
+                }
+            })
+            .try_buffered(threads);
 
-                total_chunks.fetch_add(1, Ordering::SeqCst);
-                let offset = stream_len.fetch_add(chunk_len, Ordering::SeqCst) as u64;
+        let pending = digests.and_then(move |(chunk, digest)| {
+            let chunk_len = chunk.len();
 
-                let mut chunk_builder = DataChunkBuilder::new(data.as_ref()).compress(compress);
+            if let Some(ref progress) = progress {
+                progress.add_read(chunk_len as u64);
+            }
 
-                if let Some(ref crypt_config) = crypt_config {
-                    chunk_builder = chunk_builder.crypt_config(crypt_config);
-                }
+            total_chunks.fetch_add(1, Ordering::SeqCst);
+            let offset = stream_len.fetch_add(chunk_len, Ordering::SeqCst) as u64;
 
-                let mut known_chunks = known_chunks.lock().unwrap();
-                let digest = chunk_builder.digest();
+            let data = match chunk {
+                StreamChunk::Data(data) => Some(data),
+                StreamChunk::Reused { .. } => None,
+            };
 
-                let mut guard = index_csum.lock().unwrap();
-                let csum = guard.as_mut().unwrap();
+            let mut known_chunks = known_chunks.lock().unwrap();
 
-                let chunk_end = offset + chunk_len as u64;
+            let mut guard = index_csum.lock().unwrap();
+            let csum = guard.as_mut().unwrap();
 
-                if !is_fixed_chunk_size {
-                    csum.update(&chunk_end.to_le_bytes());
-                }
-                csum.update(digest);
+            let chunk_end = offset + chunk_len as u64;
 
-                let chunk_is_known = known_chunks.contains(digest);
-                if chunk_is_known {
+            if !is_fixed_chunk_size {
+                csum.update(&chunk_end.to_le_bytes());
+            }
+            csum.update(&digest);
+
+            if let Some(ref chunk_list) = chunk_list {
+                chunk_list.lock().unwrap().push((digest, chunk_len as u32));
+            }
+
+            let chunk_is_known = known_chunks.contains(&digest);
+            match data {
+                _ if chunk_is_known => {
                     known_chunk_count.fetch_add(1, Ordering::SeqCst);
                     reused_len.fetch_add(chunk_len, Ordering::SeqCst);
-                    future::ok(MergedChunkInfo::Known(vec![(offset, *digest)]))
-                } else {
-                    let compressed_stream_len2 = compressed_stream_len.clone();
-                    known_chunks.insert(*digest);
-                    future::ready(chunk_builder.build().map(move |(chunk, digest)| {
-                        compressed_stream_len2.fetch_add(chunk.raw_size(), Ordering::SeqCst);
-                        MergedChunkInfo::New(ChunkInfo {
-                            chunk,
-                            digest,
-                            chunk_len: chunk_len as u64,
-                            offset,
+                    future::ok(PendingChunk::Known(offset, digest))
+                }
+                Some(data) => {
+                    known_chunks.insert(digest);
+                    future::ok(PendingChunk::New {
+                        data,
+                        offset,
+                        digest,
+                    })
+                }
+                // reused chunks are taken from the previous index, which registered them
+                None => future::err(format_err!(
+                    "reused chunk {} is not known for this backup session",
+                    hex::encode(digest)
+                )),
+            }
+        });
+
+        let batches = if query_known_chunks || known_chunk_cache.is_some() {
+            Either::Left(
+                pending
+                    .try_chunks(KNOWN_CHUNKS_QUERY_BATCH)
+                    .map_err(|err| err.1),
+            )
+        } else {
+            Either::Right(pending.map_ok(|chunk| vec![chunk]))
+        };
+
+        let query_h2 = h2.clone();
+        let query_enabled = Arc::new(AtomicBool::new(
+            query_known_chunks || known_chunk_cache.is_some(),
+        ));
+        let known_chunk_cache2 = known_chunk_cache.clone();
+
+        // encoding (compression and encryption) of several batches runs in parallel on the
+        // blocking thread pool, `try_buffered` keeps the order
+        batches
+            .map_ok(move |batch| {
+                let h2 = query_h2.clone();
+                let query_enabled = Arc::clone(&query_enabled);
+                let crypt_config = crypt_config2.clone();
+                let known_chunk_count = known_chunk_count3.clone();
+                let reused_len = reused_len3.clone();
+                let compressed_stream_len = compressed_stream_len3.clone();
+                let progress = progress2.clone();
+                let known_chunk_cache = known_chunk_cache2.clone();
+
+                async move {
+                    let new_chunks: Vec<([u8; 32], usize)> = batch
+                        .iter()
+                        .filter_map(|chunk| match chunk {
+                            PendingChunk::New { data, digest, .. } => Some((*digest, data.len())),
+                            PendingChunk::Known(..) => None,
+                        })
+                        // without datastore wide deduplication, only cached chunks are queried
+                        .filter(|(digest, _)| {
+                            query_known_chunks
+                                || known_chunk_cache
+                                    .as_ref()
+                                    .map_or(false, |cache| cache.contains(digest))
                         })
-                    }))
+                        .collect();
+
+                    let mut server_known = HashSet::new();
+                    if !new_chunks.is_empty() && query_enabled.load(Ordering::SeqCst) {
+                        match Self::query_known_chunks(&h2, &new_chunks).await {
+                            Ok(known) => {
+                                if let Some(ref cache) = known_chunk_cache {
+                                    cache.insert(known.iter().copied());
+                                }
+                                server_known = known;
+                            }
+                            Err(err) => {
+                                log::warn!("querying known chunks failed, disabling - {err}");
+                                query_enabled.store(false, Ordering::SeqCst);
+                            }
+                        }
+                    }
+
+                    let mut infos = Vec::with_capacity(batch.len());
+                    for chunk in batch {
+                        let info = match chunk {
+                            PendingChunk::Known(offset, digest) => {
+                                MergedChunkInfo::Known(vec![(offset, digest)])
+                            }
+                            PendingChunk::New {
+                                data,
+                                offset,
+                                digest,
+                            } => {
+                                if server_known.contains(&digest) {
+                                    known_chunk_count.fetch_add(1, Ordering::SeqCst);
+                                    reused_len.fetch_add(data.len(), Ordering::SeqCst);
+                                    MergedChunkInfo::Known(vec![(offset, digest)])
+                                } else {
+                                    let crypt_config = crypt_config.clone();
+                                    let chunk_len = data.len() as u64;
+                                    let chunk = tokio::task::spawn_blocking(move || {
+                                        DataBlob::encode(&data, crypt_config.as_deref(), compress)
+                                    })
+                                    .await??;
+                                    compressed_stream_len
+                                        .fetch_add(chunk.raw_size(), Ordering::SeqCst);
+                                    if let Some(ref progress) = progress {
+                                        progress.add_uploaded(chunk.raw_size());
+                                    }
+                                    MergedChunkInfo::New(ChunkInfo {
+                                        chunk,
+                                        digest,
+                                        chunk_len,
+                                        offset,
+                                    })
+                                }
+                            }
+                        };
+                        infos.push(Ok::<_, Error>(info));
+                    }
+
+                    Ok(futures::stream::iter(infos))
                 }
             })
+            .try_buffered(threads)
+            .try_flatten()
             .merge_known_chunks()
-            .try_for_each(move |merged_chunk_info| {
-                let upload_queue = upload_queue.clone();
+            // keep up to `threads` chunk uploads in flight, the append queue still gets the
+            // chunks in order
+            .map_ok(move |merged_chunk_info| {
+                let resume = resume.clone();
+                let known_chunk_cache = known_chunk_cache.clone();
 
                 if let MergedChunkInfo::New(chunk_info) = merged_chunk_info {
                     let offset = chunk_info.offset;
@@ -730,36 +1162,38 @@ impl BackupWriter {
                         "encoded-size": chunk_data.len(),
                     });
 
-                    let ct = "application/octet-stream";
-                    let request = H2Client::request_builder(
-                        "localhost",
-                        "POST",
-                        &upload_chunk_path,
-                        Some(param),
-                        Some(ct),
-                    )
-                    .unwrap();
-                    let upload_data = Some(bytes::Bytes::from(chunk_data));
+                    let upload_data = bytes::Bytes::from(chunk_data);
+                    let chunk_len = chunk_info.chunk_len as u32;
 
                     let new_info = MergedChunkInfo::Known(vec![(offset, digest)]);
 
-                    Either::Left(h2.send_request(request, upload_data).and_then(
-                        move |response| async move {
-                            upload_queue
-                                .send((new_info, Some(response)))
-                                .await
-                                .map_err(|err| {
-                                    format_err!("failed to send to upload queue: {}", err)
-                                })
-                        },
-                    ))
-                } else {
-                    Either::Right(async move {
-                        upload_queue
-                            .send((merged_chunk_info, None))
-                            .await
-                            .map_err(|err| format_err!("failed to send to upload queue: {}", err))
+                    let h2 = h2.clone();
+                    let upload_chunk_path = upload_chunk_path.clone();
+                    Either::Left(async move {
+                        let response = h2
+                            .upload_chunk(&upload_chunk_path, param, upload_data)
+                            .await?;
+                        if let Some(resume) = resume {
+                            resume.log_chunk(&digest, chunk_len);
+                        }
+                        if let Some(cache) = known_chunk_cache {
+                            cache.insert([digest]);
+                        }
+                        let response: PendingResponse = Box::pin(response);
+                        Ok::<_, Error>((new_info, Some(response)))
                     })
+                } else {
+                    Either::Right(future::ok((merged_chunk_info, None)))
+                }
+            })
+            .try_buffered(threads)
+            .try_for_each(move |(merged_chunk_info, response)| {
+                let upload_queue = upload_queue.clone();
+                async move {
+                    upload_queue
+                        .send((merged_chunk_info, response))
+                        .await
+                        .map_err(|err| format_err!("failed to send to upload queue: {}", err))
                 }
             })
             .then(move |result| async move { upload_result.await?.and(result) }.boxed())
@@ -774,6 +1208,8 @@ impl BackupWriter {
                 let mut guard = index_csum_2.lock().unwrap();
                 let csum = guard.take().unwrap().finish();
 
+                let chunk_list = chunk_list2.map(|list| std::mem::take(&mut *list.lock().unwrap()));
+
                 futures::future::ok(UploadStats {
                     chunk_count,
                     chunk_reused,
@@ -782,10 +1218,40 @@ impl BackupWriter {
                     size_compressed,
                     duration,
                     csum,
+                    chunk_list,
                 })
             })
     }
 
+    // Ask the server which of the given chunks exist in the datastore. Those are registered on
+    // the server side and can be appended to indices like chunks of the previous snapshot.
+    async fn query_known_chunks(
+        h2: &H2Client,
+        chunks: &[([u8; 32], usize)],
+    ) -> Result<HashSet<[u8; 32]>, Error> {
+        let param = json!({
+            "digest-list": chunks.iter().map(|(digest, _)| hex::encode(digest)).collect::<Vec<_>>(),
+            "size-list": chunks.iter().map(|(_, size)| *size).collect::<Vec<_>>(),
+        });
+
+        let value = h2.post("known_chunks", Some(param)).await?;
+        let list = value
+            .as_array()
+            .ok_or_else(|| format_err!("got unexpected known chunks result"))?;
+
+        let mut known = HashSet::with_capacity(list.len());
+        for item in list {
+            let digest_str = item
+                .as_str()
+                .ok_or_else(|| format_err!("got unexpected known chunks result"))?;
+            let mut digest = [0u8; 32];
+            hex::decode_to_slice(digest_str, &mut digest)?;
+            known.insert(digest);
+        }
+
+        Ok(known)
+    }
+
     /// Upload speed test - prints result to stderr
     pub async fn upload_speedtest(&self) -> Result<f64, Error> {
         let mut data = vec![];
@@ -7,8 +7,10 @@ use std::ops::ControlFlow;
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
+use futures::stream::{FuturesUnordered, StreamExt};
 use nix::dir::Dir;
 use nix::fcntl::OFlag;
 use nix::sys::stat::Mode;
@@ -20,9 +22,12 @@ use proxmox_sys::fs::{create_path, CreateOptions};
 use pxar::accessor::ReadAt;
 use pxar::{EntryKind, Metadata};
 
+use pbs_datastore::cached_chunk_reader::CachedChunkReader;
 use pbs_datastore::catalog::{self, DirEntryAttribute};
+use pbs_datastore::dynamic_index::DynamicIndexReader;
 use proxmox_async::runtime::block_in_place;
 
+use crate::pxar::extract::{can_skip_directory, include_prefixes, IncludePrefix};
 use crate::pxar::Flags;
 
 type CatalogReader = pbs_datastore::catalog::CatalogReader<std::fs::File>;
@@ -95,6 +100,10 @@ pub fn catalog_shell_cli() -> CommandLineInterface {
                 "find",
                 CliCommand::new(&API_METHOD_FIND_COMMAND).arg_param(&["pattern"]),
             )
+            .insert(
+                "grep",
+                CliCommand::new(&API_METHOD_GREP_COMMAND).arg_param(&["regex"]),
+            )
             .insert("exit", CliCommand::new(&API_METHOD_EXIT))
             .insert_help(),
     )
@@ -165,6 +174,12 @@ async fn ls_command(path: Option<String>) -> Result<(), Error> {
             path: {
                 type: String,
                 description: "target path."
+            },
+            catalog: {
+                type: Boolean,
+                description: "Only show the type, size and modification time stored in the catalog. The catalog has no mode, owner or other metadata.",
+                optional: true,
+                default: false,
             }
         }
     }
@@ -172,9 +187,11 @@ async fn ls_command(path: Option<String>) -> Result<(), Error> {
 /// Read the metadata for a given directory entry.
 ///
 /// This is expensive because the data has to be read from the pxar archive, which means reading
-/// over the network.
-async fn stat_command(path: String) -> Result<(), Error> {
-    Shell::with(move |shell| shell.stat(PathBuf::from(path))).await
+/// over the network. With `--catalog`, only the metadata stored in the catalog is shown, which
+/// is available immediately. The catalog only knows the type of an entry, and the size and
+/// modification time of regular files, so the mode and ownership are not shown then.
+async fn stat_command(path: String, catalog: bool) -> Result<(), Error> {
+    Shell::with(move |shell| shell.stat(PathBuf::from(path), catalog)).await
 }
 
 #[api(
@@ -253,16 +270,47 @@ async fn list_selected_command(patterns: bool) -> Result<(), Error> {
     }
 )]
 /// Find entries in the catalog matching the given match pattern.
+///
+/// The found entries become the current match set, see `restore-selected --matches`.
 async fn find_command(pattern: String, select: bool) -> Result<(), Error> {
     Shell::with(move |shell| shell.find(pattern, select)).await
 }
 
+#[api(
+    input: {
+        properties: {
+            regex: {
+                type: String,
+                description: "Regular expression matched against the paths of the entries."
+            },
+            select: {
+                type: bool,
+                optional: true,
+                default: false,
+                description: "Add matching entries to list for restore."
+            }
+        }
+    }
+)]
+/// Find entries in the catalog whose path matches the given regular expression.
+///
+/// The found entries become the current match set, see `restore-selected --matches`.
+async fn grep_command(regex: String, select: bool) -> Result<(), Error> {
+    Shell::with(move |shell| shell.grep(regex, select)).await
+}
+
 #[api(
     input: {
         properties: {
             target: {
                 type: String,
                 description: "target path for restore on local filesystem."
+            },
+            matches: {
+                type: bool,
+                optional: true,
+                default: false,
+                description: "Restore the entries found by the last 'find' or 'grep' instead."
             }
         }
     }
@@ -270,8 +318,8 @@ async fn find_command(pattern: String, select: bool) -> Result<(), Error> {
 /// Restore the selected entries to the given target path.
 ///
 /// Target must not exist on the clients filesystem.
-async fn restore_selected_command(target: String) -> Result<(), Error> {
-    Shell::with(move |shell| shell.restore_selected(PathBuf::from(target))).await
+async fn restore_selected_command(target: String, matches: bool) -> Result<(), Error> {
+    Shell::with(move |shell| shell.restore_selected(PathBuf::from(target), matches)).await
 }
 
 #[api(
@@ -318,6 +366,9 @@ pub struct Shell {
     /// List of selected paths for restore
     selected: HashMap<OsString, MatchEntry>,
 
+    /// Paths found by the last `find` or `grep`
+    matches: Vec<Vec<u8>>,
+
     /// pxar accessor instance for the current pxar archive
     accessor: Accessor,
 
@@ -366,6 +417,7 @@ impl Shell {
             prompt: String::new(),
             catalog,
             selected: HashMap::new(),
+            matches: Vec::new(),
             accessor: archive,
             position,
         };
@@ -693,7 +745,7 @@ impl Shell {
         Ok(())
     }
 
-    async fn stat(&mut self, path: PathBuf) -> Result<(), Error> {
+    async fn stat(&mut self, path: PathBuf, catalog: bool) -> Result<(), Error> {
         let mut stack = Self::lookup(
             &self.position,
             &mut self.catalog,
@@ -703,6 +755,34 @@ impl Shell {
         )
         .await?;
 
+        if catalog {
+            let path = Self::format_path_stack(&stack);
+            let entry = &stack.last().unwrap().catalog;
+            let (type_name, size, mtime) = match entry.attr {
+                DirEntryAttribute::Directory { .. } => ("directory", None, None),
+                DirEntryAttribute::File { size, mtime } => ("file", Some(size), Some(mtime)),
+                DirEntryAttribute::Symlink => ("symlink", None, None),
+                DirEntryAttribute::Hardlink => ("hardlink", None, None),
+                DirEntryAttribute::BlockDevice => ("block special file", None, None),
+                DirEntryAttribute::CharDevice => ("character special file", None, None),
+                DirEntryAttribute::Fifo => ("fifo", None, None),
+                DirEntryAttribute::Socket => ("socket", None, None),
+            };
+            let mtime = match mtime {
+                Some(mtime) => proxmox_time::strftime_local("%Y-%m-%d %H:%M:%S", mtime)?,
+                None => "-".to_string(),
+            };
+            println!(
+                "  File: {}\n  Size: {:<13} Type: {}\nModify: {}",
+                path.to_string_lossy(),
+                size.map(|size| size.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                type_name,
+                mtime,
+            );
+            return Ok(());
+        }
+
         let file = Self::walk_pxar_archive(&self.accessor, &mut stack).await?;
         std::io::stdout()
             .write_all(crate::pxar::format_multi_line_entry(file.entry()).as_bytes())?;
@@ -840,33 +920,99 @@ impl Shell {
         let pattern_entry =
             MatchEntry::parse_pattern(pattern, PatternFlag::PATH_NAME, MatchType::Include)?;
 
-        let mut found_some = false;
+        let mut matches = Vec::new();
         self.catalog.find(
             &self.position[0].catalog,
             &mut Vec::new(),
             &[&pattern_entry],
             &mut |path: &[u8]| -> Result<(), Error> {
-                found_some = true;
                 let mut out = std::io::stdout();
                 out.write_all(path)?;
                 out.write_all(b"\n")?;
+                matches.push(path.to_vec());
                 Ok(())
             },
         )?;
 
-        if found_some && select {
+        if !matches.is_empty() && select {
             self.selected.insert(pattern_os, pattern_entry);
         }
+        self.matches = matches;
+
+        Ok(())
+    }
+
+    async fn grep(&mut self, regex: String, select: bool) -> Result<(), Error> {
+        let regex = regex::bytes::Regex::new(&regex)?;
+
+        let mut matches = Vec::new();
+        let root = self.position[0].catalog.clone();
+        Self::grep_catalog(
+            &mut self.catalog,
+            &root,
+            &mut Vec::new(),
+            &regex,
+            &mut matches,
+        )?;
+
+        let mut out = std::io::stdout();
+        for path in matches.iter() {
+            out.write_all(path)?;
+            out.write_all(b"\n")?;
+        }
+
+        if select {
+            for path in matches.iter() {
+                let entry = MatchEntry::include(MatchPattern::Literal(path.clone()));
+                self.selected
+                    .insert(OsString::from_vec(path.clone()), entry);
+            }
+        }
+        self.matches = matches;
 
         Ok(())
     }
 
-    async fn restore_selected(&mut self, destination: PathBuf) -> Result<(), Error> {
-        if self.selected.is_empty() {
-            bail!("no entries selected");
+    fn grep_catalog<R: std::io::Read + std::io::Seek>(
+        catalog: &mut catalog::CatalogReader<R>,
+        parent: &catalog::DirEntry,
+        path: &mut Vec<u8>,
+        regex: &regex::bytes::Regex,
+        matches: &mut Vec<Vec<u8>>,
+    ) -> Result<(), Error> {
+        let path_len = path.len();
+        for entry in catalog.read_dir(parent)? {
+            path.truncate(path_len);
+            if !entry.name.starts_with(b"/") {
+                path.push(b'/');
+            }
+            path.extend(&entry.name);
+            if regex.is_match(path) {
+                matches.push(path.clone());
+            }
+            if entry.is_directory() {
+                Self::grep_catalog(catalog, &entry, path, regex, matches)?;
+            }
         }
+        path.truncate(path_len);
+        Ok(())
+    }
 
-        let match_list = self.build_match_list();
+    async fn restore_selected(&mut self, destination: PathBuf, matches: bool) -> Result<(), Error> {
+        let match_list = if matches {
+            if self.matches.is_empty() {
+                bail!("no entries found by the last 'find' or 'grep'");
+            }
+            self.matches
+                .iter()
+                .map(|path| MatchEntry::include(MatchPattern::Literal(path.clone())))
+                .collect()
+        } else {
+            if self.selected.is_empty() {
+                bail!("no entries selected");
+            }
+            self.build_match_list()
+        };
 
         self.restore_with_match_list(destination, &match_list).await
     }
@@ -897,21 +1043,7 @@ impl Shell {
         destination: PathBuf,
         match_list: &[MatchEntry],
     ) -> Result<(), Error> {
-        create_path(
-            &destination,
-            None,
-            Some(CreateOptions::new().perm(Mode::from_bits_truncate(0o700))),
-        )
-        .map_err(|err| format_err!("error creating directory {:?}: {}", destination, err))?;
-
-        let rootdir = Dir::open(
-            &destination,
-            OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
-            Mode::empty(),
-        )
-        .map_err(|err| {
-            format_err!("unable to open target directory {:?}: {}", destination, err,)
-        })?;
+        let rootdir = open_target_dir(&destination)?;
 
         let mut dir_stack = self.new_path_stack();
         Self::walk_pxar_archive(&self.accessor, &mut dir_stack).await?;
@@ -945,6 +1077,105 @@ impl Shell {
     }
 }
 
+fn open_target_dir(destination: &Path) -> Result<Dir, Error> {
+    create_path(
+        destination,
+        None,
+        Some(CreateOptions::new().perm(Mode::from_bits_truncate(0o700))),
+    )
+    .map_err(|err| format_err!("error creating directory {:?}: {}", destination, err))?;
+
+    Dir::open(
+        destination,
+        OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .map_err(|err| format_err!("unable to open target directory {:?}: {}", destination, err,))
+}
+
+/// Restore the entries of the pxar archive `archive_name` matching the
+/// `match_list` of `options` to `destination`.
+///
+/// The archive is traversed via the `catalog`, so only the contents of matching
+/// files are read from the archive, non-matching files are skipped without
+/// downloading their chunks.
+///
+/// With `parallel`, the file contents are not read via the `accessor`, but via
+/// the given reader, and several files are written at the same time, each by its
+/// own task.
+pub async fn restore_matching(
+    mut catalog: CatalogReader,
+    archive_name: &str,
+    accessor: Accessor,
+    destination: &Path,
+    feature_flags: Flags,
+    options: crate::pxar::PxarExtractOptions<'_>,
+    parallel: Option<ParallelRestore>,
+) -> Result<(), Error> {
+    let catalog_root = catalog.root()?;
+    let archive_root = catalog
+        .lookup(&catalog_root, archive_name.as_bytes())?
+        .ok_or_else(|| format_err!("archive not found in catalog"))?;
+    let mut dir_stack = vec![PathStackEntry::new(archive_root)];
+    Shell::walk_pxar_archive(&accessor, &mut dir_stack).await?;
+    let root_meta = dir_stack
+        .last()
+        .unwrap()
+        .pxar
+        .as_ref()
+        .unwrap()
+        .entry()
+        .metadata()
+        .clone();
+
+    let mut extractor = crate::pxar::extract::Extractor::new(
+        open_target_dir(destination)?,
+        root_meta,
+        options.allow_existing_dirs,
+        options.overwrite_flags,
+        feature_flags,
+    );
+    // shared with the parallel writers, so that they use the same error handling
+    let on_error: Arc<Mutex<crate::pxar::ErrorHandler>> = Arc::new(Mutex::new(
+        options.on_error.unwrap_or_else(|| Box::new(Err)),
+    ));
+    let extractor_on_error = Arc::clone(&on_error);
+    extractor.on_error(Box::new(move |err| {
+        (*extractor_on_error.lock().unwrap())(err)
+    }));
+
+    let mut state = ExtractorState::new(
+        &mut catalog,
+        dir_stack,
+        extractor,
+        options.match_list,
+        &accessor,
+    )?;
+    state.matches = options.extract_match_default;
+    state.parallel = parallel;
+    state.on_error = on_error;
+
+    state.extract().await
+}
+
+/// Reader for the file contents of a parallel [restore_matching].
+pub struct ParallelRestore {
+    /// Reader of the archive, shared by all concurrently written files
+    pub reader: Arc<CachedChunkReader<DynamicIndexReader, crate::RemoteChunkReader>>,
+    /// Number of files written at the same time
+    pub writers: usize,
+}
+
+/// Checks whether the catalog contains the archive `archive_name`, a requirement for
+/// [restore_matching].
+pub fn catalog_contains_archive(
+    catalog: &mut CatalogReader,
+    archive_name: &str,
+) -> Result<bool, Error> {
+    let root = catalog.root()?;
+    Ok(catalog.lookup(&root, archive_name.as_bytes())?.is_some())
+}
+
 struct ExtractorState<'a> {
     path: Vec<u8>,
     path_len: usize,
@@ -960,8 +1191,14 @@ struct ExtractorState<'a> {
 
     extractor: crate::pxar::extract::Extractor,
 
+    parallel: Option<ParallelRestore>,
+    writers: FuturesUnordered<tokio::task::JoinHandle<Result<(), Error>>>,
+    on_error: Arc<Mutex<crate::pxar::ErrorHandler>>,
+
     catalog: &'a mut CatalogReader,
     match_list: &'a [MatchEntry],
+    /// See [include_prefixes], `None` if directories cannot be skipped.
+    include_prefixes: Option<Vec<IncludePrefix>>,
     accessor: &'a Accessor,
 }
 
@@ -991,8 +1228,13 @@ impl<'a> ExtractorState<'a> {
 
             extractor,
 
+            parallel: None,
+            writers: FuturesUnordered::new(),
+            on_error: Arc::new(Mutex::new(Box::new(Err))),
+
             catalog,
             match_list,
+            include_prefixes: include_prefixes(match_list),
             accessor,
         })
     }
@@ -1019,9 +1261,20 @@ impl<'a> ExtractorState<'a> {
             self.handle_entry(entry).await?;
         }
 
+        while !self.writers.is_empty() {
+            self.wait_for_writer().await?;
+        }
+
         Ok(())
     }
 
+    async fn wait_for_writer(&mut self) -> Result<(), Error> {
+        match self.writers.next().await {
+            Some(result) => result?,
+            None => Ok(()),
+        }
+    }
+
     fn handle_end_of_directory(&mut self) -> Result<ControlFlow<()>, Error> {
         // go up a directory:
         self.read_dir = match self.read_dir_stack.pop() {
@@ -1052,6 +1305,7 @@ impl<'a> ExtractorState<'a> {
         &mut self,
         entry: catalog::DirEntry,
         match_result: Option<MatchType>,
+        did_match: bool,
     ) -> Result<(), Error> {
         // enter a new directory:
         self.read_dir_stack.push(mem::replace(
@@ -1069,6 +1323,8 @@ impl<'a> ExtractorState<'a> {
         let create = self.matches && match_result != Some(MatchType::Exclude);
         self.extractor
             .enter_directory(dir_pxar.file_name().to_os_string(), dir_meta, create)?;
+        // the contents of an included directory are restored too, unless excluded
+        self.matches = did_match;
 
         Ok(())
     }
@@ -1082,8 +1338,14 @@ impl<'a> ExtractorState<'a> {
         };
 
         match (did_match, &entry.attr) {
+            (false, DirEntryAttribute::Directory { .. })
+                if can_skip_directory(self.include_prefixes.as_deref(), &self.path) =>
+            {
+                // nothing in here can be restored, skip the whole subtree without reading it
+            }
             (_, DirEntryAttribute::Directory { .. }) => {
-                self.handle_new_directory(entry, match_result?).await?;
+                self.handle_new_directory(entry, match_result?, did_match)
+                    .await?;
             }
             (true, DirEntryAttribute::File { .. }) => {
                 self.dir_stack.push(PathStackEntry::new(entry));
@@ -1114,21 +1376,68 @@ impl<'a> ExtractorState<'a> {
     }
 
     async fn extract_file(&mut self, entry: FileEntry) -> Result<(), Error> {
-        match entry.kind() {
-            pxar::EntryKind::File { size, .. } => {
-                let file_name = CString::new(entry.file_name().as_bytes())?;
-                let mut contents = entry.contents().await?;
-                self.extractor
-                    .async_extract_file(&file_name, entry.metadata(), *size, &mut contents, false)
-                    .await
-            }
+        let size = match entry.kind() {
+            pxar::EntryKind::File { size, .. } => *size,
             _ => {
                 bail!(
                     "catalog file {:?} not a regular file in the archive",
                     self.path()
                 );
             }
+        };
+
+        let file_name = CString::new(entry.file_name().as_bytes())?;
+        let overwrite = self
+            .extractor
+            .overwrite_flags()
+            .contains(crate::pxar::extract::OverwriteFlags::FILE);
+
+        let (reader, max_writers) = match self.parallel {
+            Some(ref parallel) => (Arc::clone(&parallel.reader), parallel.writers),
+            None => {
+                let mut contents = entry.contents().await?;
+                return self
+                    .extractor
+                    .async_extract_file(
+                        &file_name,
+                        entry.metadata(),
+                        size,
+                        &mut contents,
+                        overwrite,
+                    )
+                    .await;
+            }
+        };
+
+        let range = entry
+            .content_range()?
+            .ok_or_else(|| format_err!("file {:?} without contents", self.path()))?;
+
+        while self.writers.len() >= max_writers {
+            self.wait_for_writer().await?;
         }
+
+        // the file is created right away, so that the directory contents are complete when its
+        // metadata gets applied, only the data is written by the spawned task
+        let writer = self
+            .extractor
+            .create_file(&file_name, entry.metadata(), size, overwrite)?;
+        let path = self.path().to_owned();
+        let on_error = Arc::clone(&self.on_error);
+        self.writers.push(tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+            let mut contents = reader.seekable_shared();
+            contents.seek(std::io::SeekFrom::Start(range.start)).await?;
+            let mut contents = contents.take(range.end - range.start);
+
+            let mut on_error = |err: Error| -> Result<(), Error> {
+                (*on_error.lock().unwrap())(err.context(format!("error at {path:?}")))
+            };
+            writer.write(&mut contents, &mut on_error).await
+        }));
+
+        Ok(())
     }
 
     async fn extract_special(
@@ -1232,3 +1541,75 @@ impl<'a> ExtractorState<'a> {
         })
     }
 }
+
+impl Drop for ExtractorState<'_> {
+    fn drop(&mut self) {
+        // stop writing files if the restore failed
+        for writer in self.writers.iter() {
+            writer.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pbs_datastore::catalog::{BackupCatalogWriter, CatalogWriter};
+
+    fn test_catalog() -> catalog::CatalogReader<std::io::Cursor<Vec<u8>>> {
+        let name = |name: &str| CString::new(name).unwrap();
+
+        let mut data = Vec::new();
+        let mut writer = CatalogWriter::new(&mut data).unwrap();
+        writer.start_directory(&name("root.pxar.didx")).unwrap();
+        writer.start_directory(&name("etc")).unwrap();
+        writer.add_file(&name("passwd"), 1723, 0).unwrap();
+        writer.add_file(&name("shadow"), 1024, 0).unwrap();
+        writer.add_symlink(&name("mtab")).unwrap();
+        writer.start_directory(&name("ssh")).unwrap();
+        writer.add_file(&name("sshd_config"), 3000, 0).unwrap();
+        writer.end_directory().unwrap();
+        writer.end_directory().unwrap();
+        writer.add_file(&name("passwd.bak"), 1700, 0).unwrap();
+        writer.end_directory().unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+
+        catalog::CatalogReader::new(std::io::Cursor::new(data))
+    }
+
+    fn grep(regex: &str) -> Vec<String> {
+        let mut catalog = test_catalog();
+        let root = catalog.root().unwrap();
+        let archive = catalog.lookup(&root, b"root.pxar.didx").unwrap().unwrap();
+
+        let mut matches = Vec::new();
+        Shell::grep_catalog(
+            &mut catalog,
+            &archive,
+            &mut Vec::new(),
+            &regex::bytes::Regex::new(regex).unwrap(),
+            &mut matches,
+        )
+        .unwrap();
+
+        matches
+            .into_iter()
+            .map(|path| String::from_utf8(path).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_grep_catalog() {
+        assert_eq!(
+            grep("/etc/(passwd|shadow)$"),
+            ["/etc/passwd", "/etc/shadow"]
+        );
+        // directories match as well, and are searched recursively
+        assert_eq!(grep("ssh"), ["/etc/ssh", "/etc/ssh/sshd_config"]);
+        assert_eq!(grep("passwd"), ["/etc/passwd", "/passwd.bak"]);
+        assert_eq!(grep("^/etc/mtab$"), ["/etc/mtab"]);
+        assert!(grep("^/nonexistent").is_empty());
+    }
+}
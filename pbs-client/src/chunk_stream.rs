@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
@@ -8,12 +9,49 @@ use futures::stream::{Stream, TryStream};
 
 use pbs_datastore::Chunker;
 
+use crate::pxar::ReusedRangeQueue;
+
+/// Chunk produced by a [ChunkStream]
+pub enum StreamChunk {
+    /// Chunk data, which needs to be uploaded unless the server knows it
+    Data(BytesMut),
+    /// Chunk of the previous snapshot, reused without reading it again
+    Reused { digest: [u8; 32], size: u64 },
+}
+
+impl StreamChunk {
+    /// Size of the (unencoded) chunk
+    pub fn len(&self) -> usize {
+        match self {
+            StreamChunk::Data(data) => data.len(),
+            StreamChunk::Reused { size, .. } => *size as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<BytesMut> for StreamChunk {
+    fn from(data: BytesMut) -> Self {
+        StreamChunk::Data(data)
+    }
+}
+
 /// Split input stream into dynamic sized chunks
 pub struct ChunkStream<S: Unpin> {
     input: S,
     chunker: Chunker,
     buffer: BytesMut,
     scan_pos: usize,
+    // stream offset of the first buffered byte
+    buffer_pos: u64,
+    reused_ranges: Option<ReusedRangeQueue>,
+    // reused chunks not returned yet
+    pending_reused: VecDeque<([u8; 32], u64)>,
+    // placeholder bytes of the current reused range still to drop
+    skip: u64,
 }
 
 impl<S: Unpin> ChunkStream<S> {
@@ -23,8 +61,54 @@ impl<S: Unpin> ChunkStream<S> {
             chunker: Chunker::new(chunk_size.unwrap_or(4 * 1024 * 1024)),
             buffer: BytesMut::new(),
             scan_pos: 0,
+            buffer_pos: 0,
+            reused_ranges: None,
+            pending_reused: VecDeque::new(),
+            skip: 0,
+        }
+    }
+
+    /// Replace the ranges queued by the archiver with chunks of the previous snapshot.
+    ///
+    /// A chunk boundary is forced at the start of each range, and the placeholder data of the
+    /// range is dropped from the input.
+    pub fn with_reused_ranges(mut self, reused_ranges: ReusedRangeQueue) -> Self {
+        self.reused_ranges = Some(reused_ranges);
+        self
+    }
+
+    fn split_chunk(&mut self, len: usize) -> BytesMut {
+        self.scan_pos = 0;
+        self.buffer_pos += len as u64;
+        self.buffer.split_to(len)
+    }
+
+    // Returns the number of buffered bytes before the next reused range, if it starts within or
+    // right after the buffered data.
+    fn next_range_limit(&self) -> Option<usize> {
+        let queue = self.reused_ranges.as_ref()?.lock().unwrap();
+        let start = queue.front()?.start;
+        let buffer_end = self.buffer_pos + self.buffer.len() as u64;
+        if start <= buffer_end {
+            Some((start - self.buffer_pos) as usize)
+        } else {
+            None
         }
     }
+
+    fn start_reused_range(&mut self) {
+        let range = self
+            .reused_ranges
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap();
+        self.skip = range.len();
+        self.pending_reused = range.chunks.into();
+        self.chunker.reset();
+    }
 }
 
 impl<S: Unpin> Unpin for ChunkStream<S> {}
@@ -35,25 +119,49 @@ where
     S::Ok: AsRef<[u8]>,
     S::Error: Into<Error>,
 {
-    type Item = Result<BytesMut, Error>;
+    type Item = Result<StreamChunk, Error>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
         loop {
-            if this.scan_pos < this.buffer.len() {
-                let boundary = this.chunker.scan(&this.buffer[this.scan_pos..]);
+            if let Some((digest, size)) = this.pending_reused.pop_front() {
+                return Poll::Ready(Some(Ok(StreamChunk::Reused { digest, size })));
+            }
+
+            if this.skip > 0 {
+                let drop_len = this.skip.min(this.buffer.len() as u64) as usize;
+                let _ = this.split_chunk(drop_len);
+                this.skip -= drop_len as u64;
+            }
 
-                let chunk_size = this.scan_pos + boundary;
+            if this.skip == 0 {
+                let range_limit = this.next_range_limit();
+                let scan_end = range_limit.unwrap_or(this.buffer.len());
 
-                if boundary == 0 {
-                    this.scan_pos = this.buffer.len();
-                    // continue poll
-                } else if chunk_size <= this.buffer.len() {
-                    let result = this.buffer.split_to(chunk_size);
-                    this.scan_pos = 0;
-                    return Poll::Ready(Some(Ok(result)));
-                } else {
-                    panic!("got unexpected chunk boundary from chunker");
+                if this.scan_pos < scan_end {
+                    let boundary = this.chunker.scan(&this.buffer[this.scan_pos..scan_end]);
+
+                    let chunk_size = this.scan_pos + boundary;
+
+                    if boundary == 0 {
+                        this.scan_pos = scan_end;
+                        // continue poll
+                    } else if chunk_size <= scan_end {
+                        let result = this.split_chunk(chunk_size);
+                        return Poll::Ready(Some(Ok(StreamChunk::Data(result))));
+                    } else {
+                        panic!("got unexpected chunk boundary from chunker");
+                    }
+                }
+
+                if let Some(limit) = range_limit {
+                    // all data before the range is scanned, cut the chunk at its start
+                    let result = this.split_chunk(limit);
+                    this.start_reused_range();
+                    if !result.is_empty() {
+                        return Poll::Ready(Some(Ok(StreamChunk::Data(result))));
+                    }
+                    continue;
                 }
             }
 
@@ -62,9 +170,22 @@ where
                     return Poll::Ready(Some(Err(err.into())));
                 }
                 None => {
+                    if this.skip > 0 {
+                        return Poll::Ready(Some(Err(Error::msg(
+                            "input stream ended within a reused range",
+                        ))));
+                    }
+                    if let Some(ref queue) = this.reused_ranges {
+                        if !queue.lock().unwrap().is_empty() {
+                            return Poll::Ready(Some(Err(Error::msg(
+                                "input stream ended before a queued reused range",
+                            ))));
+                        }
+                    }
                     this.scan_pos = 0;
                     if !this.buffer.is_empty() {
-                        return Poll::Ready(Some(Ok(this.buffer.split())));
+                        let len = this.buffer.len();
+                        return Poll::Ready(Some(Ok(StreamChunk::Data(this.split_chunk(len)))));
                     } else {
                         return Poll::Ready(None);
                     }
@@ -132,3 +253,108 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::stream::TryStreamExt;
+
+    use super::*;
+    use crate::pxar::ReusedRange;
+
+    fn chunk_input(
+        input: &[u8],
+        piece_size: usize,
+        ranges: Vec<ReusedRange>,
+    ) -> Result<Vec<StreamChunk>, Error> {
+        let pieces: Vec<Result<Vec<u8>, Error>> = input
+            .chunks(piece_size)
+            .map(|piece| Ok(piece.to_vec()))
+            .collect();
+        let queue = Arc::new(Mutex::new(VecDeque::from(ranges)));
+        let stream =
+            ChunkStream::new(futures::stream::iter(pieces), None).with_reused_ranges(queue);
+        futures::executor::block_on(stream.try_collect())
+    }
+
+    #[test]
+    fn test_reused_range_splice() -> Result<(), Error> {
+        let mut input = vec![b'a'; 1000];
+        input.extend(vec![0u8; 300]); // placeholder of the reused range
+        input.extend(vec![b'b'; 500]);
+
+        let range = ReusedRange {
+            start: 1000,
+            chunks: vec![([1u8; 32], 100), ([2u8; 32], 200)],
+        };
+
+        // pieces crossing the start and end of the range
+        for piece_size in [1, 7, 700, 1800] {
+            let chunks = chunk_input(
+                &input,
+                piece_size,
+                vec![ReusedRange {
+                    start: range.start,
+                    chunks: range.chunks.clone(),
+                }],
+            )?;
+
+            assert_eq!(chunks.len(), 4, "piece size {piece_size}");
+            match &chunks[0] {
+                StreamChunk::Data(data) => assert_eq!(&data[..], &input[..1000]),
+                _ => panic!("expected data before the range"),
+            }
+            match (&chunks[1], &chunks[2]) {
+                (
+                    StreamChunk::Reused {
+                        digest: digest1,
+                        size: 100,
+                    },
+                    StreamChunk::Reused {
+                        digest: digest2,
+                        size: 200,
+                    },
+                ) => {
+                    assert_eq!(digest1, &[1u8; 32]);
+                    assert_eq!(digest2, &[2u8; 32]);
+                }
+                _ => panic!("expected the reused chunks"),
+            }
+            match &chunks[3] {
+                StreamChunk::Data(data) => assert_eq!(&data[..], &input[1300..]),
+                _ => panic!("expected data after the range"),
+            }
+        }
+
+        // range at the very start, no empty data chunk before it
+        let chunks = chunk_input(
+            &input[1000..],
+            64,
+            vec![ReusedRange {
+                start: 0,
+                chunks: range.chunks.clone(),
+            }],
+        )?;
+        assert_eq!(chunks.len(), 3);
+        assert!(matches!(chunks[0], StreamChunk::Reused { size: 100, .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reused_range_truncated_input() {
+        let input = vec![b'a'; 1100];
+        let range = ReusedRange {
+            start: 1000,
+            chunks: vec![([1u8; 32], 300)],
+        };
+        assert!(chunk_input(&input, 100, vec![range]).is_err());
+
+        let range = ReusedRange {
+            start: 2000,
+            chunks: vec![([1u8; 32], 300)],
+        };
+        assert!(chunk_input(&input, 100, vec![range]).is_err());
+    }
+}
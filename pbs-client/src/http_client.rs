@@ -14,10 +14,12 @@ use openssl::{
     x509::X509StoreContextRef,
 };
 use percent_encoding::percent_encode;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use xdg::BaseDirectories;
 
 use proxmox_router::HttpError;
+use proxmox_schema::api;
 use proxmox_sys::fs::{file_get_json, replace_file, CreateOptions};
 use proxmox_sys::linux::tty;
 
@@ -28,6 +30,7 @@ use proxmox_http::{ProxyConfig, RateLimiter};
 
 use pbs_api_types::percent_encoding::DEFAULT_ENCODE_SET;
 use pbs_api_types::{Authid, RateLimitConfig, Userid};
+use pbs_tools::async_io::{cancellable_timeout, is_timeout, AbortSignal};
 
 use super::pipe_to_stream::PipeToSendStream;
 use super::PROXMOX_BACKUP_TCP_KEEPALIVE_TIME;
@@ -36,6 +39,182 @@ use super::PROXMOX_BACKUP_TCP_KEEPALIVE_TIME;
 /// certain error conditions. Keep it generous, to avoid false-positive under high load.
 const HTTP_TIMEOUT: Duration = Duration::from_secs(2 * 60);
 
+// retrying is opt-in, a request may have had side effects before it failed
+const DEFAULT_RETRY_ATTEMPTS: u64 = 0;
+const DEFAULT_RETRY_BACKOFF: u64 = 1;
+const DEFAULT_RETRY_MAX_BACKOFF: u64 = 30;
+
+#[api(
+    properties: {
+        attempts: {
+            type: Integer,
+            optional: true,
+            minimum: 0,
+            maximum: 20,
+            default: DEFAULT_RETRY_ATTEMPTS as isize,
+        },
+        backoff: {
+            type: Integer,
+            optional: true,
+            minimum: 1,
+            maximum: 600,
+            default: DEFAULT_RETRY_BACKOFF as isize,
+        },
+        "max-backoff": {
+            type: Integer,
+            optional: true,
+            minimum: 1,
+            maximum: 3600,
+            default: DEFAULT_RETRY_MAX_BACKOFF as isize,
+        },
+        jitter: {
+            type: Boolean,
+            optional: true,
+            default: true,
+        },
+    },
+)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Retry policy for requests failing with transient errors.
+pub struct RetryPolicy {
+    /// Number of retries, 0 disables retrying.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u64>,
+    /// Delay before the first retry in seconds, doubled for each further retry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backoff: Option<u64>,
+    /// Maximum delay between two retries in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_backoff: Option<u64>,
+    /// Randomize the delays, so that clients interrupted by the same event do not retry at
+    /// the same time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<bool>,
+}
+
+impl RetryPolicy {
+    /// Never retry.
+    pub fn none() -> Self {
+        Self {
+            attempts: Some(0),
+            ..Default::default()
+        }
+    }
+
+    fn delay(&self, retry: u64) -> Duration {
+        let backoff = self.backoff.unwrap_or(DEFAULT_RETRY_BACKOFF) as f64;
+        let max_backoff = self.max_backoff.unwrap_or(DEFAULT_RETRY_MAX_BACKOFF) as f64;
+
+        let mut delay = (backoff * 2f64.powi(retry.min(16) as i32)).min(max_backoff);
+        if self.jitter.unwrap_or(true) {
+            let mut random = [0u8; 2];
+            if openssl::rand::rand_bytes(&mut random).is_ok() {
+                let factor = u16::from_le_bytes(random) as f64 / u16::MAX as f64;
+                delay = delay / 2.0 + delay / 2.0 * factor;
+            }
+        }
+
+        Duration::from_secs_f64(delay)
+    }
+
+    /// Run `request` until it succeeds, fails with an error which is not transient (see
+    /// [is_transient_error]), or all retries are used up.
+    ///
+    /// Each attempt of `request` must use a fresh connection, or a connection from a pool which
+    /// drops failed connections, like the one of [HttpClient].
+    pub async fn run<T, F, R>(&self, what: &str, request: F) -> Result<T, Error>
+    where
+        F: FnMut() -> R,
+        R: Future<Output = Result<T, Error>>,
+    {
+        self.run_if(what, is_transient_error, request).await
+    }
+
+    /// Like [run](Self::run), but for requests sent on a single connection, like the HTTP/2
+    /// connection of a backup or reader session. Only errors which leave the connection usable
+    /// are retried (see [is_transient_stream_error]).
+    pub async fn run_on_connection<T, F, R>(&self, what: &str, request: F) -> Result<T, Error>
+    where
+        F: FnMut() -> R,
+        R: Future<Output = Result<T, Error>>,
+    {
+        self.run_if(what, is_transient_stream_error, request).await
+    }
+
+    async fn run_if<T, F, R>(
+        &self,
+        what: &str,
+        is_transient: fn(&Error) -> bool,
+        mut request: F,
+    ) -> Result<T, Error>
+    where
+        F: FnMut() -> R,
+        R: Future<Output = Result<T, Error>>,
+    {
+        let attempts = self.attempts.unwrap_or(DEFAULT_RETRY_ATTEMPTS);
+        let mut retry = 0;
+        loop {
+            match request().await {
+                Err(err) if retry < attempts && is_transient(&err) => {
+                    let delay = self.delay(retry);
+                    retry += 1;
+                    log::warn!(
+                        "{what} failed, retrying in {:.1}s ({retry}/{attempts}) - {err}",
+                        delay.as_secs_f64(),
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether a failed request may succeed when it is sent again on the same connection.
+///
+/// This is the case for server errors (5xx), for example from a restarting proxy, and for
+/// streams refused by the server. Any other error of the connection is not transient, as all
+/// further requests on that connection will fail as well.
+pub fn is_transient_stream_error(err: &Error) -> bool {
+    if let Some(err) = err.downcast_ref::<HttpError>() {
+        return err.code.is_server_error() && err.code != http::StatusCode::NOT_IMPLEMENTED;
+    }
+
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<h2::Error>())
+        .any(|err| err.reason() == Some(h2::Reason::REFUSED_STREAM))
+}
+
+/// Whether a failed request may succeed when it is sent again on a new connection.
+///
+/// In addition to [is_transient_stream_error], this is the case for failed connection attempts
+/// and for reset connections.
+pub fn is_transient_error(err: &Error) -> bool {
+    if is_transient_stream_error(err) {
+        return true;
+    }
+
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<hyper::Error>() {
+            if err.is_connect() || err.is_incomplete_message() {
+                return true;
+            }
+        }
+        if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+            use std::io::ErrorKind;
+            if matches!(
+                err.kind(),
+                ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+            ) {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
 #[derive(Clone)]
 pub struct AuthInfo {
     pub auth_id: Authid,
@@ -52,6 +231,8 @@ pub struct HttpClientOptions {
     fingerprint_cache: bool,
     verify_cert: bool,
     limit: RateLimitConfig,
+    abort: Option<AbortSignal>,
+    retry: RetryPolicy,
 }
 
 impl HttpClientOptions {
@@ -114,6 +295,18 @@ impl HttpClientOptions {
         self.limit = rate_limit;
         self
     }
+
+    /// Interrupt pending requests once `abort` is triggered, instead of waiting for the timeout.
+    pub fn abort_signal(mut self, abort: AbortSignal) -> Self {
+        self.abort = Some(abort);
+        self
+    }
+
+    /// Retry policy for idempotent requests and chunk transfers.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
 }
 
 impl Default for HttpClientOptions {
@@ -127,10 +320,20 @@ impl Default for HttpClientOptions {
             fingerprint_cache: false,
             verify_cert: true,
             limit: RateLimitConfig::default(), // unlimited
+            abort: None,
+            retry: RetryPolicy::default(),
         }
     }
 }
 
+fn timeout_error(err: Error, what: &str) -> Error {
+    if is_timeout(&err) {
+        format_err!("{what} timed out")
+    } else {
+        format_err!("{what} - {err}")
+    }
+}
+
 /// HTTP(S) API client
 pub struct HttpClient {
     client: Client<HttpsConnector>,
@@ -140,6 +343,8 @@ pub struct HttpClient {
     first_auth: Option<BroadcastFuture<()>>,
     auth: Arc<RwLock<AuthInfo>>,
     ticket_abort: futures::future::AbortHandle,
+    abort: Option<AbortSignal>,
+    retry: RetryPolicy,
     _options: HttpClientOptions,
 }
 
@@ -434,6 +639,7 @@ impl HttpClient {
         let client2 = client.clone();
         let auth2 = auth.clone();
         let prefix2 = options.prefix.clone();
+        let abort2 = options.abort.clone();
 
         let renewal_future = async move {
             loop {
@@ -448,6 +654,7 @@ impl HttpClient {
                     port,
                     auth_id.user().clone(),
                     ticket,
+                    abort2.clone(),
                 )
                 .await
                 {
@@ -483,6 +690,7 @@ impl HttpClient {
             port,
             auth_id.user().clone(),
             password,
+            options.abort.clone(),
         )
         .map_ok({
             let server = server.to_string();
@@ -523,6 +731,8 @@ impl HttpClient {
             auth,
             ticket_abort,
             first_auth,
+            abort: options.abort.clone(),
+            retry: options.retry,
             _options: options,
         })
     }
@@ -660,12 +870,17 @@ impl HttpClient {
             );
         }
 
-        Self::api_request(client, req).await
+        Self::api_request(client, req, self.abort.clone()).await
     }
 
     pub async fn get(&self, path: &str, data: Option<Value>) -> Result<Value, Error> {
-        let req = Self::request_builder(&self.server, self.port, "GET", path, data)?;
-        self.request(req).await
+        self.retry
+            .run(&format!("GET {path}"), || async {
+                let req =
+                    Self::request_builder(&self.server, self.port, "GET", path, data.clone())?;
+                self.request(req).await
+            })
+            .await
     }
 
     pub async fn delete(&self, path: &str, data: Option<Value>) -> Result<Value, Error> {
@@ -697,9 +912,10 @@ impl HttpClient {
         req.headers_mut()
             .insert("Cookie", HeaderValue::from_str(&enc_ticket).unwrap());
 
-        let resp = tokio::time::timeout(HTTP_TIMEOUT, client.request(req))
-            .await
-            .map_err(|_| format_err!("http download request timed out"))??;
+        let resp =
+            cancellable_timeout(client.request(req), Some(HTTP_TIMEOUT), self.abort.as_ref())
+                .await
+                .map_err(|err| timeout_error(err, "http download request"))??;
         let status = resp.status();
         if !status.is_success() {
             HttpClient::api_response(resp)
@@ -777,9 +993,10 @@ impl HttpClient {
         req.headers_mut()
             .insert("UPGRADE", HeaderValue::from_str(&protocol_name).unwrap());
 
-        let resp = tokio::time::timeout(HTTP_TIMEOUT, client.request(req))
-            .await
-            .map_err(|_| format_err!("http upgrade request timed out"))??;
+        let resp =
+            cancellable_timeout(client.request(req), Some(HTTP_TIMEOUT), self.abort.as_ref())
+                .await
+                .map_err(|err| timeout_error(err, "http upgrade request"))??;
         let status = resp.status();
 
         if status != http::StatusCode::SWITCHING_PROTOCOLS {
@@ -811,7 +1028,7 @@ impl HttpClient {
 
         // Wait until the `SendRequest` handle has available capacity.
         let c = h2.ready().await?;
-        Ok((H2Client::new(c), abort))
+        Ok((H2Client::new(c).retry_policy(self.retry), abort))
     }
 
     async fn credentials(
@@ -820,6 +1037,7 @@ impl HttpClient {
         port: u16,
         username: Userid,
         password: String,
+        abort: Option<AbortSignal>,
     ) -> Result<AuthInfo, Error> {
         let data = json!({ "username": username, "password": password });
         let req = Self::request_builder(
@@ -829,7 +1047,7 @@ impl HttpClient {
             "/api2/json/access/ticket",
             Some(data),
         )?;
-        let cred = Self::api_request(client, req).await?;
+        let cred = Self::api_request(client, req, abort).await?;
         let auth = AuthInfo {
             auth_id: cred["data"]["username"].as_str().unwrap().parse()?,
             ticket: cred["data"]["ticket"].as_str().unwrap().to_owned(),
@@ -862,11 +1080,12 @@ impl HttpClient {
     async fn api_request(
         client: Client<HttpsConnector>,
         req: Request<Body>,
+        abort: Option<AbortSignal>,
     ) -> Result<Value, Error> {
         Self::api_response(
-            tokio::time::timeout(HTTP_TIMEOUT, client.request(req))
+            cancellable_timeout(client.request(req), Some(HTTP_TIMEOUT), abort.as_ref())
                 .await
-                .map_err(|_| format_err!("http request timed out"))??,
+                .map_err(|err| timeout_error(err, "http request"))??,
         )
         .await
     }
@@ -937,16 +1156,82 @@ impl Drop for HttpClient {
 #[derive(Clone)]
 pub struct H2Client {
     h2: h2::client::SendRequest<bytes::Bytes>,
+    retry: RetryPolicy,
 }
 
 impl H2Client {
     pub fn new(h2: h2::client::SendRequest<bytes::Bytes>) -> Self {
-        Self { h2 }
+        Self {
+            h2,
+            retry: RetryPolicy::none(),
+        }
+    }
+
+    /// Retry policy for idempotent requests and chunk transfers.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Send a chunk upload request, `data` is sent again if the request fails with a transient
+    /// error. Since the chunk is only registered for the backup session, this is idempotent.
+    ///
+    /// The first attempt is sent before the returned future is awaited, so that uploads can be
+    /// pipelined.
+    pub async fn upload_chunk(
+        &self,
+        path: &str,
+        param: Value,
+        data: bytes::Bytes,
+    ) -> Result<impl Future<Output = Result<Value, Error>> + Send + 'static, Error> {
+        let content_type = "application/octet-stream";
+        let request = Self::request_builder(
+            "localhost",
+            "POST",
+            path,
+            Some(param.clone()),
+            Some(content_type),
+        )?;
+        let response = self.send_request(request, Some(data.clone())).await?;
+
+        let h2 = self.clone();
+        let path = path.to_string();
+        Ok(async move {
+            let mut first = Some(response);
+            h2.retry
+                .run_on_connection("chunk upload", || {
+                    let h2 = h2.clone();
+                    let first = first.take();
+                    let (path, param, data) = (path.clone(), param.clone(), data.clone());
+                    async move {
+                        let response = match first {
+                            Some(response) => response,
+                            None => {
+                                let request = Self::request_builder(
+                                    "localhost",
+                                    "POST",
+                                    &path,
+                                    Some(param),
+                                    Some(content_type),
+                                )?;
+                                h2.send_request(request, Some(data)).await?
+                            }
+                        };
+                        let response = response.await.map_err(Error::from)?;
+                        Self::h2api_response(response).await
+                    }
+                })
+                .await
+        })
     }
 
     pub async fn get(&self, path: &str, param: Option<Value>) -> Result<Value, Error> {
-        let req = Self::request_builder("localhost", "GET", path, param, None).unwrap();
-        self.request(req).await
+        self.retry
+            .run_on_connection(&format!("GET {path}"), || async {
+                let req = Self::request_builder("localhost", "GET", path, param.clone(), None)?;
+                self.request(req).await
+            })
+            .await
     }
 
     pub async fn put(&self, path: &str, param: Option<Value>) -> Result<Value, Error> {
@@ -965,17 +1250,19 @@ impl H2Client {
         param: Option<Value>,
         mut output: W,
     ) -> Result<(), Error> {
-        let request = Self::request_builder("localhost", "GET", path, param, None).unwrap();
-
-        let response_future = self.send_request(request, None).await?;
-
-        let resp = response_future.await?;
-
-        let status = resp.status();
-        if !status.is_success() {
-            H2Client::h2api_response(resp).await?; // raise error
-            unreachable!();
-        }
+        // only retry until the response arrives, data written to `output` cannot be taken back
+        let resp = self
+            .retry
+            .run_on_connection(&format!("download of {path}"), || async {
+                let request = Self::request_builder("localhost", "GET", path, param.clone(), None)?;
+                let resp = self.send_request(request, None).await?.await?;
+                if !resp.status().is_success() {
+                    H2Client::h2api_response(resp).await?; // raise error
+                    unreachable!();
+                }
+                Ok(resp)
+            })
+            .await?;
 
         let mut body = resp.into_body();
         while let Some(chunk) = body.data().await {
@@ -1107,3 +1394,98 @@ impl H2Client {
         Ok(request)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transient_errors() {
+        let server_error = Error::from(HttpError::new(
+            http::StatusCode::BAD_GATEWAY,
+            "proxy restarting".to_string(),
+        ));
+        let client_error = Error::from(HttpError::new(
+            http::StatusCode::BAD_REQUEST,
+            "bad request".to_string(),
+        ));
+        let not_implemented = Error::from(HttpError::new(
+            http::StatusCode::NOT_IMPLEMENTED,
+            "not implemented".to_string(),
+        ));
+        let refused =
+            Error::from(h2::Error::from(h2::Reason::REFUSED_STREAM)).context("chunk upload failed");
+        let reset = Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        let other = format_err!("something else");
+
+        // errors which leave the connection usable
+        assert!(is_transient_stream_error(&server_error));
+        assert!(is_transient_stream_error(&refused));
+        assert!(!is_transient_stream_error(&reset));
+
+        // a reset connection can only be retried with a new connection
+        assert!(is_transient_error(&server_error));
+        assert!(is_transient_error(&refused));
+        assert!(is_transient_error(&reset));
+
+        for err in [&client_error, &not_implemented, &other] {
+            assert!(!is_transient_stream_error(err));
+            assert!(!is_transient_error(err));
+        }
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            backoff: Some(2),
+            max_backoff: Some(10),
+            jitter: Some(false),
+            ..Default::default()
+        };
+        let delays: Vec<u64> = (0..5).map(|retry| policy.delay(retry).as_secs()).collect();
+        assert_eq!(delays, [2, 4, 8, 10, 10]);
+
+        let policy = RetryPolicy {
+            jitter: Some(true),
+            ..policy
+        };
+        for retry in 0..5 {
+            let delay = policy.delay(retry).as_secs_f64();
+            assert!((1.0..=10.0).contains(&delay));
+        }
+    }
+
+    #[test]
+    fn test_retry_opt_in() -> Result<(), Error> {
+        let count_attempts = |policy: RetryPolicy, err: fn() -> Error| {
+            let mut attempts = 0;
+            let result: Result<(), Error> =
+                proxmox_async::runtime::main(policy.run("test", || {
+                    attempts += 1;
+                    futures::future::ready(Err(err()))
+                }));
+            assert!(result.is_err());
+            attempts
+        };
+        let server_error = || {
+            Error::from(HttpError::new(
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                "unavailable".to_string(),
+            ))
+        };
+
+        // no retries without explicit policy
+        assert_eq!(count_attempts(RetryPolicy::default(), server_error), 1);
+
+        let policy = RetryPolicy {
+            attempts: Some(1),
+            backoff: Some(1),
+            jitter: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(count_attempts(policy, server_error), 2);
+        assert_eq!(count_attempts(policy, || format_err!("permanent")), 1);
+
+        Ok(())
+    }
+}
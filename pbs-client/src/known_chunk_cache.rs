@@ -0,0 +1,271 @@
+//! Local cache of chunks known to exist on the server
+//!
+//! Chunks are usually only deduplicated against the previous snapshot of the
+//! same group. A re-seeded or cloned machine has no previous snapshot, so it
+//! would upload all of its data again, even if identical chunks are already
+//! stored in the datastore.
+//!
+//! The cache records the digests of all chunks the server confirmed, either
+//! because they were uploaded or because a known chunks query found them.
+//! Later runs only ask the server about chunks in the cache, instead of
+//! querying every new chunk, and reuse those which still exist. The server
+//! always checks the chunks, so stale entries, for example of chunks removed
+//! by garbage collection, only cost a query.
+//!
+//! The cache directory can be shared between machines, for example on a
+//! network file system, so the digests uploaded by one machine are known to
+//! all others. It contains one file per datastore and encryption key, as
+//! digests of encrypted chunks depend on the key. A file is a sequence of 32
+//! byte digests, which are appended under a file lock. Digests another machine
+//! appended in the meantime are not appended again, and duplicates which still
+//! end up in a file are removed the next time it is opened.
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{format_err, Error};
+
+use proxmox_sys::fs::lock_file;
+
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::BackupRepository;
+
+const DIGEST_SIZE: usize = 32;
+
+/// Maximum number of digests kept per file, the oldest entries are dropped
+/// beyond that (with 4 MiB chunks, enough for 16 TiB of unique data)
+const MAX_ENTRIES: usize = 4 * 1024 * 1024;
+
+const LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Digests of chunks known to exist in a datastore
+pub struct KnownChunkCache {
+    path: PathBuf,
+    known: HashSet<[u8; 32]>,
+    new: Mutex<HashSet<[u8; 32]>>,
+}
+
+fn parse_digests(data: &[u8]) -> impl Iterator<Item = [u8; 32]> + '_ {
+    // a partially written entry at the end is ignored
+    data.chunks_exact(DIGEST_SIZE)
+        .map(|entry| entry.try_into().unwrap())
+}
+
+/// Remove duplicate digests, keeping the most recent entry of each.
+fn dedup_digests(data: &[u8]) -> Vec<[u8; 32]> {
+    let mut seen = HashSet::new();
+    let mut digests: Vec<[u8; 32]> = parse_digests(data)
+        .rev()
+        .filter(|digest| seen.insert(*digest))
+        .collect();
+    digests.reverse();
+    digests
+}
+
+fn open_locked(path: &Path) -> Result<File, Error> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format_err!("unable to open known chunks cache {path:?} - {err}"))?;
+    lock_file(&mut file, true, Some(LOCK_TIMEOUT))
+        .map_err(|err| format_err!("unable to lock known chunks cache {path:?} - {err}"))?;
+    Ok(file)
+}
+
+impl KnownChunkCache {
+    /// Default cache directory (`$HOME/.cache/proxmox-backup/known-chunks`).
+    pub fn default_dir() -> Result<PathBuf, Error> {
+        let base = crate::tools::base_directories()?;
+        Ok(base.create_cache_directory("known-chunks")?)
+    }
+
+    /// Open the cache for the datastore of `repo` in `dir`.
+    pub fn open(
+        dir: &Path,
+        repo: &BackupRepository,
+        crypt_config: Option<&CryptConfig>,
+    ) -> Result<Self, Error> {
+        // independent of the user, so that the cache can be shared
+        let key_fingerprint = crypt_config
+            .map(|config| hex::encode(config.fingerprint()))
+            .unwrap_or_default();
+        let id = openssl::sha::sha256(
+            format!("{}\n{}\n{}", repo.host(), repo.store(), key_fingerprint).as_bytes(),
+        );
+
+        std::fs::create_dir_all(dir)
+            .map_err(|err| format_err!("unable to create known chunks cache {dir:?} - {err}"))?;
+        let path = dir.join(hex::encode(id));
+
+        let mut file = open_locked(&path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let count = data.len() / DIGEST_SIZE;
+        let mut digests = dedup_digests(&data);
+        if digests.len() > MAX_ENTRIES {
+            // keep the most recent entries
+            digests.drain(..digests.len() - MAX_ENTRIES);
+        }
+        if digests.len() != count {
+            data.clear();
+            for digest in &digests {
+                data.extend_from_slice(digest);
+            }
+            // appended at the start of the now empty file
+            file.set_len(0)?;
+            file.write_all(&data)?;
+        }
+
+        Ok(Self {
+            path,
+            known: digests.into_iter().collect(),
+            new: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Check if a chunk was confirmed by the server before.
+    pub fn contains(&self, digest: &[u8; 32]) -> bool {
+        self.known.contains(digest)
+    }
+
+    /// Record chunks the server confirmed, they are written out with [save](Self::save).
+    pub fn insert(&self, digests: impl IntoIterator<Item = [u8; 32]>) {
+        let mut new = self.new.lock().unwrap();
+        new.extend(
+            digests
+                .into_iter()
+                .filter(|digest| !self.known.contains(digest)),
+        );
+    }
+
+    /// Append the recorded chunks to the cache file.
+    pub fn save(&self) -> Result<(), Error> {
+        let mut new = std::mem::take(&mut *self.new.lock().unwrap());
+        if new.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = open_locked(&self.path)?;
+        // drop a partially written entry of an aborted run
+        let len = file.metadata()?.len();
+        if len % DIGEST_SIZE as u64 != 0 {
+            file.set_len(len - len % DIGEST_SIZE as u64)?;
+        }
+
+        // skip chunks which other users of a shared cache appended in the meantime
+        let mut reader = BufReader::new(&file);
+        let mut digest = [0u8; DIGEST_SIZE];
+        while !new.is_empty() && reader.read_exact(&mut digest).is_ok() {
+            new.remove(&digest);
+        }
+        if new.is_empty() {
+            return Ok(());
+        }
+
+        let mut data = Vec::with_capacity(new.len() * DIGEST_SIZE);
+        for digest in new {
+            data.extend_from_slice(&digest);
+        }
+
+        file.write_all(&data)
+            .map_err(|err| format_err!("unable to write known chunks cache - {err}"))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test directory below ./target/testout, removed again when dropped, also if the test fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let mut path: PathBuf = String::from("./target/testout").into();
+            path.push(std::module_path!());
+            path.push(name);
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn digest(n: u8) -> [u8; 32] {
+        [n; 32]
+    }
+
+    fn file_digests(cache: &KnownChunkCache) -> Vec<[u8; 32]> {
+        let data = std::fs::read(&cache.path).unwrap();
+        parse_digests(&data).collect()
+    }
+
+    #[test]
+    fn test_dedup_digests() {
+        let mut data = Vec::new();
+        for n in [1, 2, 1, 3, 2] {
+            data.extend_from_slice(&digest(n));
+        }
+        // a partially written entry
+        data.extend_from_slice(&[4; 10]);
+
+        assert_eq!(dedup_digests(&data), [digest(1), digest(3), digest(2)]);
+    }
+
+    #[test]
+    fn test_known_chunk_cache() -> Result<(), Error> {
+        let test_dir = TestDir::new("known_chunk_cache");
+        let dir = &test_dir.0;
+        let repo: BackupRepository = "localhost:store1".parse()?;
+
+        let first = KnownChunkCache::open(dir, &repo, None)?;
+        let second = KnownChunkCache::open(dir, &repo, None)?;
+
+        first.insert([digest(1), digest(2), digest(1)]);
+        first.insert([digest(2)]);
+        first.save()?;
+        assert_eq!(file_digests(&first).len(), 2);
+
+        // the other user of the shared cache only appends what is still missing
+        second.insert([digest(2), digest(3)]);
+        second.save()?;
+        let digests = file_digests(&second);
+        assert_eq!(digests.len(), 3);
+        assert!(digests.contains(&digest(3)));
+
+        // known chunks are not recorded again
+        let third = KnownChunkCache::open(dir, &repo, None)?;
+        assert!(third.contains(&digest(1)));
+        assert!(!third.contains(&digest(4)));
+        third.insert([digest(1), digest(4)]);
+        third.save()?;
+        assert_eq!(file_digests(&third).len(), 4);
+
+        // duplicates, e.g. written by an older client, are removed on open
+        let mut file = OpenOptions::new().append(true).open(&third.path)?;
+        file.write_all(&digest(1))?;
+        drop(file);
+        let fourth = KnownChunkCache::open(dir, &repo, None)?;
+        assert_eq!(
+            file_digests(&fourth)[..],
+            [digest(2), digest(3), digest(4), digest(1)],
+        );
+
+        Ok(())
+    }
+}
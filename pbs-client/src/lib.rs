@@ -25,6 +25,15 @@ pub use backup_reader::*;
 mod backup_writer;
 pub use backup_writer::*;
 
+mod backup_resume;
+pub use backup_resume::*;
+
+mod known_chunk_cache;
+pub use known_chunk_cache::KnownChunkCache;
+
+mod progress;
+pub use progress::*;
+
 mod remote_chunk_reader;
 pub use remote_chunk_reader::*;
 
@@ -38,6 +47,8 @@ mod backup_specification;
 pub use backup_specification::*;
 
 mod chunk_stream;
-pub use chunk_stream::{ChunkStream, FixedChunkStream};
+pub use chunk_stream::{ChunkStream, FixedChunkStream, StreamChunk};
+
+pub mod tape_restore_stream;
 
 pub const PROXMOX_BACKUP_TCP_KEEPALIVE_TIME: u32 = 120;
@@ -0,0 +1,359 @@
+//! Progress reports for long running backups and restores
+//!
+//! A [Progress] collects the processed bytes and the current file, a [ProgressReporter]
+//! periodically logs it and optionally writes it to a status file descriptor, as one JSON
+//! object per line:
+//!
+//! ```text
+//! {"archive":"root.pxar.didx","file":"usr/lib/libc.so.6","read":1073741824,"uploaded":268435456,"elapsed":12,"throughput":89478485,"done":false}
+//! ```
+
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{format_err, Error};
+use serde::Serialize;
+
+use proxmox_human_byte::HumanByte;
+use proxmox_schema::{IntegerSchema, Schema};
+
+pub const STATUS_FD_SCHEMA: Schema = IntegerSchema::new(
+    "Write progress records as JSON objects, one per line, to an already opened file descriptor.",
+)
+.minimum(0)
+.schema();
+
+/// Interval for records written to the status file descriptor
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval for progress messages in the log
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A single progress record.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProgressRecord {
+    /// The archive currently processed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+    /// The file currently processed, relative to the archive root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Bytes read from the source (backup) or downloaded from the server (restore)
+    pub read: u64,
+    /// Bytes uploaded after deduplication and compression (backup only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploaded: Option<u64>,
+    /// Size of the current archive, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    /// Seconds since the start
+    pub elapsed: u64,
+    /// Average throughput in bytes per second
+    pub throughput: u64,
+    /// Estimated seconds until the current archive is done, if its size is known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta: Option<u64>,
+    /// Percentage of deduplicated chunks of the current archive so far, as reported by the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deduplicated: Option<f64>,
+    /// Compressed size of the uploaded chunks of the current archive so far, in percent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<f64>,
+    /// Set for the final record
+    pub done: bool,
+}
+
+struct ArchiveState {
+    name: Option<String>,
+    total: Option<u64>,
+    start: Instant,
+    read_at_start: u64,
+    file: Option<String>,
+    deduplicated: Option<f64>,
+    compression: Option<f64>,
+}
+
+/// Progress of a backup or restore run, shared between the workers and the reporter.
+pub struct Progress {
+    start: Instant,
+    upload: bool,
+    read: AtomicU64,
+    uploaded: AtomicU64,
+    archive: Mutex<ArchiveState>,
+}
+
+impl Progress {
+    fn new(upload: bool) -> Arc<Self> {
+        let start = Instant::now();
+        Arc::new(Self {
+            start,
+            upload,
+            read: AtomicU64::new(0),
+            uploaded: AtomicU64::new(0),
+            archive: Mutex::new(ArchiveState {
+                name: None,
+                total: None,
+                start,
+                read_at_start: 0,
+                file: None,
+                deduplicated: None,
+                compression: None,
+            }),
+        })
+    }
+
+    /// Progress of a backup, which also counts uploaded bytes.
+    pub fn backup() -> Arc<Self> {
+        Self::new(true)
+    }
+
+    /// Progress of a restore.
+    pub fn restore() -> Arc<Self> {
+        Self::new(false)
+    }
+
+    /// Start processing a new archive, `total` is its size if known in advance.
+    pub fn start_archive(&self, name: &str, total: Option<u64>) {
+        let mut archive = self.archive.lock().unwrap();
+        *archive = ArchiveState {
+            name: Some(name.to_string()),
+            total,
+            start: Instant::now(),
+            read_at_start: self.read.load(Ordering::SeqCst),
+            file: None,
+            deduplicated: None,
+            compression: None,
+        };
+    }
+
+    /// Set the running deduplication and compression ratios of the current archive.
+    pub fn set_upload_stats(&self, deduplicated: f64, compression: Option<f64>) {
+        let mut archive = self.archive.lock().unwrap();
+        archive.deduplicated = Some(deduplicated);
+        archive.compression = compression;
+    }
+
+    /// Set the file currently processed.
+    pub fn set_current_file(&self, path: &Path) {
+        self.archive.lock().unwrap().file = Some(path.to_string_lossy().into_owned());
+    }
+
+    pub fn add_read(&self, bytes: u64) {
+        self.read.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    pub fn add_uploaded(&self, bytes: u64) {
+        self.uploaded.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    /// The current state as record.
+    pub fn record(&self, done: bool) -> ProgressRecord {
+        let read = self.read.load(Ordering::SeqCst);
+        let elapsed = self.start.elapsed();
+        let throughput = (read as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+
+        let archive = self.archive.lock().unwrap();
+        let eta = match archive.total {
+            Some(total) if !done => estimate_remaining(
+                total,
+                read - archive.read_at_start,
+                archive.start.elapsed().as_secs_f64(),
+            ),
+            _ => None,
+        };
+
+        ProgressRecord {
+            archive: archive.name.clone(),
+            file: archive.file.clone(),
+            read,
+            uploaded: self.upload.then(|| self.uploaded.load(Ordering::SeqCst)),
+            total: archive.total,
+            elapsed: elapsed.as_secs(),
+            throughput,
+            eta,
+            deduplicated: archive.deduplicated,
+            compression: archive.compression,
+            done,
+        }
+    }
+
+    fn log(&self) {
+        let record = self.record(false);
+
+        let mut msg = format!("progress: read {}", HumanByte::from(record.read));
+        if let Some(uploaded) = record.uploaded {
+            msg.push_str(&format!(", uploaded {}", HumanByte::from(uploaded)));
+        }
+        msg.push_str(&format!(", {}/s", HumanByte::from(record.throughput)));
+        if let Some(eta) = record.eta {
+            msg.push_str(&format!(", ETA {}", format_seconds(eta)));
+        }
+        if let Some(deduplicated) = record.deduplicated {
+            msg.push_str(&format!(", {deduplicated:.1}% deduplicated so far"));
+        }
+        match (&record.archive, &record.file) {
+            (Some(archive), Some(file)) => msg.push_str(&format!(" ({archive}: {file})")),
+            (Some(archive), None) => msg.push_str(&format!(" ({archive})")),
+            _ => (),
+        }
+
+        log::info!("{msg}");
+    }
+}
+
+/// Estimate the seconds left for an archive of size `total`, from the bytes processed so far.
+fn estimate_remaining(total: u64, processed: u64, elapsed: f64) -> Option<u64> {
+    if processed == 0 {
+        return None;
+    }
+    let rate = processed as f64 / elapsed.max(0.001);
+    Some((total.saturating_sub(processed) as f64 / rate) as u64)
+}
+
+fn format_seconds(secs: u64) -> String {
+    format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+/// Write a record to the status file descriptor.
+///
+/// The reading side may be slow, so the write happens in a blocking thread and does not stall
+/// the runtime.
+async fn write_record(status: &Arc<Mutex<File>>, record: &ProgressRecord) {
+    let mut line = match serde_json::to_string(record) {
+        Ok(line) => line,
+        Err(_) => return,
+    };
+    line.push('\n');
+    let status = Arc::clone(status);
+    // the reading side is free to go away, the backup or restore goes on anyway
+    let _ = tokio::task::spawn_blocking(move || {
+        let _ = status.lock().unwrap().write_all(line.as_bytes());
+    })
+    .await;
+}
+
+/// Periodically logs the progress, and writes it to a status file descriptor if requested.
+///
+/// Reporting stops when the reporter is dropped.
+pub struct ProgressReporter {
+    progress: Arc<Progress>,
+    status: Option<Arc<Mutex<File>>>,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+impl ProgressReporter {
+    /// Start reporting, the file descriptor `status_fd` stays open.
+    pub fn start(progress: Arc<Progress>, status_fd: Option<RawFd>) -> Result<Self, Error> {
+        let status = match status_fd {
+            Some(fd) => {
+                let fd = nix::unistd::dup(fd)
+                    .map_err(|err| format_err!("invalid status file descriptor {fd} - {err}"))?;
+                Some(Arc::new(Mutex::new(unsafe { File::from_raw_fd(fd) })))
+            }
+            None => None,
+        };
+
+        let handle = tokio::spawn({
+            let progress = Arc::clone(&progress);
+            let status = status.clone();
+            async move {
+                let mut last_log = Instant::now();
+                loop {
+                    tokio::time::sleep(STATUS_INTERVAL).await;
+                    if let Some(ref status) = status {
+                        write_record(status, &progress.record(false)).await;
+                    }
+                    if last_log.elapsed() >= LOG_INTERVAL {
+                        progress.log();
+                        last_log = Instant::now();
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            progress,
+            status,
+            handle,
+        })
+    }
+
+    /// Stop reporting and write the final record.
+    pub async fn finish(self) {
+        self.handle.abort();
+        if let Some(ref status) = self.status {
+            write_record(status, &self.progress.record(true)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_remaining() {
+        assert_eq!(estimate_remaining(1000, 0, 10.0), None);
+        assert_eq!(estimate_remaining(1000, 250, 10.0), Some(30));
+        assert_eq!(estimate_remaining(1000, 1000, 10.0), Some(0));
+        // more processed than expected, e.g. a growing source
+        assert_eq!(estimate_remaining(1000, 2000, 10.0), Some(0));
+    }
+
+    #[test]
+    fn test_record() {
+        let progress = Progress::restore();
+        progress.start_archive("disk.img.fidx", Some(4096));
+        progress.add_read(1024);
+
+        let record = progress.record(false);
+        assert_eq!(record.archive.as_deref(), Some("disk.img.fidx"));
+        assert_eq!(record.read, 1024);
+        assert_eq!(record.total, Some(4096));
+        assert!(record.eta.is_some());
+        // restores do not upload anything
+        assert_eq!(record.uploaded, None);
+
+        // only the bytes of the current archive count for its ETA
+        progress.start_archive("root.pxar.didx", Some(4096));
+        assert_eq!(progress.record(false).eta, None);
+        assert_eq!(progress.record(false).read, 1024);
+
+        let record = progress.record(true);
+        assert!(record.done);
+        assert_eq!(record.eta, None);
+    }
+
+    #[test]
+    fn test_backup_record() {
+        let progress = Progress::backup();
+        progress.start_archive("root.pxar.didx", None);
+        progress.add_read(4096);
+        progress.add_uploaded(1024);
+
+        let record = progress.record(false);
+        assert_eq!(record.read, 4096);
+        assert_eq!(record.uploaded, Some(1024));
+        // the size of a file archive is not known in advance
+        assert_eq!(record.eta, None);
+    }
+
+    #[test]
+    fn test_format_seconds() {
+        assert_eq!(format_seconds(0), "0:00:00");
+        assert_eq!(format_seconds(3725), "1:02:05");
+        assert_eq!(format_seconds(90000), "25:00:00");
+    }
+}
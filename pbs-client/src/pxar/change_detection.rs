@@ -0,0 +1,381 @@
+//! Metadata based change detection for incremental file backups
+//!
+//! Similar to the quick check of rsync, a regular file whose size, modification time, change time
+//! and inode number did not change since the previous backup is assumed to be unchanged. Instead
+//! of reading and chunking its data again, the chunks of the previous snapshot which lie
+//! completely within the file's payload are referenced directly.
+//!
+//! For this, the client keeps a cache file per archive, listing the metadata of every regular
+//! file together with the offset of its payload in the archive stream. A cache is only used if
+//! it was written for the archive of the previous snapshot, which is checked with the index
+//! checksum recorded in the manifest.
+//!
+//! The archive itself is encoded as usual, but the part of an unchanged file covered by reused
+//! chunks is written as zeroes. The archiver queues a [ReusedRange] right before writing them,
+//! and the [ChunkStream](crate::ChunkStream) cuts a chunk at the start of the range and emits
+//! the previous chunks instead of the zeroes. Data at the start and end of the file, which shares
+//! a chunk with other entries of the archive, is still read from the file.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use nix::sys::stat::FileStat;
+use serde::{Deserialize, Serialize};
+
+use proxmox_human_byte::HumanByte;
+use proxmox_schema::api;
+use proxmox_sys::fs::file_get_optional_contents;
+
+use pbs_api_types::{BackupGroup, BackupNamespace};
+use pbs_datastore::dynamic_index::DynamicIndexReader;
+use pbs_datastore::index::IndexFile;
+
+use crate::BackupRepository;
+
+const CACHE_MAGIC: [u8; 8] = *b"PBSMDC01";
+const CACHE_TRAILER_SIZE: usize = 32 + CACHE_MAGIC.len();
+
+#[api]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// How to detect changed files when creating an archive.
+pub enum ChangeDetectionMode {
+    /// Read and chunk all files.
+    #[default]
+    Data,
+    /// Reuse chunks of files whose metadata did not change since the previous backup.
+    Metadata,
+}
+
+/// Range of the archive stream replaced by chunks of the previous snapshot
+pub struct ReusedRange {
+    /// Stream offset of the first replaced byte
+    pub start: u64,
+    /// Digests and sizes of the chunks replacing the range
+    pub chunks: Vec<([u8; 32], u64)>,
+}
+
+impl ReusedRange {
+    /// Number of replaced bytes
+    pub fn len(&self) -> u64 {
+        self.chunks.iter().map(|(_, size)| size).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+/// Reused ranges queued by the archiver for the chunker, in stream order
+pub type ReusedRangeQueue = Arc<Mutex<VecDeque<ReusedRange>>>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    ctime: i64,
+    ctime_nsec: i64,
+    ino: u64,
+}
+
+impl FileStamp {
+    fn new(stat: &FileStat) -> Self {
+        Self {
+            size: stat.st_size as u64,
+            mtime: stat.st_mtime,
+            mtime_nsec: stat.st_mtime_nsec,
+            ctime: stat.st_ctime,
+            ctime_nsec: stat.st_ctime_nsec,
+            ino: stat.st_ino,
+        }
+    }
+}
+
+struct CacheEntry {
+    stamp: FileStamp,
+    offset: u64,
+}
+
+fn encode_entry(path: &[u8], stamp: &FileStamp, offset: u64) -> Vec<u8> {
+    let mut data = Vec::with_capacity(4 + path.len() + 7 * 8);
+    data.extend_from_slice(&(path.len() as u32).to_le_bytes());
+    data.extend_from_slice(path);
+    data.extend_from_slice(&stamp.size.to_le_bytes());
+    data.extend_from_slice(&stamp.mtime.to_le_bytes());
+    data.extend_from_slice(&stamp.mtime_nsec.to_le_bytes());
+    data.extend_from_slice(&stamp.ctime.to_le_bytes());
+    data.extend_from_slice(&stamp.ctime_nsec.to_le_bytes());
+    data.extend_from_slice(&stamp.ino.to_le_bytes());
+    data.extend_from_slice(&offset.to_le_bytes());
+    data
+}
+
+fn parse_cache(mut data: &[u8]) -> Result<HashMap<Vec<u8>, CacheEntry>, Error> {
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+        if data.len() < len {
+            bail!("unexpected end of metadata cache");
+        }
+        let (head, tail) = data.split_at(len);
+        *data = tail;
+        Ok(head)
+    }
+    fn take_u64(data: &mut &[u8]) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(take(data, 8)?.try_into().unwrap()))
+    }
+
+    let mut entries = HashMap::new();
+    while !data.is_empty() {
+        let path_len = u32::from_le_bytes(take(&mut data, 4)?.try_into().unwrap()) as usize;
+        let path = take(&mut data, path_len)?.to_vec();
+        let stamp = FileStamp {
+            size: take_u64(&mut data)?,
+            mtime: take_u64(&mut data)? as i64,
+            mtime_nsec: take_u64(&mut data)? as i64,
+            ctime: take_u64(&mut data)? as i64,
+            ctime_nsec: take_u64(&mut data)? as i64,
+            ino: take_u64(&mut data)?,
+        };
+        let offset = take_u64(&mut data)?;
+        entries.insert(path, CacheEntry { stamp, offset });
+    }
+    Ok(entries)
+}
+
+/// Location of the metadata cache for an archive of a backup group (usually
+/// `$HOME/.cache/proxmox-backup/metadata-cache/<id>`).
+pub fn metadata_cache_path(
+    repo: &BackupRepository,
+    ns: &BackupNamespace,
+    group: &BackupGroup,
+    archive_name: &str,
+) -> Result<PathBuf, Error> {
+    let id = openssl::sha::sha256(format!("{repo}\n{ns}\n{group}\n{archive_name}").as_bytes());
+    let base = crate::tools::base_directories()?;
+    let dir = base.create_cache_directory("metadata-cache")?;
+    Ok(dir.join(hex::encode(id)))
+}
+
+/// Chunks to reuse for an unchanged file
+pub(crate) struct ReusePlan {
+    /// Number of bytes to read from the file before the reused chunks
+    pub head: u64,
+    /// The reused chunks
+    pub chunks: Vec<([u8; 32], u64)>,
+}
+
+struct PreviousArchive {
+    index: DynamicIndexReader,
+    entries: HashMap<Vec<u8>, CacheEntry>,
+}
+
+/// Metadata cache and reuse state of a single archive
+pub struct ChangeDetection {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    previous: Option<PreviousArchive>,
+    writer: BufWriter<File>,
+    position: Arc<AtomicU64>,
+    reused_ranges: ReusedRangeQueue,
+    reused_files: u64,
+    reused_bytes: u64,
+}
+
+impl ChangeDetection {
+    /// Open the metadata cache at `path`.
+    ///
+    /// `previous` is the index of the archive in the previous snapshot, together with its
+    /// checksum from the manifest. Without it, or if the cache belongs to another snapshot, all
+    /// files are read.
+    pub fn new(
+        path: PathBuf,
+        previous: Option<(DynamicIndexReader, [u8; 32])>,
+    ) -> Result<Self, Error> {
+        let previous = match previous {
+            Some((index, csum)) => match Self::load_cache(&path, &csum)? {
+                Some(entries) => Some(PreviousArchive { index, entries }),
+                None => {
+                    log::info!("metadata cache does not match the previous snapshot");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let tmp_path = path.with_extension("tmp");
+        let file = File::create(&tmp_path)
+            .map_err(|err| format_err!("unable to create metadata cache {tmp_path:?} - {err}"))?;
+
+        Ok(Self {
+            path,
+            tmp_path,
+            previous,
+            writer: BufWriter::new(file),
+            position: Arc::new(AtomicU64::new(0)),
+            reused_ranges: Arc::new(Mutex::new(VecDeque::new())),
+            reused_files: 0,
+            reused_bytes: 0,
+        })
+    }
+
+    fn load_cache(
+        path: &Path,
+        csum: &[u8; 32],
+    ) -> Result<Option<HashMap<Vec<u8>, CacheEntry>>, Error> {
+        let data = match file_get_optional_contents(path)? {
+            Some(data) if data.len() >= CACHE_TRAILER_SIZE => data,
+            _ => return Ok(None),
+        };
+        let (entries, trailer) = data.split_at(data.len() - CACHE_TRAILER_SIZE);
+        if trailer[32..] != CACHE_MAGIC || trailer[..32] != csum[..] {
+            return Ok(None);
+        }
+        parse_cache(entries).map(Some)
+    }
+
+    /// Counter of the bytes written to the archive stream so far.
+    pub fn position_handle(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.position)
+    }
+
+    pub(crate) fn position(&self) -> u64 {
+        self.position.load(Ordering::SeqCst)
+    }
+
+    /// Queue of reused ranges, which needs to be passed to the chunker.
+    pub fn reused_ranges(&self) -> ReusedRangeQueue {
+        Arc::clone(&self.reused_ranges)
+    }
+
+    /// Returns the chunks to reuse for the file at `path`, if it did not change.
+    pub(crate) fn plan_reuse(&self, path: &[u8], stat: &FileStat) -> Option<ReusePlan> {
+        let previous = self.previous.as_ref()?;
+        let entry = previous.entries.get(path)?;
+        if entry.stamp != FileStamp::new(stat) {
+            return None;
+        }
+
+        let payload_start = entry.offset;
+        let payload_end = entry.offset + entry.stamp.size;
+
+        // the first reused chunk must start after the payload start, the leading data written
+        // from the file tells us the payload offset in the new archive
+        let (mut pos, _) = previous.index.chunk_from_offset(payload_start)?;
+        pos += 1;
+
+        let mut head = None;
+        let mut chunks = Vec::new();
+        while let Some(info) = previous.index.chunk_info(pos) {
+            if info.range.end > payload_end {
+                break;
+            }
+            head.get_or_insert(info.range.start - payload_start);
+            chunks.push((info.digest, info.range.end - info.range.start));
+            pos += 1;
+        }
+
+        Some(ReusePlan {
+            head: head?,
+            chunks,
+        })
+    }
+
+    /// Queue a reused range starting at the current stream position.
+    pub(crate) fn queue_reused_range(&mut self, chunks: Vec<([u8; 32], u64)>) -> u64 {
+        let range = ReusedRange {
+            start: self.position(),
+            chunks,
+        };
+        let len = range.len();
+        self.reused_files += 1;
+        self.reused_bytes += len;
+        self.reused_ranges.lock().unwrap().push_back(range);
+        len
+    }
+
+    /// Record the metadata and payload offset of a regular file for the next backup.
+    pub(crate) fn record(
+        &mut self,
+        path: &[u8],
+        stat: &FileStat,
+        payload_offset: u64,
+    ) -> Result<(), Error> {
+        let entry = encode_entry(path, &FileStamp::new(stat), payload_offset);
+        self.writer
+            .write_all(&entry)
+            .map_err(|err| format_err!("unable to write metadata cache - {err}"))
+    }
+
+    /// Make the cache available for the next backup, after the archive with checksum `csum` was
+    /// uploaded.
+    pub fn commit(&mut self, csum: &[u8; 32]) -> Result<(), Error> {
+        self.writer.write_all(csum)?;
+        self.writer.write_all(&CACHE_MAGIC)?;
+        self.writer.flush()?;
+        std::fs::rename(&self.tmp_path, &self.path).map_err(|err| {
+            format_err!("unable to commit metadata cache {:?} - {err}", self.path)
+        })?;
+
+        if self.reused_files > 0 {
+            log::info!(
+                "reused {} of data from {} unchanged files",
+                HumanByte::from(self.reused_bytes),
+                self.reused_files,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts the bytes written to the archive stream
+pub(crate) struct StreamPositionWriter<W> {
+    inner: W,
+    position: Arc<AtomicU64>,
+}
+
+impl<W> StreamPositionWriter<W> {
+    pub fn new(inner: W, position: Arc<AtomicU64>) -> Self {
+        Self { inner, position }
+    }
+}
+
+impl<W: Write> Write for StreamPositionWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.position.fetch_add(written as u64, Ordering::SeqCst);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_metadata_cache_encoding() {
+    let stamp = FileStamp {
+        size: 4096,
+        mtime: 1_700_000_000,
+        mtime_nsec: 123,
+        ctime: 1_700_000_001,
+        ctime_nsec: 456,
+        ino: 42,
+    };
+
+    let mut data = encode_entry(b"etc/hostname", &stamp, 1234);
+    data.extend(encode_entry(b"var/lib/data.bin", &stamp, 5678));
+
+    let entries = parse_cache(&data).unwrap();
+    assert_eq!(entries.len(), 2);
+    let entry = &entries[&b"var/lib/data.bin"[..]];
+    assert!(entry.stamp == stamp);
+    assert_eq!(entry.offset, 5678);
+
+    assert!(parse_cache(&data[..data.len() - 1]).is_err());
+}
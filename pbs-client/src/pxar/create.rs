@@ -1,7 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use std::ffi::{CStr, CString, OsStr};
 use std::fmt;
-use std::io::{self, Read};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::path::{Path, PathBuf};
@@ -12,11 +12,14 @@ use futures::future::BoxFuture;
 use futures::FutureExt;
 use nix::dir::Dir;
 use nix::errno::Errno;
-use nix::fcntl::OFlag;
+use nix::fcntl::{AtFlags, OFlag};
 use nix::sys::stat::{FileStat, Mode};
+use serde::{Deserialize, Serialize};
 
 use pathpatterns::{MatchEntry, MatchFlag, MatchList, MatchType, PatternFlag};
+use proxmox_schema::api;
 use proxmox_sys::error::SysError;
+use proxmox_sys::linux::procfs::{mountinfo::Device, MountInfo};
 use pxar::encoder::{LinkOffset, SeqWrite};
 use pxar::Metadata;
 
@@ -26,10 +29,78 @@ use proxmox_sys::fs::{self, acl, xattr};
 
 use pbs_datastore::catalog::BackupCatalogWriter;
 
+use crate::pxar::change_detection::ChangeDetection;
 use crate::pxar::metadata::errno_is_unsupported;
+use crate::pxar::nfs4_acl::{self, Nfs4AclMode};
 use crate::pxar::tools::assert_single_path_component;
 use crate::pxar::Flags;
 
+#[api]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Whether the contents of symlinked directories or mount points are archived.
+pub enum FollowPolicy {
+    /// Never follow. Symlinks are archived as such, mount points are only descended into if
+    /// their device is included.
+    #[default]
+    Never,
+    /// Follow symlinks to directories on the same file system, and mount points of file systems
+    /// stored on the same device as the archive root (for example btrfs subvolumes or ZFS
+    /// datasets of the same pool).
+    SameDevice,
+    /// Follow the listed paths only.
+    Listed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Why an entry was not archived, or only partially.
+pub enum SkipReason {
+    /// The entry could not be opened.
+    AccessDenied,
+    /// The entry vanished while creating the archive.
+    Vanished,
+    /// The entry is a mount point whose contents are not included.
+    MountPoint,
+    /// The entry is a directory tagged with a `CACHEDIR.TAG`.
+    CacheDirectory,
+    /// The entry has the `nodump` file attribute.
+    Nodump,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// An entry which was not archived, or only partially.
+pub struct SkippedEntry {
+    /// Path relative to the archive root
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+/// Maximum number of skipped entries recorded per archive, further ones are only counted
+const MAX_SKIPPED_ENTRIES: usize = 10_000;
+
+#[derive(Clone, Debug, Default)]
+/// The entries which were not archived, up to a maximum number.
+pub struct SkippedEntries {
+    pub entries: Vec<SkippedEntry>,
+    /// Number of skipped entries beyond the maximum, which were not recorded
+    pub dropped: u64,
+}
+
+impl SkippedEntries {
+    pub fn push(&mut self, entry: SkippedEntry) {
+        if self.entries.len() < MAX_SKIPPED_ENTRIES {
+            self.entries.push(entry);
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.dropped == 0
+    }
+}
+
 /// Pxar options for creating a pxar archive/stream
 #[derive(Default, Clone)]
 pub struct PxarCreateOptions {
@@ -43,6 +114,41 @@ pub struct PxarCreateOptions {
     pub skip_lost_and_found: bool,
     /// Skip xattrs of files that return E2BIG error
     pub skip_e2big_xattr: bool,
+    /// Skip the contents of directories tagged with a valid `CACHEDIR.TAG`, except the tag itself
+    pub exclude_caches: bool,
+    /// Skip entries with the `nodump` file attribute (see chattr(1))
+    pub exclude_nodump: bool,
+    /// How to handle NFSv4 ACLs
+    pub nfs4_acl: Nfs4AclMode,
+    /// Metadata cache for reusing chunks of unchanged files
+    pub change_detection: Option<Arc<Mutex<ChangeDetection>>>,
+    /// Whether to archive the contents of symlinked directories instead of the symlinks
+    pub follow_symlinks: FollowPolicy,
+    /// Whether to archive the contents of mount points whose device is not in `device_set`
+    pub follow_mounts: FollowPolicy,
+    /// Paths followed with the [FollowPolicy::Listed] policies, relative to the archive root
+    pub follow_paths: Vec<PathBuf>,
+    /// Collects the entries which were skipped
+    pub skipped: Option<Arc<Mutex<SkippedEntries>>>,
+    /// Only archive the listed paths, relative to the archive root, and their parent
+    /// directories. None for no limitation.
+    pub file_list: Option<Vec<PathBuf>>,
+}
+
+/// The device a file system is stored on, the pool for ZFS datasets.
+fn mount_device(mount_info: &MountInfo, st_dev: u64) -> Option<String> {
+    let device = Device::from_dev_t(st_dev);
+    for (_id, entry) in mount_info {
+        if entry.device != device {
+            continue;
+        }
+        let source = entry.mount_source.as_ref()?.to_string_lossy();
+        if entry.fs_type == "zfs" {
+            return source.split('/').next().map(str::to_string);
+        }
+        return Some(source.into_owned());
+    }
+    None
 }
 
 fn detect_fs_type(fd: RawFd) -> Result<i64, Error> {
@@ -54,6 +160,36 @@ fn detect_fs_type(fd: RawFd) -> Result<i64, Error> {
     Ok(fs_stat.f_type)
 }
 
+/// File name and signature of cache directory tags, see <https://bford.info/cachedir/>
+const CACHEDIR_TAG_NAME: &[u8] = b"CACHEDIR.TAG";
+const CACHEDIR_TAG_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// Check if a directory contains a valid `CACHEDIR.TAG`.
+fn is_cache_directory(dir_fd: RawFd) -> bool {
+    // do not block on opening a FIFO or device named like the tag
+    let fd = match proxmox_sys::fd::openat(
+        &dir_fd,
+        CACHEDIR_TAG_NAME,
+        OFlag::O_RDONLY
+            | OFlag::O_NOFOLLOW
+            | OFlag::O_CLOEXEC
+            | OFlag::O_NOCTTY
+            | OFlag::O_NONBLOCK,
+        Mode::empty(),
+    ) {
+        Ok(fd) => fd,
+        Err(_) => return false,
+    };
+
+    let mut file = std::fs::File::from(fd);
+    match file.metadata() {
+        Ok(metadata) if metadata.is_file() => (),
+        _ => return false,
+    }
+    let mut signature = [0u8; CACHEDIR_TAG_SIGNATURE.len()];
+    file.read_exact(&mut signature).is_ok() && signature == CACHEDIR_TAG_SIGNATURE
+}
+
 fn strip_ascii_whitespace(line: &[u8]) -> &[u8] {
     let line = match line.iter().position(|&b| !b.is_ascii_whitespace()) {
         Some(n) => &line[n..],
@@ -131,6 +267,20 @@ struct Archiver {
     hardlinks: HashMap<HardLinkInfo, (PathBuf, LinkOffset)>,
     file_copy_buffer: Vec<u8>,
     skip_e2big_xattr: bool,
+    exclude_caches: bool,
+    exclude_nodump: bool,
+    nfs4_acl: Nfs4AclMode,
+    change_detection: Option<Arc<Mutex<ChangeDetection>>>,
+    follow_symlinks: FollowPolicy,
+    follow_mounts: FollowPolicy,
+    follow_paths: HashSet<PathBuf>,
+    /// Mount info and device of the archive root, for [FollowPolicy::SameDevice] mount points
+    mount_info: Option<(MountInfo, Option<String>)>,
+    /// `(st_dev, st_ino)` of the directories being archived, to detect symlink loops
+    dir_stack: Vec<(u64, u64)>,
+    skipped: Option<Arc<Mutex<SkippedEntries>>>,
+    /// The listed paths along with all their parent directories
+    file_list: Option<HashSet<PathBuf>>,
 }
 
 type Encoder<'a, T> = pxar::encoder::aio::Encoder<'a, T>;
@@ -162,6 +312,7 @@ where
         fs_magic,
         &mut fs_feature_flags,
         options.skip_e2big_xattr,
+        options.nfs4_acl,
     )
     .context("failed to get metadata for source directory")?;
 
@@ -182,6 +333,34 @@ where
         )?);
     }
 
+    let mount_info = if options.follow_mounts == FollowPolicy::SameDevice {
+        let mount_info = MountInfo::read()?;
+        let root_device = mount_device(&mount_info, stat.st_dev);
+        Some((mount_info, root_device))
+    } else {
+        None
+    };
+
+    // the listed paths are relative to the archive root
+    let follow_paths = options
+        .follow_paths
+        .iter()
+        .map(|path| path.strip_prefix("/").unwrap_or(path).to_path_buf())
+        .collect();
+
+    let file_list = options.file_list.map(|list| {
+        let mut set = HashSet::new();
+        for path in list {
+            let path = path.strip_prefix("/").unwrap_or(&path);
+            for ancestor in path.ancestors() {
+                if ancestor.as_os_str().is_empty() || !set.insert(ancestor.to_path_buf()) {
+                    break;
+                }
+            }
+        }
+        set
+    });
+
     let mut archiver = Archiver {
         feature_flags,
         fs_feature_flags,
@@ -197,6 +376,17 @@ where
         hardlinks: HashMap::new(),
         file_copy_buffer: vec::undefined(4 * 1024 * 1024),
         skip_e2big_xattr: options.skip_e2big_xattr,
+        exclude_caches: options.exclude_caches,
+        exclude_nodump: options.exclude_nodump,
+        nfs4_acl: options.nfs4_acl,
+        change_detection: options.change_detection,
+        follow_symlinks: options.follow_symlinks,
+        follow_mounts: options.follow_mounts,
+        follow_paths,
+        mount_info,
+        dir_stack: vec![(stat.st_dev, stat.st_ino)],
+        skipped: options.skipped,
+        file_list,
     };
 
     archiver
@@ -308,6 +498,9 @@ impl Archiver {
                 }
                 Err(Errno::EACCES) => {
                     log::warn!("failed to open file: {:?}: access denied", file_name);
+                    if existed {
+                        self.record_skipped(SkipReason::AccessDenied);
+                    }
                     Ok(None)
                 }
                 Err(Errno::EPERM) if !noatime.is_empty() => {
@@ -420,6 +613,12 @@ impl Archiver {
     ) -> Result<Vec<FileListEntry>, Error> {
         let dir_fd = dir.as_raw_fd();
 
+        let cache_directory = self.exclude_caches && is_cache_directory(dir_fd);
+        if cache_directory {
+            log::info!("skipping contents of cache directory: {:?}", self.path);
+            self.record_skipped(SkipReason::CacheDirectory);
+        }
+
         let mut file_list = Vec::new();
 
         for file in dir.iter() {
@@ -431,6 +630,11 @@ impl Archiver {
                 continue;
             }
 
+            // keep the tag, so that the directory is still recognized as cache after a restore
+            if cache_directory && file_name_bytes != CACHEDIR_TAG_NAME {
+                continue;
+            }
+
             if is_root && file_name_bytes == b".pxarexclude-cli" {
                 continue;
             }
@@ -439,6 +643,12 @@ impl Archiver {
             assert_single_path_component(os_file_name)?;
             let full_path = self.path.join(os_file_name);
 
+            if let Some(ref file_list) = self.file_list {
+                if !file_list.contains(&full_path) {
+                    continue;
+                }
+            }
+
             let match_path = PathBuf::from("/").join(full_path.clone());
 
             let mut stat_results: Option<FileStat> = None;
@@ -490,8 +700,18 @@ impl Archiver {
         Ok(file_list)
     }
 
+    fn record_skipped(&self, reason: SkipReason) {
+        if let Some(ref skipped) = self.skipped {
+            skipped.lock().unwrap().push(SkippedEntry {
+                path: self.path.clone(),
+                reason,
+            });
+        }
+    }
+
     fn report_vanished_file(&mut self) -> Result<(), Error> {
         log::warn!("warning: file vanished while reading: {:?}", self.path);
+        self.record_skipped(SkipReason::Vanished);
         Ok(())
     }
 
@@ -527,12 +747,25 @@ impl Archiver {
             OFlag::O_PATH
         };
 
-        let fd = self.open_file(
-            parent,
-            c_file_name,
-            open_mode | OFlag::O_RDONLY | OFlag::O_NOFOLLOW,
-            true,
-        )?;
+        let followed = if file_mode == libc::S_IFLNK {
+            self.open_followed_symlink(parent, c_file_name)?
+        } else {
+            None
+        };
+
+        let (fd, stat) = match followed {
+            Some((fd, stat)) => (Some(fd), stat),
+            None => {
+                let fd = self.open_file(
+                    parent,
+                    c_file_name,
+                    open_mode | OFlag::O_RDONLY | OFlag::O_NOFOLLOW,
+                    true,
+                )?;
+                (fd, *stat)
+            }
+        };
+        let stat = &stat;
 
         let fd = match fd {
             Some(fd) => fd,
@@ -555,8 +788,17 @@ impl Archiver {
             self.fs_magic,
             &mut self.fs_feature_flags,
             self.skip_e2big_xattr,
+            self.nfs4_acl,
         )?;
 
+        if self.exclude_nodump
+            && Flags::from_bits_truncate(metadata.stat.flags).contains(Flags::WITH_FLAG_NODUMP)
+        {
+            log::info!("skipping entry with nodump attribute: {:?}", self.path);
+            self.record_skipped(SkipReason::Nodump);
+            return Ok(());
+        }
+
         let file_name: &Path = OsStr::from_bytes(c_file_name.to_bytes()).as_ref();
         match metadata.file_type() {
             mode::IFREG => {
@@ -586,7 +828,7 @@ impl Archiver {
                 }
 
                 let offset: LinkOffset = self
-                    .add_regular_file(encoder, fd, file_name, &metadata, file_size)
+                    .add_regular_file(encoder, fd, file_name, &metadata, stat)
                     .await?;
 
                 if stat.st_nlink > 1 {
@@ -678,15 +920,19 @@ impl Archiver {
             if is_virtual_file_system(self.fs_magic) {
                 skip_contents = true;
             } else if let Some(set) = &self.device_set {
-                skip_contents = !set.contains(&stat.st_dev);
+                skip_contents = !set.contains(&stat.st_dev) && !self.follow_mount(stat.st_dev);
             }
         }
 
         let result = if skip_contents {
             log::info!("skipping mount point: {:?}", self.path);
+            self.record_skipped(SkipReason::MountPoint);
             Ok(())
         } else {
-            self.archive_dir_contents(&mut encoder, dir, false).await
+            self.dir_stack.push((stat.st_dev, stat.st_ino));
+            let result = self.archive_dir_contents(&mut encoder, dir, false).await;
+            self.dir_stack.pop();
+            result
         };
 
         self.fs_magic = old_fs_magic;
@@ -703,11 +949,60 @@ impl Archiver {
         fd: OwnedFd,
         file_name: &Path,
         metadata: &Metadata,
-        file_size: u64,
+        stat: &FileStat,
     ) -> Result<LinkOffset, Error> {
         let mut file = unsafe { std::fs::File::from_raw_fd(fd.into_raw_fd()) };
+        let file_size = stat.st_size as u64;
         let mut remaining = file_size;
+
+        let reuse = self.change_detection.as_ref().and_then(|cd| {
+            cd.lock()
+                .unwrap()
+                .plan_reuse(self.path.as_os_str().as_bytes(), stat)
+        });
+
         let mut out = encoder.create_file(metadata, file_name, file_size).await?;
+
+        if let Some(plan) = reuse {
+            // the data before the first reused chunk shares a chunk with the previous entry
+            let mut head = plan.head;
+            while head != 0 {
+                let want = head.min(self.file_copy_buffer.len() as u64) as usize;
+                let got = match file.read(&mut self.file_copy_buffer[..want]) {
+                    Ok(0) => break,
+                    Ok(got) => got,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(err) => bail!(err),
+                };
+                out.write_all(&self.file_copy_buffer[..got]).await?;
+                head -= got as u64;
+                remaining -= got as u64;
+            }
+
+            // only reuse the chunks if the file did not shrink in the meantime
+            if head == 0 {
+                let len = self
+                    .change_detection
+                    .as_ref()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .queue_reused_range(plan.chunks);
+
+                // the placeholder gets replaced by the reused chunks in the chunk stream
+                let mut placeholder = len;
+                let to_zero = placeholder.min(self.file_copy_buffer.len() as u64) as usize;
+                vec::clear(&mut self.file_copy_buffer[..to_zero]);
+                while placeholder != 0 {
+                    let fill = placeholder.min(self.file_copy_buffer.len() as u64) as usize;
+                    out.write_all(&self.file_copy_buffer[..fill]).await?;
+                    placeholder -= fill as u64;
+                }
+                remaining -= len;
+                file.seek(SeekFrom::Start(file_size - remaining))?;
+            }
+        }
+
         while remaining != 0 {
             let mut got = match file.read(&mut self.file_copy_buffer[..]) {
                 Ok(0) => break,
@@ -733,9 +1028,86 @@ impl Archiver {
             }
         }
 
+        if let Some(ref cd) = self.change_detection {
+            if file_size > 0 {
+                let mut cd = cd.lock().unwrap();
+                // the payload was written directly to the stream, so it ends at the current position
+                let payload_offset = cd.position() - file_size;
+                cd.record(self.path.as_os_str().as_bytes(), stat, payload_offset)?;
+            }
+        }
+
         Ok(out.file_offset())
     }
 
+    /// Open the target of the symlink `file_name` if it is a directory which is archived in
+    /// place of the symlink according to the symlink policy.
+    fn open_followed_symlink(
+        &mut self,
+        parent: RawFd,
+        file_name: &CStr,
+    ) -> Result<Option<(OwnedFd, FileStat)>, Error> {
+        match self.follow_symlinks {
+            FollowPolicy::Never => return Ok(None),
+            FollowPolicy::Listed if !self.follow_paths.contains(&self.path) => return Ok(None),
+            _ => (),
+        }
+
+        // dangling symlinks are archived as such
+        let stat = match nix::sys::stat::fstatat(parent, file_name, AtFlags::empty()) {
+            Ok(stat) => stat,
+            Err(_) => return Ok(None),
+        };
+        if stat.st_mode & libc::S_IFMT != libc::S_IFDIR {
+            return Ok(None);
+        }
+        if self.follow_symlinks == FollowPolicy::SameDevice && stat.st_dev != self.current_st_dev {
+            return Ok(None);
+        }
+        if self.dir_stack.contains(&(stat.st_dev, stat.st_ino)) {
+            log::warn!(
+                "not following symlink {:?}, it points to a parent directory",
+                self.path
+            );
+            return Ok(None);
+        }
+
+        let fd = match self.open_file(
+            parent,
+            file_name,
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+            true,
+        )? {
+            Some(fd) => fd,
+            None => return Ok(None),
+        };
+        // the symlink may have been changed in the meantime
+        let stat = nix::sys::stat::fstat(fd.as_raw_fd())?;
+        if stat.st_mode & libc::S_IFMT != libc::S_IFDIR
+            || self.dir_stack.contains(&(stat.st_dev, stat.st_ino))
+        {
+            return Ok(None);
+        }
+
+        log::info!("following symlink: {:?}", self.path);
+        Ok(Some((fd, stat)))
+    }
+
+    /// Check if the contents of a mount point with an excluded device are archived according
+    /// to the mount point policy.
+    fn follow_mount(&self, st_dev: u64) -> bool {
+        match self.follow_mounts {
+            FollowPolicy::Never => false,
+            FollowPolicy::Listed => self.follow_paths.contains(&self.path),
+            FollowPolicy::SameDevice => match &self.mount_info {
+                Some((mount_info, Some(root_device))) => {
+                    mount_device(mount_info, st_dev).as_ref() == Some(root_device)
+                }
+                _ => false,
+            },
+        }
+    }
+
     async fn add_symlink<T: SeqWrite + Send>(
         &mut self,
         encoder: &mut Encoder<'_, T>,
@@ -772,6 +1144,7 @@ fn get_metadata(
     fs_magic: i64,
     fs_feature_flags: &mut Flags,
     skip_e2big_xattr: bool,
+    nfs4_acl: Nfs4AclMode,
 ) -> Result<Metadata, Error> {
     // required for some of these
     let proc_path = Path::new("/proc/self/fd/").join(fd.to_string());
@@ -794,6 +1167,7 @@ fn get_metadata(
         flags,
         fs_feature_flags,
         skip_e2big_xattr,
+        nfs4_acl,
     )?;
     get_chattr(&mut meta, fd)?;
     get_fat_attr(&mut meta, fd, fs_magic)?;
@@ -833,6 +1207,7 @@ fn get_xattr_fcaps_acl(
     flags: Flags,
     fs_feature_flags: &mut Flags,
     skip_e2big_xattr: bool,
+    nfs4_acl: Nfs4AclMode,
 ) -> Result<(), Error> {
     if !flags.contains(Flags::WITH_XATTRS) {
         return Ok(());
@@ -867,6 +1242,11 @@ fn get_xattr_fcaps_acl(
             continue;
         }
 
+        if attr == nfs4_acl::NFS4_ACL_XATTR {
+            get_nfs4_acl(meta, fd, proc_path, flags, nfs4_acl)?;
+            continue;
+        }
+
         if !xattr::is_valid_xattr_name(attr) {
             continue;
         }
@@ -975,6 +1355,32 @@ fn get_quota_project_id(
     Ok(())
 }
 
+fn get_nfs4_acl(
+    metadata: &mut Metadata,
+    fd: RawFd,
+    proc_path: &Path,
+    flags: Flags,
+    mode: Nfs4AclMode,
+) -> Result<(), Error> {
+    if mode == Nfs4AclMode::Ignore || !flags.contains(Flags::WITH_ACL) {
+        return Ok(());
+    }
+
+    let data = match xattr::fgetxattr(fd, nfs4_acl::NFS4_ACL_XATTR) {
+        Ok(data) => data,
+        Err(Errno::ENODATA) | Err(Errno::EOPNOTSUPP) | Err(Errno::EBADF) => return Ok(()),
+        Err(err) => return Err(err).context("error while reading NFSv4 ACL"),
+    };
+
+    let lossy = nfs4_acl::translate_to_posix(metadata, &data)?;
+    if !lossy.is_empty() {
+        // resolve the real path for the warning, the error case gets it added by the caller
+        let path = std::fs::read_link(proc_path).unwrap_or_else(|_| proc_path.to_owned());
+        nfs4_acl::check_lossy(mode, &path, &lossy)?;
+    }
+    Ok(())
+}
+
 fn get_acl(
     metadata: &mut Metadata,
     proc_path: &Path,
@@ -1136,3 +1542,155 @@ fn generate_pxar_excludes_cli(patterns: &[MatchEntry]) -> Vec<u8> {
 
     content
 }
+
+#[cfg(test)]
+mod tests {
+    use pxar::EntryKind;
+
+    use super::*;
+    use crate::pxar::ENCODER_MAX_ENTRIES;
+
+    // test directory below ./target/testout, removed again when dropped, also if the test fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let mut path: PathBuf = String::from("./target/testout").into();
+            path.push(std::module_path!());
+            path.push(name);
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_is_cache_directory() -> Result<(), Error> {
+        let test_dir = TestDir::new("cachedir");
+        let dir = &test_dir.0;
+        let tag = dir.join(OsStr::from_bytes(CACHEDIR_TAG_NAME));
+        let check = || -> Result<bool, Error> {
+            let dir = std::fs::File::open(dir)?;
+            Ok(is_cache_directory(dir.as_raw_fd()))
+        };
+
+        assert!(!check()?);
+
+        std::fs::write(
+            &tag,
+            b"Signature: 8a477f597d28d172789f06886806bc55\n# a comment\n",
+        )?;
+        assert!(check()?);
+
+        std::fs::write(&tag, b"Signature: invalid")?;
+        assert!(!check()?);
+
+        // neither blocks on, nor reads from a FIFO
+        std::fs::remove_file(&tag)?;
+        nix::unistd::mkfifo(&tag, Mode::from_bits_truncate(0o600))?;
+        assert!(!check()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skipped_entries_limit() {
+        let mut skipped = SkippedEntries::default();
+        assert!(skipped.is_empty());
+
+        for n in 0..MAX_SKIPPED_ENTRIES + 5 {
+            skipped.push(SkippedEntry {
+                path: PathBuf::from(format!("dir/file{n}")),
+                reason: SkipReason::Vanished,
+            });
+        }
+        assert_eq!(skipped.entries.len(), MAX_SKIPPED_ENTRIES);
+        assert_eq!(skipped.dropped, 5);
+        assert!(!skipped.is_empty());
+        // the first entries are kept
+        assert_eq!(skipped.entries[0].path, PathBuf::from("dir/file0"));
+    }
+
+    // archives `source` with the symlink policy, returns the archived paths and whether they
+    // are symlinks
+    fn archive_with_symlink_policy(
+        source: &Path,
+        archive: &Path,
+        policy: FollowPolicy,
+        follow_paths: &[&str],
+    ) -> Result<Vec<(PathBuf, bool)>, Error> {
+        let dir = Dir::open(source, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())?;
+        let writer = pxar::encoder::sync::StandardWriter::new(std::fs::File::create(archive)?);
+        let options = PxarCreateOptions {
+            entries_max: ENCODER_MAX_ENTRIES,
+            follow_symlinks: policy,
+            follow_paths: follow_paths.iter().map(PathBuf::from).collect(),
+            ..PxarCreateOptions::default()
+        };
+        proxmox_async::runtime::main(create_archive(
+            dir,
+            writer,
+            Flags::DEFAULT,
+            |_| Ok(()),
+            None,
+            options,
+        ))?;
+
+        let mut entries = Vec::new();
+        for entry in pxar::decoder::Decoder::open(archive)? {
+            let entry = entry?;
+            let symlink = matches!(entry.kind(), EntryKind::Symlink(_));
+            entries.push((entry.path().to_path_buf(), symlink));
+        }
+        Ok(entries)
+    }
+
+    #[test]
+    fn test_follow_symlink_policies() -> Result<(), Error> {
+        let test_dir = TestDir::new("follow_symlinks");
+        let dir = &test_dir.0;
+
+        let source = dir.join("source");
+        std::fs::create_dir_all(source.join("sub"))?;
+        std::fs::write(source.join("sub/file"), b"data")?;
+        std::os::unix::fs::symlink("sub", source.join("link"))?;
+        std::os::unix::fs::symlink(".", source.join("loop"))?;
+        std::os::unix::fs::symlink("missing", source.join("dangling"))?;
+        let archive = dir.join("test.pxar");
+
+        let link = (PathBuf::from("/link"), true);
+        let followed = [
+            (PathBuf::from("/link"), false),
+            (PathBuf::from("/link/file"), false),
+        ];
+
+        let entries = archive_with_symlink_policy(&source, &archive, FollowPolicy::Never, &[])?;
+        assert!(entries.contains(&link));
+        assert!(!entries.contains(&followed[1]));
+
+        let entries =
+            archive_with_symlink_policy(&source, &archive, FollowPolicy::SameDevice, &[])?;
+        assert!(followed.iter().all(|entry| entries.contains(entry)));
+        // symlinks to a parent directory and dangling ones are kept as such
+        assert!(entries.contains(&(PathBuf::from("/loop"), true)));
+        assert!(entries.contains(&(PathBuf::from("/dangling"), true)));
+
+        // listed paths are relative to the archive root, with or without leading slash
+        for listed in ["link", "/link"] {
+            let entries =
+                archive_with_symlink_policy(&source, &archive, FollowPolicy::Listed, &[listed])?;
+            assert!(followed.iter().all(|entry| entries.contains(entry)));
+        }
+        let entries =
+            archive_with_symlink_policy(&source, &archive, FollowPolicy::Listed, &["sub"])?;
+        assert!(entries.contains(&link));
+
+        Ok(())
+    }
+}
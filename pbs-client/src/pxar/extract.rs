@@ -14,7 +14,7 @@ use nix::dir::Dir;
 use nix::fcntl::OFlag;
 use nix::sys::stat::Mode;
 
-use pathpatterns::{MatchEntry, MatchList, MatchType};
+use pathpatterns::{MatchEntry, MatchFlag, MatchList, MatchPattern, MatchType};
 use pxar::accessor::aio::{Accessor, FileContents, FileEntry};
 use pxar::decoder::{aio::Decoder, Contents};
 use pxar::format::Device;
@@ -22,7 +22,7 @@ use pxar::{Entry, EntryKind, Metadata};
 
 use proxmox_io::{sparse_copy, sparse_copy_async};
 use proxmox_sys::c_result;
-use proxmox_sys::fs::{create_path, CreateOptions};
+use proxmox_sys::fs::{acl, create_path, CreateOptions};
 
 use proxmox_compression::zip::{ZipEncoder, ZipEntry};
 
@@ -69,11 +69,261 @@ where
         .context("encountered unexpected error during extraction")
 }
 
+/// Extract a seekable archive, like [extract_archive].
+///
+/// Directories which cannot contain anything matching the include patterns are skipped via the
+/// goodbye tables, so their contents are not read at all.
+pub fn extract_archive_seekable<T, F>(
+    accessor: pxar::accessor::sync::Accessor<T>,
+    destination: &Path,
+    feature_flags: Flags,
+    callback: F,
+    options: PxarExtractOptions,
+) -> Result<(), Error>
+where
+    T: Clone + pxar::accessor::sync::ReadAt,
+    F: FnMut(&Path),
+{
+    let root = accessor.open_root().context("error reading pxar archive")?;
+    let root_entry = root.lookup_self().context("error reading pxar archive")?;
+    if !root_entry.is_dir() {
+        bail!("pxar archive does not start with a directory entry!");
+    }
+
+    create_path(
+        destination,
+        None,
+        Some(CreateOptions::new().perm(Mode::from_bits_truncate(0o700))),
+    )
+    .with_context(|| format!("error creating directory {destination:?}"))?;
+
+    let dir = Dir::open(
+        destination,
+        OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .with_context(|| format!("unable to open target directory {destination:?}"))?;
+
+    let mut extractor = Extractor::new(
+        dir,
+        root_entry.entry().metadata().clone(),
+        options.allow_existing_dirs,
+        options.overwrite_flags,
+        feature_flags,
+    );
+    if let Some(on_error) = options.on_error {
+        extractor.on_error(on_error);
+    }
+
+    let mut walker = SeekableExtractor {
+        extractor,
+        callback,
+        match_list: options.match_list,
+        include_prefixes: include_prefixes(options.match_list),
+    };
+    walker
+        .extract_directory(&root, options.extract_match_default)
+        .context("encountered unexpected error during extraction")?;
+
+    walker.extractor.set_path(OsString::from("/"));
+    walker
+        .extractor
+        .leave_directory()
+        .context(PxarExtractContext::LeaveDirectory)
+        .or_else(&mut *walker.extractor.on_error)
+}
+
+/// State of [extract_archive_seekable].
+struct SeekableExtractor<'a, F> {
+    extractor: Extractor,
+    callback: F,
+    match_list: &'a [MatchEntry],
+    /// See [include_prefixes], `None` if directories cannot be skipped.
+    include_prefixes: Option<Vec<IncludePrefix>>,
+}
+
+impl<'a, F: FnMut(&Path)> SeekableExtractor<'a, F> {
+    /// Extract the contents of `dir`, `current_match` is the match state inherited from it.
+    ///
+    /// Returns an error only if the extraction has to be aborted, other errors are passed to the
+    /// error handler of the extractor.
+    fn extract_directory<T>(
+        &mut self,
+        dir: &pxar::accessor::sync::Directory<T>,
+        current_match: bool,
+    ) -> Result<(), Error>
+    where
+        T: Clone + pxar::accessor::sync::ReadAt,
+    {
+        let mut files = Vec::new();
+        for entry in dir.read_dir() {
+            files.push(
+                entry
+                    .and_then(|entry| entry.decode_entry())
+                    .context("error reading pxar archive")?,
+            );
+        }
+        // the goodbye table is ordered by hash, restore the archive order so that hard link
+        // targets are extracted before the links
+        files.sort_by_key(|file| file.entry_range_info().entry_range.start);
+
+        for file in files {
+            let entry = file.entry();
+            let (file_name_os, file_name) = get_filename(entry)?;
+            let metadata = entry.metadata();
+            let path = entry.path();
+
+            self.extractor.set_path(path.as_os_str().to_owned());
+
+            // We can `unwrap()` safely here because we get a `Result<_, std::convert::Infallible>`
+            let match_result = self
+                .match_list
+                .matches(path.as_os_str().as_bytes(), metadata.file_type() as u32)
+                .unwrap();
+            let did_match = match match_result {
+                Some(MatchType::Include) => true,
+                Some(MatchType::Exclude) => false,
+                None => current_match,
+            };
+
+            let result = match (did_match, entry.kind()) {
+                (false, EntryKind::Directory)
+                    if can_skip_directory(
+                        self.include_prefixes.as_deref(),
+                        path.as_os_str().as_bytes(),
+                    ) =>
+                {
+                    // nothing in here can be extracted, skip the whole subtree
+                    continue;
+                }
+                (_, EntryKind::Directory) => {
+                    (self.callback)(path);
+
+                    let create = current_match && match_result != Some(MatchType::Exclude);
+                    let entered = self
+                        .extractor
+                        .enter_directory(file_name_os.clone(), metadata.clone(), create)
+                        .context(PxarExtractContext::EnterDirectory);
+                    if let Err(err) = entered {
+                        // the directory is on the stack even if it could not be created
+                        let _ = self.extractor.dir_stack.pop();
+                        (self.extractor.on_error)(
+                            err.context(format!("error at entry {file_name_os:?}")),
+                        )?;
+                        continue;
+                    }
+
+                    let subdir = file
+                        .enter_directory()
+                        .context("error reading pxar archive")?;
+                    self.extract_directory(&subdir, did_match)?;
+
+                    self.extractor.set_path(path.as_os_str().to_owned());
+                    self.extractor
+                        .leave_directory()
+                        .context(PxarExtractContext::LeaveDirectory)
+                }
+                (true, EntryKind::File { size, .. }) => {
+                    (self.callback)(path);
+                    let overwrite = self
+                        .extractor
+                        .overwrite_flags
+                        .contains(OverwriteFlags::FILE);
+                    file.contents()
+                        .map_err(Error::from)
+                        .and_then(|mut contents| {
+                            self.extractor.extract_file(
+                                &file_name,
+                                metadata,
+                                *size,
+                                &mut contents,
+                                overwrite,
+                            )
+                        })
+                        .context(PxarExtractContext::ExtractFile)
+                }
+                (true, EntryKind::GoodbyeTable) => Ok(()),
+                (true, _) => {
+                    (self.callback)(path);
+                    extract_special(&mut self.extractor, entry, &file_name)
+                }
+                (false, _) => Ok(()), // skip this
+            };
+
+            result
+                .with_context(|| format!("error at entry {file_name_os:?}"))
+                .or_else(&mut *self.extractor.on_error)?;
+        }
+
+        Ok(())
+    }
+}
+
 struct ExtractorIterState {
     match_stack: Vec<bool>,
     err_path_stack: Vec<OsString>,
     current_match: bool,
     end_reached: bool,
+    /// Nesting depth inside of a directory which is skipped as a whole.
+    skip_depth: usize,
+}
+
+/// The literal part of an anchored include pattern, up to its first wildcard.
+pub(crate) struct IncludePrefix {
+    prefix: Vec<u8>,
+    /// The pattern contains no wildcards, so it only matches the prefix itself.
+    complete: bool,
+}
+
+impl IncludePrefix {
+    /// Check whether the pattern can match anything below the directory `dir`.
+    fn may_match_below(&self, dir: &[u8]) -> bool {
+        let mut dir = dir.to_vec();
+        if !dir.ends_with(b"/") {
+            dir.push(b'/');
+        }
+        self.prefix.starts_with(&dir) || (!self.complete && dir.starts_with(&self.prefix))
+    }
+}
+
+/// Returns the prefixes of all include patterns.
+///
+/// Paths not matching one of these can never be included, which allows skipping whole
+/// directories. Returns `None` if an include pattern is not anchored, since then it can match in
+/// any directory.
+pub(crate) fn include_prefixes(match_list: &[MatchEntry]) -> Option<Vec<IncludePrefix>> {
+    let mut prefixes = Vec::new();
+    for entry in match_list {
+        if entry.match_type() != MatchType::Include {
+            continue;
+        }
+        let (mut prefix, complete) = match entry.pattern() {
+            MatchPattern::Literal(literal) => (literal.clone(), true),
+            MatchPattern::Pattern(pattern) => {
+                let pattern = pattern.pattern().to_bytes();
+                match pattern.iter().position(|b| b"*?[\\".contains(b)) {
+                    Some(end) => (pattern[..end].to_vec(), false),
+                    None => (pattern.to_vec(), true),
+                }
+            }
+        };
+        if !prefix.starts_with(b"/") {
+            if !entry.match_flags().contains(MatchFlag::ANCHORED) {
+                return None;
+            }
+            prefix.insert(0, b'/');
+        }
+        prefixes.push(IncludePrefix { prefix, complete });
+    }
+    Some(prefixes)
+}
+
+/// Check whether a directory which does not match itself can be skipped as a whole, because no
+/// include pattern can match anything below it.
+pub(crate) fn can_skip_directory(include_prefixes: Option<&[IncludePrefix]>, dir: &[u8]) -> bool {
+    include_prefixes.map_or(false, |prefixes| {
+        !prefixes.iter().any(|prefix| prefix.may_match_below(dir))
+    })
 }
 
 /// An [`Iterator`] that encapsulates the process of extraction in [extract_archive].
@@ -88,6 +338,8 @@ where
     callback: F,
     extractor: Extractor,
     match_list: &'a [MatchEntry],
+    /// See [include_prefixes], `None` if directories cannot be skipped.
+    include_prefixes: Option<Vec<IncludePrefix>>,
     state: ExtractorIterState,
 }
 
@@ -98,6 +350,7 @@ impl ExtractorIterState {
             err_path_stack: Vec::new(),
             current_match: options.extract_match_default,
             end_reached: false,
+            skip_depth: 0,
         }
     }
 }
@@ -167,6 +420,7 @@ where
             callback,
             extractor,
             match_list: options.match_list,
+            include_prefixes: include_prefixes(options.match_list),
             state,
         })
     }
@@ -227,6 +481,16 @@ where
             Some(Ok(entry)) => entry,
         };
 
+        if self.state.skip_depth > 0 {
+            // inside of a skipped directory, only keep track of where it ends
+            match entry.kind() {
+                EntryKind::Directory => self.state.skip_depth += 1,
+                EntryKind::GoodbyeTable => self.state.skip_depth -= 1,
+                _ => (),
+            }
+            return Some(Ok(()));
+        }
+
         let file_name_os = entry.file_name();
         let file_name_bytes = file_name_os.as_bytes();
 
@@ -267,6 +531,16 @@ where
         };
 
         let extract_res = match (did_match, entry.kind()) {
+            (false, EntryKind::Directory)
+                if can_skip_directory(
+                    self.include_prefixes.as_deref(),
+                    entry.path().as_os_str().as_bytes(),
+                ) =>
+            {
+                // nothing in here can be extracted, skip the whole subtree
+                self.state.skip_depth = 1;
+                Ok(())
+            }
             (_, EntryKind::Directory) => {
                 self.callback(entry.path());
 
@@ -548,6 +822,11 @@ impl Extractor {
         Ok(())
     }
 
+    /// Which kinds of existing entries get overwritten.
+    pub fn overwrite_flags(&self) -> OverwriteFlags {
+        self.overwrite_flags
+    }
+
     fn contains_flags(&self, flag: Flags) -> bool {
         self.feature_flags.contains(flag)
     }
@@ -721,6 +1000,20 @@ impl Extractor {
         contents: &mut T,
         overwrite: bool,
     ) -> Result<(), Error> {
+        let writer = self.create_file(file_name, metadata, size, overwrite)?;
+        writer.write(contents, &mut self.on_error).await
+    }
+
+    /// Create a regular file in the current directory. Its contents are written with the
+    /// returned [`FileWriter`], which does not borrow the extractor and can be moved to another
+    /// task, so that multiple files can be written in parallel.
+    pub fn create_file(
+        &mut self,
+        file_name: &CStr,
+        metadata: &Metadata,
+        size: u64,
+        overwrite: bool,
+    ) -> Result<FileWriter, Error> {
         let parent = self.parent_fd()?;
         let mut oflags = OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_CLOEXEC;
         if overwrite {
@@ -728,7 +1021,7 @@ impl Extractor {
         } else {
             oflags |= OFlag::O_EXCL;
         }
-        let mut file = tokio::fs::File::from_std(unsafe {
+        let file = tokio::fs::File::from_std(unsafe {
             std::fs::File::from_raw_fd(
                 nix::fcntl::openat(parent, file_name, oflags, Mode::from_bits(0o600).unwrap())
                     .with_context(|| format!("failed to create file {file_name:?}"))?,
@@ -743,20 +1036,46 @@ impl Extractor {
         )
         .context("failed to apply initial flags")?;
 
-        let result = sparse_copy_async(&mut *contents, &mut file)
+        Ok(FileWriter {
+            file,
+            metadata: metadata.clone(),
+            size,
+            feature_flags: self.feature_flags,
+            path_info: self.dir_stack.path().to_owned(),
+        })
+    }
+}
+
+/// A file created by [`Extractor::create_file`], waiting for its contents.
+pub struct FileWriter {
+    file: tokio::fs::File,
+    metadata: Metadata,
+    size: u64,
+    feature_flags: Flags,
+    path_info: PathBuf,
+}
+
+impl FileWriter {
+    /// Write the file contents and apply the file's metadata afterwards.
+    pub async fn write<T: tokio::io::AsyncRead + Unpin>(
+        mut self,
+        contents: &mut T,
+        on_error: &mut (dyn FnMut(Error) -> Result<(), Error> + Send),
+    ) -> Result<(), Error> {
+        let result = sparse_copy_async(&mut *contents, &mut self.file)
             .await
             .context("failed to copy file contents")?;
 
-        if size != result.written {
+        if self.size != result.written {
             bail!(
                 "extracted {} bytes of a file of {} bytes",
                 result.written,
-                size
+                self.size
             );
         }
 
         if result.seeked_last {
-            while match nix::unistd::ftruncate(file.as_raw_fd(), size as i64) {
+            while match nix::unistd::ftruncate(self.file.as_raw_fd(), self.size as i64) {
                 Ok(_) => false,
                 Err(nix::errno::Errno::EINTR) => true,
                 Err(err) => return Err(err).context("error setting file size"),
@@ -765,10 +1084,10 @@ impl Extractor {
 
         metadata::apply(
             self.feature_flags,
-            metadata,
-            file.as_raw_fd(),
-            self.dir_stack.path(),
-            &mut self.on_error,
+            &self.metadata,
+            self.file.as_raw_fd(),
+            &self.path_info,
+            on_error,
         )
     }
 }
@@ -968,6 +1287,281 @@ where
     Ok(())
 }
 
+/// Appends a pax extended header record (`"<length> <key>=<value>\n"`) to `records`.
+fn add_pax_record(records: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    // the length includes its own digits
+    let base = key.len() + value.len() + 3;
+    let mut len = base + base.to_string().len();
+    if len.to_string().len() > base.to_string().len() {
+        len += 1;
+    }
+    records.extend(len.to_string().as_bytes());
+    records.push(b' ');
+    records.extend(key);
+    records.push(b'=');
+    records.extend(value);
+    records.push(b'\n');
+}
+
+fn acl_perms_to_text(perms: u64) -> String {
+    let mut text = String::with_capacity(3);
+    text.push(if perms & acl::ACL_READ != 0 { 'r' } else { '-' });
+    text.push(if perms & acl::ACL_WRITE != 0 {
+        'w'
+    } else {
+        '-'
+    });
+    text.push(if perms & acl::ACL_EXECUTE != 0 {
+        'x'
+    } else {
+        '-'
+    });
+    text
+}
+
+/// Formats the access and default ACL in the short text form used by `SCHILY.acl.*` records.
+fn acls_to_text(metadata: &Metadata) -> (Option<String>, Option<String>) {
+    let mode = metadata.stat.mode;
+
+    let access = if metadata.acl.users.is_empty()
+        && metadata.acl.groups.is_empty()
+        && metadata.acl.group_obj.is_none()
+    {
+        None
+    } else {
+        let mut entries = vec![format!(
+            "user::{}",
+            acl_perms_to_text(acl::mode_user_to_acl_permissions(mode))
+        )];
+        for user in &metadata.acl.users {
+            entries.push(format!(
+                "user:{}:{}",
+                user.uid,
+                acl_perms_to_text(user.permissions.0)
+            ));
+        }
+        let group_obj = match metadata.acl.group_obj.as_ref() {
+            Some(group_obj) => group_obj.permissions.0,
+            None => acl::mode_group_to_acl_permissions(mode),
+        };
+        entries.push(format!("group::{}", acl_perms_to_text(group_obj)));
+        for group in &metadata.acl.groups {
+            entries.push(format!(
+                "group:{}:{}",
+                group.gid,
+                acl_perms_to_text(group.permissions.0)
+            ));
+        }
+        // with an ACL, the group bits of the mode are the mask
+        entries.push(format!(
+            "mask::{}",
+            acl_perms_to_text(acl::mode_group_to_acl_permissions(mode))
+        ));
+        entries.push(format!(
+            "other::{}",
+            acl_perms_to_text(acl::mode_other_to_acl_permissions(mode))
+        ));
+        Some(entries.join(","))
+    };
+
+    let default = metadata.acl.default.as_ref().map(|default| {
+        let mut entries = vec![format!(
+            "user::{}",
+            acl_perms_to_text(default.user_obj_permissions.0)
+        )];
+        for user in &metadata.acl.default_users {
+            entries.push(format!(
+                "user:{}:{}",
+                user.uid,
+                acl_perms_to_text(user.permissions.0)
+            ));
+        }
+        entries.push(format!(
+            "group::{}",
+            acl_perms_to_text(default.group_obj_permissions.0)
+        ));
+        for group in &metadata.acl.default_groups {
+            entries.push(format!(
+                "group:{}:{}",
+                group.gid,
+                acl_perms_to_text(group.permissions.0)
+            ));
+        }
+        if default.mask_permissions != pxar::format::acl::Permissions::NO_MASK {
+            entries.push(format!(
+                "mask::{}",
+                acl_perms_to_text(default.mask_permissions.0)
+            ));
+        }
+        entries.push(format!(
+            "other::{}",
+            acl_perms_to_text(default.other_permissions.0)
+        ));
+        entries.join(",")
+    });
+
+    (access, default)
+}
+
+/// Collects the pax records for all metadata of an entry which a ustar header cannot hold.
+fn pax_records(metadata: &Metadata, size: u64) -> Vec<u8> {
+    // largest values fitting into the octal fields of a ustar header
+    const MAX_OCTAL_ID: u64 = 0o7777777;
+    const MAX_OCTAL_SIZE: u64 = 0o77777777777;
+
+    let mut records = Vec::new();
+    let stat = &metadata.stat;
+
+    if stat.mtime.nanos != 0 {
+        let mtime = format!("{}.{:09}", stat.mtime.secs, stat.mtime.nanos);
+        add_pax_record(&mut records, b"mtime", mtime.as_bytes());
+    }
+    if stat.uid as u64 > MAX_OCTAL_ID {
+        add_pax_record(&mut records, b"uid", stat.uid.to_string().as_bytes());
+    }
+    if stat.gid as u64 > MAX_OCTAL_ID {
+        add_pax_record(&mut records, b"gid", stat.gid.to_string().as_bytes());
+    }
+    if size > MAX_OCTAL_SIZE {
+        add_pax_record(&mut records, b"size", size.to_string().as_bytes());
+    }
+
+    for xattr in &metadata.xattrs {
+        let mut key = b"SCHILY.xattr.".to_vec();
+        key.extend(xattr.name().to_bytes());
+        add_pax_record(&mut records, &key, xattr.value());
+    }
+    if let Some(fcaps) = metadata.fcaps.as_ref() {
+        add_pax_record(
+            &mut records,
+            b"SCHILY.xattr.security.capability",
+            &fcaps.data,
+        );
+    }
+
+    let (access, default) = acls_to_text(metadata);
+    if let Some(access) = access {
+        add_pax_record(&mut records, b"SCHILY.acl.access", access.as_bytes());
+    }
+    if let Some(default) = default {
+        add_pax_record(&mut records, b"SCHILY.acl.default", default.as_bytes());
+    }
+
+    records
+}
+
+/// Length of the name and link name fields of a ustar header.
+const TAR_NAME_FIELD_LEN: usize = 100;
+
+/// Stores `value` in a ustar name field, truncated if necessary.
+///
+/// Longer values must be stored in a `path` or `linkpath` pax record as well, which takes
+/// precedence over the header field.
+fn set_tar_name_field(field: &mut [u8; TAR_NAME_FIELD_LEN], value: &[u8]) {
+    let len = value.len().min(TAR_NAME_FIELD_LEN);
+    field[..len].copy_from_slice(&value[..len]);
+    field[len..].fill(0);
+}
+
+/// Converts a pxar archive into a POSIX (pax) tar archive written to `output`.
+///
+/// Paths and link targets which do not fit into a ustar header are stored as `path` and `linkpath`
+/// pax records. Extended attributes, file capabilities and POSIX ACLs are stored as
+/// `SCHILY.xattr.*` and `SCHILY.acl.*` pax records, as understood by GNU tar and bsdtar. Sockets
+/// cannot be represented in tar archives and are skipped.
+pub fn convert_to_tar<T, W>(mut decoder: pxar::decoder::Decoder<T>, output: W) -> Result<(), Error>
+where
+    T: pxar::decoder::SeqRead,
+    W: io::Write,
+{
+    let mut tar = tar::Builder::new(output);
+
+    while let Some(entry) = decoder.next() {
+        let entry = entry.context("cannot decode entry")?;
+        let metadata = entry.metadata();
+
+        // tar archives contain relative paths, and the root directory is not stored at all
+        let path = entry.path().strip_prefix("/").unwrap_or(entry.path());
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let (entry_type, size) = match entry.kind() {
+            EntryKind::File { size, .. } => (tar::EntryType::Regular, *size),
+            EntryKind::Directory => (tar::EntryType::Directory, 0),
+            EntryKind::Symlink(_) => (tar::EntryType::Symlink, 0),
+            EntryKind::Hardlink(_) => (tar::EntryType::Link, 0),
+            EntryKind::Device(_) if metadata.stat.is_chardev() => (tar::EntryType::Char, 0),
+            EntryKind::Device(_) => (tar::EntryType::Block, 0),
+            EntryKind::Fifo => (tar::EntryType::Fifo, 0),
+            EntryKind::Socket => {
+                log::info!("skipping socket {path:?}, not representable in tar archives");
+                continue;
+            }
+            _ => continue,
+        };
+
+        let link_target = match entry.kind() {
+            EntryKind::Symlink(link) => Some(Path::new(link)),
+            EntryKind::Hardlink(link) => {
+                let target = Path::new(link);
+                Some(target.strip_prefix("/").unwrap_or(target))
+            }
+            _ => None,
+        };
+        let path_bytes = path.as_os_str().as_bytes();
+        let link_bytes = link_target.map(|target| target.as_os_str().as_bytes());
+
+        let mut records = pax_records(metadata, size);
+        if path_bytes.len() > TAR_NAME_FIELD_LEN {
+            add_pax_record(&mut records, b"path", path_bytes);
+        }
+        if let Some(link) = link_bytes.filter(|link| link.len() > TAR_NAME_FIELD_LEN) {
+            add_pax_record(&mut records, b"linkpath", link);
+        }
+        if !records.is_empty() {
+            let mut header = tar::Header::new_ustar();
+            header.set_entry_type(tar::EntryType::XHeader);
+            header.set_mode(0o644);
+            header.set_size(records.len() as u64);
+            tar.append_data(&mut header, "././@PaxHeader", &records[..])
+                .context("could not write pax header")?;
+        }
+
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(entry_type);
+        add_metadata_to_header(&mut header, metadata);
+        header.set_size(size);
+        // set the name fields directly, so that the tar crate does not fall back to GNU
+        // longname entries, the full paths are in the pax records
+        set_tar_name_field(&mut header.as_old_mut().name, path_bytes);
+        if let Some(link) = link_bytes {
+            set_tar_name_field(&mut header.as_old_mut().linkname, link);
+        }
+        if let EntryKind::Device(device) = entry.kind() {
+            header.set_device_major(device.major as u32)?;
+            header.set_device_minor(device.minor as u32)?;
+        }
+        header.set_cksum();
+
+        match entry.kind() {
+            EntryKind::File { .. } => {
+                let contents = decoder
+                    .contents()
+                    .context("found regular file entry without contents in archive")?;
+                tar.append(&header, contents)
+            }
+            _ => tar.append(&header, io::empty()),
+        }
+        .with_context(|| format!("could not write entry {path:?}"))?;
+    }
+
+    let mut output = tar.into_inner()?;
+    output.flush()?;
+
+    Ok(())
+}
+
 pub async fn create_zip<T, W, P>(output: W, accessor: Accessor<T>, path: P) -> Result<(), Error>
 where
     T: Clone + pxar::accessor::ReadAt + Unpin + Send + Sync + 'static,
@@ -1300,3 +1894,284 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pathpatterns::PatternFlag;
+
+    use super::*;
+
+    // test directory below ./target/testout, removed again when dropped, also if the test fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let mut path: PathBuf = String::from("./target/testout").into();
+            path.push(std::module_path!());
+            path.push(name);
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn test_extractor(dir: &Path) -> Extractor {
+        std::fs::create_dir_all(dir).unwrap();
+        let root = Dir::open(
+            dir,
+            OFlag::O_DIRECTORY | OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .unwrap();
+        // no feature flags, so that no ownership is applied when running as normal user
+        Extractor::new(
+            root,
+            Metadata::dir_builder(0o755).build(),
+            true,
+            OverwriteFlags::empty(),
+            Flags::empty(),
+        )
+    }
+
+    #[test]
+    fn test_parallel_file_writers() -> Result<(), Error> {
+        let test_dir = TestDir::new("parallel_file_writers");
+        let dir = &test_dir.0;
+        let mut extractor = test_extractor(dir);
+        let metadata = Metadata::file_builder(0o640).build();
+
+        let first = extractor.create_file(&CString::new("first")?, &metadata, 5, false)?;
+        let second = extractor.create_file(&CString::new("second")?, &metadata, 6, false)?;
+        let short = extractor.create_file(&CString::new("short")?, &metadata, 10, false)?;
+        // files are created right away, before their contents are written
+        assert!(dir.join("first").exists());
+        assert!(extractor
+            .create_file(&CString::new("first")?, &metadata, 5, false)
+            .is_err());
+
+        let result = proxmox_async::runtime::main(async move {
+            let mut on_error = |err: Error| -> Result<(), Error> { Err(err) };
+            // written in reverse order of creation, they do not depend on each other
+            second.write(&mut &b"second"[..], &mut on_error).await?;
+            first.write(&mut &b"first"[..], &mut on_error).await?;
+            Ok::<_, Error>(short.write(&mut &b"short"[..], &mut on_error).await)
+        })?;
+
+        assert_eq!(std::fs::read(dir.join("first"))?, b"first");
+        assert_eq!(std::fs::read(dir.join("second"))?, b"second");
+        let err = result.unwrap_err().to_string();
+        assert_eq!(err, "extracted 5 bytes of a file of 10 bytes");
+
+        Ok(())
+    }
+
+    fn prefixes(patterns: &[&str]) -> Option<Vec<IncludePrefix>> {
+        let match_list: Vec<MatchEntry> = patterns
+            .iter()
+            .map(|pattern| {
+                let (pattern, ty) = match pattern.strip_prefix('!') {
+                    Some(pattern) => (pattern, MatchType::Exclude),
+                    None => (*pattern, MatchType::Include),
+                };
+                MatchEntry::parse_pattern(pattern, PatternFlag::PATH_NAME, ty).unwrap()
+            })
+            .collect();
+        include_prefixes(&match_list)
+    }
+
+    fn may_match_below(prefixes: &[IncludePrefix], dir: &str) -> bool {
+        prefixes
+            .iter()
+            .any(|prefix| prefix.may_match_below(dir.as_bytes()))
+    }
+
+    #[test]
+    fn test_include_prefixes() {
+        let list = prefixes(&["/etc/ssh/*", "/home/user/.bashrc", "!/var"]).unwrap();
+        // exclude patterns are not relevant
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].prefix, b"/etc/ssh/");
+        assert!(!list[0].complete);
+        assert_eq!(list[1].prefix, b"/home/user/.bashrc");
+        assert!(list[1].complete);
+
+        // patterns which can match in any directory
+        assert!(prefixes(&["/etc/ssh/*", "*.conf"]).is_none());
+
+        // nothing to include
+        assert!(prefixes(&["!/var"]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_may_match_below() {
+        let list = prefixes(&["/etc/ssh/*", "/home/user/.bashrc", "/srv/data?"]).unwrap();
+
+        // parents of the patterns
+        assert!(may_match_below(&list, "/etc"));
+        assert!(may_match_below(&list, "/etc/"));
+        assert!(may_match_below(&list, "/home"));
+        assert!(may_match_below(&list, "/home/user"));
+        assert!(may_match_below(&list, "/srv"));
+
+        // below a pattern with wildcards
+        assert!(may_match_below(&list, "/etc/ssh"));
+        assert!(may_match_below(&list, "/etc/ssh/keys"));
+        assert!(may_match_below(&list, "/srv/data1"));
+        assert!(may_match_below(&list, "/srv/data1/sub"));
+
+        // unrelated, or only sharing a name prefix
+        assert!(!may_match_below(&list, "/usr"));
+        assert!(!may_match_below(&list, "/etcetera"));
+        assert!(!may_match_below(&list, "/home/user2"));
+        assert!(!may_match_below(&list, "/home/other"));
+        // a literal path does not match anything below itself
+        assert!(!may_match_below(&list, "/home/user/.bashrc"));
+    }
+
+    #[test]
+    fn test_can_skip_directory() {
+        // as used for `restore --files-from`, a list of literal paths
+        let list = prefixes(&["/etc/hostname", "/srv/data/*.db", "!/srv/data/old.db"]).unwrap();
+        let list = Some(list.as_slice());
+
+        assert!(!can_skip_directory(list, b"/etc"));
+        assert!(!can_skip_directory(list, b"/srv"));
+        assert!(!can_skip_directory(list, b"/srv/data"));
+        assert!(!can_skip_directory(list, b"/srv/data/sub"));
+
+        assert!(can_skip_directory(list, b"/usr"));
+        assert!(can_skip_directory(list, b"/usr/share/doc"));
+        assert!(can_skip_directory(list, b"/etc/ssh"));
+        assert!(can_skip_directory(list, b"/srv/www"));
+
+        // nothing can be included at all
+        assert!(can_skip_directory(Some(&[]), b"/etc"));
+
+        // an unanchored include pattern may match anywhere
+        assert!(!can_skip_directory(None, b"/usr"));
+        let list = prefixes(&["/etc/hostname", "*.db"]);
+        assert!(!can_skip_directory(list.as_deref(), b"/usr"));
+    }
+
+    #[test]
+    fn test_add_pax_record() {
+        let mut records = Vec::new();
+        add_pax_record(&mut records, b"path", b"a/b");
+        assert_eq!(records, b"12 path=a/b\n");
+
+        // the length prefix must include its own digits, also when that adds a digit
+        for value_len in 0..1100 {
+            let value = vec![b'x'; value_len];
+            let mut records = Vec::new();
+            add_pax_record(&mut records, b"path", &value);
+
+            let space = records.iter().position(|b| *b == b' ').unwrap();
+            let len: usize = std::str::from_utf8(&records[..space])
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(len, records.len(), "value length {value_len}");
+            assert!(records.ends_with(b"\n"));
+        }
+    }
+
+    #[test]
+    fn test_acls_to_text() {
+        use pxar::format::acl::{self as pxar_acl, Group, GroupObject, Permissions, User};
+
+        let mut metadata = Metadata::file_builder(0o750).build();
+        assert_eq!(acls_to_text(&metadata), (None, None));
+
+        metadata.acl.users.push(User {
+            uid: 1000,
+            permissions: Permissions(acl::ACL_READ | acl::ACL_WRITE),
+        });
+        metadata.acl.groups.push(Group {
+            gid: 100,
+            permissions: Permissions(acl::ACL_READ),
+        });
+        metadata.acl.group_obj = Some(GroupObject {
+            permissions: Permissions(acl::ACL_EXECUTE),
+        });
+        metadata.acl.default = Some(pxar_acl::Default {
+            user_obj_permissions: Permissions(acl::ACL_READ | acl::ACL_WRITE | acl::ACL_EXECUTE),
+            group_obj_permissions: Permissions(acl::ACL_READ),
+            other_permissions: Permissions(0),
+            mask_permissions: Permissions::NO_MASK,
+        });
+        metadata.acl.default_users.push(User {
+            uid: 0,
+            permissions: Permissions(acl::ACL_READ),
+        });
+
+        let (access, default) = acls_to_text(&metadata);
+        assert_eq!(
+            access.as_deref(),
+            Some("user::rwx,user:1000:rw-,group::--x,group:100:r--,mask::r-x,other::---"),
+        );
+        assert_eq!(
+            default.as_deref(),
+            Some("user::rwx,user:0:r--,group::r--,other::---"),
+        );
+    }
+
+    #[test]
+    fn test_convert_to_tar_long_paths() -> Result<(), Error> {
+        let test_dir = TestDir::new("convert_to_tar_long_paths");
+        let path = test_dir.0.join("test.pxar");
+
+        let long_dir = "d".repeat(120);
+        let long_target = format!("../{}", "t".repeat(150));
+
+        let file = std::fs::File::create(&path)?;
+        let mut encoder = pxar::encoder::sync::Encoder::new(
+            pxar::encoder::sync::StandardWriter::new(file),
+            &Metadata::dir_builder(0o755).build(),
+        )?;
+        let mut dir = encoder.create_directory(&long_dir, &Metadata::dir_builder(0o700).build())?;
+        let mut contents = dir.create_file(&Metadata::file_builder(0o640).build(), "data", 5)?;
+        io::Write::write_all(&mut contents, b"hello")?;
+        drop(contents);
+        dir.add_symlink(
+            &Metadata::builder(pxar::mode::IFLNK | 0o777).build(),
+            "link",
+            &long_target,
+        )?;
+        dir.finish()?;
+        encoder.finish()?;
+
+        let mut output = Vec::new();
+        convert_to_tar(pxar::decoder::Decoder::open(&path)?, &mut output)?;
+
+        // GNU longname and longlink entries must not be used
+        for header in output.chunks(512) {
+            assert!(!matches!(header[156], b'L' | b'K'));
+        }
+
+        let mut archive = tar::Archive::new(&output[..]);
+        let mut entries = Vec::new();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let link = entry.link_name()?.map(|link| link.into_owned());
+            entries.push((entry.path()?.into_owned(), link));
+        }
+
+        let long_dir = PathBuf::from(long_dir);
+        assert_eq!(
+            entries,
+            [
+                (long_dir.clone(), None),
+                (long_dir.join("data"), None),
+                (long_dir.join("link"), Some(PathBuf::from(long_target))),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -13,6 +13,7 @@ use proxmox_sys::c_result;
 use proxmox_sys::error::SysError;
 use proxmox_sys::fs::{self, acl, xattr};
 
+use crate::pxar::nfs4_acl;
 use crate::pxar::tools::perms_from_metadata;
 use crate::pxar::Flags;
 
@@ -297,7 +298,12 @@ fn apply_acls(
         bail!("Error while restoring ACL - ACL invalid");
     }
 
-    acl.set_file(c_proc_path, acl::ACL_TYPE_ACCESS)?;
+    match acl.set_file(c_proc_path, acl::ACL_TYPE_ACCESS) {
+        Ok(()) => (),
+        // NFSv4 mounts do not support POSIX ACLs, but their own ACL format
+        Err(Errno::EOPNOTSUPP) => return apply_nfs4_acl(c_proc_path, metadata),
+        Err(err) => return Err(err.into()),
+    }
     drop(acl);
 
     // acl type default:
@@ -332,6 +338,22 @@ fn apply_acls(
     Ok(())
 }
 
+fn apply_nfs4_acl(c_proc_path: &CStr, metadata: &Metadata) -> Result<(), Error> {
+    let data = nfs4_acl::encode_from_posix(metadata);
+
+    c_result!(unsafe {
+        libc::setxattr(
+            c_proc_path.as_ptr(),
+            nfs4_acl::NFS4_ACL_XATTR.as_ptr(),
+            data.as_ptr() as *const libc::c_void,
+            data.len(),
+            0,
+        )
+    })
+    .map(drop)
+    .context("failed to apply ACL as NFSv4 ACL")
+}
+
 fn apply_quota_project_id(flags: Flags, fd: RawFd, metadata: &Metadata) -> Result<(), Error> {
     if !flags.contains(Flags::WITH_QUOTA_PROJID) {
         return Ok(());
@@ -47,24 +47,33 @@
 //! (user, group, acl, ...) because this is already defined by the
 //! linked `ENTRY`.
 
+pub(crate) mod change_detection;
 pub(crate) mod create;
 pub(crate) mod dir_stack;
 pub(crate) mod extract;
 pub(crate) mod metadata;
+pub(crate) mod nfs4_acl;
 pub(crate) mod tools;
 
 mod flags;
 pub use flags::Flags;
 
-pub use create::{create_archive, PxarCreateOptions};
+pub use change_detection::{
+    metadata_cache_path, ChangeDetection, ChangeDetectionMode, ReusedRange, ReusedRangeQueue,
+};
+pub use create::{
+    create_archive, FollowPolicy, PxarCreateOptions, SkipReason, SkippedEntries, SkippedEntry,
+};
 pub use extract::{
-    create_tar, create_zip, extract_archive, extract_sub_dir, extract_sub_dir_seq, ErrorHandler,
-    OverwriteFlags, PxarExtractContext, PxarExtractOptions,
+    convert_to_tar, create_tar, create_zip, extract_archive, extract_archive_seekable,
+    extract_sub_dir, extract_sub_dir_seq, ErrorHandler, OverwriteFlags, PxarExtractContext,
+    PxarExtractOptions,
 };
+pub use nfs4_acl::Nfs4AclMode;
 
 /// The format requires to build sorted directory lookup tables in
 /// memory, so we restrict the number of allowed entries to limit
 /// maximum memory usage.
 pub const ENCODER_MAX_ENTRIES: usize = 1024 * 1024;
 
-pub use tools::{format_multi_line_entry, format_single_line_entry};
+pub use tools::{format_mtree_entry, format_multi_line_entry, format_single_line_entry};
@@ -0,0 +1,546 @@
+//! Translation between NFSv4 ACLs and POSIX draft ACLs.
+//!
+//! The linux NFS client exposes NFSv4 ACLs as the `system.nfs4_acl` extended attribute, encoded
+//! as XDR `nfsacl41` structure (RFC 7530, section 6). pxar archives only know POSIX draft ACLs, so
+//! on backup we map NFSv4 ACLs to POSIX ACLs, and on restore to a filesystem without POSIX ACL
+//! support we map them back.
+//!
+//! NFSv4 ACLs are more expressive than POSIX ACLs, so the backup direction can lose information,
+//! for example `DENY` entries for named principals, audit entries or partial inheritance. What
+//! happens in that case is controlled by the [`Nfs4AclMode`].
+
+use std::ffi::CStr;
+use std::path::Path;
+
+use anyhow::{bail, format_err, Error};
+use nix::unistd::{Group, User};
+use serde::{Deserialize, Serialize};
+
+use proxmox_lang::c_str;
+use proxmox_schema::api;
+use proxmox_sys::fs::acl;
+
+use pxar::format::acl::{
+    Default as DefaultAcl, Group as AclGroup, GroupObject, Permissions, User as AclUser,
+};
+use pxar::Metadata;
+
+/// Name of the extended attribute used by the linux NFS client for NFSv4 ACLs.
+pub const NFS4_ACL_XATTR: &CStr = c_str!("system.nfs4_acl");
+
+const ACE4_ACCESS_ALLOWED_ACE_TYPE: u32 = 0;
+const ACE4_ACCESS_DENIED_ACE_TYPE: u32 = 1;
+
+const ACE4_FILE_INHERIT_ACE: u32 = 0x01;
+const ACE4_DIRECTORY_INHERIT_ACE: u32 = 0x02;
+const ACE4_NO_PROPAGATE_INHERIT_ACE: u32 = 0x04;
+const ACE4_INHERIT_ONLY_ACE: u32 = 0x08;
+const ACE4_IDENTIFIER_GROUP: u32 = 0x40;
+
+const ACE4_READ_DATA: u32 = 0x0000_0001;
+const ACE4_WRITE_DATA: u32 = 0x0000_0002;
+const ACE4_APPEND_DATA: u32 = 0x0000_0004;
+const ACE4_READ_NAMED_ATTRS: u32 = 0x0000_0008;
+const ACE4_WRITE_NAMED_ATTRS: u32 = 0x0000_0010;
+const ACE4_EXECUTE: u32 = 0x0000_0020;
+const ACE4_DELETE_CHILD: u32 = 0x0000_0040;
+const ACE4_READ_ATTRIBUTES: u32 = 0x0000_0080;
+const ACE4_WRITE_ATTRIBUTES: u32 = 0x0000_0100;
+const ACE4_READ_ACL: u32 = 0x0002_0000;
+const ACE4_WRITE_ACL: u32 = 0x0004_0000;
+const ACE4_WRITE_OWNER: u32 = 0x0008_0000;
+const ACE4_SYNCHRONIZE: u32 = 0x0010_0000;
+
+// bits granted to everybody with read access when mapping POSIX ACLs to NFSv4
+const ACE4_GENERIC_READ: u32 = ACE4_READ_DATA
+    | ACE4_READ_NAMED_ATTRS
+    | ACE4_READ_ATTRIBUTES
+    | ACE4_READ_ACL
+    | ACE4_SYNCHRONIZE;
+// only the owner may change the ACL, ownership and attributes on POSIX systems
+const ACE4_OWNER_ONLY: u32 = ACE4_WRITE_ACL | ACE4_WRITE_OWNER | ACE4_WRITE_ATTRIBUTES;
+
+#[api]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// How to handle NFSv4 ACLs when creating an archive.
+pub enum Nfs4AclMode {
+    /// Do not back up NFSv4 ACLs.
+    #[default]
+    Ignore,
+    /// Translate NFSv4 ACLs to POSIX ACLs, warn if information is lost.
+    Translate,
+    /// Translate NFSv4 ACLs to POSIX ACLs, fail if information would be lost.
+    Strict,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Ace {
+    ace_type: u32,
+    flags: u32,
+    mask: u32,
+    who: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Principal {
+    Owner,
+    Group,
+    Everyone,
+    User(u32),
+    NamedGroup(u32),
+}
+
+fn read_u32(data: &[u8], pos: &mut usize) -> Result<u32, Error> {
+    let bytes = data
+        .get(*pos..*pos + 4)
+        .ok_or_else(|| format_err!("NFSv4 ACL too short"))?;
+    *pos += 4;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn parse_acl(data: &[u8]) -> Result<Vec<Ace>, Error> {
+    let mut pos = 0;
+
+    let count = read_u32(data, &mut pos)?;
+    let mut aces = Vec::new();
+    for _ in 0..count {
+        let ace_type = read_u32(data, &mut pos)?;
+        let flags = read_u32(data, &mut pos)?;
+        let mask = read_u32(data, &mut pos)?;
+        let len = read_u32(data, &mut pos)? as usize;
+
+        let who = data
+            .get(pos..pos + len)
+            .ok_or_else(|| format_err!("NFSv4 ACL too short"))?;
+        let who = std::str::from_utf8(who)
+            .map_err(|_| format_err!("NFSv4 ACL contains non UTF-8 principal"))?
+            .to_string();
+        // XDR opaque data is padded to a multiple of 4 bytes
+        pos += (len + 3) & !3;
+
+        aces.push(Ace {
+            ace_type,
+            flags,
+            mask,
+            who,
+        });
+    }
+
+    Ok(aces)
+}
+
+fn encode_acl(aces: &[Ace]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(aces.len() as u32).to_be_bytes());
+    for ace in aces {
+        data.extend_from_slice(&ace.ace_type.to_be_bytes());
+        data.extend_from_slice(&ace.flags.to_be_bytes());
+        data.extend_from_slice(&ace.mask.to_be_bytes());
+        data.extend_from_slice(&(ace.who.len() as u32).to_be_bytes());
+        data.extend_from_slice(ace.who.as_bytes());
+        data.resize((data.len() + 3) & !3, 0);
+    }
+    data
+}
+
+fn resolve_principal(ace: &Ace) -> Option<Principal> {
+    match ace.who.as_str() {
+        "OWNER@" => return Some(Principal::Owner),
+        "GROUP@" => return Some(Principal::Group),
+        "EVERYONE@" => return Some(Principal::Everyone),
+        _ => (),
+    }
+
+    // principals are either numeric ids or "name@domain"
+    let name = ace
+        .who
+        .split_once('@')
+        .map_or(ace.who.as_str(), |(name, _)| name);
+    let is_group = ace.flags & ACE4_IDENTIFIER_GROUP != 0;
+
+    if let Ok(id) = name.parse::<u32>() {
+        return Some(match is_group {
+            true => Principal::NamedGroup(id),
+            false => Principal::User(id),
+        });
+    }
+
+    if is_group {
+        Group::from_name(name)
+            .ok()
+            .flatten()
+            .map(|group| Principal::NamedGroup(group.gid.as_raw()))
+    } else {
+        User::from_name(name)
+            .ok()
+            .flatten()
+            .map(|user| Principal::User(user.uid.as_raw()))
+    }
+}
+
+fn mask_to_permissions(mask: u32) -> u64 {
+    let mut permissions = 0;
+    if mask & ACE4_READ_DATA != 0 {
+        permissions |= acl::ACL_READ;
+    }
+    if mask & (ACE4_WRITE_DATA | ACE4_APPEND_DATA) != 0 {
+        permissions |= acl::ACL_WRITE;
+    }
+    if mask & ACE4_EXECUTE != 0 {
+        permissions |= acl::ACL_EXECUTE;
+    }
+    permissions
+}
+
+fn permissions_to_mask(permissions: u64, is_dir: bool) -> u32 {
+    let mut mask = 0;
+    if permissions & acl::ACL_READ != 0 {
+        mask |= ACE4_GENERIC_READ;
+    }
+    if permissions & acl::ACL_WRITE != 0 {
+        mask |= ACE4_WRITE_DATA | ACE4_APPEND_DATA | ACE4_WRITE_NAMED_ATTRS;
+        if is_dir {
+            mask |= ACE4_DELETE_CHILD;
+        }
+    }
+    if permissions & acl::ACL_EXECUTE != 0 {
+        mask |= ACE4_EXECUTE;
+    }
+    mask
+}
+
+/// The entries of one (access or default) ACL, in the order they are evaluated.
+#[derive(Default)]
+struct EntryList {
+    entries: Vec<(Principal, u32, u32)>, // principal, ace type, mask
+}
+
+impl EntryList {
+    // NFSv4 ACLs are evaluated first match per permission bit, and EVERYONE@ entries also apply
+    // to all other principals.
+    fn permissions_for(&self, principal: Principal) -> u64 {
+        let mut allowed = 0;
+        let mut denied = 0;
+        for (who, ace_type, mask) in &self.entries {
+            if *who != principal && *who != Principal::Everyone {
+                continue;
+            }
+            let mask = mask_to_permissions(*mask);
+            match *ace_type {
+                ACE4_ACCESS_ALLOWED_ACE_TYPE => allowed |= mask & !denied,
+                _ => denied |= mask & !allowed,
+            }
+        }
+        allowed
+    }
+
+    fn named(&self) -> (Vec<AclUser>, Vec<AclGroup>) {
+        let mut users = Vec::new();
+        let mut groups = Vec::new();
+        for (who, _, _) in &self.entries {
+            match *who {
+                Principal::User(uid) if !users.iter().any(|u: &AclUser| u.uid == uid) => {
+                    users.push(AclUser {
+                        uid,
+                        permissions: Permissions(self.permissions_for(*who)),
+                    });
+                }
+                Principal::NamedGroup(gid) if !groups.iter().any(|g: &AclGroup| g.gid == gid) => {
+                    groups.push(AclGroup {
+                        gid,
+                        permissions: Permissions(self.permissions_for(*who)),
+                    });
+                }
+                _ => (),
+            }
+        }
+        users.sort();
+        groups.sort();
+        (users, groups)
+    }
+}
+
+/// Translate the NFSv4 ACL `data` into the POSIX ACL of `metadata`.
+///
+/// Returns a description of every detail which could not be represented.
+pub(crate) fn translate_to_posix(
+    metadata: &mut Metadata,
+    data: &[u8],
+) -> Result<Vec<String>, Error> {
+    let is_dir = metadata.is_dir();
+    let mut lossy = Vec::new();
+    let mut access = EntryList::default();
+    let mut default = EntryList::default();
+
+    for ace in parse_acl(data)? {
+        if ace.ace_type != ACE4_ACCESS_ALLOWED_ACE_TYPE
+            && ace.ace_type != ACE4_ACCESS_DENIED_ACE_TYPE
+        {
+            lossy.push(format!("audit/alarm entry for '{}'", ace.who));
+            continue;
+        }
+
+        let principal = match resolve_principal(&ace) {
+            Some(principal) => principal,
+            None => {
+                lossy.push(format!("unknown principal '{}'", ace.who));
+                continue;
+            }
+        };
+
+        if ace.ace_type == ACE4_ACCESS_DENIED_ACE_TYPE
+            && matches!(principal, Principal::User(_) | Principal::NamedGroup(_))
+        {
+            lossy.push(format!("deny entry for '{}'", ace.who));
+        }
+
+        if (ace.mask & ACE4_WRITE_DATA != 0) != (ace.mask & ACE4_APPEND_DATA != 0) {
+            lossy.push(format!(
+                "separate write/append permission for '{}'",
+                ace.who
+            ));
+        }
+
+        if ace.ace_type == ACE4_ACCESS_ALLOWED_ACE_TYPE
+            && principal != Principal::Owner
+            && ace.mask & (ACE4_WRITE_ACL | ACE4_WRITE_OWNER) != 0
+        {
+            lossy.push(format!("ACL/owner change permission for '{}'", ace.who));
+        }
+
+        let inherit = ace.flags & (ACE4_FILE_INHERIT_ACE | ACE4_DIRECTORY_INHERIT_ACE);
+        if is_dir && inherit != 0 {
+            if inherit != ACE4_FILE_INHERIT_ACE | ACE4_DIRECTORY_INHERIT_ACE
+                || ace.flags & ACE4_NO_PROPAGATE_INHERIT_ACE != 0
+            {
+                lossy.push(format!("partial inheritance for '{}'", ace.who));
+            }
+            default.entries.push((principal, ace.ace_type, ace.mask));
+        }
+
+        if !is_dir || ace.flags & ACE4_INHERIT_ONLY_ACE == 0 {
+            access.entries.push((principal, ace.ace_type, ace.mask));
+        }
+    }
+
+    let (users, groups) = access.named();
+    if !users.is_empty() || !groups.is_empty() {
+        // the stat group bits are used as ACL mask
+        let mask = (metadata.stat.mode >> 3) & 0o7;
+        let group_obj = access.permissions_for(Principal::Group);
+        let union = users
+            .iter()
+            .map(|u| u.permissions.0)
+            .chain(groups.iter().map(|g| g.permissions.0));
+        if union.chain(Some(group_obj)).any(|perms| perms & !mask != 0) {
+            lossy.push("named entries exceed the group permissions".to_string());
+        }
+        metadata.acl.group_obj = Some(GroupObject {
+            permissions: Permissions(group_obj),
+        });
+    }
+    metadata.acl.users = users;
+    metadata.acl.groups = groups;
+
+    if !default.entries.is_empty() {
+        let (users, groups) = default.named();
+        let group_obj = default.permissions_for(Principal::Group);
+        let mask = if users.is_empty() && groups.is_empty() {
+            Permissions::NO_MASK
+        } else {
+            let union = users
+                .iter()
+                .map(|u| u.permissions.0)
+                .chain(groups.iter().map(|g| g.permissions.0));
+            Permissions(union.fold(group_obj, |acc, perms| acc | perms))
+        };
+
+        metadata.acl.default = Some(DefaultAcl {
+            user_obj_permissions: Permissions(default.permissions_for(Principal::Owner)),
+            group_obj_permissions: Permissions(group_obj),
+            other_permissions: Permissions(default.permissions_for(Principal::Everyone)),
+            mask_permissions: mask,
+        });
+        metadata.acl.default_users = users;
+        metadata.acl.default_groups = groups;
+    }
+
+    Ok(lossy)
+}
+
+fn push_allow(aces: &mut Vec<Ace>, flags: u32, mask: u32, who: String) {
+    aces.push(Ace {
+        ace_type: ACE4_ACCESS_ALLOWED_ACE_TYPE,
+        flags,
+        mask,
+        who,
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn push_posix_entries(
+    aces: &mut Vec<Ace>,
+    flags: u32,
+    is_dir: bool,
+    user_obj: u64,
+    group_obj: u64,
+    other: u64,
+    mask: u64,
+    users: &[AclUser],
+    groups: &[AclGroup],
+) {
+    push_allow(
+        aces,
+        flags,
+        permissions_to_mask(user_obj, is_dir) | ACE4_OWNER_ONLY,
+        "OWNER@".to_string(),
+    );
+    for user in users {
+        let perms = user.permissions.0 & mask;
+        push_allow(
+            aces,
+            flags,
+            permissions_to_mask(perms, is_dir),
+            user.uid.to_string(),
+        );
+    }
+    push_allow(
+        aces,
+        flags | ACE4_IDENTIFIER_GROUP,
+        permissions_to_mask(group_obj & mask, is_dir),
+        "GROUP@".to_string(),
+    );
+    for group in groups {
+        let perms = group.permissions.0 & mask;
+        push_allow(
+            aces,
+            flags | ACE4_IDENTIFIER_GROUP,
+            permissions_to_mask(perms, is_dir),
+            group.gid.to_string(),
+        );
+    }
+    push_allow(
+        aces,
+        flags,
+        permissions_to_mask(other, is_dir),
+        "EVERYONE@".to_string(),
+    );
+}
+
+/// Encode the POSIX ACL of `metadata` as NFSv4 ACL.
+///
+/// Named principals are encoded as numeric ids, which the linux NFS client maps itself. The
+/// resulting ACL grants the same permissions as the POSIX ACL for all principals which are not
+/// members of more than one group listed in it.
+pub(crate) fn encode_from_posix(metadata: &Metadata) -> Vec<u8> {
+    let is_dir = metadata.is_dir();
+    let mode = metadata.stat.mode;
+    let mut aces = Vec::new();
+
+    let stat_group = (mode >> 3) & 0o7;
+    let (group_obj, mask) = match metadata.acl.group_obj.as_ref() {
+        Some(group_obj) => (group_obj.permissions.0, stat_group),
+        None => (stat_group, 0o7),
+    };
+
+    push_posix_entries(
+        &mut aces,
+        0,
+        is_dir,
+        (mode >> 6) & 0o7,
+        group_obj,
+        mode & 0o7,
+        mask,
+        &metadata.acl.users,
+        &metadata.acl.groups,
+    );
+
+    if let Some(default) = metadata.acl.default.as_ref().filter(|_| is_dir) {
+        let mask = match default.mask_permissions {
+            Permissions::NO_MASK => 0o7,
+            mask => mask.0,
+        };
+        // NO_MASK placeholders mean the entry was not set, grant nothing in that case
+        let perms = |p: Permissions| if p == Permissions::NO_MASK { 0 } else { p.0 };
+
+        push_posix_entries(
+            &mut aces,
+            ACE4_FILE_INHERIT_ACE | ACE4_DIRECTORY_INHERIT_ACE | ACE4_INHERIT_ONLY_ACE,
+            is_dir,
+            perms(default.user_obj_permissions),
+            perms(default.group_obj_permissions),
+            perms(default.other_permissions),
+            mask,
+            &metadata.acl.default_users,
+            &metadata.acl.default_groups,
+        );
+    }
+
+    encode_acl(&aces)
+}
+
+/// Log or fail on lossy NFSv4 ACL translations according to `mode`.
+pub(crate) fn check_lossy(mode: Nfs4AclMode, path: &Path, lossy: &[String]) -> Result<(), Error> {
+    match mode {
+        Nfs4AclMode::Strict => bail!(
+            "NFSv4 ACL cannot be translated without loss: {} (try --nfs4-acl translate)",
+            lossy.join(", ")
+        ),
+        _ => {
+            log::warn!(
+                "Warning: {:?}: NFSv4 ACL translated with loss of information: {}",
+                path,
+                lossy.join(", ")
+            );
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn test_nfs4_acl_roundtrip() -> Result<(), Error> {
+    let aces = vec![
+        Ace {
+            ace_type: ACE4_ACCESS_ALLOWED_ACE_TYPE,
+            flags: 0,
+            mask: ACE4_READ_DATA | ACE4_WRITE_DATA | ACE4_APPEND_DATA,
+            who: "OWNER@".to_string(),
+        },
+        Ace {
+            ace_type: ACE4_ACCESS_ALLOWED_ACE_TYPE,
+            flags: 0,
+            mask: ACE4_READ_DATA | ACE4_EXECUTE,
+            who: "1000".to_string(),
+        },
+        Ace {
+            ace_type: ACE4_ACCESS_DENIED_ACE_TYPE,
+            flags: 0,
+            mask: ACE4_WRITE_DATA,
+            who: "EVERYONE@".to_string(),
+        },
+    ];
+    let data = encode_acl(&aces);
+    assert_eq!(data.len() % 4, 0);
+    assert_eq!(parse_acl(&data)?, aces);
+
+    let mut metadata = Metadata::file_builder(0o640).build();
+    let lossy = translate_to_posix(&mut metadata, &data)?;
+    assert!(lossy
+        .iter()
+        .any(|msg| msg.contains("exceed the group permissions")));
+    assert_eq!(metadata.acl.users.len(), 1);
+    assert_eq!(metadata.acl.users[0].uid, 1000);
+    assert_eq!(
+        metadata.acl.users[0].permissions.0,
+        acl::ACL_READ | acl::ACL_EXECUTE
+    );
+
+    let mut restored = Metadata::file_builder(0o640).build();
+    translate_to_posix(&mut restored, &encode_from_posix(&metadata))?;
+    assert_eq!(restored.acl.users[0].permissions.0, acl::ACL_READ);
+
+    assert!(parse_acl(&data[..data.len() - 4]).is_err());
+
+    Ok(())
+}
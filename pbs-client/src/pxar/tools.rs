@@ -204,3 +204,164 @@ pub fn format_multi_line_entry(entry: &Entry) -> String {
         format_mtime(&meta.stat.mtime),
     )
 }
+
+/// Encode a path or link target for mtree, using octal escapes for everything but printable
+/// characters without special meaning.
+fn mtree_escape(data: &[u8]) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for &b in data {
+        match b {
+            b'\\' | b'#' | b'=' | b'*' | b'?' | b'[' => escaped.push_str(&format!("\\{b:03o}")),
+            0x21..=0x7e => escaped.push(b as char),
+            _ => escaped.push_str(&format!("\\{b:03o}")),
+        }
+    }
+    escaped
+}
+
+/// Format an entry as a BSD mtree(5) record, `path` being its path in the archive.
+///
+/// Hard links have no metadata on their own, so `entry` should be the link's target for them,
+/// the record then describes a regular file. The `sha256` digest is only used for regular files.
+/// Goodbye tables only mark the end of a directory and have no record, `None` is returned for
+/// them.
+pub fn format_mtree_entry(path: &Path, entry: &Entry, sha256: Option<&[u8; 32]>) -> Option<String> {
+    let meta = entry.metadata();
+
+    let relative = path.strip_prefix("/").unwrap_or(path);
+    let mut record = if relative.as_os_str().is_empty() {
+        ".".to_string()
+    } else {
+        format!("./{}", mtree_escape(relative.as_os_str().as_bytes()))
+    };
+
+    let type_name = match entry.kind() {
+        EntryKind::File { .. } | EntryKind::Hardlink(_) => "file",
+        EntryKind::Directory => "dir",
+        EntryKind::Symlink(_) => "link",
+        EntryKind::Device(_) if meta.stat.is_chardev() => "char",
+        EntryKind::Device(_) => "block",
+        EntryKind::Fifo => "fifo",
+        EntryKind::Socket => "socket",
+        EntryKind::GoodbyeTable => return None,
+    };
+
+    record.push_str(&format!(
+        " type={} mode={:04o} uid={} gid={} time={}.{:09}",
+        type_name,
+        meta.file_mode(),
+        meta.stat.uid,
+        meta.stat.gid,
+        meta.stat.mtime.secs,
+        meta.stat.mtime.nanos,
+    ));
+
+    match entry.kind() {
+        EntryKind::File { size, .. } => {
+            record.push_str(&format!(" size={size}"));
+            if let Some(sha256) = sha256 {
+                record.push_str(&format!(" sha256digest={}", hex::encode(sha256)));
+            }
+        }
+        EntryKind::Symlink(link) => {
+            record.push_str(&format!(
+                " link={}",
+                mtree_escape(link.as_os_str().as_bytes())
+            ));
+        }
+        EntryKind::Device(dev) => {
+            record.push_str(&format!(" device=native,{},{}", dev.major, dev.minor));
+        }
+        _ => (),
+    }
+
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // test directory below ./target/testout, removed again when dropped, also if the test fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let mut path: PathBuf = String::from("./target/testout").into();
+            path.push(std::module_path!());
+            path.push(name);
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_mtree_escape() {
+        assert_eq!(mtree_escape(b"etc/fstab"), "etc/fstab");
+        assert_eq!(mtree_escape(b"a b\tc"), "a\\040b\\011c");
+        assert_eq!(mtree_escape(b"x#y=z"), "x\\043y\\075z");
+        assert_eq!(mtree_escape(b"*?[]"), "\\052\\077\\133]");
+        assert_eq!(mtree_escape(b"back\\slash"), "back\\134slash");
+        assert_eq!(mtree_escape("\u{e4}".as_bytes()), "\\303\\244");
+    }
+
+    #[test]
+    fn test_format_mtree_entry() -> Result<(), Error> {
+        let test_dir = TestDir::new("format_mtree_entry");
+        let path = test_dir.0.join("test.pxar");
+
+        let file = std::fs::File::create(&path)?;
+        let mut encoder = pxar::encoder::sync::Encoder::new(
+            pxar::encoder::sync::StandardWriter::new(file),
+            &Metadata::dir_builder(0o755).build(),
+        )?;
+        let mut dir = encoder.create_directory("sub dir", &Metadata::dir_builder(0o700).build())?;
+        let mut contents = dir.create_file(&Metadata::file_builder(0o640).build(), "data", 5)?;
+        std::io::Write::write_all(&mut contents, b"hello")?;
+        drop(contents);
+        dir.add_symlink(
+            &Metadata::builder(mode::IFLNK | 0o777).build(),
+            "link",
+            "../target=1",
+        )?;
+        dir.finish()?;
+        encoder.finish()?;
+
+        let digest = [0xab; 32];
+        let mut records = Vec::new();
+        for entry in pxar::decoder::Decoder::open(&path)? {
+            let entry = entry?;
+            let sha256 = matches!(entry.kind(), EntryKind::File { .. }).then_some(&digest);
+            records.extend(format_mtree_entry(entry.path(), &entry, sha256));
+        }
+
+        let time = "time=0.000000000";
+        assert_eq!(
+            records,
+            [
+                format!(". type=dir mode=0755 uid=0 gid=0 {time}"),
+                format!("./sub\\040dir type=dir mode=0700 uid=0 gid=0 {time}"),
+                format!(
+                    "./sub\\040dir/data type=file mode=0640 uid=0 gid=0 {time} size=5 \
+                    sha256digest={}",
+                    hex::encode(digest)
+                ),
+                format!(
+                    "./sub\\040dir/link type=link mode=0777 uid=0 gid=0 {time} \
+                    link=../target\\0751"
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+}
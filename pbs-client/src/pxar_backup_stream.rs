@@ -2,6 +2,7 @@ use std::io::Write;
 //use std::os::unix::io::FromRawFd;
 use std::path::Path;
 use std::pin::Pin;
+use std::sync::atomic::AtomicU64;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
@@ -17,13 +18,18 @@ use proxmox_io::StdChannelWriter;
 
 use pbs_datastore::catalog::CatalogWriter;
 
+use crate::pxar::change_detection::StreamPositionWriter;
+use crate::Progress;
+
 /// Stream implementation to encode and upload .pxar archives.
 ///
 /// The hyper client needs an async Stream for file upload, so we
 /// spawn an extra thread to encode the .pxar data and pipe it to the
-/// consumer.
+/// consumer. The data is forwarded to an async channel on the blocking
+/// thread pool, so that polling the stream never blocks a runtime worker
+/// which could otherwise chunk, compress or upload data in the meantime.
 pub struct PxarBackupStream {
-    rx: Option<std::sync::mpsc::Receiver<Result<Vec<u8>, Error>>>,
+    rx: Option<tokio::sync::mpsc::Receiver<Result<Vec<u8>, Error>>>,
     handle: Option<AbortHandle>,
     error: Arc<Mutex<Option<String>>>,
 }
@@ -40,18 +46,37 @@ impl PxarBackupStream {
         dir: Dir,
         catalog: Arc<Mutex<CatalogWriter<W>>>,
         options: crate::pxar::PxarCreateOptions,
+        progress: Option<Arc<Progress>>,
     ) -> Result<Self, Error> {
-        let (tx, rx) = std::sync::mpsc::sync_channel(10);
+        let (tx, std_rx) = std::sync::mpsc::sync_channel(10);
+        let (async_tx, rx) = tokio::sync::mpsc::channel(10);
+
+        tokio::task::spawn_blocking(move || {
+            while let Ok(data) = std_rx.recv() {
+                if async_tx.blocking_send(data).is_err() {
+                    break; // stream dropped
+                }
+            }
+        });
 
         let buffer_size = 256 * 1024;
 
+        // the archiver needs the stream position to queue reused chunk ranges
+        let position = match options.change_detection {
+            Some(ref cd) => cd.lock().unwrap().position_handle(),
+            None => Arc::new(AtomicU64::new(0)),
+        };
+
         let error = Arc::new(Mutex::new(None));
         let error2 = Arc::clone(&error);
         let handler = async move {
-            let writer = TokioWriterAdapter::new(std::io::BufWriter::with_capacity(
-                buffer_size,
-                StdChannelWriter::new(tx),
-            ));
+            let writer = StreamPositionWriter::new(
+                TokioWriterAdapter::new(std::io::BufWriter::with_capacity(
+                    buffer_size,
+                    StdChannelWriter::new(tx),
+                )),
+                position,
+            );
 
             let writer = pxar::encoder::sync::StandardWriter::new(writer);
             if let Err(err) = crate::pxar::create_archive(
@@ -60,6 +85,9 @@ impl PxarBackupStream {
                 crate::pxar::Flags::DEFAULT,
                 move |path| {
                     log::debug!("{:?}", path);
+                    if let Some(ref progress) = progress {
+                        progress.set_current_file(path);
+                    }
                     Ok(())
                 },
                 Some(catalog),
@@ -87,17 +115,18 @@ impl PxarBackupStream {
         dirname: &Path,
         catalog: Arc<Mutex<CatalogWriter<W>>>,
         options: crate::pxar::PxarCreateOptions,
+        progress: Option<Arc<Progress>>,
     ) -> Result<Self, Error> {
         let dir = nix::dir::Dir::open(dirname, OFlag::O_DIRECTORY, Mode::empty())?;
 
-        Self::new(dir, catalog, options)
+        Self::new(dir, catalog, options, progress)
     }
 }
 
 impl Stream for PxarBackupStream {
     type Item = Result<Vec<u8>, Error>;
 
-    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         {
             // limit lock scope
             let error = self.error.lock().unwrap();
@@ -106,9 +135,10 @@ impl Stream for PxarBackupStream {
             }
         }
 
-        match proxmox_async::runtime::block_in_place(|| self.rx.as_ref().unwrap().recv()) {
-            Ok(data) => Poll::Ready(Some(data)),
-            Err(_) => {
+        match self.rx.as_mut().unwrap().poll_recv(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Some(data)) => Poll::Ready(Some(data)),
+            Poll::Ready(None) => {
                 let error = self.error.lock().unwrap();
                 if let Some(ref msg) = *error {
                     return Poll::Ready(Some(Err(format_err!("{}", msg))));
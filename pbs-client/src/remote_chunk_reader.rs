@@ -4,6 +4,7 @@ use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
+use tokio::sync::Semaphore;
 
 use proxmox_async::runtime::block_on;
 
@@ -13,7 +14,7 @@ use pbs_datastore::read_chunk::AsyncReadChunk;
 use pbs_datastore::read_chunk::ReadChunk;
 use pbs_tools::crypt_config::CryptConfig;
 
-use super::BackupReader;
+use super::{BackupReader, Progress};
 
 /// Read chunks from remote host using ``BackupReader``
 #[derive(Clone)]
@@ -23,6 +24,9 @@ pub struct RemoteChunkReader {
     crypt_mode: CryptMode,
     cache_hint: Arc<HashMap<[u8; 32], usize>>,
     cache: Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>,
+    prefetch_window: usize,
+    download_limit: Option<Arc<Semaphore>>,
+    progress: Option<Arc<Progress>>,
 }
 
 impl RemoteChunkReader {
@@ -41,6 +45,40 @@ impl RemoteChunkReader {
             crypt_mode,
             cache_hint: Arc::new(cache_hint),
             cache: Arc::new(Mutex::new(HashMap::new())),
+            prefetch_window: 0,
+            download_limit: None,
+            progress: None,
+        }
+    }
+
+    /// Set the number of chunks sequential readers download ahead, see
+    /// [`AsyncReadChunk::prefetch_window`].
+    pub fn with_prefetch_window(mut self, window: usize) -> Self {
+        self.prefetch_window = window;
+        self
+    }
+
+    /// Limit the number of chunks downloaded at the same time by this reader and its clones.
+    ///
+    /// Without a limit, as many chunks are downloaded concurrently as there are concurrent
+    /// reads, including prefetches.
+    pub fn with_download_concurrency(mut self, concurrency: usize) -> Self {
+        self.download_limit = Some(Arc::new(Semaphore::new(concurrency.max(1))));
+        self
+    }
+
+    /// Count the bytes of all downloaded chunks for progress reports.
+    ///
+    /// Chunks served from the cache are not counted, so they do not inflate the throughput or
+    /// the ETA.
+    pub fn with_progress(mut self, progress: Arc<Progress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn count_read(&self, data: &[u8]) {
+        if let Some(ref progress) = self.progress {
+            progress.add_read(data.len() as u64);
         }
     }
 
@@ -49,7 +87,12 @@ impl RemoteChunkReader {
     pub async fn read_raw_chunk(&self, digest: &[u8; 32]) -> Result<DataBlob, Error> {
         let mut chunk_data = Vec::with_capacity(4 * 1024 * 1024);
 
+        let permit = match self.download_limit {
+            Some(ref limit) => Some(limit.acquire().await?),
+            None => None,
+        };
         self.client.download_chunk(digest, &mut chunk_data).await?;
+        drop(permit);
 
         let chunk = DataBlob::load_from_reader(&mut &chunk_data[..])
             .map_err(|err| format_err!("Failed to parse chunk {} - {err}", hex::encode(digest)))?;
@@ -87,6 +130,7 @@ impl ReadChunk for RemoteChunkReader {
         if use_cache {
             (*self.cache.lock().unwrap()).insert(*digest, raw_data.to_vec());
         }
+        self.count_read(&raw_data);
 
         Ok(raw_data)
     }
@@ -118,8 +162,13 @@ impl AsyncReadChunk for RemoteChunkReader {
             if use_cache {
                 (*self.cache.lock().unwrap()).insert(*digest, raw_data.to_vec());
             }
+            self.count_read(&raw_data);
 
             Ok(raw_data)
         })
     }
+
+    fn prefetch_window(&self) -> usize {
+        self.prefetch_window
+    }
 }
@@ -0,0 +1,362 @@
+//! Stream format used to restore snapshots from tape directly to a client
+//!
+//! The server reads the snapshot archive and the required chunk archives
+//! from tape and sends them as a sequence of frames, without storing
+//! anything in a datastore. Chunks arrive in tape order, so the client
+//! writes them directly to all offsets referenced by the index files.
+//!
+//! Each frame starts with a one byte frame type and the payload length
+//! (u64, little endian):
+//!
+//! - file: name length (u16, little endian), name, file contents
+//! - chunk: digest (32 bytes), raw chunk blob
+//! - end: empty payload, marks a complete stream
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_datastore::dynamic_index::DynamicIndexReader;
+use pbs_datastore::fixed_index::FixedIndexReader;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{archive_type, ArchiveType, MANIFEST_BLOB_NAME};
+use pbs_datastore::DataBlob;
+use pbs_tools::crypt_config::CryptConfig;
+
+const FRAME_FILE: u8 = 1;
+const FRAME_CHUNK: u8 = 2;
+const FRAME_END: u8 = 3;
+
+const FRAME_HEADER_SIZE: usize = 9;
+
+// chunks are at most 16 MiB, index files may be larger
+const MAX_FRAME_SIZE: u64 = 1024 * 1024 * 1024;
+
+fn encode_frame(frame_type: u8, payload: &[&[u8]]) -> Vec<u8> {
+    let len: usize = payload.iter().map(|part| part.len()).sum();
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + len);
+    frame.push(frame_type);
+    frame.extend_from_slice(&(len as u64).to_le_bytes());
+    for part in payload {
+        frame.extend_from_slice(part);
+    }
+    frame
+}
+
+/// Encode a file from the snapshot archive (index, blob or manifest).
+pub fn encode_file_frame(name: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let name_len = u16::try_from(name.len()).map_err(|_| format_err!("file name too long"))?;
+    Ok(encode_frame(
+        FRAME_FILE,
+        &[&name_len.to_le_bytes(), name.as_bytes(), data],
+    ))
+}
+
+/// Encode a chunk (raw blob data, still compressed and/or encrypted).
+pub fn encode_chunk_frame(digest: &[u8; 32], blob: &DataBlob) -> Vec<u8> {
+    encode_frame(FRAME_CHUNK, &[digest, blob.raw_data()])
+}
+
+/// Encode the end marker.
+pub fn encode_end_frame() -> Vec<u8> {
+    encode_frame(FRAME_END, &[])
+}
+
+/// A decoded frame
+pub enum TapeRestoreFrame {
+    File { name: String, data: Vec<u8> },
+    Chunk { digest: [u8; 32], data: Vec<u8> },
+    End,
+}
+
+/// Incremental frame decoder
+#[derive(Default)]
+pub struct TapeRestoreStreamDecoder {
+    buffer: Vec<u8>,
+}
+
+impl TapeRestoreStreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append received data.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Returns the next complete frame, if already received.
+    pub fn next_frame(&mut self) -> Result<Option<TapeRestoreFrame>, Error> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let frame_type = self.buffer[0];
+        let len = u64::from_le_bytes(self.buffer[1..FRAME_HEADER_SIZE].try_into().unwrap());
+        if len > MAX_FRAME_SIZE {
+            bail!("frame too large ({len} bytes)");
+        }
+        let len = len as usize;
+
+        if self.buffer.len() < FRAME_HEADER_SIZE + len {
+            return Ok(None);
+        }
+
+        let mut payload: Vec<u8> = self
+            .buffer
+            .drain(..FRAME_HEADER_SIZE + len)
+            .skip(FRAME_HEADER_SIZE)
+            .collect();
+
+        let frame = match frame_type {
+            FRAME_FILE => {
+                if payload.len() < 2 {
+                    bail!("short file frame");
+                }
+                let name_len = u16::from_le_bytes([payload[0], payload[1]]) as usize;
+                if payload.len() < 2 + name_len {
+                    bail!("short file frame");
+                }
+                let data = payload.split_off(2 + name_len);
+                let name = String::from_utf8(payload[2..].to_vec())?;
+                TapeRestoreFrame::File { name, data }
+            }
+            FRAME_CHUNK => {
+                if payload.len() < 32 {
+                    bail!("short chunk frame");
+                }
+                let data = payload.split_off(32);
+                let digest = payload[..].try_into().unwrap();
+                TapeRestoreFrame::Chunk { digest, data }
+            }
+            FRAME_END => TapeRestoreFrame::End,
+            other => bail!("unknown frame type {other}"),
+        };
+
+        Ok(Some(frame))
+    }
+
+    /// Returns true if there is no partial frame left.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+struct ChunkTarget {
+    output: usize,
+    offset: u64,
+    size: u64,
+}
+
+/// Writes a streamed snapshot into a target directory.
+///
+/// Files from the snapshot archive are stored as-is. Additionally, the
+/// content of each index is reconstructed (e.g. `drive-scsi0.img` for
+/// `drive-scsi0.img.fidx`, `root.pxar` for `root.pxar.didx`), and blobs are
+/// stored decoded without the `.blob` extension.
+pub struct TapeRestoreWriter {
+    target: PathBuf,
+    crypt_config: Option<Arc<CryptConfig>>,
+    decoder: TapeRestoreStreamDecoder,
+    outputs: Vec<File>,
+    chunk_map: HashMap<[u8; 32], Vec<ChunkTarget>>,
+    restored_chunks: usize,
+    finished: bool,
+}
+
+impl TapeRestoreWriter {
+    pub fn new(target: &Path, crypt_config: Option<Arc<CryptConfig>>) -> Result<Self, Error> {
+        std::fs::create_dir_all(target)
+            .map_err(|err| format_err!("unable to create target {target:?} - {err}"))?;
+
+        Ok(Self {
+            target: target.to_owned(),
+            crypt_config,
+            decoder: TapeRestoreStreamDecoder::new(),
+            outputs: Vec::new(),
+            chunk_map: HashMap::new(),
+            restored_chunks: 0,
+            finished: false,
+        })
+    }
+
+    fn target_path(&self, name: &str) -> Result<PathBuf, Error> {
+        if name.is_empty() || name.starts_with('.') || name.contains('/') {
+            bail!("invalid file name '{name}' in stream");
+        }
+        Ok(self.target.join(name))
+    }
+
+    fn create_output(&mut self, name: &str, size: u64) -> Result<usize, Error> {
+        let path = self.target_path(name)?;
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|err| format_err!("unable to create {path:?} - {err}"))?;
+        file.set_len(size)?;
+        self.outputs.push(file);
+        Ok(self.outputs.len() - 1)
+    }
+
+    fn add_index(&mut self, name: &str, index: &dyn IndexFile) -> Result<(), Error> {
+        let output = self.create_output(name, index.index_bytes())?;
+        for pos in 0..index.index_count() {
+            let info = index.chunk_info(pos).unwrap();
+            self.chunk_map
+                .entry(info.digest)
+                .or_default()
+                .push(ChunkTarget {
+                    output,
+                    offset: info.range.start,
+                    size: info.size(),
+                });
+        }
+        Ok(())
+    }
+
+    fn restore_file(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        let path = self.target_path(name)?;
+        proxmox_sys::fs::replace_file(&path, data, proxmox_sys::fs::CreateOptions::new(), false)?;
+
+        match archive_type(name)? {
+            ArchiveType::FixedIndex => {
+                let index = FixedIndexReader::open(&path)?;
+                self.add_index(name.strip_suffix(".fidx").unwrap(), &index)?;
+            }
+            ArchiveType::DynamicIndex => {
+                let index = DynamicIndexReader::open(&path)?;
+                self.add_index(name.strip_suffix(".didx").unwrap(), &index)?;
+            }
+            ArchiveType::Blob if name == MANIFEST_BLOB_NAME => { /* keep raw manifest only */ }
+            ArchiveType::Blob => {
+                let blob = DataBlob::load_from_reader(&mut &data[..])?;
+                let data = blob.decode(self.crypt_config.as_ref().map(Arc::as_ref), None)?;
+                let path = self.target_path(name.strip_suffix(".blob").unwrap())?;
+                proxmox_sys::fs::replace_file(
+                    path,
+                    &data,
+                    proxmox_sys::fs::CreateOptions::new(),
+                    false,
+                )?;
+            }
+        }
+
+        log::info!("restored {name}");
+
+        Ok(())
+    }
+
+    fn restore_chunk(&mut self, digest: &[u8; 32], data: Vec<u8>) -> Result<(), Error> {
+        let targets = match self.chunk_map.remove(digest) {
+            Some(targets) => targets,
+            None => bail!("unexpected chunk {}", hex::encode(digest)),
+        };
+
+        let blob = DataBlob::from_raw(data)?;
+        let data = blob.decode(self.crypt_config.as_ref().map(Arc::as_ref), Some(digest))?;
+
+        for target in targets {
+            if data.len() as u64 != target.size {
+                bail!(
+                    "chunk {} has wrong size ({} != {})",
+                    hex::encode(digest),
+                    data.len(),
+                    target.size
+                );
+            }
+            self.outputs[target.output].write_all_at(&data, target.offset)?;
+        }
+
+        self.restored_chunks += 1;
+
+        Ok(())
+    }
+
+    fn process_frames(&mut self) -> Result<(), Error> {
+        while let Some(frame) = self.decoder.next_frame()? {
+            if self.finished {
+                bail!("got data after end of stream");
+            }
+            match frame {
+                TapeRestoreFrame::File { name, data } => self.restore_file(&name, &data)?,
+                TapeRestoreFrame::Chunk { digest, data } => self.restore_chunk(&digest, data)?,
+                TapeRestoreFrame::End => self.finished = true,
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the stream was complete and flush all outputs.
+    pub fn finish(self) -> Result<usize, Error> {
+        if !self.finished || !self.decoder.is_empty() {
+            bail!("restore stream ended unexpectedly");
+        }
+        if !self.chunk_map.is_empty() {
+            bail!("missing {} chunks in restore stream", self.chunk_map.len());
+        }
+        for output in self.outputs.iter() {
+            output.sync_all()?;
+        }
+        Ok(self.restored_chunks)
+    }
+}
+
+impl Write for TapeRestoreWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.decoder.push(buf);
+        self.process_frames()
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_tape_restore_stream_decoder() -> Result<(), Error> {
+    let blob = DataBlob::encode(b"chunk data", None, false)?;
+    let digest = [7u8; 32];
+
+    let mut stream = encode_file_frame("test.blob", b"file data")?;
+    stream.extend(encode_chunk_frame(&digest, &blob));
+    stream.extend(encode_end_frame());
+
+    let mut decoder = TapeRestoreStreamDecoder::new();
+
+    // feed the stream in small pieces to test partial frames
+    let mut frames = Vec::new();
+    for piece in stream.chunks(5) {
+        decoder.push(piece);
+        while let Some(frame) = decoder.next_frame()? {
+            frames.push(frame);
+        }
+    }
+    assert!(decoder.is_empty());
+    assert_eq!(frames.len(), 3);
+
+    match &frames[0] {
+        TapeRestoreFrame::File { name, data } => {
+            assert_eq!(name, "test.blob");
+            assert_eq!(data, b"file data");
+        }
+        _ => bail!("expected file frame"),
+    }
+    match &frames[1] {
+        TapeRestoreFrame::Chunk { digest: d, data } => {
+            assert_eq!(d, &digest);
+            assert_eq!(data, blob.raw_data());
+        }
+        _ => bail!("expected chunk frame"),
+    }
+    assert!(matches!(frames[2], TapeRestoreFrame::End));
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! Shell completion scripts for the command line tools.
+//!
+//! The generated scripts call back into the command (`<command> bashcomplete`), so subcommands,
+//! options and dynamic values like datastores or snapshots are always completed according to the
+//! CLI schema of the installed version.
+
+use std::path::Path;
+
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_router::cli::CliCommand;
+use proxmox_schema::api;
+
+#[api]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Shell to generate a completion script for.
+pub enum CompletionShell {
+    /// Bash
+    Bash,
+    /// Z shell
+    Zsh,
+    /// Fish shell
+    Fish,
+}
+
+/// Generate the completion script for `program`.
+pub fn completion_script(program: &str, shell: CompletionShell) -> String {
+    match shell {
+        CompletionShell::Bash => format!(
+            "# {program} bash completion\n\
+            \n\
+            # do not split words at ':', as used in repositories and snapshot paths\n\
+            COMP_WORDBREAKS=${{COMP_WORDBREAKS//:}}\n\
+            \n\
+            complete -C '{program} bashcomplete' {program}\n"
+        ),
+        // same as the functions shipped in zsh-completions/, registered with compdef so the
+        // script can also be sourced
+        CompletionShell::Zsh => format!(
+            "#compdef _{program}() {program}\n\
+            \n\
+            function _{program}() {{\n    \
+                local cwords line point cmd curr prev\n    \
+                cwords=${{#words[@]}}\n    \
+                line=$words\n    \
+                point=${{#line}}\n    \
+                cmd=${{words[1]}}\n    \
+                curr=${{words[cwords]}}\n    \
+                prev=${{words[cwords-1]}}\n    \
+                compadd -- $(COMP_CWORD=\"$cwords\" COMP_LINE=\"$line\" COMP_POINT=\"$point\" \\\n        \
+                    {program} bashcomplete \"$cmd\" \"$curr\" \"$prev\")\n\
+            }}\n\
+            \n\
+            compdef _{program} {program}\n"
+        ),
+        CompletionShell::Fish => {
+            let function = format!("__{}_complete", program.replace('-', "_"));
+            format!(
+                "# {program} fish completion\n\
+                \n\
+                function {function}\n    \
+                    set -l line (commandline -cp)\n    \
+                    COMP_LINE=$line COMP_POINT=(string length -- \"$line\") {program} bashcomplete\n\
+                end\n\
+                \n\
+                complete -c {program} -f -a '({function})'\n"
+            )
+        }
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            shell: {
+                type: CompletionShell,
+            },
+        },
+    },
+)]
+/// Print a shell completion script for this command.
+pub fn completions(shell: CompletionShell) -> Result<(), Error> {
+    let program = std::env::args()
+        .next()
+        .as_deref()
+        .and_then(|arg0| Path::new(arg0).file_name()?.to_str().map(String::from))
+        .ok_or_else(|| format_err!("unable to get program name"))?;
+
+    print!("{}", completion_script(&program, shell));
+
+    Ok(())
+}
+
+/// The `completions` command, to be added to the command map of a CLI tool.
+pub fn completions_cmd_def() -> CliCommand {
+    CliCommand::new(&API_METHOD_COMPLETIONS).arg_param(&["shell"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zsh_completion_script() {
+        // the generated script matches the packaged completion function
+        let shipped = include_str!("../../../zsh-completions/_proxmox-backup-client");
+        assert_eq!(
+            completion_script("proxmox-backup-client", CompletionShell::Zsh),
+            format!("{shipped}\ncompdef _proxmox-backup-client proxmox-backup-client\n"),
+        );
+    }
+
+    #[test]
+    fn test_bash_completion_script() {
+        let script = completion_script("pxar", CompletionShell::Bash);
+        assert!(script.contains("\ncomplete -C 'pxar bashcomplete' pxar\n"));
+        assert!(script.contains("COMP_WORDBREAKS=${COMP_WORDBREAKS//:}\n"));
+    }
+
+    #[test]
+    fn test_fish_completion_script() {
+        let script = completion_script("proxmox-tape", CompletionShell::Fish);
+        assert!(script.contains("\nfunction __proxmox_tape_complete\n"));
+        assert!(script.contains(" proxmox-tape bashcomplete\nend\n"));
+        assert!(script.ends_with("complete -c proxmox-tape -f -a '(__proxmox_tape_complete)'\n"));
+    }
+}
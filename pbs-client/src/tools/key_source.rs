@@ -1,15 +1,18 @@
 use std::io::{IsTerminal, Read};
 use std::os::unix::io::{FromRawFd, RawFd};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, format_err, Error};
+use anyhow::{bail, format_err, Context, Error};
 use serde_json::Value;
 
 use proxmox_schema::*;
 use proxmox_sys::fs::file_get_contents;
 use proxmox_sys::linux::tty;
 
-use pbs_api_types::CryptMode;
+use pbs_api_types::{CryptMode, Fingerprint};
+use pbs_key_config::KeyConfig;
+
+use super::secret_store::{find_secret, ClientSecret, SecretStore};
 
 pub const DEFAULT_ENCRYPTION_KEY_FILE_NAME: &str = "encryption-key.json";
 pub const DEFAULT_MASTER_PUBKEY_FILE_NAME: &str = "master-public.pem";
@@ -37,6 +40,7 @@ pub enum KeySource {
     DefaultKey,
     Fd,
     Path(String),
+    SecretStore(SecretStore),
 }
 
 pub fn format_key_source(source: &KeySource, key_type: &str) -> String {
@@ -44,6 +48,7 @@ pub fn format_key_source(source: &KeySource, key_type: &str) -> String {
         KeySource::DefaultKey => format!("Using default {} key..", key_type),
         KeySource::Fd => format!("Using {} key from file descriptor..", key_type),
         KeySource::Path(path) => format!("Using {} key from '{}'..", key_type, path),
+        KeySource::SecretStore(store) => format!("Using {} key from {}..", key_type, store),
     }
 }
 
@@ -289,9 +294,20 @@ pub fn place_default_encryption_key() -> Result<PathBuf, Error> {
 
 #[cfg(not(test))]
 pub(crate) fn read_optional_default_encryption_key() -> Result<Option<KeyWithSource>, Error> {
-    find_default_encryption_key()?
-        .map(|path| file_get_contents(path).map(KeyWithSource::from_default))
-        .transpose()
+    if let Some(path) = super::profile::active_profile().and_then(|p| p.keyfile.as_ref()) {
+        return file_get_contents(path)
+            .map(|key| Some(KeyWithSource::from_path(path.clone(), key)));
+    }
+
+    if let Some(path) = find_default_encryption_key()? {
+        return file_get_contents(path).map(|key| Some(KeyWithSource::from_default(key)));
+    }
+
+    let secret = find_secret(&ClientSecret::EncryptionKey)?;
+    Ok(secret.map(|(store, key)| KeyWithSource {
+        source: KeySource::SecretStore(store),
+        key,
+    }))
 }
 
 #[cfg(not(test))]
@@ -344,8 +360,6 @@ pub(crate) unsafe fn set_test_default_master_pubkey(value: Result<Option<Vec<u8>
 }
 
 pub fn get_encryption_key_password() -> Result<Vec<u8>, Error> {
-    // fixme: implement other input methods
-
     if let Some(password) = super::get_secret_from_env("PBS_ENCRYPTION_PASSWORD")? {
         return Ok(password.as_bytes().to_vec());
     }
@@ -358,6 +372,50 @@ pub fn get_encryption_key_password() -> Result<Vec<u8>, Error> {
     bail!("no password input mechanism available");
 }
 
+/// The secret the passphrase of `key_config` is stored as.
+///
+/// Passphrases are stored per key fingerprint, older key files without fingerprint are identified
+/// by their encrypted key data instead.
+pub fn passphrase_secret(key_config: &KeyConfig) -> ClientSecret<'static> {
+    let key_id = match key_config.fingerprint {
+        Some(ref fingerprint) => *fingerprint.bytes(),
+        None => openssl::sha::sha256(&key_config.data),
+    };
+    ClientSecret::EncryptionKeyPassphrase { key_id }
+}
+
+/// Decrypt an encryption key, with the passphrase stored for it if there is one.
+///
+/// A stored passphrase which does not work, for example because the passphrase of the key was
+/// changed elsewhere, is skipped with a warning, and the passphrase is queried as usual.
+pub fn decrypt_key_config(key_config: &KeyConfig) -> Result<([u8; 32], i64, Fingerprint), Error> {
+    if key_config.kdf.is_some() && super::get_secret_from_env("PBS_ENCRYPTION_PASSWORD")?.is_none()
+    {
+        match find_secret(&passphrase_secret(key_config)) {
+            Ok(Some((store, passphrase))) => match key_config.decrypt(&|| Ok(passphrase.clone())) {
+                Ok(result) => return Ok(result),
+                Err(err) => log::warn!("passphrase stored in {store} does not work - {err}"),
+            },
+            Ok(None) => (),
+            Err(err) => log::warn!("unable to load stored passphrase - {err}"),
+        }
+    }
+
+    key_config.decrypt(&get_encryption_key_password)
+}
+
+/// Decrypt an encryption key from raw key data, see [decrypt_key_config].
+pub fn decrypt_key(keydata: &[u8]) -> Result<([u8; 32], i64, Fingerprint), Error> {
+    let key_config: KeyConfig = serde_json::from_slice(keydata)?;
+    decrypt_key_config(&key_config)
+}
+
+/// Load an encryption key from `path` and decrypt it, see [decrypt_key_config].
+pub fn load_and_decrypt_key(path: &Path) -> Result<([u8; 32], i64, Fingerprint), Error> {
+    decrypt_key(&file_get_contents(path)?)
+        .with_context(|| format!("failed to load decryption key from {:?}", path))
+}
+
 #[cfg(test)]
 fn create_testdir(name: &str) -> Result<String, Error> {
     // FIXME:
@@ -11,18 +11,25 @@ use serde_json::{json, Value};
 use xdg::BaseDirectories;
 
 use proxmox_http::uri::json_object_to_query;
+use proxmox_human_byte::HumanByte;
 use proxmox_router::cli::{complete_file_name, shellword_split};
 use proxmox_schema::*;
 use proxmox_sys::fs::file_get_json;
 
 use pbs_api_types::{Authid, BackupNamespace, RateLimitConfig, UserWithTokens, BACKUP_REPO_URL};
 
-use crate::{BackupRepository, HttpClient, HttpClientOptions};
+use crate::{BackupRepository, HttpClient, HttpClientOptions, RetryPolicy};
 
+pub mod completions;
 pub mod key_source;
+pub mod profile;
+pub mod secret_store;
 
 const ENV_VAR_PBS_FINGERPRINT: &str = "PBS_FINGERPRINT";
 const ENV_VAR_PBS_PASSWORD: &str = "PBS_PASSWORD";
+const ENV_VAR_PBS_RATE_LIMIT: &str = "PBS_RATE_LIMIT";
+const ENV_VAR_PBS_BURST_LIMIT: &str = "PBS_BURST_LIMIT";
+const ENV_VAR_PBS_RETRY: &str = "PBS_RETRY";
 
 pub const REPO_URL_SCHEMA: Schema = StringSchema::new("Repository URL.")
     .format(&BACKUP_REPO_URL)
@@ -114,7 +121,16 @@ pub fn get_secret_from_env(base_name: &str) -> Result<Option<String>, Error> {
 }
 
 pub fn get_default_repository() -> Option<String> {
-    std::env::var("PBS_REPOSITORY").ok()
+    std::env::var("PBS_REPOSITORY")
+        .ok()
+        .or_else(|| profile::active_profile()?.repository.clone())
+}
+
+/// Get the fingerprint of the server certificate from the environment or the active profile.
+fn get_fingerprint() -> Option<String> {
+    std::env::var(ENV_VAR_PBS_FINGERPRINT)
+        .ok()
+        .or_else(|| profile::active_profile()?.fingerprint.clone())
 }
 
 pub fn extract_repository_from_value(param: &Value) -> Result<BackupRepository, Error> {
@@ -129,6 +145,20 @@ pub fn extract_repository_from_value(param: &Value) -> Result<BackupRepository,
     Ok(repo)
 }
 
+/// The namespace to use if none is given explicitly.
+///
+/// This is the namespace of the repository, if it includes one, or else the one of the active
+/// profile, and the root namespace as last resort.
+pub fn default_namespace(repo: Option<&BackupRepository>) -> BackupNamespace {
+    if let Some(ns) = repo.and_then(|repo| repo.ns()) {
+        return ns.clone();
+    }
+    match profile::active_profile() {
+        Some(profile::ClientProfile { ns: Some(ns), .. }) => ns.clone(),
+        _ => BackupNamespace::root(),
+    }
+}
+
 pub fn extract_repository_from_map(param: &HashMap<String, String>) -> Option<BackupRepository> {
     param
         .get("repository")
@@ -137,6 +167,38 @@ pub fn extract_repository_from_map(param: &HashMap<String, String>) -> Option<Ba
         .and_then(|repo_url| repo_url.parse::<BackupRepository>().ok())
 }
 
+/// Extract the connection rate limit from the `rate` and `burst` parameters.
+///
+/// Falls back to the `PBS_RATE_LIMIT` and `PBS_BURST_LIMIT` environment
+/// variables, and then to the active profile, if a parameter is not set. The
+/// limit applies to both directions.
+pub fn extract_rate_limit_from_value(param: &Value) -> Result<RateLimitConfig, Error> {
+    let profile = profile::active_profile();
+    let lookup = |name: &str, env_name: &str| -> Result<Option<HumanByte>, Error> {
+        let (value, source) = match param[name].as_str() {
+            Some(value) => (value.to_string(), name),
+            None => match std::env::var(env_name) {
+                Ok(value) => (value, env_name),
+                Err(_) => {
+                    return Ok(profile.and_then(|profile| match name {
+                        "rate" => profile.rate,
+                        _ => profile.burst,
+                    }))
+                }
+            },
+        };
+        value
+            .parse::<HumanByte>()
+            .map(Some)
+            .map_err(|err| format_err!("invalid {source} '{value}' - {err}"))
+    };
+
+    let rate = lookup("rate", ENV_VAR_PBS_RATE_LIMIT)?;
+    let burst = lookup("burst", ENV_VAR_PBS_BURST_LIMIT)?;
+
+    Ok(RateLimitConfig::with_same_inout(rate, burst))
+}
+
 pub fn connect(repo: &BackupRepository) -> Result<HttpClient, Error> {
     let rate_limit = RateLimitConfig::default(); // unlimited
     connect_do(repo.host(), repo.port(), repo.auth_id(), rate_limit)
@@ -151,24 +213,78 @@ pub fn connect_rate_limited(
         .map_err(|err| format_err!("error building client for repository {} - {}", repo, err))
 }
 
+/// Get the retry policy for transient errors from the `PBS_RETRY` environment variable, as
+/// property string (for example `attempts=5,backoff=2`).
+pub fn get_retry_policy() -> Result<RetryPolicy, Error> {
+    match std::env::var(ENV_VAR_PBS_RETRY) {
+        Ok(value) => {
+            let policy = RetryPolicy::API_SCHEMA
+                .parse_property_string(&value)
+                .map_err(|err| format_err!("invalid {ENV_VAR_PBS_RETRY} '{value}' - {err}"))?;
+            Ok(serde_json::from_value(policy)?)
+        }
+        Err(_) => Ok(RetryPolicy::default()),
+    }
+}
+
 fn connect_do(
     server: &str,
     port: u16,
     auth_id: &Authid,
     rate_limit: RateLimitConfig,
 ) -> Result<HttpClient, Error> {
-    let fingerprint = std::env::var(ENV_VAR_PBS_FINGERPRINT).ok();
+    let fingerprint = get_fingerprint();
 
-    let password = get_secret_from_env(ENV_VAR_PBS_PASSWORD)?;
-    let options = HttpClientOptions::new_interactive(password, fingerprint).rate_limit(rate_limit);
+    let password = lookup_password(server, port, auth_id)?;
+    let options = HttpClientOptions::new_interactive(password, fingerprint)
+        .rate_limit(rate_limit)
+        .retry_policy(get_retry_policy()?);
 
     HttpClient::new(server, port, auth_id, options)
 }
 
+/// Get the password for `auth_id` on a server from the environment (see
+/// [get_secret_from_env]), or from a [secret store](secret_store).
+pub fn lookup_password(server: &str, port: u16, auth_id: &Authid) -> Result<Option<String>, Error> {
+    if let Some(password) = get_secret_from_env(ENV_VAR_PBS_PASSWORD)? {
+        return Ok(Some(password));
+    }
+
+    let secret = secret_store::ClientSecret::Password {
+        auth_id,
+        server,
+        port,
+    };
+    Ok(stored_password(secret_store::find_secret(&secret)))
+}
+
+/// A password found in a secret store, `None` to fall back to the password prompt.
+///
+/// A stored password which cannot be loaded, for example because the TPM refuses to unseal it
+/// after a firmware update, must not make every connection fail, so it is skipped with a warning.
+fn stored_password(
+    found: Result<Option<(secret_store::SecretStore, Vec<u8>)>, Error>,
+) -> Option<String> {
+    match found {
+        Ok(Some((store, password))) => match String::from_utf8(password) {
+            Ok(password) => Some(password),
+            Err(_) => {
+                log::warn!("password stored in {store} is not valid UTF-8, ignoring it");
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(err) => {
+            log::warn!("unable to load stored password, ignoring it - {err}");
+            None
+        }
+    }
+}
+
 /// like get, but simply ignore errors and return Null instead
 pub async fn try_get(repo: &BackupRepository, url: &str) -> Value {
-    let fingerprint = std::env::var(ENV_VAR_PBS_FINGERPRINT).ok();
-    let password = get_secret_from_env(ENV_VAR_PBS_PASSWORD).unwrap_or(None);
+    let fingerprint = get_fingerprint();
+    let password = lookup_password(repo.host(), repo.port(), repo.auth_id()).unwrap_or(None);
 
     // ticket cache, but no questions asked
     let options = HttpClientOptions::new_interactive(password, fingerprint).interactive(false);
@@ -298,8 +414,8 @@ pub async fn complete_server_file_name_do(param: &HashMap<String, String>) -> Ve
             _ => return result,
         },
         _ => {
-            // If no namespace flag is provided, we assume the root namespace
-            pbs_api_types::BackupNamespace::root()
+            // If no namespace flag is provided, use the one of the repository or the root namespace
+            repo.ns().cloned().unwrap_or_default()
         }
     };
 
@@ -526,3 +642,27 @@ pub fn place_xdg_file(
         .and_then(|base| base.place_config_file(file_name).map_err(Error::from))
         .with_context(|| format!("failed to place {} in xdg home", description))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::secret_store::SecretStore;
+    use super::*;
+
+    #[test]
+    fn test_stored_password() {
+        assert_eq!(stored_password(Ok(None)), None);
+        assert_eq!(
+            stored_password(Ok(Some((SecretStore::Tpm, b"secret".to_vec())))),
+            Some("secret".to_string()),
+        );
+        assert_eq!(
+            stored_password(Ok(Some((SecretStore::Keyring, vec![0xff, 0xfe])))),
+            None,
+        );
+        // e.g. the TPM refused to unseal the password, the user gets asked instead
+        assert_eq!(
+            stored_password(Err(format_err!("unsealing secret with the TPM failed"))),
+            None,
+        );
+    }
+}
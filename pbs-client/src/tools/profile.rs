@@ -0,0 +1,268 @@
+//! Named client profiles
+//!
+//! Profiles are stored in `client.cfg` in the client's configuration directory
+//! (usually `~/.config/proxmox-backup/client.cfg`) and provide defaults for the
+//! repository, the server fingerprint, the encryption key file, the rate limit
+//! and the namespace:
+//!
+//! ```text
+//! profile: default
+//!     repository backup@pbs@backup-server:store1
+//!     fingerprint 64:d3:...:04:5f
+//!     keyfile /root/backup-key.json
+//!     rate 10MiB
+//!     ns host1
+//! ```
+//!
+//! The profile is selected by the `PBS_PROFILE` environment variable, the
+//! profile named `default` is used if it is not set and no repository was
+//! given explicitly, as its fingerprint, key file and namespace most likely do
+//! not fit another repository. Explicit parameters and environment variables
+//! take precedence over the values of the profile.
+
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::{format_err, Error};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use proxmox_human_byte::HumanByte;
+use proxmox_schema::*;
+use proxmox_section_config::{SectionConfig, SectionConfigPlugin};
+
+use pbs_api_types::{BackupNamespace, CERT_FINGERPRINT_SHA256_SCHEMA, PROXMOX_SAFE_ID_FORMAT};
+
+use super::key_source::KEYFILE_SCHEMA;
+use super::REPO_URL_SCHEMA;
+
+pub const ENV_VAR_PBS_PROFILE: &str = "PBS_PROFILE";
+
+const PROFILE_CFG_FILE_NAME: &str = "client.cfg";
+const DEFAULT_PROFILE_NAME: &str = "default";
+
+pub const PROFILE_NAME_SCHEMA: Schema = StringSchema::new("Client profile name.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(2)
+    .max_length(32)
+    .schema();
+
+#[api(
+    properties: {
+        name: {
+            schema: PROFILE_NAME_SCHEMA,
+        },
+        repository: {
+            schema: REPO_URL_SCHEMA,
+            optional: true,
+        },
+        fingerprint: {
+            schema: CERT_FINGERPRINT_SHA256_SCHEMA,
+            optional: true,
+        },
+        keyfile: {
+            schema: KEYFILE_SCHEMA,
+            optional: true,
+        },
+        rate: {
+            type: HumanByte,
+            optional: true,
+        },
+        burst: {
+            type: HumanByte,
+            optional: true,
+        },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Client profile
+pub struct ClientProfile {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyfile: Option<String>,
+    /// Rate limit (bytes per second) for both directions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<HumanByte>,
+    /// Burst size (bytes) for both directions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<HumanByte>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+}
+
+lazy_static! {
+    static ref CONFIG: SectionConfig = init();
+}
+
+fn init() -> SectionConfig {
+    let obj_schema = match ClientProfile::API_SCHEMA {
+        Schema::Object(ref obj_schema) => obj_schema,
+        _ => unreachable!(),
+    };
+
+    let plugin =
+        SectionConfigPlugin::new("profile".to_string(), Some("name".to_string()), obj_schema);
+    let mut config = SectionConfig::new(&PROFILE_NAME_SCHEMA);
+    config.register_plugin(plugin);
+
+    config
+}
+
+/// Names of all profiles in the configuration file.
+pub fn profile_names() -> Result<Vec<String>, Error> {
+    let path = match super::find_xdg_file(PROFILE_CFG_FILE_NAME, "client profiles")? {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+    let content = proxmox_sys::fs::file_read_string(&path)?;
+    let data = CONFIG.parse(&path.to_string_lossy(), &content)?;
+    Ok(data.sections.keys().cloned().collect())
+}
+
+fn load_profile(explicit_repository: bool) -> Result<Option<ClientProfile>, Error> {
+    let requested = std::env::var(ENV_VAR_PBS_PROFILE).ok();
+
+    let path = super::find_xdg_file(PROFILE_CFG_FILE_NAME, "client profiles")?;
+    let content = match &path {
+        Some(path) => Some(proxmox_sys::fs::file_read_string(path)?),
+        None => None,
+    };
+    let config = path.as_ref().zip(content.as_deref());
+
+    select_profile(config, requested.as_deref(), explicit_repository)
+}
+
+// looks up the `requested` profile, or the default one, in the configuration file's content
+fn select_profile(
+    config: Option<(&PathBuf, &str)>,
+    requested: Option<&str>,
+    explicit_repository: bool,
+) -> Result<Option<ClientProfile>, Error> {
+    if requested.is_none() && explicit_repository {
+        return Ok(None);
+    }
+
+    let (path, content) = match config {
+        Some(config) => config,
+        None => match requested {
+            Some(name) => return Err(format_err!("no such client profile '{name}'")),
+            None => return Ok(None),
+        },
+    };
+
+    let data = CONFIG.parse(&path.to_string_lossy(), content)?;
+
+    let name = requested.unwrap_or(DEFAULT_PROFILE_NAME);
+    if !data.sections.contains_key(name) {
+        if requested.is_some() {
+            return Err(format_err!("no such client profile '{name}' in {path:?}"));
+        }
+        return Ok(None);
+    }
+
+    data.lookup("profile", name)
+        .map(Some)
+        .map_err(|err| format_err!("unable to load client profile '{name}' - {err}"))
+}
+
+fn repository_from_env() -> bool {
+    std::env::var_os("PBS_REPOSITORY").is_some()
+}
+
+static ACTIVE_PROFILE: OnceLock<Option<ClientProfile>> = OnceLock::new();
+
+/// Load the active profile, so that a broken configuration file or an unknown profile name can
+/// be reported as error instead of being ignored.
+///
+/// The default profile is not used if `explicit_repository` is set, i.e. if the repository was
+/// passed on the command line.
+pub fn init_active_profile(explicit_repository: bool) -> Result<(), Error> {
+    if ACTIVE_PROFILE.get().is_none() {
+        let explicit_repository = explicit_repository || repository_from_env();
+        let _ = ACTIVE_PROFILE.set(load_profile(explicit_repository)?);
+    }
+    Ok(())
+}
+
+/// The active profile, if any.
+///
+/// If the profile was not loaded with [init_active_profile] before, errors are only logged.
+pub fn active_profile() -> Option<&'static ClientProfile> {
+    ACTIVE_PROFILE
+        .get_or_init(|| {
+            load_profile(repository_from_env()).unwrap_or_else(|err| {
+                log::warn!("ignoring client profile - {err}");
+                None
+            })
+        })
+        .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG_DATA: &str = "\
+profile: default
+\trepository backup@pbs@default-server:store1
+\tns host1
+
+profile: offsite
+\trepository backup@pbs@offsite-server:store2
+";
+
+    fn select(
+        requested: Option<&str>,
+        explicit_repository: bool,
+    ) -> Result<Option<ClientProfile>, Error> {
+        let path = PathBuf::from("client.cfg");
+        select_profile(Some((&path, CONFIG_DATA)), requested, explicit_repository)
+    }
+
+    #[test]
+    fn test_select_profile() {
+        let profile = select(None, false).unwrap().unwrap();
+        assert_eq!(profile.name, "default");
+        assert_eq!(profile.ns, Some("host1".parse().unwrap()));
+
+        // a requested profile wins over the default one
+        let profile = select(Some("offsite"), false).unwrap().unwrap();
+        assert_eq!(
+            profile.repository.as_deref(),
+            Some("backup@pbs@offsite-server:store2")
+        );
+        assert_eq!(profile.ns, None);
+
+        // an unknown profile must not silently fall back to the default one
+        assert!(select(Some("missing"), false).is_err());
+
+        // no configuration at all is fine, unless a profile was requested
+        assert!(select_profile(None, None, false).unwrap().is_none());
+        assert!(select_profile(None, Some("offsite"), false).is_err());
+
+        // without a default profile, nothing is used
+        let path = PathBuf::from("client.cfg");
+        let data = "profile: offsite\n\trepository backup@pbs@offsite-server:store2\n";
+        assert!(select_profile(Some((&path, data)), None, false)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_select_profile_explicit_repository() {
+        // the default profile belongs to another server than the explicit repository
+        assert!(select(None, true).unwrap().is_none());
+
+        // a requested profile is still used, the explicit repository overrides its own one
+        let profile = select(Some("offsite"), true).unwrap().unwrap();
+        assert_eq!(profile.name, "offsite");
+    }
+}
@@ -0,0 +1,559 @@
+//! Storage of client secrets outside of plaintext files
+//!
+//! The encryption key, its passphrase and the password of a user on a server
+//! can be stored in the persistent kernel keyring of the local user, in the
+//! desktop's secret service (via `secret-tool` of libsecret), or sealed with
+//! the TPM of the host via `systemd-creds`. Sealed secrets are stored as
+//! credential files in the client's configuration directory, and can only be
+//! unsealed on the same host. Sealing can additionally be bound to a set of
+//! TPM PCRs, so that the secret is only released while the host boots with the
+//! same firmware, boot loader or Secure Boot state.
+//!
+//! Note that the persistent keyring does not survive a reboot, and expires if
+//! unused for a while (see `/proc/sys/kernel/keys/persistent_keyring_expiry`).
+
+use std::ffi::CString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, ApiStringFormat, Schema, StringSchema};
+
+use pbs_api_types::Authid;
+
+// from <linux/keyctl.h>
+const KEY_SPEC_USER_KEYRING: libc::c_long = -4;
+const KEYCTL_UNLINK: libc::c_long = 9;
+const KEYCTL_SEARCH: libc::c_long = 10;
+const KEYCTL_READ: libc::c_long = 11;
+const KEYCTL_GET_PERSISTENT: libc::c_long = 22;
+
+const KEY_TYPE_USER: &[u8] = b"user\0";
+const KEY_DESCRIPTION_PREFIX: &str = "proxmox-backup";
+const SYSTEMD_CREDS: &str = "systemd-creds";
+const SECRET_TOOL: &str = "secret-tool";
+const SECRET_SERVICE_APPLICATION: &str = "proxmox-backup";
+
+/// Highest PCR index of a TPM 2.0 (24 PCRs per bank)
+const TPM2_MAX_PCR: u8 = 23;
+
+pub const TPM2_PCRS_SCHEMA: Schema = StringSchema::new(
+    "TPM PCRs to bind sealed secrets to, separated by '+' (e.g. '0+7'). \
+    An empty string disables the PCR policy.",
+)
+.format(&ApiStringFormat::VerifyFn(verify_tpm2_pcrs))
+.max_length(64)
+.schema();
+
+fn verify_tpm2_pcrs(pcrs: &str) -> Result<(), Error> {
+    parse_tpm2_pcrs(pcrs).map(|_| ())
+}
+
+/// Parse a '+' (or ',') separated list of PCR indices.
+pub fn parse_tpm2_pcrs(pcrs: &str) -> Result<Vec<u8>, Error> {
+    let mut list = Vec::new();
+    for pcr in pcrs.split(['+', ',']).filter(|pcr| !pcr.is_empty()) {
+        let index: u8 = pcr
+            .trim()
+            .parse()
+            .map_err(|_| format_err!("invalid PCR index '{pcr}'"))?;
+        if index > TPM2_MAX_PCR {
+            bail!("PCR index {index} out of range (0-{TPM2_MAX_PCR})");
+        }
+        if !list.contains(&index) {
+            list.push(index);
+        }
+    }
+    list.sort_unstable();
+    Ok(list)
+}
+
+// the format systemd-creds expects, and that is parsed by `parse_tpm2_pcrs`
+fn format_tpm2_pcrs(pcrs: &[u8]) -> String {
+    let pcrs: Vec<String> = pcrs.iter().map(|pcr| pcr.to_string()).collect();
+    pcrs.join("+")
+}
+
+#[api]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Where a client secret is stored.
+pub enum SecretStore {
+    /// Persistent kernel keyring of the user
+    Keyring,
+    /// Secret service of the desktop session (libsecret)
+    SecretService,
+    /// Sealed with the host's TPM
+    Tpm,
+}
+
+impl SecretStore {
+    /// All stores, in the order they are searched.
+    pub const ALL: [SecretStore; 3] = [
+        SecretStore::Keyring,
+        SecretStore::SecretService,
+        SecretStore::Tpm,
+    ];
+}
+
+impl std::fmt::Display for SecretStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretStore::Keyring => f.write_str("kernel keyring"),
+            SecretStore::SecretService => f.write_str("secret service"),
+            SecretStore::Tpm => f.write_str("TPM"),
+        }
+    }
+}
+
+/// A secret the client can store.
+pub enum ClientSecret<'a> {
+    /// The default encryption key
+    EncryptionKey,
+    /// The passphrase of an encryption key, identified by its fingerprint
+    EncryptionKeyPassphrase { key_id: [u8; 32] },
+    /// The password of a user on a server
+    Password {
+        auth_id: &'a Authid,
+        server: &'a str,
+        port: u16,
+    },
+}
+
+impl ClientSecret<'_> {
+    fn name(&self) -> String {
+        match self {
+            ClientSecret::EncryptionKey => "encryption-key".to_string(),
+            ClientSecret::EncryptionKeyPassphrase { key_id } => {
+                format!("encryption-key-passphrase-{}", hex::encode(&key_id[..8]))
+            }
+            ClientSecret::Password {
+                auth_id,
+                server,
+                port,
+            } => {
+                // also used as file name, so do not include the ID itself
+                let id = openssl::sha::sha256(format!("{auth_id}@{server}:{port}").as_bytes());
+                format!("password-{}", hex::encode(&id[..8]))
+            }
+        }
+    }
+}
+
+fn keyring() -> Result<libc::c_long, Error> {
+    // -1 means the current user, the persistent keyring gets linked into the user keyring
+    let keyring = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            KEYCTL_GET_PERSISTENT,
+            -1 as libc::c_long,
+            KEY_SPEC_USER_KEYRING,
+        )
+    };
+    if keyring < 0 {
+        bail!(
+            "unable to get persistent keyring - {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(keyring)
+}
+
+fn key_description(secret: &ClientSecret) -> Result<CString, Error> {
+    Ok(CString::new(format!(
+        "{KEY_DESCRIPTION_PREFIX}:{}",
+        secret.name()
+    ))?)
+}
+
+fn keyring_search(keyring: libc::c_long, description: &CString) -> Option<libc::c_long> {
+    let key = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            KEYCTL_SEARCH,
+            keyring,
+            KEY_TYPE_USER.as_ptr(),
+            description.as_ptr(),
+            0 as libc::c_long,
+        )
+    };
+    (key >= 0).then_some(key)
+}
+
+fn keyring_store(secret: &ClientSecret, data: &[u8]) -> Result<(), Error> {
+    let keyring = keyring()?;
+    let description = key_description(secret)?;
+    // replaces the payload of an existing key with the same description
+    let key = unsafe {
+        libc::syscall(
+            libc::SYS_add_key,
+            KEY_TYPE_USER.as_ptr(),
+            description.as_ptr(),
+            data.as_ptr(),
+            data.len(),
+            keyring,
+        )
+    };
+    if key < 0 {
+        bail!(
+            "unable to add key to keyring - {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+fn keyring_load(secret: &ClientSecret) -> Result<Option<Vec<u8>>, Error> {
+    let keyring = keyring()?;
+    let key = match keyring_search(keyring, &key_description(secret)?) {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+
+    let mut data = vec![0u8; 4096];
+    loop {
+        let len = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                KEYCTL_READ,
+                key,
+                data.as_mut_ptr(),
+                data.len(),
+            )
+        };
+        if len < 0 {
+            bail!(
+                "unable to read key from keyring - {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        let len = len as usize;
+        if len <= data.len() {
+            data.truncate(len);
+            return Ok(Some(data));
+        }
+        // the payload did not fit, retry with its full size
+        data.resize(len, 0);
+    }
+}
+
+fn keyring_remove(secret: &ClientSecret) -> Result<bool, Error> {
+    let keyring = keyring()?;
+    let key = match keyring_search(keyring, &key_description(secret)?) {
+        Some(key) => key,
+        None => return Ok(false),
+    };
+    let res = unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_UNLINK, key, keyring) };
+    if res < 0 {
+        bail!(
+            "unable to remove key from keyring - {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(true)
+}
+
+fn credential_path(secret: &ClientSecret) -> Result<PathBuf, Error> {
+    super::place_xdg_file(format!("{}.cred", secret.name()), "sealed credential")
+}
+
+// systemd-creds cannot tell which PCRs a credential is bound to, so remember them
+fn pcrs_file_name(secret: &ClientSecret) -> String {
+    format!("{}.pcrs", secret.name())
+}
+
+fn tpm_store(secret: &ClientSecret, data: &[u8], pcrs: Option<&[u8]>) -> Result<(), Error> {
+    let path = credential_path(secret)?;
+    let mut command = Command::new(SYSTEMD_CREDS);
+    command.arg("encrypt").arg("--with-key=tpm2");
+    if let Some(pcrs) = pcrs {
+        // without this option systemd-creds binds to PCR 7 (Secure Boot state)
+        command.arg(format!("--tpm2-pcrs={}", format_tpm2_pcrs(pcrs)));
+    }
+    let mut child = command
+        .arg(format!("--name={}", secret.name()))
+        .arg("-")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format_err!("unable to execute {SYSTEMD_CREDS} - {err}"))?;
+
+    // dropping stdin closes it, so that the command sees the end of the input
+    child.stdin.take().unwrap().write_all(data)?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "sealing secret with the TPM failed - {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    match pcrs {
+        Some(pcrs) => {
+            let path = super::place_xdg_file(pcrs_file_name(secret), "PCR binding")?;
+            std::fs::write(&path, format_tpm2_pcrs(pcrs))
+                .map_err(|err| format_err!("unable to write {path:?} - {err}"))?;
+        }
+        None => tpm_remove_pcrs(secret)?,
+    }
+    Ok(())
+}
+
+fn tpm_remove_pcrs(secret: &ClientSecret) -> Result<(), Error> {
+    if let Some(path) = super::find_xdg_file(pcrs_file_name(secret), "PCR binding")? {
+        std::fs::remove_file(&path)
+            .map_err(|err| format_err!("unable to remove {path:?} - {err}"))?;
+    }
+    Ok(())
+}
+
+fn tpm_load(secret: &ClientSecret) -> Result<Option<Vec<u8>>, Error> {
+    let path = match super::find_xdg_file(format!("{}.cred", secret.name()), "sealed credential")? {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let output = Command::new(SYSTEMD_CREDS)
+        .arg("decrypt")
+        .arg(format!("--name={}", secret.name()))
+        .arg(&path)
+        .arg("-")
+        .output()
+        .map_err(|err| format_err!("unable to execute {SYSTEMD_CREDS} - {err}"))?;
+    if !output.status.success() {
+        bail!(
+            "unsealing secret {path:?} with the TPM failed (the PCR state might have \
+            changed, e.g. by a firmware or boot loader update, in that case enroll the \
+            secret again) - {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(Some(output.stdout))
+}
+
+fn tpm_remove(secret: &ClientSecret) -> Result<bool, Error> {
+    match super::find_xdg_file(format!("{}.cred", secret.name()), "sealed credential")? {
+        Some(path) => {
+            std::fs::remove_file(&path)
+                .map_err(|err| format_err!("unable to remove {path:?} - {err}"))?;
+            tpm_remove_pcrs(secret)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+fn secret_tool(action: &str, secret: &ClientSecret) -> Command {
+    let mut command = Command::new(SECRET_TOOL);
+    command.arg(action);
+    if action == "store" {
+        command.arg(format!("--label=Proxmox Backup {}", secret.name()));
+    }
+    command
+        .arg("application")
+        .arg(SECRET_SERVICE_APPLICATION)
+        .arg("name")
+        .arg(secret.name());
+    command
+}
+
+fn secret_service_store(secret: &ClientSecret, data: &[u8]) -> Result<(), Error> {
+    let mut child = secret_tool("store", secret)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format_err!("unable to execute {SECRET_TOOL} - {err}"))?;
+
+    // dropping stdin closes it, so that the command sees the end of the input
+    child.stdin.take().unwrap().write_all(data)?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "storing secret in the secret service failed - {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+fn secret_service_load(secret: &ClientSecret) -> Result<Option<Vec<u8>>, Error> {
+    let output = secret_tool("lookup", secret)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format_err!("unable to execute {SECRET_TOOL} - {err}"))?;
+    if !output.status.success() {
+        // a missing secret only results in a non-zero exit code
+        if output.stderr.is_empty() {
+            return Ok(None);
+        }
+        bail!(
+            "looking up secret in the secret service failed - {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(Some(output.stdout))
+}
+
+fn secret_service_remove(secret: &ClientSecret) -> Result<bool, Error> {
+    if secret_service_load(secret)?.is_none() {
+        return Ok(false);
+    }
+    let output = secret_tool("clear", secret)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format_err!("unable to execute {SECRET_TOOL} - {err}"))?;
+    if !output.status.success() {
+        bail!(
+            "removing secret from the secret service failed - {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(true)
+}
+
+/// Store `secret` in `store`, replacing a previously stored value.
+pub fn store_secret(store: SecretStore, secret: &ClientSecret, data: &[u8]) -> Result<(), Error> {
+    match store {
+        SecretStore::Keyring => keyring_store(secret, data),
+        SecretStore::SecretService => secret_service_store(secret, data),
+        SecretStore::Tpm => tpm_store(secret, data, None),
+    }
+}
+
+/// Seal `secret` with the TPM, bound to the given PCRs instead of the default
+/// policy of `systemd-creds`. An empty list seals without PCR policy.
+pub fn seal_secret(secret: &ClientSecret, data: &[u8], pcrs: &[u8]) -> Result<(), Error> {
+    tpm_store(secret, data, Some(pcrs))
+}
+
+/// PCRs the TPM sealed `secret` is bound to, `None` if it was sealed with the
+/// default policy of `systemd-creds`.
+pub fn sealed_pcrs(secret: &ClientSecret) -> Result<Option<Vec<u8>>, Error> {
+    match super::find_xdg_file(pcrs_file_name(secret), "PCR binding")? {
+        Some(path) => read_pcrs_file(&path).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn read_pcrs_file(path: &Path) -> Result<Vec<u8>, Error> {
+    let pcrs = std::fs::read_to_string(path)
+        .map_err(|err| format_err!("unable to read {path:?} - {err}"))?;
+    parse_tpm2_pcrs(pcrs.trim())
+}
+
+/// Check whether `secret` is stored in `store`, without unsealing a TPM
+/// credential.
+pub fn secret_exists(store: SecretStore, secret: &ClientSecret) -> Result<bool, Error> {
+    match store {
+        SecretStore::Keyring => Ok(keyring_search(keyring()?, &key_description(secret)?).is_some()),
+        SecretStore::SecretService => Ok(secret_service_load(secret)?.is_some()),
+        SecretStore::Tpm => Ok(super::find_xdg_file(
+            format!("{}.cred", secret.name()),
+            "sealed credential",
+        )?
+        .is_some()),
+    }
+}
+
+/// Load `secret` from `store`, returns `None` if it is not stored there.
+pub fn load_secret(store: SecretStore, secret: &ClientSecret) -> Result<Option<Vec<u8>>, Error> {
+    match store {
+        SecretStore::Keyring => keyring_load(secret),
+        SecretStore::SecretService => secret_service_load(secret),
+        SecretStore::Tpm => tpm_load(secret),
+    }
+}
+
+/// Remove `secret` from `store`, returns whether it was stored there.
+pub fn remove_secret(store: SecretStore, secret: &ClientSecret) -> Result<bool, Error> {
+    match store {
+        SecretStore::Keyring => keyring_remove(secret),
+        SecretStore::SecretService => secret_service_remove(secret),
+        SecretStore::Tpm => tpm_remove(secret),
+    }
+}
+
+/// Search all stores for `secret`.
+///
+/// A kernel without persistent keyrings or a session without secret service is
+/// treated like an empty store, but failing to unseal an existing TPM
+/// credential is an error. Callers which can ask for the secret instead, like
+/// for passwords and passphrases, fall back to that with a warning.
+pub fn find_secret(secret: &ClientSecret) -> Result<Option<(SecretStore, Vec<u8>)>, Error> {
+    for store in SecretStore::ALL {
+        let data = match (store, load_secret(store, secret)) {
+            (_, Ok(data)) => data,
+            (SecretStore::Keyring | SecretStore::SecretService, Err(err)) => {
+                log::debug!("skipping {store} - {err}");
+                None
+            }
+            (_, Err(err)) => return Err(err),
+        };
+        if let Some(data) = data {
+            return Ok(Some((store, data)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tpm2_pcrs() {
+        assert_eq!(parse_tpm2_pcrs("").unwrap(), Vec::<u8>::new());
+        assert_eq!(parse_tpm2_pcrs("7").unwrap(), vec![7]);
+        // sorted and deduplicated, both separators work
+        assert_eq!(parse_tpm2_pcrs("7+0,4+7").unwrap(), vec![0, 4, 7]);
+        assert_eq!(parse_tpm2_pcrs(" 0 + 23 ").unwrap(), vec![0, 23]);
+        assert_eq!(parse_tpm2_pcrs("+7+").unwrap(), vec![7]);
+
+        assert!(parse_tpm2_pcrs("24").is_err());
+        assert!(parse_tpm2_pcrs("-1").is_err());
+        assert!(parse_tpm2_pcrs("0+pcr7").is_err());
+        assert!(parse_tpm2_pcrs("0 7").is_err());
+    }
+
+    #[test]
+    fn test_format_tpm2_pcrs() {
+        assert_eq!(format_tpm2_pcrs(&[]), "");
+        assert_eq!(format_tpm2_pcrs(&[0, 4, 7]), "0+4+7");
+
+        let pcrs = parse_tpm2_pcrs("7,0").unwrap();
+        assert_eq!(parse_tpm2_pcrs(&format_tpm2_pcrs(&pcrs)).unwrap(), pcrs);
+    }
+
+    #[test]
+    fn test_read_pcrs_file() {
+        let mut dir: PathBuf = String::from("./target/testout").into();
+        dir.push(std::module_path!());
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let secret = ClientSecret::EncryptionKey;
+        let path = dir.join(pcrs_file_name(&secret));
+        assert!(read_pcrs_file(&path).is_err());
+
+        std::fs::write(&path, format_tpm2_pcrs(&[0, 7])).unwrap();
+        assert_eq!(read_pcrs_file(&path).unwrap(), vec![0, 7]);
+
+        // sealed without PCR policy
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(read_pcrs_file(&path).unwrap(), Vec::<u8>::new());
+
+        // e.g. edited by hand
+        std::fs::write(&path, "7\n").unwrap();
+        assert_eq!(read_pcrs_file(&path).unwrap(), vec![7]);
+        std::fs::write(&path, "7+99").unwrap();
+        assert!(read_pcrs_file(&path).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
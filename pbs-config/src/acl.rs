@@ -877,6 +877,37 @@ acl:1:/storage/store1:user1@pbs:DatastoreBackup
         Ok(())
     }
 
+    #[test]
+    fn test_namespace_roles() -> Result<(), Error> {
+        let tree = AclTree::from_raw(
+            r###"
+acl:1:/datastore/store1/tenant:user1@pbs:NamespaceAdmin
+acl:1:/datastore/store1/tenant/sub:user2@pbs:NamespaceAudit
+"###,
+        )?;
+        let user1: Authid = "user1@pbs".parse()?;
+        check_roles(&tree, &user1, "/datastore/store1", "");
+        check_roles(&tree, &user1, "/datastore/store1/other", "");
+        check_roles(&tree, &user1, "/datastore/store1/tenant", "NamespaceAdmin");
+        check_roles(
+            &tree,
+            &user1,
+            "/datastore/store1/tenant/sub",
+            "NamespaceAdmin",
+        );
+
+        let user2: Authid = "user2@pbs".parse()?;
+        check_roles(&tree, &user2, "/datastore/store1/tenant", "");
+        check_roles(
+            &tree,
+            &user2,
+            "/datastore/store1/tenant/sub",
+            "NamespaceAudit",
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_role_add_delete() -> Result<(), Error> {
         let mut tree = AclTree::new();
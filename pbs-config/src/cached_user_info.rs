@@ -107,6 +107,30 @@ impl CachedUserInfo {
         true
     }
 
+    /// Test if an authentication id may only add new backups
+    ///
+    /// API tokens are append-only if either the token or its user is.
+    pub fn is_append_only(&self, auth_id: &Authid) -> bool {
+        let userid = auth_id.user();
+
+        if let Ok(info) = self.user_cfg.lookup::<User>("user", userid.as_str()) {
+            if info.is_append_only() {
+                return true;
+            }
+        }
+
+        if auth_id.is_token() {
+            if let Ok(info) = self
+                .user_cfg
+                .lookup::<ApiToken>("token", &auth_id.to_string())
+            {
+                return info.is_append_only();
+            }
+        }
+
+        false
+    }
+
     pub fn check_privs(
         &self,
         auth_id: &Authid,
@@ -4,6 +4,9 @@ pub use cached_user_info::CachedUserInfo;
 pub mod datastore;
 pub mod domains;
 pub mod drive;
+pub mod expectation;
+pub mod hook;
+pub mod log_forward;
 pub mod media_pool;
 pub mod metrics;
 pub mod network;
@@ -16,10 +19,16 @@ pub mod token_shadow;
 pub mod traffic_control;
 pub mod user;
 pub mod verify;
+pub mod webhook;
 
 mod config_version_cache;
 pub use config_version_cache::ConfigVersionCache;
 
+mod write_mode;
+pub use write_mode::{
+    config_write_mode, preview_config_change, set_config_write_mode, ConfigWriteMode,
+};
+
 use anyhow::{format_err, Error};
 use nix::unistd::{Gid, Group, Uid, User};
 
@@ -83,6 +92,28 @@ pub fn open_backup_lockfile<P: AsRef<std::path::Path>>(
 ///
 /// Only the superuser can write those files, but group 'backup' can read them.
 pub fn replace_backup_config<P: AsRef<std::path::Path>>(path: P, data: &[u8]) -> Result<(), Error> {
+    write_backup_config(path, data, false)
+}
+
+/// Like [`replace_backup_config`], for files containing secrets like passwords or tokens.
+///
+/// Their contents are never shown by the diff and dry-run write modes.
+pub fn replace_backup_config_with_secrets<P: AsRef<std::path::Path>>(
+    path: P,
+    data: &[u8],
+) -> Result<(), Error> {
+    write_backup_config(path, data, true)
+}
+
+fn write_backup_config<P: AsRef<std::path::Path>>(
+    path: P,
+    data: &[u8],
+    secret: bool,
+) -> Result<(), Error> {
+    if !preview_config_change(&path, data, secret)? {
+        return Ok(());
+    }
+
     let backup_user = backup_user()?;
     let mode = nix::sys::stat::Mode::from_bits_truncate(0o0640);
     // set the correct owner/group/permissions while saving file
@@ -101,6 +132,10 @@ pub fn replace_backup_config<P: AsRef<std::path::Path>>(path: P, data: &[u8]) ->
 ///
 /// Only the superuser can read and write those files.
 pub fn replace_secret_config<P: AsRef<std::path::Path>>(path: P, data: &[u8]) -> Result<(), Error> {
+    if !preview_config_change(&path, data, true)? {
+        return Ok(());
+    }
+
     let mode = nix::sys::stat::Mode::from_bits_truncate(0o0600);
     // set the correct owner/group/permissions while saving file
     // owner(rw) = root, group(r)= root
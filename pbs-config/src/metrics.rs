@@ -57,7 +57,7 @@ pub fn config() -> Result<(SectionConfigData, [u8; 32]), Error> {
 
 pub fn save_config(config: &SectionConfigData) -> Result<(), Error> {
     let raw = CONFIG.write(METRIC_SERVER_CFG_FILENAME, config)?;
-    crate::replace_backup_config(METRIC_SERVER_CFG_FILENAME, raw.as_bytes())
+    crate::replace_backup_config_with_secrets(METRIC_SERVER_CFG_FILENAME, raw.as_bytes())
 }
 
 // shell completion helper
@@ -491,6 +491,9 @@ pub fn save_config(config: &NetworkConfig) -> Result<(), Error> {
         .owner(nix::unistd::ROOT)
         .group(nix::unistd::Gid::from_raw(0));
 
+    if !crate::preview_config_change(NETWORK_INTERFACES_NEW_FILENAME, &raw, false)? {
+        return Ok(());
+    }
     replace_file(NETWORK_INTERFACES_NEW_FILENAME, &raw, options, true)?;
 
     Ok(())
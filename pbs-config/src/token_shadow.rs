@@ -45,6 +45,9 @@ fn write_file(data: HashMap<Authid, String>) -> Result<(), Error> {
         .group(backup_user.gid);
 
     let json = serde_json::to_vec(&data)?;
+    if !crate::preview_config_change(CONF_FILE, &json, true)? {
+        return Ok(());
+    }
     proxmox_sys::fs::replace_file(CONF_FILE, &json, options, true)
 }
 
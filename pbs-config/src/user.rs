@@ -63,9 +63,11 @@ pub fn config() -> Result<(SectionConfigData, [u8; 32]), Error> {
             comment: Some("Superuser".to_string()),
             enable: None,
             expire: None,
+            append_only: None,
             firstname: None,
             lastname: None,
             email: None,
+            lang: None,
         };
         data.set_data("root@pam", "user", &user).unwrap();
     }
@@ -0,0 +1,176 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use anyhow::{format_err, Error};
+
+/// How configuration changes of this process are applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ConfigWriteMode {
+    /// Write the configuration files (default).
+    Write = 0,
+    /// Write the configuration files and print the changes as unified diff.
+    Diff = 1,
+    /// Only print the changes, do not write anything.
+    DryRun = 2,
+}
+
+static CONFIG_WRITE_MODE: AtomicU8 = AtomicU8::new(ConfigWriteMode::Write as u8);
+
+/// Set the write mode for all following configuration changes of this process.
+pub fn set_config_write_mode(mode: ConfigWriteMode) {
+    CONFIG_WRITE_MODE.store(mode as u8, Ordering::SeqCst);
+}
+
+/// Get the current configuration write mode.
+pub fn config_write_mode() -> ConfigWriteMode {
+    match CONFIG_WRITE_MODE.load(Ordering::SeqCst) {
+        1 => ConfigWriteMode::Diff,
+        2 => ConfigWriteMode::DryRun,
+        _ => ConfigWriteMode::Write,
+    }
+}
+
+fn file_diff(path: &Path, data: &[u8]) -> Result<String, Error> {
+    let old = if path.exists() {
+        path
+    } else {
+        Path::new("/dev/null")
+    };
+
+    let label = path.to_string_lossy();
+    let mut child = Command::new("diff")
+        .arg("-u")
+        .arg(format!("--label={label}"))
+        .arg(format!("--label={label}"))
+        .arg(old)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format_err!("failed to execute diff - {}", err))?;
+
+    // dropping stdin closes it, so that diff sees the end of the input
+    child.stdin.take().unwrap().write_all(data)?;
+    let output = child.wait_with_output()?;
+
+    proxmox_sys::command::command_output_as_string(output, Some(|c| c == 0 || c == 1))
+        .map_err(|err| format_err!("diff failed: {}", err))
+}
+
+/// Describe the changes writing `data` to `path` makes, `None` if the contents stay the same.
+fn describe_change(path: &Path, data: &[u8], secret: bool) -> Result<Option<String>, Error> {
+    let old = proxmox_sys::fs::file_get_optional_contents(path)?;
+    if old.as_deref() == Some(data) {
+        return Ok(None);
+    }
+
+    if secret {
+        Ok(Some(format!(
+            "{path:?} changed (secret, contents not shown)\n"
+        )))
+    } else {
+        file_diff(path, data).map(Some)
+    }
+}
+
+/// Print the changes writing `data` to `path` would make, depending on the write mode.
+///
+/// Returns whether the file should actually be written. The contents of `secret` files are never
+/// printed, only that they change.
+pub fn preview_config_change<P: AsRef<Path>>(
+    path: P,
+    data: &[u8],
+    secret: bool,
+) -> Result<bool, Error> {
+    let mode = config_write_mode();
+    if mode == ConfigWriteMode::Write {
+        return Ok(true);
+    }
+
+    if let Some(change) = describe_change(path.as_ref(), data, secret)? {
+        print!("{change}");
+    }
+
+    Ok(mode == ConfigWriteMode::Diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn create_testdir(name: &str) -> PathBuf {
+        let mut testdir: PathBuf = String::from("./target/testout").into();
+        testdir.push(std::module_path!());
+        testdir.push(name);
+
+        let _ = std::fs::remove_dir_all(&testdir);
+        let _ = std::fs::create_dir_all(&testdir);
+
+        testdir
+    }
+
+    #[test]
+    fn test_describe_change() -> Result<(), Error> {
+        let testdir = create_testdir("describe_change");
+        let path = testdir.join("remote.cfg");
+        std::fs::write(&path, "remote: pbs1\n\tpassword c2VjcmV0\n")?;
+
+        assert_eq!(
+            describe_change(&path, b"remote: pbs1\n\tpassword c2VjcmV0\n", false)?,
+            None
+        );
+
+        let new = b"remote: pbs1\n\tpassword bmV3\n";
+        let diff = describe_change(&path, new, false)?.unwrap();
+        assert!(diff.contains("-\tpassword c2VjcmV0"));
+        assert!(diff.contains("+\tpassword bmV3"));
+
+        // secrets are neither shown from the old nor from the new contents
+        let change = describe_change(&path, new, true)?.unwrap();
+        assert!(change.contains("contents not shown"));
+        assert!(!change.contains("c2VjcmV0"));
+        assert!(!change.contains("bmV3"));
+
+        // new files are compared against an empty file
+        let diff = describe_change(&testdir.join("new.cfg"), b"line\n", false)?.unwrap();
+        assert!(diff.contains("+line"));
+
+        let _ = std::fs::remove_dir_all(&testdir);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_write_mode() -> Result<(), Error> {
+        // the only test changing the process wide write mode
+        let testdir = create_testdir("config_write_mode");
+        let path = testdir.join("test.cfg");
+
+        assert_eq!(config_write_mode(), ConfigWriteMode::Write);
+        assert!(preview_config_change(&path, b"data\n", false)?);
+
+        set_config_write_mode(ConfigWriteMode::Diff);
+        assert_eq!(config_write_mode(), ConfigWriteMode::Diff);
+        let diff = preview_config_change(&path, b"data\n", false);
+
+        set_config_write_mode(ConfigWriteMode::DryRun);
+        assert_eq!(config_write_mode(), ConfigWriteMode::DryRun);
+        let dry_run = preview_config_change(&path, b"data\n", true);
+
+        set_config_write_mode(ConfigWriteMode::Write);
+
+        assert!(diff?);
+        assert!(!dry_run?);
+        assert!(!path.exists());
+
+        let _ = std::fs::remove_dir_all(&testdir);
+
+        Ok(())
+    }
+}
@@ -491,6 +491,8 @@ impl BackupDir {
             format_err!("removing backup snapshot {:?} failed - {}", full_path, err,)
         })?;
 
+        crate::chunk_ref_tracker::untrack_snapshot(self);
+
         // the manifest doesn't exist anymore, no need to keep the lock (already done by guard?)
         if let Ok(path) = self.manifest_lock_path() {
             let _ = std::fs::remove_file(path); // ignore errors
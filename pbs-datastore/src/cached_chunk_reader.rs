@@ -3,6 +3,7 @@
 use std::future::Future;
 use std::io::SeekFrom;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
 
@@ -41,15 +42,23 @@ impl<T: AsyncReadChunk + Send + Sync + 'static> AsyncCacher<[u8; 32], Arc<Vec<u8
 /// Allows arbitrary data reads from an Index via an AsyncReadChunk implementation, using an LRU
 /// cache internally to cache chunks and provide support for multiple concurrent reads (potentially
 /// to the same chunk).
+///
+/// If the underlying reader has a prefetch window set, the following chunks are fetched into the
+/// cache in the background whenever a read moves on to a new chunk.
 pub struct CachedChunkReader<I: IndexFile, R: AsyncReadChunk + Send + Sync + 'static> {
     cache: Arc<AsyncLruCache<[u8; 32], Arc<Vec<u8>>>>,
     cacher: AsyncChunkCacher<R>,
     index: I,
+    last_prefetch: AtomicUsize,
 }
 
 impl<I: IndexFile, R: AsyncReadChunk + Send + Sync + 'static> CachedChunkReader<I, R> {
     /// Create a new reader with a local LRU cache containing 'capacity' chunks.
+    ///
+    /// The capacity is increased to hold the reader's prefetch window, so prefetched chunks do not
+    /// evict the one currently in use.
     pub fn new(reader: R, index: I, capacity: usize) -> Self {
+        let capacity = capacity.max(reader.prefetch_window() + 1);
         let cache = Arc::new(AsyncLruCache::new(capacity));
         Self::new_with_cache(reader, index, cache)
     }
@@ -67,6 +76,29 @@ impl<I: IndexFile, R: AsyncReadChunk + Send + Sync + 'static> CachedChunkReader<
                 reader: Arc::new(reader),
             },
             index,
+            last_prefetch: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    // Start fetching the chunks following `chunk` into the cache, once per chunk. Errors are
+    // ignored here, they are reported by the actual read of the chunk.
+    fn prefetch(&self, chunk: usize) {
+        let window = self.cacher.reader.prefetch_window();
+        if window == 0 || self.last_prefetch.swap(chunk, Ordering::SeqCst) == chunk {
+            return;
+        }
+
+        let end = (chunk + 1 + window).min(self.index.index_count());
+        for pos in (chunk + 1)..end {
+            // pos is always in range
+            let digest = self.index.chunk_info(pos).unwrap().digest;
+            let cache = Arc::clone(&self.cache);
+            let cacher = AsyncChunkCacher {
+                reader: Arc::clone(&self.cacher.reader),
+            };
+            tokio::spawn(async move {
+                let _ = cache.access(digest, &cacher).await;
+            });
         }
     }
 
@@ -81,6 +113,8 @@ impl<I: IndexFile, R: AsyncReadChunk + Send + Sync + 'static> CachedChunkReader<
                 // chunk indices retrieved from chunk_from_offset always resolve to Some(_)
                 let info = self.index.chunk_info(chunk.0).unwrap();
 
+                self.prefetch(chunk.0);
+
                 // will never be None, see AsyncChunkCacher
                 let data = self.cache.access(info.digest, &self.cacher).await?.unwrap();
 
@@ -105,9 +139,15 @@ impl<I: IndexFile + Send + Sync + 'static, R: AsyncReadChunk + Send + Sync + 'st
     /// AsyncRead for use in interfaces which require that. Direct use of read_at is preferred
     /// otherwise.
     pub fn seekable(self) -> SeekableCachedChunkReader<I, R> {
+        Arc::new(self).seekable_shared()
+    }
+
+    /// Like [seekable](Self::seekable), but the returned reader shares this instance and its
+    /// cache, so that multiple readers can be used concurrently.
+    pub fn seekable_shared(self: &Arc<Self>) -> SeekableCachedChunkReader<I, R> {
         SeekableCachedChunkReader {
             index_bytes: self.index.index_bytes(),
-            reader: Arc::new(self),
+            reader: Arc::clone(self),
             position: 0,
             read_future: None,
         }
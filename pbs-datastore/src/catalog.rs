@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::ffi::{CStr, CString, OsStr};
 use std::fmt;
 use std::io::{Read, Seek, SeekFrom, Write};
@@ -714,6 +715,102 @@ impl<R: Read + Seek> CatalogReader<R> {
 
         Ok(res)
     }
+
+    /// Call `callback` for every entry below `parent`, recursing into subdirectories.
+    pub fn walk(
+        &mut self,
+        parent: &DirEntry,
+        file_path: &mut Vec<u8>,
+        callback: &mut dyn FnMut(&[u8], &DirEntryAttribute) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let file_len = file_path.len();
+        for e in self.read_dir(parent)? {
+            file_path.truncate(file_len);
+            file_path.push(b'/');
+            file_path.extend(&e.name);
+            callback(file_path, &e.attr)?;
+            if e.is_directory() {
+                self.walk(&e, file_path, callback)?;
+            }
+        }
+        file_path.truncate(file_len);
+
+        Ok(())
+    }
+
+    /// Compare the directory `dir` of this catalog with `other_dir` of the `other` catalog.
+    ///
+    /// `callback` gets called with the path and the entries on both sides for every entry
+    /// which only exists on one side, or whose type, size or modification time differs.
+    /// Directories existing on one side only are reported along with all of their contents.
+    pub fn diff<R2: Read + Seek>(
+        &mut self,
+        dir: &DirEntry,
+        other: &mut CatalogReader<R2>,
+        other_dir: &DirEntry,
+        file_path: &mut Vec<u8>,
+        callback: &mut dyn FnMut(
+            &[u8],
+            Option<&DirEntryAttribute>,
+            Option<&DirEntryAttribute>,
+        ) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut entries = self.read_dir(dir)?;
+        entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let mut other_entries = other.read_dir(other_dir)?;
+        other_entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+        let mut entries = entries.into_iter().peekable();
+        let mut other_entries = other_entries.into_iter().peekable();
+
+        let file_len = file_path.len();
+        loop {
+            let order = match (entries.peek(), other_entries.peek()) {
+                (None, None) => break,
+                (Some(old), Some(new)) => old.name.cmp(&new.name),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+            };
+            let (old, new) = match order {
+                Ordering::Equal => (entries.next(), other_entries.next()),
+                Ordering::Less => (entries.next(), None),
+                Ordering::Greater => (None, other_entries.next()),
+            };
+
+            file_path.truncate(file_len);
+            file_path.push(b'/');
+            file_path.extend(&old.as_ref().or(new.as_ref()).unwrap().name);
+
+            match (&old, &new) {
+                (Some(old), Some(new)) if old.is_directory() && new.is_directory() => {
+                    self.diff(old, other, new, file_path, callback)?;
+                    continue;
+                }
+                (Some(old), Some(new)) if old.attr == new.attr => continue,
+                _ => (),
+            }
+
+            callback(
+                file_path,
+                old.as_ref().map(|e| &e.attr),
+                new.as_ref().map(|e| &e.attr),
+            )?;
+
+            if let Some(old) = old.as_ref().filter(|e| e.is_directory()) {
+                self.walk(old, file_path, &mut |path, attr| {
+                    callback(path, Some(attr), None)
+                })?;
+            }
+            if let Some(new) = new.as_ref().filter(|e| e.is_directory()) {
+                other.walk(new, file_path, &mut |path, attr| {
+                    callback(path, None, Some(attr))
+                })?;
+            }
+        }
+        file_path.truncate(file_len);
+
+        Ok(())
+    }
 }
 
 /// Serialize i64 as short, variable length byte sequence
@@ -897,6 +994,58 @@ fn test_catalog_i64_compatibility() {
     test_encode_decode(u64::MAX);
 }
 
+#[test]
+fn test_catalog_diff() {
+    fn catalog(files: &[(&str, u64)]) -> CatalogReader<std::io::Cursor<Vec<u8>>> {
+        let mut writer = CatalogWriter::new(Vec::new()).unwrap();
+        let archive = CString::new("root.pxar.didx").unwrap();
+        writer.start_directory(&archive).unwrap();
+        writer
+            .start_directory(&CString::new("etc").unwrap())
+            .unwrap();
+        for (name, size) in files {
+            let name = CString::new(*name).unwrap();
+            writer.add_file(&name, *size, 0).unwrap();
+        }
+        writer.end_directory().unwrap();
+        writer.end_directory().unwrap();
+        writer.finish().unwrap();
+        CatalogReader::new(std::io::Cursor::new(writer.writer))
+    }
+
+    let mut old = catalog(&[("hosts", 10), ("passwd", 20), ("shadow", 30)]);
+    let mut new = catalog(&[("hostname", 5), ("passwd", 25), ("shadow", 30)]);
+
+    let old_root = old.root().unwrap();
+    let new_root = new.root().unwrap();
+
+    let mut changes = Vec::new();
+    old.diff(
+        &old_root,
+        &mut new,
+        &new_root,
+        &mut Vec::new(),
+        &mut |path, old, new| {
+            changes.push((
+                String::from_utf8(path.to_vec()).unwrap(),
+                old.is_some(),
+                new.is_some(),
+            ));
+            Ok(())
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        changes,
+        [
+            ("/root.pxar.didx/etc/hostname".to_string(), false, true),
+            ("/root.pxar.didx/etc/hosts".to_string(), true, false),
+            ("/root.pxar.didx/etc/passwd".to_string(), true, true),
+        ]
+    );
+}
+
 /// An entry in a hierarchy of files for restore and listing.
 #[api]
 #[derive(Serialize, Deserialize)]
@@ -0,0 +1,174 @@
+//! Size bounded in-memory cache for chunks read from a datastore.
+//!
+//! The cache is shared by all users of a datastore instance, e.g. reader environments, verify
+//! workers and tape restores, so chunks referenced by several snapshots only have to be read
+//! from disk once while they stay hot.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{format_err, Error};
+
+use pbs_api_types::ChunkCacheStats;
+use pbs_tools::async_lru_cache::{AsyncCacher, AsyncLruCache};
+
+use crate::{ChunkStore, DataBlob};
+
+// the cache is bounded by entries, sized for the average chunk size of fixed and dynamic indices
+const AVERAGE_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+struct ChunkLoader {
+    chunk_store: Arc<ChunkStore>,
+    misses: Arc<AtomicU64>,
+}
+
+impl AsyncCacher<[u8; 32], Arc<DataBlob>> for ChunkLoader {
+    fn fetch(
+        &self,
+        digest: [u8; 32],
+    ) -> Box<dyn Future<Output = Result<Option<Arc<DataBlob>>, Error>> + Send> {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let chunk_store = Arc::clone(&self.chunk_store);
+        Box::new(async move {
+            let blob = tokio::task::spawn_blocking(move || {
+                let (chunk_path, digest_str) = chunk_store.chunk_path(&digest);
+                proxmox_lang::try_block!({
+                    crate::fault_injection::inject_read(&chunk_path)?;
+                    let mut file = std::fs::File::open(&chunk_path)?;
+                    DataBlob::load_from_reader(&mut file)
+                })
+                .map_err(|err| {
+                    format_err!(
+                        "store '{}', unable to load chunk '{}' - {}",
+                        chunk_store.name(),
+                        digest_str,
+                        err,
+                    )
+                })
+            })
+            .await??;
+            Ok(Some(Arc::new(blob)))
+        })
+    }
+}
+
+/// A chunk cache with hit statistics.
+pub struct ChunkCache {
+    cache: AsyncLruCache<[u8; 32], Arc<DataBlob>>,
+    loader: ChunkLoader,
+    capacity: usize,
+    requests: AtomicU64,
+    misses: Arc<AtomicU64>,
+}
+
+impl ChunkCache {
+    /// Create a new cache for chunks of `chunk_store`, holding about `size` bytes.
+    pub fn new(chunk_store: Arc<ChunkStore>, size: u64) -> Self {
+        let capacity = (size / AVERAGE_CHUNK_SIZE).max(1) as usize;
+        let misses = Arc::new(AtomicU64::new(0));
+        Self {
+            cache: AsyncLruCache::new(capacity),
+            loader: ChunkLoader {
+                chunk_store,
+                misses: Arc::clone(&misses),
+            },
+            capacity,
+            requests: AtomicU64::new(0),
+            misses,
+        }
+    }
+
+    /// Get a chunk from the cache, loading it from disk on a miss.
+    pub async fn load_chunk(&self, digest: &[u8; 32]) -> Result<Arc<DataBlob>, Error> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.cache
+            .access(*digest, &self.loader)
+            .await?
+            .ok_or_else(|| format_err!("chunk loader returned no data"))
+    }
+
+    /// Put a chunk into the cache, e.g. after it was written to the datastore.
+    pub fn insert(&self, digest: &[u8; 32], chunk: Arc<DataBlob>) {
+        self.cache.insert(*digest, chunk);
+    }
+
+    /// Returns the current cache statistics.
+    pub fn stats(&self) -> ChunkCacheStats {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        ChunkCacheStats {
+            capacity: self.capacity as u64,
+            hits: requests.saturating_sub(misses),
+            misses,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use pbs_api_types::DatastoreFSyncLevel;
+
+    use super::*;
+    use crate::data_blob::DataChunkBuilder;
+
+    fn create_testdir(name: &str) -> PathBuf {
+        let mut testdir: PathBuf = String::from("./target/testout").into();
+        testdir.push(std::module_path!());
+        testdir.push(name);
+
+        let _ = std::fs::remove_dir_all(&testdir);
+        let _ = std::fs::create_dir_all(&testdir);
+
+        std::fs::canonicalize(testdir).unwrap()
+    }
+
+    #[test]
+    fn test_chunk_cache() -> Result<(), Error> {
+        let testdir = create_testdir("chunk_cache");
+        let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())?.unwrap();
+        let chunk_store = Arc::new(ChunkStore::create(
+            "test",
+            &testdir,
+            user.uid,
+            user.gid,
+            None,
+            DatastoreFSyncLevel::None,
+        )?);
+
+        let (chunk1, digest1) = DataChunkBuilder::new(b"chunk 1").build()?;
+        let (chunk2, digest2) = DataChunkBuilder::new(b"chunk 2").build()?;
+        chunk_store.insert_chunk(&chunk1, &digest1)?;
+
+        // room for two chunks
+        let cache = ChunkCache::new(chunk_store, 2 * AVERAGE_CHUNK_SIZE);
+        assert_eq!(cache.stats().capacity, 2);
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        runtime.block_on(async {
+            let loaded = cache.load_chunk(&digest1).await?;
+            assert_eq!(loaded.raw_data(), chunk1.raw_data());
+            cache.load_chunk(&digest1).await?;
+
+            // not on disk yet
+            assert!(cache.load_chunk(&digest2).await.is_err());
+
+            // inserted chunks are served without reading them
+            cache.insert(&digest2, Arc::new(chunk2.clone()));
+            let loaded = cache.load_chunk(&digest2).await?;
+            assert_eq!(loaded.raw_data(), chunk2.raw_data());
+
+            Ok::<(), Error>(())
+        })?;
+
+        let stats = cache.stats();
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.hits, 2);
+
+        let _ = std::fs::remove_dir_all(&testdir);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,421 @@
+//! Chunk reference tracking for debugging purposes
+//!
+//! If `PROXMOX_DEBUG_CHUNK_REFS` is set in the environment, the process
+//! records which chunks are referenced by the index files it writes
+//! (backup sessions, sync jobs) as soon as each index is complete, so
+//! snapshots still in progress are covered, and by the snapshots backup
+//! sessions build upon. The references are dropped when those snapshots are
+//! removed. Garbage collection reports removed chunks which are still
+//! referenced by a tracked index immediately, and [report] compares the
+//! tracked references against the on-disk state.
+//!
+//! Only snapshots written, used or removed by the current process are tracked.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{format_err, Error};
+use serde::Serialize;
+
+use crate::dynamic_index::DynamicIndexReader;
+use crate::fixed_index::FixedIndexReader;
+use crate::index::IndexFile;
+use crate::manifest::{archive_type, ArchiveType};
+use crate::{BackupDir, DataStore};
+
+/// Environment variable to enable chunk reference tracking
+pub const CHUNK_REF_DEBUG_ENV: &str = "PROXMOX_DEBUG_CHUNK_REFS";
+
+// keep the event log bounded, this can run for a long time
+const MAX_EVENTS: usize = 1000;
+
+lazy_static::lazy_static! {
+    static ref TRACKER: Option<Mutex<HashMap<String, StoreRefs>>> =
+        if std::env::var_os(CHUNK_REF_DEBUG_ENV).is_some() {
+            log::info!("chunk reference tracking enabled");
+            Some(Mutex::new(HashMap::new()))
+        } else {
+            None
+        };
+}
+
+struct TrackedIndex {
+    digests: Vec<[u8; 32]>,
+    csum: [u8; 32],
+}
+
+#[derive(Default)]
+struct StoreRefs {
+    // key is the index path relative to the datastore base
+    indexes: HashMap<String, TrackedIndex>,
+    chunk_refs: HashMap<[u8; 32], usize>,
+    events: Vec<ChunkRefEvent>,
+}
+
+impl StoreRefs {
+    fn add_index(&mut self, path: String, index: TrackedIndex) {
+        self.remove_index(&path);
+        for digest in index.digests.iter() {
+            *self.chunk_refs.entry(*digest).or_default() += 1;
+        }
+        self.indexes.insert(path, index);
+    }
+
+    fn remove_index(&mut self, path: &str) -> bool {
+        let index = match self.indexes.remove(path) {
+            Some(index) => index,
+            None => return false,
+        };
+        for digest in index.digests.iter() {
+            if let Some(count) = self.chunk_refs.get_mut(digest) {
+                *count -= 1;
+                if *count == 0 {
+                    self.chunk_refs.remove(digest);
+                }
+            }
+        }
+        true
+    }
+
+    fn referencing_indexes(&self, digest: &[u8; 32]) -> Vec<String> {
+        let mut list: Vec<String> = self
+            .indexes
+            .iter()
+            .filter(|(_, index)| index.digests.contains(digest))
+            .map(|(path, _)| path.clone())
+            .collect();
+        list.sort();
+        list
+    }
+
+    fn push_event(&mut self, event: ChunkRefEvent) {
+        if self.events.len() >= MAX_EVENTS {
+            self.events.remove(0);
+        }
+        self.events.push(event);
+    }
+}
+
+/// Garbage collection removed a chunk with tracked references
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChunkRefEvent {
+    pub time: i64,
+    #[serde(with = "hex::serde")]
+    pub digest: [u8; 32],
+    /// Tracked indexes referencing the chunk at that time
+    pub indexes: Vec<String>,
+    /// Referencing indexes which did not exist on disk anymore
+    pub vanished_indexes: Vec<String>,
+}
+
+/// Tracked chunk missing on disk
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MissingChunk {
+    #[serde(with = "hex::serde")]
+    pub digest: [u8; 32],
+    pub indexes: Vec<String>,
+}
+
+/// Differences between tracked references and the on-disk state
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChunkRefReport {
+    pub store: String,
+    pub tracked_indexes: usize,
+    pub tracked_chunks: usize,
+    /// Referenced chunks removed by garbage collection (most recent last)
+    pub gc_removed_referenced: Vec<ChunkRefEvent>,
+    /// Referenced chunks which do not exist on disk
+    pub missing_chunks: Vec<MissingChunk>,
+    /// Tracked indexes removed without dropping the references (e.g. by
+    /// another process or manually)
+    pub vanished_indexes: Vec<String>,
+    /// Tracked indexes which changed on disk
+    pub changed_indexes: Vec<String>,
+}
+
+/// Returns true if chunk reference tracking is enabled.
+pub fn enabled() -> bool {
+    TRACKER.is_some()
+}
+
+fn open_index(path: &Path) -> Result<Option<Box<dyn IndexFile>>, Error> {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    Ok(match archive_type(name) {
+        Ok(ArchiveType::FixedIndex) => Some(Box::new(FixedIndexReader::open(path)?)),
+        Ok(ArchiveType::DynamicIndex) => Some(Box::new(DynamicIndexReader::open(path)?)),
+        _ => None,
+    })
+}
+
+fn read_index(path: &Path) -> Result<Option<TrackedIndex>, Error> {
+    let index = match open_index(path)? {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+
+    let mut seen = HashSet::new();
+    let mut digests = Vec::new();
+    for pos in 0..index.index_count() {
+        let digest = *index.index_digest(pos).unwrap();
+        if seen.insert(digest) {
+            digests.push(digest);
+        }
+    }
+
+    Ok(Some(TrackedIndex {
+        digests,
+        csum: index.compute_csum().0,
+    }))
+}
+
+fn track_snapshot_do(snapshot: &BackupDir) -> Result<(), Error> {
+    let tracker = match *TRACKER {
+        Some(ref tracker) => tracker,
+        None => return Ok(()),
+    };
+
+    let relative_path = snapshot.relative_path();
+    let mut indexes = Vec::new();
+
+    for entry in std::fs::read_dir(snapshot.full_path())? {
+        let entry = entry?;
+        let path = entry.path();
+        if let Some(index) = read_index(&path)? {
+            let name = entry.file_name();
+            let key = relative_path.join(name).to_string_lossy().into_owned();
+            indexes.push((key, index));
+        }
+    }
+
+    add_indexes(tracker, snapshot, indexes);
+
+    Ok(())
+}
+
+fn track_index_do(snapshot: &BackupDir, name: &str) -> Result<(), Error> {
+    let tracker = match *TRACKER {
+        Some(ref tracker) => tracker,
+        None => return Ok(()),
+    };
+
+    let mut path = snapshot.full_path();
+    path.push(name);
+    if let Some(index) = read_index(&path)? {
+        let key = snapshot
+            .relative_path()
+            .join(name)
+            .to_string_lossy()
+            .into_owned();
+        add_indexes(tracker, snapshot, vec![(key, index)]);
+    }
+
+    Ok(())
+}
+
+fn add_indexes(
+    tracker: &Mutex<HashMap<String, StoreRefs>>,
+    snapshot: &BackupDir,
+    indexes: Vec<(String, TrackedIndex)>,
+) {
+    let mut map = tracker.lock().unwrap();
+    let refs = map
+        .entry(snapshot.datastore().name().to_string())
+        .or_default();
+    for (key, index) in indexes {
+        refs.add_index(key, index);
+    }
+}
+
+/// Track the chunk references of all indexes of a snapshot.
+pub fn track_snapshot(snapshot: &BackupDir) {
+    if let Err(err) = track_snapshot_do(snapshot) {
+        log::error!(
+            "chunk ref tracker: unable to track snapshot {:?} - {err}",
+            snapshot.relative_path()
+        );
+    }
+}
+
+/// Track the chunk references of a single index of a snapshot, once it is completely written.
+pub fn track_index(snapshot: &BackupDir, name: &str) {
+    if let Err(err) = track_index_do(snapshot, name) {
+        log::error!(
+            "chunk ref tracker: unable to track index {name:?} of snapshot {:?} - {err}",
+            snapshot.relative_path()
+        );
+    }
+}
+
+/// Drop the chunk references of a removed snapshot.
+pub fn untrack_snapshot(snapshot: &BackupDir) {
+    let tracker = match *TRACKER {
+        Some(ref tracker) => tracker,
+        None => return,
+    };
+
+    let prefix = format!("{}/", snapshot.relative_path().to_string_lossy());
+
+    let mut map = tracker.lock().unwrap();
+    if let Some(refs) = map.get_mut(snapshot.datastore().name()) {
+        let keys: Vec<String> = refs
+            .indexes
+            .keys()
+            .filter(|key| key.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for key in keys {
+            refs.remove_index(&key);
+        }
+    }
+}
+
+/// Called by garbage collection before removing a chunk file.
+///
+/// Logs an error (and records an event for [report]) if the chunk is still
+/// referenced by a tracked index.
+pub fn chunk_removed(store: &str, base: &Path, digest_str: &str) {
+    let tracker = match *TRACKER {
+        Some(ref tracker) => tracker,
+        None => return,
+    };
+
+    let digest = match <[u8; 32]>::try_from(hex::decode(digest_str).unwrap_or_default()) {
+        Ok(digest) => digest,
+        Err(_) => return, // not a valid chunk name (e.g. bad chunk)
+    };
+
+    let mut map = tracker.lock().unwrap();
+    let refs = match map.get_mut(store) {
+        Some(refs) => refs,
+        None => return,
+    };
+
+    if !refs.chunk_refs.contains_key(&digest) {
+        return;
+    }
+
+    let indexes = refs.referencing_indexes(&digest);
+    let vanished_indexes: Vec<String> = indexes
+        .iter()
+        .filter(|path| !base.join(path).exists())
+        .cloned()
+        .collect();
+
+    log::error!(
+        "chunk ref tracker: store '{store}': garbage collection removes chunk {digest_str} \
+        referenced by {:?} (vanished: {:?})",
+        indexes,
+        vanished_indexes,
+    );
+
+    refs.push_event(ChunkRefEvent {
+        time: proxmox_time::epoch_i64(),
+        digest,
+        indexes,
+        vanished_indexes,
+    });
+}
+
+/// Compare the tracked chunk references of a datastore with the on-disk state.
+pub fn report(datastore: &DataStore) -> Result<ChunkRefReport, Error> {
+    let tracker = TRACKER.as_ref().ok_or_else(|| {
+        format_err!("chunk reference tracking is not enabled ({CHUNK_REF_DEBUG_ENV})")
+    })?;
+
+    let store = datastore.name().to_string();
+    let base = datastore.base_path();
+
+    let map = tracker.lock().unwrap();
+    let refs = match map.get(&store) {
+        Some(refs) => refs,
+        None => {
+            return Ok(ChunkRefReport {
+                store,
+                tracked_indexes: 0,
+                tracked_chunks: 0,
+                gc_removed_referenced: Vec::new(),
+                missing_chunks: Vec::new(),
+                vanished_indexes: Vec::new(),
+                changed_indexes: Vec::new(),
+            })
+        }
+    };
+
+    let mut vanished_indexes = Vec::new();
+    let mut changed_indexes = Vec::new();
+    for (path, index) in refs.indexes.iter() {
+        let full_path = base.join(path);
+        match open_index(&full_path) {
+            Ok(Some(disk_index)) => {
+                if disk_index.compute_csum().0 != index.csum {
+                    changed_indexes.push(path.clone());
+                }
+            }
+            Ok(None) => {}
+            Err(_) if !full_path.exists() => vanished_indexes.push(path.clone()),
+            Err(err) => {
+                log::warn!("chunk ref tracker: unable to read index {full_path:?} - {err}");
+                changed_indexes.push(path.clone());
+            }
+        }
+    }
+    vanished_indexes.sort();
+    changed_indexes.sort();
+
+    let mut missing_chunks = Vec::new();
+    for digest in refs.chunk_refs.keys() {
+        let (chunk_path, _digest_str) = datastore.chunk_path(digest);
+        if !chunk_path.exists() {
+            missing_chunks.push(MissingChunk {
+                digest: *digest,
+                indexes: refs.referencing_indexes(digest),
+            });
+        }
+    }
+    missing_chunks.sort_by(|a, b| a.digest.cmp(&b.digest));
+
+    Ok(ChunkRefReport {
+        store,
+        tracked_indexes: refs.indexes.len(),
+        tracked_chunks: refs.chunk_refs.len(),
+        gc_removed_referenced: refs.events.clone(),
+        missing_chunks,
+        vanished_indexes,
+        changed_indexes,
+    })
+}
+
+#[test]
+fn test_store_refs() {
+    let mut refs = StoreRefs::default();
+
+    let index = |digests: &[[u8; 32]]| TrackedIndex {
+        digests: digests.to_vec(),
+        csum: [0u8; 32],
+    };
+
+    refs.add_index("vm/100/a/drive.fidx".into(), index(&[[1u8; 32], [2u8; 32]]));
+    refs.add_index("vm/100/b/drive.fidx".into(), index(&[[2u8; 32], [3u8; 32]]));
+    assert_eq!(refs.chunk_refs.len(), 3);
+    assert_eq!(refs.chunk_refs[&[2u8; 32]], 2);
+    assert_eq!(
+        refs.referencing_indexes(&[2u8; 32]),
+        vec!["vm/100/a/drive.fidx", "vm/100/b/drive.fidx"]
+    );
+
+    // re-adding the same index must not count references twice
+    refs.add_index("vm/100/a/drive.fidx".into(), index(&[[1u8; 32], [2u8; 32]]));
+    assert_eq!(refs.chunk_refs[&[2u8; 32]], 2);
+
+    assert!(refs.remove_index("vm/100/a/drive.fidx"));
+    assert!(!refs.remove_index("vm/100/a/drive.fidx"));
+    assert!(!refs.chunk_refs.contains_key(&[1u8; 32]));
+    assert_eq!(refs.chunk_refs[&[2u8; 32]], 1);
+}
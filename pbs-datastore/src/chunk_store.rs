@@ -6,7 +6,9 @@ use anyhow::{bail, format_err, Error};
 
 use pbs_api_types::{DatastoreFSyncLevel, GarbageCollectionStatus};
 use proxmox_io::ReadExt;
-use proxmox_sys::fs::{create_dir, create_path, file_type_from_file_stat, CreateOptions};
+use proxmox_sys::fs::{
+    create_dir, create_path, file_type_from_file_stat, make_tmp_file, CreateOptions,
+};
 use proxmox_sys::process_locker::{
     ProcessLockExclusiveGuard, ProcessLockSharedGuard, ProcessLocker,
 };
@@ -14,10 +16,14 @@ use proxmox_sys::task_log;
 use proxmox_sys::WorkerTaskContext;
 
 use crate::file_formats::{
-    COMPRESSED_BLOB_MAGIC_1_0, ENCRYPTED_BLOB_MAGIC_1_0, UNCOMPRESSED_BLOB_MAGIC_1_0,
+    COMPRESSED_BLOB_MAGIC_1_0, ENCRYPTED_BLOB_MAGIC_1_0, LZ4_COMPRESSED_BLOB_MAGIC_1_0,
+    UNCOMPRESSED_BLOB_MAGIC_1_0,
 };
 use crate::DataBlob;
 
+// FICLONE from linux/fs.h, shares the extents of the source fd with the target file
+nix::ioctl_write_int!(ficlone, 0x94, 9);
+
 /// File system based chunk store
 pub struct ChunkStore {
     name: String, // used for error reporting
@@ -404,6 +410,13 @@ impl ChunkStore {
                 if stat.st_atime < min_atime {
                     //let age = now - stat.st_atime;
                     //println!("UNLINK {}  {:?}", age/(3600*24), filename);
+                    if crate::chunk_ref_tracker::enabled() && !bad {
+                        crate::chunk_ref_tracker::chunk_removed(
+                            &self.name,
+                            &self.base,
+                            filename.to_str().unwrap_or_default(),
+                        );
+                    }
                     if let Err(err) = unlinkat(Some(dirfd), filename, UnlinkatFlags::NoRemoveDir) {
                         if bad {
                             status.still_bad += 1;
@@ -471,7 +484,10 @@ impl ChunkStore {
 
                 // going from unencrypted to encrypted can never be right, since the digest
                 // includes data derived from the encryption key
-                if magic == UNCOMPRESSED_BLOB_MAGIC_1_0 || magic == COMPRESSED_BLOB_MAGIC_1_0 {
+                if magic == UNCOMPRESSED_BLOB_MAGIC_1_0
+                    || magic == COMPRESSED_BLOB_MAGIC_1_0
+                    || magic == LZ4_COMPRESSED_BLOB_MAGIC_1_0
+                {
                     bail!("Overwriting unencrypted chunk '{digest_str}' on store '{name}' with encrypted chunk with same digest not allowed!");
                 }
 
@@ -500,15 +516,18 @@ impl ChunkStore {
             .parent()
             .ok_or_else(|| format_err!("unable to get chunk dir"))?;
 
-        proxmox_sys::fs::replace_file(
-            &chunk_path,
-            raw_data,
-            CreateOptions::new(),
-            self.sync_level == DatastoreFSyncLevel::File,
-        )
-        .map_err(|err| {
-            format_err!("inserting chunk on store '{name}' failed for {digest_str} - {err}")
-        })?;
+        crate::fault_injection::inject_write(&chunk_path, raw_data)
+            .and_then(|raw_data| {
+                proxmox_sys::fs::replace_file(
+                    &chunk_path,
+                    raw_data,
+                    CreateOptions::new(),
+                    self.sync_level == DatastoreFSyncLevel::File,
+                )
+            })
+            .map_err(|err| {
+                format_err!("inserting chunk on store '{name}' failed for {digest_str} - {err}")
+            })?;
 
         if self.sync_level == DatastoreFSyncLevel::File {
             // fsync dir handle to persist the tmp rename
@@ -522,6 +541,128 @@ impl ChunkStore {
         Ok((false, encoded_size))
     }
 
+    /// Insert a chunk by hard linking it from another chunk store.
+    ///
+    /// Returns `false` if the chunk could not be linked, e.g. because both stores are located on
+    /// different file systems or the link count limit of the chunk is reached, in which case the
+    /// caller needs to fall back to copying it.
+    pub fn link_chunk(&self, source: &ChunkStore, digest: &[u8; 32]) -> Result<bool, Error> {
+        // unwrap: only `None` in unit tests
+        assert!(self.locker.is_some());
+
+        let (source_path, digest_str) = source.chunk_path(digest);
+        let (chunk_path, _digest_str) = self.chunk_path(digest);
+
+        let lock = self.mutex.lock();
+
+        if self.cond_touch_path(&chunk_path, false)? {
+            return Ok(true);
+        }
+
+        match std::fs::hard_link(&source_path, &chunk_path) {
+            Ok(()) => (),
+            Err(err) if link_needs_copy(&err) => return Ok(false),
+            Err(err) => bail!(
+                "linking chunk {digest_str} from store '{}' into store '{}' failed - {err}",
+                source.name,
+                self.name,
+            ),
+        }
+
+        // a hard link keeps the old atime, make sure GC sees the chunk as in use
+        self.touch_chunk(digest)?;
+
+        if self.sync_level == DatastoreFSyncLevel::File {
+            let chunk_dir_path = chunk_path
+                .parent()
+                .ok_or_else(|| format_err!("unable to get chunk dir"))?;
+            let dir = std::fs::File::open(chunk_dir_path)?;
+            nix::unistd::fsync(dir.as_raw_fd())
+                .map_err(|err| format_err!("fsync failed: {err}"))?;
+        }
+
+        drop(lock);
+
+        Ok(true)
+    }
+
+    /// Insert a chunk by creating a reflink copy (`FICLONE`) of a chunk from another chunk store.
+    ///
+    /// Unlike hard links, the copy is a separate inode, but shares the data extents with the
+    /// source until either is modified. Returns `false` if the file system does not support
+    /// reflinks or both stores are located on different file systems, in which case the caller
+    /// needs to fall back to copying it.
+    pub fn clone_chunk(&self, source: &ChunkStore, digest: &[u8; 32]) -> Result<bool, Error> {
+        // unwrap: only `None` in unit tests
+        assert!(self.locker.is_some());
+
+        let (source_path, digest_str) = source.chunk_path(digest);
+        let (chunk_path, _digest_str) = self.chunk_path(digest);
+
+        let lock = self.mutex.lock();
+
+        if self.cond_touch_path(&chunk_path, false)? {
+            return Ok(true);
+        }
+
+        let source_file = std::fs::File::open(&source_path).map_err(|err| {
+            format_err!(
+                "unable to open chunk {digest_str} on store '{}' - {err}",
+                source.name
+            )
+        })?;
+
+        let (tmp_file, tmp_path) = make_tmp_file(&chunk_path, CreateOptions::new())?;
+
+        let res = unsafe { ficlone(tmp_file.as_raw_fd(), source_file.as_raw_fd() as _) };
+        match res {
+            Ok(_) => (),
+            Err(
+                nix::errno::Errno::EXDEV
+                | nix::errno::Errno::EOPNOTSUPP
+                | nix::errno::Errno::EINVAL
+                | nix::errno::Errno::ENOTTY,
+            ) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                return Ok(false);
+            }
+            Err(err) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                bail!(
+                    "cloning chunk {digest_str} from store '{}' into store '{}' failed - {err}",
+                    source.name,
+                    self.name,
+                );
+            }
+        }
+
+        if self.sync_level == DatastoreFSyncLevel::File {
+            if let Err(err) = nix::unistd::fsync(tmp_file.as_raw_fd()) {
+                let _ = std::fs::remove_file(&tmp_path);
+                bail!("fsync of cloned chunk {digest_str} failed - {err}");
+            }
+        }
+        drop(tmp_file);
+
+        if let Err(err) = std::fs::rename(&tmp_path, &chunk_path) {
+            let _ = std::fs::remove_file(&tmp_path);
+            bail!("atomic rename of cloned chunk {digest_str} failed - {err}");
+        }
+
+        if self.sync_level == DatastoreFSyncLevel::File {
+            let chunk_dir_path = chunk_path
+                .parent()
+                .ok_or_else(|| format_err!("unable to get chunk dir"))?;
+            let dir = std::fs::File::open(chunk_dir_path)?;
+            nix::unistd::fsync(dir.as_raw_fd())
+                .map_err(|err| format_err!("fsync failed: {err}"))?;
+        }
+
+        drop(lock);
+
+        Ok(true)
+    }
+
     pub fn chunk_path(&self, digest: &[u8; 32]) -> (PathBuf, String) {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
@@ -565,6 +706,17 @@ impl ChunkStore {
     }
 }
 
+/// Whether a failed hard link of a chunk can be replaced by a copy.
+///
+/// Besides links across file systems, this covers link count limits (`EMLINK`) and file systems
+/// not allowing hard links at all (`EPERM`).
+fn link_needs_copy(err: &std::io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EXDEV | libc::EMLINK | libc::EPERM)
+    )
+}
+
 #[test]
 fn test_chunk_store1() {
     let mut path = std::fs::canonicalize(".").unwrap(); // we need absolute path
@@ -610,3 +762,123 @@ fn test_chunk_store1() {
 
     if let Err(_e) = std::fs::remove_dir_all(".testdir") { /* ignore */ }
 }
+
+#[cfg(test)]
+pub(crate) fn create_test_chunk_store(name: &str) -> ChunkStore {
+    let mut path: PathBuf = String::from("./target/testout").into();
+    path.push(std::module_path!());
+    path.push(name);
+
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    let path = std::fs::canonicalize(path).unwrap();
+
+    let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())
+        .unwrap()
+        .unwrap();
+    ChunkStore::create(
+        name,
+        path,
+        user.uid,
+        user.gid,
+        None,
+        DatastoreFSyncLevel::None,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_link_chunk() {
+    use std::os::unix::fs::MetadataExt;
+
+    let source = create_test_chunk_store("link_source");
+    let target = create_test_chunk_store("link_target");
+
+    let (chunk, digest) = crate::data_blob::DataChunkBuilder::new(b"linked chunk")
+        .build()
+        .unwrap();
+    source.insert_chunk(&chunk, &digest).unwrap();
+
+    assert!(target.link_chunk(&source, &digest).unwrap());
+    let (chunk_path, _) = target.chunk_path(&digest);
+    assert_eq!(std::fs::metadata(&chunk_path).unwrap().nlink(), 2);
+
+    // existing chunks are kept
+    assert!(target.link_chunk(&source, &digest).unwrap());
+    assert_eq!(std::fs::metadata(&chunk_path).unwrap().nlink(), 2);
+
+    // a missing source chunk is an error, not a reason to copy
+    let (_, missing) = crate::data_blob::DataChunkBuilder::new(b"missing chunk")
+        .build()
+        .unwrap();
+    assert!(target.link_chunk(&source, &missing).is_err());
+
+    let _ = std::fs::remove_dir_all(source.base_path());
+    let _ = std::fs::remove_dir_all(target.base_path());
+}
+
+#[test]
+fn test_clone_chunk() {
+    use std::os::unix::fs::MetadataExt;
+
+    let source = create_test_chunk_store("clone_source");
+    let target = create_test_chunk_store("clone_target");
+
+    let (chunk, digest) = crate::data_blob::DataChunkBuilder::new(b"cloned chunk")
+        .build()
+        .unwrap();
+    source.insert_chunk(&chunk, &digest).unwrap();
+    let (source_path, _) = source.chunk_path(&digest);
+    let (chunk_path, _) = target.chunk_path(&digest);
+
+    // whether reflinks work depends on the file system the tests run on
+    if target.clone_chunk(&source, &digest).unwrap() {
+        let metadata = std::fs::metadata(&chunk_path).unwrap();
+        assert_eq!(metadata.nlink(), 1);
+        assert_ne!(
+            metadata.ino(),
+            std::fs::metadata(&source_path).unwrap().ino()
+        );
+        assert_eq!(
+            std::fs::read(&chunk_path).unwrap(),
+            std::fs::read(&source_path).unwrap()
+        );
+    } else {
+        assert!(!chunk_path.exists());
+    }
+
+    // no temporary files are left behind in either case
+    let chunk_dir = chunk_path.parent().unwrap();
+    for entry in std::fs::read_dir(chunk_dir).unwrap() {
+        assert_eq!(entry.unwrap().path(), chunk_path);
+    }
+
+    // existing chunks are kept
+    target.insert_chunk(&chunk, &digest).unwrap();
+    assert!(target.clone_chunk(&source, &digest).unwrap());
+
+    // a missing source chunk is an error, not a reason to copy
+    let (_, missing) = crate::data_blob::DataChunkBuilder::new(b"missing chunk")
+        .build()
+        .unwrap();
+    assert!(target.clone_chunk(&source, &missing).is_err());
+
+    let _ = std::fs::remove_dir_all(source.base_path());
+    let _ = std::fs::remove_dir_all(target.base_path());
+}
+
+#[test]
+fn test_link_needs_copy() {
+    use std::io::Error as IoError;
+
+    for errno in [libc::EXDEV, libc::EMLINK, libc::EPERM] {
+        assert!(link_needs_copy(&IoError::from_raw_os_error(errno)));
+    }
+    for errno in [libc::ENOENT, libc::EACCES, libc::ENOSPC] {
+        assert!(!link_needs_copy(&IoError::from_raw_os_error(errno)));
+    }
+    assert!(!link_needs_copy(&IoError::new(
+        std::io::ErrorKind::Other,
+        "no errno"
+    )));
+}
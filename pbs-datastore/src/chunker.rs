@@ -105,6 +105,14 @@ impl Chunker {
         }
     }
 
+    /// Reset the rolling hash state, so that the next scan starts a new
+    /// chunk. Used after cutting a chunk at an externally forced boundary.
+    pub fn reset(&mut self) {
+        self.h = 0;
+        self.chunk_size = 0;
+        self.window_size = 0;
+    }
+
     /// Scans the specified data for a chunk border. Returns 0 if none
     /// was found (and the function should be called with more data
     /// later on), or another value indicating the position of a
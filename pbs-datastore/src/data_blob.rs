@@ -1,11 +1,11 @@
 use std::io::Write;
 
-use anyhow::{bail, Error};
+use anyhow::{bail, format_err, Error};
 use openssl::symm::{decrypt_aead, Mode};
 
 use proxmox_io::{ReadExt, WriteExt};
 
-use pbs_api_types::CryptMode;
+use pbs_api_types::{ChunkCompression, CryptMode};
 use pbs_tools::crypt_config::CryptConfig;
 
 use super::file_formats::*;
@@ -29,6 +29,7 @@ pub struct ChunkInfo {
 /// Please use index files to store large data files (".fidx" of
 /// ".didx").
 ///
+#[derive(Clone)]
 pub struct DataBlob {
     raw_data: Vec<u8>, // tagged, compressed, encryped data
 }
@@ -88,6 +89,22 @@ impl DataBlob {
         data: &[u8],
         config: Option<&CryptConfig>,
         compress: bool,
+    ) -> Result<Self, Error> {
+        let compression = if compress {
+            ChunkCompression::default()
+        } else {
+            ChunkCompression::None
+        };
+        Self::encode_with_compression(data, config, compression)
+    }
+
+    /// Create a DataBlob using `compression`, optionally encrypted
+    ///
+    /// Encrypted blobs only support zstd compression.
+    pub fn encode_with_compression(
+        data: &[u8],
+        config: Option<&CryptConfig>,
+        compression: ChunkCompression,
     ) -> Result<Self, Error> {
         if data.len() > MAX_BLOB_SIZE {
             bail!("data blob too large ({} bytes).", data.len());
@@ -95,16 +112,20 @@ impl DataBlob {
 
         let mut blob = if let Some(config) = config {
             let compr_data;
-            let (_compress, data, magic) = if compress {
-                compr_data = zstd::bulk::compress(data, 1)?;
-                // Note: We only use compression if result is shorter
-                if compr_data.len() < data.len() {
-                    (true, &compr_data[..], ENCR_COMPR_BLOB_MAGIC_1_0)
-                } else {
-                    (false, data, ENCRYPTED_BLOB_MAGIC_1_0)
+            let (_compress, data, magic) = match compression {
+                ChunkCompression::Zstd(level) => {
+                    compr_data = zstd::bulk::compress(data, level)?;
+                    // Note: We only use compression if result is shorter
+                    if compr_data.len() < data.len() {
+                        (true, &compr_data[..], ENCR_COMPR_BLOB_MAGIC_1_0)
+                    } else {
+                        (false, data, ENCRYPTED_BLOB_MAGIC_1_0)
+                    }
                 }
-            } else {
-                (false, data, ENCRYPTED_BLOB_MAGIC_1_0)
+                ChunkCompression::Lz4 => {
+                    bail!("lz4 compression is not supported for encrypted blobs")
+                }
+                ChunkCompression::None => (false, data, ENCRYPTED_BLOB_MAGIC_1_0),
             };
 
             let header_len = std::mem::size_of::<EncryptedDataBlobHeader>();
@@ -137,18 +158,26 @@ impl DataBlob {
             DataBlob { raw_data }
         } else {
             let max_data_len = data.len() + std::mem::size_of::<DataBlobHeader>();
-            if compress {
+            let magic = match compression {
+                ChunkCompression::Zstd(_) => Some(COMPRESSED_BLOB_MAGIC_1_0),
+                ChunkCompression::Lz4 => Some(LZ4_COMPRESSED_BLOB_MAGIC_1_0),
+                ChunkCompression::None => None,
+            };
+            if let Some(magic) = magic {
                 let mut comp_data = Vec::with_capacity(max_data_len);
 
-                let head = DataBlobHeader {
-                    magic: COMPRESSED_BLOB_MAGIC_1_0,
-                    crc: [0; 4],
-                };
+                let head = DataBlobHeader { magic, crc: [0; 4] };
                 unsafe {
                     comp_data.write_le_value(head)?;
                 }
 
-                zstd::stream::copy_encode(data, &mut comp_data, 1)?;
+                match compression {
+                    ChunkCompression::Zstd(level) => {
+                        zstd::stream::copy_encode(data, &mut comp_data, level)?
+                    }
+                    // the uncompressed size is prepended as u32, see decode()
+                    _ => comp_data.extend(lz4_flex::block::compress_prepend_size(data)),
+                }
 
                 if comp_data.len() < max_data_len {
                     let mut blob = DataBlob {
@@ -183,7 +212,10 @@ impl DataBlob {
         let magic = self.magic();
 
         Ok(
-            if magic == &UNCOMPRESSED_BLOB_MAGIC_1_0 || magic == &COMPRESSED_BLOB_MAGIC_1_0 {
+            if magic == &UNCOMPRESSED_BLOB_MAGIC_1_0
+                || magic == &COMPRESSED_BLOB_MAGIC_1_0
+                || magic == &LZ4_COMPRESSED_BLOB_MAGIC_1_0
+            {
                 CryptMode::None
             } else if magic == &ENCR_COMPR_BLOB_MAGIC_1_0 || magic == &ENCRYPTED_BLOB_MAGIC_1_0 {
                 CryptMode::Encrypt
@@ -218,6 +250,23 @@ impl DataBlob {
                 Self::verify_digest(&data, None, digest)?;
             }
             Ok(data)
+        } else if magic == &LZ4_COMPRESSED_BLOB_MAGIC_1_0 {
+            let data_start = std::mem::size_of::<DataBlobHeader>();
+            let compr_data = &self.raw_data[data_start..];
+            // check the prepended size first, it is used to allocate the output buffer
+            let size = match compr_data.get(0..4) {
+                Some(size) => u32::from_le_bytes(size.try_into().unwrap()) as usize,
+                None => bail!("lz4 compressed blob too small"),
+            };
+            if size > MAX_BLOB_SIZE {
+                bail!("lz4 compressed blob too large ({size} bytes).");
+            }
+            let data = lz4_flex::block::decompress_size_prepended(compr_data)
+                .map_err(|err| format_err!("lz4 decompression failed - {err}"))?;
+            if let Some(digest) = digest {
+                Self::verify_digest(&data, None, digest)?;
+            }
+            Ok(data)
         } else if magic == &ENCR_COMPR_BLOB_MAGIC_1_0 || magic == &ENCRYPTED_BLOB_MAGIC_1_0 {
             let header_len = std::mem::size_of::<EncryptedDataBlobHeader>();
             let head = unsafe {
@@ -280,7 +329,10 @@ impl DataBlob {
             let blob = DataBlob { raw_data: data };
 
             Ok(blob)
-        } else if magic == COMPRESSED_BLOB_MAGIC_1_0 || magic == UNCOMPRESSED_BLOB_MAGIC_1_0 {
+        } else if magic == COMPRESSED_BLOB_MAGIC_1_0
+            || magic == LZ4_COMPRESSED_BLOB_MAGIC_1_0
+            || magic == UNCOMPRESSED_BLOB_MAGIC_1_0
+        {
             let blob = DataBlob { raw_data: data };
 
             Ok(blob)
@@ -298,7 +350,33 @@ impl DataBlob {
     /// Returns if chunk is compressed
     pub fn is_compressed(&self) -> bool {
         let magic = self.magic();
-        magic == &ENCR_COMPR_BLOB_MAGIC_1_0 || magic == &COMPRESSED_BLOB_MAGIC_1_0
+        magic == &ENCR_COMPR_BLOB_MAGIC_1_0
+            || magic == &COMPRESSED_BLOB_MAGIC_1_0
+            || magic == &LZ4_COMPRESSED_BLOB_MAGIC_1_0
+    }
+
+    /// Re-encode an unencrypted blob using `compression`.
+    ///
+    /// Returns `None` for encrypted blobs and blobs already stored with the requested algorithm.
+    /// The zstd level is not recorded in the blob, so zstd compressed blobs are only considered
+    /// up to date for the default level, which is the one used by clients.
+    pub fn recompress(&self, compression: ChunkCompression) -> Result<Option<Self>, Error> {
+        if self.is_encrypted() {
+            return Ok(None);
+        }
+
+        let up_to_date = match (compression, *self.magic()) {
+            (ChunkCompression::None, UNCOMPRESSED_BLOB_MAGIC_1_0) => true,
+            (ChunkCompression::Lz4, LZ4_COMPRESSED_BLOB_MAGIC_1_0) => true,
+            (compression, COMPRESSED_BLOB_MAGIC_1_0) => compression == ChunkCompression::default(),
+            _ => false,
+        };
+        if up_to_date {
+            return Ok(None);
+        }
+
+        let data = self.decode(None, None)?;
+        Self::encode_with_compression(&data, None, compression).map(Some)
     }
 
     /// Verify digest and data length for unencrypted chunks.
@@ -561,4 +639,83 @@ impl<'a, 'b> DataChunkBuilder<'a, 'b> {
 
         chunk_builder.build()
     }
+
+    /// Compute the digest of a chunk filled with zeroes, without encoding it
+    pub fn zero_chunk_digest(crypt_config: Option<&CryptConfig>, chunk_size: usize) -> [u8; 32] {
+        let zero_bytes = vec![0; chunk_size];
+        match crypt_config {
+            Some(config) => config.compute_digest(&zero_bytes),
+            None => openssl::sha::sha256(&zero_bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_data() -> Vec<u8> {
+        (0..64 * 1024u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_compression_roundtrip() -> Result<(), Error> {
+        let data = test_data();
+        let digest = openssl::sha::sha256(&data);
+
+        for (compression, magic) in [
+            (ChunkCompression::None, UNCOMPRESSED_BLOB_MAGIC_1_0),
+            (ChunkCompression::Zstd(1), COMPRESSED_BLOB_MAGIC_1_0),
+            (ChunkCompression::Zstd(19), COMPRESSED_BLOB_MAGIC_1_0),
+            (ChunkCompression::Lz4, LZ4_COMPRESSED_BLOB_MAGIC_1_0),
+        ] {
+            let blob = DataBlob::encode_with_compression(&data, None, compression)?;
+            assert_eq!(blob.magic(), &magic, "{compression}");
+            assert_eq!(blob.is_compressed(), compression != ChunkCompression::None);
+            assert_eq!(blob.crypt_mode()?, CryptMode::None);
+            blob.verify_crc()?;
+
+            // as read from disk
+            let blob = DataBlob::from_raw(blob.into_inner())?;
+            assert_eq!(blob.decode(None, Some(&digest))?, data, "{compression}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recompress() -> Result<(), Error> {
+        let data = test_data();
+
+        let zstd = DataBlob::encode(&data, None, true)?;
+        assert!(zstd.recompress(ChunkCompression::default())?.is_none());
+
+        let lz4 = zstd.recompress(ChunkCompression::Lz4)?.unwrap();
+        assert_eq!(lz4.magic(), &LZ4_COMPRESSED_BLOB_MAGIC_1_0);
+        assert!(lz4.recompress(ChunkCompression::Lz4)?.is_none());
+
+        // served to clients which cannot decode lz4
+        let zstd = lz4.recompress(ChunkCompression::default())?.unwrap();
+        assert_eq!(zstd.magic(), &COMPRESSED_BLOB_MAGIC_1_0);
+        assert_eq!(zstd.decode(None, None)?, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lz4_invalid_size() -> Result<(), Error> {
+        let mut raw_data = Vec::new();
+        unsafe {
+            raw_data.write_le_value(DataBlobHeader {
+                magic: LZ4_COMPRESSED_BLOB_MAGIC_1_0,
+                crc: [0; 4],
+            })?;
+        }
+        // prepended size larger than any blob
+        raw_data.extend(u32::MAX.to_le_bytes());
+        let blob = DataBlob::from_raw(raw_data)?;
+        assert!(blob.decode(None, None).is_err());
+
+        Ok(())
+    }
 }
@@ -5,11 +5,14 @@ use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
+use hex::FromHex;
 use lazy_static::lazy_static;
 use nix::unistd::{unlinkat, UnlinkatFlags};
 
 use proxmox_human_byte::HumanByte;
 use proxmox_schema::ApiType;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 use proxmox_sys::error::SysError;
 use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
@@ -18,19 +21,27 @@ use proxmox_sys::process_locker::ProcessLockSharedGuard;
 use proxmox_sys::WorkerTaskContext;
 use proxmox_sys::{task_log, task_warn};
 
+use pbs_api_types::parse_client_version;
 use pbs_api_types::{
-    Authid, BackupNamespace, BackupType, ChunkOrder, DataStoreConfig, DatastoreFSyncLevel,
-    DatastoreTuning, GarbageCollectionStatus, MaintenanceMode, MaintenanceType, Operation, UPID,
+    Authid, BackupNamespace, BackupType, BulkActionResult, ChunkCacheStats, ChunkCompression,
+    ChunkOrder, CompressionStats, CryptMode, DataStoreConfig, DatastoreFSyncLevel,
+    DatastoreNamingPolicy, DatastoreTuning, DedupStats, GarbageCollectionStatus,
+    GroupCompressionStats, GroupDedupStats, MaintenanceMode, MaintenanceType, Operation, UPID,
 };
 
 use crate::backup_info::{BackupDir, BackupGroup, BackupGroupDeleteStats};
+use crate::chunk_cache::ChunkCache;
 use crate::chunk_store::ChunkStore;
 use crate::dynamic_index::{DynamicIndexReader, DynamicIndexWriter};
+use crate::file_formats::{
+    ENCRYPTED_BLOB_MAGIC_1_0, ENCR_COMPR_BLOB_MAGIC_1_0, UNCOMPRESSED_BLOB_MAGIC_1_0,
+};
 use crate::fixed_index::{FixedIndexReader, FixedIndexWriter};
 use crate::hierarchy::{ListGroups, ListGroupsType, ListNamespaces, ListNamespacesRecursive};
-use crate::index::IndexFile;
+use crate::index::{IndexAccessPattern, IndexFile};
 use crate::manifest::{archive_type, ArchiveType};
 use crate::task_tracking::{self, update_active_operations};
+use crate::write_epoch;
 use crate::DataBlob;
 
 lazy_static! {
@@ -38,6 +49,70 @@ lazy_static! {
         Mutex::new(HashMap::new());
 }
 
+const DEDUP_STATS_FILE: &str = ".dedup-stats";
+const BULK_ACTION_RESULTS_FILE: &str = ".bulk-action-results";
+// number of bulk action results kept per datastore
+const BULK_ACTION_RESULTS_KEEP: usize = 16;
+const COMPRESSION_STATS_FILE: &str = ".compression-stats";
+
+// whether a chunk is part of a `sample` percent sample, selected by its digest
+fn chunk_is_sampled(digest: &[u8; 32], sample: u64) -> bool {
+    let selector = u16::from_le_bytes([digest[0], digest[1]]);
+    (selector as u64) * 100 < sample * 65536
+}
+
+// account a sampled chunk by its stored size and blob magic
+fn account_sampled_chunk(
+    stats: &mut GroupCompressionStats,
+    logical_size: u64,
+    stored_size: u64,
+    magic: &[u8; 8],
+) {
+    stats.sampled_chunks += 1;
+    stats.logical_size += logical_size;
+    stats.stored_size += stored_size;
+    if *magic == ENCRYPTED_BLOB_MAGIC_1_0 || *magic == ENCR_COMPR_BLOB_MAGIC_1_0 {
+        stats.encrypted_chunks += 1;
+    }
+    if *magic == UNCOMPRESSED_BLOB_MAGIC_1_0 || *magic == ENCRYPTED_BLOB_MAGIC_1_0 {
+        stats.uncompressed_chunks += 1;
+    }
+}
+
+// derive the ratios of a group once all its sampled chunks are accounted
+fn finish_group_compression_stats(stats: &mut GroupCompressionStats) {
+    if stats.stored_size > 0 {
+        stats.compression_ratio = stats.logical_size as f64 / stats.stored_size as f64;
+    }
+    if stats.sampled_chunks > 0 {
+        stats.encrypted_fraction = stats.encrypted_chunks as f64 / stats.sampled_chunks as f64;
+    }
+}
+
+// size and references of a chunk while computing the deduplication statistics
+struct ChunkUsage {
+    size: u64,
+    groups: u32,
+    // index into the group list of the first referencing group
+    first_group: usize,
+}
+
+// split the referenced sizes of the groups into unique and shared ones, once all groups are
+// accounted
+fn finish_dedup_stats<'a>(stats: &mut DedupStats, chunks: impl Iterator<Item = &'a ChunkUsage>) {
+    for usage in chunks {
+        stats.disk_chunks += 1;
+        stats.disk_size += usage.size;
+        if usage.groups == 1 {
+            stats.groups[usage.first_group].unique_size += usage.size;
+        }
+    }
+
+    for group in stats.groups.iter_mut() {
+        group.shared_size = group.referenced_size - group.unique_size;
+    }
+}
+
 /// checks if auth_id is owner, or, if owner is a token, if
 /// auth_id is the user of the token
 pub fn check_backup_owner(owner: &Authid, auth_id: &Authid) -> Result<(), Error> {
@@ -49,6 +124,72 @@ pub fn check_backup_owner(owner: &Authid, auth_id: &Authid) -> Result<(), Error>
     Ok(())
 }
 
+// checks `version` against the minimum client version of the datastore `name`, as configured
+// and parsed
+fn check_min_client_version(
+    name: &str,
+    (min_version, min): &(String, (u32, u32, u32)),
+    version: Option<&str>,
+) -> Result<(), Error> {
+    match version {
+        None => bail!(
+            "datastore '{name}' requires a client of version {min_version} or newer, \
+            but the client did not report its version - please upgrade the client"
+        ),
+        Some(version) => match parse_client_version(version) {
+            Ok(parsed) if parsed >= *min => Ok(()),
+            Ok(_) => bail!(
+                "client version {version} is older than the minimum version {min_version} \
+                required by datastore '{name}' - please upgrade the client"
+            ),
+            Err(_) => bail!(
+                "datastore '{name}' requires a client of version {min_version} or newer, \
+                but the client reported an invalid version '{version}'"
+            ),
+        },
+    }
+}
+
+/// Checks if a datastore is available, i.e. it is not located on a removable device, or the
+/// removable device with the configured filesystem UUID is currently mounted on its path.
+pub fn is_datastore_available(config: &DataStoreConfig) -> bool {
+    match config.backing_device.as_deref() {
+        Some(uuid) => is_device_mounted_on(uuid, Path::new(&config.path)),
+        None => true,
+    }
+}
+
+fn is_device_mounted_on(uuid: &str, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let device = match std::fs::metadata(format!("/dev/disk/by-uuid/{uuid}")) {
+        Ok(device) => device,
+        Err(_) => return false,
+    };
+
+    std::fs::metadata(path)
+        .map(|stat| stat.dev() == device.rdev())
+        .unwrap_or(false)
+}
+
+/// Result of [DataStore::recover_partial_snapshots]
+#[derive(Default)]
+pub struct PartialSnapshotReport {
+    /// Interrupted index commits which got replayed
+    pub replayed_journals: Vec<String>,
+    /// Unfinished snapshots which got removed
+    pub removed_snapshots: Vec<String>,
+}
+
+/// Result of [DataStore::check_recent_chunks]
+#[derive(Default)]
+pub struct RecentChunkReport {
+    /// Number of chunks written in the checked window
+    pub checked: usize,
+    /// Chunks which failed the check and got renamed to `.bad`
+    pub corrupt: Vec<String>,
+}
+
 /// Datastore Management
 ///
 /// A Datastore can store severals backups, and provides the
@@ -58,9 +199,16 @@ pub struct DataStoreImpl {
     gc_mutex: Mutex<()>,
     last_gc_status: Mutex<GarbageCollectionStatus>,
     verify_new: bool,
+    known_chunk_query: bool,
     chunk_order: ChunkOrder,
     last_digest: Option<[u8; 32]>,
     sync_level: DatastoreFSyncLevel,
+    chunk_compression: Option<ChunkCompression>,
+    chunk_cache: Option<ChunkCache>,
+    verify_upload: bool,
+    naming_policy: DatastoreNamingPolicy,
+    min_client_version: Option<(String, (u32, u32, u32))>,
+    backing_device: Option<String>,
 }
 
 impl DataStoreImpl {
@@ -72,9 +220,16 @@ impl DataStoreImpl {
             gc_mutex: Mutex::new(()),
             last_gc_status: Mutex::new(GarbageCollectionStatus::default()),
             verify_new: false,
+            known_chunk_query: false,
             chunk_order: Default::default(),
             last_digest: None,
             sync_level: Default::default(),
+            chunk_compression: None,
+            chunk_cache: None,
+            verify_upload: false,
+            naming_policy: Default::default(),
+            min_client_version: None,
+            backing_device: None,
         })
     }
 }
@@ -158,6 +313,10 @@ impl DataStore {
             }
         }
 
+        if !is_datastore_available(&config) {
+            bail!("datastore '{name}' is not available, its removable device is not mounted");
+        }
+
         if let Some(operation) = operation {
             update_active_operations(name, operation, 1)?;
         }
@@ -305,14 +464,39 @@ impl DataStore {
                 .parse_property_string(config.tuning.as_deref().unwrap_or(""))?,
         )?;
 
+        let naming_policy: DatastoreNamingPolicy = serde_json::from_value(
+            DatastoreNamingPolicy::API_SCHEMA
+                .parse_property_string(config.naming_policy.as_deref().unwrap_or(""))?,
+        )?;
+
+        let min_client_version = match config.min_client_version.as_deref() {
+            Some(version) => Some((version.to_string(), parse_client_version(version)?)),
+            None => None,
+        };
+
+        let chunk_cache = match tuning.chunk_cache_size {
+            Some(size) if size > 0 => Some(ChunkCache::new(
+                Arc::clone(&chunk_store),
+                size * 1024 * 1024,
+            )),
+            _ => None,
+        };
+
         Ok(DataStoreImpl {
             chunk_store,
             gc_mutex: Mutex::new(()),
             last_gc_status: Mutex::new(gc_status),
             verify_new: config.verify_new.unwrap_or(false),
+            known_chunk_query: config.known_chunk_query.unwrap_or(false),
             chunk_order: tuning.chunk_order.unwrap_or_default(),
             last_digest,
             sync_level: tuning.sync_level.unwrap_or_default(),
+            chunk_compression: tuning.compression,
+            chunk_cache,
+            verify_upload: tuning.verify_upload.unwrap_or(false),
+            naming_policy,
+            min_client_version,
+            backing_device: config.backing_device.clone(),
         })
     }
 
@@ -959,6 +1143,8 @@ impl DataStore {
         status.index_file_count += 1;
         status.index_data_bytes += index.index_bytes();
 
+        index.advise(IndexAccessPattern::Sequential);
+
         for pos in 0..index.index_count() {
             worker.check_abort()?;
             worker.fail_on_shutdown()?;
@@ -1054,6 +1240,195 @@ impl DataStore {
         Ok(())
     }
 
+    /// Clean up after crashed backup sessions.
+    ///
+    /// Replays index journals left over from interrupted commits (see
+    /// [crate::index_journal]) and removes unfinished snapshots. Groups which
+    /// are locked (for example by a running backup) are skipped.
+    pub fn recover_partial_snapshots(
+        self: &Arc<Self>,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<PartialSnapshotReport, Error> {
+        let mut report = PartialSnapshotReport::default();
+
+        for ns in self.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+            for group in self.iter_backup_groups_ok(ns.clone())? {
+                worker.check_abort()?;
+
+                let group_path = group.full_group_path();
+                let _group_guard = match lock_dir_noblock(
+                    &group_path,
+                    "backup group",
+                    "possible running backup",
+                ) {
+                    Ok(guard) => guard,
+                    Err(_) => continue,
+                };
+
+                let list = match group.list_backups() {
+                    Ok(list) => list,
+                    Err(err) => {
+                        task_warn!(
+                            worker,
+                            "group {} in {}: unable to list snapshots - {err}",
+                            group.group(),
+                            pbs_api_types::print_store_and_ns(self.name(), &ns),
+                        );
+                        continue;
+                    }
+                };
+
+                for info in list {
+                    let snapshot = info.backup_dir;
+                    let name = pbs_api_types::print_ns_and_snapshot(&ns, snapshot.dir());
+
+                    match crate::index_journal::replay_journals(&snapshot.full_path()) {
+                        Ok(actions) => {
+                            for action in actions {
+                                task_warn!(worker, "snapshot {name}: {action}");
+                                report.replayed_journals.push(format!("{name}: {action}"));
+                            }
+                        }
+                        Err(err) => {
+                            task_warn!(
+                                worker,
+                                "snapshot {name}: replaying journals failed - {err}"
+                            );
+                        }
+                    }
+
+                    if info.is_finished() {
+                        continue;
+                    }
+
+                    match snapshot.destroy(false) {
+                        Ok(()) => {
+                            task_warn!(worker, "removed partial snapshot {name}");
+                            report.removed_snapshots.push(name);
+                        }
+                        Err(err) => {
+                            task_warn!(worker, "unable to remove partial snapshot {name} - {err}");
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Start a new write epoch, see [crate::write_epoch].
+    ///
+    /// Returns the unclean shutdown to check for, if one was detected and not
+    /// checked yet.
+    pub fn begin_write_epoch(&self) -> Result<Option<write_epoch::UncleanShutdown>, Error> {
+        write_epoch::begin(&self.base_path())
+    }
+
+    /// Sync the datastore file system and record it in the write epoch marker.
+    pub fn sync_write_epoch(&self) -> Result<(), Error> {
+        write_epoch::sync(&self.base_path())
+    }
+
+    /// Sync the datastore file system and flag the write epoch as cleanly ended.
+    pub fn end_write_epoch(&self) -> Result<(), Error> {
+        write_epoch::end(&self.base_path())
+    }
+
+    /// Check all chunks written since `since` (by modification time).
+    ///
+    /// Chunks which cannot be loaded, have a wrong CRC or (if unencrypted) do
+    /// not match their digest are renamed to `.bad`, like in a verification.
+    /// Marks a pending unclean shutdown check as done if successful.
+    pub fn check_recent_chunks(
+        &self,
+        since: i64,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<RecentChunkReport, Error> {
+        let mut report = RecentChunkReport::default();
+        let mut last_percentage = 0;
+
+        for (entry, percentage, bad) in self.get_chunk_iterator()? {
+            if last_percentage != percentage {
+                last_percentage = percentage;
+                task_log!(
+                    worker,
+                    "processed {percentage}% ({} recent chunks)",
+                    report.checked
+                );
+            }
+
+            worker.check_abort()?;
+            worker.fail_on_shutdown()?;
+
+            let entry = entry.map_err(|err| {
+                format_err!("chunk iterator on store '{}' failed - {err}", self.name())
+            })?;
+            if bad {
+                continue;
+            }
+
+            let stat = match nix::sys::stat::fstatat(
+                entry.parent_fd(),
+                entry.file_name(),
+                nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+            ) {
+                Ok(stat) => stat,
+                Err(_) => continue, // removed in the meantime
+            };
+            if stat.st_mtime < since {
+                continue;
+            }
+
+            let digest_str = entry.file_name().to_string_lossy().into_owned();
+            let digest = match <[u8; 32]>::from_hex(&digest_str) {
+                Ok(digest) => digest,
+                Err(_) => continue,
+            };
+
+            report.checked += 1;
+
+            let result = self.load_chunk(&digest).and_then(|blob| {
+                if blob.crypt_mode()? == CryptMode::None {
+                    blob.decode(None, Some(&digest))?;
+                }
+                Ok(())
+            });
+            if let Err(err) = result {
+                task_warn!(worker, "chunk {digest_str} is corrupt - {err}");
+                self.rename_corrupted_chunk(&digest, worker);
+                report.corrupt.push(digest_str);
+            }
+        }
+
+        write_epoch::check_finished(&self.base_path())?;
+
+        Ok(report)
+    }
+
+    /// Rename a corrupt chunk to `<digest>.<n>.bad`, so that it is uploaded or synced again and
+    /// not used by new backups. Up to 10 bad copies are kept.
+    pub fn rename_corrupted_chunk(&self, digest: &[u8; 32], worker: &dyn WorkerTaskContext) {
+        let (path, digest_str) = self.chunk_path(digest);
+
+        let mut counter = 0;
+        let mut new_path = path.clone();
+        loop {
+            new_path.set_file_name(format!("{digest_str}.{counter}.bad"));
+            if new_path.exists() && counter < 9 {
+                counter += 1;
+            } else {
+                break;
+            }
+        }
+
+        match std::fs::rename(&path, &new_path) {
+            Ok(()) => task_log!(worker, "corrupted chunk renamed to {new_path:?}"),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => task_warn!(worker, "could not rename corrupted chunk {path:?} - {err}"),
+        }
+    }
+
     pub fn last_gc_status(&self) -> GarbageCollectionStatus {
         self.inner.last_gc_status.lock().unwrap().clone()
     }
@@ -1062,6 +1437,320 @@ impl DataStore {
         self.inner.gc_mutex.try_lock().is_err()
     }
 
+    // read the statistics saved as `name` in the datastore's base directory
+    fn load_stats<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, Error> {
+        let mut path = self.base_path();
+        path.push(name);
+
+        match file_read_optional_string(path)? {
+            Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    // save statistics as `name` in the datastore's base directory
+    fn save_stats<T: Serialize>(&self, name: &str, stats: &T) -> Result<(), Error> {
+        let mut path = self.base_path();
+        path.push(name);
+
+        let backup_user = pbs_config::backup_user()?;
+        let options = CreateOptions::new()
+            .perm(nix::sys::stat::Mode::from_bits_truncate(0o0644))
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+        replace_file(
+            path,
+            serde_json::to_string(stats)?.as_bytes(),
+            options,
+            false,
+        )
+    }
+
+    /// Calls `func` for every backup group of the datastore, with the number of snapshots of the
+    /// group and an iterator over the fixed and dynamic indexes of all its snapshots.
+    ///
+    /// Indexes which cannot be opened are skipped with a warning.
+    fn for_each_group_indexes<F>(
+        self: &Arc<Self>,
+        worker: &dyn WorkerTaskContext,
+        mut func: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(
+            &BackupNamespace,
+            &BackupGroup,
+            u64,
+            &mut dyn Iterator<Item = Box<dyn IndexFile + Send>>,
+        ) -> Result<(), Error>,
+    {
+        for ns in self.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+            for group in self.iter_backup_groups_ok(ns.clone())? {
+                worker.check_abort()?;
+                worker.fail_on_shutdown()?;
+
+                let snapshots = match group.list_backups() {
+                    Ok(snapshots) => snapshots,
+                    Err(err) => {
+                        task_warn!(
+                            worker,
+                            "group {} in {}: unable to list snapshots - {err}",
+                            group.group(),
+                            pbs_api_types::print_store_and_ns(self.name(), &ns),
+                        );
+                        continue;
+                    }
+                };
+                let snapshot_count = snapshots.len() as u64;
+
+                let mut indexes = snapshots.into_iter().flat_map(|info| {
+                    let backup_dir = info.backup_dir;
+                    info.files.into_iter().filter_map(move |file| {
+                        match archive_type(&file) {
+                            Ok(ArchiveType::FixedIndex | ArchiveType::DynamicIndex) => (),
+                            _ => return None,
+                        }
+
+                        let mut path = backup_dir.full_path();
+                        path.push(&file);
+                        match self.open_index(&path) {
+                            Ok(index) => {
+                                index.advise(IndexAccessPattern::Sequential);
+                                Some(index)
+                            }
+                            Err(err) => {
+                                task_warn!(worker, "unable to open index {path:?} - {err}");
+                                None
+                            }
+                        }
+                    })
+                });
+
+                func(&ns, &group, snapshot_count, &mut indexes)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Save the per item results of a bulk action task, only the latest results are kept.
+    pub fn save_bulk_action_result(&self, result: BulkActionResult) -> Result<(), Error> {
+        let mut lock_path = self.base_path();
+        lock_path.push(format!("{BULK_ACTION_RESULTS_FILE}.lck"));
+        let _lock = pbs_config::open_backup_lockfile(lock_path, None, true)?;
+
+        let mut results: Vec<BulkActionResult> = self
+            .load_stats(BULK_ACTION_RESULTS_FILE)?
+            .unwrap_or_default();
+        results.retain(|old| old.upid != result.upid);
+        results.push(result);
+        if results.len() > BULK_ACTION_RESULTS_KEEP {
+            results.drain(..results.len() - BULK_ACTION_RESULTS_KEEP);
+        }
+
+        self.save_stats(BULK_ACTION_RESULTS_FILE, &results)
+    }
+
+    /// Returns the results saved by [`Self::save_bulk_action_result`] for the task `upid`.
+    pub fn bulk_action_result(&self, upid: &str) -> Result<Option<BulkActionResult>, Error> {
+        let results: Vec<BulkActionResult> = self
+            .load_stats(BULK_ACTION_RESULTS_FILE)?
+            .unwrap_or_default();
+        Ok(results.into_iter().find(|result| result.upid == upid))
+    }
+
+    /// Returns the deduplication statistics saved by the last [`Self::compute_dedup_stats`] run.
+    pub fn last_dedup_stats(&self) -> Result<Option<DedupStats>, Error> {
+        self.load_stats(DEDUP_STATS_FILE)
+    }
+
+    /// Compute per group deduplication statistics and save them for [`Self::last_dedup_stats`].
+    ///
+    /// Every chunk is accounted with its size on disk, to the group itself if only one group
+    /// references it, or as shared between all referencing groups otherwise.
+    pub fn compute_dedup_stats(
+        self: &Arc<Self>,
+        worker: &dyn WorkerTaskContext,
+        upid: &UPID,
+    ) -> Result<DedupStats, Error> {
+        let mut chunks: HashMap<[u8; 32], ChunkUsage> = HashMap::new();
+        let mut stats = DedupStats {
+            upid: Some(upid.to_string()),
+            time: proxmox_time::epoch_i64(),
+            ..Default::default()
+        };
+
+        self.for_each_group_indexes(worker, |ns, group, snapshot_count, indexes| {
+            let group_index = stats.groups.len();
+            let mut group_stats = GroupDedupStats {
+                ns: (!ns.is_root()).then(|| ns.clone()),
+                backup: group.group().clone(),
+                snapshot_count,
+                logical_size: 0,
+                referenced_size: 0,
+                unique_size: 0,
+                shared_size: 0,
+            };
+            // chunks are only accounted once per group
+            let mut group_chunks = HashSet::new();
+
+            for index in indexes {
+                group_stats.logical_size += index.index_bytes();
+
+                for pos in 0..index.index_count() {
+                    let digest = *index.index_digest(pos).unwrap();
+                    if !group_chunks.insert(digest) {
+                        continue;
+                    }
+
+                    let usage = chunks.entry(digest).or_insert_with(|| {
+                        let size = match self.stat_chunk(&digest) {
+                            Ok(metadata) => metadata.len(),
+                            Err(_) => {
+                                stats.missing_chunks += 1;
+                                0
+                            }
+                        };
+                        ChunkUsage {
+                            size,
+                            groups: 0,
+                            first_group: group_index,
+                        }
+                    });
+                    usage.groups += 1;
+                    group_stats.referenced_size += usage.size;
+                }
+            }
+
+            stats.logical_size += group_stats.logical_size;
+            stats.groups.push(group_stats);
+            Ok(())
+        })?;
+
+        finish_dedup_stats(&mut stats, chunks.values());
+
+        task_log!(
+            worker,
+            "{} groups reference {} chunks ({}), logical size {}",
+            stats.groups.len(),
+            stats.disk_chunks,
+            HumanByte::from(stats.disk_size),
+            HumanByte::from(stats.logical_size),
+        );
+        if stats.missing_chunks > 0 {
+            task_warn!(
+                worker,
+                "{} referenced chunks are missing",
+                stats.missing_chunks
+            );
+        }
+
+        self.save_stats(DEDUP_STATS_FILE, &stats)?;
+
+        Ok(stats)
+    }
+
+    /// Returns the compression statistics saved by the last
+    /// [`Self::compute_compression_stats`] run.
+    pub fn last_compression_stats(&self) -> Result<Option<CompressionStats>, Error> {
+        self.load_stats(COMPRESSION_STATS_FILE)
+    }
+
+    // read the magic number at the start of a chunk file
+    fn read_chunk_magic(&self, digest: &[u8; 32]) -> Result<(u64, [u8; 8]), Error> {
+        use std::io::Read;
+
+        let (chunk_path, _digest_str) = self.inner.chunk_store.chunk_path(digest);
+        let mut file = std::fs::File::open(chunk_path)?;
+        let size = file.metadata()?.len();
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        Ok((size, magic))
+    }
+
+    /// Estimate the compression ratio and the fraction of encrypted chunks per group, and
+    /// save them for [`Self::last_compression_stats`].
+    ///
+    /// Only `sample` percent of the chunks of each group are looked at, selected by their
+    /// digest so the same chunks are sampled in every group. For each one, the stored size and
+    /// the blob header are compared to the uncompressed size recorded in the index, so no chunk
+    /// needs to be decoded.
+    pub fn compute_compression_stats(
+        self: &Arc<Self>,
+        worker: &dyn WorkerTaskContext,
+        upid: &UPID,
+        sample: u64,
+    ) -> Result<CompressionStats, Error> {
+        let mut stats = CompressionStats {
+            upid: Some(upid.to_string()),
+            time: proxmox_time::epoch_i64(),
+            sample,
+            groups: Vec::new(),
+        };
+        let mut unreadable_chunks = 0;
+
+        self.for_each_group_indexes(worker, |ns, group, _snapshot_count, indexes| {
+            let mut group_stats = GroupCompressionStats {
+                ns: (!ns.is_root()).then(|| ns.clone()),
+                backup: group.group().clone(),
+                sampled_chunks: 0,
+                logical_size: 0,
+                stored_size: 0,
+                encrypted_chunks: 0,
+                uncompressed_chunks: 0,
+                compression_ratio: 0.0,
+                encrypted_fraction: 0.0,
+            };
+            // chunks are only sampled once per group
+            let mut group_chunks = HashSet::new();
+
+            for index in indexes {
+                for pos in 0..index.index_count() {
+                    let chunk = index.chunk_info(pos).unwrap();
+                    if !chunk_is_sampled(&chunk.digest, sample)
+                        || !group_chunks.insert(chunk.digest)
+                    {
+                        continue;
+                    }
+
+                    match self.read_chunk_magic(&chunk.digest) {
+                        Ok((stored_size, magic)) => account_sampled_chunk(
+                            &mut group_stats,
+                            chunk.size(),
+                            stored_size,
+                            &magic,
+                        ),
+                        Err(_) => unreadable_chunks += 1,
+                    }
+                }
+            }
+
+            finish_group_compression_stats(&mut group_stats);
+            stats.groups.push(group_stats);
+            Ok(())
+        })?;
+
+        task_log!(
+            worker,
+            "sampled {} chunks of {} groups",
+            stats
+                .groups
+                .iter()
+                .map(|group| group.sampled_chunks)
+                .sum::<u64>(),
+            stats.groups.len(),
+        );
+        if unreadable_chunks > 0 {
+            task_warn!(
+                worker,
+                "{unreadable_chunks} sampled chunks could not be read"
+            );
+        }
+
+        self.save_stats(COMPRESSION_STATS_FILE, &stats)?;
+
+        Ok(stats)
+    }
+
     pub fn garbage_collection(
         &self,
         worker: &dyn WorkerTaskContext,
@@ -1194,15 +1883,100 @@ impl DataStore {
         self.inner.chunk_store.insert_chunk(chunk, digest)
     }
 
+    /// Re-encode a chunk with the compression configured in the datastore tuning options.
+    ///
+    /// The chunk is returned unchanged if no compression is configured, if it is encrypted or
+    /// already stored with the configured algorithm. Chunks already present in the store are not
+    /// recompressed either, inserting them usually just touches the existing copy.
+    pub fn recompress_chunk(&self, chunk: DataBlob, digest: &[u8; 32]) -> Result<DataBlob, Error> {
+        let compression = match self.inner.chunk_compression {
+            Some(compression) => compression,
+            None => return Ok(chunk),
+        };
+
+        if self.chunk_path(digest).0.exists() {
+            return Ok(chunk);
+        }
+
+        Ok(chunk.recompress(compression)?.unwrap_or(chunk))
+    }
+
+    /// Checks if the datastore is currently available, i.e. it is not located on a removable
+    /// device, or that device is (still) mounted.
+    ///
+    /// Long running tasks can use this to detect a device that was detached while running.
+    pub fn is_available(&self) -> bool {
+        match self.inner.backing_device.as_deref() {
+            Some(uuid) => is_device_mounted_on(uuid, &self.base_path()),
+            None => true,
+        }
+    }
+
+    /// Whether the CRC of uploaded chunks should be verified before they are inserted.
+    pub fn verify_upload(&self) -> bool {
+        self.inner.verify_upload
+    }
+
+    /// Insert a chunk by hard linking it from the chunk store of another datastore.
+    ///
+    /// Returns `false` if both datastores are on different file systems.
+    pub fn link_chunk_from(&self, source: &DataStore, digest: &[u8; 32]) -> Result<bool, Error> {
+        self.inner
+            .chunk_store
+            .link_chunk(&source.inner.chunk_store, digest)
+    }
+
+    /// Insert a chunk by creating a reflink copy from the chunk store of another datastore.
+    ///
+    /// Returns `false` if reflinks are not supported, e.g. because both datastores are on
+    /// different file systems.
+    pub fn clone_chunk_from(&self, source: &DataStore, digest: &[u8; 32]) -> Result<bool, Error> {
+        self.inner
+            .chunk_store
+            .clone_chunk(&source.inner.chunk_store, digest)
+    }
+
     pub fn stat_chunk(&self, digest: &[u8; 32]) -> Result<std::fs::Metadata, Error> {
         let (chunk_path, _digest_str) = self.inner.chunk_store.chunk_path(digest);
         std::fs::metadata(chunk_path).map_err(Error::from)
     }
 
+    /// Load a chunk via the chunk cache, or directly from disk if the cache is disabled.
+    pub async fn load_chunk_cached(&self, digest: &[u8; 32]) -> Result<Arc<DataBlob>, Error> {
+        match self.inner.chunk_cache {
+            Some(ref cache) => cache.load_chunk(digest).await,
+            None => {
+                let digest = *digest;
+                let chunk_store = Arc::clone(&self.inner.chunk_store);
+                let blob = tokio::task::spawn_blocking(move || {
+                    let (chunk_path, _) = chunk_store.chunk_path(&digest);
+                    crate::fault_injection::inject_read(&chunk_path)?;
+                    let mut file = std::fs::File::open(&chunk_path)?;
+                    DataBlob::load_from_reader(&mut file)
+                })
+                .await??;
+                Ok(Arc::new(blob))
+            }
+        }
+    }
+
+    /// Put a chunk into the chunk cache, if enabled.
+    pub fn cache_chunk(&self, digest: &[u8; 32], chunk: &DataBlob) {
+        if let Some(ref cache) = self.inner.chunk_cache {
+            cache.insert(digest, Arc::new(chunk.clone()));
+        }
+    }
+
+    /// Returns the statistics of the chunk cache, if enabled.
+    pub fn chunk_cache_stats(&self) -> Option<ChunkCacheStats> {
+        self.inner.chunk_cache.as_ref().map(|cache| cache.stats())
+    }
+
     pub fn load_chunk(&self, digest: &[u8; 32]) -> Result<DataBlob, Error> {
         let (chunk_path, digest_str) = self.inner.chunk_store.chunk_path(digest);
 
         proxmox_lang::try_block!({
+            crate::fault_injection::inject_read(&chunk_path)?;
             let mut file = std::fs::File::open(&chunk_path)?;
             DataBlob::load_from_reader(&mut file)
         })
@@ -1244,6 +2018,28 @@ impl DataStore {
         self.inner.verify_new
     }
 
+    /// Whether backup clients may query which chunks exist anywhere in the datastore
+    pub fn known_chunk_query(&self) -> bool {
+        self.inner.known_chunk_query
+    }
+
+    /// Naming policy for new backups
+    pub fn naming_policy(&self) -> &DatastoreNamingPolicy {
+        &self.inner.naming_policy
+    }
+
+    /// Check if a client announcing `version` is allowed to create backups.
+    ///
+    /// Clients which do not announce a version are rejected if a minimum version is configured.
+    pub fn check_client_version(&self, version: Option<&str>) -> Result<(), Error> {
+        match &self.inner.min_client_version {
+            Some(min_client_version) => {
+                check_min_client_version(self.name(), min_client_version, version)
+            }
+            None => Ok(()),
+        }
+    }
+
     /// returns a list of chunks sorted by their inode number on disk chunks that couldn't get
     /// stat'ed are placed at the end of the list
     pub fn get_chunks_in_order<F, A>(
@@ -1487,3 +2283,153 @@ impl DataStore {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::file_formats::COMPRESSED_BLOB_MAGIC_1_0;
+
+    fn group_stats() -> GroupCompressionStats {
+        GroupCompressionStats {
+            ns: None,
+            backup: "host/test".parse().unwrap(),
+            sampled_chunks: 0,
+            logical_size: 0,
+            stored_size: 0,
+            encrypted_chunks: 0,
+            uncompressed_chunks: 0,
+            compression_ratio: 0.0,
+            encrypted_fraction: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_chunk_is_sampled() {
+        let mut low = [0u8; 32];
+        low[0] = 1;
+        let high = [0xffu8; 32];
+        // 0x8000 selects the middle of the range
+        let mut middle = [0u8; 32];
+        middle[1] = 0x80;
+
+        for digest in [&low, &high, &middle] {
+            assert!(!chunk_is_sampled(digest, 0));
+            assert!(chunk_is_sampled(digest, 100));
+        }
+
+        assert!(chunk_is_sampled(&low, 1));
+        assert!(!chunk_is_sampled(&high, 99));
+        assert!(!chunk_is_sampled(&middle, 50));
+        assert!(chunk_is_sampled(&middle, 51));
+    }
+
+    #[test]
+    fn test_compression_stats_accounting() {
+        let mut stats = group_stats();
+        account_sampled_chunk(&mut stats, 4096, 1024, &COMPRESSED_BLOB_MAGIC_1_0);
+        account_sampled_chunk(&mut stats, 4096, 4096, &UNCOMPRESSED_BLOB_MAGIC_1_0);
+        account_sampled_chunk(&mut stats, 4096, 2048, &ENCR_COMPR_BLOB_MAGIC_1_0);
+        account_sampled_chunk(&mut stats, 4096, 1024, &ENCRYPTED_BLOB_MAGIC_1_0);
+        finish_group_compression_stats(&mut stats);
+
+        assert_eq!(stats.sampled_chunks, 4);
+        assert_eq!(stats.logical_size, 16384);
+        assert_eq!(stats.stored_size, 8192);
+        assert_eq!(stats.encrypted_chunks, 2);
+        assert_eq!(stats.uncompressed_chunks, 2);
+        assert_eq!(stats.compression_ratio, 2.0);
+        assert_eq!(stats.encrypted_fraction, 0.5);
+    }
+
+    #[test]
+    fn test_dedup_stats_accounting() {
+        let group = |referenced_size| GroupDedupStats {
+            ns: None,
+            backup: "host/test".parse().unwrap(),
+            snapshot_count: 1,
+            logical_size: 0,
+            referenced_size,
+            unique_size: 0,
+            shared_size: 0,
+        };
+        let mut stats = DedupStats {
+            groups: vec![group(300), group(200), group(0)],
+            ..Default::default()
+        };
+        let chunks = [
+            // only in the first group
+            ChunkUsage {
+                size: 100,
+                groups: 1,
+                first_group: 0,
+            },
+            // shared by the first two groups
+            ChunkUsage {
+                size: 200,
+                groups: 2,
+                first_group: 0,
+            },
+        ];
+        finish_dedup_stats(&mut stats, chunks.iter());
+
+        assert_eq!(stats.disk_chunks, 2);
+        assert_eq!(stats.disk_size, 300);
+        assert_eq!(
+            (stats.groups[0].unique_size, stats.groups[0].shared_size),
+            (100, 200)
+        );
+        assert_eq!(
+            (stats.groups[1].unique_size, stats.groups[1].shared_size),
+            (0, 200)
+        );
+        assert_eq!(
+            (stats.groups[2].unique_size, stats.groups[2].shared_size),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn test_compression_stats_empty_group() {
+        let mut stats = group_stats();
+        finish_group_compression_stats(&mut stats);
+
+        assert_eq!(stats.compression_ratio, 0.0);
+        assert_eq!(stats.encrypted_fraction, 0.0);
+    }
+
+    #[test]
+    fn test_check_min_client_version() {
+        let min = ("3.2".to_string(), parse_client_version("3.2").unwrap());
+        let check = |version| check_min_client_version("store1", &min, version);
+
+        assert!(check(Some("3.2")).is_ok());
+        assert!(check(Some("3.2.0")).is_ok());
+        assert!(check(Some("3.10.1")).is_ok());
+        assert!(check(Some("4.0")).is_ok());
+
+        let err = check(Some("3.1.9")).unwrap_err().to_string();
+        assert!(err.contains("older than the minimum version 3.2"));
+        assert!(err.contains("please upgrade"));
+
+        // old clients do not report their version at all
+        let err = check(None).unwrap_err().to_string();
+        assert!(err.contains("did not report its version"));
+
+        let err = check(Some("garbage")).unwrap_err().to_string();
+        assert!(err.contains("invalid version 'garbage'"));
+    }
+
+    #[test]
+    fn test_is_datastore_available() {
+        let mut config = DataStoreConfig::new("test".to_string(), "/".to_string());
+        assert!(is_datastore_available(&config));
+
+        // no such device
+        config.backing_device = Some("00000000-0000-0000-0000-000000000000".to_string());
+        assert!(!is_datastore_available(&config));
+
+        // not a block device, so nothing can be mounted from it
+        assert!(!is_device_mounted_on("../../null", Path::new("/")));
+    }
+}
@@ -21,7 +21,7 @@ use crate::chunk_stat::ChunkStat;
 use crate::chunk_store::ChunkStore;
 use crate::data_blob::{DataBlob, DataChunkBuilder};
 use crate::file_formats;
-use crate::index::{ChunkReadInfo, IndexFile};
+use crate::index::{advise_mapped_index, ChunkReadInfo, IndexAccessPattern, IndexFile};
 use crate::read_chunk::ReadChunk;
 use crate::Chunker;
 
@@ -95,6 +95,11 @@ impl DynamicIndexReader {
         &self.index
     }
 
+    /// Open the same index again, for example to read it with multiple readers.
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        Self::new(self._file.try_clone()?)
+    }
+
     pub fn new(mut file: std::fs::File) -> Result<Self, Error> {
         // FIXME: This is NOT OUR job! Check the callers of this method and remove this!
         file.seek(SeekFrom::Start(0))?;
@@ -267,6 +272,10 @@ impl IndexFile for DynamicIndexReader {
 
         Some((found_idx, offset - found_start))
     }
+
+    fn advise(&self, pattern: IndexAccessPattern) {
+        advise_mapped_index(&self.index[..], pattern);
+    }
 }
 
 /// Create dynamic index files (`.dixd`)
@@ -354,12 +363,18 @@ impl DynamicIndexWriter {
         let csum = self.csum.take().unwrap();
         let index_csum = csum.finish();
 
-        self.writer.write_all(&index_csum)?;
+        self.writer.write_all(crate::fault_injection::inject_write(
+            &self.tmp_filename,
+            &index_csum,
+        )?)?;
         self.writer.flush()?;
 
-        if let Err(err) = std::fs::rename(&self.tmp_filename, &self.filename) {
-            bail!("Atomic rename file {:?} failed - {}", self.filename, err);
-        }
+        crate::index_journal::commit_index(
+            self.writer.get_ref(),
+            &self.tmp_filename,
+            &self.filename,
+            &index_csum,
+        )?;
 
         Ok(index_csum)
     }
@@ -380,8 +395,13 @@ impl DynamicIndexWriter {
             csum.update(digest);
         }
 
-        self.writer.write_all(&offset_le)?;
-        self.writer.write_all(digest)?;
+        let mut entry = [0u8; 40];
+        entry[..8].copy_from_slice(&offset_le);
+        entry[8..].copy_from_slice(digest);
+        self.writer.write_all(crate::fault_injection::inject_write(
+            &self.tmp_filename,
+            &entry,
+        )?)?;
         Ok(())
     }
 }
@@ -0,0 +1,310 @@
+//! Fault injection for storage operations
+//!
+//! Developer feature to test the error handling of verify, garbage collection, sync and backups.
+//! With the `fault-injection` feature enabled, reading chunks and writing chunks and index files
+//! can be made to fail with `EIO`, to write only part of the data or to be delayed, according to
+//! a list of [FaultRule]s. The rules are stored in [FAULT_INJECTION_RULES_FN], so they apply to
+//! all daemons and can be changed at runtime. Without the feature, the hooks do nothing.
+//!
+//! Index files are hooked per entry and for the final checksum, so a write rule with a
+//! probability below 1 corrupts single entries of an index.
+
+use std::path::Path;
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::api;
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+
+/// File containing the active fault injection rules.
+pub const FAULT_INJECTION_RULES_FN: &str = concat!(
+    pbs_buildcfg::PROXMOX_BACKUP_RUN_DIR_M!(),
+    "/fault-injection.json"
+);
+
+#[api]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Storage operation a fault is injected into.
+pub enum FaultOperation {
+    /// Reading a chunk
+    Read,
+    /// Writing a chunk or an index file
+    Write,
+}
+
+#[api]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Kind of an injected fault.
+pub enum FaultKind {
+    /// Fail with an I/O error (EIO)
+    Error,
+    /// Only write the first half of the data, without reporting an error
+    ShortWrite,
+    /// Delay the operation
+    Delay,
+}
+
+#[api(
+    properties: {
+        operation: {
+            type: FaultOperation,
+        },
+        kind: {
+            type: FaultKind,
+        },
+        path: {
+            type: String,
+            optional: true,
+        },
+        probability: {
+            type: Number,
+            optional: true,
+            minimum: 0.0,
+            maximum: 1.0,
+            default: 1.0,
+        },
+        delay: {
+            type: Integer,
+            optional: true,
+            minimum: 0,
+            default: 1000,
+        },
+    },
+)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Rule for injecting faults into storage operations.
+pub struct FaultRule {
+    pub operation: FaultOperation,
+    pub kind: FaultKind,
+    /// Only inject the fault if the file path contains this string (default: all files)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Probability of injecting the fault into a matching operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probability: Option<f64>,
+    /// Delay in milliseconds, for faults of kind 'delay'
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay: Option<u64>,
+}
+
+/// Read the active fault injection rules.
+pub fn load_rules() -> Result<Vec<FaultRule>, Error> {
+    match file_read_optional_string(FAULT_INJECTION_RULES_FN)? {
+        Some(data) => Ok(serde_json::from_str(&data)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Replace the active fault injection rules, an empty list removes the rules file.
+pub fn save_rules(rules: &[FaultRule]) -> Result<(), Error> {
+    if rules.is_empty() {
+        match std::fs::remove_file(FAULT_INJECTION_RULES_FN) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => return Ok(()),
+        }
+    }
+
+    // readable by the proxy, which does most of the storage operations
+    let backup_user = pbs_config::backup_user()?;
+    let options = CreateOptions::new()
+        .perm(nix::sys::stat::Mode::from_bits_truncate(0o644))
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    replace_file(
+        FAULT_INJECTION_RULES_FN,
+        serde_json::to_string(rules)?.as_bytes(),
+        options,
+        false,
+    )
+}
+
+/// The first of `rules` matching `operation` on `path`.
+#[cfg_attr(not(feature = "fault-injection"), allow(dead_code))]
+fn find_rule<'a>(
+    rules: &'a [FaultRule],
+    operation: FaultOperation,
+    path: &str,
+) -> Option<&'a FaultRule> {
+    rules.iter().find(|rule| {
+        rule.operation == operation
+            && rule
+                .path
+                .as_deref()
+                .map_or(true, |part| path.contains(part))
+    })
+}
+
+/// Decide whether a fault with `probability` is injected, `random` is uniformly distributed.
+#[cfg_attr(not(feature = "fault-injection"), allow(dead_code))]
+fn roll(probability: f64, random: u32) -> bool {
+    probability >= 1.0 || (random as f64 / (u32::MAX as f64 + 1.0)) < probability
+}
+
+#[cfg(feature = "fault-injection")]
+mod imp {
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
+
+    use super::{FaultOperation, FaultRule, FAULT_INJECTION_RULES_FN};
+
+    /// Rules cached along with the modification time of the rules file.
+    static RULES: Mutex<Option<(SystemTime, Arc<Vec<FaultRule>>)>> = Mutex::new(None);
+
+    fn current_rules() -> Arc<Vec<FaultRule>> {
+        let mtime = match std::fs::metadata(FAULT_INJECTION_RULES_FN).and_then(|m| m.modified()) {
+            Ok(mtime) => mtime,
+            Err(_) => return Arc::new(Vec::new()),
+        };
+
+        let mut cached = RULES.lock().unwrap();
+        match &*cached {
+            Some((cached_mtime, rules)) if *cached_mtime == mtime => Arc::clone(rules),
+            _ => {
+                let rules = Arc::new(super::load_rules().unwrap_or_else(|err| {
+                    log::error!("unable to load fault injection rules - {err}");
+                    Vec::new()
+                }));
+                *cached = Some((mtime, Arc::clone(&rules)));
+                rules
+            }
+        }
+    }
+
+    fn random() -> Option<u32> {
+        let mut buf = [0u8; 4];
+        openssl::rand::rand_bytes(&mut buf).ok()?;
+        Some(u32::from_le_bytes(buf))
+    }
+
+    /// The first rule matching an operation on `path`, if its fault is to be injected.
+    pub(super) fn matching_rule(operation: FaultOperation, path: &Path) -> Option<FaultRule> {
+        let rules = current_rules();
+        super::find_rule(&rules, operation, &path.to_string_lossy())
+            .filter(|rule| match random() {
+                Some(random) => super::roll(rule.probability.unwrap_or(1.0), random),
+                None => false,
+            })
+            .cloned()
+    }
+
+    pub(super) fn delay(rule: &FaultRule) {
+        std::thread::sleep(Duration::from_millis(rule.delay.unwrap_or(1000)));
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+fn injected_error(path: &Path) -> Error {
+    log::warn!("injecting I/O error for {path:?}");
+    std::io::Error::from_raw_os_error(libc::EIO).into()
+}
+
+/// Hook for reading the file at `path`.
+#[cfg(feature = "fault-injection")]
+pub fn inject_read(path: &Path) -> Result<(), Error> {
+    match imp::matching_rule(FaultOperation::Read, path) {
+        Some(rule) if rule.kind == FaultKind::Error => Err(injected_error(path)),
+        Some(rule) if rule.kind == FaultKind::Delay => {
+            imp::delay(&rule);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Hook for reading the file at `path`.
+#[cfg(not(feature = "fault-injection"))]
+#[inline(always)]
+pub fn inject_read(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Hook for writing `data` to the file at `path`, returns the data to actually write.
+#[cfg(feature = "fault-injection")]
+pub fn inject_write<'a>(path: &Path, data: &'a [u8]) -> Result<&'a [u8], Error> {
+    match imp::matching_rule(FaultOperation::Write, path) {
+        Some(rule) => match rule.kind {
+            FaultKind::Error => Err(injected_error(path)),
+            FaultKind::ShortWrite => {
+                log::warn!("injecting short write for {path:?}");
+                Ok(&data[..data.len() / 2])
+            }
+            FaultKind::Delay => {
+                imp::delay(&rule);
+                Ok(data)
+            }
+        },
+        None => Ok(data),
+    }
+}
+
+/// Hook for writing `data` to the file at `path`, returns the data to actually write.
+#[cfg(not(feature = "fault-injection"))]
+#[inline(always)]
+pub fn inject_write<'a>(_path: &Path, data: &'a [u8]) -> Result<&'a [u8], Error> {
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(operation: FaultOperation, kind: FaultKind, path: Option<&str>) -> FaultRule {
+        FaultRule {
+            operation,
+            kind,
+            path: path.map(String::from),
+            probability: None,
+            delay: None,
+        }
+    }
+
+    #[test]
+    fn test_find_rule() {
+        let rules = [
+            rule(FaultOperation::Read, FaultKind::Delay, Some("/.chunks/ab")),
+            rule(FaultOperation::Read, FaultKind::Error, None),
+            rule(FaultOperation::Write, FaultKind::ShortWrite, Some(".fidx")),
+        ];
+
+        let kind = |operation, path| find_rule(&rules, operation, path).map(|rule| rule.kind);
+
+        // the first matching rule wins
+        assert_eq!(
+            kind(FaultOperation::Read, "/store/.chunks/abcd/abcdef"),
+            Some(FaultKind::Delay)
+        );
+        assert_eq!(
+            kind(FaultOperation::Read, "/store/.chunks/cdef/cdef01"),
+            Some(FaultKind::Error)
+        );
+        assert_eq!(
+            kind(
+                FaultOperation::Write,
+                "/store/vm/100/2024-01-01T00:00:00Z/drive.fidx"
+            ),
+            Some(FaultKind::ShortWrite)
+        );
+        assert_eq!(
+            kind(FaultOperation::Write, "/store/.chunks/abcd/abcdef"),
+            None
+        );
+        assert!(find_rule(&[], FaultOperation::Read, "/store/.chunks/ab").is_none());
+    }
+
+    #[test]
+    fn test_roll() {
+        for random in [0, 1, u32::MAX / 2, u32::MAX] {
+            assert!(roll(1.0, random));
+            assert!(!roll(0.0, random));
+        }
+        assert!(roll(0.5, 0));
+        assert!(roll(0.5, u32::MAX / 2));
+        assert!(!roll(0.5, u32::MAX / 2 + 1));
+        assert!(!roll(0.5, u32::MAX));
+    }
+}
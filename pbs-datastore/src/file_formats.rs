@@ -11,6 +11,9 @@ pub const UNCOMPRESSED_BLOB_MAGIC_1_0: [u8; 8] = [66, 171, 56, 7, 190, 131, 112,
 //openssl::sha::sha256(b"Proxmox Backup zstd compressed blob v1.0")[0..8]
 pub const COMPRESSED_BLOB_MAGIC_1_0: [u8; 8] = [49, 185, 88, 66, 111, 182, 163, 127];
 
+// openssl::sha::sha256(b"Proxmox Backup lz4 compressed blob v1.0")[0..8]
+pub const LZ4_COMPRESSED_BLOB_MAGIC_1_0: [u8; 8] = [213, 216, 187, 143, 197, 88, 198, 147];
+
 // openssl::sha::sha256(b"Proxmox Backup encrypted blob v1.0")[0..8]
 pub const ENCRYPTED_BLOB_MAGIC_1_0: [u8; 8] = [123, 103, 133, 190, 34, 45, 76, 240];
 
@@ -66,6 +69,7 @@ pub fn header_size(magic: &[u8; 8]) -> usize {
     match *magic {
         UNCOMPRESSED_BLOB_MAGIC_1_0 => std::mem::size_of::<DataBlobHeader>(),
         COMPRESSED_BLOB_MAGIC_1_0 => std::mem::size_of::<DataBlobHeader>(),
+        LZ4_COMPRESSED_BLOB_MAGIC_1_0 => std::mem::size_of::<DataBlobHeader>(),
         ENCRYPTED_BLOB_MAGIC_1_0 => std::mem::size_of::<EncryptedDataBlobHeader>(),
         ENCR_COMPR_BLOB_MAGIC_1_0 => std::mem::size_of::<EncryptedDataBlobHeader>(),
         _ => panic!("unknown blob magic"),
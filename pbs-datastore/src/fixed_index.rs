@@ -8,6 +8,7 @@ use std::sync::Arc;
 use anyhow::{bail, format_err, Error};
 
 use proxmox_io::ReadExt;
+use proxmox_sys::mmap::Mmap;
 use proxmox_sys::process_locker::ProcessLockSharedGuard;
 use proxmox_uuid::Uuid;
 
@@ -15,7 +16,7 @@ use crate::chunk_stat::ChunkStat;
 use crate::chunk_store::ChunkStore;
 use crate::data_blob::ChunkInfo;
 use crate::file_formats;
-use crate::index::{ChunkReadInfo, IndexFile};
+use crate::index::{advise_mapped_index, ChunkReadInfo, IndexAccessPattern, IndexFile};
 
 /// Header format definition for fixed index files (`.fidx`)
 #[repr(C)]
@@ -37,8 +38,7 @@ pub struct FixedIndexReader {
     _file: File,
     pub chunk_size: usize,
     pub size: u64,
-    index_length: usize,
-    index: *mut u8,
+    index: Mmap<[u8; 32]>,
     pub uuid: [u8; 16],
     pub ctime: i64,
     pub index_csum: [u8; 32],
@@ -48,14 +48,6 @@ pub struct FixedIndexReader {
 unsafe impl Send for FixedIndexReader {}
 unsafe impl Sync for FixedIndexReader {}
 
-impl Drop for FixedIndexReader {
-    fn drop(&mut self) {
-        if let Err(err) = self.unmap() {
-            log::error!("Unable to unmap file - {}", err);
-        }
-    }
-}
-
 impl FixedIndexReader {
     pub fn open(path: &Path) -> Result<Self, Error> {
         File::open(path)
@@ -102,60 +94,39 @@ impl FixedIndexReader {
             );
         }
 
-        let data = unsafe {
-            nix::sys::mman::mmap(
-                None,
-                std::num::NonZeroUsize::new(index_size)
-                    .ok_or_else(|| format_err!("invalid index size"))?,
+        if index_length == 0 {
+            bail!("invalid index size");
+        }
+
+        let index = unsafe {
+            Mmap::map_fd(
+                file.as_raw_fd(),
+                header_size as u64,
+                index_length,
                 nix::sys::mman::ProtFlags::PROT_READ,
                 nix::sys::mman::MapFlags::MAP_PRIVATE,
-                file.as_raw_fd(),
-                header_size as i64,
-            )
-        }? as *mut u8;
+            )?
+        };
 
         Ok(Self {
             _file: file,
             chunk_size: chunk_size as usize,
             size,
-            index_length,
-            index: data,
+            index,
             ctime,
             uuid: header.uuid,
             index_csum: header.index_csum,
         })
     }
-
-    fn unmap(&mut self) -> Result<(), Error> {
-        if self.index.is_null() {
-            return Ok(());
-        }
-
-        let index_size = self.index_length * 32;
-
-        if let Err(err) =
-            unsafe { nix::sys::mman::munmap(self.index as *mut std::ffi::c_void, index_size) }
-        {
-            bail!("unmap file failed - {}", err);
-        }
-
-        self.index = std::ptr::null_mut();
-
-        Ok(())
-    }
 }
 
 impl IndexFile for FixedIndexReader {
     fn index_count(&self) -> usize {
-        self.index_length
+        self.index.len()
     }
 
     fn index_digest(&self, pos: usize) -> Option<&[u8; 32]> {
-        if pos >= self.index_length {
-            None
-        } else {
-            Some(unsafe { &*(self.index.add(pos * 32) as *const [u8; 32]) })
-        }
+        self.index.get(pos)
     }
 
     fn index_bytes(&self) -> u64 {
@@ -163,7 +134,7 @@ impl IndexFile for FixedIndexReader {
     }
 
     fn chunk_info(&self, pos: usize) -> Option<ChunkReadInfo> {
-        if pos >= self.index_length {
+        if pos >= self.index.len() {
             return None;
         }
 
@@ -212,6 +183,10 @@ impl IndexFile for FixedIndexReader {
             offset & (self.chunk_size - 1) as u64, // fast modulo, valid for 2^x chunk_size
         ))
     }
+
+    fn advise(&self, pattern: IndexAccessPattern) {
+        advise_mapped_index(&self.index[..], pattern);
+    }
 }
 
 pub struct FixedIndexWriter {
@@ -351,12 +326,18 @@ impl FixedIndexWriter {
 
         let csum_offset = proxmox_lang::offsetof!(FixedIndexHeader, index_csum);
         self.file.seek(SeekFrom::Start(csum_offset as u64))?;
-        self.file.write_all(&index_csum)?;
+        self.file.write_all(crate::fault_injection::inject_write(
+            &self.tmp_filename,
+            &index_csum,
+        )?)?;
         self.file.flush()?;
 
-        if let Err(err) = std::fs::rename(&self.tmp_filename, &self.filename) {
-            bail!("Atomic rename file {:?} failed - {}", self.filename, err);
-        }
+        crate::index_journal::commit_index(
+            &self.file,
+            &self.tmp_filename,
+            &self.filename,
+            &index_csum,
+        )?;
 
         Ok(index_csum)
     }
@@ -438,10 +419,13 @@ impl FixedIndexWriter {
             bail!("cannot write to closed index file.");
         }
 
+        // a short write leaves the rest of the digest zeroed
+        let data = crate::fault_injection::inject_write(&self.tmp_filename, digest)?;
+
         let index_pos = index * 32;
         unsafe {
             let dst = self.index.add(index_pos);
-            dst.copy_from_nonoverlapping(digest.as_ptr(), 32);
+            dst.copy_from_nonoverlapping(data.as_ptr(), data.len());
         }
 
         Ok(())
@@ -459,3 +443,90 @@ impl FixedIndexWriter {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_store::create_test_chunk_store;
+
+    const CHUNK_SIZE: usize = 4096;
+
+    // writes a fixed index over `size` bytes, using the chunk position as digest
+    fn write_index(store: &Arc<ChunkStore>, name: &str, size: usize) -> PathBuf {
+        let mut writer =
+            FixedIndexWriter::create(Arc::clone(store), Path::new(name), size, CHUNK_SIZE).unwrap();
+        for pos in 0..writer.index_length() {
+            writer.add_digest(pos, &[pos as u8; 32]).unwrap();
+        }
+        writer.close().unwrap();
+
+        store.relative_path(Path::new(name))
+    }
+
+    #[test]
+    fn test_mapped_fixed_index() -> Result<(), Error> {
+        let store = Arc::new(create_test_chunk_store("fixed_index_mapped"));
+        let path = write_index(&store, "test.fidx", 3 * CHUNK_SIZE + 100);
+
+        let reader = FixedIndexReader::open(&path)?;
+        assert_eq!(reader.index_count(), 4);
+        assert_eq!(reader.index_bytes(), 3 * CHUNK_SIZE as u64 + 100);
+        assert_eq!(reader.index_digest(2), Some(&[2u8; 32]));
+        assert_eq!(reader.index_digest(4), None);
+
+        let info = reader.chunk_info(3).unwrap();
+        assert_eq!(
+            info.range,
+            3 * CHUNK_SIZE as u64..3 * CHUNK_SIZE as u64 + 100
+        );
+        assert_eq!(info.digest, [3u8; 32]);
+        assert!(reader.chunk_info(4).is_none());
+
+        assert_eq!(
+            reader.chunk_from_offset(CHUNK_SIZE as u64 + 10),
+            Some((1, 10))
+        );
+        assert_eq!(reader.chunk_from_offset(4 * CHUNK_SIZE as u64), None);
+
+        let (csum, end) = reader.compute_csum();
+        assert_eq!(csum, reader.index_csum);
+        assert_eq!(end, reader.index_bytes());
+
+        // the hints must not change what we read, even after dropping the pages
+        for pattern in [
+            IndexAccessPattern::Sequential,
+            IndexAccessPattern::Random,
+            IndexAccessPattern::DontNeed,
+            IndexAccessPattern::Normal,
+        ] {
+            reader.advise(pattern);
+            for pos in 0..reader.index_count() {
+                assert_eq!(reader.index_digest(pos), Some(&[pos as u8; 32]));
+            }
+        }
+
+        let _ = std::fs::remove_dir_all(store.base_path());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_fixed_index() -> Result<(), Error> {
+        let store = Arc::new(create_test_chunk_store("fixed_index_empty"));
+        let path = write_index(&store, "test.fidx", CHUNK_SIZE);
+
+        // keep only the header and claim an image size of zero
+        let header_size = std::mem::size_of::<FixedIndexHeader>();
+        let mut data = std::fs::read(&path)?;
+        data.truncate(header_size);
+        let size_offset = proxmox_lang::offsetof!(FixedIndexHeader, size);
+        data[size_offset..size_offset + 8].copy_from_slice(&0u64.to_le_bytes());
+        std::fs::write(&path, &data)?;
+
+        assert!(FixedIndexReader::open(&path).is_err());
+
+        let _ = std::fs::remove_dir_all(store.base_path());
+
+        Ok(())
+    }
+}
@@ -1,6 +1,44 @@
 use std::collections::HashMap;
 use std::ops::Range;
 
+/// Expected access pattern of an index, used as hint for the kernel's paging of the memory mapped
+/// index data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexAccessPattern {
+    /// No special treatment.
+    Normal,
+    /// The index is read from start to end once, pages can be read ahead aggressively and
+    /// dropped soon after use.
+    Sequential,
+    /// The index is accessed at random positions, read-ahead is of no use.
+    Random,
+    /// The index is not needed for now, drop the pages from our resident memory. They will be
+    /// read in again on the next access.
+    DontNeed,
+}
+
+/// Pass `pattern` on to the kernel for the memory mapped `index` data.
+pub(crate) fn advise_mapped_index<T>(index: &[T], pattern: IndexAccessPattern) {
+    use nix::sys::mman::{madvise, MmapAdvise};
+
+    let advice = match pattern {
+        IndexAccessPattern::Normal => MmapAdvise::MADV_NORMAL,
+        IndexAccessPattern::Sequential => MmapAdvise::MADV_SEQUENTIAL,
+        IndexAccessPattern::Random => MmapAdvise::MADV_RANDOM,
+        IndexAccessPattern::DontNeed => MmapAdvise::MADV_DONTNEED,
+    };
+
+    let length = std::mem::size_of_val(index);
+    if length == 0 {
+        return;
+    }
+
+    // the mapping starts after the page sized header, so it is page aligned
+    if let Err(err) = unsafe { madvise(index.as_ptr() as *mut std::ffi::c_void, length, advice) } {
+        log::warn!("madvise {:?} on index failed - {}", pattern, err);
+    }
+}
+
 #[derive(Clone)]
 pub struct ChunkReadInfo {
     pub range: Range<u64>,
@@ -31,6 +69,11 @@ pub trait IndexFile {
     /// Compute index checksum and size
     fn compute_csum(&self) -> ([u8; 32], u64);
 
+    /// Hint how the index is going to be accessed, see [`IndexAccessPattern`].
+    ///
+    /// Failing to apply the hint is not fatal, so only a warning is logged.
+    fn advise(&self, _pattern: IndexAccessPattern) {}
+
     /// Returns most often used chunks
     fn find_most_used_chunks(&self, max: usize) -> HashMap<[u8; 32], usize> {
         let mut map = HashMap::new();
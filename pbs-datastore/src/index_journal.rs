@@ -0,0 +1,306 @@
+//! Write-ahead journal for committing index files
+//!
+//! Index writers create their file under a temporary name and rename it
+//! into place on close. To make this crash-consistent, the temporary file
+//! is synced first, then a small journal entry (`<index>.journal`) is
+//! written next to it, the file is renamed and the parent directory is
+//! synced. The journal entry is removed once the rename is durable.
+//!
+//! A journal entry left over after a crash means the commit of that index
+//! was interrupted, [replay_journals] finishes or discards it.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{replace_file, CreateOptions};
+
+use crate::dynamic_index::DynamicIndexReader;
+use crate::fixed_index::FixedIndexReader;
+use crate::index::IndexFile;
+use crate::manifest::{archive_type, ArchiveType};
+
+/// File name extension of journal entries
+pub const JOURNAL_EXT: &str = "journal";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct JournalEntry {
+    /// Temporary file name (in the same directory)
+    tmp_name: String,
+    /// Checksum of the committed index
+    #[serde(with = "hex::serde")]
+    csum: [u8; 32],
+}
+
+/// Action taken when replaying a journal entry
+#[derive(Debug, PartialEq, Eq)]
+pub enum JournalReplay {
+    /// The temporary file was complete and got renamed into place
+    Committed(PathBuf),
+    /// The rename already happened, only the journal entry was left
+    AlreadyCommitted(PathBuf),
+    /// The temporary file did not match the journal and got removed
+    Discarded(PathBuf),
+    /// Neither the temporary nor the final file exist
+    Lost(PathBuf),
+}
+
+impl std::fmt::Display for JournalReplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JournalReplay::Committed(path) => write!(f, "committed interrupted index {path:?}"),
+            JournalReplay::AlreadyCommitted(path) => {
+                write!(f, "index {path:?} was already committed")
+            }
+            JournalReplay::Discarded(path) => {
+                write!(f, "discarded incomplete index {path:?}")
+            }
+            JournalReplay::Lost(path) => write!(f, "index {path:?} got lost"),
+        }
+    }
+}
+
+fn journal_path(path: &Path) -> PathBuf {
+    let mut journal = path.as_os_str().to_owned();
+    journal.push(".");
+    journal.push(JOURNAL_EXT);
+    PathBuf::from(journal)
+}
+
+/// Sync the directory containing `path`, so that renames and unlinks within
+/// it are durable.
+pub fn sync_parent_dir(path: &Path) -> Result<(), Error> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| format_err!("unable to get parent directory of {path:?}"))?;
+    File::open(parent)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|err| format_err!("fsync of directory {parent:?} failed - {err}"))
+}
+
+/// Atomically move the finished index file `tmp_path` to `path`.
+///
+/// `file` is the open temporary file, it is synced before anything else.
+pub fn commit_index(
+    file: &File,
+    tmp_path: &Path,
+    path: &Path,
+    csum: &[u8; 32],
+) -> Result<(), Error> {
+    file.sync_all()
+        .map_err(|err| format_err!("fsync of {tmp_path:?} failed - {err}"))?;
+
+    let tmp_name = tmp_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format_err!("invalid temporary file name {tmp_path:?}"))?;
+    if tmp_path.parent() != path.parent() {
+        bail!("temporary file {tmp_path:?} not in the same directory as {path:?}");
+    }
+
+    let entry = JournalEntry {
+        tmp_name: tmp_name.to_string(),
+        csum: *csum,
+    };
+    let journal = journal_path(path);
+    replace_file(
+        &journal,
+        &serde_json::to_vec(&entry)?,
+        CreateOptions::new(),
+        true,
+    )?;
+
+    if let Err(err) = std::fs::rename(tmp_path, path) {
+        let _ = std::fs::remove_file(&journal);
+        bail!("Atomic rename file {:?} failed - {}", path, err);
+    }
+    sync_parent_dir(path)?;
+
+    // a left-over entry is harmless, the replay notices the finished rename
+    let _ = std::fs::remove_file(&journal);
+
+    Ok(())
+}
+
+// the archive type is derived from the final name, temporary files use other extensions
+fn index_csum(path: &Path, tmp_path: &Path) -> Result<[u8; 32], Error> {
+    let index: Box<dyn IndexFile> = match archive_type(path)? {
+        ArchiveType::FixedIndex => Box::new(FixedIndexReader::open(tmp_path)?),
+        ArchiveType::DynamicIndex => Box::new(DynamicIndexReader::open(tmp_path)?),
+        ArchiveType::Blob => bail!("{path:?} is not an index file"),
+    };
+    Ok(index.compute_csum().0)
+}
+
+fn replay_entry(journal: &Path) -> Result<JournalReplay, Error> {
+    let path = journal.with_extension("");
+    let entry: JournalEntry = serde_json::from_slice(&std::fs::read(journal)?)
+        .map_err(|err| format_err!("unable to parse journal entry {journal:?} - {err}"))?;
+    let tmp_path = path.with_file_name(&entry.tmp_name);
+
+    let action = if tmp_path.exists() {
+        // the journal entry is only written after syncing the file, but
+        // don't trust a file which does not match it
+        match index_csum(&path, &tmp_path) {
+            Ok(csum) if csum == entry.csum => {
+                std::fs::rename(&tmp_path, &path)?;
+                JournalReplay::Committed(path.clone())
+            }
+            _ => {
+                std::fs::remove_file(&tmp_path)?;
+                JournalReplay::Discarded(path.clone())
+            }
+        }
+    } else if path.exists() {
+        JournalReplay::AlreadyCommitted(path.clone())
+    } else {
+        JournalReplay::Lost(path.clone())
+    };
+
+    std::fs::remove_file(journal)?;
+    sync_parent_dir(&path)?;
+
+    Ok(action)
+}
+
+/// Replay all journal entries left in directory `dir`.
+pub fn replay_journals(dir: &Path) -> Result<Vec<JournalReplay>, Error> {
+    let mut actions = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(JOURNAL_EXT) {
+            continue;
+        }
+        actions.push(replay_entry(&path)?);
+    }
+
+    Ok(actions)
+}
+
+#[test]
+fn test_journal_path() {
+    assert_eq!(
+        journal_path(Path::new("/store/vm/100/snap/drive-scsi0.img.fidx")),
+        Path::new("/store/vm/100/snap/drive-scsi0.img.fidx.journal"),
+    );
+    assert_eq!(
+        journal_path(Path::new("/store/vm/100/snap/drive-scsi0.img.fidx")).with_extension(""),
+        Path::new("/store/vm/100/snap/drive-scsi0.img.fidx"),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::chunk_store::create_test_chunk_store;
+    use crate::dynamic_index::DynamicIndexWriter;
+
+    fn write_journal(path: &Path, tmp_name: &str, csum: [u8; 32]) {
+        let entry = JournalEntry {
+            tmp_name: tmp_name.to_string(),
+            csum,
+        };
+        std::fs::write(journal_path(path), serde_json::to_vec(&entry).unwrap()).unwrap();
+    }
+
+    // writes a committed dynamic index with a few entries, returning its checksum
+    fn write_index(store: &Arc<crate::ChunkStore>, name: &str) -> [u8; 32] {
+        let mut writer = DynamicIndexWriter::create(Arc::clone(store), Path::new(name)).unwrap();
+        for offset in 1..4u64 {
+            writer
+                .add_chunk(offset * 4096, &[offset as u8; 32])
+                .unwrap();
+        }
+        writer.close().unwrap()
+    }
+
+    #[test]
+    fn test_commit_index() -> Result<(), Error> {
+        let store = create_test_chunk_store("index_journal_commit");
+        let base = store.base_path();
+
+        let tmp_path = base.join("test.tmp_didx");
+        let path = base.join("test.didx");
+        let file = File::create(&tmp_path)?;
+        commit_index(&file, &tmp_path, &path, &[0u8; 32])?;
+
+        assert!(path.exists());
+        assert!(!tmp_path.exists());
+        assert!(!journal_path(&path).exists());
+
+        // the rename must stay within a directory
+        let tmp_path = base.join(".chunks").join("test.tmp_didx");
+        let file = File::create(&tmp_path)?;
+        assert!(commit_index(&file, &tmp_path, &path, &[0u8; 32]).is_err());
+
+        let _ = std::fs::remove_dir_all(&base);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_journals() -> Result<(), Error> {
+        let store = Arc::new(create_test_chunk_store("index_journal_replay"));
+        let base = store.base_path();
+
+        // nothing to do after a regular commit
+        let csum = write_index(&store, "done.didx");
+        assert!(replay_journals(&base)?.is_empty());
+
+        // interrupted before the rename, complete temporary file
+        let path = base.join("interrupted.didx");
+        let csum_interrupted = write_index(&store, "interrupted.didx");
+        std::fs::rename(&path, base.join("interrupted.tmp_didx"))?;
+        write_journal(&path, "interrupted.tmp_didx", csum_interrupted);
+        assert_eq!(
+            replay_entry(&journal_path(&path))?,
+            JournalReplay::Committed(path.clone())
+        );
+        assert!(path.exists());
+        assert!(!base.join("interrupted.tmp_didx").exists());
+
+        // temporary file not matching the journal
+        let path = base.join("mismatch.didx");
+        write_index(&store, "mismatch.didx");
+        std::fs::rename(&path, base.join("mismatch.tmp_didx"))?;
+        write_journal(&path, "mismatch.tmp_didx", [0u8; 32]);
+        assert_eq!(
+            replay_entry(&journal_path(&path))?,
+            JournalReplay::Discarded(path.clone())
+        );
+        assert!(!path.exists());
+        assert!(!base.join("mismatch.tmp_didx").exists());
+
+        // interrupted after the rename
+        let path = base.join("done.didx");
+        write_journal(&path, "done.tmp_didx", csum);
+        assert_eq!(
+            replay_entry(&journal_path(&path))?,
+            JournalReplay::AlreadyCommitted(path.clone())
+        );
+        assert!(path.exists());
+
+        // neither file left
+        let path = base.join("lost.didx");
+        write_journal(&path, "lost.tmp_didx", csum);
+        assert_eq!(
+            replay_journals(&base)?,
+            vec![JournalReplay::Lost(path.clone())]
+        );
+
+        // all journal entries got removed
+        assert!(replay_journals(&base)?.is_empty());
+
+        // unparsable entries are an error
+        std::fs::write(base.join("broken.didx.journal"), b"garbage")?;
+        assert!(replay_journals(&base).is_err());
+
+        let _ = std::fs::remove_dir_all(&base);
+        Ok(())
+    }
+}
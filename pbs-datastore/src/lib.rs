@@ -160,6 +160,9 @@ macro_rules! PROXMOX_BACKUP_PROTOCOL_ID_V1 {
     };
 }
 
+/// Header used by the client to announce its version when starting a backup session.
+pub const CLIENT_VERSION_HEADER: &str = "proxmox-backup-client-version";
+
 #[macro_export]
 macro_rules! PROXMOX_BACKUP_READER_PROTOCOL_ID_V1 {
     () => {
@@ -172,6 +175,8 @@ pub mod cached_chunk_reader;
 pub mod catalog;
 pub mod checksum_reader;
 pub mod checksum_writer;
+pub mod chunk_cache;
+pub mod chunk_ref_tracker;
 pub mod chunk_stat;
 pub mod chunk_store;
 pub mod chunker;
@@ -180,14 +185,17 @@ pub mod crypt_writer;
 pub mod data_blob;
 pub mod data_blob_reader;
 pub mod data_blob_writer;
+pub mod fault_injection;
 pub mod file_formats;
 pub mod index;
+pub mod index_journal;
 pub mod manifest;
 pub mod paperkey;
 pub mod prune;
 pub mod read_chunk;
 pub mod store_progress;
 pub mod task_tracking;
+pub mod write_epoch;
 
 pub mod dynamic_index;
 pub mod fixed_index;
@@ -206,7 +214,9 @@ pub use manifest::BackupManifest;
 pub use store_progress::StoreProgress;
 
 mod datastore;
-pub use datastore::{check_backup_owner, DataStore};
+pub use datastore::{
+    check_backup_owner, is_datastore_available, DataStore, PartialSnapshotReport, RecentChunkReport,
+};
 
 mod hierarchy;
 pub use hierarchy::{
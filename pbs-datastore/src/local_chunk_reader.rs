@@ -16,6 +16,7 @@ pub struct LocalChunkReader {
     store: Arc<DataStore>,
     crypt_config: Option<Arc<CryptConfig>>,
     crypt_mode: CryptMode,
+    prefetch_window: usize,
 }
 
 impl LocalChunkReader {
@@ -28,9 +29,17 @@ impl LocalChunkReader {
             store,
             crypt_config,
             crypt_mode,
+            prefetch_window: 0,
         }
     }
 
+    /// Set the number of chunks sequential readers fetch ahead, see
+    /// [`AsyncReadChunk::prefetch_window`].
+    pub fn with_prefetch_window(mut self, window: usize) -> Self {
+        self.prefetch_window = window;
+        self
+    }
+
     fn ensure_crypt_mode(&self, chunk_mode: CryptMode) -> Result<(), Error> {
         match self.crypt_mode {
             CryptMode::Encrypt => match chunk_mode {
@@ -95,4 +104,8 @@ impl AsyncReadChunk for LocalChunkReader {
             Ok(raw_data)
         })
     }
+
+    fn prefetch_window(&self) -> usize {
+        self.prefetch_window
+    }
 }
@@ -2,6 +2,7 @@ use std::future::Future;
 use std::pin::Pin;
 
 use anyhow::Error;
+use futures::stream::{Stream, StreamExt};
 
 use crate::data_blob::DataBlob;
 
@@ -26,4 +27,29 @@ pub trait AsyncReadChunk: Send + Sync {
         &'a self,
         digest: &'a [u8; 32],
     ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>> + Send + 'a>>;
+
+    /// Number of chunks sequential readers should fetch ahead of the one currently in use.
+    fn prefetch_window(&self) -> usize {
+        0
+    }
+}
+
+/// Read the decoded chunks for `digests` in order.
+///
+/// While a chunk is consumed, up to `reader.prefetch_window()` of the following chunks are
+/// fetched concurrently.
+pub fn read_chunks_prefetched<'a, R, I>(
+    reader: &'a R,
+    digests: I,
+) -> impl Stream<Item = Result<Vec<u8>, Error>> + Send + 'a
+where
+    R: AsyncReadChunk + ?Sized,
+    I: IntoIterator<Item = [u8; 32]>,
+    I::IntoIter: Send + 'a,
+{
+    let window = reader.prefetch_window() + 1;
+
+    futures::stream::iter(digests)
+        .map(move |digest| async move { reader.read_chunk(&digest).await })
+        .buffered(window)
 }
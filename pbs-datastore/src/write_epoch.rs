@@ -0,0 +1,238 @@
+//! Detection of unclean shutdowns
+//!
+//! While a datastore is in use, a small marker file (`.write-epoch`) in its
+//! base directory records the boot ID, the owning process and the time up to
+//! which all writes are known to be on disk. The marker is updated after each
+//! periodic `syncfs(2)` and flagged as clean on shutdown.
+//!
+//! Finding a marker which is not flagged as clean and was written during a
+//! previous boot means the system went down without a clean shutdown, for
+//! example due to a power cut. Chunks written after the last recorded sync
+//! may then be incomplete or corrupt and need to be checked. A process
+//! restart within the same boot does not lose data, so it just continues the
+//! current window.
+
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+
+/// File name of the marker in the datastore base directory
+pub const WRITE_EPOCH_FILE_NAME: &str = ".write-epoch";
+
+// serializes read-modify-write cycles of the marker within this process
+static MARKER_MUTEX: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct WriteEpoch {
+    /// Incremented on each start of the owning process
+    epoch: u64,
+    boot_id: String,
+    pid: u32,
+    /// Everything written before this time is on disk
+    synced: i64,
+    /// Set on clean shutdown
+    clean: bool,
+    /// Start of the window of a detected unclean shutdown which was not checked yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pending_check: Option<i64>,
+}
+
+/// An unclean shutdown which requires checking the recently written chunks
+#[derive(Clone, Copy, Debug)]
+pub struct UncleanShutdown {
+    /// Epoch which was active during the unclean shutdown
+    pub epoch: u64,
+    /// Chunks written since then may be incomplete
+    pub since: i64,
+}
+
+fn marker_path(base: &Path) -> PathBuf {
+    base.join(WRITE_EPOCH_FILE_NAME)
+}
+
+fn boot_id() -> Result<String, Error> {
+    let boot_id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map_err(|err| format_err!("unable to read boot id - {err}"))?;
+    Ok(boot_id.trim().to_string())
+}
+
+fn load_marker(base: &Path) -> Result<Option<WriteEpoch>, Error> {
+    let path = marker_path(base);
+    match file_read_optional_string(&path)? {
+        Some(content) => serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|err| format_err!("unable to parse {path:?} - {err}")),
+        None => Ok(None),
+    }
+}
+
+fn store_marker(base: &Path, marker: &WriteEpoch) -> Result<(), Error> {
+    let data = serde_json::to_vec(marker)?;
+    replace_file(marker_path(base), &data, CreateOptions::new(), true)
+}
+
+fn sync_filesystem(base: &Path) -> Result<(), Error> {
+    let dir = std::fs::File::open(base)?;
+    if unsafe { libc::syncfs(dir.as_raw_fd()) } < 0 {
+        bail!("error during syncfs: {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Take over the marker for this process.
+///
+/// Returns the window which needs to be checked if an unclean shutdown was
+/// detected now or earlier and its check did not finish yet.
+pub fn begin(base: &Path) -> Result<Option<UncleanShutdown>, Error> {
+    let _guard = MARKER_MUTEX.lock().unwrap();
+
+    let boot_id = boot_id()?;
+    let now = proxmox_time::epoch_i64();
+    let previous = load_marker(base)?;
+
+    let (epoch, synced, pending_check) = match previous {
+        None => (1, now, None),
+        Some(previous) if previous.clean => (previous.epoch + 1, now, previous.pending_check),
+        Some(previous) if previous.boot_id == boot_id => {
+            // restarted within the same boot, unsynced data is still in the page cache
+            (previous.epoch + 1, previous.synced, previous.pending_check)
+        }
+        Some(previous) => {
+            let since = match previous.pending_check {
+                Some(pending) => pending.min(previous.synced),
+                None => previous.synced,
+            };
+            (previous.epoch + 1, now, Some(since))
+        }
+    };
+
+    store_marker(
+        base,
+        &WriteEpoch {
+            epoch,
+            boot_id,
+            pid: std::process::id(),
+            synced,
+            clean: false,
+            pending_check,
+        },
+    )?;
+
+    Ok(pending_check.map(|since| UncleanShutdown {
+        epoch: epoch - 1,
+        since,
+    }))
+}
+
+// sync the file system and record it, if the marker still belongs to this process
+fn update_owned(base: &Path, clean: bool) -> Result<(), Error> {
+    let now = proxmox_time::epoch_i64();
+    sync_filesystem(base)?;
+
+    let _guard = MARKER_MUTEX.lock().unwrap();
+    let mut marker = match load_marker(base)? {
+        Some(marker) if marker.pid == std::process::id() => marker,
+        // taken over by a newer process (e.g. after a reload)
+        _ => return Ok(()),
+    };
+    marker.synced = now;
+    marker.clean = clean;
+    store_marker(base, &marker)
+}
+
+/// Sync the file system and record that all previous writes are on disk.
+pub fn sync(base: &Path) -> Result<(), Error> {
+    update_owned(base, false)
+}
+
+/// Sync the file system and flag the marker as clean.
+pub fn end(base: &Path) -> Result<(), Error> {
+    update_owned(base, true)
+}
+
+/// Record that the chunks of a detected unclean shutdown were checked.
+pub fn check_finished(base: &Path) -> Result<(), Error> {
+    let _guard = MARKER_MUTEX.lock().unwrap();
+    if let Some(mut marker) = load_marker(base)? {
+        if marker.pending_check.is_some() {
+            marker.pending_check = None;
+            store_marker(base, &marker)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marker(epoch: u64, boot_id: &str, synced: i64, clean: bool) -> WriteEpoch {
+        WriteEpoch {
+            epoch,
+            boot_id: boot_id.to_string(),
+            pid: 1,
+            synced,
+            clean,
+            pending_check: None,
+        }
+    }
+
+    #[test]
+    fn test_begin() -> Result<(), Error> {
+        let mut base: PathBuf = String::from("./target/testout").into();
+        base.push(std::module_path!());
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(&base)?;
+        let boot_id = boot_id()?;
+
+        let result = (|| -> Result<(), Error> {
+            // first start
+            assert!(begin(&base)?.is_none());
+            let current = load_marker(&base)?.unwrap();
+            assert_eq!(current.epoch, 1);
+            assert_eq!(current.pid, std::process::id());
+            assert!(!current.clean);
+
+            // restart within the same boot keeps the unsynced window
+            store_marker(&base, &marker(3, &boot_id, 100, false))?;
+            assert!(begin(&base)?.is_none());
+            let current = load_marker(&base)?.unwrap();
+            assert_eq!((current.epoch, current.synced), (4, 100));
+
+            // clean shutdown during a previous boot
+            store_marker(&base, &marker(4, "other-boot", 100, true))?;
+            assert!(begin(&base)?.is_none());
+            assert_eq!(load_marker(&base)?.unwrap().epoch, 5);
+
+            // unclean shutdown during a previous boot
+            store_marker(&base, &marker(5, "other-boot", 200, false))?;
+            let unclean = begin(&base)?.unwrap();
+            assert_eq!((unclean.epoch, unclean.since), (5, 200));
+
+            // reported again until the check finished, even after a clean shutdown
+            end(&base)?;
+            let unclean = begin(&base)?.unwrap();
+            assert_eq!((unclean.epoch, unclean.since), (6, 200));
+
+            // a second unclean shutdown extends the pending window
+            let mut previous = marker(7, "other-boot", 300, false);
+            previous.pending_check = Some(200);
+            store_marker(&base, &previous)?;
+            assert_eq!(begin(&base)?.unwrap().since, 200);
+
+            check_finished(&base)?;
+            assert!(begin(&base)?.is_none());
+
+            Ok(())
+        })();
+
+        let _ = std::fs::remove_dir_all(&base);
+        result
+    }
+}
@@ -17,6 +17,7 @@ use futures::stream::{StreamExt, TryStreamExt};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
 
 use super::loopdev;
+use super::overlay::{CowOverlay, OVERLAY_BLOCK_SIZE};
 use proxmox_fuse::{requests::FuseRequest, *};
 use proxmox_time::epoch_i64;
 
@@ -36,17 +37,22 @@ pub struct FuseLoopSession<R: AsyncRead + AsyncSeek + Unpin> {
     reader: R,
     fuse_path: String,
     pid_path: String,
+    overlay: Option<CowOverlay>,
     pub loopdev_path: String,
 }
 
 impl<R: AsyncRead + AsyncSeek + Unpin> FuseLoopSession<R> {
     /// Prepare for mapping the given reader as a block device node at
     /// /dev/loopN. Creates a temporary file for FUSE and a PID file for unmap.
+    ///
+    /// With `overlay` set, the device is mapped writable and all writes are
+    /// redirected into a sparse temporary file, which is removed on unmap.
     pub async fn map_loop<P: AsRef<str>>(
         size: u64,
         mut reader: R,
         name: P,
         options: &OsStr,
+        overlay: bool,
     ) -> Result<Self, Error> {
         // attempt a single read to check if the reader is configured correctly
         let _ = reader.read_u8().await?;
@@ -73,11 +79,21 @@ impl<R: AsyncRead + AsyncSeek + Unpin> FuseLoopSession<R> {
             }
         }
 
-        let session = Fuse::builder("pbs-block-dev")?
+        let overlay = if overlay {
+            let mut overlay_path = path.clone();
+            overlay_path.set_extension("overlay");
+            Some(CowOverlay::create(overlay_path, size)?)
+        } else {
+            None
+        };
+
+        let mut builder = Fuse::builder("pbs-block-dev")?
             .options_os(options)?
-            .enable_read()
-            .build()?
-            .mount(&path)?;
+            .enable_read();
+        if overlay.is_some() {
+            builder = builder.enable_write();
+        }
+        let session = builder.build()?.mount(&path)?;
 
         let loopdev_path = loopdev::get_or_create_free_dev()
             .map_err(|err| format_err!("loop-control GET_FREE failed - {}", err))?;
@@ -88,13 +104,93 @@ impl<R: AsyncRead + AsyncSeek + Unpin> FuseLoopSession<R> {
         Ok(Self {
             session: Some(session),
             reader,
-            stat: minimal_stat(size as i64),
+            stat: minimal_stat(size as i64, overlay.is_some()),
             fuse_path: path.to_string_lossy().into_owned(),
             pid_path: pid_path.to_string_lossy().into_owned(),
+            overlay,
             loopdev_path,
         })
     }
 
+    async fn read_base(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), std::io::Error> {
+        self.reader.seek(SeekFrom::Start(offset)).await?;
+        self.reader.read_exact(buf).await?;
+        Ok(())
+    }
+
+    /// Read from the original image, or from the overlay for already written blocks.
+    async fn read_data(&mut self, offset: u64, size: usize) -> Result<Vec<u8>, std::io::Error> {
+        let mut buf = vec![0u8; size];
+
+        if self.overlay.is_none() {
+            self.read_base(offset, &mut buf).await?;
+            return Ok(buf);
+        }
+
+        let mut pos = 0;
+        while pos < size {
+            let current = offset + pos as u64;
+            let block = current / OVERLAY_BLOCK_SIZE;
+            let block_end = (block + 1) * OVERLAY_BLOCK_SIZE;
+            let len = ((block_end - current) as usize).min(size - pos);
+            let part = &mut buf[pos..(pos + len)];
+
+            match self.overlay.as_ref() {
+                Some(overlay) if overlay.is_dirty(block) => overlay.read_at(part, current)?,
+                _ => self.read_base(current, part).await?,
+            }
+
+            pos += len;
+        }
+
+        Ok(buf)
+    }
+
+    /// Write data into the overlay, merging partially written blocks with the original image.
+    async fn write_data(&mut self, offset: u64, data: &[u8]) -> Result<usize, std::io::Error> {
+        let size = match self.overlay.as_ref() {
+            Some(overlay) => overlay.size(),
+            None => return Err(std::io::Error::from_raw_os_error(libc::EROFS)),
+        };
+
+        if offset + data.len() as u64 > size {
+            return Err(std::io::Error::from_raw_os_error(libc::ENOSPC));
+        }
+
+        let mut pos = 0;
+        while pos < data.len() {
+            let current = offset + pos as u64;
+            let block = current / OVERLAY_BLOCK_SIZE;
+            let block_start = block * OVERLAY_BLOCK_SIZE;
+            let block_end = block_start + OVERLAY_BLOCK_SIZE;
+            let len = ((block_end - current) as usize).min(data.len() - pos);
+            let part = &data[pos..(pos + len)];
+
+            // unwrap: checked above
+            let overlay = self.overlay.as_ref().unwrap();
+            let block_len = overlay.block_len(block);
+
+            if overlay.is_dirty(block) {
+                self.overlay.as_mut().unwrap().write_at(part, current)?;
+            } else if current == block_start && len == block_len {
+                self.overlay.as_mut().unwrap().write_block(block, part)?;
+            } else {
+                let mut block_data = vec![0u8; block_len];
+                self.read_base(block_start, &mut block_data).await?;
+                let start = (current - block_start) as usize;
+                block_data[start..(start + len)].copy_from_slice(part);
+                self.overlay
+                    .as_mut()
+                    .unwrap()
+                    .write_block(block, &block_data)?;
+            }
+
+            pos += len;
+        }
+
+        Ok(data.len())
+    }
+
     fn write_pidfile(path: &Path) -> Result<(), Error> {
         let pid = unsafe { libc::getpid() };
         let mut file = File::create(path)?;
@@ -119,8 +215,9 @@ impl<R: AsyncRead + AsyncSeek + Unpin> FuseLoopSession<R> {
         let mut abort_chan = abort_chan.fuse();
 
         let (loopdev_path, fuse_path) = (self.loopdev_path.clone(), self.fuse_path.clone());
+        let read_only = self.overlay.is_none();
         tokio::task::spawn_blocking(move || {
-            if let Err(err) = loopdev::assign(loopdev_path, fuse_path) {
+            if let Err(err) = loopdev::assign(loopdev_path, fuse_path, read_only) {
                 let _ = startup_chan.try_send(Err(format_err!(
                     "error while assigning loop device - {}",
                     err
@@ -185,17 +282,19 @@ impl<R: AsyncRead + AsyncSeek + Unpin> FuseLoopSession<R> {
                             req.reply(&self.stat, f64::MAX)
                         },
                         Some(Request::Read(req)) => {
-                            match self.reader.seek(SeekFrom::Start(req.offset)).await {
-                                Ok(_) => {
-                                    let mut buf = vec![0u8; req.size];
-                                    match self.reader.read_exact(&mut buf).await {
-                                        Ok(_) => {
-                                            req.reply(&buf)
-                                        },
-                                        Err(e) => {
-                                            req.io_fail(e)
-                                        }
-                                    }
+                            match self.read_data(req.offset, req.size).await {
+                                Ok(buf) => {
+                                    req.reply(&buf)
+                                },
+                                Err(e) => {
+                                    req.io_fail(e)
+                                }
+                            }
+                        },
+                        Some(Request::Write(req)) => {
+                            match self.write_data(req.offset, req.data()).await {
+                                Ok(size) => {
+                                    req.reply(size)
                                 },
                                 Err(e) => {
                                     req.io_fail(e)
@@ -215,6 +314,7 @@ impl<R: AsyncRead + AsyncSeek + Unpin> FuseLoopSession<R> {
                     if let Err(err) = res {
                         // error during FUSE reply, cleanup and exit
                         cleanup(session);
+                        self.overlay.take();
                         bail!(err);
                     }
                 }
@@ -223,6 +323,8 @@ impl<R: AsyncRead + AsyncSeek + Unpin> FuseLoopSession<R> {
 
         // non-error FUSE exit
         cleanup(session);
+        // drops the overlay file, if any
+        self.overlay.take();
         Ok(())
     }
 }
@@ -254,6 +356,8 @@ pub fn cleanup_unused_run_files(filter_name: Option<String>) {
                 let _ = remove_file(&path);
                 path.set_extension("pid");
                 let _ = remove_file(&path);
+                path.set_extension("overlay");
+                let _ = remove_file(&path);
             }
         }
     }
@@ -313,6 +417,8 @@ fn emerg_cleanup(loopdev: Option<&str>, mut backing_file: PathBuf) {
     let _ = remove_file(&backing_file);
     backing_file.set_extension("pid");
     let _ = remove_file(&backing_file);
+    backing_file.set_extension("overlay");
+    let _ = remove_file(&backing_file);
 }
 
 fn unmap_from_backing(backing_file: &Path, loopdev: Option<&str>) -> Result<(), Error> {
@@ -384,7 +490,11 @@ pub fn find_all_mappings() -> Result<impl Iterator<Item = (String, Option<String
             match ent {
                 Ok(ent) => {
                     let file = ent.file_name().to_string_lossy();
-                    if file == "." || file == ".." || file.ends_with(".pid") {
+                    if file == "."
+                        || file == ".."
+                        || file.ends_with(".pid")
+                        || file.ends_with(".overlay")
+                    {
                         None
                     } else {
                         let loopdev = loopmap.get(file.as_ref()).map(String::to_owned);
@@ -421,9 +531,12 @@ pub fn unmap_name<S: AsRef<str>>(name: S) -> Result<(), Error> {
     Err(format_err!("no mapping for name '{}' found", name.as_ref()))
 }
 
-fn minimal_stat(size: i64) -> libc::stat {
+fn minimal_stat(size: i64, writable: bool) -> libc::stat {
     let mut stat: libc::stat = unsafe { std::mem::zeroed() };
     stat.st_mode = libc::S_IFREG;
+    if writable {
+        stat.st_mode |= libc::S_IRUSR | libc::S_IWUSR;
+    }
     stat.st_ino = 1;
     stat.st_nlink = 1;
     stat.st_size = size;
@@ -2,3 +2,6 @@ pub mod loopdev;
 
 mod fuse_loop;
 pub use fuse_loop::*;
+
+mod overlay;
+pub use overlay::*;
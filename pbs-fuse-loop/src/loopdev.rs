@@ -4,7 +4,7 @@ use std::fs::{File, OpenOptions};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::Path;
 
-use anyhow::Error;
+use anyhow::{bail, Error};
 
 const LOOP_CONTROL: &str = "/dev/loop-control";
 const LOOP_NAME: &str = "/dev/loop";
@@ -69,14 +69,17 @@ pub fn get_or_create_free_dev() -> Result<String, Error> {
     Ok(loop_file_path)
 }
 
-fn assign_dev(fd: RawFd, backing_fd: RawFd) -> Result<(), Error> {
+fn assign_dev(fd: RawFd, backing_fd: RawFd, read_only: bool) -> Result<(), Error> {
     unsafe {
         ioctl_set_fd(fd, backing_fd)?;
     }
 
-    // set required read-only flag and partscan for convenience
+    // set read-only flag if required and partscan for convenience
     let mut info: LoopInfo64 = unsafe { std::mem::zeroed() };
-    info.lo_flags = LO_FLAGS_READ_ONLY | LO_FLAGS_PARTSCAN;
+    info.lo_flags = LO_FLAGS_PARTSCAN;
+    if read_only {
+        info.lo_flags |= LO_FLAGS_READ_ONLY;
+    }
     unsafe {
         ioctl_set_status64(fd, &info)?;
     }
@@ -85,11 +88,14 @@ fn assign_dev(fd: RawFd, backing_fd: RawFd) -> Result<(), Error> {
 }
 
 /// Open the next available /dev/loopN file and assign the given path to
-/// it as it's backing file in read-only mode.
-pub fn assign<P: AsRef<Path>>(loop_dev: P, backing: P) -> Result<(), Error> {
+/// it as it's backing file, in read-only mode unless `read_only` is false.
+pub fn assign<P: AsRef<Path>>(loop_dev: P, backing: P, read_only: bool) -> Result<(), Error> {
     let loop_file = File::open(loop_dev)?;
-    let backing_file = OpenOptions::new().read(true).open(backing)?;
-    assign_dev(loop_file.as_raw_fd(), backing_file.as_raw_fd())?;
+    let backing_file = OpenOptions::new()
+        .read(true)
+        .write(!read_only)
+        .open(backing)?;
+    assign_dev(loop_file.as_raw_fd(), backing_file.as_raw_fd(), read_only)?;
     Ok(())
 }
 
@@ -102,3 +108,66 @@ pub fn unassign<P: AsRef<Path>>(path: P) -> Result<(), Error> {
     }
     Ok(())
 }
+
+/// Returns the partition number if `entry` is a partition of the loop device `loop_name` in
+/// sysfs, e.g. 2 for "loop0p2" of "loop0".
+fn partition_number(loop_name: &str, entry: &str) -> Option<u32> {
+    entry
+        .strip_prefix(loop_name)?
+        .strip_prefix('p')?
+        .parse::<u32>()
+        .ok()
+}
+
+/// Returns the partition device nodes (/dev/loopNpM) of a mapped loop device, sorted by partition
+/// number.
+///
+/// Devices are always assigned with `LO_FLAGS_PARTSCAN`, and the kernel scans the partition table
+/// synchronously when setting it, so sysfs is already up to date once the mapping is done.
+pub fn partitions<P: AsRef<Path>>(loop_dev: P) -> Result<Vec<String>, Error> {
+    let loop_dev = loop_dev.as_ref();
+    let name = match loop_dev.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.starts_with("loop") => name,
+        _ => bail!("{:?} is not a loop device", loop_dev),
+    };
+
+    let mut partitions = Vec::new();
+    for entry in std::fs::read_dir(format!("/sys/block/{}", name))? {
+        let file_name = entry?.file_name();
+        let file_name = match file_name.to_str() {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+        if let Some(number) = partition_number(name, file_name) {
+            partitions.push((number, format!("/dev/{}", file_name)));
+        }
+    }
+    partitions.sort_unstable();
+
+    Ok(partitions.into_iter().map(|(_, path)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_number() {
+        assert_eq!(partition_number("loop0", "loop0p1"), Some(1));
+        assert_eq!(partition_number("loop0", "loop0p12"), Some(12));
+        assert_eq!(partition_number("loop1", "loop1p3"), Some(3));
+
+        // other sysfs entries of the device
+        assert_eq!(partition_number("loop0", "queue"), None);
+        assert_eq!(partition_number("loop0", "loop"), None);
+        assert_eq!(partition_number("loop0", "loop0p"), None);
+        // partitions of another device sharing the prefix
+        assert_eq!(partition_number("loop1", "loop12p1"), None);
+    }
+
+    #[test]
+    fn test_partitions_rejects_non_loop_device() {
+        assert!(partitions("/dev/sda").is_err());
+        assert!(partitions("/").is_err());
+    }
+}
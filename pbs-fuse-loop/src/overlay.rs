@@ -0,0 +1,176 @@
+//! Copy-on-write overlay for read-only block device images
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+
+use anyhow::{format_err, Error};
+
+/// Granularity of the overlay, writes smaller than this are merged with the original data.
+pub const OVERLAY_BLOCK_SIZE: u64 = 4096;
+
+/// A sparse temporary file storing all blocks written to a mapped image.
+///
+/// Blocks which were never written are read from the original image, so the backup data itself
+/// is never modified. The overlay file is removed when dropped.
+pub struct CowOverlay {
+    file: File,
+    path: PathBuf,
+    size: u64,
+    dirty: Vec<u64>,
+}
+
+impl CowOverlay {
+    /// Create a new, empty overlay for an image with `size` bytes at `path`.
+    pub fn create<P: Into<PathBuf>>(path: P, size: u64) -> Result<Self, Error> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|err| format_err!("unable to create overlay file {path:?} - {err}"))?;
+
+        // only allocates blocks once they are actually written
+        file.set_len(size)?;
+
+        let blocks = (size + OVERLAY_BLOCK_SIZE - 1) / OVERLAY_BLOCK_SIZE;
+        let dirty = vec![0u64; ((blocks + 63) / 64) as usize];
+
+        Ok(Self {
+            file,
+            path,
+            size,
+            dirty,
+        })
+    }
+
+    /// Size of the overlayed image.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Check if a block has been written to the overlay.
+    pub fn is_dirty(&self, block: u64) -> bool {
+        let (index, bit) = ((block / 64) as usize, block % 64);
+        self.dirty
+            .get(index)
+            .map(|word| word & (1 << bit) != 0)
+            .unwrap_or(false)
+    }
+
+    fn set_dirty(&mut self, block: u64) {
+        let (index, bit) = ((block / 64) as usize, block % 64);
+        self.dirty[index] |= 1 << bit;
+    }
+
+    /// Number of bytes of block `block` which are part of the image.
+    pub fn block_len(&self, block: u64) -> usize {
+        let start = block * OVERLAY_BLOCK_SIZE;
+        self.size.saturating_sub(start).min(OVERLAY_BLOCK_SIZE) as usize
+    }
+
+    /// Read from the overlay, the range must be inside a single dirty block.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.file.read_exact_at(buf, offset)
+    }
+
+    /// Write a complete block into the overlay and mark it as dirty.
+    pub fn write_block(&mut self, block: u64, data: &[u8]) -> io::Result<()> {
+        if block * OVERLAY_BLOCK_SIZE >= self.size || data.len() != self.block_len(block) {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        self.file.write_all_at(data, block * OVERLAY_BLOCK_SIZE)?;
+        self.set_dirty(block);
+        Ok(())
+    }
+
+    /// Write a partial block into the overlay, the block must already be dirty.
+    pub fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<()> {
+        if !self.is_dirty(offset / OVERLAY_BLOCK_SIZE) {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        self.file.write_all_at(data, offset)
+    }
+}
+
+impl Drop for CowOverlay {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                log::warn!(
+                    "cleanup: warning: could not remove overlay file {:?} - {}",
+                    self.path,
+                    err,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_testdir(name: &str) -> PathBuf {
+        let mut testdir: PathBuf = String::from("./target/testout").into();
+        testdir.push(std::module_path!());
+        testdir.push(name);
+
+        let _ = std::fs::remove_dir_all(&testdir);
+        let _ = std::fs::create_dir_all(&testdir);
+
+        testdir
+    }
+
+    #[test]
+    fn test_cow_overlay() -> Result<(), Error> {
+        let testdir = create_testdir("cow_overlay");
+        let path = testdir.join("image.overlay");
+
+        // two full blocks and a partial one
+        let size = 2 * OVERLAY_BLOCK_SIZE + 100;
+        let mut overlay = CowOverlay::create(&path, size)?;
+        assert!(CowOverlay::create(&path, size).is_err());
+
+        assert_eq!(overlay.size(), size);
+        assert_eq!(overlay.block_len(0), OVERLAY_BLOCK_SIZE as usize);
+        assert_eq!(overlay.block_len(2), 100);
+        assert_eq!(overlay.block_len(3), 0);
+        assert!(!overlay.is_dirty(1));
+        assert!(!overlay.is_dirty(1000));
+
+        // partial writes need the block to be copied up first
+        assert!(overlay.write_at(b"data", OVERLAY_BLOCK_SIZE).is_err());
+        // only complete blocks inside the image can be written
+        assert!(overlay.write_block(1, &[1u8; 100]).is_err());
+        assert!(overlay.write_block(3, &[]).is_err());
+
+        overlay.write_block(1, &[1u8; OVERLAY_BLOCK_SIZE as usize])?;
+        overlay.write_block(2, &[2u8; 100])?;
+        assert!(!overlay.is_dirty(0));
+        assert!(overlay.is_dirty(1));
+        assert!(overlay.is_dirty(2));
+
+        overlay.write_at(b"data", OVERLAY_BLOCK_SIZE + 10)?;
+        let mut buf = [0u8; 16];
+        overlay.read_at(&mut buf, OVERLAY_BLOCK_SIZE + 8)?;
+        assert_eq!(
+            &buf,
+            b"\x01\x01data\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01"
+        );
+
+        let mut buf = [0u8; 100];
+        overlay.read_at(&mut buf, 2 * OVERLAY_BLOCK_SIZE)?;
+        assert_eq!(buf, [2u8; 100]);
+
+        // the overlay file is removed with the overlay
+        drop(overlay);
+        assert!(!path.exists());
+
+        let _ = std::fs::remove_dir_all(&testdir);
+
+        Ok(())
+    }
+}
@@ -297,19 +297,140 @@ pub fn rsa_encrypt_key_config(
     Ok(buffer)
 }
 
+/// A KeyConfig encrypted for several RSA master keys
+///
+/// Used instead of the plain RSA encrypted KeyConfig if there is more than
+/// one master key, so that the key can be recovered with any of them.
+#[derive(Deserialize, Serialize)]
+pub struct RsaKeyEnvelope {
+    pub recipients: Vec<RsaKeyRecipient>,
+}
+
+/// The KeyConfig encrypted for one master key
+#[derive(Deserialize, Serialize)]
+pub struct RsaKeyRecipient {
+    /// Fingerprint of the master public key, see [rsa_pubkey_fingerprint]
+    pub fingerprint: Fingerprint,
+    #[serde(with = "proxmox_serde::bytes_as_base64")]
+    pub data: Vec<u8>,
+}
+
+/// SHA-256 fingerprint of the DER encoded public key
+pub fn rsa_pubkey_fingerprint(
+    rsa: &openssl::rsa::Rsa<openssl::pkey::Public>,
+) -> Result<Fingerprint, Error> {
+    Ok(Fingerprint::new(openssl::sha::sha256(
+        &rsa.public_key_to_der()?,
+    )))
+}
+
+/// Parse all PEM encoded RSA public keys in `pem`.
+///
+/// A master public key file may contain several concatenated keys.
+pub fn parse_rsa_pubkeys(
+    pem: &[u8],
+) -> Result<Vec<openssl::rsa::Rsa<openssl::pkey::Public>>, Error> {
+    let text = std::str::from_utf8(pem).map_err(|_| format_err!("PEM data is not UTF-8"))?;
+
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("-----BEGIN ") {
+        let end = rest[start..]
+            .find("-----END ")
+            .and_then(|end| {
+                let end = start + end + "-----END ".len();
+                rest[end..]
+                    .find("-----")
+                    .map(|tail| end + tail + "-----".len())
+            })
+            .ok_or_else(|| format_err!("unterminated PEM block"))?;
+        let key = openssl::rsa::Rsa::public_key_from_pem(rest[start..end].as_bytes())
+            .map_err(|err| format_err!("unable to decode PEM data - {err}"))?;
+        keys.push(key);
+        rest = &rest[end..];
+    }
+
+    if keys.is_empty() {
+        bail!("no PEM encoded public key found");
+    }
+
+    Ok(keys)
+}
+
+/// RSA encrypt a KeyConfig for one or more master keys
+///
+/// For a single master key, this is the same as [rsa_encrypt_key_config],
+/// otherwise an [RsaKeyEnvelope] is returned.
+pub fn rsa_encrypt_key_config_for(
+    recipients: &[openssl::rsa::Rsa<openssl::pkey::Public>],
+    key: &KeyConfig,
+) -> Result<Vec<u8>, Error> {
+    match recipients {
+        [] => bail!("no master key given"),
+        [rsa] => rsa_encrypt_key_config(rsa.clone(), key),
+        recipients => {
+            let mut envelope = RsaKeyEnvelope {
+                recipients: Vec::with_capacity(recipients.len()),
+            };
+            for rsa in recipients {
+                envelope.recipients.push(RsaKeyRecipient {
+                    fingerprint: rsa_pubkey_fingerprint(rsa)?,
+                    data: rsa_encrypt_key_config(rsa.clone(), key)?,
+                });
+            }
+            Ok(serde_json::to_vec(&envelope)?)
+        }
+    }
+}
+
 /// RSA deccrypt a KeyConfig using a private key
+///
+/// Accepts both a plain RSA encrypted KeyConfig and an [RsaKeyEnvelope].
 pub fn rsa_decrypt_key_config(
     rsa: openssl::rsa::Rsa<openssl::pkey::Private>,
     key: &[u8],
     passphrase: &dyn Fn() -> Result<Vec<u8>, Error>,
 ) -> Result<([u8; 32], i64, Fingerprint), Error> {
+    let key = match serde_json::from_slice::<RsaKeyEnvelope>(key) {
+        Ok(envelope) => {
+            let public = openssl::rsa::Rsa::from_public_components(
+                rsa.n().to_owned()?,
+                rsa.e().to_owned()?,
+            )?;
+            let fingerprint = rsa_pubkey_fingerprint(&public)?;
+            let recipient = envelope
+                .recipients
+                .into_iter()
+                .find(|recipient| recipient.fingerprint == fingerprint)
+                .ok_or_else(|| format_err!("key was not encrypted for master key {fingerprint}"))?;
+            recipient.data
+        }
+        Err(_) => key.to_vec(),
+    };
+
     let mut buffer = vec![0u8; rsa.size() as usize];
     let decrypted = rsa
-        .private_decrypt(key, &mut buffer, openssl::rsa::Padding::PKCS1)
+        .private_decrypt(&key, &mut buffer, openssl::rsa::Padding::PKCS1)
         .map_err(|err| format_err!("failed to decrypt KeyConfig using RSA - {}", err))?;
     decrypt_key(&buffer[..decrypted], passphrase)
 }
 
+/// List the fingerprints of the master keys an RSA encrypted KeyConfig can be decrypted with.
+///
+/// Returns `None` for a plain RSA encrypted KeyConfig, which does not record its master key.
+#[cfg(test)]
+fn rsa_encrypted_key_recipients(key: &[u8]) -> Option<Vec<Fingerprint>> {
+    serde_json::from_slice::<RsaKeyEnvelope>(key)
+        .ok()
+        .map(|envelope| {
+            envelope
+                .recipients
+                .into_iter()
+                .map(|recipient| recipient.fingerprint)
+                .collect()
+        })
+}
+
 #[test]
 fn encrypt_decrypt_test() -> Result<(), Error> {
     use openssl::bn::BigNum;
@@ -354,6 +475,40 @@ fn encrypt_decrypt_test() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn envelope_encrypt_decrypt_test() -> Result<(), Error> {
+    let first = openssl::rsa::Rsa::generate(2048)?;
+    let second = openssl::rsa::Rsa::generate(2048)?;
+    let other = openssl::rsa::Rsa::generate(2048)?;
+
+    let mut pem =
+        openssl::rsa::Rsa::from_public_components(first.n().to_owned()?, first.e().to_owned()?)?
+            .public_key_to_pem()?;
+    pem.extend(
+        openssl::rsa::Rsa::from_public_components(second.n().to_owned()?, second.e().to_owned()?)?
+            .public_key_to_pem()?,
+    );
+    let recipients = parse_rsa_pubkeys(&pem)?;
+    assert_eq!(recipients.len(), 2);
+
+    let key = KeyConfig::without_password(std::array::from_fn(|i| i as u8))?;
+    let encrypted = rsa_encrypt_key_config_for(&recipients, &key)?;
+    assert_eq!(
+        rsa_encrypted_key_recipients(&encrypted).map(|r| r.len()),
+        Some(2)
+    );
+
+    let passphrase = || -> Result<Vec<u8>, Error> { Ok(Vec::new()) };
+    for private in [first, second] {
+        let (decrypted, _created, _fingerprint) =
+            rsa_decrypt_key_config(private, &encrypted, &passphrase)?;
+        assert_eq!(key.data, decrypted);
+    }
+    assert!(rsa_decrypt_key_config(other, &encrypted, &passphrase).is_err());
+
+    Ok(())
+}
+
 #[test]
 fn fingerprint_checks() -> Result<(), Error> {
     let key = KeyConfig {
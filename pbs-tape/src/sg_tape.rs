@@ -2,7 +2,7 @@ use std::fs::{File, OpenOptions};
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
-use std::time::SystemTime;
+use std::time::Duration;
 
 use anyhow::{bail, format_err, Error};
 use endian_trait::Endian;
@@ -30,6 +30,7 @@ use proxmox_sys::error::SysResult;
 use pbs_api_types::{
     Lp17VolumeStatistics, LtoDriveAndMediaStatus, LtoTapeDrive, MamAttribute, TapeDensity,
 };
+use pbs_tools::async_io::{cancellable_retry, is_timeout, AbortSignal};
 
 use crate::linux_list_drives::open_lto_tape_device;
 use crate::{
@@ -106,6 +107,7 @@ pub struct SgTape {
     file: File,
     locate_offset: Option<i64>,
     info: InquiryInfo,
+    abort: Option<AbortSignal>,
 }
 
 impl SgTape {
@@ -128,9 +130,15 @@ impl SgTape {
             file,
             info,
             locate_offset: None,
+            abort: None,
         })
     }
 
+    /// Set a signal to interrupt long waits for the drive, e.g. when the owning task is aborted.
+    pub fn set_abort_signal(&mut self, abort: Option<AbortSignal>) {
+        self.abort = abort;
+    }
+
     /// Open a tape device
     ///
     /// This does additional checks:
@@ -619,21 +627,25 @@ impl SgTape {
     }
 
     pub fn wait_until_ready(&mut self, timeout: Option<u64>) -> Result<(), Error> {
-        let start = SystemTime::now();
         let timeout = timeout.unwrap_or(Self::SCSI_TAPE_DEFAULT_TIMEOUT as u64);
-        let max_wait = std::time::Duration::new(timeout, 0);
-
-        loop {
-            match self.test_unit_ready() {
-                Ok(()) => return Ok(()),
-                _ => {
-                    std::thread::sleep(std::time::Duration::new(1, 0));
-                    if start.elapsed()? > max_wait {
-                        bail!("wait_until_ready failed - got timeout");
-                    }
-                }
+        let abort = self.abort.clone();
+
+        cancellable_retry(
+            Some(Duration::from_secs(timeout)),
+            Duration::from_secs(1),
+            || match &abort {
+                Some(abort) => abort.check(),
+                None => Ok(()),
+            },
+            || Ok(self.test_unit_ready().ok()),
+        )
+        .map_err(|err| {
+            if is_timeout(&err) {
+                format_err!("wait_until_ready failed - got timeout")
+            } else {
+                format_err!("wait_until_ready failed - {err}")
             }
-        }
+        })
     }
 
     /// Read Tape Alert Flags
@@ -6,17 +6,24 @@
 //!
 //! The SCSI Commands Reference Manual also contains some useful information.
 
+use std::collections::HashMap;
 use std::ffi::CStr;
 use std::os::unix::io::AsRawFd;
+use std::panic::AssertUnwindSafe;
 use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
 use endian_trait::Endian;
+use lazy_static::lazy_static;
 use libc::{c_char, c_int};
 use serde::{Deserialize, Serialize};
 
 use proxmox_io::ReadExt;
 
+use pbs_tools::async_io::{cancellable_timeout, AbortSignal};
+
 #[derive(thiserror::Error, Debug)]
 pub struct SenseInfo {
     pub sense_key: u8,
@@ -1012,3 +1019,224 @@ pub fn scsi_request_sense<F: AsRawFd>(file: &mut F) -> Result<RequestSenseFixed,
 
     Ok(sense)
 }
+
+// Asynchronous submission
+//
+// SCSI commands like LOCATE or REWIND can block for minutes. Async code must not issue them on
+// runtime worker threads, so they are run on a dedicated thread pool instead, serialized per
+// device.
+
+/// Number of threads used to run SCSI commands for async callers.
+const SG_IO_POOL_THREADS: usize = 4;
+
+type SgJob = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    static ref SG_IO_POOL: Mutex<Sender<SgJob>> = Mutex::new(start_sg_io_pool());
+    static ref SG_IO_DEVICE_LOCKS: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>> =
+        Mutex::new(HashMap::new());
+}
+
+fn start_sg_io_pool() -> Sender<SgJob> {
+    let (sender, receiver) = channel::<SgJob>();
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    for i in 0..SG_IO_POOL_THREADS {
+        let receiver = Arc::clone(&receiver);
+        let _ = std::thread::Builder::new()
+            .name(format!("sg-io ({i})"))
+            .spawn(move || loop {
+                let job = match receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                // a panicking command must not take down the pool thread
+                if std::panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    log::error!("SCSI command panicked");
+                }
+            });
+    }
+
+    sender
+}
+
+/// Run blocking SCSI commands for `device` from async code.
+///
+/// `command` is executed on a dedicated thread pool, so long running commands do not block the
+/// async runtime. Commands for the same `device` (any unique name, e.g. the drive name) run one at
+/// a time, in submission order.
+///
+/// If `abort` is triggered before the command started, the command is skipped and this returns
+/// an [`AbortedError`](pbs_tools::async_io::AbortedError). A running command cannot be
+/// interrupted, so its result is still awaited and returned. `abort` should only fire on a worker
+/// abort or a real daemon shutdown, never on a reload.
+pub async fn sg_submit<T, F>(
+    device: &str,
+    abort: Option<&AbortSignal>,
+    command: F,
+) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+    let device_lock = Arc::clone(
+        SG_IO_DEVICE_LOCKS
+            .lock()
+            .unwrap()
+            .entry(device.to_string())
+            .or_default(),
+    );
+    let guard = cancellable_timeout(device_lock.lock_owned(), None, abort).await?;
+
+    // None: queued, Some(true): running, Some(false): caller gave up
+    let state = Arc::new(Mutex::new(None));
+
+    let (sender, mut receiver) = tokio::sync::oneshot::channel();
+    let job: SgJob = Box::new({
+        let state = Arc::clone(&state);
+        move || {
+            let _guard = guard; // keep the device busy until the command finished
+            {
+                let mut state = state.lock().unwrap();
+                if *state == Some(false) {
+                    return;
+                }
+                *state = Some(true);
+            }
+            let _ = sender.send(command());
+        }
+    });
+    SG_IO_POOL
+        .lock()
+        .unwrap()
+        .send(job)
+        .map_err(|_| format_err!("SCSI command pool is gone"))?;
+
+    let result = match cancellable_timeout(&mut receiver, None, abort).await {
+        Ok(result) => result,
+        Err(err) => {
+            let running = {
+                let mut state = state.lock().unwrap();
+                if *state != Some(true) {
+                    *state = Some(false);
+                }
+                *state == Some(true)
+            };
+            if !running {
+                return Err(err);
+            }
+            // the command is already running, do not lose its result
+            receiver.await
+        }
+    };
+
+    result.map_err(|_| format_err!("SCSI command for {device} did not return a result"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_sg_submit_serialized() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let submit = |id: usize| {
+            let events = Arc::clone(&events);
+            sg_submit("test-serialized", None, move || {
+                events.lock().unwrap().push(format!("start {id}"));
+                std::thread::sleep(Duration::from_millis(20));
+                events.lock().unwrap().push(format!("end {id}"));
+                Ok(id)
+            })
+        };
+
+        block_on(async {
+            let first = tokio::spawn(submit(0));
+            let second = tokio::spawn(submit(1));
+            assert_eq!(first.await.unwrap().unwrap(), 0);
+            assert_eq!(second.await.unwrap().unwrap(), 1);
+        });
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["start 0", "end 0", "start 1", "end 1"]
+        );
+    }
+
+    #[test]
+    fn test_sg_submit_abort_queued() {
+        let (started_sender, started) = channel();
+        let (release, release_receiver) = channel::<()>();
+        let ran = Arc::new(AtomicBool::new(false));
+
+        block_on(async {
+            let busy = tokio::spawn(sg_submit("test-abort-queued", None, move || {
+                started_sender.send(()).unwrap();
+                let _ = release_receiver.recv();
+                Ok(1)
+            }));
+            tokio::task::yield_now().await;
+            started.recv().unwrap();
+
+            // the device is busy, so the command is still queued when the abort fires
+            let abort = AbortSignal::new();
+            abort.abort();
+            let result = sg_submit("test-abort-queued", Some(&abort), {
+                let ran = Arc::clone(&ran);
+                move || {
+                    ran.store(true, Ordering::SeqCst);
+                    Ok(2)
+                }
+            })
+            .await;
+            assert!(pbs_tools::async_io::is_aborted(&result.unwrap_err()));
+
+            release.send(()).unwrap();
+            assert_eq!(busy.await.unwrap().unwrap(), 1);
+
+            // the device is free again
+            let result = sg_submit("test-abort-queued", None, || Ok(3)).await;
+            assert_eq!(result.unwrap(), 3);
+        });
+
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_sg_submit_abort_running() {
+        let abort = AbortSignal::new();
+
+        // an abort while the command runs must not lose its result
+        let result = block_on(sg_submit("test-abort-running", Some(&abort), {
+            let abort = abort.clone();
+            move || {
+                abort.abort();
+                std::thread::sleep(Duration::from_millis(50));
+                Ok(42)
+            }
+        }));
+        assert_eq!(result.unwrap(), 42);
+    }
+
+    #[test]
+    fn test_sg_submit_panic() {
+        let result: Result<(), Error> =
+            block_on(sg_submit("test-panic", None, || panic!("command panicked")));
+        assert!(result.is_err());
+
+        // the pool survives a panicking command
+        let result = block_on(sg_submit("test-panic", None, || Ok(1)));
+        assert_eq!(result.unwrap(), 1);
+    }
+}
@@ -0,0 +1,289 @@
+//! Cancellable, time limited waits for blocking and async operations.
+//!
+//! Long running waits (for a tape drive to become ready, for an HTTP response, for a lock) should
+//! not delay aborting a task or shutting down a daemon until their internal timeout is reached.
+//! An [`AbortSignal`] is shared between the owner of such a wait and the code requesting the
+//! abort, the helpers below return as soon as it is triggered.
+
+use std::future::Future;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use tokio::sync::Notify;
+
+/// Error returned if a timed operation did not finish in time.
+#[derive(Debug)]
+pub struct TimeoutError(pub Duration);
+
+impl std::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "operation timed out after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+/// Error returned if a timed operation was cancelled via its [`AbortSignal`].
+#[derive(Debug)]
+pub struct AbortedError;
+
+impl std::fmt::Display for AbortedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "operation aborted")
+    }
+}
+
+impl std::error::Error for AbortedError {}
+
+/// Returns true if `err` was caused by a timeout of a timed operation.
+pub fn is_timeout(err: &Error) -> bool {
+    err.downcast_ref::<TimeoutError>().is_some()
+}
+
+/// Returns true if `err` was caused by an aborted timed operation.
+pub fn is_aborted(err: &Error) -> bool {
+    err.downcast_ref::<AbortedError>().is_some()
+}
+
+/// A cloneable signal used to cancel pending operations.
+///
+/// Once triggered, the signal stays set.
+#[derive(Clone, Default)]
+pub struct AbortSignal {
+    inner: Arc<AbortSignalInner>,
+}
+
+#[derive(Default)]
+struct AbortSignalInner {
+    aborted: AtomicBool,
+    notify: Notify,
+}
+
+impl AbortSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trigger the signal, waking up all pending waits.
+    pub fn abort(&self) {
+        self.inner.aborted.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Check if the signal was triggered.
+    pub fn is_aborted(&self) -> bool {
+        self.inner.aborted.load(Ordering::SeqCst)
+    }
+
+    /// Returns an error if the signal was triggered.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_aborted() {
+            return Err(AbortedError.into());
+        }
+        Ok(())
+    }
+
+    /// Wait until the signal is triggered.
+    pub async fn aborted(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            if self.is_aborted() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Run `future` until it completes, the `timeout` elapsed or `abort` is triggered.
+///
+/// Errors are a [`TimeoutError`] or an [`AbortedError`] respectively.
+pub async fn cancellable_timeout<F>(
+    future: F,
+    timeout: Option<Duration>,
+    abort: Option<&AbortSignal>,
+) -> Result<F::Output, Error>
+where
+    F: Future,
+{
+    let abort_future = async {
+        match abort {
+            Some(abort) => abort.aborted().await,
+            None => futures::future::pending().await,
+        }
+    };
+
+    let timeout_future = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => futures::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        result = future => Ok(result),
+        _ = abort_future => Err(AbortedError.into()),
+        _ = timeout_future => Err(TimeoutError(timeout.unwrap_or_default()).into()),
+    }
+}
+
+/// Blocking variant of [`cancellable_timeout`] for polling loops.
+///
+/// Calls `poll` every `interval` until it returns a value, the `timeout` elapsed or `check_abort`
+/// returns an error, which is passed through.
+pub fn cancellable_retry<T, P, C>(
+    timeout: Option<Duration>,
+    interval: Duration,
+    mut check_abort: C,
+    mut poll: P,
+) -> Result<T, Error>
+where
+    P: FnMut() -> Result<Option<T>, Error>,
+    C: FnMut() -> Result<(), Error>,
+{
+    let start = Instant::now();
+
+    loop {
+        check_abort()?;
+
+        if let Some(result) = poll()? {
+            return Ok(result);
+        }
+
+        let elapsed = start.elapsed();
+        let wait = match timeout {
+            Some(timeout) if elapsed >= timeout => return Err(TimeoutError(timeout).into()),
+            Some(timeout) => interval.min(timeout - elapsed),
+            None => interval,
+        };
+
+        std::thread::sleep(wait);
+    }
+}
+
+/// Acquire a lock on `file` like [`proxmox_sys::fs::lock_file`], but poll for it, so the wait can
+/// be interrupted via `abort`. Waits indefinitely if `timeout` is `None`.
+pub fn lock_file_cancellable<F: AsRawFd>(
+    file: &mut F,
+    exclusive: bool,
+    timeout: Option<Duration>,
+    abort: Option<&AbortSignal>,
+) -> Result<(), Error> {
+    cancellable_retry(
+        timeout,
+        Duration::from_millis(100),
+        || match abort {
+            Some(abort) => abort.check(),
+            None => Ok(()),
+        },
+        || match proxmox_sys::fs::lock_file(file, exclusive, Some(Duration::ZERO)) {
+            Ok(()) => Ok(Some(())),
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err.into()),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_cancellable_timeout() {
+        let result = block_on(cancellable_timeout(async { 1 }, None, None));
+        assert_eq!(result.unwrap(), 1);
+
+        let timeout = Duration::from_millis(10);
+        let result = block_on(cancellable_timeout(
+            futures::future::pending::<()>(),
+            Some(timeout),
+            None,
+        ));
+        assert!(is_timeout(&result.unwrap_err()));
+
+        // an already triggered signal wins over a pending future without timeout
+        let abort = AbortSignal::new();
+        abort.abort();
+        let result = block_on(cancellable_timeout(
+            futures::future::pending::<()>(),
+            None,
+            Some(&abort),
+        ));
+        assert!(is_aborted(&result.unwrap_err()));
+
+        // triggered while waiting
+        let abort = AbortSignal::new();
+        let result = block_on(async {
+            let trigger = abort.clone();
+            let wait = cancellable_timeout(
+                futures::future::pending::<()>(),
+                Some(Duration::from_secs(60)),
+                Some(&abort),
+            );
+            let trigger = async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                trigger.abort();
+                futures::future::pending::<()>().await
+            };
+            tokio::select! {
+                result = wait => result,
+                _ = trigger => unreachable!(),
+            }
+        });
+        assert!(is_aborted(&result.unwrap_err()));
+    }
+
+    #[test]
+    fn test_cancellable_retry() {
+        let interval = Duration::from_millis(1);
+        let no_abort = || Ok(());
+
+        let mut polls = 0;
+        let result = cancellable_retry(None, interval, no_abort, || {
+            polls += 1;
+            Ok((polls == 3).then_some(polls))
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let timeout = Duration::from_millis(10);
+        let start = Instant::now();
+        let result = cancellable_retry::<(), _, _>(Some(timeout), interval, no_abort, || Ok(None));
+        assert!(is_timeout(&result.unwrap_err()));
+        assert!(start.elapsed() >= timeout);
+
+        // errors of the poll function are passed through
+        let result = cancellable_retry::<(), _, _>(None, interval, no_abort, || {
+            anyhow::bail!("poll failed")
+        });
+        let err = result.unwrap_err();
+        assert!(!is_timeout(&err) && !is_aborted(&err));
+
+        let abort = AbortSignal::new();
+        let mut polls = 0;
+        let result = cancellable_retry::<(), _, _>(
+            None,
+            interval,
+            || abort.check(),
+            || {
+                polls += 1;
+                if polls == 2 {
+                    abort.abort();
+                }
+                Ok(None)
+            },
+        );
+        assert!(is_aborted(&result.unwrap_err()));
+        assert_eq!(polls, 2);
+    }
+}
@@ -78,6 +78,11 @@ impl<K: std::cmp::Eq + std::hash::Hash + Copy, V: Clone + Send + 'static> AsyncL
 
         result
     }
+
+    /// Insert a value into the cache directly, replacing any cached value for key.
+    pub fn insert(&self, key: K, value: V) {
+        self.maps.lock().unwrap().0.insert(key, value);
+    }
 }
 
 mod test {
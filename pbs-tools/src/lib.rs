@@ -4,10 +4,28 @@ pub mod format;
 pub mod json;
 pub mod lru_cache;
 pub mod nom;
+pub mod pdf;
 pub mod sha;
+pub mod shamir;
 
+pub mod async_io;
 pub mod async_lru_cache;
 
+use std::ffi::{OsStr, OsString};
+use std::os::unix::process::CommandExt;
+
+/// Re-execute the current binary with `args` and an additional environment variable
+///
+/// Used to pass global options, which the command line parser does not know about, to the
+/// actual command. Only returns if executing failed.
+pub fn reexec_with_env(args: &[OsString], key: &str, value: impl AsRef<OsStr>) -> std::io::Error {
+    std::process::Command::new("/proc/self/exe")
+        .arg0(&args[0])
+        .args(&args[1..])
+        .env(key, value)
+        .exec()
+}
+
 /// Set MMAP_THRESHOLD to a fixed value (128 KiB)
 ///
 /// This avoids the "dynamic" mmap-treshold logic from glibc's malloc, which seems misguided and
@@ -0,0 +1,115 @@
+//! Minimal PDF writer
+//!
+//! Just enough to produce printable documents consisting of text (using
+//! the standard Type1 fonts, so no font data needs to be embedded) and
+//! filled rectangles.
+
+/// Resource name of the standard `Courier` font
+pub const FONT_COURIER: &str = "F1";
+/// Resource name of the standard `Courier-Bold` font
+pub const FONT_COURIER_BOLD: &str = "F2";
+
+/// Glyph width of the Courier fonts (relative to the font size)
+pub const COURIER_GLYPH_WIDTH: f64 = 0.6;
+
+/// Points per millimeter
+pub const POINTS_PER_MM: f64 = 72.0 / 25.4;
+
+/// Escape a string for use in a PDF text object.
+///
+/// Non-ASCII characters are replaced by '?', because the standard fonts
+/// only cover ASCII reliably.
+pub fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+/// Builds a PDF document page by page.
+pub struct PdfWriter {
+    objects: Vec<String>,
+    kids: Vec<String>,
+}
+
+impl Default for PdfWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PdfWriter {
+    pub fn new() -> Self {
+        Self {
+            objects: vec![
+                "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+                String::new(), // page tree, filled in by finish()
+                "<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>"
+                    .to_string(),
+                "<< /Type /Font /Subtype /Type1 /BaseFont /Courier-Bold \
+                 /Encoding /WinAnsiEncoding >>"
+                    .to_string(),
+            ],
+            kids: Vec::new(),
+        }
+    }
+
+    /// Add a page with the given size (in points) and content stream.
+    pub fn add_page(&mut self, width: f64, height: f64, content: &str) {
+        let page_id = self.objects.len() + 1;
+        self.objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {width:.2} {height:.2}] \
+             /Resources << /Font << /{FONT_COURIER} 3 0 R /{FONT_COURIER_BOLD} 4 0 R >> >> \
+             /Contents {} 0 R >>",
+            page_id + 1
+        ));
+        self.objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ));
+        self.kids.push(format!("{} 0 R", page_id));
+    }
+
+    /// Serialize the document.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.objects[1] = format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            self.kids.join(" "),
+            self.kids.len()
+        );
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (i, object) in self.objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+        }
+
+        let xref_offset = pdf.len();
+        pdf.extend_from_slice(
+            format!("xref\n0 {}\n0000000000 65535 f \n", self.objects.len() + 1).as_bytes(),
+        );
+        for offset in offsets {
+            pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        pdf.extend_from_slice(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+                self.objects.len() + 1,
+                xref_offset
+            )
+            .as_bytes(),
+        );
+
+        pdf
+    }
+}
@@ -0,0 +1,147 @@
+//! Shamir's secret sharing over GF(256)
+//!
+//! Splits a secret into `n` shares, so that any `k` of them can restore it, while fewer shares
+//! reveal nothing about the secret. Every byte of the secret is shared separately, using the
+//! same field as AES (reduction polynomial x^8 + x^4 + x^3 + x + 1).
+
+use anyhow::{bail, Error};
+
+/// Maximum number of shares (as in SLIP-0039)
+pub const MAX_SHARES: u8 = 16;
+
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut result = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            result ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    result
+}
+
+fn gf_inv(a: u8) -> u8 {
+    // a^254 == a^-1, since the multiplicative group has order 255
+    let mut result = 1;
+    for _ in 0..254 {
+        result = gf_mul(result, a);
+    }
+    result
+}
+
+/// Split `secret` into `shares` shares, any `threshold` of which restore it.
+///
+/// Returns the share index (the x coordinate, starting at 1) and data for each share.
+pub fn split(secret: &[u8], threshold: u8, shares: u8) -> Result<Vec<(u8, Vec<u8>)>, Error> {
+    if threshold < 2 {
+        bail!("threshold must be at least 2");
+    }
+    if shares < threshold {
+        bail!("number of shares must not be lower than the threshold");
+    }
+    if shares > MAX_SHARES {
+        bail!("at most {} shares are supported", MAX_SHARES);
+    }
+
+    let degree = threshold as usize - 1;
+    let coefficients = proxmox_sys::linux::random_data(secret.len() * degree)?;
+
+    let result = (1..=shares)
+        .map(|x| {
+            let data = secret
+                .iter()
+                .zip(coefficients.chunks(degree))
+                .map(|(byte, coefficients)| {
+                    // horner scheme, the secret byte is the constant term
+                    coefficients
+                        .iter()
+                        .rev()
+                        .chain(std::iter::once(byte))
+                        .fold(0, |acc, c| gf_mul(acc, x) ^ c)
+                })
+                .collect();
+            (x, data)
+        })
+        .collect();
+
+    Ok(result)
+}
+
+/// Restore a secret from (at least `threshold`) shares created by `split`.
+///
+/// Note that there is no way to detect wrong or too few shares here, the result is garbage in
+/// that case. Callers need to validate the restored secret.
+pub fn combine(shares: &[(u8, &[u8])]) -> Result<Vec<u8>, Error> {
+    let len = match shares.first() {
+        Some((_, data)) => data.len(),
+        None => bail!("no shares given"),
+    };
+
+    for (i, (x, data)) in shares.iter().enumerate() {
+        if *x == 0 {
+            bail!("invalid share index 0");
+        }
+        if data.len() != len {
+            bail!("share {} has a different length", x);
+        }
+        if shares[..i].iter().any(|(other, _)| other == x) {
+            bail!("share {} given twice", x);
+        }
+    }
+
+    let mut secret = vec![0u8; len];
+    for (xi, data) in shares {
+        // lagrange basis polynomial evaluated at 0
+        let basis = shares
+            .iter()
+            .filter(|(xj, _)| xj != xi)
+            .fold(1, |acc, (xj, _)| gf_mul(acc, gf_mul(*xj, gf_inv(xj ^ xi))));
+
+        for (byte, y) in secret.iter_mut().zip(data.iter()) {
+            *byte ^= gf_mul(basis, *y);
+        }
+    }
+
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gf_inv() {
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn test_split_combine() -> Result<(), Error> {
+        let secret = b"a secret which is split into shares";
+        let shares = split(secret, 3, 5)?;
+        assert_eq!(shares.len(), 5);
+
+        let subset: Vec<(u8, &[u8])> = shares
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .chain(shares.last())
+            .map(|(x, data)| (*x, &data[..]))
+            .collect();
+        assert_eq!(subset.len(), 3);
+        assert_eq!(combine(&subset)?, secret);
+
+        assert_ne!(combine(&subset[..2])?, secret);
+
+        assert!(split(secret, 1, 5).is_err());
+        assert!(split(secret, 4, 3).is_err());
+
+        Ok(())
+    }
+}
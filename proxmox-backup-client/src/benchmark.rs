@@ -15,10 +15,10 @@ use proxmox_router::{
 use proxmox_schema::{api, ApiType, ReturnType};
 
 use pbs_api_types::{BackupNamespace, BackupType};
-use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::tools::key_source::load_and_decrypt_key;
 use pbs_client::{BackupRepository, BackupWriter};
 use pbs_datastore::data_blob::{DataBlob, DataChunkBuilder};
-use pbs_key_config::{load_and_decrypt_key, KeyDerivationConfig};
+use pbs_key_config::KeyDerivationConfig;
 use pbs_tools::crypt_config::CryptConfig;
 
 use crate::{
@@ -135,7 +135,7 @@ pub async fn benchmark(
     let crypt_config = match keyfile {
         None => None,
         Some(path) => {
-            let (key, _, _) = load_and_decrypt_key(&path, &get_encryption_key_password)?;
+            let (key, _, _) = load_and_decrypt_key(&path)?;
             let crypt_config = CryptConfig::new(key)?;
             Some(Arc::new(crypt_config))
         }
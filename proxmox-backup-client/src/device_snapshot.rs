@@ -0,0 +1,347 @@
+//! Temporary block device snapshots for crash consistent image backups
+//!
+//! Block devices backed up as image archives are snapshotted before the upload starts, and the
+//! image is read from the snapshot instead. Supported are LVM logical volumes: thick volumes get
+//! a classic device mapper snapshot with a copy-on-write area in the volume group, thin volumes
+//! a thin snapshot. Creating the snapshot suspends the origin device, which also freezes a file
+//! system mounted on it. The snapshots are removed once the [DeviceSnapshots] are dropped, or
+//! when the client is interrupted. Snapshots left behind by a client which got killed are removed
+//! before the volume is snapshotted again.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use futures::FutureExt;
+use serde_json::{json, Value};
+use tokio::signal::unix::{signal, SignalKind};
+
+use proxmox_sys::command::run_command;
+use proxmox_sys::fs::{open_file_locked, CreateOptions};
+
+/// Name prefix of the snapshot volumes, followed by the backup time.
+const SNAPSHOT_NAME_PREFIX: &str = "pbs-snapshot-";
+
+/// Directory for the per volume locks, which protect the snapshots of running backups from being
+/// removed as stale.
+const LOCK_DIR: &str = "/run/lock";
+
+/// Properties of a block device as reported by `lsblk`.
+const LSBLK_COLUMNS: &str = "NAME,PATH,SIZE,TYPE,MODEL,SERIAL,WWN,UUID,PTUUID,FSTYPE,LABEL";
+
+/// Metadata of a block device, recorded in the manifest.
+///
+/// Returns the `lsblk` properties of the device, with empty values left out, and the kind of
+/// snapshot the image was read from, if any.
+pub fn device_metadata(device: &str, snapshot: Option<&str>) -> Result<Value, Error> {
+    let output = run_command(
+        Command::new("lsblk")
+            .args(["--json", "--bytes", "--nodeps", "-o", LSBLK_COLUMNS])
+            .arg(device),
+        None,
+    )?;
+    let mut output: Value = serde_json::from_str(&output)?;
+    let mut metadata = match output["blockdevices"][0].take() {
+        Value::Object(map) => map,
+        _ => bail!("unexpected output of lsblk for {device:?}"),
+    };
+    metadata.retain(|_, value| !value.is_null() && value.as_str() != Some(""));
+    let mut metadata = Value::Object(metadata);
+    if let Some(snapshot) = snapshot {
+        metadata["snapshot"] = json!(snapshot);
+    }
+    Ok(metadata)
+}
+
+enum SnapshotKind {
+    /// Classic snapshot with a copy-on-write area of a thick volume
+    Lvm,
+    /// Snapshot of a thin volume
+    LvmThin,
+}
+
+impl SnapshotKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotKind::Lvm => "lvm",
+            SnapshotKind::LvmThin => "lvm-thin",
+        }
+    }
+}
+
+/// Parse the `vg_name:lv_name:pool_lv` output of `lvs` for a single volume.
+fn parse_lvs_volume(output: &str) -> Result<(&str, &str, SnapshotKind), Error> {
+    let fields: Vec<&str> = output.trim().split(':').collect();
+    Ok(match fields[..] {
+        [vg, lv, pool] if !vg.is_empty() && !lv.is_empty() => {
+            let kind = if pool.is_empty() {
+                SnapshotKind::Lvm
+            } else {
+                SnapshotKind::LvmThin
+            };
+            (vg, lv, kind)
+        }
+        _ => bail!("unexpected output of lvs: {output:?}"),
+    })
+}
+
+/// Parse the `lv_name:origin` output of `lvs` for a volume group and return the names of the
+/// snapshots of `origin` created by the client.
+fn parse_lvs_snapshots<'a>(output: &'a str, origin: &str) -> Vec<&'a str> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .filter(|(name, lv_origin)| {
+            *lv_origin == origin
+                && name
+                    .strip_prefix(origin)
+                    .and_then(|rest| rest.strip_prefix('-'))
+                    .and_then(|rest| rest.strip_prefix(SNAPSHOT_NAME_PREFIX))
+                    .map_or(false, |time| {
+                        !time.is_empty() && time.bytes().all(|b| b.is_ascii_digit())
+                    })
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Remove snapshots of `vg/origin` left behind by a client which did not finish. The caller
+/// must hold the lock of the volume.
+fn remove_stale_snapshots(vg: &str, origin: &str) -> Result<(), Error> {
+    let output = run_command(
+        Command::new("lvs").args([
+            "--noheadings",
+            "--separator",
+            ":",
+            "-o",
+            "lv_name,origin",
+            vg,
+        ]),
+        None,
+    )?;
+    for name in parse_lvs_snapshots(&output, origin) {
+        log::warn!("removing stale snapshot {vg}/{name}");
+        run_command(
+            Command::new("lvremove").args(["-f", &format!("{vg}/{name}")]),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+struct DeviceSnapshot {
+    device: String,
+    /// `vg/lv` of the snapshot volume
+    lv: String,
+    kind: SnapshotKind,
+    /// Lock of the origin volume, held as long as the snapshot exists
+    _lock: File,
+}
+
+impl DeviceSnapshot {
+    /// Snapshot `device`, `cow_size` is the size of the copy-on-write area of snapshots of thick
+    /// volumes in MiB, 10% of the volume by default.
+    fn create(device: &str, name: &str, cow_size: Option<u64>) -> Result<Self, Error> {
+        let output = run_command(
+            Command::new("lvs").args([
+                "--noheadings",
+                "--separator",
+                ":",
+                "-o",
+                "vg_name,lv_name,pool_lv",
+                device,
+            ]),
+            None,
+        )
+        .map_err(|err| {
+            format_err!("{device} is not an LVM logical volume, unable to snapshot it - {err}")
+        })?;
+        let (vg, lv, kind) = parse_lvs_volume(&output)?;
+
+        let lock_path = Path::new(LOCK_DIR).join(format!("{SNAPSHOT_NAME_PREFIX}{vg}-{lv}.lck"));
+        let lock = open_file_locked(
+            &lock_path,
+            Duration::from_secs(10),
+            true,
+            CreateOptions::new(),
+        )
+        .map_err(|err| format_err!("{vg}/{lv} is already snapshotted by another backup - {err}"))?;
+        remove_stale_snapshots(vg, lv)?;
+
+        let snapshot_name = format!("{lv}-{name}");
+        let mut command = Command::new("lvcreate");
+        command.args(["--snapshot", "--name", &snapshot_name]);
+        match kind {
+            SnapshotKind::LvmThin => {
+                command.args(["--setactivationskip", "n"]);
+            }
+            SnapshotKind::Lvm => match cow_size {
+                Some(size) => {
+                    command.arg("--size").arg(format!("{size}m"));
+                }
+                None => {
+                    command.args(["--extents", "10%ORIGIN"]);
+                }
+            },
+        }
+        command.arg(format!("{vg}/{lv}"));
+        run_command(&mut command, None)?;
+
+        let snapshot = Self {
+            device: device.to_string(),
+            lv: format!("{vg}/{snapshot_name}"),
+            kind,
+            _lock: lock,
+        };
+
+        // wait for the device node of the snapshot
+        if let Err(err) = run_command(Command::new("udevadm").arg("settle"), None) {
+            log::warn!("udevadm settle failed - {err}");
+        }
+        if !snapshot.path().exists() {
+            let _ = snapshot.remove();
+            bail!("device of snapshot {} did not show up", snapshot.lv);
+        }
+
+        Ok(snapshot)
+    }
+
+    fn path(&self) -> PathBuf {
+        Path::new("/dev").join(&self.lv)
+    }
+
+    fn remove(&self) -> Result<(), Error> {
+        run_command(Command::new("lvremove").args(["-f", &self.lv]), None)?;
+        Ok(())
+    }
+}
+
+/// Remove all `snapshots`, newest first.
+fn remove_snapshots(snapshots: &Mutex<Vec<DeviceSnapshot>>) {
+    let mut snapshots = snapshots.lock().unwrap();
+    while let Some(snapshot) = snapshots.pop() {
+        if let Err(err) = snapshot.remove() {
+            log::warn!("unable to remove snapshot of {} - {err}", snapshot.device);
+        }
+    }
+}
+
+/// Snapshots of the block devices of a backup, removed on drop or on SIGINT and SIGTERM.
+pub struct DeviceSnapshots {
+    snapshots: Arc<Mutex<Vec<DeviceSnapshot>>>,
+    signal_handler: tokio::task::JoinHandle<()>,
+}
+
+impl DeviceSnapshots {
+    /// Snapshot all `devices`, which are replaced by the paths of the corresponding snapshot
+    /// devices.
+    ///
+    /// `cow_size` is the size of the copy-on-write area in MiB for snapshots of thick volumes.
+    pub fn create(
+        devices: &mut [String],
+        cow_size: Option<u64>,
+        backup_time: i64,
+    ) -> Result<Self, Error> {
+        let name = format!("{SNAPSHOT_NAME_PREFIX}{backup_time}");
+
+        // remove the snapshots if the client is interrupted, the process exits without drop
+        let snapshots = Arc::new(Mutex::new(Vec::with_capacity(devices.len())));
+        let mut interrupt_int = signal(SignalKind::interrupt())?;
+        let mut interrupt_term = signal(SignalKind::terminate())?;
+        let signal_handler = tokio::spawn({
+            let snapshots = Arc::clone(&snapshots);
+            async move {
+                futures::future::select(
+                    interrupt_int.recv().boxed(),
+                    interrupt_term.recv().boxed(),
+                )
+                .await;
+                log::info!("interrupted, removing device snapshots");
+                tokio::task::block_in_place(|| remove_snapshots(&snapshots));
+                std::process::exit(-1);
+            }
+        });
+
+        let snapshots = Self {
+            snapshots,
+            signal_handler,
+        };
+        for device in devices.iter_mut() {
+            let snapshot = DeviceSnapshot::create(device, &name, cow_size)
+                .map_err(|err| format_err!("unable to snapshot {device} - {err}"))?;
+            let path = snapshot.path();
+            log::info!("using snapshot of {device:?} at {path:?}");
+            *device = path.to_string_lossy().into_owned();
+            snapshots.snapshots.lock().unwrap().push(snapshot);
+        }
+        Ok(snapshots)
+    }
+
+    /// The kind of snapshot taken of `device`, if any.
+    pub fn snapshot_kind(&self, device: &str) -> Option<&'static str> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|snapshot| snapshot.device == device)
+            .map(|snapshot| snapshot.kind.as_str())
+    }
+}
+
+impl Drop for DeviceSnapshots {
+    fn drop(&mut self) {
+        self.signal_handler.abort();
+        remove_snapshots(&self.snapshots);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lvs_volume() -> Result<(), Error> {
+        let (vg, lv, kind) = parse_lvs_volume("  pve:root:\n")?;
+        assert_eq!((vg, lv), ("pve", "root"));
+        assert!(matches!(kind, SnapshotKind::Lvm));
+
+        let (vg, lv, kind) = parse_lvs_volume("  pve:vm-100-disk-0:data\n")?;
+        assert_eq!((vg, lv), ("pve", "vm-100-disk-0"));
+        assert!(matches!(kind, SnapshotKind::LvmThin));
+
+        assert!(parse_lvs_volume("").is_err());
+        assert!(parse_lvs_volume("pve:root").is_err());
+        assert!(parse_lvs_volume("  pve:root:\n  pve:swap:\n").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_lvs_snapshots() {
+        let output = "  data::\n  \
+            root:\n  \
+            root-pbs-snapshot-1700000000:root\n  \
+            root-pbs-snapshot-1700000100:root\n  \
+            root-manual:root\n  \
+            root-pbs-snapshot-:root\n  \
+            root-pbs-snapshot-17x:root\n  \
+            root2-pbs-snapshot-1700000000:root2\n  \
+            vm-100-disk-0-pbs-snapshot-1700000000:vm-100-disk-0\n";
+
+        assert_eq!(
+            parse_lvs_snapshots(output, "root"),
+            [
+                "root-pbs-snapshot-1700000000",
+                "root-pbs-snapshot-1700000100"
+            ],
+        );
+        assert_eq!(
+            parse_lvs_snapshots(output, "vm-100-disk-0"),
+            ["vm-100-disk-0-pbs-snapshot-1700000000"],
+        );
+        assert!(parse_lvs_snapshots(output, "data").is_empty());
+    }
+}
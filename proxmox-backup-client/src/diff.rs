@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use proxmox_human_byte::HumanByte;
+use proxmox_router::cli::*;
+use proxmox_schema::{api, ApiType, ArraySchema, ReturnType};
+
+use pbs_api_types::BackupNamespace;
+use pbs_client::BackupReader;
+use pbs_datastore::catalog::{CatalogEntryType, DirEntryAttribute};
+use pbs_tools::crypt_config::CryptConfig;
+use pbs_tools::json::required_string_param;
+
+use crate::catalog::download_catalog;
+use crate::{
+    complete_group_or_snapshot, complete_namespace, complete_pxar_archive_name,
+    complete_repository, connect, crypto_parameters, decrypt_key, dir_or_last_from_group,
+    extract_repository_from_value, format_key_source, optional_ns_param, record_repository,
+    KEYFD_SCHEMA, REPO_URL_SCHEMA,
+};
+
+#[api]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// How an entry differs between two snapshots.
+enum Change {
+    /// Only present in the second snapshot
+    Added,
+    /// Only present in the first snapshot
+    Removed,
+    /// Type, size or modification time differ
+    Modified,
+}
+
+#[api]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// An entry which differs between two snapshots.
+struct DiffEntry {
+    /// Path inside the archive
+    path: String,
+    change: Change,
+    /// Catalog entry type, in the second snapshot unless removed
+    #[serde(rename = "type")]
+    entry_type: String,
+    /// File size in the second snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    /// Change of the file size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size_delta: Option<i64>,
+}
+
+fn file_size(attr: Option<&DirEntryAttribute>) -> Option<u64> {
+    match attr {
+        Some(DirEntryAttribute::File { size, .. }) => Some(*size),
+        _ => None,
+    }
+}
+
+impl DiffEntry {
+    fn new(
+        path: &[u8],
+        old: Option<&DirEntryAttribute>,
+        new: Option<&DirEntryAttribute>,
+    ) -> Result<Self, Error> {
+        let change = match (old, new) {
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Removed,
+            (Some(_), Some(_)) => Change::Modified,
+            (None, None) => bail!("no entry for {:?}", String::from_utf8_lossy(path)),
+        };
+
+        let old_size = file_size(old);
+        let size = file_size(new);
+        let size_delta = match (old_size, size) {
+            (None, None) => None,
+            (old_size, size) => Some(size.unwrap_or(0) as i64 - old_size.unwrap_or(0) as i64),
+        };
+
+        Ok(Self {
+            path: String::from_utf8_lossy(path).to_string(),
+            change,
+            entry_type: CatalogEntryType::from(new.or(old).unwrap()).to_string(),
+            size,
+            size_delta,
+        })
+    }
+}
+
+const DIFF_RETURN_TYPE: ReturnType = ReturnType {
+    optional: false,
+    schema: &ArraySchema::new("Changed entries.", &DiffEntry::API_SCHEMA).schema(),
+};
+
+fn render_size_delta(value: &Value, _record: &Value) -> Result<String, Error> {
+    Ok(match value.as_i64() {
+        Some(delta) if delta < 0 => format!("-{}", HumanByte::from(delta.unsigned_abs())),
+        Some(delta) => format!("+{}", HumanByte::from(delta as u64)),
+        None => String::new(),
+    })
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "snapshot-a": {
+                type: String,
+                description: "Group/Snapshot path of the older snapshot.",
+            },
+            "snapshot-b": {
+                type: String,
+                description: "Group/Snapshot path of the newer snapshot.",
+            },
+            "archive-name": {
+                type: String,
+                description: "Backup archive name.",
+            },
+            keyfile: {
+                optional: true,
+                type: String,
+                description: "Path to encryption key.",
+            },
+            keyfd: {
+                schema: KEYFD_SCHEMA,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// List the files which were added, removed or modified in a file archive between two
+/// snapshots, based on their catalogs.
+async fn diff(param: Value) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let client = connect(&repo)?;
+    let backup_ns = optional_ns_param(&param)?;
+    let archive_name = required_string_param(&param, "archive-name")?;
+
+    let output_format = get_output_format(&param);
+
+    let server_archive_name = if archive_name.ends_with(".pxar") {
+        format!("{}.didx", archive_name)
+    } else {
+        bail!("Can only compare pxar archives.");
+    };
+
+    let crypto = crypto_parameters(&param)?;
+
+    let crypt_config = match crypto.enc_key {
+        None => None,
+        Some(key) => {
+            let (key, _created, _fingerprint) = decrypt_key(&key.key).map_err(|err| {
+                log::error!("{}", format_key_source(&key.source, "encryption"));
+                err
+            })?;
+            let crypt_config = CryptConfig::new(key)?;
+            Some(Arc::new(crypt_config))
+        }
+    };
+
+    let mut catalogs = Vec::new();
+    for param_name in ["snapshot-a", "snapshot-b"] {
+        let path = required_string_param(&param, param_name)?;
+        let backup_dir = dir_or_last_from_group(&client, &repo, &backup_ns, path).await?;
+
+        let reader = BackupReader::start(
+            &client,
+            crypt_config.clone(),
+            repo.store(),
+            &backup_ns,
+            &backup_dir,
+            true,
+        )
+        .await?;
+
+        let (manifest, _) = reader.download_manifest().await?;
+        manifest.check_fingerprint(crypt_config.as_ref().map(Arc::as_ref))?;
+
+        let mut catalog = download_catalog(&reader, &manifest, crypt_config.clone()).await?;
+        let root = catalog.root()?;
+        let archive_root = catalog
+            .lookup(&root, server_archive_name.as_bytes())?
+            .ok_or_else(|| format_err!("archive '{archive_name}' not found in {backup_dir}"))?;
+        catalogs.push((catalog, archive_root));
+    }
+
+    let (mut new_catalog, new_root) = catalogs.pop().unwrap();
+    let (mut old_catalog, old_root) = catalogs.pop().unwrap();
+
+    let mut entries = Vec::new();
+    old_catalog.diff(
+        &old_root,
+        &mut new_catalog,
+        &new_root,
+        &mut Vec::new(),
+        &mut |path, old, new| {
+            entries.push(DiffEntry::new(path, old, new)?);
+            Ok(())
+        },
+    )?;
+
+    record_repository(&repo);
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("change"))
+        .column(ColumnConfig::new("type"))
+        .column(ColumnConfig::new("path"))
+        .column(ColumnConfig::new("size").renderer(pbs_tools::format::render_bytes_human_readable))
+        .column(ColumnConfig::new("size-delta").renderer(render_size_delta));
+
+    format_and_print_result_full(
+        &mut serde_json::to_value(entries)?,
+        &DIFF_RETURN_TYPE,
+        &output_format,
+        &options,
+    );
+
+    Ok(())
+}
+
+fn complete_diff_archive_name(arg: &str, param: &HashMap<String, String>) -> Vec<String> {
+    let mut param = param.clone();
+    if let Some(snapshot) = param.get("snapshot-b").cloned() {
+        param.insert("snapshot".to_string(), snapshot);
+    }
+    complete_pxar_archive_name(arg, &param)
+}
+
+pub fn diff_cmd_def() -> CliCommand {
+    CliCommand::new(&API_METHOD_DIFF)
+        .arg_param(&["snapshot-a", "snapshot-b", "archive-name"])
+        .completion_cb("repository", complete_repository)
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("snapshot-a", complete_group_or_snapshot)
+        .completion_cb("snapshot-b", complete_group_or_snapshot)
+        .completion_cb("archive-name", complete_diff_archive_name)
+}
@@ -0,0 +1,491 @@
+//! Temporary file system snapshots for crash consistent backups
+//!
+//! The file systems of the backed up directories are snapshotted before the upload starts, and
+//! the directories are read from the snapshots instead. Supported are ZFS datasets, btrfs
+//! subvolumes and thin provisioned LVM volumes. The snapshots are removed once the
+//! [FsSnapshots] are dropped, or when the client is interrupted.
+//!
+//! File systems mounted below a snapshotted one (for example child ZFS datasets) and nested btrfs
+//! subvolumes are not part of its snapshot. They show up as empty directories, like they would be
+//! skipped without snapshots, a warning lists them.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use futures::FutureExt;
+use tokio::signal::unix::{signal, SignalKind};
+
+use proxmox_sys::command::run_command;
+use proxmox_sys::linux::procfs::MountInfo;
+
+/// A mounted file system, from `/proc/self/mountinfo`.
+struct Mount {
+    mount_point: PathBuf,
+    fs_type: String,
+    source: String,
+}
+
+/// Find the file system `path` is on.
+fn find_mount(mount_info: &MountInfo, path: &Path) -> Result<Mount, Error> {
+    let mounts = mount_info.into_iter().map(|(_id, entry)| Mount {
+        mount_point: entry.mount_point.clone(),
+        fs_type: entry.fs_type.clone(),
+        source: entry
+            .mount_source
+            .as_ref()
+            .map(|source| source.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    });
+    select_mount(mounts, path)
+}
+
+/// Select the innermost of `mounts` containing `path`, in mount order.
+fn select_mount(mounts: impl Iterator<Item = Mount>, path: &Path) -> Result<Mount, Error> {
+    let mut found: Option<Mount> = None;
+    for mount in mounts {
+        if !path.starts_with(&mount.mount_point) {
+            continue;
+        }
+        // the innermost mount point wins, on equal paths the latter (over-)mount
+        let len = mount.mount_point.as_os_str().len();
+        if found
+            .as_ref()
+            .map_or(false, |found| found.mount_point.as_os_str().len() > len)
+        {
+            continue;
+        }
+        found = Some(mount);
+    }
+    found.ok_or_else(|| format_err!("unable to find the file system of {path:?}"))
+}
+
+/// Mount points of all `mounts` strictly below `mount_point`.
+fn nested_mounts(mounts: impl Iterator<Item = Mount>, mount_point: &Path) -> Vec<PathBuf> {
+    let mut nested: Vec<PathBuf> = mounts
+        .filter(|mount| {
+            mount.mount_point != mount_point && mount.mount_point.starts_with(mount_point)
+        })
+        .map(|mount| mount.mount_point)
+        .collect();
+    nested.sort();
+    nested.dedup();
+    nested
+}
+
+/// Parse the output of `btrfs subvolume list -o`, one `ID 257 gen 8 top level 5 path <path>`
+/// line per subvolume, into the subvolume paths.
+fn parse_subvolume_list(output: &str) -> Vec<&str> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(" path ").map(|(_, path)| path))
+        .collect()
+}
+
+/// Warn about file systems and btrfs subvolumes below `mount`, which are not part of its snapshot.
+fn warn_nested(mount_info: &MountInfo, mount: &Mount) {
+    let mounts = mount_info.into_iter().map(|(_id, entry)| Mount {
+        mount_point: entry.mount_point.clone(),
+        fs_type: entry.fs_type.clone(),
+        source: String::new(),
+    });
+    for nested in nested_mounts(mounts, &mount.mount_point) {
+        log::warn!(
+            "{} is a separate file system, it is not part of the snapshot of {}",
+            nested.display(),
+            mount.mount_point.display()
+        );
+    }
+
+    if mount.fs_type == "btrfs" {
+        let output = run_command(
+            Command::new("btrfs")
+                .args(["subvolume", "list", "-o"])
+                .arg(&mount.mount_point),
+            None,
+        );
+        match output {
+            Ok(output) => {
+                for path in parse_subvolume_list(&output) {
+                    log::warn!(
+                        "btrfs subvolume '{path}' is not part of the snapshot of {}",
+                        mount.mount_point.display()
+                    );
+                }
+            }
+            Err(err) => log::warn!(
+                "unable to list the subvolumes of {} - {err}",
+                mount.mount_point.display()
+            ),
+        }
+    }
+}
+
+/// Parse the `vg_name:lv_name:pool_lv` output of `lvs`, only thin volumes can be snapshotted.
+fn parse_thin_volume(output: &str) -> Result<(&str, &str), Error> {
+    let fields: Vec<&str> = output.trim().split(':').map(str::trim).collect();
+    match fields[..] {
+        [vg, lv, pool] if !pool.is_empty() => Ok((vg, lv)),
+        [vg, lv, _] => {
+            bail!("{vg}/{lv} is not a thin volume, only thin volumes can be snapshotted")
+        }
+        _ => bail!("unexpected output of lvs: {output:?}"),
+    }
+}
+
+enum SnapshotKind {
+    /// `dataset@name`
+    Zfs(String),
+    /// Path of the read-only snapshot subvolume
+    Btrfs(PathBuf),
+    /// `vg/lv` of the snapshot volume, which is mounted on a temporary directory
+    Lvm(String),
+}
+
+struct FsSnapshot {
+    mount_point: PathBuf,
+    /// Directory containing the snapshotted file system
+    root: PathBuf,
+    kind: SnapshotKind,
+}
+
+impl FsSnapshot {
+    fn create(mount: &Mount, name: &str) -> Result<Self, Error> {
+        let kind = match mount.fs_type.as_str() {
+            "zfs" => {
+                let snapshot = format!("{}@{name}", mount.source);
+                run_command(Command::new("zfs").args(["snapshot", &snapshot]), None)?;
+                SnapshotKind::Zfs(snapshot)
+            }
+            "btrfs" => {
+                let path = mount.mount_point.join(format!(".{name}"));
+                run_command(
+                    Command::new("btrfs")
+                        .args(["subvolume", "snapshot", "-r"])
+                        .arg(&mount.mount_point)
+                        .arg(&path),
+                    None,
+                )?;
+                SnapshotKind::Btrfs(path)
+            }
+            _ if mount.source.starts_with("/dev/") => {
+                let output = run_command(
+                    Command::new("lvs").args([
+                        "--noheadings",
+                        "--separator",
+                        ":",
+                        "-o",
+                        "vg_name,lv_name,pool_lv",
+                        &mount.source,
+                    ]),
+                    None,
+                )
+                .map_err(|err| {
+                    format_err!(
+                        "{} is neither on ZFS, btrfs nor on LVM - {err}",
+                        mount.mount_point.display()
+                    )
+                })?;
+                let (vg, lv) = parse_thin_volume(&output)?;
+                let snapshot = format!("{vg}/{lv}-{name}");
+                run_command(
+                    Command::new("lvcreate").args([
+                        "--snapshot",
+                        "--setactivationskip",
+                        "n",
+                        "--name",
+                        &format!("{lv}-{name}"),
+                        &format!("{vg}/{lv}"),
+                    ]),
+                    None,
+                )?;
+                SnapshotKind::Lvm(snapshot)
+            }
+            other => bail!(
+                "unable to snapshot {}, file system '{other}' is not supported",
+                mount.mount_point.display()
+            ),
+        };
+
+        let root = match kind {
+            SnapshotKind::Zfs(_) => mount.mount_point.join(".zfs/snapshot").join(name),
+            SnapshotKind::Btrfs(ref path) => path.clone(),
+            SnapshotKind::Lvm(_) => PathBuf::new(), // set once mounted
+        };
+
+        let mut snapshot = Self {
+            mount_point: mount.mount_point.clone(),
+            root,
+            kind,
+        };
+
+        if let SnapshotKind::Lvm(ref lv) = snapshot.kind {
+            let dir = nix::unistd::mkdtemp("/tmp/pbs-fs-snapshot-XXXXXX")?;
+            // the snapshot shares the UUID of the origin
+            let options = if mount.fs_type == "xfs" {
+                "ro,nouuid"
+            } else {
+                "ro"
+            };
+            let result = run_command(
+                Command::new("mount")
+                    .args(["-t", &mount.fs_type, "-o", options])
+                    .arg(format!("/dev/{lv}"))
+                    .arg(&dir),
+                None,
+            );
+            snapshot.root = dir;
+            if let Err(err) = result {
+                let _ = snapshot.remove();
+                return Err(err);
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    fn remove(&self) -> Result<(), Error> {
+        match self.kind {
+            SnapshotKind::Zfs(ref snapshot) => {
+                run_command(Command::new("zfs").args(["destroy", snapshot]), None)?;
+            }
+            SnapshotKind::Btrfs(ref path) => {
+                run_command(
+                    Command::new("btrfs")
+                        .args(["subvolume", "delete"])
+                        .arg(path),
+                    None,
+                )?;
+            }
+            SnapshotKind::Lvm(ref lv) => {
+                if !self.root.as_os_str().is_empty() {
+                    // not mounted if mounting failed
+                    let _ = run_command(Command::new("umount").arg(&self.root), None);
+                    std::fs::remove_dir(&self.root)?;
+                }
+                run_command(Command::new("lvremove").args(["-f", lv]), None)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn run_hook(hook: &str, phase: &str) -> Result<(), Error> {
+    log::info!("running file system snapshot hook '{hook}' ({phase})");
+    run_command(Command::new(hook).arg(phase), None)
+        .map_err(|err| format_err!("{phase} hook failed - {err}"))?;
+    Ok(())
+}
+
+/// Remove all `snapshots`, newest first.
+fn remove_snapshots(snapshots: &Mutex<Vec<FsSnapshot>>) {
+    let mut snapshots = snapshots.lock().unwrap();
+    while let Some(snapshot) = snapshots.pop() {
+        if let Err(err) = snapshot.remove() {
+            log::warn!(
+                "unable to remove snapshot of {} - {err}",
+                snapshot.mount_point.display()
+            );
+        }
+    }
+}
+
+/// Snapshots of the file systems of a backup, removed on drop or on SIGINT and SIGTERM.
+pub struct FsSnapshots {
+    snapshots: Arc<Mutex<Vec<FsSnapshot>>>,
+    signal_handler: tokio::task::JoinHandle<()>,
+}
+
+impl FsSnapshots {
+    /// Snapshot the file systems of all `paths`, which are mapped to the corresponding paths
+    /// inside the snapshots.
+    ///
+    /// The optional `hook` is called with `pre-snapshot` before and `post-snapshot` after
+    /// taking the snapshots, for example to quiesce databases for the time in between.
+    pub fn create(
+        paths: &mut [String],
+        hook: Option<&str>,
+        backup_time: i64,
+    ) -> Result<Self, Error> {
+        let mount_info = MountInfo::read()?;
+
+        let mut mounts: Vec<Mount> = Vec::new();
+        let mut path_mounts = Vec::with_capacity(paths.len());
+        for path in paths.iter() {
+            let path = std::fs::canonicalize(path)
+                .map_err(|err| format_err!("unable to resolve {path:?} - {err}"))?;
+            let mount = find_mount(&mount_info, &path)?;
+            let index = match mounts
+                .iter()
+                .position(|m| m.mount_point == mount.mount_point)
+            {
+                Some(index) => index,
+                None => {
+                    mounts.push(mount);
+                    mounts.len() - 1
+                }
+            };
+            path_mounts.push((path, index));
+        }
+
+        for mount in &mounts {
+            warn_nested(&mount_info, mount);
+        }
+
+        // remove the snapshots if the client is interrupted, the process exits without drop
+        let snapshots = Arc::new(Mutex::new(Vec::with_capacity(mounts.len())));
+        let mut interrupt_int = signal(SignalKind::interrupt())?;
+        let mut interrupt_term = signal(SignalKind::terminate())?;
+        let signal_handler = tokio::spawn({
+            let snapshots = Arc::clone(&snapshots);
+            async move {
+                futures::future::select(
+                    interrupt_int.recv().boxed(),
+                    interrupt_term.recv().boxed(),
+                )
+                .await;
+                log::info!("interrupted, removing file system snapshots");
+                tokio::task::block_in_place(|| remove_snapshots(&snapshots));
+                std::process::exit(-1);
+            }
+        });
+        let snapshots = Self {
+            snapshots,
+            signal_handler,
+        };
+
+        if let Some(hook) = hook {
+            run_hook(hook, "pre-snapshot")?;
+        }
+
+        let name = format!("pbs-snapshot-{backup_time}");
+        let mut result = Ok(());
+        for mount in &mounts {
+            match FsSnapshot::create(mount, &name) {
+                Ok(snapshot) => snapshots.snapshots.lock().unwrap().push(snapshot),
+                Err(err) => {
+                    result = Err(format_err!(
+                        "unable to snapshot {} - {err}",
+                        mount.mount_point.display()
+                    ));
+                    break;
+                }
+            }
+        }
+
+        if let Some(hook) = hook {
+            if let Err(err) = run_hook(hook, "post-snapshot") {
+                result = result.and(Err(err));
+            }
+        }
+        result?;
+
+        let list = snapshots.snapshots.lock().unwrap();
+        for (path, (resolved, index)) in paths.iter_mut().zip(path_mounts) {
+            let snapshot = &list[index];
+            let relative = resolved.strip_prefix(&snapshot.mount_point).unwrap();
+            let snapshot_path = snapshot.root.join(relative);
+            log::info!("using snapshot of {path:?} at {snapshot_path:?}");
+            *path = snapshot_path.to_string_lossy().into_owned();
+        }
+        drop(list);
+
+        Ok(snapshots)
+    }
+}
+
+impl Drop for FsSnapshots {
+    fn drop(&mut self) {
+        self.signal_handler.abort();
+        remove_snapshots(&self.snapshots);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mount(mount_point: &str, fs_type: &str) -> Mount {
+        Mount {
+            mount_point: mount_point.into(),
+            fs_type: fs_type.to_string(),
+            source: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_select_mount() {
+        let mounts = || {
+            vec![
+                mount("/", "ext4"),
+                mount("/srv", "zfs"),
+                mount("/srv/data", "btrfs"),
+                mount("/srv", "xfs"),
+            ]
+            .into_iter()
+        };
+
+        let found = select_mount(mounts(), Path::new("/etc/fstab")).unwrap();
+        assert_eq!(found.fs_type, "ext4");
+
+        // over-mounted, the latter mount is visible
+        let found = select_mount(mounts(), Path::new("/srv/www")).unwrap();
+        assert_eq!(found.fs_type, "xfs");
+
+        let found = select_mount(mounts(), Path::new("/srv/data/db")).unwrap();
+        assert_eq!(found.fs_type, "btrfs");
+
+        // path components, not string prefixes
+        let found = select_mount(mounts(), Path::new("/srv/database")).unwrap();
+        assert_eq!(found.fs_type, "xfs");
+
+        assert!(select_mount(mounts().skip(1), Path::new("/etc")).is_err());
+    }
+
+    #[test]
+    fn test_nested_mounts() {
+        let mounts = || {
+            vec![
+                mount("/", "ext4"),
+                mount("/tank", "zfs"),
+                mount("/tank/vm", "zfs"),
+                mount("/tank/vm/disks", "zfs"),
+                mount("/tankdata", "zfs"),
+                mount("/tank/vm", "zfs"),
+            ]
+            .into_iter()
+        };
+
+        assert_eq!(
+            nested_mounts(mounts(), Path::new("/tank")),
+            vec![PathBuf::from("/tank/vm"), PathBuf::from("/tank/vm/disks")]
+        );
+        assert_eq!(
+            nested_mounts(mounts(), Path::new("/tank/vm/disks")),
+            Vec::<PathBuf>::new()
+        );
+        assert_eq!(nested_mounts(mounts(), Path::new("/")).len(), 4);
+    }
+
+    #[test]
+    fn test_parse_subvolume_list() {
+        let output = "ID 257 gen 9 top level 5 path home\n\
+                      ID 258 gen 12 top level 5 path var/lib/machines\n";
+        assert_eq!(
+            parse_subvolume_list(output),
+            vec!["home", "var/lib/machines"]
+        );
+        assert!(parse_subvolume_list("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_thin_volume() {
+        assert_eq!(
+            parse_thin_volume("  pve:data-root:data\n").unwrap(),
+            ("pve", "data-root")
+        );
+        assert!(parse_thin_volume("  pve:root:\n").is_err());
+        assert!(parse_thin_volume("").is_err());
+    }
+}
@@ -1,21 +1,23 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
 use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::Context;
 
 use anyhow::{bail, format_err, Error};
-use futures::stream::{StreamExt, TryStreamExt};
+use futures::stream::{Stream, StreamExt, TryStreamExt};
 use serde::Deserialize;
 use serde_json::{json, Value};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use xdg::BaseDirectories;
 
-use pathpatterns::{MatchEntry, MatchType, PatternFlag};
+use pathpatterns::{MatchEntry, MatchPattern, MatchType, PatternFlag};
 use proxmox_async::blocking::TokioWriterAdapter;
-use proxmox_human_byte::HumanByte;
 use proxmox_io::StdChannelWriter;
 use proxmox_router::{cli::*, ApiMethod, RpcEnvironment};
 use proxmox_schema::api;
@@ -25,40 +27,50 @@ use pxar::accessor::{MaybeReady, ReadAt, ReadAtOperation};
 
 use pbs_api_types::{
     Authid, BackupDir, BackupGroup, BackupNamespace, BackupPart, BackupType, CryptMode,
-    Fingerprint, GroupListItem, PruneJobOptions, PruneListItem, RateLimitConfig, SnapshotListItem,
-    StorageStatus, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
-    BACKUP_TYPE_SCHEMA, TRAFFIC_CONTROL_BURST_SCHEMA, TRAFFIC_CONTROL_RATE_SCHEMA,
+    Fingerprint, GroupListItem, KeepOptions, PruneJobOptions, PruneListItem, SnapshotLineage,
+    SnapshotListItem, StorageStatus, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
+    BACKUP_TYPE_SCHEMA, MULTI_LINE_COMMENT_SCHEMA, PREFETCH_WINDOW_SCHEMA,
+    SNAPSHOT_TAG_LIST_SCHEMA, TRAFFIC_CONTROL_BURST_SCHEMA, TRAFFIC_CONTROL_RATE_SCHEMA,
 };
 use pbs_client::catalog_shell::Shell;
-use pbs_client::pxar::ErrorHandler as PxarErrorHandler;
+use pbs_client::pxar::{
+    metadata_cache_path, ChangeDetection, ChangeDetectionMode, ErrorHandler as PxarErrorHandler,
+    FollowPolicy, Nfs4AclMode, SkippedEntries,
+};
+use pbs_client::tools::completions::completions_cmd_def;
 use pbs_client::tools::{
     complete_archive_name, complete_auth_id, complete_backup_group, complete_backup_snapshot,
     complete_backup_source, complete_chunk_size, complete_group_or_snapshot,
     complete_img_archive_name, complete_namespace, complete_pxar_archive_name, complete_repository,
-    connect, connect_rate_limited, extract_repository_from_value,
+    connect, connect_rate_limited, default_namespace, extract_rate_limit_from_value,
+    extract_repository_from_value,
     key_source::{
-        crypto_parameters, format_key_source, get_encryption_key_password, KEYFD_SCHEMA,
-        KEYFILE_SCHEMA, MASTER_PUBKEY_FD_SCHEMA, MASTER_PUBKEY_FILE_SCHEMA,
+        crypto_parameters, decrypt_key, format_key_source, KEYFD_SCHEMA, KEYFILE_SCHEMA,
+        MASTER_PUBKEY_FD_SCHEMA, MASTER_PUBKEY_FILE_SCHEMA,
     },
+    profile::{init_active_profile, ENV_VAR_PBS_PROFILE},
     CHUNK_SIZE_SCHEMA, REPO_URL_SCHEMA,
 };
 use pbs_client::{
-    delete_ticket_info, parse_backup_specification, view_task_result, BackupReader,
-    BackupRepository, BackupSpecificationType, BackupStats, BackupWriter, ChunkStream,
-    FixedChunkStream, HttpClient, PxarBackupStream, RemoteChunkReader, UploadOptions,
-    BACKUP_SOURCE_SCHEMA,
+    delete_ticket_info, parse_backup_specification, source_mtime, view_task_result, BackupReader,
+    BackupRepository, BackupResumeState, BackupSpecificationType, BackupStats, BackupWriter,
+    ChunkStream, FixedChunkStream, HttpClient, KnownChunkCache, Progress, ProgressReporter,
+    PxarBackupStream, RemoteChunkReader, UploadOptions, BACKUP_SOURCE_SCHEMA, STATUS_FD_SCHEMA,
 };
+use pbs_datastore::cached_chunk_reader::CachedChunkReader;
 use pbs_datastore::catalog::{BackupCatalogWriter, CatalogReader, CatalogWriter};
 use pbs_datastore::chunk_store::verify_chunk_size;
-use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicIndexReader};
+use pbs_datastore::data_blob::DataChunkBuilder;
+use pbs_datastore::dynamic_index::BufferedDynamicReader;
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{
-    archive_type, ArchiveType, BackupManifest, ENCRYPTED_KEY_BLOB_NAME, MANIFEST_BLOB_NAME,
+    archive_type, ArchiveType, BackupManifest, CLIENT_LOG_BLOB_NAME, ENCRYPTED_KEY_BLOB_NAME,
+    MANIFEST_BLOB_NAME,
 };
-use pbs_datastore::read_chunk::AsyncReadChunk;
+use pbs_datastore::read_chunk::read_chunks_prefetched;
 use pbs_datastore::CATALOG_NAME;
-use pbs_key_config::{decrypt_key, rsa_encrypt_key_config, KeyConfig};
+use pbs_key_config::{parse_rsa_pubkeys, rsa_encrypt_key_config_for, KeyConfig};
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::json;
 
@@ -70,10 +82,20 @@ mod task;
 pub use task::*;
 mod catalog;
 pub use catalog::*;
+mod diff;
+pub use diff::*;
 mod snapshot;
 pub use snapshot::*;
 pub mod key;
 pub mod namespace;
+mod secret;
+mod tape_restore;
+pub use tape_restore::*;
+mod verify;
+pub use verify::*;
+mod device_snapshot;
+mod fs_snapshot;
+mod run_log;
 
 fn record_repository(repo: &BackupRepository) {
     let base = match BaseDirectories::with_prefix("proxmox-backup") {
@@ -135,6 +157,7 @@ async fn api_datastore_list_snapshots(
     store: &str,
     ns: &BackupNamespace,
     group: Option<&BackupGroup>,
+    tags: Option<&[String]>,
 ) -> Result<Value, Error> {
     let path = format!("api2/json/admin/datastore/{}/snapshots", store);
 
@@ -145,6 +168,9 @@ async fn api_datastore_list_snapshots(
     if !ns.is_root() {
         args["ns"] = serde_json::to_value(ns)?;
     }
+    if let Some(tags) = tags {
+        args["tags"] = tags.into();
+    }
 
     let mut result = client.get(&path, Some(args)).await?;
 
@@ -157,7 +183,7 @@ pub async fn api_datastore_latest_snapshot(
     ns: &BackupNamespace,
     group: BackupGroup,
 ) -> Result<BackupDir, Error> {
-    let list = api_datastore_list_snapshots(client, store, ns, Some(&group)).await?;
+    let list = api_datastore_list_snapshots(client, store, ns, Some(&group), None).await?;
     let mut list: Vec<SnapshotListItem> = serde_json::from_value(list)?;
 
     if list.is_empty() {
@@ -183,6 +209,26 @@ pub async fn dir_or_last_from_group(
     }
 }
 
+/// Run the chunker in a separate task, so that finding the chunk boundaries runs in parallel
+/// to hashing, encoding and uploading the previous chunks.
+fn spawn_chunker<S, T>(mut chunk_stream: S) -> impl Stream<Item = Result<T, Error>>
+where
+    S: Stream<Item = Result<T, Error>> + Send + Unpin + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(10); // allow to buffer 10 chunks
+
+    tokio::spawn(async move {
+        while let Some(v) = chunk_stream.next().await {
+            if tx.send(v).await.is_err() {
+                break; // upload failed
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
 async fn backup_directory<P: AsRef<Path>>(
     client: &BackupWriter,
     dir_path: P,
@@ -196,19 +242,52 @@ async fn backup_directory<P: AsRef<Path>>(
         bail!("cannot backup directory with fixed chunk size!");
     }
 
-    let pxar_stream = PxarBackupStream::open(dir_path.as_ref(), catalog, pxar_create_options)?;
+    let reused_ranges = pxar_create_options
+        .change_detection
+        .as_ref()
+        .map(|cd| cd.lock().unwrap().reused_ranges());
+
+    let pxar_stream = PxarBackupStream::open(
+        dir_path.as_ref(),
+        catalog,
+        pxar_create_options,
+        upload_options.progress.clone(),
+    )?;
     let mut chunk_stream = ChunkStream::new(pxar_stream, chunk_size);
+    if let Some(reused_ranges) = reused_ranges {
+        chunk_stream = chunk_stream.with_reused_ranges(reused_ranges);
+    }
 
-    let (tx, rx) = mpsc::channel(10); // allow to buffer 10 chunks
+    let stream = spawn_chunker(chunk_stream);
 
-    let stream = ReceiverStream::new(rx).map_err(Error::from);
+    let stats = client
+        .upload_stream(archive_name, stream, upload_options)
+        .await?;
 
-    // spawn chunker inside a separate task so that it can run parallel
-    tokio::spawn(async move {
-        while let Some(v) = chunk_stream.next().await {
-            let _ = tx.send(v).await;
-        }
-    });
+    Ok(stats)
+}
+
+async fn backup_stream(
+    client: &BackupWriter,
+    source: &str,
+    archive_name: &str,
+    chunk_size: Option<usize>,
+    upload_options: UploadOptions,
+) -> Result<BackupStats, Error> {
+    if upload_options.fixed_size.is_some() {
+        bail!("cannot backup stream with fixed chunk size!");
+    }
+
+    let input: Box<dyn tokio::io::AsyncRead + Send + Unpin> = if source == "-" {
+        Box::new(tokio::io::stdin())
+    } else {
+        Box::new(tokio::fs::File::open(source).await?)
+    };
+
+    let stream = tokio_util::codec::FramedRead::new(input, tokio_util::codec::BytesCodec::new())
+        .map_err(Error::from);
+
+    let stream = spawn_chunker(ChunkStream::new(stream, chunk_size));
 
     let stats = client
         .upload_stream(archive_name, stream, upload_options)
@@ -231,7 +310,10 @@ async fn backup_image<P: AsRef<Path>>(
     let stream = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
         .map_err(Error::from);
 
-    let stream = FixedChunkStream::new(stream, chunk_size.unwrap_or(4 * 1024 * 1024));
+    let stream = spawn_chunker(FixedChunkStream::new(
+        stream,
+        chunk_size.unwrap_or(4 * 1024 * 1024),
+    ));
 
     if upload_options.fixed_size.is_none() {
         bail!("cannot backup image with dynamic chunk size!");
@@ -244,11 +326,109 @@ async fn backup_image<P: AsRef<Path>>(
     Ok(stats)
 }
 
+// Errors are not fatal, the backup just cannot be resumed then.
+async fn open_resume_state(
+    client: &BackupWriter,
+    repo: &BackupRepository,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+    crypt_config: Option<&CryptConfig>,
+    resume: bool,
+) -> Option<Arc<BackupResumeState>> {
+    let state = match BackupResumeState::open(repo, ns, &snapshot.group, crypt_config, resume) {
+        Ok(state) => state,
+        Err(err) => {
+            log::warn!("unable to open resume state - {err}");
+            return None;
+        }
+    };
+
+    if resume {
+        let previous = state.previous_chunks();
+        if previous.is_empty() {
+            log::info!("No interrupted backup run to resume.");
+        } else {
+            match client.register_known_chunks(previous).await {
+                Ok(known) => {
+                    log::info!(
+                        "Resuming interrupted backup run: {} of {} uploaded chunks still available",
+                        known.len(),
+                        previous.len()
+                    );
+                    state.add_known_chunks(known);
+                }
+                Err(err) => log::warn!(
+                    "unable to resume interrupted backup run, resuming requires the \
+                    'known-chunk-query' option on the datastore - {err}"
+                ),
+            }
+        }
+    }
+
+    Some(Arc::new(state))
+}
+
+// Errors are not fatal, all files are read then.
+//
+// Also returns the chunks of the previous index if it was downloaded, so that the upload does not
+// need to download it again.
+async fn open_change_detection(
+    client: &BackupWriter,
+    repo: &BackupRepository,
+    ns: &BackupNamespace,
+    group: &BackupGroup,
+    archive_name: &str,
+    previous_manifest: Option<&BackupManifest>,
+    crypt_mode: CryptMode,
+) -> (
+    Option<Arc<Mutex<ChangeDetection>>>,
+    Option<Arc<Mutex<HashSet<[u8; 32]>>>>,
+) {
+    let path = match metadata_cache_path(repo, ns, group, archive_name) {
+        Ok(path) => path,
+        Err(err) => {
+            log::warn!("unable to open metadata cache - {err}");
+            return (None, None);
+        }
+    };
+
+    let mut previous = None;
+    let mut previous_chunks = None;
+    let previous_info = previous_manifest
+        .and_then(|manifest| Some((manifest, manifest.lookup_file_info(archive_name).ok()?)));
+    match previous_info {
+        // chunk digests depend on the encryption key
+        Some((manifest, info)) if info.crypt_mode == crypt_mode => {
+            let known_chunks = Arc::new(Mutex::new(HashSet::new()));
+            match client
+                .download_previous_dynamic_index(archive_name, manifest, known_chunks.clone())
+                .await
+            {
+                Ok(index) => {
+                    previous = Some((index, info.csum));
+                    previous_chunks = Some(known_chunks);
+                }
+                Err(err) => log::warn!("unable to download previous index - {err}"),
+            }
+        }
+        _ => log::info!("No previous archive '{archive_name}' to reuse chunks from."),
+    }
+
+    match ChangeDetection::new(path, previous) {
+        Ok(cd) => (Some(Arc::new(Mutex::new(cd))), previous_chunks),
+        Err(err) => {
+            log::warn!("unable to open metadata cache - {err}");
+            (None, previous_chunks)
+        }
+    }
+}
+
+/// Get the namespace from the `ns` parameter, the repository or the active profile, in that order.
 pub fn optional_ns_param(param: &Value) -> Result<BackupNamespace, Error> {
     Ok(match param.get("ns") {
         Some(Value::String(ns)) => ns.parse()?,
         Some(_) => bail!("invalid namespace parameter"),
-        None => BackupNamespace::root(),
+        None => default_namespace(extract_repository_from_value(param).ok().as_ref()),
     })
 }
 
@@ -584,6 +764,24 @@ fn spawn_catalog_upload(
                    description: "Path to file.",
                }
            },
+           "follow-symlinks": {
+               type: FollowPolicy,
+               optional: true,
+           },
+           "follow-mounts": {
+               type: FollowPolicy,
+               optional: true,
+           },
+           "follow-path": {
+               type: Array,
+               description: "Paths of symlinks and mount points followed with the 'listed' \
+                   policies, relative to the archive root.",
+               optional: true,
+               items: {
+                   type: String,
+                   description: "Path relative to the archive root.",
+               }
+           },
            "all-file-systems": {
                type: Boolean,
                description: "Include all mounted subdirectories.",
@@ -671,21 +869,151 @@ fn spawn_catalog_upload(
                optional: true,
                default: false,
            },
+           "exclude-caches": {
+               type: Boolean,
+               description: "Skip the contents of directories containing a valid CACHEDIR.TAG file, except the tag itself.",
+               optional: true,
+               default: false,
+           },
+           "exclude-nodump": {
+               type: Boolean,
+               description: "Skip files and directories with the 'nodump' attribute (see chattr(1)).",
+               optional: true,
+               default: false,
+           },
+           "fs-snapshot": {
+               type: Boolean,
+               description: "Back up directories from a temporary snapshot of their file system \
+                   (ZFS, btrfs or thin LVM), which is removed afterwards.",
+               optional: true,
+               default: false,
+           },
+           "fs-snapshot-hook": {
+               type: String,
+               description: "Script called with 'pre-snapshot' before and 'post-snapshot' after \
+                   taking the file system snapshots, for example to quiesce databases.",
+               optional: true,
+           },
+           "device-snapshot": {
+               type: Boolean,
+               description: "Back up block devices from a temporary snapshot (LVM logical \
+                   volumes), which is removed afterwards.",
+               optional: true,
+               default: false,
+           },
+           "device-snapshot-size": {
+               type: Integer,
+               description: "Size of the copy-on-write area of snapshots of thick LVM volumes \
+                   in MiB (default: 10% of the volume).",
+               optional: true,
+               minimum: 1,
+           },
+           "nfs4-acl": {
+               type: Nfs4AclMode,
+               optional: true,
+           },
+           "datastore-dedup": {
+               type: Boolean,
+               description: "Ask the server for chunks already present anywhere in the datastore, \
+                   instead of only reusing chunks of the previous snapshot.",
+               optional: true,
+               default: false,
+           },
+           "chunk-cache": {
+               type: Boolean,
+               description: "Remember the chunks the server confirmed, and reuse them in later \
+                   runs instead of uploading identical data again.",
+               optional: true,
+               default: false,
+           },
+           "chunk-cache-dir": {
+               type: String,
+               description: "Directory of the chunk cache, can be shared between machines \
+                   (implies 'chunk-cache', default: $HOME/.cache/proxmox-backup/known-chunks).",
+               optional: true,
+           },
+           threads: {
+               type: Integer,
+               description: "Number of chunks hashed, compressed, encrypted and uploaded in parallel \
+                   (default: number of CPUs, at most 4).",
+               optional: true,
+               minimum: 1,
+               maximum: 64,
+           },
+           "change-detection-mode": {
+               type: ChangeDetectionMode,
+               optional: true,
+           },
+           resume: {
+               type: Boolean,
+               description: "Resume an interrupted backup run, chunks which were already \
+                   uploaded are not sent again. Assumes that the backup sources did not change.",
+               optional: true,
+               default: false,
+           },
+           "upload-log": {
+               type: Boolean,
+               description: "Attach the log of the backup run, its statistics and the skipped \
+                   files to the snapshot ('client.log.blob').",
+               optional: true,
+               default: false,
+           },
+           keep: {
+               type: KeepOptions,
+               flatten: true,
+           },
+           notes: {
+               schema: MULTI_LINE_COMMENT_SCHEMA,
+               optional: true,
+           },
+           tags: {
+               schema: SNAPSHOT_TAG_LIST_SCHEMA,
+               optional: true,
+           },
+           "output-format": {
+               schema: OUTPUT_FORMAT,
+               optional: true,
+           },
+           "status-fd": {
+               schema: STATUS_FD_SCHEMA,
+               optional: true,
+           },
        }
    }
 )]
 /// Create (host) backup.
+///
+/// With a JSON output format, the snapshot and its files are printed once the backup is
+/// finished.
 async fn create_backup(
     param: Value,
+    follow_symlinks: Option<FollowPolicy>,
+    follow_mounts: Option<FollowPolicy>,
     all_file_systems: bool,
     skip_lost_and_found: bool,
     dry_run: bool,
     skip_e2big_xattr: bool,
+    exclude_caches: bool,
+    exclude_nodump: bool,
+    fs_snapshot: bool,
+    fs_snapshot_hook: Option<String>,
+    device_snapshot: bool,
+    device_snapshot_size: Option<u64>,
+    nfs4_acl: Option<Nfs4AclMode>,
+    datastore_dedup: bool,
+    chunk_cache: bool,
+    chunk_cache_dir: Option<String>,
+    change_detection_mode: Option<ChangeDetectionMode>,
+    resume: bool,
+    upload_log: bool,
+    keep: KeepOptions,
     _info: &ApiMethod,
     _rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
+    let output_format = get_output_format(&param);
+
     let backupspec_list = json::required_array_param(&param, "backupspec")?;
 
     let backup_time_opt = param["backup-time"].as_i64();
@@ -696,16 +1024,16 @@ async fn create_backup(
         verify_chunk_size(size)?;
     }
 
-    let rate = match param["rate"].as_str() {
-        Some(s) => Some(s.parse::<HumanByte>()?),
-        None => None,
-    };
-    let burst = match param["burst"].as_str() {
-        Some(s) => Some(s.parse::<HumanByte>()?),
-        None => None,
-    };
+    let rate_limit = extract_rate_limit_from_value(&param)?;
 
-    let rate_limit = RateLimitConfig::with_same_inout(rate, burst);
+    let status_fd = param["status-fd"].as_i64().map(|fd| fd as RawFd);
+
+    let threads = match param["threads"].as_u64() {
+        Some(threads) => threads as usize,
+        None => std::thread::available_parallelism()
+            .map(|cpus| cpus.get().min(4))
+            .unwrap_or(1),
+    };
 
     let crypto = crypto_parameters(&param)?;
 
@@ -719,6 +1047,18 @@ async fn create_backup(
 
     let include_dev = param["include-dev"].as_array();
 
+    let follow_symlinks = follow_symlinks.unwrap_or_default();
+    let follow_mounts = follow_mounts.unwrap_or_default();
+    let follow_paths: Vec<PathBuf> = param["follow-path"]
+        .as_array()
+        .map(|paths| {
+            paths
+                .iter()
+                .filter_map(|path| path.as_str().map(PathBuf::from))
+                .collect()
+        })
+        .unwrap_or_default();
+
     let entries_max = param["entries-max"]
         .as_u64()
         .unwrap_or(pbs_client::pxar::ENCODER_MAX_ENTRIES as u64);
@@ -747,6 +1087,9 @@ async fn create_backup(
         if all_file_systems {
             bail!("option 'all-file-systems' conflicts with option 'include-dev'");
         }
+        if fs_snapshot {
+            bail!("option 'fs-snapshot' conflicts with option 'include-dev'");
+        }
 
         let mut set = HashSet::new();
         for path in include_dev {
@@ -758,8 +1101,23 @@ async fn create_backup(
         devices = Some(set);
     }
 
+    if fs_snapshot && all_file_systems {
+        // only the file systems of the given paths are snapshotted
+        bail!("option 'fs-snapshot' conflicts with option 'all-file-systems'");
+    }
+
+    if fs_snapshot_hook.is_some() && !fs_snapshot {
+        bail!("option 'fs-snapshot-hook' requires option 'fs-snapshot'");
+    }
+
+    if device_snapshot_size.is_some() && !device_snapshot {
+        bail!("option 'device-snapshot-size' requires option 'device-snapshot'");
+    }
+
     let mut upload_list = vec![];
     let mut target_set = HashSet::new();
+    // (archive name, device) of all backed up block devices
+    let mut block_devices = Vec::new();
 
     for backupspec in backupspec_list {
         let spec = parse_backup_specification(backupspec.as_str().unwrap())?;
@@ -773,6 +1131,31 @@ async fn create_backup(
 
         use std::os::unix::fs::FileTypeExt;
 
+        if let BackupSpecificationType::STREAM = spec.spec_type {
+            if filename == "-" {
+                if upload_list
+                    .iter()
+                    .any(|(_, filename, ..)| filename.as_str() == "-")
+                {
+                    bail!("only one archive can be read from stdin");
+                }
+            } else {
+                let file_type = std::fs::metadata(filename)
+                    .map_err(|err| format_err!("unable to access '{}' - {}", filename, err))?
+                    .file_type();
+                if !(file_type.is_fifo() || file_type.is_file() || file_type.is_char_device()) {
+                    bail!("got unexpected file type (expected named pipe, file or '-')");
+                }
+            }
+            upload_list.push((
+                BackupSpecificationType::STREAM,
+                filename.to_owned(),
+                format!("{}.didx", target),
+                0,
+            ));
+            continue;
+        }
+
         let metadata = std::fs::metadata(filename)
             .map_err(|err| format_err!("unable to access '{}' - {}", filename, err))?;
         let file_type = metadata.file_type();
@@ -800,6 +1183,10 @@ async fn create_backup(
                     bail!("got zero-sized file '{}'", filename);
                 }
 
+                if file_type.is_block_device() {
+                    block_devices.push((format!("{}.fidx", target), filename.to_owned()));
+                }
+
                 upload_list.push((
                     BackupSpecificationType::IMAGE,
                     filename.to_owned(),
@@ -829,6 +1216,7 @@ async fn create_backup(
                     metadata.len(),
                 ));
             }
+            BackupSpecificationType::STREAM => unreachable!("streams are handled above"),
         }
     }
 
@@ -837,6 +1225,13 @@ async fn create_backup(
     let http_client = connect_rate_limited(&repo, rate_limit)?;
     record_repository(&repo);
 
+    let upload_log = upload_log && !dry_run;
+    if upload_log {
+        run_log::start_capture();
+    }
+    let mut skipped_entries = BTreeMap::new();
+    let mut skipped_dropped = BTreeMap::new();
+
     let snapshot = BackupDir::from((backup_type, backup_id.to_owned(), backup_time));
     if backup_ns.is_root() {
         log::info!("Starting backup: {snapshot}");
@@ -861,8 +1256,7 @@ async fn create_backup(
                 format_key_source(&key_with_source.source, "encryption")
             );
 
-            let (key, created, fingerprint) =
-                decrypt_key(&key_with_source.key, &get_encryption_key_password)?;
+            let (key, created, fingerprint) = decrypt_key(&key_with_source.key)?;
             log::info!("Encryption key fingerprint: {}", fingerprint);
 
             let crypt_config = CryptConfig::new(key)?;
@@ -871,12 +1265,15 @@ async fn create_backup(
                 Some(pem_with_source) => {
                     log::info!("{}", format_key_source(&pem_with_source.source, "master"));
 
-                    let rsa = openssl::rsa::Rsa::public_key_from_pem(&pem_with_source.key)?;
+                    let recipients = parse_rsa_pubkeys(&pem_with_source.key)?;
+                    if recipients.len() > 1 {
+                        log::info!("Encrypting key for {} master keys", recipients.len());
+                    }
 
                     let mut key_config = KeyConfig::without_password(key)?;
                     key_config.created = created; // keep original value
 
-                    let enc_key = rsa_encrypt_key_config(rsa, &key_config)?;
+                    let enc_key = rsa_encrypt_key_config_for(&recipients, &key_config)?;
 
                     (Some(Arc::new(crypt_config)), Some(enc_key))
                 }
@@ -934,6 +1331,37 @@ async fn create_backup(
         None
     };
 
+    let resume_state = if dry_run {
+        None
+    } else {
+        open_resume_state(
+            &client,
+            &repo,
+            &backup_ns,
+            &snapshot,
+            crypt_config.as_deref(),
+            resume,
+        )
+        .await
+    };
+
+    let known_chunk_cache = if (chunk_cache || chunk_cache_dir.is_some()) && !dry_run {
+        let dir = match chunk_cache_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => KnownChunkCache::default_dir()?,
+        };
+        match KnownChunkCache::open(&dir, &repo, crypt_config.as_deref()) {
+            Ok(cache) => Some(Arc::new(cache)),
+            Err(err) => {
+                log::warn!("unable to open chunk cache - {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let backup_group = snapshot.group.clone();
     let mut manifest = BackupManifest::new(snapshot);
 
     let mut catalog = None;
@@ -944,6 +1372,72 @@ async fn create_backup(
         log::info!("{} {} '{}' to '{}' as {}", what, desc, file, repo, target);
     };
 
+    let progress = Progress::backup();
+    let progress_reporter = if dry_run {
+        None
+    } else {
+        Some(ProgressReporter::start(Arc::clone(&progress), status_fd)?)
+    };
+
+    // kept until all archives are uploaded, the snapshots are removed on drop
+    let _fs_snapshots = if fs_snapshot && !dry_run {
+        let mut paths: Vec<String> = upload_list
+            .iter()
+            .filter(|(backup_type, ..)| matches!(backup_type, BackupSpecificationType::PXAR))
+            .map(|(_, filename, ..)| filename.clone())
+            .collect();
+        let snapshots =
+            fs_snapshot::FsSnapshots::create(&mut paths, fs_snapshot_hook.as_deref(), backup_time)?;
+        let mut paths = paths.into_iter();
+        for (backup_type, filename, ..) in upload_list.iter_mut() {
+            if let BackupSpecificationType::PXAR = backup_type {
+                *filename = paths.next().unwrap();
+            }
+        }
+        Some(snapshots)
+    } else {
+        None
+    };
+
+    // kept until all archives are uploaded, the snapshots are removed on drop
+    let device_snapshots = if device_snapshot && !dry_run && !block_devices.is_empty() {
+        let mut devices: Vec<String> = block_devices.iter().map(|(_, d)| d.clone()).collect();
+        let snapshots = device_snapshot::DeviceSnapshots::create(
+            &mut devices,
+            device_snapshot_size,
+            backup_time,
+        )?;
+        for (backup_type, filename, target, _) in upload_list.iter_mut() {
+            if let BackupSpecificationType::IMAGE = backup_type {
+                if let Some(index) = block_devices.iter().position(|(t, _)| t == target) {
+                    *filename = devices[index].clone();
+                }
+            }
+        }
+        Some(snapshots)
+    } else {
+        None
+    };
+
+    let mut device_info = serde_json::Map::new();
+    if !dry_run {
+        for (target, device) in block_devices.iter() {
+            let snapshot = device_snapshots
+                .as_ref()
+                .and_then(|snapshots| snapshots.snapshot_kind(device));
+            match device_snapshot::device_metadata(device, snapshot) {
+                Ok(metadata) => {
+                    device_info.insert(target.clone(), metadata);
+                }
+                Err(err) => log::warn!("unable to query metadata of {device:?} - {err}"),
+            }
+        }
+    }
+
+    let upload_list_has_pxar = upload_list
+        .iter()
+        .any(|(backup_type, ..)| matches!(backup_type, BackupSpecificationType::PXAR));
+
     for (backup_type, filename, target, size) in upload_list {
         match (backup_type, dry_run) {
             // dry-run
@@ -951,6 +1445,7 @@ async fn create_backup(
             (BackupSpecificationType::LOGFILE, true) => log_file("log file", &filename, &target),
             (BackupSpecificationType::PXAR, true) => log_file("directory", &filename, &target),
             (BackupSpecificationType::IMAGE, true) => log_file("image", &filename, &target),
+            (BackupSpecificationType::STREAM, true) => log_file("stream", &filename, &target),
             // no dry-run
             (BackupSpecificationType::CONFIG, false) => {
                 let upload_options = UploadOptions {
@@ -995,18 +1490,51 @@ async fn create_backup(
                     .unwrap()
                     .start_directory(std::ffi::CString::new(target.as_str())?.as_c_str())?;
 
+                let (change_detection, previous_chunks) =
+                    match change_detection_mode.unwrap_or_default() {
+                        ChangeDetectionMode::Data => (None, None),
+                        ChangeDetectionMode::Metadata => {
+                            open_change_detection(
+                                &client,
+                                &repo,
+                                &backup_ns,
+                                &backup_group,
+                                &target,
+                                previous_manifest.as_deref(),
+                                crypto.mode,
+                            )
+                            .await
+                        }
+                    };
+
                 let pxar_options = pbs_client::pxar::PxarCreateOptions {
                     device_set: devices.clone(),
                     patterns: pattern_list.clone(),
                     entries_max: entries_max as usize,
                     skip_lost_and_found,
                     skip_e2big_xattr,
+                    exclude_caches,
+                    exclude_nodump,
+                    nfs4_acl: nfs4_acl.unwrap_or_default(),
+                    change_detection: change_detection.clone(),
+                    follow_symlinks,
+                    follow_mounts,
+                    follow_paths: follow_paths.clone(),
+                    skipped: upload_log.then(|| Arc::new(Mutex::new(SkippedEntries::default()))),
+                    file_list: None,
                 };
+                let skipped = pxar_options.skipped.clone();
 
                 let upload_options = UploadOptions {
                     previous_manifest: previous_manifest.clone(),
                     compress: true,
                     encrypt: crypto.mode == CryptMode::Encrypt,
+                    query_known_chunks: datastore_dedup,
+                    resume: resume_state.clone(),
+                    threads: Some(threads),
+                    progress: Some(Arc::clone(&progress)),
+                    known_chunk_cache: known_chunk_cache.clone(),
+                    previous_chunks,
                     ..UploadOptions::default()
                 };
 
@@ -1020,6 +1548,20 @@ async fn create_backup(
                     upload_options,
                 )
                 .await?;
+                if let Some(cd) = change_detection {
+                    if let Err(err) = cd.lock().unwrap().commit(&stats.csum) {
+                        log::warn!("unable to save metadata cache - {err}");
+                    }
+                }
+                if let Some(skipped) = skipped {
+                    let skipped = std::mem::take(&mut *skipped.lock().unwrap());
+                    if skipped.dropped > 0 {
+                        skipped_dropped.insert(target.clone(), skipped.dropped);
+                    }
+                    if !skipped.entries.is_empty() {
+                        skipped_entries.insert(target.clone(), skipped.entries);
+                    }
+                }
                 manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
                 catalog.lock().unwrap().end_directory()?;
             }
@@ -1031,10 +1573,62 @@ async fn create_backup(
                     fixed_size: Some(size),
                     compress: true,
                     encrypt: crypto.mode == CryptMode::Encrypt,
+                    query_known_chunks: datastore_dedup,
+                    resume: resume_state.clone(),
+                    threads: Some(threads),
+                    progress: Some(Arc::clone(&progress)),
+                    known_chunk_cache: known_chunk_cache.clone(),
+                    ..UploadOptions::default()
+                };
+
+                // block devices have no usable mtime, they are always read again
+                let mtime = source_mtime(&filename)?;
+                let replay_state = mtime.and(resume_state.as_ref());
+
+                let mut resumed_stats = None;
+                if let (Some(state), Some(mtime)) = (replay_state, mtime) {
+                    if let Some((archive, chunks)) =
+                        state.finished_archive(&target, &filename, mtime)?
+                    {
+                        if archive.size == size {
+                            resumed_stats = client
+                                .replay_index(&target, &chunks, Some(size), size, &archive.csum)
+                                .await?;
+                        }
+                    }
+                }
+
+                let stats = match resumed_stats {
+                    Some(stats) => stats,
+                    None => {
+                        backup_image(&client, &filename, &target, chunk_size_opt, upload_options)
+                            .await?
+                    }
+                };
+                if let (Some(state), Some(mtime)) = (replay_state, mtime) {
+                    if let Err(err) = state.finish_archive(&target, &filename, mtime, &stats) {
+                        log::warn!("unable to record finished archive for resume - {err}");
+                    }
+                }
+                manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
+            }
+            (BackupSpecificationType::STREAM, false) => {
+                log_file("stream", &filename, &target);
+
+                // the input cannot be read again, so streams are never resumed
+                let upload_options = UploadOptions {
+                    previous_manifest: previous_manifest.clone(),
+                    compress: true,
+                    encrypt: crypto.mode == CryptMode::Encrypt,
+                    query_known_chunks: datastore_dedup,
+                    threads: Some(threads),
+                    progress: Some(Arc::clone(&progress)),
+                    known_chunk_cache: known_chunk_cache.clone(),
+                    ..UploadOptions::default()
                 };
 
                 let stats =
-                    backup_image(&client, &filename, &target, chunk_size_opt, upload_options)
+                    backup_stream(&client, &filename, &target, chunk_size_opt, upload_options)
                         .await?;
                 manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
             }
@@ -1075,6 +1669,32 @@ async fn create_backup(
             .await?;
         manifest.add_file(target.to_string(), stats.size, stats.csum, crypto.mode)?;
     }
+    // the server adds the previous snapshot it based the backup on
+    let lineage = SnapshotLineage {
+        client_version: Some(format!(
+            "{}.{}",
+            pbs_buildcfg::PROXMOX_PKG_VERSION,
+            pbs_buildcfg::PROXMOX_PKG_RELEASE,
+        )),
+        chunk_size: Some(chunk_size_opt.unwrap_or(4 * 1024 * 1024) as u64),
+        ..Default::default()
+    };
+    manifest.unprotected["lineage"] = serde_json::to_value(lineage)?;
+    // tell what the pxar archives include, mount points and symlinked directories are not
+    // archived by default
+    if upload_list_has_pxar {
+        manifest.unprotected["follow-policy"] = json!({
+            "symlinks": follow_symlinks,
+            "mounts": follow_mounts,
+            "paths": follow_paths,
+        });
+    }
+    if !device_info.is_empty() {
+        manifest.unprotected["devices"] = Value::Object(device_info);
+    }
+
+    let files = serde_json::to_value(manifest.files())?;
+
     // create manifest (index.json)
     // manifests are never encrypted, but include a signature
     let manifest = manifest
@@ -1092,54 +1712,251 @@ async fn create_backup(
         .upload_blob_from_data(manifest.into_bytes(), MANIFEST_BLOB_NAME, options)
         .await?;
 
-    client.finish().await?;
+    if upload_log {
+        let report = run_log::RunReport {
+            client_version: format!(
+                "{}.{}",
+                pbs_buildcfg::PROXMOX_PKG_VERSION,
+                pbs_buildcfg::PROXMOX_PKG_RELEASE,
+            ),
+            start_time: backup_time,
+            duration: start_time.elapsed().as_secs_f64(),
+            files: files.clone(),
+            progress: progress.record(true),
+            skipped: skipped_entries,
+            skipped_dropped,
+            log: run_log::finish_capture(),
+        };
+        let options = UploadOptions {
+            compress: true,
+            encrypt: crypto.mode == CryptMode::Encrypt,
+            ..UploadOptions::default()
+        };
+        // a missing log is no reason to fail the backup
+        if let Err(err) = client
+            .upload_blob_from_data(serde_json::to_vec(&report)?, CLIENT_LOG_BLOB_NAME, options)
+            .await
+        {
+            log::warn!("unable to upload client log - {err}");
+        }
+    }
+
+    let notes = param["notes"].as_str();
+    let tags: Vec<String> = match param.get("tags") {
+        Some(tags) => serde_json::from_value(tags.clone())?,
+        None => Vec::new(),
+    };
+
+    client.finish_with_notes(notes, &tags).await?;
+
+    if let Some(reporter) = progress_reporter {
+        reporter.finish().await;
+    }
+
+    if let Some(state) = resume_state {
+        if let Err(err) = state.remove() {
+            log::warn!("{err}");
+        }
+    }
+
+    if keep.keeps_something() {
+        // the snapshot is complete, so a failing prune does not fail the backup
+        if let Err(err) =
+            prune_group_after_backup(&http_client, &repo, &backup_ns, &snapshot.group, keep).await
+        {
+            log::warn!("unable to prune backup group {} - {err}", snapshot.group);
+        }
+    }
 
     let end_time = std::time::Instant::now();
     let elapsed = end_time.duration_since(start_time);
     log::info!("Duration: {:.2}s", elapsed.as_secs_f64());
     log::info!("End Time: {}", strftime_local("%c", epoch_i64())?);
+
+    if output_format != "text" {
+        let mut result = serde_json::to_value(&snapshot)?;
+        if !backup_ns.is_root() {
+            result["ns"] = serde_json::to_value(&backup_ns)?;
+        }
+        result["files"] = files;
+        result["duration"] = elapsed.as_secs_f64().into();
+        format_and_print_result(&result, &output_format);
+    }
+
     Ok(Value::Null)
 }
 
-async fn dump_image<W: Write>(
+/// Prune the group of a new snapshot with the keep options given to the backup command.
+async fn prune_group_after_backup(
+    client: &HttpClient,
+    repo: &BackupRepository,
+    ns: &BackupNamespace,
+    group: &BackupGroup,
+    keep: KeepOptions,
+) -> Result<(), Error> {
+    let path = format!("api2/json/admin/datastore/{}/prune", repo.store());
+
+    let mut api_param = serde_json::to_value(keep)?;
+    if !ns.is_root() {
+        api_param["ns"] = serde_json::to_value(ns)?;
+    }
+    merge_group_into(api_param.as_object_mut().unwrap(), group.clone());
+
+    let mut result = client.post(&path, Some(api_param)).await?;
+    let items: Vec<PruneListItem> = serde_json::from_value(result["data"].take())?;
+
+    let mut removed = 0;
+    for item in items.iter().filter(|item| !item.keep) {
+        log::info!("pruned snapshot {}", item.backup);
+        removed += 1;
+    }
+    log::info!(
+        "prune: kept {} snapshots, removed {removed}",
+        items.len() - removed
+    );
+
+    Ok(())
+}
+
+/// Alignment of buffers, file offsets and sizes required for writes with `O_DIRECT`.
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// Write `data` to a file opened with `O_DIRECT`.
+///
+/// The data is copied into the aligned part of `buffer`. An unaligned tail, which can only be the
+/// last chunk of an image, is written through the page cache instead.
+fn write_direct(file: &mut std::fs::File, data: &[u8], buffer: &mut Vec<u8>) -> Result<(), Error> {
+    use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+    if data.len() % DIRECT_IO_ALIGNMENT != 0 {
+        let flags = OFlag::from_bits_truncate(fcntl(file.as_raw_fd(), FcntlArg::F_GETFL)?);
+        fcntl(
+            file.as_raw_fd(),
+            FcntlArg::F_SETFL(flags.difference(OFlag::O_DIRECT)),
+        )?;
+        file.write_all(data)?;
+        return Ok(());
+    }
+
+    buffer.resize(data.len() + DIRECT_IO_ALIGNMENT, 0);
+    let start = buffer.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+    let aligned = &mut buffer[start..start + data.len()];
+    aligned.copy_from_slice(data);
+    file.write_all(aligned)?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn dump_image(
     client: Arc<BackupReader>,
     crypt_config: Option<Arc<CryptConfig>>,
     crypt_mode: CryptMode,
     index: FixedIndexReader,
-    mut writer: W,
+    prefetch_window: usize,
+    writer: &mut std::fs::File,
+    sparse: bool,
+    direct_io: bool,
+    json_progress: bool,
+    progress: Arc<Progress>,
 ) -> Result<(), Error> {
     let most_used = index.find_most_used_chunks(8);
 
-    let chunk_reader = RemoteChunkReader::new(client.clone(), crypt_config, crypt_mode, most_used);
+    // compute the zero chunk digest locally instead of trusting the index header, so that this
+    // also works for encrypted images and older indices
+    let zero_digest = if sparse {
+        let config = match crypt_mode {
+            CryptMode::Encrypt => crypt_config.as_deref(),
+            _ => None,
+        };
+        Some(DataChunkBuilder::zero_chunk_digest(
+            config,
+            index.chunk_size,
+        ))
+    } else {
+        None
+    };
+    let is_zero_chunk = |pos: usize| match zero_digest {
+        Some(ref zero_digest) => index.index_digest(pos) == Some(zero_digest),
+        None => false,
+    };
+
+    let chunk_reader = RemoteChunkReader::new(client.clone(), crypt_config, crypt_mode, most_used)
+        .with_prefetch_window(prefetch_window)
+        .with_progress(Arc::clone(&progress));
 
     // Note: we avoid using BufferedFixedReader, because that add an additional buffer/copy
     // and thus slows down reading. Instead, directly use RemoteChunkReader
     let mut per = 0;
     let mut bytes = 0;
+    let mut zero_bytes = 0;
+    let mut direct_buffer = Vec::new();
     let start_time = std::time::Instant::now();
 
+    // zero chunks are never downloaded when restoring into a file, they end up as holes
+    let digests = (0..index.index_count())
+        .filter(|pos| !is_zero_chunk(*pos))
+        .map(|pos| *index.index_digest(pos).unwrap());
+    let mut chunks = std::pin::pin!(read_chunks_prefetched(&chunk_reader, digests));
+
+    // the target file is newly created, so skipped ranges read back as zeroes
     for pos in 0..index.index_count() {
-        let digest = index.index_digest(pos).unwrap();
-        let raw_data = chunk_reader.read_chunk(digest).await?;
-        writer.write_all(&raw_data)?;
-        bytes += raw_data.len();
+        let info = index.chunk_info(pos).unwrap();
+        if is_zero_chunk(pos) {
+            writer.seek(SeekFrom::Start(info.range.end))?;
+            zero_bytes += info.size() as usize;
+            progress.add_read(info.size());
+        } else {
+            let raw_data = chunks
+                .try_next()
+                .await?
+                .ok_or_else(|| format_err!("chunk stream ended unexpectedly"))?;
+            // catches zero chunks with another digest, like a shorter last chunk
+            if sparse && raw_data.iter().all(|byte| *byte == 0) {
+                writer.seek(SeekFrom::Start(info.range.end))?;
+                zero_bytes += info.size() as usize;
+            } else if direct_io {
+                write_direct(writer, &raw_data, &mut direct_buffer)?;
+            } else {
+                writer.write_all(&raw_data)?;
+            }
+        }
+        bytes += info.size() as usize;
         let next_per = ((pos + 1) * 100) / index.index_count();
         if per != next_per {
-            log::debug!(
-                "progress {}% (read {} bytes, duration {} sec)",
-                next_per,
-                bytes,
-                start_time.elapsed().as_secs()
-            );
+            if json_progress {
+                // one object per line, so that the progress can be parsed while it is written,
+                // stdout only gets the final result so it stays a single valid JSON document
+                eprintln!(
+                    "{}",
+                    json!({
+                        "progress": next_per,
+                        "bytes": bytes,
+                        "duration": start_time.elapsed().as_secs(),
+                    })
+                );
+            } else {
+                log::debug!(
+                    "progress {}% (read {} bytes, duration {} sec)",
+                    next_per,
+                    bytes,
+                    start_time.elapsed().as_secs()
+                );
+            }
             per = next_per;
         }
     }
 
+    if sparse {
+        // trailing holes do not extend the file
+        writer.set_len(index.index_bytes())?;
+    }
+
     let end_time = std::time::Instant::now();
     let elapsed = end_time.duration_since(start_time);
     log::info!(
-        "restore image complete (bytes={}, duration={:.2}s, speed={:.2}MB/s)",
+        "restore image complete (bytes={}, holes={}, duration={:.2}s, speed={:.2}MB/s)",
         bytes,
+        zero_bytes,
         elapsed.as_secs_f64(),
         bytes as f64 / (1024.0 * 1024.0 * elapsed.as_secs_f64())
     );
@@ -1147,10 +1964,35 @@ async fn dump_image<W: Write>(
     Ok(())
 }
 
+/// Print the summary of a restore, unless the output format is text.
+fn print_restore_result(
+    output_format: &str,
+    ns: &BackupNamespace,
+    backup_dir: &BackupDir,
+    archive_name: &str,
+    target: Option<&str>,
+    elapsed: std::time::Duration,
+) -> Result<(), Error> {
+    if output_format == "text" {
+        return Ok(());
+    }
+
+    let mut result = serde_json::to_value(backup_dir)?;
+    if !ns.is_root() {
+        result["ns"] = serde_json::to_value(ns)?;
+    }
+    result["archive-name"] = archive_name.into();
+    result["target"] = target.into();
+    result["duration"] = elapsed.as_secs_f64().into();
+    format_and_print_result(&result, output_format);
+
+    Ok(())
+}
+
 fn parse_archive_type(name: &str) -> (String, ArchiveType) {
     if name.ends_with(".didx") || name.ends_with(".fidx") || name.ends_with(".blob") {
         (name.into(), archive_type(name).unwrap())
-    } else if name.ends_with(".pxar") {
+    } else if name.ends_with(".pxar") || name.ends_with(".dat") {
         (format!("{}.didx", name), ArchiveType::DynamicIndex)
     } else if name.ends_with(".img") {
         (format!("{}.fidx", name), ArchiveType::FixedIndex)
@@ -1159,6 +2001,73 @@ fn parse_archive_type(name: &str) -> (String, ArchiveType) {
     }
 }
 
+#[api]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Format of file archives written to standard output.
+pub enum StdoutFormat {
+    #[default]
+    /// Raw pxar archive.
+    Pxar,
+    /// Tar archive, e.g. to pipe into `tar -x`.
+    Tar,
+}
+
+// Build the match list for restoring only some entries of a '.pxar' archive.
+fn restore_match_list(param: &Value) -> Result<Vec<MatchEntry>, Error> {
+    let mut match_list = Vec::new();
+
+    if let Some(path) = param["files-from"].as_str() {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| format_err!("unable to read file list {:?} - {}", path, err))?;
+        for line in content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            let path = if line.starts_with('/') {
+                line.to_string()
+            } else {
+                format!("/{line}")
+            };
+            match_list.push(MatchEntry::include(MatchPattern::Literal(
+                path.into_bytes(),
+            )));
+        }
+    }
+
+    for (name, match_type) in [
+        ("include", MatchType::Include),
+        ("exclude", MatchType::Exclude),
+    ] {
+        for entry in param[name]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+        {
+            let entry = entry
+                .as_str()
+                .ok_or_else(|| format_err!("Invalid pattern string slice"))?;
+            match_list.push(
+                MatchEntry::parse_pattern(entry, PatternFlag::PATH_NAME, match_type)
+                    .map_err(|err| format_err!("invalid {name} pattern entry: {}", err))?,
+            );
+        }
+    }
+
+    Ok(match_list)
+}
+
+// Opening /dev/stdout fails if standard output is a socket, so use a duplicate
+// of the file descriptor instead.
+fn stdout_file() -> Result<std::fs::File, Error> {
+    use std::os::unix::io::FromRawFd;
+
+    let fd = nix::unistd::dup(std::io::stdout().as_raw_fd())
+        .map_err(|err| format_err!("unable to duplicate stdout - {}", err))?;
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
+
 #[api(
     input: {
         properties: {
@@ -1186,6 +2095,18 @@ We do not extract '.pxar' archives when writing to standard output.
 
 "###
             },
+            "stdout-format": {
+                type: StdoutFormat,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+            "status-fd": {
+                schema: STATUS_FD_SCHEMA,
+                optional: true,
+            },
             rate: {
                 schema: TRAFFIC_CONTROL_RATE_SCHEMA,
                 optional: true,
@@ -1262,7 +2183,54 @@ We do not extract '.pxar' archives when writing to standard output.
                 description: "ignore errors that occur during device node extraction",
                 optional: true,
                 default: false,
-            }
+            },
+            include: {
+                type: Array,
+                description: "Only restore entries of '.pxar' archives matching these paths or patterns.",
+                optional: true,
+                items: {
+                    type: String,
+                    description: "Path or match pattern.",
+                },
+            },
+            exclude: {
+                type: Array,
+                description: "Do not restore entries of '.pxar' archives matching these paths or patterns.",
+                optional: true,
+                items: {
+                    type: String,
+                    description: "Path or match pattern.",
+                },
+            },
+            "files-from": {
+                type: String,
+                description: "Only restore the entries of '.pxar' archives listed in this file, one path per line.",
+                optional: true,
+            },
+            "prefetch-window": {
+                schema: PREFETCH_WINDOW_SCHEMA,
+                optional: true,
+            },
+            "download-concurrency": {
+                type: Integer,
+                description: "number of chunks downloaded in parallel, and files written in parallel with 'parallel-restore'",
+                optional: true,
+                minimum: 1,
+                maximum: 32,
+                default: 4,
+            },
+            "parallel-restore": {
+                type: Boolean,
+                description: "Write several files of '.pxar' archives in parallel, walking the archive via the catalog.",
+                optional: true,
+                default: false,
+            },
+            "direct-io": {
+                type: Boolean,
+                description: "Write '.img' archives to the target file with O_DIRECT, bypassing the page cache.",
+                optional: true,
+                default: false,
+            },
         }
     }
 )]
@@ -1279,21 +2247,17 @@ async fn restore(
     overwrite_symlinks: bool,
     overwrite_hardlinks: bool,
     ignore_extract_device_errors: bool,
+    prefetch_window: u64,
+    download_concurrency: u64,
+    parallel_restore: bool,
+    direct_io: bool,
+    stdout_format: Option<StdoutFormat>,
 ) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
     let archive_name = json::required_string_param(&param, "archive-name")?;
 
-    let rate = match param["rate"].as_str() {
-        Some(s) => Some(s.parse::<HumanByte>()?),
-        None => None,
-    };
-    let burst = match param["burst"].as_str() {
-        Some(s) => Some(s.parse::<HumanByte>()?),
-        None => None,
-    };
-
-    let rate_limit = RateLimitConfig::with_same_inout(rate, burst);
+    let rate_limit = extract_rate_limit_from_value(&param)?;
 
     let client = connect_rate_limited(&repo, rate_limit)?;
     record_repository(&repo);
@@ -1306,16 +2270,35 @@ async fn restore(
     let target = json::required_string_param(&param, "target")?;
     let target = if target == "-" { None } else { Some(target) };
 
+    let status_fd = param["status-fd"].as_i64().map(|fd| fd as RawFd);
+
+    // the data itself goes to stdout then, so ignore a default set in the environment
+    let output_format = match target {
+        Some(_) => get_output_format(&param),
+        None if param["output-format"].is_null() => "text".to_string(),
+        None => bail!("'output-format' cannot be used when writing to standard output"),
+    };
+    let start_time = std::time::Instant::now();
+
+    let match_list = restore_match_list(&param)?;
+    if !match_list.is_empty() && target.is_none() {
+        bail!("restoring only matching entries is not possible when writing to standard output");
+    }
+
+    let stdout_format = stdout_format.unwrap_or_default();
+    if stdout_format == StdoutFormat::Tar && target.is_some() {
+        bail!("'stdout-format' can only be used when writing to standard output");
+    }
+
     let crypto = crypto_parameters(&param)?;
 
     let crypt_config = match crypto.enc_key {
         None => None,
         Some(ref key) => {
-            let (key, _, _) =
-                decrypt_key(&key.key, &get_encryption_key_password).map_err(|err| {
-                    log::error!("{}", format_key_source(&key.source, "encryption"));
-                    err
-                })?;
+            let (key, _, _) = decrypt_key(&key.key).map_err(|err| {
+                log::error!("{}", format_key_source(&key.source, "encryption"));
+                err
+            })?;
             Some(Arc::new(CryptConfig::new(key)?))
         }
     };
@@ -1359,12 +2342,35 @@ async fn restore(
                 .map_err(|err| format_err!("unable to pipe data - {}", err))?;
         }
 
+        let elapsed = start_time.elapsed();
+        print_restore_result(
+            &output_format,
+            &ns,
+            &backup_dir,
+            &archive_name,
+            target,
+            elapsed,
+        )?;
         return Ok(Value::Null);
     }
 
     let file_info = manifest.lookup_file_info(&archive_name)?;
 
+    // data streams are dynamic indices, but no pxar archives
+    let is_pxar = archive_type == ArchiveType::DynamicIndex && !archive_name.ends_with(".dat.didx");
+    if stdout_format == StdoutFormat::Tar && !is_pxar {
+        bail!("only file archives (.pxar) can be written as tar archive");
+    }
+    if !match_list.is_empty() && !is_pxar {
+        bail!("only entries of file archives (.pxar) can be restored selectively");
+    }
+
+    let progress = Progress::restore();
+    let progress_reporter = ProgressReporter::start(Arc::clone(&progress), status_fd)?;
+
     if archive_type == ArchiveType::Blob {
+        progress.start_archive(&archive_name, None);
+
         let mut reader = client.download_blob(&manifest, &archive_name).await?;
 
         if let Some(target) = target {
@@ -1383,21 +2389,73 @@ async fn restore(
             std::io::copy(&mut reader, &mut writer)
                 .map_err(|err| format_err!("unable to pipe data - {}", err))?;
         }
+    } else if archive_type == ArchiveType::DynamicIndex && !is_pxar {
+        let index = client
+            .download_dynamic_index(&manifest, &archive_name)
+            .await?;
+
+        progress.start_archive(&archive_name, Some(index.index_bytes()));
+
+        let most_used = index.find_most_used_chunks(8);
+        let chunk_reader = RemoteChunkReader::new(
+            client.clone(),
+            crypt_config.clone(),
+            file_info.chunk_crypt_mode(),
+            most_used,
+        )
+        .with_download_concurrency(download_concurrency as usize)
+        .with_progress(Arc::clone(&progress));
+
+        let mut reader = BufferedDynamicReader::new(index, chunk_reader);
+        let mut writer = if let Some(target) = target {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .create_new(true)
+                .open(target)
+                .map_err(|err| format_err!("unable to create target file {:?} - {}", target, err))?
+        } else {
+            stdout_file()?
+        };
+
+        std::io::copy(&mut reader, &mut writer)
+            .map_err(|err| format_err!("unable to write data - {}", err))?;
     } else if archive_type == ArchiveType::DynamicIndex {
         let index = client
             .download_dynamic_index(&manifest, &archive_name)
             .await?;
 
+        progress.start_archive(&archive_name, Some(index.index_bytes()));
+
         let most_used = index.find_most_used_chunks(8);
 
+        let download_concurrency = download_concurrency as usize;
         let chunk_reader = RemoteChunkReader::new(
             client.clone(),
-            crypt_config,
+            crypt_config.clone(),
             file_info.chunk_crypt_mode(),
             most_used,
-        );
+        )
+        .with_download_concurrency(download_concurrency)
+        .with_progress(Arc::clone(&progress));
 
-        let mut reader = BufferedDynamicReader::new(index, chunk_reader);
+        // files can only be written in parallel when walking the archive via the catalog
+        let parallel =
+            parallel_restore && target.is_some() && manifest.lookup_file_info(CATALOG_NAME).is_ok();
+
+        if target.is_none() && stdout_format == StdoutFormat::Tar {
+            let reader = BufferedDynamicReader::new(index, chunk_reader);
+            let archive_size = reader.archive_size();
+            let reader: pbs_pxar_fuse::Reader = Arc::new(BufferedDynamicReadAt::new(reader));
+            let accessor = pbs_pxar_fuse::Accessor::new(reader, archive_size).await?;
+
+            pbs_client::pxar::create_tar(tokio::io::stdout(), accessor, "/")
+                .await
+                .map_err(|err| format_err!("unable to pipe data - {:#}", err))?;
+
+            progress_reporter.finish().await;
+            return Ok(Value::Null);
+        }
 
         let on_error = if ignore_extract_device_errors {
             let handler: PxarErrorHandler = Box::new(move |err: Error| {
@@ -1429,8 +2487,10 @@ async fn restore(
         }
 
         let options = pbs_client::pxar::PxarExtractOptions {
-            match_list: &[],
-            extract_match_default: true,
+            match_list: &match_list,
+            extract_match_default: !match_list
+                .iter()
+                .any(|entry| entry.match_type() == MatchType::Include),
             allow_existing_dirs,
             overwrite_flags,
             on_error,
@@ -1451,22 +2511,74 @@ async fn restore(
             feature_flags.remove(pbs_client::pxar::Flags::WITH_PERMISSIONS);
         }
 
-        if let Some(target) = target {
+        let mut catalog = None;
+        if parallel || !match_list.is_empty() {
+            let mut downloaded =
+                catalog::download_catalog(&client, &manifest, crypt_config.clone()).await?;
+            if match_list.is_empty()
+                && !pbs_client::catalog_shell::catalog_contains_archive(
+                    &mut downloaded,
+                    &archive_name,
+                )?
+            {
+                log::info!("archive not found in catalog, restoring sequentially");
+            } else {
+                catalog = Some(downloaded);
+            }
+        }
+
+        if let (Some(target), Some(catalog)) = (target, catalog) {
+            let parallel = if parallel {
+                // the prefetch window lets a single large file download its chunks in parallel,
+                // the cache keeps the prefetched chunks of concurrently written files
+                let chunk_reader = chunk_reader
+                    .clone()
+                    .with_prefetch_window(download_concurrency);
+                let reader = CachedChunkReader::new(
+                    chunk_reader,
+                    index.try_clone()?,
+                    2 * download_concurrency,
+                );
+                Some(pbs_client::catalog_shell::ParallelRestore {
+                    reader: Arc::new(reader),
+                    writers: download_concurrency,
+                })
+            } else {
+                None
+            };
+
+            let reader = BufferedDynamicReader::new(index, chunk_reader);
+            let archive_size = reader.archive_size();
+            let reader: pbs_pxar_fuse::Reader = Arc::new(BufferedDynamicReadAt::new(reader));
+            let accessor = pbs_pxar_fuse::Accessor::new(reader, archive_size).await?;
+
+            pbs_client::catalog_shell::restore_matching(
+                catalog,
+                &archive_name,
+                accessor,
+                Path::new(target),
+                feature_flags,
+                options,
+                parallel,
+            )
+            .await
+            .map_err(|err| format_err!("error extracting archive - {:#}", err))?;
+        } else if let Some(target) = target {
+            let reader = BufferedDynamicReader::new(index, chunk_reader);
             pbs_client::pxar::extract_archive(
                 pxar::decoder::Decoder::from_std(reader)?,
                 Path::new(target),
                 feature_flags,
                 |path| {
                     log::debug!("{:?}", path);
+                    progress.set_current_file(path);
                 },
                 options,
             )
             .map_err(|err| format_err!("error extracting archive - {:#}", err))?;
         } else {
-            let mut writer = std::fs::OpenOptions::new()
-                .write(true)
-                .open("/dev/stdout")
-                .map_err(|err| format_err!("unable to open /dev/stdout - {}", err))?;
+            let mut reader = BufferedDynamicReader::new(index, chunk_reader);
+            let mut writer = stdout_file()?;
 
             std::io::copy(&mut reader, &mut writer)
                 .map_err(|err| format_err!("unable to pipe data - {}", err))?;
@@ -1476,18 +2588,21 @@ async fn restore(
             .download_fixed_index(&manifest, &archive_name)
             .await?;
 
+        progress.start_archive(&archive_name, Some(index.index_bytes()));
+
         let mut writer = if let Some(target) = target {
             std::fs::OpenOptions::new()
                 .write(true)
                 .create(true)
                 .create_new(true)
+                .custom_flags(if direct_io { libc::O_DIRECT } else { 0 })
                 .open(target)
                 .map_err(|err| format_err!("unable to create target file {:?} - {}", target, err))?
         } else {
-            std::fs::OpenOptions::new()
-                .write(true)
-                .open("/dev/stdout")
-                .map_err(|err| format_err!("unable to open /dev/stdout - {}", err))?
+            if direct_io {
+                bail!("direct I/O is only possible when restoring into a target file");
+            }
+            stdout_file()?
         };
 
         dump_image(
@@ -1495,11 +2610,28 @@ async fn restore(
             crypt_config.clone(),
             file_info.chunk_crypt_mode(),
             index,
+            prefetch_window as usize,
             &mut writer,
+            target.is_some(),
+            direct_io,
+            output_format != "text",
+            Arc::clone(&progress),
         )
         .await?;
     }
 
+    progress_reporter.finish().await;
+
+    let elapsed = start_time.elapsed();
+    print_restore_result(
+        &output_format,
+        &ns,
+        &backup_dir,
+        &archive_name,
+        target,
+        elapsed,
+    )?;
+
     Ok(Value::Null)
 }
 
@@ -1540,12 +2672,19 @@ async fn restore(
 async fn prune(
     dry_run: Option<bool>,
     group: String,
-    prune_options: PruneJobOptions,
+    mut prune_options: PruneJobOptions,
     quiet: bool,
     mut param: Value,
 ) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
+    if prune_options.ns.is_none() {
+        let ns = optional_ns_param(&param)?;
+        if !ns.is_root() {
+            prune_options.ns = Some(ns);
+        }
+    }
+
     let client = connect(&repo)?;
 
     let path = format!("api2/json/admin/datastore/{}/prune", repo.store());
@@ -1715,9 +2854,59 @@ impl ReadAt for BufferedDynamicReadAt {
     }
 }
 
+/// Handle the global `--profile <name>` option by executing the command again with the profile
+/// set in the environment, as the CLI parser does not know about global options.
+fn handle_profile_option() -> Result<(), Error> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    if args.len() < 2 || args[1] == "bashcomplete" || args[1] == "printdoc" {
+        return Ok(());
+    }
+
+    let mut profile = None;
+    let mut explicit_repository = false;
+    let mut i = 1;
+    while i < args.len() {
+        if args[i] == "--repository"
+            || args[i]
+                .to_str()
+                .map_or(false, |arg| arg.starts_with("--repository="))
+        {
+            explicit_repository = true;
+        }
+        if args[i] == "--profile" {
+            if i + 1 >= args.len() {
+                bail!("missing value for option '--profile'");
+            }
+            profile = Some(args.remove(i + 1));
+            args.remove(i);
+        } else if let Some(name) = args[i]
+            .to_str()
+            .and_then(|arg| arg.strip_prefix("--profile="))
+        {
+            profile = Some(name.into());
+            args.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+
+    let profile = match profile {
+        Some(profile) => profile,
+        None => return init_active_profile(explicit_repository),
+    };
+
+    let err = pbs_tools::reexec_with_env(&args, ENV_VAR_PBS_PROFILE, &profile);
+    bail!("unable to execute command with profile {profile:?} - {err}");
+}
+
 fn main() {
     pbs_tools::setup_libc_malloc_opts();
-    init_cli_logger("PBS_LOG", "info");
+    run_log::init_logger("PBS_LOG", "info");
+
+    if let Err(err) = handle_profile_option() {
+        eprintln!("{err}");
+        std::process::exit(-1);
+    }
 
     let backup_cmd_def = CliCommand::new(&API_METHOD_CREATE_BACKUP)
         .arg_param(&["backupspec"])
@@ -1782,15 +2971,20 @@ fn main() {
         .insert("snapshot", snapshot_mgtm_cli())
         .insert("status", status_cmd_def)
         .insert("key", key::cli())
+        .insert("secret", secret::cli())
         .insert("mount", mount_cmd_def())
         .insert("map", map_cmd_def())
         .insert("unmap", unmap_cmd_def())
         .insert("catalog", catalog_mgmt_cli())
+        .insert("diff", diff_cmd_def())
+        .insert("verify", verify_cmd_def())
         .insert("task", task_mgmt_cli())
         .insert("version", version_cmd_def)
+        .insert("completions", completions_cmd_def())
         .insert("benchmark", benchmark_cmd_def)
         .insert("change-owner", change_owner_cmd_def)
         .insert("namespace", namespace::cli_map())
+        .insert("tape-restore", tape_restore_cmd_def())
         .alias(&["files"], &["snapshot", "files"])
         .alias(&["forget"], &["snapshot", "forget"])
         .alias(&["upload-log"], &["snapshot", "upload-log"])
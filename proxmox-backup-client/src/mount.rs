@@ -18,12 +18,11 @@ use proxmox_schema::*;
 use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::BackupNamespace;
-use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::tools::key_source::load_and_decrypt_key;
 use pbs_client::{BackupReader, RemoteChunkReader};
 use pbs_datastore::cached_chunk_reader::CachedChunkReader;
 use pbs_datastore::dynamic_index::BufferedDynamicReader;
-use pbs_datastore::index::IndexFile;
-use pbs_key_config::load_and_decrypt_key;
+use pbs_datastore::index::{IndexAccessPattern, IndexFile};
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::json::required_string_param;
 
@@ -81,6 +80,16 @@ const API_METHOD_MAP: ApiMethod = ApiMethod::new(
 WARNING: Only do this with *trusted* backups!",
         &sorted!([
             ("ns", true, &BackupNamespace::API_SCHEMA,),
+            (
+                "overlay",
+                true,
+                &BooleanSchema::new(concat!(
+                    "Map the image writable. All writes go to a temporary copy-on-write ",
+                    "overlay, which is discarded on unmap. The backup itself is never modified."
+                ))
+                .default(false)
+                .schema()
+            ),
             (
                 "snapshot",
                 false,
@@ -213,7 +222,7 @@ async fn mount_do(param: Value, pipe: Option<OwnedFd>) -> Result<Value, Error> {
         None => None,
         Some(path) => {
             log::info!("Encryption key file: '{:?}'", path);
-            let (key, _, fingerprint) = load_and_decrypt_key(&path, &get_encryption_key_password)?;
+            let (key, _, fingerprint) = load_and_decrypt_key(&path)?;
             log::info!("Encryption key fingerprint: '{}'", fingerprint);
             Some(Arc::new(CryptConfig::new(key)?))
         }
@@ -273,7 +282,13 @@ async fn mount_do(param: Value, pipe: Option<OwnedFd>) -> Result<Value, Error> {
         Ok(())
     };
 
-    let options = OsStr::new("ro,default_permissions");
+    let overlay = param["overlay"].as_bool().unwrap_or(false);
+
+    let options = if overlay {
+        OsStr::new("rw,default_permissions")
+    } else {
+        OsStr::new("ro,default_permissions")
+    };
 
     // handle SIGINT and SIGTERM
     let mut interrupt_int = signal(SignalKind::interrupt())?;
@@ -314,6 +329,7 @@ async fn mount_do(param: Value, pipe: Option<OwnedFd>) -> Result<Value, Error> {
         let index = client
             .download_fixed_index(&manifest, &server_archive_name)
             .await?;
+        index.advise(IndexAccessPattern::Random);
         let size = index.index_bytes();
         let chunk_reader = RemoteChunkReader::new(
             client.clone(),
@@ -327,7 +343,8 @@ async fn mount_do(param: Value, pipe: Option<OwnedFd>) -> Result<Value, Error> {
         let name_escaped = proxmox_sys::systemd::escape_unit(name, false);
 
         let mut session =
-            pbs_fuse_loop::FuseLoopSession::map_loop(size, reader, &name_escaped, options).await?;
+            pbs_fuse_loop::FuseLoopSession::map_loop(size, reader, &name_escaped, options, overlay)
+                .await?;
         let loopdev = session.loopdev_path.clone();
 
         let (st_send, st_recv) = futures::channel::mpsc::channel(1);
@@ -353,7 +370,21 @@ async fn mount_do(param: Value, pipe: Option<OwnedFd>) -> Result<Value, Error> {
         }
 
         // daemonize only now to be able to print mapped loopdev or startup errors
-        log::info!("Image '{}' mapped on {}", name, loopdev);
+        if overlay {
+            log::info!("Image '{}' mapped writable (overlay) on {}", name, loopdev);
+        } else {
+            log::info!("Image '{}' mapped on {}", name, loopdev);
+        }
+        match pbs_fuse_loop::loopdev::partitions(&loopdev) {
+            Ok(partitions) if partitions.len() == 1 => {
+                log::info!("Single partition available on {}", partitions[0]);
+            }
+            Ok(partitions) if !partitions.is_empty() => {
+                log::info!("Partitions available on {}", partitions.join(", "));
+            }
+            Ok(_) => (),
+            Err(err) => log::warn!("unable to scan partitions of {} - {}", loopdev, err),
+        }
         daemonize()?;
 
         // continue polling until complete or interrupted (which also happens on unmap)
@@ -387,11 +418,19 @@ fn unmap(
             let mut any = false;
             for (backing, loopdev) in pbs_fuse_loop::find_all_mappings()? {
                 let name = proxmox_sys::systemd::unescape_unit(&backing)?;
-                log::info!(
-                    "{}:\t{}",
-                    loopdev.unwrap_or_else(|| "(unmapped)".to_string()),
-                    name
-                );
+                match loopdev {
+                    Some(loopdev) => {
+                        log::info!("{}:\t{}", loopdev, name);
+                        match pbs_fuse_loop::loopdev::partitions(&loopdev) {
+                            Ok(partitions) if !partitions.is_empty() => {
+                                log::info!("\tpartitions: {}", partitions.join(", "));
+                            }
+                            Ok(_) => (),
+                            Err(err) => log::warn!("\tunable to list partitions - {}", err),
+                        }
+                    }
+                    None => log::info!("(unmapped):\t{}", name),
+                }
                 any = true;
             }
             if !any {
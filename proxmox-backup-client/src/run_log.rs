@@ -0,0 +1,156 @@
+//! Log of a backup run, attached to the snapshot as `client.log.blob`
+//!
+//! The client logger passes all messages on to the console like the usual CLI logger, and also
+//! records them while capturing is enabled. With `--upload-log`, the recorded messages are
+//! uploaded together with the statistics of the run and the entries skipped while creating the
+//! file archives, so they can be looked at on the server.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+use log::{Log, Metadata, Record};
+use serde::Serialize;
+use serde_json::Value;
+
+use pbs_client::pxar::SkippedEntry;
+use pbs_client::ProgressRecord;
+
+/// Maximum number of recorded log messages, older ones are dropped beyond that
+const MAX_CAPTURED_LINES: usize = 50_000;
+
+/// The most recent log messages, and how many older ones were dropped.
+#[derive(Default)]
+struct Capture {
+    lines: VecDeque<String>,
+    dropped: usize,
+}
+
+impl Capture {
+    fn push(&mut self, line: String) {
+        if self.lines.len() >= MAX_CAPTURED_LINES {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+        self.lines.push_back(line);
+    }
+
+    fn finish(self) -> Vec<String> {
+        let mut lines = Vec::with_capacity(self.lines.len() + 1);
+        if self.dropped > 0 {
+            lines.push(format!("({} older messages were dropped)", self.dropped));
+        }
+        lines.extend(self.lines);
+        lines
+    }
+}
+
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+
+struct RunLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for RunLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        self.inner.log(record);
+
+        if let Some(capture) = CAPTURE.lock().unwrap().as_mut() {
+            let time = proxmox_time::strftime_local("%F %T", proxmox_time::epoch_i64())
+                .unwrap_or_default();
+            capture.push(format!("{time} {:<5} {}", record.level(), record.args()));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Initialize the client logger, messages are printed like with `init_cli_logger`.
+pub fn init_logger(env_var_name: &str, default_log_level: &str) {
+    let inner = env_logger::Builder::from_env(
+        env_logger::Env::new().filter_or(env_var_name, default_log_level),
+    )
+    .write_style(env_logger::WriteStyle::Never)
+    .format_level(false)
+    .format_module_path(false)
+    .format_target(false)
+    .format_timestamp(None)
+    .build();
+
+    let max_level = inner.filter();
+    if log::set_boxed_logger(Box::new(RunLogger { inner })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Start recording the log messages.
+pub fn start_capture() {
+    *CAPTURE.lock().unwrap() = Some(Capture::default());
+}
+
+/// Stop recording and return the recorded log messages.
+pub fn finish_capture() -> Vec<String> {
+    CAPTURE
+        .lock()
+        .unwrap()
+        .take()
+        .map(Capture::finish)
+        .unwrap_or_default()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// Contents of the uploaded client log.
+pub struct RunReport {
+    /// Version of the client which created the backup
+    pub client_version: String,
+    /// Start of the backup run (epoch)
+    pub start_time: i64,
+    /// Duration of the backup run in seconds
+    pub duration: f64,
+    /// Files of the snapshot, as listed in the manifest
+    pub files: Value,
+    /// Amount of data read and uploaded
+    pub progress: ProgressRecord,
+    /// Entries which were not archived, by archive name
+    pub skipped: BTreeMap<String, Vec<SkippedEntry>>,
+    /// Number of further skipped entries, which were not recorded, by archive name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub skipped_dropped: BTreeMap<String, u64>,
+    /// The log messages
+    pub log: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_limit() {
+        let mut capture = Capture::default();
+        capture.push("first".to_string());
+        assert_eq!(capture.finish(), ["first"]);
+
+        let mut capture = Capture::default();
+        for n in 0..MAX_CAPTURED_LINES + 3 {
+            capture.push(format!("line {n}"));
+        }
+        let lines = capture.finish();
+        assert_eq!(lines.len(), MAX_CAPTURED_LINES + 1);
+        assert_eq!(lines[0], "(3 older messages were dropped)");
+        // the most recent messages are kept
+        assert_eq!(lines[1], "line 3");
+        assert_eq!(
+            lines[MAX_CAPTURED_LINES],
+            format!("line {}", MAX_CAPTURED_LINES + 2)
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::io::IsTerminal;
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use proxmox_router::cli::{complete_file_name, CliCommand, CliCommandMap};
+use proxmox_schema::api;
+use proxmox_sys::fs::file_get_contents;
+use proxmox_sys::linux::tty;
+
+use pbs_client::tools::key_source::{find_default_encryption_key, KEYFILE_SCHEMA};
+use pbs_client::tools::secret_store::{
+    parse_tpm2_pcrs, remove_secret, seal_secret, sealed_pcrs, secret_exists, store_secret,
+    ClientSecret, SecretStore, TPM2_PCRS_SCHEMA,
+};
+use pbs_client::tools::{complete_repository, get_secret_from_env, REPO_URL_SCHEMA};
+use pbs_client::BackupRepository;
+use pbs_key_config::KeyConfig;
+
+use crate::extract_repository_from_value;
+
+#[api]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Kind of client secret.
+enum SecretType {
+    /// The default encryption key
+    EncryptionKey,
+    /// The password of the repository's user
+    Password,
+}
+
+fn client_secret<'a>(ty: SecretType, repo: Option<&'a BackupRepository>) -> ClientSecret<'a> {
+    match (ty, repo) {
+        (SecretType::Password, Some(repo)) => ClientSecret::Password {
+            auth_id: repo.auth_id(),
+            server: repo.host(),
+            port: repo.port(),
+        },
+        _ => ClientSecret::EncryptionKey,
+    }
+}
+
+fn read_secret(
+    ty: SecretType,
+    param: &Value,
+) -> Result<(Vec<u8>, Option<BackupRepository>), Error> {
+    match ty {
+        SecretType::EncryptionKey => {
+            let path = match param["keyfile"].as_str() {
+                Some(path) => path.into(),
+                None => find_default_encryption_key()?
+                    .ok_or_else(|| format_err!("no default encryption key, use --keyfile"))?,
+            };
+            let data = file_get_contents(&path)?;
+            // make sure to not store anything else
+            serde_json::from_slice::<KeyConfig>(&data)
+                .map_err(|err| format_err!("{path:?} is not a valid key file - {err}"))?;
+            Ok((data, None))
+        }
+        SecretType::Password => {
+            let repo = extract_repository_from_value(param)?;
+            let password = match get_secret_from_env("PBS_PASSWORD")? {
+                Some(password) => password.into_bytes(),
+                None if std::io::stdin().is_terminal() => {
+                    tty::read_and_verify_password(&format!("Password for \"{}\": ", repo.user()))?
+                }
+                None => bail!("unable to read password - no tty"),
+            };
+            Ok((password, Some(repo)))
+        }
+    }
+}
+
+fn do_enroll(ty: SecretType, store: SecretStore, param: &Value, rotate: bool) -> Result<(), Error> {
+    let (data, repo) = read_secret(ty, param)?;
+    let secret = client_secret(ty, repo.as_ref());
+
+    let exists = secret_exists(store, &secret)?;
+    match (exists, rotate) {
+        (true, false) => bail!("secret already stored in {store}, use 'rotate' to replace it"),
+        (false, true) => bail!("no secret stored in {store}, use 'enroll' to store it"),
+        _ => (),
+    }
+
+    let pcrs = match (store, param["tpm-pcrs"].as_str()) {
+        (SecretStore::Tpm, Some(pcrs)) => Some(parse_tpm2_pcrs(pcrs)?),
+        // keep the binding of the replaced secret
+        (SecretStore::Tpm, None) if rotate => sealed_pcrs(&secret)?,
+        (_, Some(_)) => bail!("'tpm-pcrs' is only valid with store 'tpm'"),
+        (_, None) => None,
+    };
+    match pcrs {
+        Some(pcrs) => seal_secret(&secret, &data, &pcrs)?,
+        None => store_secret(store, &secret, &data)?,
+    }
+    log::info!("stored secret in {store}");
+
+    if ty == SecretType::EncryptionKey && param["keyfile"].is_null() {
+        if let Some(path) = find_default_encryption_key()? {
+            log::warn!(
+                "the default key file {path:?} takes precedence over the stored key, remove it \
+                once you made sure you have a backup of the key"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            "secret-type": {
+                type: SecretType,
+            },
+            store: {
+                type: SecretStore,
+            },
+            keyfile: {
+                schema: KEYFILE_SCHEMA,
+                optional: true,
+            },
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            "tpm-pcrs": {
+                schema: TPM2_PCRS_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Store a secret in the kernel keyring or sealed with the TPM. The encryption
+/// key is read from the default key file or '--keyfile', the password from the
+/// PBS_PASSWORD environment variables or the terminal. Secrets sealed with the
+/// TPM are bound to PCR 7 unless '--tpm-pcrs' is given.
+fn enroll(secret_type: SecretType, store: SecretStore, param: Value) -> Result<(), Error> {
+    do_enroll(secret_type, store, &param, false)
+}
+
+#[api(
+    input: {
+        properties: {
+            "secret-type": {
+                type: SecretType,
+            },
+            store: {
+                type: SecretStore,
+            },
+            keyfile: {
+                schema: KEYFILE_SCHEMA,
+                optional: true,
+            },
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            "tpm-pcrs": {
+                schema: TPM2_PCRS_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Replace a stored secret, for example after changing the password. Secrets
+/// sealed with the TPM keep their PCR binding unless '--tpm-pcrs' is given.
+fn rotate(secret_type: SecretType, store: SecretStore, param: Value) -> Result<(), Error> {
+    do_enroll(secret_type, store, &param, true)
+}
+
+#[api(
+    input: {
+        properties: {
+            "secret-type": {
+                type: SecretType,
+            },
+            store: {
+                type: SecretStore,
+            },
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Remove a stored secret.
+fn remove(secret_type: SecretType, store: SecretStore, param: Value) -> Result<(), Error> {
+    let repo = match secret_type {
+        SecretType::Password => Some(extract_repository_from_value(&param)?),
+        SecretType::EncryptionKey => None,
+    };
+
+    if !remove_secret(store, &client_secret(secret_type, repo.as_ref()))? {
+        bail!("no secret stored in {store}");
+    }
+
+    Ok(())
+}
+
+pub fn cli() -> CliCommandMap {
+    CliCommandMap::new()
+        .insert(
+            "enroll",
+            CliCommand::new(&API_METHOD_ENROLL)
+                .arg_param(&["secret-type"])
+                .completion_cb("keyfile", complete_file_name)
+                .completion_cb("repository", complete_repository),
+        )
+        .insert(
+            "rotate",
+            CliCommand::new(&API_METHOD_ROTATE)
+                .arg_param(&["secret-type"])
+                .completion_cb("keyfile", complete_file_name)
+                .completion_cb("repository", complete_repository),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&API_METHOD_REMOVE)
+                .arg_param(&["secret-type"])
+                .completion_cb("repository", complete_repository),
+        )
+}
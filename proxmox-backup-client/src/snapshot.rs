@@ -7,10 +7,11 @@ use proxmox_router::cli::*;
 use proxmox_schema::api;
 use proxmox_sys::fs::file_get_contents;
 
-use pbs_api_types::{BackupGroup, BackupNamespace, CryptMode, SnapshotListItem};
-use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_api_types::{
+    BackupGroup, BackupNamespace, CryptMode, SnapshotListItem, SNAPSHOT_TAG_LIST_SCHEMA,
+};
+use pbs_client::tools::key_source::decrypt_key;
 use pbs_datastore::DataBlob;
-use pbs_key_config::decrypt_key;
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::json::required_string_param;
 
@@ -45,6 +46,10 @@ fn snapshot_args(ns: &BackupNamespace, snapshot: &BackupDir) -> Result<Value, Er
                 description: "Backup group.",
                 optional: true,
             },
+            tags: {
+                schema: SNAPSHOT_TAG_LIST_SCHEMA,
+                optional: true,
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
@@ -52,7 +57,7 @@ fn snapshot_args(ns: &BackupNamespace, snapshot: &BackupDir) -> Result<Value, Er
         }
    }
 )]
-/// List backup snapshots.
+/// List backup snapshots (optionally only those with all given tags).
 async fn list_snapshots(param: Value) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
@@ -67,8 +72,19 @@ async fn list_snapshots(param: Value) -> Result<Value, Error> {
 
     let backup_ns = optional_ns_param(&param)?;
 
-    let mut data =
-        api_datastore_list_snapshots(&client, repo.store(), &backup_ns, group.as_ref()).await?;
+    let tags: Option<Vec<String>> = match param.get("tags") {
+        Some(tags) => Some(serde_json::from_value(tags.clone())?),
+        None => None,
+    };
+
+    let mut data = api_datastore_list_snapshots(
+        &client,
+        repo.store(),
+        &backup_ns,
+        group.as_ref(),
+        tags.as_deref(),
+    )
+    .await?;
 
     record_repository(&repo);
 
@@ -86,6 +102,11 @@ async fn list_snapshots(param: Value) -> Result<Value, Error> {
         Ok(pbs_tools::format::render_backup_file_list(&filenames[..]))
     };
 
+    let render_tags = |_v: &Value, record: &Value| -> Result<String, Error> {
+        let item: SnapshotListItem = serde_json::from_value(record.to_owned())?;
+        Ok(item.tags.unwrap_or_default().join(", "))
+    };
+
     let options = default_table_format_options()
         .sortby("backup-type", false)
         .sortby("backup-id", false)
@@ -96,7 +117,8 @@ async fn list_snapshots(param: Value) -> Result<Value, Error> {
                 .header("snapshot"),
         )
         .column(ColumnConfig::new("size").renderer(pbs_tools::format::render_bytes_human_readable))
-        .column(ColumnConfig::new("files").renderer(render_files));
+        .column(ColumnConfig::new("files").renderer(render_files))
+        .column(ColumnConfig::new("tags").renderer(render_tags));
 
     let return_type = &pbs_api_types::ADMIN_DATASTORE_LIST_SNAPSHOTS_RETURN_TYPE;
 
@@ -247,7 +269,7 @@ async fn upload_log(param: Value) -> Result<Value, Error> {
     let crypt_config = match crypto.enc_key {
         None => None,
         Some(key) => {
-            let (key, _created, _) = decrypt_key(&key.key, &get_encryption_key_password)?;
+            let (key, _created, _) = decrypt_key(&key.key)?;
             let crypt_config = CryptConfig::new(key)?;
             Some(Arc::new(crypt_config))
         }
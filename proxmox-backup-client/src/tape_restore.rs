@@ -0,0 +1,103 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::cli::*;
+use proxmox_schema::api;
+
+use pbs_api_types::percent_encoding::percent_encode_component;
+use pbs_api_types::{DRIVE_NAME_SCHEMA, MEDIA_SET_UUID_SCHEMA};
+use pbs_client::tape_restore_stream::TapeRestoreWriter;
+use pbs_client::tools::key_source::decrypt_key;
+use pbs_tools::crypt_config::CryptConfig;
+use pbs_tools::json::required_string_param;
+
+use crate::{
+    complete_repository, connect, crypto_parameters, extract_repository_from_value,
+    format_key_source, record_repository, KEYFD_SCHEMA, KEYFILE_SCHEMA, REPO_URL_SCHEMA,
+};
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            drive: {
+                schema: DRIVE_NAME_SCHEMA,
+            },
+            "media-set": {
+                schema: MEDIA_SET_UUID_SCHEMA,
+            },
+            snapshot: {
+                type: String,
+                description: "Snapshot path (including namespace, e.g. 'ns/foo/vm/100/2024-01-01T00:00:00Z').",
+            },
+            target: {
+                type: String,
+                description: "Target directory path.",
+            },
+            keyfile: {
+                schema: KEYFILE_SCHEMA,
+                optional: true,
+            },
+            "keyfd": {
+                schema: KEYFD_SCHEMA,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Restore a snapshot directly from tape, without a datastore on the server.
+///
+/// The snapshot is read from the media set in the given drive. Images
+/// and archives are reconstructed in the target directory.
+async fn tape_restore(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+
+    let drive = required_string_param(&param, "drive")?;
+    let media_set = required_string_param(&param, "media-set")?;
+    let snapshot = required_string_param(&param, "snapshot")?;
+    let target = required_string_param(&param, "target")?;
+
+    let crypto = crypto_parameters(&param)?;
+    let crypt_config = match crypto.enc_key {
+        None => None,
+        Some(ref key) => {
+            let (key, _, _) = decrypt_key(&key.key).map_err(|err| {
+                log::error!("{}", format_key_source(&key.source, "encryption"));
+                err
+            })?;
+            Some(Arc::new(CryptConfig::new(key)?))
+        }
+    };
+
+    let client = connect(&repo)?;
+    record_repository(&repo);
+
+    let path = format!(
+        "api2/json/tape/restore-stream?drive={}&media-set={}&snapshot={}",
+        percent_encode_component(drive),
+        percent_encode_component(media_set),
+        percent_encode_component(&format!("{}:{}", repo.store(), snapshot)),
+    );
+
+    let mut writer = TapeRestoreWriter::new(Path::new(target), crypt_config)?;
+
+    client.download(&path, &mut writer).await?;
+
+    let chunks = writer.finish()?;
+    log::info!("restored {chunks} chunks from tape");
+
+    Ok(Value::Null)
+}
+
+pub fn tape_restore_cmd_def() -> CliCommand {
+    CliCommand::new(&API_METHOD_TAPE_RESTORE)
+        .arg_param(&["drive", "media-set", "snapshot", "target"])
+        .completion_cb("repository", complete_repository)
+        .completion_cb("target", complete_file_name)
+}
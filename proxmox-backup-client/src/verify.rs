@@ -0,0 +1,337 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use futures::stream::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use proxmox_router::cli::*;
+use proxmox_schema::{api, ApiType, ArraySchema, ReturnType};
+
+use pbs_api_types::{BackupNamespace, CryptMode, Fingerprint};
+use pbs_client::BackupReader;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest};
+use pbs_datastore::DataBlob;
+use pbs_tools::crypt_config::CryptConfig;
+use pbs_tools::json::required_string_param;
+
+use crate::{
+    complete_backup_snapshot, complete_namespace, complete_repository, connect, crypto_parameters,
+    decrypt_key, dir_or_last_from_group, extract_repository_from_value, format_key_source,
+    optional_ns_param, record_repository, KEYFD_SCHEMA, REPO_URL_SCHEMA,
+};
+
+/// Number of chunks downloaded in parallel
+const VERIFY_CONCURRENCY: usize = 4;
+
+#[api]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Result of verifying a file of a snapshot.
+enum VerifyStatus {
+    /// Checksums and all checked chunks are valid
+    Ok,
+    /// The file or one of its chunks is invalid
+    Failed,
+}
+
+#[api]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Verification result of a file of a snapshot.
+struct VerifyFileResult {
+    /// File name
+    filename: String,
+    status: VerifyStatus,
+    /// Number of chunks referenced by the index
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunks: Option<usize>,
+    /// Number of chunks downloaded and checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checked: Option<usize>,
+    /// First error found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl VerifyFileResult {
+    fn new(filename: &str, result: Result<Option<(usize, usize)>, Error>) -> Self {
+        let (status, counts, error) = match result {
+            Ok(counts) => (VerifyStatus::Ok, counts, None),
+            Err(err) => (VerifyStatus::Failed, None, Some(err.to_string())),
+        };
+        Self {
+            filename: filename.to_string(),
+            status,
+            chunks: counts.map(|(chunks, _)| chunks),
+            checked: counts.map(|(_, checked)| checked),
+            error,
+        }
+    }
+}
+
+const VERIFY_RETURN_TYPE: ReturnType = ReturnType {
+    optional: false,
+    schema: &ArraySchema::new("Verification results.", &VerifyFileResult::API_SCHEMA).schema(),
+};
+
+// check a downloaded blob against the manifest, and decode it if possible
+async fn verify_blob(
+    reader: &BackupReader,
+    manifest: &BackupManifest,
+    name: &str,
+    crypt_config: Option<&CryptConfig>,
+) -> Result<(), Error> {
+    let mut raw_data = Vec::new();
+    reader.download(name, &mut raw_data).await?;
+
+    let csum = openssl::sha::sha256(&raw_data);
+    manifest.verify_file(name, &csum, raw_data.len() as u64)?;
+
+    // verifies the CRC
+    let blob = DataBlob::load_from_reader(&mut &raw_data[..])?;
+    if !blob.is_encrypted() || crypt_config.is_some() {
+        blob.decode(crypt_config, None)?;
+    }
+    Ok(())
+}
+
+// download a chunk and check its CRC, and if possible its digest and size
+async fn verify_chunk(
+    reader: &BackupReader,
+    digest: [u8; 32],
+    size: u64,
+    crypt_config: Option<&CryptConfig>,
+) -> Result<(), Error> {
+    let mut raw_data = Vec::new();
+    reader.download_chunk(&digest, &mut raw_data).await?;
+
+    let result = DataBlob::load_from_reader(&mut &raw_data[..]).and_then(|blob| {
+        if blob.is_encrypted() && crypt_config.is_none() {
+            // without the key only the CRC can be checked
+            return Ok(());
+        }
+        let data = blob.decode(crypt_config, Some(&digest))?;
+        if data.len() as u64 != size {
+            bail!("wrong chunk size ({} != {size})", data.len());
+        }
+        Ok(())
+    });
+
+    result.map_err(|err| format_err!("chunk {} - {err}", hex::encode(digest)))
+}
+
+fn sample_chunk(sample: u64) -> Result<bool, Error> {
+    match sample {
+        0 => Ok(false),
+        100.. => Ok(true),
+        _ => {
+            let mut buf = [0u8; 2];
+            openssl::rand::rand_bytes(&mut buf)?;
+            Ok(u16::from_le_bytes(buf) as u64 % 100 < sample)
+        }
+    }
+}
+
+// check an index against the manifest and verify a sample of its chunks
+async fn verify_index(
+    reader: &BackupReader,
+    manifest: &BackupManifest,
+    name: &str,
+    sample: u64,
+    crypt_config: Option<&CryptConfig>,
+    checked_chunks: &mut HashSet<[u8; 32]>,
+) -> Result<(usize, usize), Error> {
+    // both verify the index checksum against the manifest
+    let index: Box<dyn IndexFile + Send> = match archive_type(name)? {
+        ArchiveType::DynamicIndex => Box::new(reader.download_dynamic_index(manifest, name).await?),
+        ArchiveType::FixedIndex => Box::new(reader.download_fixed_index(manifest, name).await?),
+        ArchiveType::Blob => unreachable!(),
+    };
+
+    let mut chunks = Vec::new();
+    for pos in 0..index.index_count() {
+        let info = index.chunk_info(pos).unwrap();
+        // chunks shared with already checked archives are not downloaded again
+        if checked_chunks.contains(&info.digest) || !sample_chunk(sample)? {
+            continue;
+        }
+        checked_chunks.insert(info.digest);
+        chunks.push((info.digest, info.size()));
+    }
+
+    let checked = chunks.len();
+    futures::stream::iter(chunks)
+        .map(|(digest, size)| verify_chunk(reader, digest, size, crypt_config))
+        .buffer_unordered(VERIFY_CONCURRENCY)
+        .try_collect::<Vec<()>>()
+        .await?;
+
+    Ok((index.index_count(), checked))
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            snapshot: {
+                type: String,
+                description: "Group/Snapshot path.",
+            },
+            sample: {
+                type: Integer,
+                description: "Percentage of chunks to download and check.",
+                optional: true,
+                minimum: 0,
+                maximum: 100,
+                default: 0,
+            },
+            keyfile: {
+                optional: true,
+                type: String,
+                description: "Path to encryption key.",
+            },
+            keyfd: {
+                schema: KEYFD_SCHEMA,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Verify a snapshot from the client side, independently of the server.
+///
+/// Checks the signature of the manifest (requires the encryption key), the
+/// checksums of all files against the manifest and, with '--sample', downloads
+/// the given percentage of chunks and checks their digests.
+async fn verify(param: Value, sample: u64) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let client = connect(&repo)?;
+    let backup_ns = optional_ns_param(&param)?;
+    let path = required_string_param(&param, "snapshot")?;
+
+    let output_format = get_output_format(&param);
+
+    let backup_dir = dir_or_last_from_group(&client, &repo, &backup_ns, path).await?;
+
+    let crypto = crypto_parameters(&param)?;
+
+    let crypt_config = match crypto.enc_key {
+        None => None,
+        Some(key) => {
+            let (key, _created, _fingerprint) = decrypt_key(&key.key).map_err(|err| {
+                log::error!("{}", format_key_source(&key.source, "encryption"));
+                err
+            })?;
+            Some(Arc::new(CryptConfig::new(key)?))
+        }
+    };
+
+    let reader = BackupReader::start(
+        &client,
+        crypt_config.clone(),
+        repo.store(),
+        &backup_ns,
+        &backup_dir,
+        true,
+    )
+    .await?;
+
+    // verifies the signature if we have a key
+    let (manifest, _) = reader.download_manifest().await?;
+
+    match (manifest.signature.is_some(), &crypt_config) {
+        (true, Some(config)) => {
+            manifest.check_fingerprint(Some(config))?;
+            log::info!(
+                "manifest signature valid (key {})",
+                Fingerprint::new(config.fingerprint())
+            );
+        }
+        (true, None) => log::warn!(
+            "manifest is signed, but no encryption key is available - signature and chunk \
+            digests of encrypted files cannot be checked"
+        ),
+        (false, _) => {
+            if manifest
+                .files()
+                .iter()
+                .any(|file| file.crypt_mode != CryptMode::None)
+            {
+                bail!("manifest of a snapshot with encrypted files is not signed");
+            }
+            log::info!("manifest is not signed (unencrypted snapshot)");
+        }
+    }
+
+    let crypt_config = crypt_config.as_ref().map(Arc::as_ref);
+
+    let mut results = Vec::new();
+    let mut checked_chunks = HashSet::new();
+    for file in manifest.files() {
+        let name = &file.filename;
+        let result = match archive_type(name)? {
+            ArchiveType::Blob => verify_blob(&reader, &manifest, name, crypt_config)
+                .await
+                .map(|()| None),
+            _ => verify_index(
+                &reader,
+                &manifest,
+                name,
+                sample,
+                crypt_config,
+                &mut checked_chunks,
+            )
+            .await
+            .map(Some),
+        };
+        results.push(VerifyFileResult::new(name, result));
+    }
+
+    record_repository(&repo);
+
+    let failed = results
+        .iter()
+        .filter(|result| result.status == VerifyStatus::Failed)
+        .count();
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("filename"))
+        .column(ColumnConfig::new("status"))
+        .column(ColumnConfig::new("chunks"))
+        .column(ColumnConfig::new("checked"))
+        .column(ColumnConfig::new("error"));
+
+    format_and_print_result_full(
+        &mut serde_json::to_value(results)?,
+        &VERIFY_RETURN_TYPE,
+        &output_format,
+        &options,
+    );
+
+    if failed > 0 {
+        bail!("verification of {backup_dir} failed - {failed} file(s) with errors");
+    }
+
+    Ok(())
+}
+
+pub fn verify_cmd_def() -> CliCommand {
+    CliCommand::new(&API_METHOD_VERIFY)
+        .arg_param(&["snapshot"])
+        .completion_cb("repository", complete_repository)
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("snapshot", complete_backup_snapshot)
+}
@@ -23,10 +23,10 @@ use pxar::decoder::aio::Decoder;
 use pbs_api_types::{file_restore::FileRestoreFormat, BackupDir, BackupNamespace, CryptMode};
 use pbs_client::pxar::{create_tar, create_zip, extract_sub_dir, extract_sub_dir_seq};
 use pbs_client::tools::{
-    complete_group_or_snapshot, complete_repository, connect, extract_repository_from_value,
+    complete_group_or_snapshot, complete_repository, connect, default_namespace,
+    extract_repository_from_value,
     key_source::{
-        crypto_parameters_keep_fd, format_key_source, get_encryption_key_password, KEYFD_SCHEMA,
-        KEYFILE_SCHEMA,
+        crypto_parameters_keep_fd, decrypt_key, format_key_source, KEYFD_SCHEMA, KEYFILE_SCHEMA,
     },
     REPO_URL_SCHEMA,
 };
@@ -35,7 +35,6 @@ use pbs_datastore::catalog::{ArchiveEntry, CatalogReader, DirEntryAttribute};
 use pbs_datastore::dynamic_index::{BufferedDynamicReader, LocalDynamicReadAt};
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::CATALOG_NAME;
-use pbs_key_config::decrypt_key;
 use pbs_tools::crypt_config::CryptConfig;
 
 pub mod block_driver;
@@ -246,7 +245,7 @@ async fn list(
     param: Value,
 ) -> Result<(), Error> {
     let repo = extract_repository_from_value(&param)?;
-    let ns = ns.unwrap_or_default();
+    let ns = ns.unwrap_or_else(|| default_namespace(Some(&repo)));
     let snapshot: BackupDir = snapshot.parse()?;
     let path = parse_path(path, base64)?;
 
@@ -255,11 +254,10 @@ async fn list(
     let crypt_config = match crypto.enc_key {
         None => None,
         Some(ref key) => {
-            let (key, _, _) =
-                decrypt_key(&key.key, &get_encryption_key_password).map_err(|err| {
-                    log::error!("{}", format_key_source(&key.source, "encryption"));
-                    err
-                })?;
+            let (key, _, _) = decrypt_key(&key.key).map_err(|err| {
+                log::error!("{}", format_key_source(&key.source, "encryption"));
+                err
+            })?;
             Some(Arc::new(CryptConfig::new(key)?))
         }
     };
@@ -403,7 +401,7 @@ async fn extract(
     param: Value,
 ) -> Result<(), Error> {
     let repo = extract_repository_from_value(&param)?;
-    let namespace = ns.unwrap_or_default();
+    let namespace = ns.unwrap_or_else(|| default_namespace(Some(&repo)));
     let snapshot: BackupDir = snapshot.parse()?;
     let orig_path = path;
     let path = parse_path(orig_path.clone(), base64)?;
@@ -419,11 +417,10 @@ async fn extract(
     let crypt_config = match crypto.enc_key {
         None => None,
         Some(ref key) => {
-            let (key, _, _) =
-                decrypt_key(&key.key, &get_encryption_key_password).map_err(|err| {
-                    log::error!("{}", format_key_source(&key.source, "encryption"));
-                    err
-                })?;
+            let (key, _, _) = decrypt_key(&key.key).map_err(|err| {
+                log::error!("{}", format_key_source(&key.source, "encryption"));
+                err
+            })?;
             Some(Arc::new(CryptConfig::new(key)?))
         }
     };
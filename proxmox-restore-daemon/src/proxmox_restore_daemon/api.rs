@@ -353,6 +353,15 @@ fn extract(
                         patterns,
                         skip_lost_and_found: false,
                         skip_e2big_xattr: false,
+                        exclude_caches: false,
+                        exclude_nodump: false,
+                        nfs4_acl: Default::default(),
+                        change_detection: None,
+                        follow_symlinks: Default::default(),
+                        follow_mounts: Default::default(),
+                        follow_paths: Vec::new(),
+                        skipped: None,
+                        file_list: None,
                     };
 
                     let pxar_writer = TokioWriter::new(writer);
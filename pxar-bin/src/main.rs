@@ -13,11 +13,14 @@ use tokio::signal::unix::{signal, SignalKind};
 
 use pathpatterns::{MatchEntry, MatchType, PatternFlag};
 use pbs_client::pxar::{
-    format_single_line_entry, Flags, OverwriteFlags, PxarExtractOptions, ENCODER_MAX_ENTRIES,
+    format_mtree_entry, format_single_line_entry, Flags, FollowPolicy, OverwriteFlags,
+    PxarExtractOptions, ENCODER_MAX_ENTRIES,
 };
+use pbs_client::tools::completions::completions_cmd_def;
+use pxar::EntryKind;
 
 use proxmox_router::cli::*;
-use proxmox_schema::api;
+use proxmox_schema::{api, ApiStringFormat, EnumEntry, Schema, StringSchema};
 
 fn extract_archive_from_reader<R: std::io::Read>(
     reader: &mut R,
@@ -51,6 +54,24 @@ fn extract_archive_from_reader<R: std::io::Read>(
                 },
                 optional: true,
             },
+            include: {
+                description: "List of paths or patterns matching files to restore.",
+                type: Array,
+                items: {
+                    type: String,
+                    description: "Path or pattern matching files to restore.",
+                },
+                optional: true,
+            },
+            exclude: {
+                description: "List of paths or patterns matching files to skip.",
+                type: Array,
+                items: {
+                    type: String,
+                    description: "Path or pattern matching files to skip.",
+                },
+                optional: true,
+            },
             target: {
                 description: "Target directory",
                 optional: true,
@@ -127,6 +148,8 @@ fn extract_archive_from_reader<R: std::io::Read>(
 fn extract_archive(
     archive: String,
     pattern: Option<Vec<String>>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
     target: Option<String>,
     no_xattrs: bool,
     no_fcaps: bool,
@@ -184,14 +207,24 @@ fn extract_archive(
         }
     }
 
-    for entry in pattern {
+    for entry in pattern.into_iter().chain(include.unwrap_or_default()) {
         match_list.push(
             MatchEntry::parse_pattern(entry, PatternFlag::PATH_NAME, MatchType::Include)
                 .map_err(|err| format_err!("error in pattern: {}", err))?,
         );
     }
 
-    let extract_match_default = match_list.is_empty();
+    for entry in exclude.unwrap_or_default() {
+        match_list.push(
+            MatchEntry::parse_pattern(entry, PatternFlag::PATH_NAME, MatchType::Exclude)
+                .map_err(|err| format_err!("error in exclude pattern: {}", err))?,
+        );
+    }
+
+    // only excluding restores everything else
+    let extract_match_default = !match_list
+        .iter()
+        .any(|entry| entry.match_type() == MatchType::Include);
 
     let was_ok = Arc::new(AtomicBool::new(true));
     let on_error = if strict {
@@ -222,9 +255,16 @@ fn extract_archive(
         extract_archive_from_reader(&mut reader, target, feature_flags, options)?;
     } else {
         log::debug!("PXAR extract: {}", archive);
-        let file = std::fs::File::open(archive)?;
-        let mut reader = std::io::BufReader::new(file);
-        extract_archive_from_reader(&mut reader, target, feature_flags, options)?;
+        // random access allows skipping directories which contain nothing to restore
+        pbs_client::pxar::extract_archive_seekable(
+            pxar::accessor::sync::Accessor::open(archive)?,
+            Path::new(target),
+            feature_flags,
+            |path| {
+                log::debug!("{:?}", path);
+            },
+            options,
+        )?;
     }
 
     if !was_ok.load(Ordering::Acquire) {
@@ -278,6 +318,33 @@ fn extract_archive(
                 optional: true,
                 default: false,
             },
+            "exclude-caches": {
+                description: "Skip the contents of directories containing a valid CACHEDIR.TAG file, except the tag itself.",
+                optional: true,
+                default: false,
+            },
+            "exclude-nodump": {
+                description: "Skip files and directories with the 'nodump' attribute (see chattr(1)).",
+                optional: true,
+                default: false,
+            },
+            "follow-symlinks": {
+                type: FollowPolicy,
+                optional: true,
+            },
+            "follow-mounts": {
+                type: FollowPolicy,
+                optional: true,
+            },
+            "follow-path": {
+                description: "Paths of symlinks and mount points followed with the 'listed' policies.",
+                optional: true,
+                type: Array,
+                items: {
+                    description: "Path relative to the source directory.",
+                    type: String,
+                },
+            },
             exclude: {
                 description: "List of paths or pattern matching files to exclude.",
                 optional: true,
@@ -294,6 +361,10 @@ fn extract_archive(
                 minimum: 0,
                 maximum: isize::MAX,
             },
+            "files-from0": {
+                description: "Only archive the NUL separated paths read from this file ('-' for stdin), along with their parent directories.",
+                optional: true,
+            },
         },
     },
 )]
@@ -309,8 +380,14 @@ async fn create_archive(
     no_device_nodes: bool,
     no_fifos: bool,
     no_sockets: bool,
+    exclude_caches: bool,
+    exclude_nodump: bool,
+    follow_symlinks: Option<FollowPolicy>,
+    follow_mounts: Option<FollowPolicy>,
+    follow_path: Option<Vec<String>>,
     exclude: Option<Vec<String>>,
     entries_max: isize,
+    files_from0: Option<String>,
 ) -> Result<(), Error> {
     let patterns = {
         let input = exclude.unwrap_or_default();
@@ -330,12 +407,30 @@ async fn create_archive(
         Some(HashSet::new())
     };
 
+    let file_list = match files_from0 {
+        Some(from) => Some(read_file_list0(Path::new(&source), &from)?),
+        None => None,
+    };
+
     let options = pbs_client::pxar::PxarCreateOptions {
         entries_max: entries_max as usize,
         device_set,
         patterns,
         skip_lost_and_found: false,
         skip_e2big_xattr: false,
+        exclude_caches,
+        exclude_nodump,
+        nfs4_acl: Default::default(),
+        change_detection: None,
+        follow_symlinks: follow_symlinks.unwrap_or_default(),
+        follow_mounts: follow_mounts.unwrap_or_default(),
+        follow_paths: follow_path
+            .unwrap_or_default()
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+        skipped: None,
+        file_list,
     };
 
     let source = PathBuf::from(source);
@@ -390,6 +485,58 @@ async fn create_archive(
     Ok(())
 }
 
+/// Reads a list of NUL separated paths, as written by `find -print0`, from the file `from` or
+/// from stdin for '-'.
+///
+/// The paths are returned relative to `source`. Absolute paths have to be below `source`, relative
+/// ones are taken relative to it, like the paths printed by `find . -print0` within `source`.
+fn read_file_list0(source: &Path, from: &str) -> Result<Vec<PathBuf>, Error> {
+    use std::io::Read;
+
+    let data = if from == "-" {
+        let mut data = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut data)?;
+        data
+    } else {
+        std::fs::read(from).map_err(|err| format_err!("unable to read {from:?} - {err}"))?
+    };
+
+    parse_file_list0(source, &data)
+}
+
+fn parse_file_list0(source: &Path, data: &[u8]) -> Result<Vec<PathBuf>, Error> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Component;
+
+    let mut list = Vec::new();
+    for entry in data.split(|b| *b == 0) {
+        if entry.is_empty() {
+            continue;
+        }
+        let path = Path::new(OsStr::from_bytes(entry));
+        let relative = if path.is_absolute() {
+            path.strip_prefix(source)
+                .map_err(|_| format_err!("path {path:?} is not below the source {source:?}"))?
+        } else {
+            path
+        };
+
+        let mut normalized = PathBuf::new();
+        for component in relative.components() {
+            match component {
+                Component::Normal(name) => normalized.push(name),
+                Component::CurDir => (),
+                _ => bail!("unsupported path {path:?} in file list"),
+            }
+        }
+        if !normalized.as_os_str().is_empty() {
+            list.push(normalized);
+        }
+    }
+
+    Ok(list)
+}
+
 #[api(
     input: {
         properties: {
@@ -425,17 +572,90 @@ async fn mount_archive(archive: String, mountpoint: String, verbose: bool) -> Re
     Ok(())
 }
 
+const LIST_FORMAT_SCHEMA: Schema = StringSchema::new("Output format.")
+    .format(&ApiStringFormat::Enum(&[
+        EnumEntry::new("plain", "Paths of the entries, with details in debug mode."),
+        EnumEntry::new(
+            "mtree",
+            "BSD mtree specification, including SHA-256 digests of files.",
+        ),
+    ]))
+    .default("plain")
+    .schema();
+
+fn sha256_digest<R: std::io::Read>(mut reader: R) -> Result<[u8; 32], Error> {
+    let mut hasher = openssl::sha::Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+    Ok(hasher.finish())
+}
+
+/// Print an mtree specification of the archive.
+fn dump_archive_mtree(archive: &str) -> Result<(), Error> {
+    let mut decoder = pxar::decoder::Decoder::open(archive)?;
+    // hard links are resolved via random access, which is only needed for archives using them
+    let mut accessor = None;
+
+    println!("#mtree");
+    while let Some(entry) = decoder.next() {
+        let entry = entry?;
+
+        let record = match entry.kind() {
+            EntryKind::File { .. } => {
+                let contents = decoder
+                    .contents()
+                    .ok_or_else(|| format_err!("file {:?} without contents", entry.path()))?;
+                format_mtree_entry(entry.path(), &entry, Some(&sha256_digest(contents)?))
+            }
+            EntryKind::Hardlink(link) => {
+                let accessor = match accessor {
+                    Some(ref accessor) => accessor,
+                    None => &*accessor.insert(pxar::accessor::sync::Accessor::open(archive)?),
+                };
+                let target = accessor
+                    .open_root()?
+                    .lookup(Path::new(link))?
+                    .ok_or_else(|| {
+                        format_err!("hard link target {:?} not found", link.as_os_str())
+                    })?;
+                let digest = sha256_digest(target.contents()?)?;
+                format_mtree_entry(entry.path(), target.entry(), Some(&digest))
+            }
+            _ => format_mtree_entry(entry.path(), &entry, None),
+        };
+        if let Some(record) = record {
+            println!("{record}");
+        }
+    }
+
+    Ok(())
+}
+
 #[api(
     input: {
         properties: {
             archive: {
                 description: "Archive name.",
             },
+            format: {
+                schema: LIST_FORMAT_SCHEMA,
+                optional: true,
+            },
         },
     },
 )]
 /// List the contents of an archive.
-fn dump_archive(archive: String) -> Result<(), Error> {
+fn dump_archive(archive: String, format: Option<String>) -> Result<(), Error> {
+    if format.as_deref() == Some("mtree") {
+        return dump_archive_mtree(&archive);
+    }
+
     for entry in pxar::decoder::Decoder::open(archive)? {
         let entry = entry?;
 
@@ -448,6 +668,46 @@ fn dump_archive(archive: String) -> Result<(), Error> {
     Ok(())
 }
 
+#[api(
+    input: {
+        properties: {
+            archive: {
+                description: "Archive name, or '-' to read from stdin.",
+            },
+            output: {
+                description: "Tar file to write, or '-' for stdout.",
+                optional: true,
+                default: "-",
+            },
+        },
+    },
+)]
+/// Convert an archive into a POSIX (pax) tar archive.
+fn convert_to_tar(archive: String, output: String) -> Result<(), Error> {
+    let writer: Box<dyn std::io::Write> = if output == "-" {
+        Box::new(std::io::stdout().lock())
+    } else {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .mode(0o640)
+            .open(&output)
+            .map_err(|err| format_err!("unable to create '{}' - {}", output, err))?;
+        Box::new(file)
+    };
+    let writer = std::io::BufWriter::new(writer);
+
+    if archive == "-" {
+        let stdin = std::io::stdin();
+        let reader = stdin.lock();
+        pbs_client::pxar::convert_to_tar(pxar::decoder::Decoder::from_std(reader)?, writer)
+    } else {
+        let file = std::fs::File::open(&archive)?;
+        let reader = std::io::BufReader::new(file);
+        pbs_client::pxar::convert_to_tar(pxar::decoder::Decoder::from_std(reader)?, writer)
+    }
+}
+
 fn main() {
     init_cli_logger("PXAR_LOG", "info");
 
@@ -479,7 +739,15 @@ fn main() {
             CliCommand::new(&API_METHOD_DUMP_ARCHIVE)
                 .arg_param(&["archive"])
                 .completion_cb("archive", complete_file_name),
-        );
+        )
+        .insert(
+            "to-tar",
+            CliCommand::new(&API_METHOD_CONVERT_TO_TAR)
+                .arg_param(&["archive"])
+                .completion_cb("archive", complete_file_name)
+                .completion_cb("output", complete_file_name),
+        )
+        .insert("completions", completions_cmd_def());
 
     let rpcenv = CliEnvironment::new();
     run_cli_command(
@@ -488,3 +756,50 @@ fn main() {
         Some(|future| proxmox_async::runtime::main(future)),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> Result<Vec<PathBuf>, Error> {
+        parse_file_list0(Path::new("/srv/data"), data)
+    }
+
+    #[test]
+    fn test_parse_file_list0() -> Result<(), Error> {
+        // as printed by `find . -print0` within the source
+        assert_eq!(
+            parse(b".\0./etc\0./etc/hosts\0")?,
+            [PathBuf::from("etc"), PathBuf::from("etc/hosts")],
+        );
+
+        // relative paths without leading "./", redundant separators
+        assert_eq!(
+            parse(b"etc//hosts\0var/./log\0")?,
+            [PathBuf::from("etc/hosts"), PathBuf::from("var/log")],
+        );
+
+        // absolute paths below the source
+        assert_eq!(
+            parse(b"/srv/data\0/srv/data/etc/hosts\0")?,
+            [PathBuf::from("etc/hosts")],
+        );
+        assert!(parse(b"/srv/other/file\0").is_err());
+        assert!(parse(b"/srv/database/file\0").is_err());
+
+        // no escaping from the source
+        assert!(parse(b"../file\0").is_err());
+        assert!(parse(b"etc/../../file\0").is_err());
+        assert!(parse(b"/srv/data/../other\0").is_err());
+
+        // empty entries, also without a trailing NUL
+        assert!(parse(b"")?.is_empty());
+        assert!(parse(b"\0\0")?.is_empty());
+        assert_eq!(
+            parse(b"\0etc\0\0var")?,
+            [PathBuf::from("etc"), PathBuf::from("var")],
+        );
+
+        Ok(())
+    }
+}
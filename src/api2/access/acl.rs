@@ -7,31 +7,55 @@ use proxmox_router::{Permission, Router, RpcEnvironment};
 use proxmox_schema::api;
 
 use pbs_api_types::{
-    AclListItem, Authid, Role, ACL_PATH_SCHEMA, ACL_PROPAGATE_SCHEMA, PRIV_PERMISSIONS_MODIFY,
-    PRIV_SYS_AUDIT, PROXMOX_CONFIG_DIGEST_SCHEMA, PROXMOX_GROUP_ID_SCHEMA,
+    AclListItem, Authid, Role, ACL_PATH_SCHEMA, ACL_PROPAGATE_SCHEMA, PRIV_PERMISSIONS_AUDIT,
+    PRIV_PERMISSIONS_MODIFY, PRIV_SYS_AUDIT, PROXMOX_CONFIG_DIGEST_SCHEMA, PROXMOX_GROUP_ID_SCHEMA,
 };
 
-use pbs_config::acl::AclTreeNode;
+use pbs_config::acl::{split_acl_path, AclTreeNode, ROLE_NAMES};
 
 use pbs_config::CachedUserInfo;
 
+/// Privileges of `auth_id` for managing the ACLs on `path` without privileges on '/access/acl'.
+///
+/// The ACLs of datastores and namespaces can be delegated, with the privileges the user has on
+/// the path itself. Those are only inherited downwards, so delegated users cannot see or change
+/// ACLs above the datastore or namespace they were given.
+///
+/// `lookup_privs` returns the privileges of the user on a path.
+fn delegated_acl_privs(lookup_privs: impl Fn(&[&str]) -> u64, path: &str) -> u64 {
+    let components = split_acl_path(path);
+    if components.len() < 2 || components[0] != "datastore" {
+        return 0;
+    }
+    lookup_privs(&components)
+}
+
+/// Check that a delegated user with `delegated_privs` on `path` may assign `role` there.
+fn check_delegated_role(role: &str, delegated_privs: u64, path: &str) -> Result<(), Error> {
+    let role_privs = match ROLE_NAMES.get(role) {
+        Some((privs, _)) => *privs,
+        None => bail!("unknown role '{role}'"),
+    };
+    if role_privs & !delegated_privs != 0 {
+        bail!("role '{role}' exceeds the privileges on '{path}'");
+    }
+    Ok(())
+}
+
 fn extract_acl_node_data(
     node: &AclTreeNode,
     path: &str,
     list: &mut Vec<AclListItem>,
     exact: bool,
     auth_id_filter: &Option<Authid>,
+    delegated: &dyn Fn(&str) -> bool,
 ) {
-    // tokens can't have tokens, so we can early return
-    if let Some(auth_id_filter) = auth_id_filter {
-        if auth_id_filter.is_token() {
-            return;
-        }
-    }
+    let filter = auth_id_filter.as_ref().filter(|_| !delegated(path));
 
     for (user, roles) in &node.users {
-        if let Some(auth_id_filter) = auth_id_filter {
-            if !user.is_token() || user.user() != auth_id_filter.user() {
+        if let Some(filter) = filter {
+            // tokens can't have tokens
+            if filter.is_token() || !user.is_token() || user.user() != filter.user() {
                 continue;
             }
         }
@@ -51,7 +75,7 @@ fn extract_acl_node_data(
         }
     }
     for (group, roles) in &node.groups {
-        if auth_id_filter.is_some() {
+        if filter.is_some() {
             continue;
         }
 
@@ -74,7 +98,7 @@ fn extract_acl_node_data(
     }
     for (comp, child) in &node.children {
         let new_path = format!("{}/{}", path, comp);
-        extract_acl_node_data(child, &new_path, list, exact, auth_id_filter);
+        extract_acl_node_data(child, &new_path, list, exact, auth_id_filter, delegated);
     }
 }
 
@@ -102,7 +126,7 @@ fn extract_acl_node_data(
     },
     access: {
         permission: &Permission::Anybody,
-        description: "Returns all ACLs if user has Sys.Audit on '/access/acl', or just the ACLs containing the user's API tokens.",
+        description: "Returns all ACLs if user has Sys.Audit on '/access/acl', or just the ACLs containing the user's API tokens and the ACLs of datastores and namespaces the user has Permissions.Audit on.",
     },
 )]
 /// Read Access Control List (ACLs).
@@ -111,26 +135,37 @@ pub fn read_acl(
     exact: bool,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Vec<AclListItem>, Error> {
-    let auth_id = rpcenv.get_auth_id().unwrap().parse()?;
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
     let user_info = CachedUserInfo::new()?;
 
     let top_level_privs = user_info.lookup_privs(&auth_id, &["access", "acl"]);
     let auth_id_filter = if (top_level_privs & PRIV_SYS_AUDIT) == 0 {
-        Some(auth_id)
+        Some(auth_id.clone())
     } else {
         None
     };
 
+    let lookup_privs = |components: &[&str]| user_info.lookup_privs(&auth_id, components);
+    let delegated =
+        |path: &str| delegated_acl_privs(lookup_privs, path) & PRIV_PERMISSIONS_AUDIT != 0;
+
     let (mut tree, digest) = pbs_config::acl::config()?;
 
     let mut list: Vec<AclListItem> = Vec::new();
     if let Some(path) = &path {
         if let Some(node) = &tree.find_node(path) {
-            extract_acl_node_data(node, path, &mut list, exact, &auth_id_filter);
+            extract_acl_node_data(node, path, &mut list, exact, &auth_id_filter, &delegated);
         }
     } else {
-        extract_acl_node_data(&tree.root, "", &mut list, exact, &auth_id_filter);
+        extract_acl_node_data(
+            &tree.root,
+            "",
+            &mut list,
+            exact,
+            &auth_id_filter,
+            &delegated,
+        );
     }
 
     rpcenv["digest"] = hex::encode(digest).into();
@@ -173,7 +208,7 @@ pub fn read_acl(
     },
     access: {
         permission: &Permission::Anybody,
-        description: "Requires Permissions.Modify on '/access/acl', limited to updating ACLs of the user's API tokens otherwise."
+        description: "Requires Permissions.Modify on '/access/acl', or Permissions.Modify on the datastore or namespace path for roles within the user's own privileges there, limited to updating ACLs of the user's API tokens otherwise."
     },
 )]
 /// Update Access Control List (ACLs).
@@ -198,20 +233,31 @@ pub fn update_acl(
             bail!("Unprivileged users are not allowed to create group ACL item.");
         }
 
-        match &auth_id {
-            Some(auth_id) => {
-                if current_auth_id.is_token() {
-                    bail!("Unprivileged API tokens can't set ACL items.");
-                } else if !auth_id.is_token() {
-                    bail!("Unprivileged users can only set ACL items for API tokens.");
-                } else if auth_id.user() != current_auth_id.user() {
-                    bail!("Unprivileged users can only set ACL items for their own API tokens.");
+        let delegated_privs = delegated_acl_privs(
+            |components| user_info.lookup_privs(&current_auth_id, components),
+            &path,
+        );
+        if delegated_privs & PRIV_PERMISSIONS_MODIFY != 0 {
+            // delegated users can only pass on their own privileges
+            check_delegated_role(&role, delegated_privs, &path)?;
+        } else {
+            match &auth_id {
+                Some(auth_id) => {
+                    if current_auth_id.is_token() {
+                        bail!("Unprivileged API tokens can't set ACL items.");
+                    } else if !auth_id.is_token() {
+                        bail!("Unprivileged users can only set ACL items for API tokens.");
+                    } else if auth_id.user() != current_auth_id.user() {
+                        bail!(
+                            "Unprivileged users can only set ACL items for their own API tokens."
+                        );
+                    }
                 }
-            }
-            None => {
-                bail!("Unprivileged user needs to provide auth_id to update ACL item.");
-            }
-        };
+                None => {
+                    bail!("Unprivileged user needs to provide auth_id to update ACL item.");
+                }
+            };
+        }
     }
 
     let _lock = pbs_config::acl::lock_config()?;
@@ -275,3 +321,129 @@ pub fn update_acl(
 pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_READ_ACL)
     .put(&API_METHOD_UPDATE_ACL);
+
+#[cfg(test)]
+mod tests {
+    use pbs_api_types::{ROLE_ADMIN, ROLE_NAMESPACE_ADMIN, ROLE_NAMESPACE_AUDIT};
+    use pbs_config::acl::AclTree;
+
+    use super::*;
+
+    // privileges of `auth_id` according to `tree` alone
+    fn privs(tree: &AclTree, auth_id: &Authid, components: &[&str]) -> u64 {
+        tree.roles(auth_id, components)
+            .keys()
+            .map(|role| ROLE_NAMES[role.as_str()].0)
+            .fold(0, |privs, role_privs| privs | role_privs)
+    }
+
+    fn test_tree() -> Result<AclTree, Error> {
+        AclTree::from_raw(
+            r###"
+acl:1:/:admin@pbs:Admin
+acl:1:/datastore/store1/tenant:tenant@pbs:NamespaceAdmin
+acl:1:/datastore/store1/tenant:auditor@pbs:NamespaceAudit
+acl:1:/datastore/store1/tenant/sub:user@pbs:DatastoreBackup
+acl:1:/datastore/store1/tenant/sub:user@pbs!token:DatastoreBackup
+acl:1:/datastore/store1/other:user@pbs:DatastoreBackup
+acl:1:/datastore/store1/other:@group:DatastoreReader
+"###,
+        )
+    }
+
+    #[test]
+    fn test_delegated_acl_privs() -> Result<(), Error> {
+        let tree = test_tree()?;
+        let tenant: Authid = "tenant@pbs".parse()?;
+        let lookup = |components: &[&str]| privs(&tree, &tenant, components);
+
+        assert_eq!(
+            delegated_acl_privs(lookup, "/datastore/store1/tenant"),
+            ROLE_NAMESPACE_ADMIN
+        );
+        assert_eq!(
+            delegated_acl_privs(lookup, "/datastore/store1/tenant/sub"),
+            ROLE_NAMESPACE_ADMIN
+        );
+        assert_eq!(delegated_acl_privs(lookup, "/datastore/store1"), 0);
+        assert_eq!(delegated_acl_privs(lookup, "/datastore/store1/other"), 0);
+
+        // only datastore and namespace paths can be delegated
+        let admin: Authid = "admin@pbs".parse()?;
+        let lookup = |components: &[&str]| privs(&tree, &admin, components);
+        assert_eq!(delegated_acl_privs(lookup, "/datastore/store1"), ROLE_ADMIN);
+        assert_eq!(delegated_acl_privs(lookup, "/"), 0);
+        assert_eq!(delegated_acl_privs(lookup, "/datastore"), 0);
+        assert_eq!(delegated_acl_privs(lookup, "/access/acl"), 0);
+        assert_eq!(delegated_acl_privs(lookup, "/remote/remote1"), 0);
+        assert_eq!(delegated_acl_privs(lookup, "/system/network"), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_delegated_role() {
+        let path = "/datastore/store1/tenant";
+        for role in [
+            "NamespaceAdmin",
+            "DatastoreAdmin",
+            "DatastoreBackup",
+            "NoAccess",
+        ] {
+            assert!(check_delegated_role(role, ROLE_NAMESPACE_ADMIN, path).is_ok());
+        }
+        for role in ["Admin", "Audit", "RemoteSyncOperator", "TapeAdmin"] {
+            assert!(check_delegated_role(role, ROLE_NAMESPACE_ADMIN, path).is_err());
+        }
+
+        assert!(check_delegated_role("NamespaceAudit", ROLE_NAMESPACE_AUDIT, path).is_ok());
+        assert!(check_delegated_role("NamespaceAdmin", ROLE_NAMESPACE_AUDIT, path).is_err());
+        assert!(check_delegated_role("NoSuchRole", ROLE_ADMIN, path).is_err());
+    }
+
+    fn read(auth_id: &Authid, path: &str) -> Result<Vec<(String, String)>, Error> {
+        let tree = test_tree()?;
+        let lookup = |components: &[&str]| privs(&tree, auth_id, components);
+        let delegated =
+            |path: &str| delegated_acl_privs(lookup, path) & PRIV_PERMISSIONS_AUDIT != 0;
+        let mut nodes = test_tree()?;
+        let node = nodes.find_node(path).unwrap();
+
+        let mut list = Vec::new();
+        let filter = Some(auth_id.clone());
+        extract_acl_node_data(node, path, &mut list, false, &filter, &delegated);
+
+        let mut list: Vec<_> = list
+            .into_iter()
+            .map(|item| (item.path, item.ugid))
+            .collect();
+        list.sort();
+        Ok(list)
+    }
+
+    #[test]
+    fn test_read_filter() -> Result<(), Error> {
+        let entry = |path: &str, ugid: &str| (path.to_string(), ugid.to_string());
+
+        // everything below the delegated namespace
+        let auditor: Authid = "auditor@pbs".parse()?;
+        assert_eq!(
+            read(&auditor, "/datastore/store1")?,
+            [
+                entry("/datastore/store1/tenant", "auditor@pbs"),
+                entry("/datastore/store1/tenant", "tenant@pbs"),
+                entry("/datastore/store1/tenant/sub", "user@pbs"),
+                entry("/datastore/store1/tenant/sub", "user@pbs!token"),
+            ],
+        );
+
+        // only the own API tokens elsewhere
+        let user: Authid = "user@pbs".parse()?;
+        assert_eq!(
+            read(&user, "/datastore/store1")?,
+            [entry("/datastore/store1/tenant/sub", "user@pbs!token")],
+        );
+
+        Ok(())
+    }
+}
@@ -180,9 +180,11 @@ pub fn openid_login(
                     comment: None,
                     enable: None,
                     expire: None,
+                    append_only: None,
                     firstname,
                     lastname,
                     email,
+                    lang: None,
                 };
                 let (mut config, _digest) = user::config()?;
                 if let Ok(old_user) = config.lookup::<User>("user", user.userid.as_str()) {
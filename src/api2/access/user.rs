@@ -11,9 +11,9 @@ use proxmox_schema::api;
 use proxmox_tfa::api::TfaConfig;
 
 use pbs_api_types::{
-    ApiToken, Authid, Tokenname, User, UserUpdater, UserWithTokens, Userid, ENABLE_USER_SCHEMA,
-    EXPIRE_USER_SCHEMA, PBS_PASSWORD_SCHEMA, PRIV_PERMISSIONS_MODIFY, PRIV_SYS_AUDIT,
-    PROXMOX_CONFIG_DIGEST_SCHEMA, SINGLE_LINE_COMMENT_SCHEMA,
+    ApiToken, Authid, Tokenname, User, UserUpdater, UserWithTokens, Userid, APPEND_ONLY_SCHEMA,
+    ENABLE_USER_SCHEMA, EXPIRE_USER_SCHEMA, PBS_PASSWORD_SCHEMA, PRIV_PERMISSIONS_MODIFY,
+    PRIV_SYS_AUDIT, PROXMOX_CONFIG_DIGEST_SCHEMA, SINGLE_LINE_COMMENT_SCHEMA,
 };
 use pbs_config::token_shadow;
 
@@ -34,13 +34,40 @@ fn new_user_with_tokens(user: User, tfa: &TfaConfig) -> UserWithTokens {
         comment: user.comment,
         enable: user.enable,
         expire: user.expire,
+        append_only: user.append_only,
         firstname: user.firstname,
         lastname: user.lastname,
         email: user.email,
+        lang: user.lang,
         tokens: Vec::new(),
     }
 }
 
+// Self-service may enable the append-only mode, lifting it requires user management privileges.
+fn check_append_only_update(
+    current: bool,
+    append_only: Option<bool>,
+    rpcenv: &dyn RpcEnvironment,
+) -> Result<(), Error> {
+    if !current || append_only != Some(false) {
+        return Ok(());
+    }
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+    user_info
+        .check_privs(
+            &auth_id,
+            &["access", "users"],
+            PRIV_PERMISSIONS_MODIFY,
+            false,
+        )
+        .map_err(|_| {
+            format_err!(
+                "disabling the append-only mode requires Permissions.Modify on /access/users"
+            )
+        })
+}
+
 #[api(
     protected: true,
     input: {
@@ -213,6 +240,8 @@ pub enum DeletableProperty {
     Lastname,
     /// Delete the email property.
     Email,
+    /// Delete the lang property.
+    Lang,
 }
 
 #[api(
@@ -276,6 +305,8 @@ pub async fn update_user(
 
     let mut data: User = config.lookup("user", userid.as_str())?;
 
+    check_append_only_update(data.is_append_only(), update.append_only, rpcenv)?;
+
     if let Some(delete) = delete {
         for delete_prop in delete {
             match delete_prop {
@@ -283,6 +314,7 @@ pub async fn update_user(
                 DeletableProperty::Firstname => data.firstname = None,
                 DeletableProperty::Lastname => data.lastname = None,
                 DeletableProperty::Email => data.email = None,
+                DeletableProperty::Lang => data.lang = None,
             }
         }
     }
@@ -304,6 +336,10 @@ pub async fn update_user(
         data.expire = if expire > 0 { Some(expire) } else { None };
     }
 
+    if let Some(append_only) = update.append_only {
+        data.append_only = if append_only { Some(true) } else { None };
+    }
+
     if let Some(password) = password {
         let user_info = CachedUserInfo::new()?;
         let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
@@ -335,6 +371,9 @@ pub async fn update_user(
     if let Some(email) = update.email {
         data.email = if email.is_empty() { None } else { Some(email) };
     }
+    if update.lang.is_some() {
+        data.lang = update.lang;
+    }
 
     config.set_data(userid.as_str(), "user", &data)?;
 
@@ -456,6 +495,10 @@ pub fn read_token(
                 schema: EXPIRE_USER_SCHEMA,
                 optional: true,
             },
+            "append-only": {
+                schema: APPEND_ONLY_SCHEMA,
+                optional: true,
+            },
             digest: {
                 optional: true,
                 schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
@@ -483,12 +526,14 @@ pub fn read_token(
     },
 )]
 /// Generate a new API token with given metadata
+#[allow(clippy::too_many_arguments)]
 pub fn generate_token(
     userid: Userid,
     token_name: Tokenname,
     comment: Option<String>,
     enable: Option<bool>,
     expire: Option<i64>,
+    append_only: Option<bool>,
     digest: Option<String>,
 ) -> Result<Value, Error> {
     let _lock = pbs_config::user::lock_config()?;
@@ -519,6 +564,7 @@ pub fn generate_token(
         comment,
         enable,
         expire,
+        append_only: append_only.filter(|append_only| *append_only),
     };
 
     config.set_data(&tokenid_string, "token", &token)?;
@@ -553,6 +599,10 @@ pub fn generate_token(
                 schema: EXPIRE_USER_SCHEMA,
                 optional: true,
             },
+            "append-only": {
+                schema: APPEND_ONLY_SCHEMA,
+                optional: true,
+            },
             digest: {
                 optional: true,
                 schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
@@ -567,13 +617,16 @@ pub fn generate_token(
     },
 )]
 /// Update user's API token metadata
+#[allow(clippy::too_many_arguments)]
 pub fn update_token(
     userid: Userid,
     token_name: Tokenname,
     comment: Option<String>,
     enable: Option<bool>,
     expire: Option<i64>,
+    append_only: Option<bool>,
     digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<(), Error> {
     let _lock = pbs_config::user::lock_config()?;
 
@@ -589,6 +642,8 @@ pub fn update_token(
 
     let mut data: ApiToken = config.lookup("token", &tokenid_string)?;
 
+    check_append_only_update(data.is_append_only(), append_only, rpcenv)?;
+
     if let Some(comment) = comment {
         let comment = comment.trim().to_string();
         if comment.is_empty() {
@@ -606,6 +661,10 @@ pub fn update_token(
         data.expire = if expire > 0 { Some(expire) } else { None };
     }
 
+    if let Some(append_only) = append_only {
+        data.append_only = if append_only { Some(true) } else { None };
+    }
+
     config.set_data(&tokenid_string, "token", &data)?;
 
     pbs_config::user::save_config(&config)?;
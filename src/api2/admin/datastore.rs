@@ -3,7 +3,7 @@
 use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
@@ -17,31 +17,37 @@ use tokio_stream::wrappers::ReceiverStream;
 use proxmox_async::blocking::WrappedReaderStream;
 use proxmox_async::{io::AsyncChannelWriter, stream::AsyncReaderStream};
 use proxmox_compression::zstd::ZstdEncoder;
+use proxmox_human_byte::HumanByte;
 use proxmox_router::{
-    http_err, list_subdirs_api_method, ApiHandler, ApiMethod, ApiResponseFuture, Permission,
-    Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
+    http_bail, http_err, list_subdirs_api_method, ApiHandler, ApiMethod, ApiResponseFuture,
+    Permission, Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
 };
 use proxmox_schema::*;
 use proxmox_sortable_macro::sortable;
 use proxmox_sys::fs::{
     file_read_firstline, file_read_optional_string, replace_file, CreateOptions,
 };
-use proxmox_sys::{task_log, task_warn};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 use proxmox_time::CalendarEvent;
 
 use pxar::accessor::aio::Accessor;
 use pxar::EntryKind;
 
 use pbs_api_types::{
-    print_ns_and_snapshot, print_store_and_ns, Authid, BackupContent, BackupNamespace, BackupType,
-    Counts, CryptMode, DataStoreConfig, DataStoreListItem, DataStoreStatus,
-    GarbageCollectionJobStatus, GroupListItem, JobScheduleStatus, KeepOptions, Operation,
-    PruneJobOptions, RRDMode, RRDTimeFrame, SnapshotListItem, SnapshotVerifyState,
-    BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
-    BACKUP_TYPE_SCHEMA, DATASTORE_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA, MAX_NAMESPACE_DEPTH,
-    NS_MAX_DEPTH_SCHEMA, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY,
-    PRIV_DATASTORE_PRUNE, PRIV_DATASTORE_READ, PRIV_DATASTORE_VERIFY, UPID, UPID_SCHEMA,
-    VERIFICATION_OUTDATED_AFTER_SCHEMA,
+    print_ns_and_snapshot, print_store_and_ns, Authid, BackupContent, BackupNamespace, BackupPart,
+    BackupType, BulkAction, BulkActionItemResult, BulkActionResult, CompressionStats, Counts,
+    CryptMode, DataStoreConfig, DataStoreListItem, DataStoreStatus, DedupStats,
+    GarbageCollectionJobStatus, GroupFilter, GroupListItem, HookJobType, JobScheduleStatus,
+    KeepOptions, MaintenanceMode, MaintenanceType, Operation, PruneJobOptions, RRDMode,
+    RRDTimeFrame, SnapshotLineage, SnapshotListItem, SnapshotReplication, SnapshotVerifyState,
+    VerifyAttestationListItem, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA,
+    BACKUP_NAMESPACE_SCHEMA, BACKUP_PART_LIST_SCHEMA, BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA,
+    COMPRESSION_SAMPLE_SCHEMA, DATASTORE_SCHEMA, FIELD_MASK_SCHEMA, GROUP_FILTER_LIST_SCHEMA,
+    IGNORE_VERIFIED_BACKUPS_SCHEMA, MAX_NAMESPACE_DEPTH, NS_MAX_DEPTH_REDUCED_SCHEMA,
+    NS_MAX_DEPTH_SCHEMA, PREFETCH_WINDOW_SCHEMA, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP,
+    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE, PRIV_DATASTORE_READ, PRIV_DATASTORE_VERIFY,
+    PRIV_SYS_MODIFY, SNAPSHOT_TAG_LIST_SCHEMA, UPID, UPID_SCHEMA,
+    VERIFICATION_OUTDATED_AFTER_SCHEMA, VERIFY_ATTEST_SCHEMA,
 };
 use pbs_client::pxar::{create_tar, create_zip};
 use pbs_config::CachedUserInfo;
@@ -52,24 +58,31 @@ use pbs_datastore::data_blob::DataBlob;
 use pbs_datastore::data_blob_reader::DataBlobReader;
 use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicIndexReader, LocalDynamicReadAt};
 use pbs_datastore::fixed_index::FixedIndexReader;
-use pbs_datastore::index::IndexFile;
+use pbs_datastore::index::{IndexAccessPattern, IndexFile};
 use pbs_datastore::manifest::{BackupManifest, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME};
 use pbs_datastore::prune::compute_prune_info;
 use pbs_datastore::{
     check_backup_owner, task_tracking, BackupDir, BackupGroup, DataStore, LocalChunkReader,
     StoreProgress, CATALOG_NAME,
 };
-use pbs_tools::json::required_string_param;
+use pbs_tools::json::{required_integer_param, required_string_param};
 use proxmox_rest_server::{formatter, WorkerTask};
 
 use crate::api2::backup::optional_ns_param;
 use crate::api2::node::rrd::create_value_from_rrd;
 use crate::backup::{
-    check_ns_privs_full, verify_all_backups, verify_backup_dir, verify_backup_group, verify_filter,
-    ListAccessibleBackupGroups, NS_PRIVS_OK,
+    check_not_append_only, check_ns_privs_full, check_user_not_append_only,
+    check_user_ns_privs_full, verify_all_backups, verify_backup_dir, verify_backup_group,
+    verify_filter, ListAccessibleBackupGroups, NS_PRIVS_OK,
 };
 
-use crate::server::jobstate::{compute_schedule_status, Job, JobState};
+use crate::server::hooks::{with_hooks, HookContext};
+use crate::server::jobstate::{compute_schedule_status, splay_delay, Job, JobState};
+use crate::server::log_forward::forward_task_result;
+use crate::server::pull::{pull_store, remove_copied_source_snapshots, PullParameters};
+use crate::tools::compression::{encode_body, encoded_api_response, ContentEncoding};
+use crate::tools::disks::{mount_datastore_device, unmount_datastore_device};
+use crate::tools::serde_filter::filter_fields;
 
 const GROUP_NOTES_FILE_NAME: &str = "notes";
 
@@ -296,6 +309,8 @@ pub async fn delete_group(
             &group,
         )?;
 
+        check_not_append_only(&auth_id)?;
+
         let delete_stats = datastore.remove_backup_group(&ns, &group)?;
         if !delete_stats.all_removed() {
             bail!("group only partially deleted due to protected snapshots");
@@ -404,6 +419,8 @@ pub async fn delete_snapshot(
             &backup_dir.group,
         )?;
 
+        check_not_append_only(&auth_id)?;
+
         let snapshot = datastore.backup_dir(ns, backup_dir)?;
 
         snapshot.destroy(false)?;
@@ -430,6 +447,14 @@ pub async fn delete_snapshot(
                 optional: true,
                 schema: BACKUP_ID_SCHEMA,
             },
+            tags: {
+                optional: true,
+                schema: SNAPSHOT_TAG_LIST_SCHEMA,
+            },
+            fields: {
+                optional: true,
+                schema: FIELD_MASK_SCHEMA,
+            },
         },
     },
     returns: pbs_api_types::ADMIN_DATASTORE_LIST_SNAPSHOTS_RETURN_TYPE,
@@ -440,30 +465,53 @@ pub async fn delete_snapshot(
     },
 )]
 /// List backup snapshots.
+///
+/// If tags are given, only snapshots which have all of them are listed.
 pub async fn list_snapshots(
     store: String,
     ns: Option<BackupNamespace>,
     backup_type: Option<BackupType>,
     backup_id: Option<String>,
-    _param: Value,
+    tags: Option<Vec<String>>,
+    param: Value,
     _info: &ApiMethod,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<Vec<SnapshotListItem>, Error> {
+) -> Result<Value, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
-    tokio::task::spawn_blocking(move || unsafe {
-        list_snapshots_blocking(store, ns, backup_type, backup_id, auth_id)
+    let list = tokio::task::spawn_blocking(move || unsafe {
+        list_snapshots_blocking(store, ns, backup_type, backup_id, tags, auth_id)
     })
     .await
-    .map_err(|err| format_err!("failed to await blocking task: {err}"))?
+    .map_err(|err| format_err!("failed to await blocking task: {err}"))??;
+
+    filter_fields(list, param["fields"].as_str())
+}
+
+fn encoded_list_snapshots(
+    parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move { encoded_api_response(&parts, param, &API_METHOD_LIST_SNAPSHOTS, rpcenv).await }
+        .boxed()
 }
 
+/// Like [`API_METHOD_LIST_SNAPSHOTS`], compressed with zstd or gzip if the client accepts it.
+pub const API_METHOD_LIST_SNAPSHOTS_ENCODED: ApiMethod = ApiMethod {
+    handler: &ApiHandler::AsyncHttp(&encoded_list_snapshots),
+    ..API_METHOD_LIST_SNAPSHOTS
+};
+
 /// This must not run in a main worker thread as it potentially does tons of I/O.
 unsafe fn list_snapshots_blocking(
     store: String,
     ns: Option<BackupNamespace>,
     backup_type: Option<BackupType>,
     backup_id: Option<String>,
+    tags: Option<Vec<String>>,
     auth_id: Authid,
 ) -> Result<Vec<SnapshotListItem>, Error> {
     let ns = ns.unwrap_or_default();
@@ -535,6 +583,16 @@ unsafe fn list_snapshots_blocking(
 
                 let size = Some(files.iter().map(|x| x.size.unwrap_or(0)).sum());
 
+                let tags: Option<Vec<String>> =
+                    serde_json::from_value(manifest.unprotected["tags"].clone()).unwrap_or(None);
+
+                let lineage: Option<SnapshotLineage> =
+                    serde_json::from_value(manifest.unprotected["lineage"].clone()).unwrap_or(None);
+
+                let replication: Option<SnapshotReplication> =
+                    serde_json::from_value(manifest.unprotected["replication"].clone())
+                        .unwrap_or(None);
+
                 SnapshotListItem {
                     backup,
                     comment,
@@ -544,6 +602,9 @@ unsafe fn list_snapshots_blocking(
                     size,
                     owner,
                     protected,
+                    tags,
+                    lineage,
+                    replication,
                 }
             }
             Err(err) => {
@@ -567,6 +628,9 @@ unsafe fn list_snapshots_blocking(
                     size: None,
                     owner,
                     protected,
+                    tags: None,
+                    lineage: None,
+                    replication: None,
                 }
             }
         }
@@ -595,7 +659,12 @@ unsafe fn list_snapshots_blocking(
         snapshots.extend(
             group_backups
                 .into_iter()
-                .map(|info| info_to_snapshot_list_item(group, Some(owner.clone()), info)),
+                .map(|info| info_to_snapshot_list_item(group, Some(owner.clone()), info))
+                .filter(|item| match (&tags, &item.tags) {
+                    (None, _) => true,
+                    (Some(wanted), Some(have)) => wanted.iter().all(|tag| have.contains(tag)),
+                    (Some(wanted), None) => wanted.is_empty(),
+                }),
         );
 
         Ok(snapshots)
@@ -720,6 +789,7 @@ pub async fn status(
             avail: storage.available,
             gc_status,
             counts,
+            chunk_cache: datastore.chunk_cache_stats(),
         }
     } else {
         DataStoreStatus {
@@ -728,6 +798,7 @@ pub async fn status(
             avail: 0,
             gc_status,
             counts,
+            chunk_cache: None,
         }
     })
 }
@@ -766,6 +837,10 @@ pub async fn status(
                 schema: NS_MAX_DEPTH_SCHEMA,
                 optional: true,
             },
+            attest: {
+                schema: VERIFY_ATTEST_SCHEMA,
+                optional: true,
+            },
         },
     },
     returns: {
@@ -791,6 +866,7 @@ pub fn verify(
     ignore_verified: Option<bool>,
     outdated_after: Option<i64>,
     max_depth: Option<usize>,
+    attest: bool,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
@@ -870,7 +946,10 @@ pub fn verify(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
-            let verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
+            let mut verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
+            if attest {
+                verify_worker = verify_worker.with_attestation();
+            }
             let failed_dirs = if let Some(backup_dir) = backup_dir {
                 let mut res = Vec::new();
                 if !verify_backup_dir(
@@ -909,6 +988,10 @@ pub fn verify(
                     Some(&move |manifest| verify_filter(ignore_verified, outdated_after, manifest)),
                 )?
             };
+            if attest {
+                verify_worker.attest(worker.upid())?;
+                task_log!(worker, "stored signed verification report");
+            }
             if !failed_dirs.is_empty() {
                 task_log!(worker, "Failed to verify the following snapshots/groups:");
                 for dir in failed_dirs {
@@ -923,6 +1006,60 @@ pub fn verify(
     Ok(json!(upid_str))
 }
 
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "List of signed verification reports.",
+        type: Array,
+        items: {
+            type: VerifyAttestationListItem,
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, false),
+    },
+)]
+/// List the signed verification reports of a datastore.
+pub fn list_attestations(store: String) -> Result<Vec<VerifyAttestationListItem>, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    crate::backup::list_attestations(&datastore)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            upid: {
+                schema: UPID_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "The report as signed JSON string, its signature and the fingerprint of \
+            the signing server certificate.",
+        type: Object,
+        properties: {},
+        additional_properties: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, false),
+    },
+)]
+/// Get the signed verification report of a verify task.
+pub fn get_attestation(store: String, upid: String) -> Result<Value, Error> {
+    let upid: UPID = upid.parse()?;
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    crate::backup::read_attestation(&datastore, &upid)
+}
+
 #[api(
     input: {
         properties: {
@@ -984,6 +1121,10 @@ pub fn prune(
         &group,
     )?;
 
+    if !dry_run {
+        check_not_append_only(&auth_id)?;
+    }
+
     let worker_id = format!("{}:{}:{}", store, ns, group);
     let group = datastore.backup_group(ns.clone(), group);
 
@@ -1151,6 +1292,10 @@ pub fn prune_datastore(
         true,
     )?;
 
+    if !dry_run {
+        check_not_append_only(&auth_id)?;
+    }
+
     let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
     let ns = prune_options.ns.clone().unwrap_or_default();
     let worker_id = format!("{}:{}", store, ns);
@@ -1163,7 +1308,35 @@ pub fn prune_datastore(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
-            crate::server::prune_datastore(worker, auth_id, prune_options, datastore, dry_run)
+            if dry_run {
+                return crate::server::prune_datastore(
+                    worker,
+                    auth_id,
+                    prune_options,
+                    datastore,
+                    dry_run,
+                );
+            }
+
+            let hook_ctx = HookContext::new(
+                HookJobType::Prune,
+                &store,
+                worker.upid().to_string(),
+                json!({ "ns": ns }),
+            );
+            let result = with_hooks(&*worker, &hook_ctx, || {
+                crate::server::prune_datastore(
+                    worker.clone(),
+                    auth_id,
+                    prune_options,
+                    datastore,
+                    dry_run,
+                )
+            });
+            if !dry_run {
+                forward_task_result(&hook_ctx, &result, None);
+            }
+            result
         },
     )?;
 
@@ -1283,7 +1456,8 @@ pub fn garbage_collection_status(
                 .map_err(|err| log::error!("{err}"))
                 .ok()
         })
-        .and_then(|ne| ne);
+        .and_then(|ne| ne)
+        .map(|next| next + splay_delay("garbage_collection", &store, next, store_config.gc_splay));
 
     info.status = status_in_memory;
 
@@ -1438,6 +1612,7 @@ pub const API_METHOD_DOWNLOAD_FILE_DECODED: ApiMethod = ApiMethod::new(
             ("backup-id", false, &BACKUP_ID_SCHEMA),
             ("backup-time", false, &BACKUP_TIME_SCHEMA),
             ("file-name", false, &BACKUP_ARCHIVE_NAME_SCHEMA),
+            ("prefetch-window", true, &PREFETCH_WINDOW_SCHEMA),
         ]),
     ),
 )
@@ -1450,7 +1625,7 @@ pub const API_METHOD_DOWNLOAD_FILE_DECODED: ApiMethod = ApiMethod::new(
 );
 
 pub fn download_file_decoded(
-    _parts: Parts,
+    parts: Parts,
     _req_body: Body,
     param: Value,
     _info: &ApiMethod,
@@ -1473,6 +1648,7 @@ pub fn download_file_decoded(
         )?;
 
         let file_name = required_string_param(&param, "file-name")?.to_owned();
+        let prefetch_window = param["prefetch-window"].as_u64().unwrap_or(0) as usize;
         let backup_dir = datastore.backup_dir(backup_ns.clone(), backup_dir_api.clone())?;
 
         let (manifest, files) = read_backup_index(&backup_dir)?;
@@ -1496,6 +1672,8 @@ pub fn download_file_decoded(
 
         let (_, extension) = file_name.rsplit_once('.').unwrap();
 
+        let encoding = ContentEncoding::from_headers(&parts.headers);
+
         let body = match extension {
             "didx" => {
                 let index = DynamicIndexReader::open(&path).map_err(|err| {
@@ -1506,10 +1684,13 @@ pub fn download_file_decoded(
 
                 let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
                 let reader = CachedChunkReader::new(chunk_reader, index, 1).seekable();
-                Body::wrap_stream(AsyncReaderStream::new(reader).map_err(move |err| {
-                    eprintln!("error during streaming of '{:?}' - {}", path, err);
-                    err
-                }))
+                encode_body(
+                    Box::pin(AsyncReaderStream::new(reader).map_err(move |err| {
+                        eprintln!("error during streaming of '{:?}' - {}", path, err);
+                        err
+                    })),
+                    encoding,
+                )?
             }
             "fidx" => {
                 let index = FixedIndexReader::open(&path).map_err(|err| {
@@ -1519,16 +1700,20 @@ pub fn download_file_decoded(
                 let (csum, size) = index.compute_csum();
                 manifest.verify_file(&file_name, &csum, size)?;
 
-                let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
+                let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None)
+                    .with_prefetch_window(prefetch_window);
                 let reader = CachedChunkReader::new(chunk_reader, index, 1).seekable();
-                Body::wrap_stream(
-                    AsyncReaderStream::with_buffer_size(reader, 4 * 1024 * 1024).map_err(
-                        move |err| {
-                            eprintln!("error during streaming of '{:?}' - {}", path, err);
-                            err
-                        },
+                encode_body(
+                    Box::pin(
+                        AsyncReaderStream::with_buffer_size(reader, 4 * 1024 * 1024).map_err(
+                            move |err| {
+                                eprintln!("error during streaming of '{:?}' - {}", path, err);
+                                err
+                            },
+                        ),
                     ),
-                )
+                    encoding,
+                )?
             }
             "blob" => {
                 let file = std::fs::File::open(&path)
@@ -1536,14 +1721,17 @@ pub fn download_file_decoded(
 
                 // FIXME: load full blob to verify index checksum?
 
-                Body::wrap_stream(
-                    WrappedReaderStream::new(DataBlobReader::new(file, None)?).map_err(
-                        move |err| {
-                            eprintln!("error during streaming of '{:?}' - {}", path, err);
-                            err
-                        },
+                encode_body(
+                    Box::pin(
+                        WrappedReaderStream::new(DataBlobReader::new(file, None)?).map_err(
+                            move |err| {
+                                eprintln!("error during streaming of '{:?}' - {}", path, err);
+                                err
+                            },
+                        ),
                     ),
-                )
+                    encoding,
+                )?
             }
             extension => {
                 bail!("cannot download '{}' files", extension);
@@ -1551,11 +1739,15 @@ pub fn download_file_decoded(
         };
 
         // fixme: set other headers ?
-        Ok(Response::builder()
+        let mut response = Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, "application/octet-stream")
             .body(body)
-            .unwrap())
+            .unwrap();
+        if let Some(encoding) = encoding {
+            encoding.add_headers(response.headers_mut());
+        }
+        Ok(response)
     }
     .boxed()
 }
@@ -1637,6 +1829,81 @@ pub fn upload_backup_log(
     .boxed()
 }
 
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_dir: {
+                type: pbs_api_types::BackupDir,
+                flatten: true,
+            },
+        },
+    },
+    returns: {
+        description: "The client log. Logs uploaded with the backup also contain the \
+            statistics of the run and the skipped files, other logs only the 'log' lines.",
+        type: Object,
+        properties: {},
+        additional_properties: true,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT for any \
+            or DATASTORE_BACKUP and being the owner of the group",
+    },
+)]
+/// Get the client log of a snapshot ('client.log.blob').
+pub fn get_client_log(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_dir: pbs_api_types::BackupDir,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT,
+        PRIV_DATASTORE_BACKUP,
+        Some(Operation::Read),
+        &backup_dir.group,
+    )?;
+
+    let backup_dir = datastore.backup_dir(ns, backup_dir)?;
+
+    let mut path = backup_dir.full_path();
+    path.push(CLIENT_LOG_BLOB_NAME);
+
+    let mut file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            bail!("snapshot {} has no client log", backup_dir.dir());
+        }
+        Err(err) => bail!("unable to open client log - {err}"),
+    };
+    let blob = DataBlob::load_from_reader(&mut file)?;
+    if blob.crypt_mode()? == CryptMode::Encrypt {
+        bail!("the client log is encrypted");
+    }
+    let data = blob.decode(None, None)?;
+
+    // logs uploaded together with the backup are JSON, others plain text
+    match serde_json::from_slice::<Value>(&data) {
+        Ok(log) if log["log"].is_array() => Ok(log),
+        _ => {
+            let text = String::from_utf8_lossy(&data);
+            Ok(json!({ "log": text.lines().collect::<Vec<_>>() }))
+        }
+    }
+}
+
 #[api(
     input: {
         properties: {
@@ -1704,6 +1971,7 @@ pub async fn catalog(
 
         let (csum, size) = index.compute_csum();
         manifest.verify_file(file_name, &csum, size)?;
+        index.advise(IndexAccessPattern::Random);
 
         let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
         let reader = BufferedDynamicReader::new(index, chunk_reader);
@@ -1721,6 +1989,22 @@ pub async fn catalog(
     .await?
 }
 
+fn encoded_catalog(
+    parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move { encoded_api_response(&parts, param, &API_METHOD_CATALOG, rpcenv).await }.boxed()
+}
+
+/// Like [`API_METHOD_CATALOG`], compressed with zstd or gzip if the client accepts it.
+pub const API_METHOD_CATALOG_ENCODED: ApiMethod = ApiMethod {
+    handler: &ApiHandler::AsyncHttp(&encoded_catalog),
+    ..API_METHOD_CATALOG
+};
+
 #[sortable]
 pub const API_METHOD_PXAR_FILE_DOWNLOAD: ApiMethod = ApiMethod::new(
     &ApiHandler::AsyncHttp(&pxar_file_download),
@@ -1745,7 +2029,7 @@ pub const API_METHOD_PXAR_FILE_DOWNLOAD: ApiMethod = ApiMethod::new(
 );
 
 pub fn pxar_file_download(
-    _parts: Parts,
+    parts: Parts,
     _req_body: Body,
     param: Value,
     _info: &ApiMethod,
@@ -1797,6 +2081,7 @@ pub fn pxar_file_download(
 
         let (csum, size) = index.compute_csum();
         manifest.verify_file(pxar_name, &csum, size)?;
+        index.advise(IndexAccessPattern::Random);
 
         let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
         let reader = BufferedDynamicReader::new(index, chunk_reader);
@@ -1811,21 +2096,31 @@ pub fn pxar_file_download(
             .await?
             .ok_or_else(|| format_err!("error opening '{:?}'", path))?;
 
+        // zip and tar.zst archives are already compressed, only encode plain files
+        let mut encoding = ContentEncoding::from_headers(&parts.headers);
+
         let body = match file.kind() {
-            EntryKind::File { .. } => Body::wrap_stream(
-                AsyncReaderStream::new(file.contents().await?).map_err(move |err| {
-                    eprintln!("error during streaming of file '{:?}' - {}", filepath, err);
-                    err
-                }),
-            ),
-            EntryKind::Hardlink(_) => Body::wrap_stream(
-                AsyncReaderStream::new(decoder.follow_hardlink(&file).await?.contents().await?)
-                    .map_err(move |err| {
-                        eprintln!("error during streaming of hardlink '{:?}' - {}", path, err);
+            EntryKind::File { .. } => encode_body(
+                Box::pin(
+                    AsyncReaderStream::new(file.contents().await?).map_err(move |err| {
+                        eprintln!("error during streaming of file '{:?}' - {}", filepath, err);
                         err
                     }),
-            ),
+                ),
+                encoding,
+            )?,
+            EntryKind::Hardlink(_) => encode_body(
+                Box::pin(
+                    AsyncReaderStream::new(decoder.follow_hardlink(&file).await?.contents().await?)
+                        .map_err(move |err| {
+                            eprintln!("error during streaming of hardlink '{:?}' - {}", path, err);
+                            err
+                        }),
+                ),
+                encoding,
+            )?,
             EntryKind::Directory => {
+                encoding = None;
                 let (sender, receiver) = tokio::sync::mpsc::channel::<Result<_, Error>>(100);
                 let channelwriter = AsyncChannelWriter::new(sender, 1024 * 1024);
                 if tar {
@@ -1855,11 +2150,199 @@ pub fn pxar_file_download(
         };
 
         // fixme: set other headers ?
-        Ok(Response::builder()
+        let mut response = Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, "application/octet-stream")
             .body(body)
-            .unwrap())
+            .unwrap();
+        if let Some(encoding) = encoding {
+            encoding.add_headers(response.headers_mut());
+        }
+        Ok(response)
+    }
+    .boxed()
+}
+
+const ARCHIVE_FORMAT_SCHEMA: Schema = StringSchema::new("Format of the downloaded archive.")
+    .format(&ApiStringFormat::Enum(&[
+        EnumEntry::new("pxar", "Proxmox File Archive"),
+        EnumEntry::new("tar", "Zstd compressed tar archive"),
+    ]))
+    .default("pxar")
+    .schema();
+
+#[sortable]
+pub const API_METHOD_DOWNLOAD_ARCHIVE_AT: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&download_archive_at),
+    &ObjectSchema::new(
+        "Download a pxar archive of a backup group as it was at a point in time, from the \
+        newest snapshot finished by then. Only works if it's not encrypted.",
+        &sorted!([
+            ("store", false, &DATASTORE_SCHEMA),
+            ("ns", true, &BACKUP_NAMESPACE_SCHEMA),
+            ("backup-type", false, &BACKUP_TYPE_SCHEMA),
+            ("backup-id", false, &BACKUP_ID_SCHEMA),
+            (
+                "time",
+                false,
+                &IntegerSchema::new("Point in time (Unix epoch).").schema()
+            ),
+            ("archive-name", false, &BACKUP_ARCHIVE_NAME_SCHEMA),
+            ("format", true, &ARCHIVE_FORMAT_SCHEMA),
+        ]),
+    ),
+)
+.access(
+    Some(
+        "Requires on /datastore/{store}[/{namespace}] either DATASTORE_READ for any or \
+        DATASTORE_BACKUP and being the owner of the group",
+    ),
+    &Permission::Anybody,
+);
+
+/// Returns the newest of `snapshots` which was finished at `time`.
+///
+/// `snapshots` are the finished snapshots of a group with their start time. Snapshots of a group
+/// never overlap, so the newest one finished by then is the newest one started by then whose
+/// finish time, as returned by `finish_time`, is not after `time`.
+fn snapshot_finished_at<T>(
+    mut snapshots: Vec<(T, i64)>,
+    time: i64,
+    finish_time: impl Fn(&T) -> i64,
+) -> Option<T> {
+    snapshots.retain(|(_, backup_time)| *backup_time <= time);
+    snapshots.sort_unstable_by_key(|(_, backup_time)| std::cmp::Reverse(*backup_time));
+    snapshots
+        .into_iter()
+        .map(|(snapshot, _)| snapshot)
+        .find(|snapshot| finish_time(snapshot) <= time)
+}
+
+// the finish time recorded in the manifest, or the manifest's modification time for older
+// snapshots, which is never before the actual finish time
+fn snapshot_finish_time(backup_dir: &BackupDir) -> i64 {
+    if let Ok((manifest, _)) = backup_dir.load_manifest() {
+        if let Some(finish_time) = manifest.unprotected["finish_time"].as_i64() {
+            return finish_time;
+        }
+    }
+
+    let mut path = backup_dir.full_path();
+    path.push(MANIFEST_BLOB_NAME);
+    std::fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(i64::MAX)
+}
+
+/// Streams a pxar archive from the newest snapshot of a group which was finished at `time`.
+///
+/// Every snapshot references all of its data, so this includes files removed in later snapshots,
+/// as long as the snapshot itself was not pruned. The archive itself is sent by the regular
+/// download handlers, [download_file_decoded] or [pxar_file_download] for a tar archive.
+pub fn download_archive_at(
+    parts: Parts,
+    req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+        let store = required_string_param(&param, "store")?;
+        let ns = optional_ns_param(&param)?;
+
+        let group: pbs_api_types::BackupGroup = Deserialize::deserialize(&param)?;
+        let datastore = check_privs_and_load_store(
+            store,
+            &ns,
+            &auth_id,
+            PRIV_DATASTORE_READ,
+            PRIV_DATASTORE_BACKUP,
+            Some(Operation::Read),
+            &group,
+        )?;
+
+        let time = required_integer_param(&param, "time")?;
+        let archive_name = required_string_param(&param, "archive-name")?.to_owned();
+        let tar = param["format"].as_str() == Some("tar");
+
+        if !archive_name.ends_with(".pxar.didx") {
+            bail!("cannot assemble '{archive_name}' - not a pxar archive");
+        }
+
+        let backup_group = datastore.backup_group(ns.clone(), group);
+        let snapshots = backup_group
+            .list_backups()?
+            .into_iter()
+            .filter(|info| info.is_finished())
+            .map(|info| {
+                let backup_time = info.backup_dir.backup_time();
+                (info.backup_dir, backup_time)
+            })
+            .collect();
+        let backup_dir =
+            snapshot_finished_at(snapshots, time, snapshot_finish_time).ok_or_else(|| {
+                http_err!(
+                    NOT_FOUND,
+                    "no snapshot of group {} finished at {}",
+                    backup_group.group(),
+                    time
+                )
+            })?;
+
+        let (_manifest, files) = read_backup_index(&backup_dir)?;
+        if !files.iter().any(|file| file.filename == archive_name) {
+            http_bail!(
+                NOT_FOUND,
+                "snapshot {} has no archive '{}'",
+                backup_dir.dir(),
+                archive_name
+            );
+        }
+
+        println!(
+            "Download {} at {} from {} ({})",
+            archive_name,
+            time,
+            print_store_and_ns(store, &ns),
+            backup_dir.dir(),
+        );
+
+        let mut param = json!({
+            "store": store,
+            "backup-type": backup_dir.backup_type(),
+            "backup-id": backup_dir.backup_id(),
+            "backup-time": backup_dir.backup_time(),
+        });
+        if !ns.is_root() {
+            param["ns"] = ns.to_string().into();
+        }
+
+        if tar {
+            param["filepath"] = base64::encode(&archive_name).into();
+            param["tar"] = true.into();
+            pxar_file_download(
+                parts,
+                req_body,
+                param,
+                &API_METHOD_PXAR_FILE_DOWNLOAD,
+                rpcenv,
+            )
+            .await
+        } else {
+            param["file-name"] = archive_name.into();
+            download_file_decoded(
+                parts,
+                req_body,
+                param,
+                &API_METHOD_DOWNLOAD_FILE_DECODED,
+                rpcenv,
+            )
+            .await
+        }
     }
     .boxed()
 }
@@ -2220,6 +2703,10 @@ pub async fn set_protection(
             &backup_dir.group,
         )?;
 
+        if !protected {
+            check_not_append_only(&auth_id)?;
+        }
+
         let backup_dir = datastore.backup_dir(ns, backup_dir)?;
 
         datastore.update_protection(&backup_dir, protected)
@@ -2227,107 +2714,938 @@ pub async fn set_protection(
     .await?
 }
 
-#[api(
-    input: {
-        properties: {
-            store: { schema: DATASTORE_SCHEMA },
-            ns: {
-                type: BackupNamespace,
-                optional: true,
-            },
-            backup_group: {
-                type: pbs_api_types::BackupGroup,
-                flatten: true,
-            },
-            "new-owner": {
-                type: Authid,
-            },
-        },
-    },
+/// Check the privileges for applying `action` to items in `ns`.
+///
+/// Returns whether the user must own the group of each item, because only the partial access
+/// privileges are fulfilled.
+fn check_bulk_action_access(
+    user_info: &CachedUserInfo,
+    store: &str,
+    ns: &BackupNamespace,
+    auth_id: &Authid,
+    action: BulkAction,
+) -> Result<bool, Error> {
+    let (full_access_privs, partial_access_privs) = match action {
+        BulkAction::Delete => (PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE),
+        BulkAction::Protect | BulkAction::Unprotect => {
+            (PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_BACKUP)
+        }
+        BulkAction::Verify => (PRIV_DATASTORE_VERIFY, PRIV_DATASTORE_BACKUP),
+    };
+
+    let owner_check_required = check_user_ns_privs_full(
+        user_info,
+        store,
+        ns,
+        auth_id,
+        full_access_privs,
+        partial_access_privs,
+    )?;
+
+    if matches!(action, BulkAction::Delete | BulkAction::Unprotect) {
+        check_user_not_append_only(user_info, auth_id)?;
+    }
+
+    Ok(owner_check_required)
+}
+
+/// Apply a bulk action to a single backup group or snapshot.
+///
+/// `owner` is set if the user may only modify groups it owns.
+fn bulk_action_item(
+    worker: &WorkerTask,
+    verify_worker: Option<&crate::backup::VerifyWorker>,
+    datastore: &Arc<DataStore>,
+    ns: &BackupNamespace,
+    action: BulkAction,
+    part: BackupPart,
+    owner: Option<&Authid>,
+) -> Result<(), Error> {
+    if let Some(auth_id) = owner {
+        let group = match &part {
+            BackupPart::Group(group) => group,
+            BackupPart::Dir(dir) => &dir.group,
+        };
+        let group_owner = datastore.get_owner(ns, group)?;
+        check_backup_owner(&group_owner, auth_id)?;
+    }
+
+    match (action, part) {
+        (BulkAction::Delete, BackupPart::Group(group)) => {
+            let delete_stats = datastore.remove_backup_group(ns, &group)?;
+            if !delete_stats.all_removed() {
+                bail!("group only partially deleted due to protected snapshots");
+            }
+        }
+        (BulkAction::Delete, BackupPart::Dir(dir)) => {
+            datastore.backup_dir(ns.clone(), dir)?.destroy(false)?;
+        }
+        (BulkAction::Protect | BulkAction::Unprotect, part) => {
+            let snapshots = match part {
+                BackupPart::Group(group) => datastore
+                    .backup_group(ns.clone(), group)
+                    .list_backups()?
+                    .into_iter()
+                    .map(|info| info.backup_dir)
+                    .collect(),
+                BackupPart::Dir(dir) => vec![datastore.backup_dir(ns.clone(), dir)?],
+            };
+            for snapshot in snapshots {
+                datastore.update_protection(&snapshot, action == BulkAction::Protect)?;
+            }
+        }
+        (BulkAction::Verify, part) => {
+            // always set for verify actions
+            let verify_worker = verify_worker.unwrap();
+            match part {
+                BackupPart::Group(group) => {
+                    let group = datastore.backup_group(ns.clone(), group);
+                    let failed_dirs = verify_backup_group(
+                        verify_worker,
+                        &group,
+                        &mut StoreProgress::new(1),
+                        worker.upid(),
+                        None,
+                    )?;
+                    if !failed_dirs.is_empty() {
+                        bail!("verification failed for {}", failed_dirs.join(", "));
+                    }
+                }
+                BackupPart::Dir(dir) => {
+                    let snapshot = datastore.backup_dir(ns.clone(), dir)?;
+                    if !verify_backup_dir(verify_worker, &snapshot, worker.upid().clone(), None)? {
+                        bail!("verification failed");
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            action: {
+                type: BulkAction,
+            },
+            items: {
+                schema: BACKUP_PART_LIST_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] the privileges of the single \
+            action for every item: DATASTORE_MODIFY (or DATASTORE_PRUNE and being the owner) to \
+            delete, DATASTORE_MODIFY (or DATASTORE_BACKUP and being the owner) to change the \
+            protection and DATASTORE_VERIFY (or DATASTORE_BACKUP and being the owner) to verify.",
+    },
+)]
+/// Delete, protect, unprotect or verify a list of backup groups and snapshots.
+///
+/// All items are processed in a single task, one after the other. The result of each item is
+/// logged and saved for [`API_METHOD_GET_BULK_ACTION_RESULT`], and the task fails if any item
+/// failed, after processing all others.
+pub fn bulk_action(
+    store: String,
+    ns: Option<BackupNamespace>,
+    action: BulkAction,
+    items: Vec<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let owner_check_required =
+        check_bulk_action_access(&CachedUserInfo::new()?, &store, &ns, &auth_id, action)?;
+
+    let operation = match action {
+        BulkAction::Verify => Operation::Read,
+        _ => Operation::Write,
+    };
+
+    // reject invalid items before starting to modify anything
+    for item in items.iter() {
+        item.parse::<BackupPart>()?;
+    }
+
+    let datastore = DataStore::lookup_datastore(&store, Some(operation))?;
+
+    let worker_id = if ns.is_root() {
+        store
+    } else {
+        format!("{}:{}", store, ns.display_as_path())
+    };
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        &format!("bulk-{action}"),
+        Some(worker_id),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let verify_worker = (action == BulkAction::Verify)
+                .then(|| crate::backup::VerifyWorker::new(worker.clone(), datastore.clone()));
+            let owner = owner_check_required.then_some(&auth_id);
+
+            task_log!(worker, "{action}: {} items", items.len());
+
+            let mut result = BulkActionResult {
+                upid: worker.upid().to_string(),
+                action,
+                items: Vec::with_capacity(items.len()),
+            };
+            let mut failed = 0;
+            for item in items.iter() {
+                if let Err(err) = worker.check_abort() {
+                    if let Err(save_err) = datastore.save_bulk_action_result(result) {
+                        task_warn!(worker, "unable to save results - {save_err}");
+                    }
+                    return Err(err);
+                }
+
+                let part = item.parse::<BackupPart>()?;
+                let error = match bulk_action_item(
+                    &worker,
+                    verify_worker.as_ref(),
+                    &datastore,
+                    &ns,
+                    action,
+                    part,
+                    owner,
+                ) {
+                    Ok(()) => {
+                        task_log!(worker, "{item}: ok");
+                        None
+                    }
+                    Err(err) => {
+                        task_warn!(worker, "{item}: failed - {err}");
+                        failed += 1;
+                        Some(err.to_string())
+                    }
+                };
+                result.items.push(BulkActionItemResult {
+                    item: item.clone(),
+                    error,
+                });
+            }
+
+            if let Err(err) = datastore.save_bulk_action_result(result) {
+                task_warn!(worker, "unable to save results - {err}");
+            }
+
+            if failed > 0 {
+                bail!("{action} failed for {failed} of {} items", items.len());
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(json!(upid_str))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            upid: { schema: UPID_SCHEMA },
+        },
+    },
+    returns: {
+        type: BulkActionResult,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Only the user who started the task, or the user owning the API token \
+            which started it, can read the results.",
+    },
+)]
+/// Get the per item results of a bulk action task.
+///
+/// Returns nothing if the task is still running, or if its results are no longer available, as
+/// only the results of the latest tasks are kept.
+pub fn get_bulk_action_result(
+    store: String,
+    upid: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<BulkActionResult>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let task: UPID = upid.parse()?;
+    let task_auth_id: Authid = task.auth_id.parse()?;
+
+    if check_backup_owner(&task_auth_id, &auth_id).is_err() {
+        http_bail!(FORBIDDEN, "task was started by another user");
+    }
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Lookup))?;
+    datastore.bulk_action_result(&upid)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
+            },
+            "new-owner": {
+                type: Authid,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Datastore.Modify on whole datastore, or changing ownership between user and \
+            a user's token for owned backups with Datastore.Backup"
+    },
+)]
+/// Change owner of a backup group
+pub async fn set_backup_owner(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_group: pbs_api_types::BackupGroup,
+    new_owner: Authid,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    tokio::task::spawn_blocking(move || {
+        let ns = ns.unwrap_or_default();
+        let owner_check_required = check_ns_privs_full(
+            &store,
+            &ns,
+            &auth_id,
+            PRIV_DATASTORE_MODIFY,
+            PRIV_DATASTORE_BACKUP,
+        )?;
+
+        check_not_append_only(&auth_id)?;
+
+        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+        let backup_group = datastore.backup_group(ns, backup_group);
+
+        if owner_check_required {
+            let owner = backup_group.get_owner()?;
+
+            let allowed = match (owner.is_token(), new_owner.is_token()) {
+                (true, true) => {
+                    // API token to API token, owned by same user
+                    let owner = owner.user();
+                    let new_owner = new_owner.user();
+                    owner == new_owner && Authid::from(owner.clone()) == auth_id
+                }
+                (true, false) => {
+                    // API token to API token owner
+                    Authid::from(owner.user().clone()) == auth_id && new_owner == auth_id
+                }
+                (false, true) => {
+                    // API token owner to API token
+                    owner == auth_id && Authid::from(new_owner.user().clone()) == auth_id
+                }
+                (false, false) => {
+                    // User to User, not allowed for unprivileged users
+                    false
+                }
+            };
+
+            if !allowed {
+                return Err(http_err!(
+                    UNAUTHORIZED,
+                    "{} does not have permission to change owner of backup group '{}' to {}",
+                    auth_id,
+                    backup_group.group(),
+                    new_owner,
+                ));
+            }
+        }
+
+        let user_info = CachedUserInfo::new()?;
+
+        if !user_info.is_active_auth_id(&new_owner) {
+            bail!(
+                "{} '{}' is inactive or non-existent",
+                if new_owner.is_token() {
+                    "API token".to_string()
+                } else {
+                    "user".to_string()
+                },
+                new_owner
+            );
+        }
+
+        backup_group.set_owner(&new_owner, true)?;
+
+        Ok(())
+    })
+    .await?
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "source-store": {
+                schema: DATASTORE_SCHEMA,
+            },
+            "source-ns": {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_REDUCED_SCHEMA,
+                optional: true,
+            },
+            "group-filter": {
+                schema: GROUP_FILTER_LIST_SCHEMA,
+                optional: true,
+            },
+            "link-chunks": {
+                description: "Hard link chunks into the target datastore instead of copying them, if both datastores are on the same file system.",
+                type: bool,
+                optional: true,
+                default: true,
+            },
+            "reflink-chunks": {
+                description: "Copy chunks as reflinks, if not hard linked and the file system supports it (e.g. XFS or btrfs).",
+                type: bool,
+                optional: true,
+                default: true,
+            },
+            "remove-source": {
+                description: "Remove copied snapshots from the source datastore (move).",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Backup on the target and the source datastore. \
+            Removing the source additionally requires Datastore.Prune on the source datastore.",
+    },
+)]
+/// Copy backup groups (indices and referenced chunks) from another local datastore.
+#[allow(clippy::too_many_arguments)]
+pub fn copy_groups(
+    store: String,
+    ns: Option<BackupNamespace>,
+    source_store: String,
+    source_ns: Option<BackupNamespace>,
+    max_depth: Option<usize>,
+    group_filter: Option<Vec<GroupFilter>>,
+    link_chunks: bool,
+    reflink_chunks: bool,
+    remove_source: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    if store == source_store {
+        bail!("can't copy to same datastore");
+    }
+
+    let ns = ns.unwrap_or_default();
+    let source_ns = source_ns.unwrap_or_default();
+
+    let ns_str = if ns.is_root() {
+        None
+    } else {
+        Some(ns.to_string())
+    };
+
+    crate::api2::pull::check_pull_privs(
+        &auth_id,
+        &store,
+        ns_str.as_deref(),
+        None,
+        &source_store,
+        false,
+    )?;
+
+    let source = if remove_source {
+        let user_info = CachedUserInfo::new()?;
+        user_info.check_privs(
+            &auth_id,
+            &source_ns.acl_path(&source_store),
+            PRIV_DATASTORE_PRUNE,
+            false,
+        )?;
+        // moving removes the source groups
+        check_not_append_only(&auth_id)?;
+        Some(DataStore::lookup_datastore(
+            &source_store,
+            Some(Operation::Write),
+        )?)
+    } else {
+        None
+    };
+
+    let target = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    let params = PullParameters::new_local_copy(
+        &store,
+        ns.clone(),
+        &source_store,
+        source_ns.clone(),
+        auth_id.clone(),
+        max_depth,
+        group_filter.clone(),
+        link_chunks,
+        reflink_chunks,
+    )?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::spawn(
+        "datastore-copy",
+        Some(format!("{source_store}:{store}")),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| async move {
+            task_log!(
+                worker,
+                "copy {} into {}",
+                print_store_and_ns(&source_store, &source_ns),
+                print_store_and_ns(&store, &ns),
+            );
+
+            let copy_future = pull_store(&worker, params);
+            let stats = (select! {
+                success = copy_future.fuse() => success,
+                abort = worker.abort_future().map(|_| Err(format_err!("copy aborted"))) => abort,
+            })?;
+
+            task_log!(
+                worker,
+                "Summary: copied {} in {} chunks",
+                HumanByte::from(stats.bytes),
+                stats.chunk_count,
+            );
+
+            if let Some(source) = source {
+                let removed = remove_copied_source_snapshots(
+                    &worker,
+                    &source,
+                    &source_ns,
+                    &target,
+                    &ns,
+                    max_depth,
+                    &group_filter.unwrap_or_default(),
+                    &auth_id,
+                )?;
+                task_log!(
+                    worker,
+                    "Summary: removed from source: snapshots: {}, groups: {}",
+                    removed.snapshots,
+                    removed.groups,
+                );
+            }
+
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::And(&[
+            &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, false),
+            &Permission::Privilege(&["system", "disks"], PRIV_SYS_MODIFY, false),
+        ]),
+    },
+)]
+/// Mount the removable device backing a datastore.
+pub fn mount(store: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let datastore: DataStoreConfig = config.lookup("datastore", &store)?;
+
+    if !datastore.is_removable() {
+        bail!("datastore '{store}' is not located on a removable device");
+    }
+
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::spawn(
+        "mount-device",
+        Some(store.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| async move {
+            if mount_datastore_device(&datastore)? {
+                task_log!(
+                    worker,
+                    "mounted removable device of datastore '{store}' on '{}'",
+                    datastore.path
+                );
+            } else {
+                task_log!(worker, "datastore '{store}' is already mounted");
+            }
+
+            if !Path::new(&datastore.path).join(".chunks").is_dir() {
+                task_warn!(worker, "no datastore found on the removable device");
+            }
+
+            // drop a possibly stale cache entry from before the device was attached
+            if let Err(err) = crate::server::notify_datastore_cache_update(&store).await {
+                task_warn!(
+                    worker,
+                    "could not notify proxy about mounted datastore - {err}"
+                );
+            }
+
+            Ok(())
+        },
+    )?;
+
+    Ok(json!(upid_str))
+}
+
+fn set_unmount_maintenance(
+    store: &str,
+    mode: Option<MaintenanceMode>,
+) -> Result<Option<MaintenanceMode>, Error> {
+    let _lock = pbs_config::datastore::lock_config()?;
+    let (mut config, _digest) = pbs_config::datastore::config()?;
+    let mut datastore: DataStoreConfig = config.lookup("datastore", store)?;
+
+    let previous = datastore.get_maintenance_mode();
+    datastore.set_maintenance_mode(mode)?;
+
+    config.set_data(store, "datastore", &datastore)?;
+    pbs_config::datastore::save_config(&config)?;
+
+    Ok(previous)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::And(&[
+            &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, false),
+            &Permission::Privilege(&["system", "disks"], PRIV_SYS_MODIFY, false),
+        ]),
+    },
+)]
+/// Unmount the removable device backing a datastore.
+///
+/// The datastore is put into the 'unmount' maintenance mode until all running operations
+/// finished, its previous maintenance mode is restored afterwards.
+pub fn unmount(store: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let datastore: DataStoreConfig = config.lookup("datastore", &store)?;
+
+    if !datastore.is_removable() {
+        bail!("datastore '{store}' is not located on a removable device");
+    }
+
+    if let Some(MaintenanceType::Unmount) = datastore.get_maintenance_mode().map(|mode| mode.ty) {
+        bail!("datastore '{store}' is already being unmounted");
+    }
+
+    let previous_mode = set_unmount_maintenance(
+        &store,
+        Some(MaintenanceMode {
+            ty: MaintenanceType::Unmount,
+            message: None,
+        }),
+    )?;
+
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::spawn(
+        "unmount-device",
+        Some(store.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| async move {
+            let _ = crate::server::notify_datastore_cache_update(&store).await;
+
+            let result = async {
+                let mut logged = false;
+                loop {
+                    let active = task_tracking::get_active_operations(&store)?;
+                    if active.read + active.write == 0 {
+                        break;
+                    }
+                    if !logged {
+                        task_log!(
+                            worker,
+                            "waiting for {} read and {} write operations to finish",
+                            active.read,
+                            active.write
+                        );
+                        logged = true;
+                    }
+                    worker.check_abort()?;
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+
+                unmount_datastore_device(&datastore)?;
+                task_log!(worker, "unmounted removable device of datastore '{store}'");
+                Ok::<(), Error>(())
+            }
+            .await;
+
+            if let Err(err) = set_unmount_maintenance(&store, previous_mode) {
+                task_warn!(worker, "could not restore maintenance mode - {err}");
+            }
+            let _ = crate::server::notify_datastore_cache_update(&store).await;
+
+            result
+        },
+    )?;
+
+    Ok(json!(upid_str))
+}
+
+// whether the statistics of a group are visible without audit privileges on the whole datastore
+fn stats_group_visible(
+    datastore: &DataStore,
+    auth_id: &Authid,
+    ns: &Option<BackupNamespace>,
+    group: &pbs_api_types::BackupGroup,
+) -> bool {
+    let ns = ns.clone().unwrap_or_default();
+    match check_ns_privs_full(
+        datastore.name(),
+        &ns,
+        auth_id,
+        PRIV_DATASTORE_AUDIT,
+        PRIV_DATASTORE_BACKUP,
+    ) {
+        Ok(false) => true,
+        Ok(true) => datastore
+            .get_owner(&ns, group)
+            .map(|owner| check_backup_owner(&owner, auth_id).is_ok())
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: DedupStats,
+        optional: true,
+    },
     access: {
         permission: &Permission::Anybody,
-        description: "Datastore.Modify on whole datastore, or changing ownership between user and \
-            a user's token for owned backups with Datastore.Backup"
+        description: "Requires DATASTORE_AUDIT for all or DATASTORE_BACKUP for owned groups on \
+            /datastore/{store}[/{namespace}]. Datastore wide totals require DATASTORE_AUDIT on \
+            /datastore/{store}.",
     },
 )]
-/// Change owner of a backup group
-pub async fn set_backup_owner(
+/// Get the deduplication statistics computed by the last 'dedupstats' task.
+pub fn get_dedup_stats(
     store: String,
-    ns: Option<BackupNamespace>,
-    backup_group: pbs_api_types::BackupGroup,
-    new_owner: Authid,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<(), Error> {
+) -> Result<Option<DedupStats>, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
 
-    tokio::task::spawn_blocking(move || {
-        let ns = ns.unwrap_or_default();
-        let owner_check_required = check_ns_privs_full(
-            &store,
-            &ns,
-            &auth_id,
-            PRIV_DATASTORE_MODIFY,
-            PRIV_DATASTORE_BACKUP,
-        )?;
+    let mut stats = match datastore.last_dedup_stats()? {
+        Some(stats) => stats,
+        None => return Ok(None),
+    };
 
-        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let user_info = CachedUserInfo::new()?;
+    let root_privs = user_info.lookup_privs(&auth_id, &["datastore", &store]);
+    if root_privs & PRIV_DATASTORE_AUDIT != 0 {
+        return Ok(Some(stats));
+    }
 
-        let backup_group = datastore.backup_group(ns, backup_group);
+    stats
+        .groups
+        .retain(|group| stats_group_visible(&datastore, &auth_id, &group.ns, &group.backup));
 
-        if owner_check_required {
-            let owner = backup_group.get_owner()?;
+    stats.logical_size = stats.groups.iter().map(|group| group.logical_size).sum();
+    stats.disk_size = 0;
+    stats.disk_chunks = 0;
+    stats.missing_chunks = 0;
 
-            let allowed = match (owner.is_token(), new_owner.is_token()) {
-                (true, true) => {
-                    // API token to API token, owned by same user
-                    let owner = owner.user();
-                    let new_owner = new_owner.user();
-                    owner == new_owner && Authid::from(owner.clone()) == auth_id
-                }
-                (true, false) => {
-                    // API token to API token owner
-                    Authid::from(owner.user().clone()) == auth_id && new_owner == auth_id
-                }
-                (false, true) => {
-                    // API token owner to API token
-                    owner == auth_id && Authid::from(new_owner.user().clone()) == auth_id
-                }
-                (false, false) => {
-                    // User to User, not allowed for unprivileged users
-                    false
-                }
-            };
+    Ok(Some(stats))
+}
 
-            if !allowed {
-                return Err(http_err!(
-                    UNAUTHORIZED,
-                    "{} does not have permission to change owner of backup group '{}' to {}",
-                    auth_id,
-                    backup_group.group(),
-                    new_owner,
-                ));
-            }
-        }
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, false),
+    },
+)]
+/// Compute the deduplication statistics of all backup groups in a background task.
+pub fn start_dedup_stats(store: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
 
-        let user_info = CachedUserInfo::new()?;
+    let upid_str = WorkerTask::new_thread(
+        "dedupstats",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            datastore.compute_dedup_stats(&*worker, worker.upid())?;
+            Ok(())
+        },
+    )?;
 
-        if !user_info.is_active_auth_id(&new_owner) {
-            bail!(
-                "{} '{}' is inactive or non-existent",
-                if new_owner.is_token() {
-                    "API token".to_string()
-                } else {
-                    "user".to_string()
-                },
-                new_owner
-            );
-        }
+    Ok(json!(upid_str))
+}
 
-        backup_group.set_owner(&new_owner, true)?;
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CompressionStats,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires DATASTORE_AUDIT for all or DATASTORE_BACKUP for owned groups on \
+            /datastore/{store}[/{namespace}].",
+    },
+)]
+/// Get the compression statistics computed by the last 'compressionstats' task.
+pub fn get_compression_stats(
+    store: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CompressionStats>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
 
-        Ok(())
-    })
-    .await?
+    let mut stats = match datastore.last_compression_stats()? {
+        Some(stats) => stats,
+        None => return Ok(None),
+    };
+
+    let user_info = CachedUserInfo::new()?;
+    let root_privs = user_info.lookup_privs(&auth_id, &["datastore", &store]);
+    if root_privs & PRIV_DATASTORE_AUDIT != 0 {
+        return Ok(Some(stats));
+    }
+
+    stats
+        .groups
+        .retain(|group| stats_group_visible(&datastore, &auth_id, &group.ns, &group.backup));
+
+    Ok(Some(stats))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            sample: {
+                schema: COMPRESSION_SAMPLE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, false),
+    },
+)]
+/// Estimate the compression ratio and the fraction of encrypted chunks of all backup groups
+/// from a sample of their chunks in a background task.
+pub fn start_compression_stats(
+    store: String,
+    sample: Option<u64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+    let sample = sample.unwrap_or(10);
+
+    let upid_str = WorkerTask::new_thread(
+        "compressionstats",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            datastore.compute_compression_stats(&*worker, worker.upid(), sample)?;
+            Ok(())
+        },
+    )?;
+
+    Ok(json!(upid_str))
 }
 
 #[sortable]
@@ -2336,11 +3654,43 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
         "active-operations",
         &Router::new().get(&API_METHOD_GET_ACTIVE_OPERATIONS),
     ),
-    ("catalog", &Router::new().get(&API_METHOD_CATALOG)),
+    (
+        "archive-at",
+        &Router::new().download(&API_METHOD_DOWNLOAD_ARCHIVE_AT),
+    ),
+    (
+        "attestation",
+        &Router::new().get(&API_METHOD_GET_ATTESTATION),
+    ),
+    (
+        "attestations",
+        &Router::new().get(&API_METHOD_LIST_ATTESTATIONS),
+    ),
+    (
+        "bulk",
+        &Router::new()
+            .get(&API_METHOD_GET_BULK_ACTION_RESULT)
+            .post(&API_METHOD_BULK_ACTION),
+    ),
+    ("catalog", &Router::new().get(&API_METHOD_CATALOG_ENCODED)),
     (
         "change-owner",
         &Router::new().post(&API_METHOD_SET_BACKUP_OWNER),
     ),
+    ("client-log", &Router::new().get(&API_METHOD_GET_CLIENT_LOG)),
+    (
+        "compression-stats",
+        &Router::new()
+            .get(&API_METHOD_GET_COMPRESSION_STATS)
+            .post(&API_METHOD_START_COMPRESSION_STATS),
+    ),
+    ("copy", &Router::new().post(&API_METHOD_COPY_GROUPS)),
+    (
+        "dedup-stats",
+        &Router::new()
+            .get(&API_METHOD_GET_DEDUP_STATS)
+            .post(&API_METHOD_START_DEDUP_STATS),
+    ),
     (
         "download",
         &Router::new().download(&API_METHOD_DOWNLOAD_FILE),
@@ -2368,6 +3718,7 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
             .get(&API_METHOD_LIST_GROUPS)
             .delete(&API_METHOD_DELETE_GROUP),
     ),
+    ("mount", &Router::new().post(&API_METHOD_MOUNT)),
     (
         "namespace",
         // FIXME: move into datastore:: sub-module?!
@@ -2398,10 +3749,11 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
     (
         "snapshots",
         &Router::new()
-            .get(&API_METHOD_LIST_SNAPSHOTS)
+            .get(&API_METHOD_LIST_SNAPSHOTS_ENCODED)
             .delete(&API_METHOD_DELETE_SNAPSHOT),
     ),
     ("status", &Router::new().get(&API_METHOD_STATUS)),
+    ("unmount", &Router::new().post(&API_METHOD_UNMOUNT)),
     (
         "upload-backup-log",
         &Router::new().upload(&API_METHOD_UPLOAD_BACKUP_LOG),
@@ -2416,3 +3768,145 @@ const DATASTORE_INFO_ROUTER: Router = Router::new()
 pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_GET_DATASTORE_LIST)
     .match_all("store", &DATASTORE_INFO_ROUTER);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (name, backup time, finish time)
+    const SNAPSHOTS: &[(&str, i64, i64)] = &[("a", 100, 150), ("b", 200, 400), ("c", 500, 510)];
+
+    fn finished_at(time: i64) -> Option<&'static str> {
+        let snapshots = SNAPSHOTS
+            .iter()
+            .map(|(name, backup_time, _)| (*name, *backup_time))
+            .collect();
+        snapshot_finished_at(snapshots, time, |name| {
+            SNAPSHOTS.iter().find(|(n, _, _)| n == name).unwrap().2
+        })
+    }
+
+    #[test]
+    fn test_snapshot_finished_at() {
+        assert_eq!(finished_at(99), None);
+        // started, but not finished yet
+        assert_eq!(finished_at(149), None);
+        assert_eq!(finished_at(150), Some("a"));
+        // "b" is running, so "a" is still the newest finished one
+        assert_eq!(finished_at(300), Some("a"));
+        assert_eq!(finished_at(400), Some("b"));
+        assert_eq!(finished_at(505), Some("b"));
+        assert_eq!(finished_at(i64::MAX), Some("c"));
+    }
+
+    #[test]
+    fn test_bulk_action_access() -> Result<(), Error> {
+        let (user_cfg, _) = pbs_config::user::test_cfg_from_str(
+            r###"
+user: noperm@pbs
+
+user: admin@pbs
+
+user: reader@pbs
+
+user: backup@pbs
+
+user: power@pbs
+
+user: audit@pbs
+
+user: appendonly@pbs
+	append-only true
+
+user: nsadmin@pbs
+
+"###,
+        )
+        .expect("test user.cfg is not parsable");
+        let acl_tree = pbs_config::acl::AclTree::from_raw(
+            r###"
+acl:1:/datastore/store1:admin@pbs,appendonly@pbs:DatastoreAdmin
+acl:1:/datastore/store1:reader@pbs:DatastoreReader
+acl:1:/datastore/store1:backup@pbs:DatastoreBackup
+acl:1:/datastore/store1:power@pbs:DatastorePowerUser
+acl:1:/datastore/store1:audit@pbs:DatastoreAudit
+acl:1:/datastore/store1/ns1:nsadmin@pbs:DatastoreAdmin
+"###,
+        )
+        .expect("test acl.cfg is not parsable");
+        let user_info = CachedUserInfo::test_new(user_cfg, acl_tree);
+
+        let root = BackupNamespace::root();
+        let ns1: BackupNamespace = "ns1".parse()?;
+
+        // Ok(false): full access, Ok(true): only owned groups, Err: no access
+        let check = |auth_id: &str, ns: &BackupNamespace, action| {
+            let auth_id: Authid = auth_id.parse().unwrap();
+            check_bulk_action_access(&user_info, "store1", ns, &auth_id, action).ok()
+        };
+
+        use BulkAction::*;
+        for (auth_id, expected) in [
+            (
+                "admin@pbs",
+                [Some(false), Some(false), Some(false), Some(false)],
+            ),
+            ("reader@pbs", [None, None, None, Some(false)]),
+            ("backup@pbs", [None, Some(true), Some(true), Some(true)]),
+            (
+                "power@pbs",
+                [Some(true), Some(true), Some(true), Some(true)],
+            ),
+            ("audit@pbs", [None, None, None, None]),
+            // append-only users must not remove or unprotect anything
+            ("appendonly@pbs", [None, Some(false), None, Some(false)]),
+            ("noperm@pbs", [None, None, None, None]),
+            ("nsadmin@pbs", [None, None, None, None]),
+        ] {
+            for (action, expected) in [Delete, Protect, Unprotect, Verify]
+                .into_iter()
+                .zip(expected)
+            {
+                assert_eq!(
+                    check(auth_id, &root, action),
+                    expected,
+                    "{auth_id} {action}"
+                );
+            }
+        }
+
+        // privileges on a namespace only apply to it
+        assert_eq!(check("nsadmin@pbs", &ns1, Delete), Some(false));
+        assert_eq!(check("admin@pbs", &ns1, Delete), Some(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_action_owner() -> Result<(), Error> {
+        let user: Authid = "backup@pbs".parse()?;
+        let token: Authid = "backup@pbs!token".parse()?;
+        let other: Authid = "power@pbs".parse()?;
+
+        // users may modify the groups they or their tokens own
+        assert!(check_backup_owner(&user, &user).is_ok());
+        assert!(check_backup_owner(&token, &user).is_ok());
+        assert!(check_backup_owner(&token, &token).is_ok());
+        // but neither groups of other users nor, as token, groups of their user
+        assert!(check_backup_owner(&other, &user).is_err());
+        assert!(check_backup_owner(&user, &token).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_finished_at_unordered() {
+        let snapshots = vec![("c", 500), ("a", 100), ("b", 200)];
+        let finish_time = |name: &&str| match *name {
+            "a" => 150,
+            "b" => 400,
+            _ => 510,
+        };
+        assert_eq!(snapshot_finished_at(snapshots, 450, finish_time), Some("b"));
+    }
+}
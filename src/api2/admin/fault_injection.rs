@@ -0,0 +1,79 @@
+//! Developer API to control fault injection for storage operations
+//!
+//! Only built with the 'fault-injection' feature.
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router};
+use proxmox_schema::{api, ApiStringFormat, ArraySchema, Schema, StringSchema};
+
+use pbs_datastore::fault_injection::{self, FaultRule};
+
+const FAULT_RULE_PROPERTY_SCHEMA: Schema = StringSchema::new("Fault injection rule.")
+    .format(&ApiStringFormat::PropertyString(&FaultRule::API_SCHEMA))
+    .schema();
+
+const FAULT_RULE_LIST_SCHEMA: Schema = ArraySchema::new(
+    "List of fault injection rules.",
+    &FAULT_RULE_PROPERTY_SCHEMA,
+)
+.schema();
+
+#[api(
+    returns: {
+        description: "The active fault injection rules.",
+        type: Array,
+        items: {
+            type: FaultRule,
+        },
+    },
+    access: {
+        permission: &Permission::Superuser,
+    },
+)]
+/// List the active fault injection rules.
+pub fn list_rules() -> Result<Vec<FaultRule>, Error> {
+    fault_injection::load_rules()
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            rules: {
+                schema: FAULT_RULE_LIST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Superuser,
+    },
+)]
+/// Replace the active fault injection rules.
+pub fn set_rules(rules: Vec<String>) -> Result<(), Error> {
+    let rules = rules
+        .iter()
+        .map(|rule| {
+            let value = FaultRule::API_SCHEMA.parse_property_string(rule)?;
+            Ok(serde_json::from_value(value)?)
+        })
+        .collect::<Result<Vec<FaultRule>, Error>>()?;
+
+    fault_injection::save_rules(&rules)
+}
+
+#[api(
+    protected: true,
+    access: {
+        permission: &Permission::Superuser,
+    },
+)]
+/// Remove all fault injection rules.
+pub fn clear_rules() -> Result<(), Error> {
+    fault_injection::save_rules(&[])
+}
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_RULES)
+    .put(&API_METHOD_SET_RULES)
+    .delete(&API_METHOD_CLEAR_RULES);
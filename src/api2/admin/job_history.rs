@@ -0,0 +1,231 @@
+//! History of finished job runs
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    Authid, JobHistoryEntry, JobResult, DATASTORE_SCHEMA, JOB_ID_SCHEMA, PRIV_DATASTORE_AUDIT,
+    PRIV_SYS_AUDIT,
+};
+use pbs_config::CachedUserInfo;
+
+use crate::server::jobstate::read_all_job_history;
+
+// the filter parameters of the history listing, except for access
+struct HistoryFilter {
+    job_type: Option<String>,
+    id: Option<String>,
+    store: Option<String>,
+    result: Option<JobResult>,
+    since: Option<i64>,
+    until: Option<i64>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, entry: &JobHistoryEntry) -> bool {
+        self.job_type
+            .as_ref()
+            .map_or(true, |ty| &entry.job_type == ty)
+            && self.id.as_ref().map_or(true, |id| &entry.job_id == id)
+            && (self.store.is_none() || entry.store == self.store)
+            && self.result.map_or(true, |result| entry.result == result)
+            && self.since.map_or(true, |since| entry.endtime >= since)
+            && self.until.map_or(true, |until| entry.endtime < until)
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            "job-type": {
+                type: String,
+                description: "Only list runs of this job type (e.g. 'syncjob' or 'garbage_collection').",
+                optional: true,
+            },
+            id: {
+                schema: JOB_ID_SCHEMA,
+                optional: true,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            result: {
+                type: JobResult,
+                optional: true,
+            },
+            since: {
+                type: Integer,
+                description: "Only list runs which finished at or after this time (UNIX epoch).",
+                optional: true,
+            },
+            until: {
+                type: Integer,
+                description: "Only list runs which finished before this time (UNIX epoch).",
+                optional: true,
+            },
+            limit: {
+                type: Integer,
+                description: "Maximum number of runs to list.",
+                optional: true,
+                minimum: 1,
+                default: 50,
+            },
+        },
+    },
+    returns: {
+        description: "Finished job runs, most recent first (filtered by access).",
+        type: Array,
+        items: { type: JobHistoryEntry },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Sys.Audit on '/system/tasks', or Datastore.Audit on the datastore of a job.",
+    },
+)]
+/// List the results of finished job runs.
+#[allow(clippy::too_many_arguments)]
+pub fn list_job_history(
+    job_type: Option<String>,
+    id: Option<String>,
+    store: Option<String>,
+    result: Option<JobResult>,
+    since: Option<i64>,
+    until: Option<i64>,
+    limit: u64,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<JobHistoryEntry>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let list_all = user_info.lookup_privs(&auth_id, &["system", "tasks"]) & PRIV_SYS_AUDIT != 0;
+
+    let filter = HistoryFilter {
+        job_type,
+        id,
+        store,
+        result,
+        since,
+        until,
+    };
+
+    let mut list: Vec<JobHistoryEntry> = read_all_job_history()?
+        .into_iter()
+        .filter(|entry| filter.matches(entry))
+        .filter(|entry| {
+            list_all
+                || entry.store.as_ref().map_or(false, |store| {
+                    user_info.lookup_privs(&auth_id, &["datastore", store]) & PRIV_DATASTORE_AUDIT
+                        != 0
+                })
+        })
+        .collect();
+
+    list.sort_unstable_by(|a, b| b.endtime.cmp(&a.endtime));
+    list.truncate(limit as usize);
+
+    Ok(list)
+}
+
+pub const ROUTER: Router = Router::new().get(&API_METHOD_LIST_JOB_HISTORY);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(job_type: &str, job_id: &str, store: Option<&str>, endtime: i64) -> JobHistoryEntry {
+        JobHistoryEntry {
+            job_type: job_type.to_string(),
+            job_id: job_id.to_string(),
+            store: store.map(String::from),
+            upid: String::new(),
+            starttime: endtime - 10,
+            endtime,
+            result: JobResult::Ok,
+            message: None,
+        }
+    }
+
+    fn filter() -> HistoryFilter {
+        HistoryFilter {
+            job_type: None,
+            id: None,
+            store: None,
+            result: None,
+            since: None,
+            until: None,
+        }
+    }
+
+    #[test]
+    fn test_history_filter() {
+        let gc = entry("garbage_collection", "store1", Some("store1"), 100);
+        let mut sync = entry("syncjob", "s-1", Some("store2"), 200);
+        sync.result = JobResult::Error;
+        let realm = entry("realm-sync", "ldap", None, 300);
+
+        assert!([&gc, &sync, &realm].iter().all(|e| filter().matches(e)));
+
+        let by_type = HistoryFilter {
+            job_type: Some("syncjob".to_string()),
+            ..filter()
+        };
+        assert!(!by_type.matches(&gc) && by_type.matches(&sync));
+
+        let by_id = HistoryFilter {
+            id: Some("ldap".to_string()),
+            ..filter()
+        };
+        assert!(!by_id.matches(&sync) && by_id.matches(&realm));
+
+        // jobs without datastore never match a store filter
+        let by_store = HistoryFilter {
+            store: Some("store1".to_string()),
+            ..filter()
+        };
+        assert!(by_store.matches(&gc) && !by_store.matches(&sync) && !by_store.matches(&realm));
+
+        let by_result = HistoryFilter {
+            result: Some(JobResult::Error),
+            ..filter()
+        };
+        assert!(!by_result.matches(&gc) && by_result.matches(&sync));
+    }
+
+    #[test]
+    fn test_history_filter_time() {
+        let entries = [
+            entry("syncjob", "a", None, 100),
+            entry("syncjob", "b", None, 200),
+            entry("syncjob", "c", None, 300),
+        ];
+
+        let matching = |filter: HistoryFilter| -> Vec<&str> {
+            entries
+                .iter()
+                .filter(|e| filter.matches(e))
+                .map(|e| e.job_id.as_str())
+                .collect()
+        };
+
+        // since is inclusive, until is exclusive
+        let since = HistoryFilter {
+            since: Some(200),
+            ..filter()
+        };
+        assert_eq!(matching(since), ["b", "c"]);
+        let until = HistoryFilter {
+            until: Some(200),
+            ..filter()
+        };
+        assert_eq!(matching(until), ["a"]);
+        let range = HistoryFilter {
+            since: Some(150),
+            until: Some(300),
+            ..filter()
+        };
+        assert_eq!(matching(range), ["b"]);
+    }
+}
@@ -2,10 +2,12 @@
 
 use proxmox_router::list_subdirs_api_method;
 use proxmox_router::{Router, SubdirMap};
-use proxmox_sortable_macro::sortable;
 
 pub mod datastore;
+#[cfg(feature = "fault-injection")]
+pub mod fault_injection;
 pub mod gc;
+pub mod job_history;
 pub mod metrics;
 pub mod namespace;
 pub mod prune;
@@ -13,16 +15,19 @@ pub mod sync;
 pub mod traffic_control;
 pub mod verify;
 
-#[sortable]
-const SUBDIRS: SubdirMap = &sorted!([
+const SUBDIRS: SubdirMap = &[
     ("datastore", &datastore::ROUTER),
+    // developer builds only, see pbs_datastore::fault_injection
+    #[cfg(feature = "fault-injection")]
+    ("fault-injection", &fault_injection::ROUTER),
+    ("gc", &gc::ROUTER),
+    ("job-history", &job_history::ROUTER),
     ("metrics", &metrics::ROUTER),
     ("prune", &prune::ROUTER),
-    ("gc", &gc::ROUTER),
     ("sync", &sync::ROUTER),
     ("traffic-control", &traffic_control::ROUTER),
     ("verify", &verify::ROUTER),
-]);
+];
 
 pub const ROUTER: Router = Router::new()
     .get(&list_subdirs_api_method!(SUBDIRS))
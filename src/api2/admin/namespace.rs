@@ -12,7 +12,9 @@ use pbs_api_types::{
 
 use pbs_datastore::DataStore;
 
-use crate::backup::{check_ns_modification_privs, check_ns_privs, NS_PRIVS_OK};
+use crate::backup::{
+    check_not_append_only, check_ns_modification_privs, check_ns_privs, NS_PRIVS_OK,
+};
 
 #[api(
     input: {
@@ -155,6 +157,9 @@ pub fn delete_namespace(
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
     check_ns_modification_privs(&store, &ns, &auth_id)?;
+    if delete_groups {
+        check_not_append_only(&auth_id)?;
+    }
 
     let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
 
@@ -16,9 +16,10 @@ use pbs_api_types::{
 use pbs_config::prune;
 use pbs_config::CachedUserInfo;
 
+use crate::api2::config::prune::check_prune_job_modify_access;
 use crate::server::{
     do_prune_job,
-    jobstate::{compute_schedule_status, Job, JobState},
+    jobstate::{compute_schedule_status, splay_delay, Job, JobState},
 };
 
 #[api(
@@ -77,6 +78,9 @@ pub fn list_prune_jobs(
             .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
 
         let mut status = compute_schedule_status(&last_state, Some(&job.schedule))?;
+        status.next_run = status
+            .next_run
+            .map(|next| next + splay_delay("prunejob", &job.id, next, job.splay));
         if job.disable {
             status.next_run = None;
         }
@@ -102,7 +106,7 @@ pub fn list_prune_jobs(
     },
     access: {
         permission: &Permission::Anybody,
-        description: "Requires Datastore.Modify on job's datastore.",
+        description: "Requires Datastore.Modify on job's datastore, not allowed for append-only users and tokens.",
     },
 )]
 /// Runs a prune job manually.
@@ -117,7 +121,7 @@ pub fn run_prune_job(
     let (config, _digest) = prune::config()?;
     let prune_job: PruneJobConfig = config.lookup("prune", &id)?;
 
-    user_info.check_privs(&auth_id, &prune_job.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+    check_prune_job_modify_access(&user_info, &auth_id, &prune_job)?;
 
     let job = Job::new("prunejob", &id)?;
 
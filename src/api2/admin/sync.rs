@@ -19,7 +19,7 @@ use crate::{
         config::sync::{check_sync_job_modify_access, check_sync_job_read_access},
         pull::do_sync_job,
     },
-    server::jobstate::{compute_schedule_status, Job, JobState},
+    server::jobstate::{compute_schedule_status, splay_delay, Job, JobState},
 };
 
 #[api(
@@ -70,7 +70,10 @@ pub fn list_sync_jobs(
         let last_state = JobState::load("syncjob", &job.id)
             .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
 
-        let status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        let mut status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        status.next_run = status
+            .next_run
+            .map(|next| next + splay_delay("syncjob", &job.id, next, job.splay));
 
         list.push(SyncJobStatus {
             config: job,
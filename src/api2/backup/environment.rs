@@ -1,6 +1,7 @@
 use anyhow::{bail, format_err, Error};
 use nix::dir::Dir;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::{Arc, Mutex};
 
 use ::serde::Serialize;
@@ -11,12 +12,15 @@ use proxmox_sys::fs::{lock_dir_noblock_shared, replace_file, CreateOptions};
 
 use pbs_api_types::Authid;
 use pbs_datastore::backup_info::{BackupDir, BackupInfo};
+use pbs_datastore::data_blob::DataChunkBuilder;
 use pbs_datastore::dynamic_index::DynamicIndexWriter;
 use pbs_datastore::fixed_index::FixedIndexWriter;
-use pbs_datastore::{DataBlob, DataStore};
+use pbs_datastore::manifest::{CLIENT_LOG_BLOB_NAME, ENCRYPTED_KEY_BLOB_NAME, MANIFEST_BLOB_NAME};
+use pbs_datastore::{DataBlob, DataStore, CATALOG_NAME};
 use proxmox_rest_server::{formatter::*, WorkerTask};
 
 use crate::backup::verify_backup_dir_with_lock;
+use crate::traffic_control_cache::SessionRateLimiter;
 
 use hyper::{Body, Response};
 
@@ -39,6 +43,55 @@ impl UploadStatistic {
     }
 }
 
+/// Running upload statistics of an open archive.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ArchiveUploadStatus {
+    /// Writer ID
+    pub wid: usize,
+    pub archive_name: String,
+    /// Chunks appended to the index so far
+    pub chunk_count: u64,
+    /// Chunks uploaded so far
+    pub upload_count: u64,
+    /// Uncompressed size of the uploaded chunks
+    pub upload_size: u64,
+    /// Compressed size of the uploaded chunks
+    pub compressed_size: u64,
+    /// Uploaded chunks which already existed on the server
+    pub duplicates: u64,
+    /// Percentage of deduplicated chunks, client and server side
+    pub deduplicated: f64,
+    /// Percentage of the compressed size, relative to the uploaded size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<f64>,
+}
+
+impl ArchiveUploadStatus {
+    fn new(wid: usize, name: &str, chunk_count: u64, stat: &UploadStatistic) -> Self {
+        // chunks appended without upload were deduplicated on the client side, appends may lag
+        // behind uploads though
+        let reused = chunk_count.saturating_sub(stat.count) + stat.duplicates;
+        let total = chunk_count.max(stat.count);
+        Self {
+            wid,
+            archive_name: name.to_string(),
+            chunk_count,
+            upload_count: stat.count,
+            upload_size: stat.size,
+            compressed_size: stat.compressed_size,
+            duplicates: stat.duplicates,
+            deduplicated: if total > 0 {
+                (reused as f64 * 100.0) / total as f64
+            } else {
+                0.0
+            },
+            compression: (stat.size > 0)
+                .then(|| (stat.compressed_size as f64 * 100.0) / stat.size as f64),
+        }
+    }
+}
+
 impl std::ops::Add for UploadStatistic {
     type Output = Self;
 
@@ -58,6 +111,7 @@ struct DynamicWriterState {
     offset: u64,
     chunk_count: u64,
     upload_stat: UploadStatistic,
+    last_progress: i64,
 }
 
 struct FixedWriterState {
@@ -67,8 +121,11 @@ struct FixedWriterState {
     chunk_size: u32,
     chunk_count: u64,
     small_chunk_count: usize, // allow 0..1 small chunks (last chunk may be smaller)
+    zero_digest: [u8; 32],
+    zero_chunk_count: u64,
     upload_stat: UploadStatistic,
     incremental: bool,
+    last_progress: i64,
 }
 
 // key=digest, value=length
@@ -83,6 +140,7 @@ struct SharedBackupState {
     known_chunks: KnownChunksMap,
     backup_size: u64, // sums up size of all files
     backup_stat: UploadStatistic,
+    rejected_chunks: u64, // uploaded chunks which failed validation
 }
 
 impl SharedBackupState {
@@ -112,8 +170,10 @@ pub struct BackupEnvironment {
     pub worker: Arc<WorkerTask>,
     pub datastore: Arc<DataStore>,
     pub backup_dir: BackupDir,
+    pub rate_limiter: SessionRateLimiter,
     pub last_backup: Option<BackupInfo>,
     state: Arc<Mutex<SharedBackupState>>,
+    last_activity: Arc<AtomicI64>,
 }
 
 impl BackupEnvironment {
@@ -133,6 +193,7 @@ impl BackupEnvironment {
             known_chunks: HashMap::new(),
             backup_size: 0,
             backup_stat: UploadStatistic::new(),
+            rejected_chunks: 0,
         };
 
         Self {
@@ -144,11 +205,61 @@ impl BackupEnvironment {
             debug: false,
             formatter: JSON_FORMATTER,
             backup_dir,
+            rate_limiter: SessionRateLimiter::default(),
             last_backup: None,
             state: Arc::new(Mutex::new(state)),
+            last_activity: Arc::new(AtomicI64::new(proxmox_time::epoch_i64())),
         }
     }
 
+    /// Record client activity (any request, including heartbeats).
+    pub fn touch(&self) {
+        self.last_activity
+            .store(proxmox_time::epoch_i64(), Ordering::Relaxed);
+    }
+
+    /// Seconds since the last request from the client.
+    pub fn idle_time(&self) -> i64 {
+        proxmox_time::epoch_i64() - self.last_activity.load(Ordering::Relaxed)
+    }
+
+    /// Returns the open archive with the longest time (in seconds) without a
+    /// chunk being uploaded or appended to it.
+    pub fn stalled_archive(&self) -> Option<(String, i64)> {
+        let state = self.state.lock().unwrap();
+        let now = proxmox_time::epoch_i64();
+
+        let dynamic = state
+            .dynamic_writers
+            .values()
+            .map(|data| (&data.name, data.last_progress));
+        let fixed = state
+            .fixed_writers
+            .values()
+            .map(|data| (&data.name, data.last_progress));
+
+        dynamic
+            .chain(fixed)
+            .min_by_key(|(_, last_progress)| *last_progress)
+            .map(|(name, last_progress)| (name.clone(), now - last_progress))
+    }
+
+    /// Returns the running upload statistics of all open archives.
+    pub fn upload_status(&self) -> Vec<ArchiveUploadStatus> {
+        let state = self.state.lock().unwrap();
+
+        let dynamic = state.dynamic_writers.iter().map(|(wid, data)| {
+            ArchiveUploadStatus::new(*wid, &data.name, data.chunk_count, &data.upload_stat)
+        });
+        let fixed = state.fixed_writers.iter().map(|(wid, data)| {
+            ArchiveUploadStatus::new(*wid, &data.name, data.chunk_count, &data.upload_stat)
+        });
+
+        let mut list: Vec<ArchiveUploadStatus> = dynamic.chain(fixed).collect();
+        list.sort_unstable_by_key(|status| status.wid);
+        list
+    }
+
     /// Register a Chunk with associated length.
     ///
     /// We do not fully trust clients, so a client may only use registered
@@ -204,6 +315,7 @@ impl BackupEnvironment {
         }
 
         // record statistics
+        data.last_progress = proxmox_time::epoch_i64();
         data.upload_stat.count += 1;
         data.upload_stat.size += size as u64;
         data.upload_stat.compressed_size += compressed_size as u64;
@@ -239,6 +351,7 @@ impl BackupEnvironment {
         };
 
         // record statistics
+        data.last_progress = proxmox_time::epoch_i64();
         data.upload_stat.count += 1;
         data.upload_stat.size += size as u64;
         data.upload_stat.compressed_size += compressed_size as u64;
@@ -278,6 +391,7 @@ impl BackupEnvironment {
                 offset: 0,
                 chunk_count: 0,
                 upload_stat: UploadStatistic::new(),
+                last_progress: proxmox_time::epoch_i64(),
             },
         );
 
@@ -308,14 +422,32 @@ impl BackupEnvironment {
                 size,
                 chunk_size,
                 small_chunk_count: 0,
+                zero_digest: DataChunkBuilder::zero_chunk_digest(None, chunk_size as usize),
+                zero_chunk_count: 0,
                 upload_stat: UploadStatistic::new(),
                 incremental,
+                last_progress: proxmox_time::epoch_i64(),
             },
         );
 
         Ok(uid)
     }
 
+    /// Account for an uploaded chunk which failed validation and was not stored
+    ///
+    /// A rejected upload usually makes the client abort the backup, so this is logged right
+    /// away instead of at the end of the backup.
+    pub fn register_rejected_chunk(&self, digest: &[u8; 32], err: &Error) {
+        let mut state = self.state.lock().unwrap();
+        state.rejected_chunks += 1;
+        self.log(format!(
+            "chunk {} - {} ({} rejected chunk uploads in this backup)",
+            hex::encode(digest),
+            err,
+            state.rejected_chunks,
+        ));
+    }
+
     /// Append chunk to dynamic writer
     pub fn dynamic_writer_append_chunk(
         &self,
@@ -344,6 +476,7 @@ impl BackupEnvironment {
 
         data.offset += size as u64;
         data.chunk_count += 1;
+        data.last_progress = proxmox_time::epoch_i64();
 
         data.index.add_chunk(data.offset, digest)?;
 
@@ -371,6 +504,11 @@ impl BackupEnvironment {
         let idx = data.index.check_chunk_alignment(end, size as usize)?;
 
         data.chunk_count += 1;
+        data.last_progress = proxmox_time::epoch_i64();
+
+        if size == data.chunk_size && *digest == data.zero_digest {
+            data.zero_chunk_count += 1;
+        }
 
         data.index.add_digest(idx, digest)?;
 
@@ -473,6 +611,8 @@ impl BackupEnvironment {
             );
         }
 
+        pbs_datastore::chunk_ref_tracker::track_index(&self.backup_dir, &data.name);
+
         self.log_upload_stat(
             &data.name,
             &csum,
@@ -538,6 +678,7 @@ impl BackupEnvironment {
         }
 
         let uuid = data.index.uuid;
+
         let expected_csum = data.index.close()?;
 
         if csum != expected_csum {
@@ -547,6 +688,8 @@ impl BackupEnvironment {
             );
         }
 
+        pbs_datastore::chunk_ref_tracker::track_index(&self.backup_dir, &data.name);
+
         self.log_upload_stat(
             &data.name,
             &expected_csum,
@@ -556,6 +699,10 @@ impl BackupEnvironment {
             &data.upload_stat,
         );
 
+        if data.zero_chunk_count > 0 {
+            self.log(format!("Zero chunks: {}", data.zero_chunk_count));
+        }
+
         state.file_counter += 1;
         state.backup_size += size;
         state.backup_stat = state.backup_stat + data.upload_stat;
@@ -590,8 +737,12 @@ impl BackupEnvironment {
         Ok(())
     }
 
-    /// Mark backup as finished
-    pub fn finish_backup(&self) -> Result<(), Error> {
+    /// Mark backup as finished, optionally storing client supplied notes and tags
+    pub fn finish_backup(
+        &self,
+        notes: Option<String>,
+        tags: Option<Vec<String>>,
+    ) -> Result<(), Error> {
         let mut state = self.state.lock().unwrap();
 
         state.ensure_unfinished()?;
@@ -607,9 +758,23 @@ impl BackupEnvironment {
 
         // check for valid manifest and store stats
         let stats = serde_json::to_value(state.backup_stat)?;
+        let previous_snapshot = self
+            .last_backup
+            .as_ref()
+            .map(|base| base.backup_dir.dir().to_string());
         self.backup_dir
             .update_manifest(|manifest| {
                 manifest.unprotected["chunk_upload_stats"] = stats;
+                manifest.unprotected["finish_time"] = proxmox_time::epoch_i64().into();
+                if let Some(previous_snapshot) = previous_snapshot {
+                    set_previous_snapshot(&mut manifest.unprotected, previous_snapshot);
+                }
+                if let Some(notes) = notes {
+                    manifest.unprotected["notes"] = notes.into();
+                }
+                if let Some(tags) = tags {
+                    manifest.unprotected["tags"] = tags.into();
+                }
             })
             .map_err(|err| format_err!("unable to update manifest blob - {}", err))?;
 
@@ -628,18 +793,30 @@ impl BackupEnvironment {
         // marks the backup as successful
         state.finished = true;
 
+        pbs_datastore::chunk_ref_tracker::track_snapshot(&self.backup_dir);
+
         Ok(())
     }
 
     /// If verify-new is set on the datastore, this will run a new verify task
     /// for the backup. If not, this will return and also drop the passed lock
     /// immediately.
-    pub fn verify_after_complete(&self, excl_snap_lock: Dir) -> Result<(), Error> {
+    ///
+    /// Returns whether a verify task was started, `on_verified` is then called
+    /// (within the tokio runtime) once it stored the verify state.
+    pub fn verify_after_complete<F>(
+        &self,
+        excl_snap_lock: Dir,
+        on_verified: F,
+    ) -> Result<bool, Error>
+    where
+        F: FnOnce() + Send + 'static,
+    {
         self.ensure_finished()?;
 
         if !self.datastore.verify_new() {
             // no verify requested, do nothing
-            return Ok(());
+            return Ok(false);
         }
 
         // Downgrade to shared lock, the backup itself is finished
@@ -660,6 +837,7 @@ impl BackupEnvironment {
 
         let datastore = self.datastore.clone();
         let backup_dir = self.backup_dir.clone();
+        let runtime = tokio::runtime::Handle::current();
 
         WorkerTask::new_thread(
             "verify",
@@ -670,20 +848,51 @@ impl BackupEnvironment {
                 worker.log_message("Automatically verifying newly added snapshot");
 
                 let verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
-                if !verify_backup_dir_with_lock(
+                let verified = verify_backup_dir_with_lock(
                     &verify_worker,
                     &backup_dir,
                     worker.upid().clone(),
                     None,
                     snap_lock,
-                )? {
+                );
+
+                {
+                    let _guard = runtime.enter();
+                    on_verified();
+                }
+
+                if !verified? {
                     bail!("verification failed - please check the log for details");
                 }
 
                 Ok(())
             },
         )
-        .map(|_| ())
+        .map(|_| true)
+    }
+
+    /// Check the name of a new archive against the naming policy of the datastore.
+    pub fn check_archive_name(&self, name: &str) -> Result<(), Error> {
+        // files managed by the client itself
+        if [
+            MANIFEST_BLOB_NAME,
+            CLIENT_LOG_BLOB_NAME,
+            ENCRYPTED_KEY_BLOB_NAME,
+            CATALOG_NAME,
+        ]
+        .contains(&name)
+        {
+            return Ok(());
+        }
+
+        let archive_name = [".didx", ".fidx", ".blob"]
+            .iter()
+            .find_map(|extension| name.strip_suffix(extension))
+            .unwrap_or(name);
+
+        self.datastore
+            .naming_policy()
+            .check_archive(self.backup_dir.group(), archive_name)
     }
 
     pub fn log<S: AsRef<str>>(&self, msg: S) {
@@ -763,3 +972,99 @@ impl AsRef<BackupEnvironment> for Box<dyn RpcEnvironment> {
         self.as_any().downcast_ref::<BackupEnvironment>().unwrap()
     }
 }
+
+// adds the base snapshot to the lineage the client recorded in the unprotected manifest part
+fn set_previous_snapshot(unprotected: &mut Value, previous_snapshot: String) {
+    let lineage = &mut unprotected["lineage"];
+    if !lineage.is_object() {
+        *lineage = json!({});
+    }
+    lineage["previous-snapshot"] = previous_snapshot.into();
+}
+
+#[cfg(test)]
+mod tests {
+    use pbs_api_types::SnapshotLineage;
+
+    use super::*;
+
+    fn stat(count: u64, duplicates: u64) -> UploadStatistic {
+        UploadStatistic {
+            count,
+            size: count * 4096,
+            compressed_size: count * 1024,
+            duplicates,
+        }
+    }
+
+    #[test]
+    fn test_archive_upload_status() {
+        // nothing appended or uploaded yet
+        let status = ArchiveUploadStatus::new(1, "root.pxar.didx", 0, &stat(0, 0));
+        assert_eq!(status.deduplicated, 0.0);
+        assert_eq!(status.compression, None);
+
+        // 60 of 100 chunks reused by the client, 10 of the 40 uploaded ones known to the server
+        let status = ArchiveUploadStatus::new(1, "root.pxar.didx", 100, &stat(40, 10));
+        assert_eq!(status.deduplicated, 70.0);
+        assert_eq!(status.compression, Some(25.0));
+
+        // uploads not appended yet do not count as reused
+        let status = ArchiveUploadStatus::new(1, "root.pxar.didx", 20, &stat(40, 10));
+        assert_eq!(status.deduplicated, 25.0);
+
+        // everything known to the server
+        let status = ArchiveUploadStatus::new(1, "root.pxar.didx", 50, &stat(50, 50));
+        assert_eq!(status.deduplicated, 100.0);
+    }
+
+    fn lineage(unprotected: &Value) -> Option<SnapshotLineage> {
+        serde_json::from_value(unprotected["lineage"].clone()).unwrap_or(None)
+    }
+
+    #[test]
+    fn test_set_previous_snapshot() {
+        // manifests of older clients have no lineage at all
+        let mut unprotected = json!({});
+        assert_eq!(lineage(&unprotected), None);
+
+        set_previous_snapshot(
+            &mut unprotected,
+            "host/test/2024-01-01T00:00:00Z".to_string(),
+        );
+        assert_eq!(
+            lineage(&unprotected),
+            Some(SnapshotLineage {
+                previous_snapshot: Some("host/test/2024-01-01T00:00:00Z".to_string()),
+                ..Default::default()
+            })
+        );
+
+        // the client's part is kept
+        let client_lineage = SnapshotLineage {
+            client_version: Some("3.2.2-1".to_string()),
+            chunk_size: Some(4 * 1024 * 1024),
+            ..Default::default()
+        };
+        let mut unprotected = json!({ "lineage": client_lineage.clone() });
+        set_previous_snapshot(
+            &mut unprotected,
+            "host/test/2024-01-01T00:00:00Z".to_string(),
+        );
+        assert_eq!(
+            lineage(&unprotected),
+            Some(SnapshotLineage {
+                previous_snapshot: Some("host/test/2024-01-01T00:00:00Z".to_string()),
+                ..client_lineage
+            })
+        );
+
+        // garbage is replaced
+        let mut unprotected = json!({ "lineage": "garbage" });
+        set_previous_snapshot(
+            &mut unprotected,
+            "host/test/2024-01-01T00:00:00Z".to_string(),
+        );
+        assert!(lineage(&unprotected).is_some());
+    }
+}
@@ -1,5 +1,8 @@
 //! Backup protocol (HTTP2 upgrade)
 
+use std::task::{Context, Poll};
+use std::time::Duration;
+
 use anyhow::{bail, format_err, Error};
 use futures::*;
 use hex::FromHex;
@@ -17,18 +20,29 @@ use proxmox_schema::*;
 use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::{
-    Authid, BackupNamespace, BackupType, Operation, SnapshotVerifyState, VerifyState,
+    Authid, BackupNamespace, BackupType, HookJobType, Operation, SnapshotVerifyState, VerifyState,
     BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
-    BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA, PRIV_DATASTORE_BACKUP,
+    BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA, MULTI_LINE_COMMENT_SCHEMA,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ, SNAPSHOT_TAG_LIST_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{archive_type, ArchiveType};
-use pbs_datastore::{DataStore, PROXMOX_BACKUP_PROTOCOL_ID_V1};
+use pbs_datastore::{DataStore, CLIENT_VERSION_HEADER, PROXMOX_BACKUP_PROTOCOL_ID_V1};
 use pbs_tools::json::{required_array_param, required_integer_param, required_string_param};
 use proxmox_rest_server::{H2Service, WorkerTask};
 use proxmox_sys::fs::lock_dir_noblock_shared;
 
+use crate::server::hooks::{run_post_hooks, run_pre_hooks, HookContext};
+use crate::server::log_forward::forward_task_result;
+use crate::server::webhook::send_backup_webhooks;
+use crate::traffic_control_cache::{SessionRateLimiter, TrafficClass};
+
+/// Default for the `backup-session-timeout` node option (seconds)
+const DEFAULT_BACKUP_SESSION_TIMEOUT: u64 = 900;
+/// How often the reaper checks for client activity
+const SESSION_REAPER_INTERVAL: Duration = Duration::from_secs(10);
+
 mod environment;
 use environment::*;
 
@@ -50,6 +64,7 @@ pub const API_METHOD_UPGRADE_BACKUP: ApiMethod = ApiMethod::new(
             ("backup-time", false, &BACKUP_TIME_SCHEMA),
             ("debug", true, &BooleanSchema::new("Enable verbose debug logging.").schema()),
             ("benchmark", true, &BooleanSchema::new("Job is a benchmark (do not keep data).").schema()),
+            ("heartbeat", true, &BooleanSchema::new("Client sends keep-alive messages, so the session can be aborted once it is silent.").schema()),
         ]),
     )
 ).access(
@@ -76,6 +91,7 @@ fn upgrade_to_backup_protocol(
     async move {
         let debug = param["debug"].as_bool().unwrap_or(false);
         let benchmark = param["benchmark"].as_bool().unwrap_or(false);
+        let heartbeat = param["heartbeat"].as_bool().unwrap_or(false);
 
         let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
@@ -113,6 +129,14 @@ fn upgrade_to_backup_protocol(
             );
         }
 
+        let client_version = parts
+            .headers
+            .get(CLIENT_VERSION_HEADER)
+            .map(|version| version.to_str())
+            .transpose()
+            .map_err(|_| format_err!("invalid client version header"))?;
+        datastore.check_client_version(client_version)?;
+
         if !datastore.namespace_path(&backup_ns).exists() {
             proxmox_router::http_bail!(NOT_FOUND, "namespace not found");
         }
@@ -135,6 +159,10 @@ fn upgrade_to_backup_protocol(
             if benchmark {
                 bail!("benchmark flags is only allowed on 'host/benchmark'");
             }
+            // existing groups are not affected by a policy configured later on
+            if !backup_group.exists() {
+                datastore.naming_policy().check_group(backup_group.group())?;
+            }
             "backup"
         };
 
@@ -197,12 +225,27 @@ fn upgrade_to_backup_protocol(
             bail!("backup directory already exists.");
         }
 
+        let hook_info = json!({
+            "ns": backup_dir.backup_ns(),
+            "snapshot": backup_dir.dir().to_string(),
+            "owner": owner,
+        });
+        let webhook_owner = owner.clone();
+
         WorkerTask::spawn(
             worker_type,
             Some(worker_id),
             auth_id.to_string(),
             true,
             move |worker| {
+                let rate_limiter =
+                    SessionRateLimiter::lookup(
+                        rpcenv.get_client_ip(),
+                        &auth_id,
+                        &store,
+                        TrafficClass::Backup,
+                    );
+
                 let mut env = BackupEnvironment::new(
                     env_type,
                     auth_id,
@@ -212,6 +255,10 @@ fn upgrade_to_backup_protocol(
                 );
 
                 env.debug = debug;
+                if let Some(base) = &last_backup {
+                    // chunks of the previous snapshot get reused by this session
+                    pbs_datastore::chunk_ref_tracker::track_snapshot(&base.backup_dir);
+                }
                 env.last_backup = last_backup;
 
                 let origin = match rpcenv.get_client_ip().map(|addr| addr.ip()) {
@@ -222,8 +269,15 @@ fn upgrade_to_backup_protocol(
                     "starting new {worker_type} on datastore '{store}'{origin}: {path:?}",
                 ));
 
-                let service =
-                    H2Service::new(env.clone(), worker.clone(), &BACKUP_API_ROUTER, debug);
+                if let Some(rule) = rate_limiter.rule() {
+                    env.log(format!("applying traffic control rule '{rule}'"));
+                }
+                env.rate_limiter = rate_limiter;
+
+                let service = ActivityTracker {
+                    inner: H2Service::new(env.clone(), worker.clone(), &BACKUP_API_ROUTER, debug),
+                    env: env.clone(),
+                };
 
                 let abort_future = worker.abort_future();
 
@@ -262,15 +316,68 @@ fn upgrade_to_backup_protocol(
                     });
                 let mut abort_future = abort_future.map(|_| Err(format_err!("task aborted")));
 
+                let (session_timeout, archive_timeout) = match crate::config::node::config() {
+                    Ok((node_config, _)) => (
+                        node_config.backup_session_timeout,
+                        node_config.backup_archive_timeout,
+                    ),
+                    Err(err) => {
+                        env.log(format!("unable to read node config - {err}"));
+                        (None, None)
+                    }
+                };
+                let session_timeout = effective_session_timeout(heartbeat, session_timeout);
+                let archive_timeout = archive_timeout.unwrap_or(0);
+
+                let hook_ctx = HookContext::new(
+                    HookJobType::Backup,
+                    &store,
+                    worker.upid().to_string(),
+                    hook_info,
+                );
+
+                let env4 = env.clone();
+                let mut reaper_future = async move {
+                    if session_timeout == 0 && archive_timeout == 0 {
+                        return future::pending::<Result<(), Error>>().await;
+                    }
+                    loop {
+                        tokio::time::sleep(SESSION_REAPER_INTERVAL).await;
+                        check_session_activity(
+                            env4.idle_time(),
+                            env4.stalled_archive(),
+                            session_timeout,
+                            archive_timeout,
+                        )?;
+                    }
+                }
+                .boxed()
+                .fuse();
+
                 async move {
                     // keep flock until task ends
                     let _group_guard = _group_guard;
                     let snap_guard = snap_guard;
                     let _last_guard = _last_guard;
 
+                    if !benchmark {
+                        let pre_result = proxmox_async::runtime::block_in_place(|| {
+                            run_pre_hooks(&*env.worker, &hook_ctx)
+                        });
+                        if let Err(err) = pre_result {
+                            env.log(format!("backup aborted by hook: {err}"));
+                            env.log("removing failed backup");
+                            proxmox_async::runtime::block_in_place(|| env.remove_backup())?;
+                            let result = Err(err);
+                            forward_task_result(&hook_ctx, &result, None);
+                            return result;
+                        }
+                    }
+
                     let res = select! {
                         req = req_fut => req,
                         abrt = abort_future => abrt,
+                        reaped = reaper_future => reaped,
                     };
                     if benchmark {
                         env.log("benchmark finished successfully");
@@ -278,25 +385,44 @@ fn upgrade_to_backup_protocol(
                         return Ok(());
                     }
 
-                    let verify = |env: BackupEnvironment| {
-                        if let Err(err) = env.verify_after_complete(snap_guard) {
-                            env.log(format!(
-                                "backup finished, but starting the requested verify task failed: {}",
-                                err
-                            ));
+                    let worker = env.worker.clone();
+                    let snapshot = env.backup_dir.clone();
+
+                    let send_webhooks = {
+                        let snapshot = snapshot.clone();
+                        let upid = worker.upid().to_string();
+                        let duration = proxmox_time::epoch_i64() - worker.upid().starttime;
+                        move |result: &Result<(), Error>| {
+                            send_backup_webhooks(&snapshot, &webhook_owner, &upid, duration, result)
+                        }
+                    };
+
+                    // with verify-new, webhooks are sent once the verify state is known
+                    let verify = |env: BackupEnvironment| -> bool {
+                        let send_webhooks = send_webhooks.clone();
+                        match env.verify_after_complete(snap_guard, move || send_webhooks(&Ok(()))) {
+                            Ok(started) => started,
+                            Err(err) => {
+                                env.log(format!(
+                                    "backup finished, but starting the requested verify task failed: {}",
+                                    err
+                                ));
+                                false
+                            }
                         }
                     };
 
-                    match (res, env.ensure_finished()) {
+                    let mut webhooks_sent = false;
+                    let result = match (res, env.ensure_finished()) {
                         (Ok(_), Ok(())) => {
                             env.log("backup finished successfully");
-                            verify(env);
+                            webhooks_sent = verify(env);
                             Ok(())
                         }
                         (Err(err), Ok(())) => {
                             // ignore errors after finish
                             env.log(format!("backup had errors but finished: {}", err));
-                            verify(env);
+                            webhooks_sent = verify(env);
                             Ok(())
                         }
                         (Ok(_), Err(err)) => {
@@ -311,7 +437,29 @@ fn upgrade_to_backup_protocol(
                             proxmox_async::runtime::block_in_place(|| env.remove_backup())?;
                             Err(err)
                         }
+                    };
+
+                    // do not block other backups of the group while the post hooks run
+                    drop(_group_guard);
+                    drop(_last_guard);
+
+                    proxmox_async::runtime::block_in_place(|| {
+                        run_post_hooks(&*worker, &hook_ctx, &result)
+                    });
+
+                    if !webhooks_sent {
+                        send_webhooks(&result);
                     }
+
+                    let bytes = match result {
+                        Ok(()) => snapshot.load_manifest().ok().map(|(manifest, _)| {
+                            manifest.files().iter().map(|file| file.size).sum()
+                        }),
+                        Err(_) => None,
+                    };
+                    forward_task_result(&hook_ctx, &result, bytes);
+
+                    result
                 }
             },
         )?;
@@ -330,6 +478,67 @@ fn upgrade_to_backup_protocol(
     .boxed()
 }
 
+/// Session timeout in seconds (0 disables) for a client, depending on whether it announced to
+/// send heartbeats.
+fn effective_session_timeout(heartbeat: bool, configured: Option<u64>) -> u64 {
+    // older clients do not send heartbeats, so their silence means nothing
+    if heartbeat {
+        configured.unwrap_or(DEFAULT_BACKUP_SESSION_TIMEOUT)
+    } else {
+        0
+    }
+}
+
+/// Fails if the client has been silent for longer than `session_timeout`, or the stalled archive
+/// got no chunk for longer than `archive_timeout` (both in seconds, 0 disables the check).
+fn check_session_activity(
+    idle_time: i64,
+    stalled_archive: Option<(String, i64)>,
+    session_timeout: u64,
+    archive_timeout: u64,
+) -> Result<(), Error> {
+    if session_timeout > 0 && idle_time >= session_timeout as i64 {
+        bail!("client has been silent for {idle_time} seconds - aborting stale session");
+    }
+    if archive_timeout == 0 {
+        return Ok(());
+    }
+    if let Some((archive, stall_time)) = stalled_archive {
+        if stall_time >= archive_timeout as i64 {
+            bail!(
+                "no chunk uploaded to archive '{archive}' for {stall_time} \
+                seconds - aborting stuck session"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Records client activity on the [BackupEnvironment] for every request,
+/// so that stale sessions can be detected.
+struct ActivityTracker<S> {
+    inner: S,
+    env: BackupEnvironment,
+}
+
+impl<S> hyper::service::Service<Request<Body>> for ActivityTracker<S>
+where
+    S: hyper::service::Service<Request<Body>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        self.env.touch();
+        self.inner.call(req)
+    }
+}
+
 const BACKUP_API_SUBDIRS: SubdirMap = &[
     ("blob", &Router::new().upload(&API_METHOD_UPLOAD_BLOB)),
     (
@@ -346,11 +555,12 @@ const BACKUP_API_SUBDIRS: SubdirMap = &[
             .post(&API_METHOD_CREATE_DYNAMIC_INDEX)
             .put(&API_METHOD_DYNAMIC_APPEND),
     ),
+    ("finish", &Router::new().post(&API_METHOD_FINISH_BACKUP)),
     (
-        "finish",
+        "heartbeat",
         &Router::new().post(&ApiMethod::new(
-            &ApiHandler::Sync(&finish_backup),
-            &ObjectSchema::new("Mark backup as finished.", &[]),
+            &ApiHandler::Sync(&heartbeat),
+            &ObjectSchema::new("Keep the backup session alive.", &[]),
         )),
     ),
     (
@@ -367,6 +577,10 @@ const BACKUP_API_SUBDIRS: SubdirMap = &[
             .post(&API_METHOD_CREATE_FIXED_INDEX)
             .put(&API_METHOD_FIXED_APPEND),
     ),
+    (
+        "known_chunks",
+        &Router::new().post(&API_METHOD_QUERY_KNOWN_CHUNKS),
+    ),
     (
         "previous",
         &Router::new().download(&API_METHOD_DOWNLOAD_PREVIOUS),
@@ -379,6 +593,10 @@ const BACKUP_API_SUBDIRS: SubdirMap = &[
         "speedtest",
         &Router::new().upload(&API_METHOD_UPLOAD_SPEEDTEST),
     ),
+    (
+        "upload_stats",
+        &Router::new().get(&API_METHOD_GET_UPLOAD_STATS),
+    ),
 ];
 
 pub const BACKUP_API_ROUTER: Router = Router::new()
@@ -407,6 +625,7 @@ fn create_dynamic_index(
     if !archive_name.ends_with(".didx") {
         bail!("wrong archive extension: '{}'", archive_name);
     }
+    env.check_archive_name(&archive_name)?;
 
     let mut path = env.backup_dir.relative_path();
     path.push(archive_name);
@@ -459,6 +678,7 @@ fn create_fixed_index(
     if !archive_name.ends_with(".fidx") {
         bail!("wrong archive extension: '{}'", archive_name);
     }
+    env.check_archive_name(&archive_name)?;
 
     let mut path = env.backup_dir.relative_path();
     path.push(&archive_name);
@@ -587,6 +807,107 @@ fn dynamic_append(
     Ok(Value::Null)
 }
 
+#[sortable]
+pub const API_METHOD_QUERY_KNOWN_CHUNKS: ApiMethod = ApiMethod::new(
+    &ApiHandler::Sync(&query_known_chunks),
+    &ObjectSchema::new(
+        "Query which of the given chunks already exist in the datastore. Existing chunks are \
+         registered, so that they can be appended to an index without uploading them. Needs \
+         to be enabled with the datastore's 'known-chunk-query' option and requires \
+         Datastore.Read on the datastore, with Datastore.Audit only chunks of the previous \
+         snapshot are reported.",
+        &sorted!([
+            (
+                "digest-list",
+                false,
+                &ArraySchema::new("Chunk digest list.", &CHUNK_DIGEST_SCHEMA)
+                    .max_length(1024)
+                    .schema()
+            ),
+            (
+                "size-list",
+                false,
+                &ArraySchema::new(
+                    "Chunk size list.",
+                    &IntegerSchema::new("Corresponding chunk sizes.")
+                        .minimum(1)
+                        .maximum(1024 * 1024 * 16)
+                        .schema()
+                )
+                .max_length(1024)
+                .schema()
+            ),
+        ]),
+    ),
+);
+
+fn query_known_chunks(
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let digest_list = required_array_param(&param, "digest-list")?;
+    let size_list = required_array_param(&param, "size-list")?;
+
+    if size_list.len() != digest_list.len() {
+        bail!(
+            "size list has wrong length ({} != {})",
+            size_list.len(),
+            digest_list.len()
+        );
+    }
+
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let env: &BackupEnvironment = rpcenv.as_ref();
+
+    if !env.datastore.known_chunk_query() {
+        bail!(
+            "datastore '{}' does not allow querying known chunks",
+            env.datastore.name()
+        );
+    }
+
+    // the result tells which chunks exist anywhere in the datastore, across owners and
+    // namespaces, so this requires privileges on the datastore itself
+    let user_info = CachedUserInfo::new()?;
+    let privs = user_info.lookup_privs(&auth_id, &["datastore", env.datastore.name()]);
+    if privs & (PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_READ) == 0 {
+        bail!(
+            "querying known chunks requires Datastore.Audit or Datastore.Read on datastore '{}'",
+            env.datastore.name()
+        );
+    }
+    // chunks the caller could not read otherwise must not be referenced without uploading them
+    let may_register = privs & PRIV_DATASTORE_READ != 0;
+
+    let mut known = Vec::new();
+
+    for (item, size) in digest_list.iter().zip(size_list) {
+        let digest_str = item.as_str().unwrap();
+        let digest = <[u8; 32]>::from_hex(digest_str)?;
+        let size = size.as_u64().unwrap() as u32;
+
+        if env.lookup_chunk(&digest).is_some() {
+            known.push(digest_str);
+            continue;
+        }
+
+        // touching the chunk protects it from a concurrent garbage collection
+        if may_register && env.datastore.cond_touch_chunk(&digest, false)? {
+            env.register_chunk(digest, size)?;
+            known.push(digest_str);
+        }
+    }
+
+    env.debug(format!(
+        "query_known_chunks: {} of {} chunks known",
+        known.len(),
+        digest_list.len()
+    ));
+
+    Ok(json!(known))
+}
+
 #[sortable]
 pub const API_METHOD_FIXED_APPEND: ApiMethod = ApiMethod::new(
     &ApiHandler::Sync(&fixed_append),
@@ -775,19 +1096,64 @@ fn close_fixed_index(
     Ok(Value::Null)
 }
 
+#[sortable]
+pub const API_METHOD_FINISH_BACKUP: ApiMethod = ApiMethod::new(
+    &ApiHandler::Sync(&finish_backup),
+    &ObjectSchema::new(
+        "Mark backup as finished.",
+        &sorted!([
+            ("notes", true, &MULTI_LINE_COMMENT_SCHEMA),
+            ("tags", true, &SNAPSHOT_TAG_LIST_SCHEMA),
+        ]),
+    ),
+);
+
 fn finish_backup(
-    _param: Value,
+    param: Value,
     _info: &ApiMethod,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
+    let notes = param["notes"].as_str().map(String::from);
+    let tags: Option<Vec<String>> = match param.get("tags") {
+        Some(tags) => Some(serde_json::from_value(tags.clone())?),
+        None => None,
+    };
+
     let env: &BackupEnvironment = rpcenv.as_ref();
 
-    env.finish_backup()?;
+    env.finish_backup(notes, tags)?;
     env.log("successfully finished backup");
 
     Ok(Value::Null)
 }
 
+fn heartbeat(
+    _param: Value,
+    _info: &ApiMethod,
+    _rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    // activity is already recorded by the ActivityTracker
+    Ok(Value::Null)
+}
+
+pub const API_METHOD_GET_UPLOAD_STATS: ApiMethod = ApiMethod::new(
+    &ApiHandler::Sync(&get_upload_stats),
+    &ObjectSchema::new(
+        "Get the running deduplication and compression statistics of the open archives.",
+        &[],
+    ),
+);
+
+fn get_upload_stats(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let env: &BackupEnvironment = rpcenv.as_ref();
+
+    Ok(serde_json::to_value(env.upload_status())?)
+}
+
 #[sortable]
 pub const API_METHOD_GET_PREVIOUS_BACKUP_TIME: ApiMethod = ApiMethod::new(
     &ApiHandler::Sync(&get_previous_backup_time),
@@ -869,3 +1235,38 @@ fn download_previous(
     }
     .boxed()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_session_timeout() {
+        assert_eq!(
+            effective_session_timeout(true, None),
+            DEFAULT_BACKUP_SESSION_TIMEOUT
+        );
+        assert_eq!(effective_session_timeout(true, Some(60)), 60);
+        assert_eq!(effective_session_timeout(true, Some(0)), 0);
+        // clients without heartbeats are never considered stale
+        assert_eq!(effective_session_timeout(false, None), 0);
+        assert_eq!(effective_session_timeout(false, Some(60)), 0);
+    }
+
+    #[test]
+    fn test_check_session_activity() {
+        let stalled = || Some(("root.pxar.didx".to_string(), 120));
+
+        assert!(check_session_activity(59, None, 60, 0).is_ok());
+        assert!(check_session_activity(60, None, 60, 0).is_err());
+        // disabled session timeout
+        assert!(check_session_activity(3600, None, 0, 0).is_ok());
+
+        assert!(check_session_activity(0, stalled(), 0, 121).is_ok());
+        let err = check_session_activity(0, stalled(), 0, 120).unwrap_err();
+        assert!(err.to_string().contains("root.pxar.didx"));
+        // disabled archive timeout
+        assert!(check_session_activity(0, stalled(), 0, 0).is_ok());
+        assert!(check_session_activity(0, None, 0, 120).is_ok());
+    }
+}
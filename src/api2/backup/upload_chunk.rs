@@ -20,6 +20,18 @@ use pbs_tools::json::{required_integer_param, required_string_param};
 
 use super::environment::*;
 
+/// Error for uploaded chunks which failed validation, as opposed to e.g. transport errors
+#[derive(Debug)]
+pub struct RejectedChunk(Error);
+
+impl std::fmt::Display for RejectedChunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "rejected uploaded chunk - {}", self.0)
+    }
+}
+
+impl std::error::Error for RejectedChunk {}
+
 pub struct UploadChunk {
     stream: Body,
     store: Arc<DataStore>,
@@ -74,10 +86,21 @@ impl Future for UploadChunk {
                         }
 
                         let (is_duplicate, compressed_size) = match proxmox_lang::try_block! {
-                            let mut chunk = DataBlob::from_raw(raw_data)?;
+                            let chunk = DataBlob::from_raw(raw_data)
+                                .map_err(|err| Error::from(RejectedChunk(err)))?;
 
                             proxmox_async::runtime::block_in_place(|| {
-                                chunk.verify_unencrypted(this.size as usize, &this.digest)?;
+                                if this.store.verify_upload() {
+                                    chunk
+                                        .verify_crc()
+                                        .map_err(|err| Error::from(RejectedChunk(err)))?;
+                                }
+
+                                chunk
+                                    .verify_unencrypted(this.size as usize, &this.digest)
+                                    .map_err(|err| Error::from(RejectedChunk(err)))?;
+
+                                let mut chunk = this.store.recompress_chunk(chunk, &this.digest)?;
 
                                 // always comput CRC at server side
                                 chunk.set_crc(chunk.compute_crc());
@@ -162,7 +185,16 @@ fn upload_fixed_chunk(
         let env: &BackupEnvironment = rpcenv.as_ref();
 
         let (digest, size, compressed_size, is_duplicate) =
-            UploadChunk::new(req_body, env.datastore.clone(), digest, size, encoded_size).await?;
+            UploadChunk::new(req_body, env.datastore.clone(), digest, size, encoded_size)
+                .await
+                .map_err(|err| {
+                    if err.downcast_ref::<RejectedChunk>().is_some() {
+                        env.register_rejected_chunk(&digest, &err);
+                    }
+                    err
+                })?;
+
+        env.rate_limiter.throttle_in(encoded_size as usize).await;
 
         env.register_fixed_chunk(wid, digest, size, compressed_size, is_duplicate)?;
         let digest_str = hex::encode(digest);
@@ -231,7 +263,16 @@ fn upload_dynamic_chunk(
         let env: &BackupEnvironment = rpcenv.as_ref();
 
         let (digest, size, compressed_size, is_duplicate) =
-            UploadChunk::new(req_body, env.datastore.clone(), digest, size, encoded_size).await?;
+            UploadChunk::new(req_body, env.datastore.clone(), digest, size, encoded_size)
+                .await
+                .map_err(|err| {
+                    if err.downcast_ref::<RejectedChunk>().is_some() {
+                        env.register_rejected_chunk(&digest, &err);
+                    }
+                    err
+                })?;
+
+        env.rate_limiter.throttle_in(encoded_size as usize).await;
 
         env.register_dynamic_chunk(wid, digest, size, compressed_size, is_duplicate)?;
         let digest_str = hex::encode(digest);
@@ -317,6 +358,7 @@ fn upload_blob(
         if !file_name.ends_with(".blob") {
             bail!("wrong blob file extension: '{}'", file_name);
         }
+        env.check_archive_name(&file_name)?;
 
         let data = req_body
             .map_err(Error::from)
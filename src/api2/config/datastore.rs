@@ -8,16 +8,18 @@ use serde_json::Value;
 use proxmox_router::{http_bail, Permission, Router, RpcEnvironment, RpcEnvironmentType};
 use proxmox_schema::{api, param_bail, ApiType};
 use proxmox_section_config::SectionConfigData;
-use proxmox_sys::{task_warn, WorkerTaskContext};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 use proxmox_uuid::Uuid;
 
 use pbs_api_types::{
-    Authid, DataStoreConfig, DataStoreConfigUpdater, DatastoreNotify, DatastoreTuning, KeepOptions,
-    MaintenanceMode, PruneJobConfig, PruneJobOptions, DATASTORE_SCHEMA, PRIV_DATASTORE_ALLOCATE,
-    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA, UPID_SCHEMA,
+    Authid, BackupNamespace, DataStoreConfig, DataStoreConfigUpdater, DatastoreNotify,
+    DatastoreTuning, KeepOptions, MaintenanceMode, Operation, PruneJobConfig, PruneJobOptions,
+    DATASTORE_SCHEMA, PRIV_DATASTORE_ALLOCATE, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA, UPID_SCHEMA,
 };
 use pbs_config::BackupLockGuard;
 use pbs_datastore::chunk_store::ChunkStore;
+use pbs_datastore::DataStore;
 
 use crate::api2::admin::{
     prune::list_prune_jobs, sync::list_sync_jobs, verify::list_verification_jobs,
@@ -26,6 +28,7 @@ use crate::api2::config::prune::{delete_prune_job, do_create_prune_job};
 use crate::api2::config::sync::delete_sync_job;
 use crate::api2::config::tape_backup_job::{delete_tape_backup_job, list_tape_backup_jobs};
 use crate::api2::config::verify::delete_verification_job;
+use crate::backup::check_user_not_append_only;
 use pbs_config::CachedUserInfo;
 
 use proxmox_rest_server::WorkerTask;
@@ -78,15 +81,30 @@ pub(crate) fn do_create_datastore(
         DatastoreTuning::API_SCHEMA
             .parse_property_string(datastore.tuning.as_deref().unwrap_or(""))?,
     )?;
-    let backup_user = pbs_config::backup_user()?;
-    let _store = ChunkStore::create(
-        &datastore.name,
-        path,
-        backup_user.uid,
-        backup_user.gid,
-        worker,
-        tuning.sync_level.unwrap_or_default(),
-    )?;
+
+    if datastore.is_removable() {
+        crate::tools::disks::mount_datastore_device(&datastore)?;
+    }
+
+    if datastore.is_removable() && path.join(".chunks").is_dir() {
+        // re-use the datastore on a previously used removable device
+        if let Some(worker) = worker {
+            task_log!(
+                worker,
+                "found existing datastore on removable device, re-using it"
+            );
+        }
+    } else {
+        let backup_user = pbs_config::backup_user()?;
+        let _store = ChunkStore::create(
+            &datastore.name,
+            path,
+            backup_user.uid,
+            backup_user.gid,
+            worker,
+            tuning.sync_level.unwrap_or_default(),
+        )?;
+    }
 
     config.set_data(&datastore.name, "datastore", &datastore)?;
 
@@ -135,6 +153,7 @@ pub fn create_datastore(
             comment: None,
             disable: false,
             schedule: schedule.clone(),
+            splay: None,
             options: PruneJobOptions {
                 keep: config.keep.clone(),
                 max_depth: None,
@@ -202,6 +221,8 @@ pub enum DeletableProperty {
     Comment,
     /// Delete the garbage collection schedule.
     GcSchedule,
+    /// Delete the garbage collection splay.
+    GcSplay,
     /// Delete the prune job schedule.
     PruneSchedule,
     /// Delete the keep-last property
@@ -218,6 +239,8 @@ pub enum DeletableProperty {
     KeepYearly,
     /// Delete the verify-new property
     VerifyNew,
+    /// Delete the known-chunk-query property
+    KnownChunkQuery,
     /// Delete the notify-user property
     NotifyUser,
     /// Delete the notify property
@@ -226,10 +249,27 @@ pub enum DeletableProperty {
     NotificationMode,
     /// Delete the tuning property
     Tuning,
+    /// Delete the naming-policy property
+    NamingPolicy,
+    /// Delete the min-client-version property
+    MinClientVersion,
+    /// Delete the ephemeral property
+    Ephemeral,
     /// Delete the maintenance-mode property
     MaintenanceMode,
 }
 
+/// Checks whether any namespace of the datastore contains a backup group.
+fn datastore_has_backups(config: &DataStoreConfig) -> Result<bool, Error> {
+    let datastore = DataStore::lookup_datastore(&config.name, Some(Operation::Read))?;
+    for ns in datastore.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+        if datastore.iter_backup_groups_ok(ns)?.next().is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 #[api(
     protected: true,
     input: {
@@ -249,6 +289,13 @@ pub enum DeletableProperty {
                     type: DeletableProperty,
                 }
             },
+            "confirm-ephemeral": {
+                description: "Confirm marking a datastore which already contains backups as \
+                    ephemeral, its snapshots older than the TTL are removed.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
             digest: {
                 optional: true,
                 schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
@@ -264,6 +311,7 @@ pub fn update_datastore(
     update: DataStoreConfigUpdater,
     name: String,
     delete: Option<Vec<DeletableProperty>>,
+    confirm_ephemeral: bool,
     digest: Option<String>,
 ) -> Result<(), Error> {
     let _lock = pbs_config::datastore::lock_config()?;
@@ -287,6 +335,9 @@ pub fn update_datastore(
                 DeletableProperty::GcSchedule => {
                     data.gc_schedule = None;
                 }
+                DeletableProperty::GcSplay => {
+                    data.gc_splay = None;
+                }
                 DeletableProperty::PruneSchedule => {
                     data.prune_schedule = None;
                 }
@@ -311,6 +362,9 @@ pub fn update_datastore(
                 DeletableProperty::VerifyNew => {
                     data.verify_new = None;
                 }
+                DeletableProperty::KnownChunkQuery => {
+                    data.known_chunk_query = None;
+                }
                 DeletableProperty::Notify => {
                     data.notify = None;
                 }
@@ -323,6 +377,15 @@ pub fn update_datastore(
                 DeletableProperty::Tuning => {
                     data.tuning = None;
                 }
+                DeletableProperty::NamingPolicy => {
+                    data.naming_policy = None;
+                }
+                DeletableProperty::MinClientVersion => {
+                    data.min_client_version = None;
+                }
+                DeletableProperty::Ephemeral => {
+                    data.ephemeral = None;
+                }
                 DeletableProperty::MaintenanceMode => {
                     data.set_maintenance_mode(None)?;
                 }
@@ -344,6 +407,9 @@ pub fn update_datastore(
         gc_schedule_changed = data.gc_schedule != update.gc_schedule;
         data.gc_schedule = update.gc_schedule;
     }
+    if update.gc_splay.is_some() {
+        data.gc_splay = update.gc_splay;
+    }
 
     macro_rules! prune_disabled {
         ($(($param:literal, $($member:tt)+)),+) => {
@@ -386,6 +452,10 @@ pub fn update_datastore(
         data.verify_new = update.verify_new;
     }
 
+    if update.known_chunk_query.is_some() {
+        data.known_chunk_query = update.known_chunk_query;
+    }
+
     if update.notify_user.is_some() {
         data.notify_user = update.notify_user;
     }
@@ -398,6 +468,25 @@ pub fn update_datastore(
         data.tuning = update.tuning;
     }
 
+    if update.naming_policy.is_some() {
+        data.naming_policy = update.naming_policy;
+    }
+
+    if update.min_client_version.is_some() {
+        data.min_client_version = update.min_client_version;
+    }
+
+    if update.ephemeral.is_some() {
+        if data.ephemeral.is_none() && !confirm_ephemeral && datastore_has_backups(&data)? {
+            param_bail!(
+                "ephemeral",
+                "datastore contains backups, which would expire - use 'confirm-ephemeral' to \
+                 mark it as ephemeral anyway",
+            );
+        }
+        data.ephemeral = update.ephemeral;
+    }
+
     let mut maintenance_mode_changed = false;
     if update.maintenance_mode.is_some() {
         maintenance_mode_changed = data.maintenance_mode != update.maintenance_mode;
@@ -424,25 +513,26 @@ pub fn update_datastore(
     // tell the proxy it might have to clear a cache entry
     if maintenance_mode_changed {
         tokio::spawn(async move {
-            if let Ok(proxy_pid) =
-                proxmox_rest_server::read_pid(pbs_buildcfg::PROXMOX_BACKUP_PROXY_PID_FN)
-            {
-                let sock = proxmox_rest_server::ctrl_sock_from_pid(proxy_pid);
-                let _ = proxmox_rest_server::send_raw_command(
-                    sock,
-                    &format!(
-                        "{{\"command\":\"update-datastore-cache\",\"args\":\"{}\"}}\n",
-                        &name
-                    ),
-                )
-                .await;
-            }
+            let _ = crate::server::notify_datastore_cache_update(&name).await;
         });
     }
 
     Ok(())
 }
 
+/// Checks that `auth_id` may remove a datastore, which is refused for append-only users and tokens
+/// if its contents are destroyed as well.
+fn check_delete_datastore_access(
+    user_info: &CachedUserInfo,
+    auth_id: &Authid,
+    destroy_data: bool,
+) -> Result<(), Error> {
+    if destroy_data {
+        check_user_not_append_only(user_info, auth_id)?;
+    }
+    Ok(())
+}
+
 #[api(
     protected: true,
     input: {
@@ -470,6 +560,7 @@ pub fn update_datastore(
     },
     access: {
         permission: &Permission::Privilege(&["datastore", "{name}"], PRIV_DATASTORE_ALLOCATE, false),
+        description: "Destroying the data is not allowed for append-only users and tokens.",
     },
     returns: {
         schema: UPID_SCHEMA,
@@ -483,6 +574,9 @@ pub async fn delete_datastore(
     digest: Option<String>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    check_delete_datastore_access(&CachedUserInfo::new()?, &auth_id, destroy_data)?;
+
     let _lock = pbs_config::datastore::lock_config()?;
 
     let (config, expected_digest) = pbs_config::datastore::config()?;
@@ -520,7 +614,6 @@ pub async fn delete_datastore(
         }
     }
 
-    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
 
     let upid = WorkerTask::new_thread(
@@ -557,3 +650,37 @@ pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_DATASTORES)
     .post(&API_METHOD_CREATE_DATASTORE)
     .match_all("name", &ITEM_ROUTER);
+
+#[test]
+fn delete_datastore_access_test() -> Result<(), Error> {
+    let (user_cfg, _) = pbs_config::user::test_cfg_from_str(
+        r###"
+user: admin@pbs
+
+user: appendonly@pbs
+	append-only true
+
+"###,
+    )
+    .expect("test user.cfg is not parsable");
+    let acl_tree = pbs_config::acl::AclTree::from_raw(
+        r###"
+acl:1:/datastore/localstore1:admin@pbs,appendonly@pbs:Admin
+"###,
+    )
+    .expect("test acl.cfg is not parsable");
+
+    let user_info = CachedUserInfo::test_new(user_cfg, acl_tree);
+
+    let admin_auth_id: Authid = "admin@pbs".parse()?;
+    let append_only_auth_id: Authid = "appendonly@pbs".parse()?;
+
+    assert!(check_delete_datastore_access(&user_info, &admin_auth_id, false).is_ok());
+    assert!(check_delete_datastore_access(&user_info, &admin_auth_id, true).is_ok());
+
+    // removing only the configuration keeps all backups
+    assert!(check_delete_datastore_access(&user_info, &append_only_auth_id, false).is_ok());
+    assert!(check_delete_datastore_access(&user_info, &append_only_auth_id, true).is_err());
+
+    Ok(())
+}
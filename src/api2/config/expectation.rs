@@ -0,0 +1,321 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+use serde_json::Value;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    Authid, BackupExpectationConfig, BackupExpectationConfigUpdater, JOB_ID_SCHEMA,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+use pbs_config::expectation;
+
+use pbs_config::CachedUserInfo;
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "List configured backup expectations.",
+        type: Array,
+        items: { type: BackupExpectationConfig },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Audit.",
+    },
+)]
+/// List all backup expectations.
+pub fn list_expectations(
+    _param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<BackupExpectationConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, digest) = expectation::config()?;
+
+    let list = config.convert_to_typed_array("expectation")?;
+
+    let list = list
+        .into_iter()
+        .filter(|expectation: &BackupExpectationConfig| {
+            let privs = user_info.lookup_privs(&auth_id, &expectation.acl_path());
+            privs & PRIV_DATASTORE_AUDIT != 0
+        })
+        .collect();
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: BackupExpectationConfig,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Modify on the expectation's datastore.",
+    },
+)]
+/// Create a new backup expectation.
+pub fn create_expectation(
+    config: BackupExpectationConfig,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    user_info.check_privs(&auth_id, &config.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+
+    let _lock = expectation::lock_config()?;
+
+    let (mut section_config, _digest) = expectation::config()?;
+
+    if section_config.sections.get(&config.id).is_some() {
+        param_bail!("id", "expectation '{}' already exists.", config.id);
+    }
+
+    section_config.set_data(&config.id, "expectation", &config)?;
+
+    expectation::save_config(&section_config)?;
+
+    Ok(())
+}
+
+#[api(
+   input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: BackupExpectationConfig },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Audit on the expectation's datastore.",
+    },
+)]
+/// Read a backup expectation.
+pub fn read_expectation(
+    id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<BackupExpectationConfig, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, digest) = expectation::config()?;
+
+    let data: BackupExpectationConfig = config.lookup("expectation", &id)?;
+
+    user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_AUDIT, true)?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(data)
+}
+
+#[api]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Reset the namespace to the root namespace.
+    Ns,
+    /// Check groups of all types.
+    BackupType,
+    /// Check groups with any ID.
+    BackupId,
+    /// Delete the comment.
+    Comment,
+    /// Unset the disable flag.
+    Disable,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            update: {
+                type: BackupExpectationConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Modify on the expectation's datastore.",
+    },
+)]
+/// Update a backup expectation.
+pub fn update_expectation(
+    id: String,
+    update: BackupExpectationConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let _lock = expectation::lock_config()?;
+
+    let (mut config, expected_digest) = expectation::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: BackupExpectationConfig = config.lookup("expectation", &id)?;
+
+    user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Ns => {
+                    data.ns = None;
+                }
+                DeletableProperty::BackupType => {
+                    data.backup_type = None;
+                }
+                DeletableProperty::BackupId => {
+                    data.backup_id = None;
+                }
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Disable => {
+                    data.disable = None;
+                }
+            }
+        }
+    }
+
+    let mut recheck_privs = false;
+    if let Some(store) = update.store {
+        recheck_privs = true;
+        data.store = store;
+    }
+    if let Some(ns) = update.ns {
+        recheck_privs = true;
+        data.ns = if ns.is_root() { None } else { Some(ns) };
+    }
+    if recheck_privs {
+        user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+    }
+
+    if update.backup_type.is_some() {
+        data.backup_type = update.backup_type;
+    }
+    if update.backup_id.is_some() {
+        data.backup_id = update.backup_id;
+    }
+    if let Some(max_age) = update.max_age {
+        data.max_age = max_age;
+    }
+    if let Some(comment) = update.comment {
+        let comment = comment.trim().to_string();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment);
+        }
+    }
+    if update.disable.is_some() {
+        data.disable = update.disable;
+    }
+
+    config.set_data(&id, "expectation", &data)?;
+
+    expectation::save_config(&config)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Modify on the expectation's datastore.",
+    },
+)]
+/// Remove a backup expectation.
+pub fn delete_expectation(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let _lock = expectation::lock_config()?;
+
+    let (mut config, expected_digest) = expectation::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let data: BackupExpectationConfig = match config.lookup("expectation", &id) {
+        Ok(data) => data,
+        Err(_) => http_bail!(NOT_FOUND, "expectation '{}' does not exist.", id),
+    };
+
+    user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+
+    config.sections.remove(&id);
+
+    expectation::save_config(&config)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_EXPECTATION)
+    .put(&API_METHOD_UPDATE_EXPECTATION)
+    .delete(&API_METHOD_DELETE_EXPECTATION);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_EXPECTATIONS)
+    .post(&API_METHOD_CREATE_EXPECTATION)
+    .match_all("id", &ITEM_ROUTER);
@@ -0,0 +1,287 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::{bail, Error};
+use hex::FromHex;
+use serde_json::Value;
+
+use proxmox_router::{http_bail, ApiMethod, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    Authid, HookConfig, HookConfigUpdater, Userid, HOOK_ID_SCHEMA, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+// hook scripts are run by the server, so only allow root to configure them
+fn check_root(rpcenv: &dyn RpcEnvironment) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    if auth_id.is_token() || auth_id.user() != Userid::root_userid() {
+        bail!("only root@pam can configure hook scripts");
+    }
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "The list of configured hook scripts (with config digest).",
+        type: Array,
+        items: { type: HookConfig },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// List hook scripts
+pub fn list_hooks(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<HookConfig>, Error> {
+    let (config, digest) = pbs_config::hook::config()?;
+
+    let list: Vec<HookConfig> = config.convert_to_typed_array("hook")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: HookConfig,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+        description: "Only root@pam can configure hook scripts.",
+    },
+)]
+/// Create a new hook script entry.
+pub fn create_hook(config: HookConfig, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    check_root(rpcenv)?;
+
+    let _lock = pbs_config::hook::lock_config()?;
+
+    let (mut section_config, _digest) = pbs_config::hook::config()?;
+
+    if section_config.sections.get(&config.id).is_some() {
+        param_bail!("id", "hook '{}' already exists.", config.id);
+    }
+
+    section_config.set_data(&config.id, "hook", &config)?;
+
+    pbs_config::hook::save_config(&section_config)?;
+
+    Ok(())
+}
+
+#[api(
+   input: {
+        properties: {
+            id: {
+                schema: HOOK_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: HookConfig },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_AUDIT, false),
+    }
+)]
+/// Read a hook script entry.
+pub fn read_hook(
+    id: String,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<HookConfig, Error> {
+    let (config, digest) = pbs_config::hook::config()?;
+    let data: HookConfig = config.lookup("hook", &id)?;
+    rpcenv["digest"] = hex::encode(digest).into();
+    Ok(data)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the job-type property.
+    JobType,
+    /// Delete the store property.
+    Store,
+    /// Delete the timeout property.
+    Timeout,
+    /// Delete the comment property.
+    Comment,
+    /// Delete the disable property.
+    Disable,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: HOOK_ID_SCHEMA,
+            },
+            update: {
+                type: HookConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+        description: "Only root@pam can configure hook scripts.",
+    },
+)]
+/// Update a hook script entry.
+pub fn update_hook(
+    id: String,
+    update: HookConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    check_root(rpcenv)?;
+
+    let _lock = pbs_config::hook::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::hook::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: HookConfig = config.lookup("hook", &id)?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::JobType => {
+                    data.job_type = None;
+                }
+                DeletableProperty::Store => {
+                    data.store = None;
+                }
+                DeletableProperty::Timeout => {
+                    data.timeout = None;
+                }
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Disable => {
+                    data.disable = None;
+                }
+            }
+        }
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim().to_string();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment);
+        }
+    }
+
+    if let Some(script) = update.script {
+        data.script = script;
+    }
+    if update.job_type.is_some() {
+        data.job_type = update.job_type;
+    }
+    if update.store.is_some() {
+        data.store = update.store;
+    }
+    if update.timeout.is_some() {
+        data.timeout = update.timeout;
+    }
+    if update.disable.is_some() {
+        data.disable = update.disable;
+    }
+
+    config.set_data(&id, "hook", &data)?;
+
+    pbs_config::hook::save_config(&config)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: HOOK_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+        description: "Only root@pam can configure hook scripts.",
+    },
+)]
+/// Remove a hook script entry from the configuration file.
+pub fn delete_hook(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    check_root(rpcenv)?;
+
+    let _lock = pbs_config::hook::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::hook::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    match config.sections.get(&id) {
+        Some(_) => {
+            config.sections.remove(&id);
+        }
+        None => http_bail!(NOT_FOUND, "hook '{}' does not exist.", id),
+    }
+
+    pbs_config::hook::save_config(&config)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_HOOK)
+    .put(&API_METHOD_UPDATE_HOOK)
+    .delete(&API_METHOD_DELETE_HOOK);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_HOOKS)
+    .post(&API_METHOD_CREATE_HOOK)
+    .match_all("id", &ITEM_ROUTER);
@@ -0,0 +1,285 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+use serde_json::Value;
+
+use proxmox_router::{http_bail, ApiMethod, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    LogForwardConfig, LogForwardConfigUpdater, LOG_FORWARD_ID_SCHEMA, PRIV_SYS_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "The list of configured log forwarders (with config digest).",
+        type: Array,
+        items: { type: LogForwardConfig },
+    },
+    access: {
+        // the targets reveal the internal infrastructure
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// List log forwarders
+pub fn list_log_forwarders(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<LogForwardConfig>, Error> {
+    let (config, digest) = pbs_config::log_forward::config()?;
+
+    let list: Vec<LogForwardConfig> = config.convert_to_typed_array("log-forward")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: LogForwardConfig,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Create a new log forwarder.
+pub fn create_log_forward(config: LogForwardConfig) -> Result<(), Error> {
+    let _lock = pbs_config::log_forward::lock_config()?;
+
+    let (mut section_config, _digest) = pbs_config::log_forward::config()?;
+
+    if section_config.sections.get(&config.name).is_some() {
+        param_bail!("name", "log forwarder '{}' already exists.", config.name);
+    }
+
+    config.check_target()?;
+
+    section_config.set_data(&config.name, "log-forward", &config)?;
+
+    pbs_config::log_forward::save_config(&section_config)?;
+
+    Ok(())
+}
+
+#[api(
+   input: {
+        properties: {
+            name: {
+                schema: LOG_FORWARD_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: LogForwardConfig },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    }
+)]
+/// Read a log forwarder configuration.
+pub fn read_log_forward(
+    name: String,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<LogForwardConfig, Error> {
+    let (config, digest) = pbs_config::log_forward::config()?;
+    let data: LogForwardConfig = config.lookup("log-forward", &name)?;
+    rpcenv["digest"] = hex::encode(digest).into();
+    Ok(data)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the protocol property.
+    Protocol,
+    /// Delete the format property.
+    Format,
+    /// Delete the job-type property.
+    JobType,
+    /// Delete the failures-only property.
+    FailuresOnly,
+    /// Delete the store property.
+    Store,
+    /// Delete the comment property.
+    Comment,
+    /// Delete the disable property.
+    Disable,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: LOG_FORWARD_ID_SCHEMA,
+            },
+            update: {
+                type: LogForwardConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Update a log forwarder configuration.
+pub fn update_log_forward(
+    name: String,
+    update: LogForwardConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+) -> Result<(), Error> {
+    let _lock = pbs_config::log_forward::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::log_forward::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: LogForwardConfig = config.lookup("log-forward", &name)?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Protocol => {
+                    data.protocol = None;
+                }
+                DeletableProperty::Format => {
+                    data.format = None;
+                }
+                DeletableProperty::JobType => {
+                    data.job_type = None;
+                }
+                DeletableProperty::FailuresOnly => {
+                    data.failures_only = None;
+                }
+                DeletableProperty::Store => {
+                    data.store = None;
+                }
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Disable => {
+                    data.disable = None;
+                }
+            }
+        }
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim().to_string();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment);
+        }
+    }
+
+    if update.protocol.is_some() {
+        data.protocol = update.protocol;
+    }
+    if let Some(target) = update.target {
+        data.target = target;
+    }
+    if update.format.is_some() {
+        data.format = update.format;
+    }
+    if update.job_type.is_some() {
+        data.job_type = update.job_type;
+    }
+    if update.failures_only.is_some() {
+        data.failures_only = update.failures_only;
+    }
+    if update.store.is_some() {
+        data.store = update.store;
+    }
+    if update.disable.is_some() {
+        data.disable = update.disable;
+    }
+
+    data.check_target()?;
+
+    config.set_data(&name, "log-forward", &data)?;
+
+    pbs_config::log_forward::save_config(&config)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: LOG_FORWARD_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Remove a log forwarder from the configuration file.
+pub fn delete_log_forward(name: String, digest: Option<String>) -> Result<(), Error> {
+    let _lock = pbs_config::log_forward::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::log_forward::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    match config.sections.get(&name) {
+        Some(_) => {
+            config.sections.remove(&name);
+        }
+        None => http_bail!(NOT_FOUND, "log forwarder '{}' does not exist.", name),
+    }
+
+    pbs_config::log_forward::save_config(&config)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_LOG_FORWARD)
+    .put(&API_METHOD_UPDATE_LOG_FORWARD)
+    .delete(&API_METHOD_DELETE_LOG_FORWARD);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_LOG_FORWARDERS)
+    .post(&API_METHOD_CREATE_LOG_FORWARD)
+    .match_all("name", &ITEM_ROUTER);
@@ -114,6 +114,8 @@ pub enum DeletableProperty {
     Template,
     /// Delete encryption fingerprint
     Encrypt,
+    /// Delete media set slots
+    Slots,
     /// Delete comment
     Comment,
 }
@@ -170,6 +172,9 @@ pub fn update_pool(
                 DeletableProperty::Encrypt => {
                     data.encrypt = None;
                 }
+                DeletableProperty::Slots => {
+                    data.slots = None;
+                }
                 DeletableProperty::Comment => {
                     data.comment = None;
                 }
@@ -189,6 +194,9 @@ pub fn update_pool(
     if update.encrypt.is_some() {
         data.encrypt = update.encrypt;
     }
+    if update.slots.is_some() {
+        data.slots = update.slots;
+    }
 
     if let Some(comment) = update.comment {
         let comment = comment.trim();
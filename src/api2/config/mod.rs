@@ -9,6 +9,9 @@ pub mod acme;
 pub mod changer;
 pub mod datastore;
 pub mod drive;
+pub mod expectation;
+pub mod hook;
+pub mod log_forward;
 pub mod media_pool;
 pub mod metrics;
 pub mod notifications;
@@ -19,6 +22,7 @@ pub mod tape_backup_job;
 pub mod tape_encryption_keys;
 pub mod traffic_control;
 pub mod verify;
+pub mod webhook;
 
 #[sortable]
 const SUBDIRS: SubdirMap = &sorted!([
@@ -27,6 +31,9 @@ const SUBDIRS: SubdirMap = &sorted!([
     ("changer", &changer::ROUTER),
     ("datastore", &datastore::ROUTER),
     ("drive", &drive::ROUTER),
+    ("expectation", &expectation::ROUTER),
+    ("hook", &hook::ROUTER),
+    ("log-forward", &log_forward::ROUTER),
     ("media-pool", &media_pool::ROUTER),
     ("metrics", &metrics::ROUTER),
     ("notifications", &notifications::ROUTER),
@@ -37,6 +44,7 @@ const SUBDIRS: SubdirMap = &sorted!([
     ("tape-encryption-keys", &tape_encryption_keys::ROUTER),
     ("traffic-control", &traffic_control::ROUTER),
     ("verify", &verify::ROUTER),
+    ("webhook", &webhook::ROUTER),
 ]);
 
 pub const ROUTER: Router = Router::new()
@@ -16,6 +16,20 @@ use pbs_config::prune;
 
 use pbs_config::CachedUserInfo;
 
+use crate::backup::check_user_not_append_only;
+
+/// Checks that `auth_id` may create, modify or run the prune job `job`.
+///
+/// Prune jobs remove snapshots, so append-only users and tokens are refused.
+pub fn check_prune_job_modify_access(
+    user_info: &CachedUserInfo,
+    auth_id: &Authid,
+    job: &PruneJobConfig,
+) -> Result<(), Error> {
+    user_info.check_privs(auth_id, &job.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+    check_user_not_append_only(user_info, auth_id)
+}
+
 #[api(
     input: {
         properties: {},
@@ -95,7 +109,7 @@ pub fn do_create_prune_job(
     },
     access: {
         permission: &Permission::Anybody,
-        description: "Requires Datastore.Modify on job's datastore.",
+        description: "Requires Datastore.Modify on job's datastore, not allowed for append-only users and tokens.",
     },
 )]
 /// Create a new prune job.
@@ -106,7 +120,7 @@ pub fn create_prune_job(
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let user_info = CachedUserInfo::new()?;
 
-    user_info.check_privs(&auth_id, &config.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+    check_prune_job_modify_access(&user_info, &auth_id, &config)?;
 
     do_create_prune_job(config, None)
 }
@@ -154,6 +168,8 @@ pub enum DeletableProperty {
     Comment,
     /// Unset the disable flag.
     Disable,
+    /// Delete the job splay.
+    Splay,
     /// Reset the namespace to the root namespace.
     Ns,
     /// Reset the maximum depth to full recursion.
@@ -199,7 +215,7 @@ pub enum DeletableProperty {
     },
     access: {
         permission: &Permission::Anybody,
-        description: "Requires Datastore.Modify on job's datastore.",
+        description: "Requires Datastore.Modify on job's datastore, not allowed for append-only users and tokens.",
     },
 )]
 /// Update prune job config.
@@ -226,7 +242,7 @@ pub fn update_prune_job(
 
     let mut data: PruneJobConfig = config.lookup("prune", &id)?;
 
-    user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+    check_prune_job_modify_access(&user_info, &auth_id, &data)?;
 
     if let Some(delete) = delete {
         for delete_prop in delete {
@@ -237,6 +253,9 @@ pub fn update_prune_job(
                 DeletableProperty::Disable => {
                     data.disable = false;
                 }
+                DeletableProperty::Splay => {
+                    data.splay = None;
+                }
                 DeletableProperty::Ns => {
                     data.options.ns = None;
                 }
@@ -278,7 +297,7 @@ pub fn update_prune_job(
     }
 
     if recheck_privs {
-        user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+        check_prune_job_modify_access(&user_info, &auth_id, &data)?;
     }
 
     let mut schedule_changed = false;
@@ -287,6 +306,10 @@ pub fn update_prune_job(
         data.schedule = schedule;
     }
 
+    if update.splay.is_some() {
+        data.splay = update.splay;
+    }
+
     if let Some(max_depth) = update.options.max_depth {
         if max_depth <= pbs_api_types::MAX_NAMESPACE_DEPTH {
             data.options.max_depth = Some(max_depth);
@@ -389,3 +412,60 @@ pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_PRUNE_JOBS)
     .post(&API_METHOD_CREATE_PRUNE_JOB)
     .match_all("id", &ITEM_ROUTER);
+
+#[test]
+fn prune_job_access_test() -> Result<(), Error> {
+    let (user_cfg, _) = pbs_config::user::test_cfg_from_str(
+        r###"
+user: noperm@pbs
+
+user: write@pbs
+
+user: appendonly@pbs
+	append-only true
+
+user: tokenowner@pbs
+
+token: tokenowner@pbs!appendonly
+	append-only true
+
+"###,
+    )
+    .expect("test user.cfg is not parsable");
+    let acl_tree = pbs_config::acl::AclTree::from_raw(
+        r###"
+acl:1:/datastore/localstore1:write@pbs,appendonly@pbs:DatastoreAdmin
+acl:1:/datastore/localstore1:tokenowner@pbs,tokenowner@pbs!appendonly:DatastoreAdmin
+"###,
+    )
+    .expect("test acl.cfg is not parsable");
+
+    let user_info = CachedUserInfo::test_new(user_cfg, acl_tree);
+
+    let job = PruneJobConfig {
+        id: "prune1".to_string(),
+        store: "localstore1".to_string(),
+        disable: false,
+        schedule: "daily".to_string(),
+        splay: None,
+        comment: None,
+        options: pbs_api_types::PruneJobOptions::default(),
+    };
+
+    let no_perm_auth_id: Authid = "noperm@pbs".parse()?;
+    let write_auth_id: Authid = "write@pbs".parse()?;
+    let append_only_auth_id: Authid = "appendonly@pbs".parse()?;
+    let token_owner_auth_id: Authid = "tokenowner@pbs".parse()?;
+    let append_only_token_id: Authid = "tokenowner@pbs!appendonly".parse()?;
+
+    assert!(check_prune_job_modify_access(&user_info, Authid::root_auth_id(), &job).is_ok());
+    assert!(check_prune_job_modify_access(&user_info, &write_auth_id, &job).is_ok());
+    assert!(check_prune_job_modify_access(&user_info, &token_owner_auth_id, &job).is_ok());
+    assert!(check_prune_job_modify_access(&user_info, &no_perm_auth_id, &job).is_err());
+
+    // append-only users and tokens must not prune, even with full privileges
+    assert!(check_prune_job_modify_access(&user_info, &append_only_auth_id, &job).is_err());
+    assert!(check_prune_job_modify_access(&user_info, &append_only_token_id, &job).is_err());
+
+    Ok(())
+}
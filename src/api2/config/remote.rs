@@ -314,6 +314,8 @@ pub fn remote_client_config(
         options = options.rate_limit(limit);
     }
 
+    options = options.abort_signal(crate::tools::async_io::shutdown_abort_signal());
+
     let client = HttpClient::new(
         &remote.config.host,
         remote.config.port.unwrap_or(8007),
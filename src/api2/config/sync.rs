@@ -49,7 +49,7 @@ pub fn check_sync_job_modify_access(
     }
 
     if let Some(true) = job.remove_vanished {
-        if ns_anchor_privs & PRIV_DATASTORE_PRUNE == 0 {
+        if ns_anchor_privs & PRIV_DATASTORE_PRUNE == 0 || user_info.is_append_only(auth_id) {
             return false;
         }
     }
@@ -211,6 +211,8 @@ pub enum DeletableProperty {
     Comment,
     /// Delete the job schedule.
     Schedule,
+    /// Delete the job splay.
+    Splay,
     /// Delete the remove-vanished flag.
     RemoveVanished,
     /// Delete the group_filter property.
@@ -231,6 +233,8 @@ pub enum DeletableProperty {
     MaxDepth,
     /// Delete the transfer_last property,
     TransferLast,
+    /// Delete the verify_synced property,
+    VerifySynced,
 }
 
 #[api(
@@ -301,6 +305,9 @@ pub fn update_sync_job(
                 DeletableProperty::Schedule => {
                     data.schedule = None;
                 }
+                DeletableProperty::Splay => {
+                    data.splay = None;
+                }
                 DeletableProperty::RemoveVanished => {
                     data.remove_vanished = None;
                 }
@@ -331,6 +338,9 @@ pub fn update_sync_job(
                 DeletableProperty::TransferLast => {
                     data.transfer_last = None;
                 }
+                DeletableProperty::VerifySynced => {
+                    data.verify_synced = None;
+                }
             }
         }
     }
@@ -389,9 +399,15 @@ pub fn update_sync_job(
     if update.schedule.is_some() {
         data.schedule = update.schedule;
     }
+    if update.splay.is_some() {
+        data.splay = update.splay;
+    }
     if update.remove_vanished.is_some() {
         data.remove_vanished = update.remove_vanished;
     }
+    if update.verify_synced.is_some() {
+        data.verify_synced = update.verify_synced;
+    }
     if let Some(max_depth) = update.max_depth {
         data.max_depth = Some(max_depth);
     }
@@ -495,6 +511,9 @@ user: read@pbs
 
 user: write@pbs
 
+user: appendonly@pbs
+	append-only true
+
 "###,
     )
     .expect("test user.cfg is not parsable");
@@ -504,8 +523,9 @@ acl:1:/datastore/localstore1:read@pbs,write@pbs:DatastoreAudit
 acl:1:/datastore/localstore1:write@pbs:DatastoreBackup
 acl:1:/datastore/localstore2:write@pbs:DatastorePowerUser
 acl:1:/datastore/localstore3:write@pbs:DatastoreAdmin
+acl:1:/datastore/localstore3:appendonly@pbs:DatastoreAdmin
 acl:1:/remote/remote1:read@pbs,write@pbs:RemoteAudit
-acl:1:/remote/remote1/remotestore1:write@pbs:RemoteSyncOperator
+acl:1:/remote/remote1/remotestore1:write@pbs,appendonly@pbs:RemoteSyncOperator
 "###,
     )
     .expect("test acl.cfg is not parsable");
@@ -531,8 +551,10 @@ acl:1:/remote/remote1/remotestore1:write@pbs:RemoteSyncOperator
         max_depth: None,
         group_filter: None,
         schedule: None,
+        splay: None,
         limit: pbs_api_types::RateLimitConfig::default(), // no limit
         transfer_last: None,
+        verify_synced: None,
     };
 
     // should work without ACLs
@@ -662,5 +684,20 @@ acl:1:/remote/remote1/remotestore1:write@pbs:RemoteSyncOperator
         &job
     ));
 
+    // append-only users can't remove vanished snapshots, regardless of privileges
+    let append_only_auth_id: Authid = "appendonly@pbs".parse()?;
+    job.owner = Some(append_only_auth_id.clone());
+    assert!(!check_sync_job_modify_access(
+        &user_info,
+        &append_only_auth_id,
+        &job
+    ));
+    job.remove_vanished = None;
+    assert!(check_sync_job_modify_access(
+        &user_info,
+        &append_only_auth_id,
+        &job
+    ));
+
     Ok(())
 }
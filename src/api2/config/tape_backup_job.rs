@@ -124,12 +124,16 @@ pub enum DeletableProperty {
     Comment,
     /// Delete the job schedule.
     Schedule,
+    /// Delete the job splay.
+    Splay,
     /// Delete the eject-media property
     EjectMedia,
     /// Delete the export-media-set property
     ExportMediaSet,
     /// Delete the 'latest-only' property
     LatestOnly,
+    /// Delete the 'since-last-run' property
+    SinceLastRun,
     /// Delete the 'notify-user' property
     NotifyUser,
     /// Delete the 'notification-mode' property
@@ -201,6 +205,9 @@ pub fn update_tape_backup_job(
                 DeletableProperty::LatestOnly => {
                     data.setup.latest_only = None;
                 }
+                DeletableProperty::SinceLastRun => {
+                    data.setup.since_last_run = None;
+                }
                 DeletableProperty::NotifyUser => {
                     data.setup.notify_user = None;
                 }
@@ -210,6 +217,9 @@ pub fn update_tape_backup_job(
                 DeletableProperty::Schedule => {
                     data.schedule = None;
                 }
+                DeletableProperty::Splay => {
+                    data.splay = None;
+                }
                 DeletableProperty::Comment => {
                     data.comment = None;
                 }
@@ -245,6 +255,9 @@ pub fn update_tape_backup_job(
     if update.setup.latest_only.is_some() {
         data.setup.latest_only = update.setup.latest_only;
     }
+    if update.setup.since_last_run.is_some() {
+        data.setup.since_last_run = update.setup.since_last_run;
+    }
     if update.setup.notify_user.is_some() {
         data.setup.notify_user = update.setup.notify_user;
     }
@@ -265,6 +278,9 @@ pub fn update_tape_backup_job(
     if update.schedule.is_some() {
         data.schedule = update.schedule;
     }
+    if update.splay.is_some() {
+        data.splay = update.splay;
+    }
 
     if let Some(comment) = update.comment {
         let comment = comment.trim();
@@ -39,6 +39,16 @@ pub fn list_traffic_controls(
     Ok(list)
 }
 
+fn check_restore_share(config: &TrafficControlRule) -> Result<(), Error> {
+    if config.restore_share.is_some() && !config.is_session_rule() {
+        param_bail!(
+            "restore-share",
+            "restore share is only supported for rules restricted to users or datastores"
+        );
+    }
+    Ok(())
+}
+
 #[api(
     protected: true,
     input: {
@@ -67,6 +77,8 @@ pub fn create_traffic_control(config: TrafficControlRule) -> Result<(), Error> {
         );
     }
 
+    check_restore_share(&config)?;
+
     section_config.set_data(&config.name, "rule", &config)?;
 
     pbs_config::traffic_control::save_config(&section_config)?;
@@ -116,6 +128,12 @@ pub enum DeletableProperty {
     Comment,
     /// Delete the timeframe property
     Timeframe,
+    /// Delete the users property
+    Users,
+    /// Delete the datastores property
+    Datastores,
+    /// Delete the restore-share property
+    RestoreShare,
 }
 
 // fixme: use  TrafficControlUpdater
@@ -187,6 +205,15 @@ pub fn update_traffic_control(
                 DeletableProperty::Timeframe => {
                     data.timeframe = None;
                 }
+                DeletableProperty::Users => {
+                    data.users = None;
+                }
+                DeletableProperty::Datastores => {
+                    data.datastores = None;
+                }
+                DeletableProperty::RestoreShare => {
+                    data.restore_share = None;
+                }
             }
         }
     }
@@ -222,6 +249,17 @@ pub fn update_traffic_control(
     if update.timeframe.is_some() {
         data.timeframe = update.timeframe;
     }
+    if update.users.is_some() {
+        data.users = update.users;
+    }
+    if update.datastores.is_some() {
+        data.datastores = update.datastores;
+    }
+    if update.restore_share.is_some() {
+        data.restore_share = update.restore_share;
+    }
+
+    check_restore_share(&data)?;
 
     config.set_data(&name, "rule", &data)?;
 
@@ -143,12 +143,16 @@ pub enum DeletableProperty {
     Comment,
     /// Delete the job schedule.
     Schedule,
+    /// Delete the job splay.
+    Splay,
     /// Delete outdated after property.
     OutdatedAfter,
     /// Delete namespace property, defaulting to root namespace then.
     Ns,
     /// Delete max-depth property, defaulting to full recursion again
     MaxDepth,
+    /// Delete the attest property.
+    Attest,
 }
 
 #[api(
@@ -223,12 +227,18 @@ pub fn update_verification_job(
                 DeletableProperty::Schedule => {
                     data.schedule = None;
                 }
+                DeletableProperty::Splay => {
+                    data.splay = None;
+                }
                 DeletableProperty::Ns => {
                     data.ns = None;
                 }
                 DeletableProperty::MaxDepth => {
                     data.max_depth = None;
                 }
+                DeletableProperty::Attest => {
+                    data.attest = None;
+                }
             }
         }
     }
@@ -252,10 +262,16 @@ pub fn update_verification_job(
     if update.outdated_after.is_some() {
         data.outdated_after = update.outdated_after;
     }
+    if update.attest.is_some() {
+        data.attest = update.attest;
+    }
     let schedule_changed = data.schedule != update.schedule;
     if update.schedule.is_some() {
         data.schedule = update.schedule;
     }
+    if update.splay.is_some() {
+        data.splay = update.splay;
+    }
     if let Some(ns) = update.ns {
         if !ns.is_root() {
             data.ns = Some(ns);
@@ -0,0 +1,281 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+use serde_json::Value;
+
+use proxmox_router::{http_bail, ApiMethod, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    WebhookConfig, WebhookConfigUpdater, PRIV_SYS_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
+    WEBHOOK_ID_SCHEMA,
+};
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "The list of configured webhooks (with config digest).",
+        type: Array,
+        items: { type: WebhookConfig },
+    },
+    access: {
+        // headers may contain credentials
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// List webhooks
+pub fn list_webhooks(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<WebhookConfig>, Error> {
+    let (config, digest) = pbs_config::webhook::config()?;
+
+    let list: Vec<WebhookConfig> = config.convert_to_typed_array("webhook")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: WebhookConfig,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Create a new webhook.
+pub fn create_webhook(config: WebhookConfig) -> Result<(), Error> {
+    let _lock = pbs_config::webhook::lock_config()?;
+
+    let (mut section_config, _digest) = pbs_config::webhook::config()?;
+
+    if section_config.sections.get(&config.name).is_some() {
+        param_bail!("name", "webhook '{}' already exists.", config.name);
+    }
+
+    section_config.set_data(&config.name, "webhook", &config)?;
+
+    pbs_config::webhook::save_config(&section_config)?;
+
+    Ok(())
+}
+
+#[api(
+   input: {
+        properties: {
+            name: {
+                schema: WEBHOOK_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: WebhookConfig },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    }
+)]
+/// Read a webhook configuration.
+pub fn read_webhook(
+    name: String,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<WebhookConfig, Error> {
+    let (config, digest) = pbs_config::webhook::config()?;
+    let data: WebhookConfig = config.lookup("webhook", &name)?;
+    rpcenv["digest"] = hex::encode(digest).into();
+    Ok(data)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the header property.
+    Header,
+    /// Delete the trigger property.
+    Trigger,
+    /// Delete the retries property.
+    Retries,
+    /// Delete the retry-delay property.
+    RetryDelay,
+    /// Delete the store property.
+    Store,
+    /// Delete the comment property.
+    Comment,
+    /// Delete the disable property.
+    Disable,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: WEBHOOK_ID_SCHEMA,
+            },
+            update: {
+                type: WebhookConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Update a webhook configuration.
+pub fn update_webhook(
+    name: String,
+    update: WebhookConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+) -> Result<(), Error> {
+    let _lock = pbs_config::webhook::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::webhook::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: WebhookConfig = config.lookup("webhook", &name)?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Header => {
+                    data.header = None;
+                }
+                DeletableProperty::Trigger => {
+                    data.trigger = None;
+                }
+                DeletableProperty::Retries => {
+                    data.retries = None;
+                }
+                DeletableProperty::RetryDelay => {
+                    data.retry_delay = None;
+                }
+                DeletableProperty::Store => {
+                    data.store = None;
+                }
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Disable => {
+                    data.disable = None;
+                }
+            }
+        }
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim().to_string();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment);
+        }
+    }
+
+    if let Some(url) = update.url {
+        data.url = url;
+    }
+    if update.header.is_some() {
+        data.header = update.header;
+    }
+    if update.trigger.is_some() {
+        data.trigger = update.trigger;
+    }
+    if update.retries.is_some() {
+        data.retries = update.retries;
+    }
+    if update.retry_delay.is_some() {
+        data.retry_delay = update.retry_delay;
+    }
+    if update.store.is_some() {
+        data.store = update.store;
+    }
+    if update.disable.is_some() {
+        data.disable = update.disable;
+    }
+
+    config.set_data(&name, "webhook", &data)?;
+
+    pbs_config::webhook::save_config(&config)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: WEBHOOK_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Remove a webhook from the configuration file.
+pub fn delete_webhook(name: String, digest: Option<String>) -> Result<(), Error> {
+    let _lock = pbs_config::webhook::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::webhook::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    match config.sections.get(&name) {
+        Some(_) => {
+            config.sections.remove(&name);
+        }
+        None => http_bail!(NOT_FOUND, "webhook '{}' does not exist.", name),
+    }
+
+    pbs_config::webhook::save_config(&config)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_WEBHOOK)
+    .put(&API_METHOD_UPDATE_WEBHOOK)
+    .delete(&API_METHOD_DELETE_WEBHOOK);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_WEBHOOKS)
+    .post(&API_METHOD_CREATE_WEBHOOK)
+    .match_all("name", &ITEM_ROUTER);
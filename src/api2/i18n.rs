@@ -0,0 +1,67 @@
+//! Translated labels of GUI-facing values
+
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{list_subdirs_api_method, Permission, Router, RpcEnvironment, SubdirMap};
+use proxmox_schema::{api, ApiType};
+use proxmox_sortable_macro::sortable;
+
+use pbs_api_types::{Authid, Translation, User};
+
+use crate::tools::i18n::{enum_labels, DEFAULT_LANG};
+
+// The user's language, then the node's default language.
+fn default_lang(rpcenv: &dyn RpcEnvironment) -> String {
+    let user_lang = rpcenv
+        .get_auth_id()
+        .and_then(|auth_id| auth_id.parse::<Authid>().ok())
+        .and_then(|auth_id| {
+            let config = pbs_config::user::cached_config().ok()?;
+            let user: User = config.lookup("user", auth_id.user().as_str()).ok()?;
+            user.lang
+        });
+
+    user_lang
+        .or_else(|| {
+            crate::config::node::config()
+                .ok()
+                .and_then(|(config, _)| config.default_lang)
+        })
+        .unwrap_or_else(|| DEFAULT_LANG.to_string())
+}
+
+#[api(
+    input: {
+        properties: {
+            lang: {
+                schema: Translation::API_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "Map of enum name to a map of its values to their translated labels.",
+        type: Object,
+        properties: {},
+        additional_properties: true,
+    },
+    access: {
+        permission: &Permission::Anybody,
+    },
+)]
+/// Get translated labels for the values of enums shown in the GUI.
+///
+/// Without `lang`, the language of the current user is used, falling back to
+/// the default language of the node. Untranslated labels are returned in English.
+pub fn get_labels(lang: Option<String>, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let lang = lang.unwrap_or_else(|| default_lang(rpcenv));
+    Ok(Value::Object(enum_labels(&lang)))
+}
+
+#[sortable]
+const SUBDIRS: SubdirMap = &sorted!([("labels", &Router::new().get(&API_METHOD_GET_LABELS))]);
+
+pub const ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(SUBDIRS))
+    .subdirs(SUBDIRS);
@@ -7,6 +7,7 @@ pub mod admin;
 pub mod backup;
 pub mod config;
 pub mod helpers;
+pub mod i18n;
 pub mod node;
 pub mod ping;
 pub mod pull;
@@ -24,6 +25,7 @@ const SUBDIRS: SubdirMap = &sorted!([
     ("admin", &admin::ROUTER),
     ("backup", &backup::ROUTER),
     ("config", &config::ROUTER),
+    ("i18n", &i18n::ROUTER),
     ("nodes", &node::ROUTER),
     ("ping", &ping::ROUTER),
     ("pull", &pull::ROUTER),
@@ -62,7 +62,12 @@ pub fn update_apt_proxy_config(proxy_config: Option<&ProxyConfig>) -> Result<(),
     if let Some(proxy_config) = proxy_config {
         let proxy = proxy_config.to_proxy_string()?;
         let data = format!("Acquire::http::Proxy \"{}\";\n", proxy);
+        if !pbs_config::preview_config_change(PROXY_CFG_FN, data.as_bytes(), false)? {
+            return Ok(());
+        }
         replace_file(PROXY_CFG_FN, data.as_bytes(), CreateOptions::new(), false)
+    } else if pbs_config::config_write_mode() == pbs_config::ConfigWriteMode::DryRun {
+        Ok(())
     } else {
         match std::fs::remove_file(PROXY_CFG_FN) {
             Ok(()) => Ok(()),
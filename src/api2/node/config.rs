@@ -63,10 +63,28 @@ pub enum DeletableProperty {
     CiphersTls1_2,
     /// Delete the default-lang property.
     DefaultLang,
+    /// Delete the notification-lang property.
+    NotificationLang,
     /// Delete any description
     Description,
     /// Delete the task-log-max-days property
     TaskLogMaxDays,
+    /// Delete the backup-session-timeout property
+    BackupSessionTimeout,
+    /// Delete the backup-archive-timeout property
+    BackupArchiveTimeout,
+    /// Delete the reader-max-sessions-per-snapshot property
+    ReaderMaxSessionsPerSnapshot,
+    /// Delete the reader-max-sessions-per-datastore property
+    ReaderMaxSessionsPerDatastore,
+    /// Delete the reader-max-sessions-per-user property
+    ReaderMaxSessionsPerUser,
+    /// Delete the reader-queue-timeout property
+    ReaderQueueTimeout,
+    /// Delete the job-start-limit property
+    JobStartLimit,
+    /// Delete the snmp property
+    Snmp,
 }
 
 #[api(
@@ -149,12 +167,39 @@ pub fn update_node_config(
                 DeletableProperty::DefaultLang => {
                     config.default_lang = None;
                 }
+                DeletableProperty::NotificationLang => {
+                    config.notification_lang = None;
+                }
                 DeletableProperty::Description => {
                     config.description = None;
                 }
                 DeletableProperty::TaskLogMaxDays => {
                     config.task_log_max_days = None;
                 }
+                DeletableProperty::BackupSessionTimeout => {
+                    config.backup_session_timeout = None;
+                }
+                DeletableProperty::BackupArchiveTimeout => {
+                    config.backup_archive_timeout = None;
+                }
+                DeletableProperty::ReaderMaxSessionsPerSnapshot => {
+                    config.reader_max_sessions_per_snapshot = None;
+                }
+                DeletableProperty::ReaderMaxSessionsPerDatastore => {
+                    config.reader_max_sessions_per_datastore = None;
+                }
+                DeletableProperty::ReaderMaxSessionsPerUser => {
+                    config.reader_max_sessions_per_user = None;
+                }
+                DeletableProperty::ReaderQueueTimeout => {
+                    config.reader_queue_timeout = None;
+                }
+                DeletableProperty::JobStartLimit => {
+                    config.job_start_limit = None;
+                }
+                DeletableProperty::Snmp => {
+                    config.snmp = None;
+                }
             }
         }
     }
@@ -192,12 +237,39 @@ pub fn update_node_config(
     if update.default_lang.is_some() {
         config.default_lang = update.default_lang;
     }
+    if update.notification_lang.is_some() {
+        config.notification_lang = update.notification_lang;
+    }
     if update.description.is_some() {
         config.description = update.description;
     }
     if update.task_log_max_days.is_some() {
         config.task_log_max_days = update.task_log_max_days;
     }
+    if update.backup_session_timeout.is_some() {
+        config.backup_session_timeout = update.backup_session_timeout;
+    }
+    if update.backup_archive_timeout.is_some() {
+        config.backup_archive_timeout = update.backup_archive_timeout;
+    }
+    if update.reader_max_sessions_per_snapshot.is_some() {
+        config.reader_max_sessions_per_snapshot = update.reader_max_sessions_per_snapshot;
+    }
+    if update.reader_max_sessions_per_datastore.is_some() {
+        config.reader_max_sessions_per_datastore = update.reader_max_sessions_per_datastore;
+    }
+    if update.reader_max_sessions_per_user.is_some() {
+        config.reader_max_sessions_per_user = update.reader_max_sessions_per_user;
+    }
+    if update.reader_queue_timeout.is_some() {
+        config.reader_queue_timeout = update.reader_queue_timeout;
+    }
+    if update.job_start_limit.is_some() {
+        config.job_start_limit = update.job_start_limit;
+    }
+    if update.snmp.is_some() {
+        config.snmp = update.snmp;
+    }
 
     crate::config::node::save_config(&config)?;
 
@@ -4,7 +4,7 @@ use serde_json::json;
 use std::os::linux::fs::MetadataExt;
 
 use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
-use proxmox_schema::api;
+use proxmox_schema::{api, param_bail};
 use proxmox_section_config::SectionConfigData;
 use proxmox_sys::task_log;
 
@@ -127,6 +127,13 @@ pub fn list_datastore_mounts() -> Result<Vec<DatastoreMountInfo>, Error> {
                 type: FileSystemType,
                 optional: true,
             },
+            "removable-datastore": {
+                description: "Do not mount the filesystem on boot, but configure a datastore \
+                    which is mounted on demand, once the disk is attached. Requires 'add-datastore'.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
          }
     },
     returns: {
@@ -142,8 +149,16 @@ pub fn create_datastore_disk(
     disk: String,
     add_datastore: Option<bool>,
     filesystem: Option<FileSystemType>,
+    removable_datastore: bool,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<String, Error> {
+    if removable_datastore && !add_datastore.unwrap_or(false) {
+        param_bail!(
+            "removable-datastore",
+            "a removable disk can only be used with 'add-datastore'"
+        );
+    }
+
     let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
 
     let auth_id = rpcenv.get_auth_id().unwrap();
@@ -195,17 +210,25 @@ pub fn create_datastore_disk(
             let uuid = get_fs_uuid(&partition)?;
             let uuid_path = format!("/dev/disk/by-uuid/{}", uuid);
 
-            let mount_unit_name =
-                create_datastore_mount_unit(&name, &mount_point, filesystem, &uuid_path)?;
+            if removable_datastore {
+                // mounted on demand by the datastore, see `mount_datastore_device`
+                task_log!(worker, "using removable disk with filesystem UUID '{uuid}'");
+            } else {
+                let mount_unit_name =
+                    create_datastore_mount_unit(&name, &mount_point, filesystem, &uuid_path)?;
 
-            crate::tools::systemd::reload_daemon()?;
-            crate::tools::systemd::enable_unit(&mount_unit_name)?;
-            crate::tools::systemd::start_unit(&mount_unit_name)?;
+                crate::tools::systemd::reload_daemon()?;
+                crate::tools::systemd::enable_unit(&mount_unit_name)?;
+                crate::tools::systemd::start_unit(&mount_unit_name)?;
+            }
 
             if add_datastore {
                 let lock = pbs_config::datastore::lock_config()?;
-                let datastore: DataStoreConfig =
+                let mut datastore: DataStoreConfig =
                     serde_json::from_value(json!({ "name": name, "path": mount_point }))?;
+                if removable_datastore {
+                    datastore.backing_device = Some(uuid);
+                }
 
                 let (config, _digest) = pbs_config::datastore::config()?;
 
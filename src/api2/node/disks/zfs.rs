@@ -174,7 +174,7 @@ pub fn create_zpool(
         .map(|v| v.as_str().unwrap().to_string())
         .collect();
 
-    let disk_map = crate::tools::disks::DiskUsageQuery::new().query()?;
+    let disk_map = crate::tools::disks::DiskUsageQuery::new().find_all(devices.clone())?;
     for disk in devices.iter() {
         match disk_map.get(disk) {
             Some(info) => {
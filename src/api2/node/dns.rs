@@ -189,7 +189,9 @@ pub fn update_dns(
         data.push_str(options);
     }
 
-    replace_file(RESOLV_CONF_FN, data.as_bytes(), CreateOptions::new(), true)?;
+    if pbs_config::preview_config_change(RESOLV_CONF_FN, data.as_bytes(), false)? {
+        replace_file(RESOLV_CONF_FN, data.as_bytes(), CreateOptions::new(), true)?;
+    }
 
     Ok(Value::Null)
 }
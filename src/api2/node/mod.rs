@@ -35,6 +35,7 @@ pub mod config;
 pub mod disks;
 pub mod dns;
 pub mod network;
+pub mod reader_queue;
 pub mod subscription;
 pub mod tasks;
 
@@ -329,6 +330,7 @@ pub const SUBDIRS: SubdirMap = &[
     ("dns", &dns::ROUTER),
     ("journal", &journal::ROUTER),
     ("network", &network::ROUTER),
+    ("reader-queue", &reader_queue::ROUTER),
     ("report", &report::ROUTER),
     ("rrd", &rrd::ROUTER),
     ("services", &services::ROUTER),
@@ -0,0 +1,67 @@
+//! Queued reader sessions (restores)
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    QueuedReaderSession, NODE_SCHEMA, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY, READER_QUEUE_PRIORITY_SCHEMA,
+};
+
+use crate::api2::reader::session_limit;
+
+// Note: the queue lives in the proxy, so these methods must not be 'protected'
+
+#[api(
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Queued reader sessions, in the order they will start.",
+        type: Array,
+        items: { type: QueuedReaderSession },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "tasks"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// List reader sessions waiting for a free session slot.
+pub fn list_queued_reader_sessions() -> Result<Vec<QueuedReaderSession>, Error> {
+    Ok(session_limit::list_queued_sessions())
+}
+
+#[api(
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+            id: {
+                type: u64,
+                description: "Queue ID of the reader session.",
+            },
+            priority: {
+                schema: READER_QUEUE_PRIORITY_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "tasks"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Change the priority of a queued reader session, sessions with higher
+/// priority start first.
+pub fn set_reader_session_priority(id: u64, priority: i64) -> Result<(), Error> {
+    session_limit::set_queued_session_priority(id, priority)
+}
+
+const ITEM_ROUTER: Router = Router::new().put(&API_METHOD_SET_READER_SESSION_PRIORITY);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_QUEUED_READER_SESSIONS)
+    .match_all("id", &ITEM_ROUTER);
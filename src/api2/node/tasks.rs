@@ -17,12 +17,14 @@ use proxmox_schema::{api, BooleanSchema, IntegerSchema, ObjectSchema, Schema};
 use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::{
-    Authid, TaskListItem, TaskStateType, Tokenname, Userid, DATASTORE_SCHEMA, NODE_SCHEMA,
-    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_VERIFY, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY,
+    Authid, TaskListItem, TaskStateType, Tokenname, Userid, DATASTORE_SCHEMA, FIELD_MASK_SCHEMA,
+    NODE_SCHEMA, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_VERIFY, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY,
     SYNC_JOB_WORKER_ID_REGEX, UPID, UPID_SCHEMA, VERIFICATION_JOB_WORKER_ID_REGEX,
 };
 
 use crate::api2::pull::check_pull_privs;
+use crate::tools::compression::encoded_api_response;
+use crate::tools::serde_filter::filter_fields;
 
 use pbs_config::CachedUserInfo;
 use proxmox_rest_server::{upid_log_path, upid_read_status, TaskListInfoIterator, TaskState};
@@ -507,6 +509,10 @@ fn stop_task(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Err
                     type: TaskStateType,
                 },
             },
+            fields: {
+                optional: true,
+                schema: FIELD_MASK_SCHEMA,
+            },
         },
     },
     returns: pbs_api_types::NODE_TASKS_LIST_TASKS_RETURN_TYPE,
@@ -529,7 +535,7 @@ pub fn list_tasks(
     statusfilter: Option<Vec<TaskStateType>>,
     param: Value,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<Vec<TaskListItem>, Error> {
+) -> Result<Value, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let user_info = CachedUserInfo::new()?;
     let user_privs = user_info.lookup_privs(&auth_id, &["system", "tasks"]);
@@ -626,7 +632,7 @@ pub fn list_tasks(
 
     rpcenv["total"] = Value::from(count);
 
-    Ok(result)
+    filter_fields(result, param["fields"].as_str())
 }
 
 #[sortable]
@@ -640,6 +646,22 @@ pub const UPID_API_ROUTER: Router = Router::new()
     .delete(&API_METHOD_STOP_TASK)
     .subdirs(UPID_API_SUBDIRS);
 
+fn encoded_list_tasks(
+    parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move { encoded_api_response(&parts, param, &API_METHOD_LIST_TASKS, rpcenv).await }.boxed()
+}
+
+/// Like [`API_METHOD_LIST_TASKS`], compressed with zstd or gzip if the client accepts it.
+pub const API_METHOD_LIST_TASKS_ENCODED: ApiMethod = ApiMethod {
+    handler: &ApiHandler::AsyncHttp(&encoded_list_tasks),
+    ..API_METHOD_LIST_TASKS
+};
+
 pub const ROUTER: Router = Router::new()
-    .get(&API_METHOD_LIST_TASKS)
+    .get(&API_METHOD_LIST_TASKS_ENCODED)
     .match_all("upid", &UPID_API_ROUTER);
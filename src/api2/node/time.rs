@@ -94,6 +94,10 @@ fn set_timezone(timezone: String, _param: Value) -> Result<Value, Error> {
         bail!("No such timezone.");
     }
 
+    if !pbs_config::preview_config_change("/etc/timezone", timezone.as_bytes(), false)? {
+        return Ok(Value::Null);
+    }
+
     replace_file(
         "/etc/timezone",
         timezone.as_bytes(),
@@ -1,23 +1,86 @@
 //! Sync datastore from remote server
+use std::sync::Arc;
+
 use anyhow::{bail, format_err, Error};
 use futures::{future::FutureExt, select};
+use serde_json::json;
 
+use proxmox_async::runtime::block_in_place;
 use proxmox_router::{Permission, Router, RpcEnvironment};
 use proxmox_schema::api;
 use proxmox_sys::task_log;
 
 use pbs_api_types::{
-    Authid, BackupNamespace, GroupFilter, RateLimitConfig, SyncJobConfig, DATASTORE_SCHEMA,
-    GROUP_FILTER_LIST_SCHEMA, NS_MAX_DEPTH_REDUCED_SCHEMA, PRIV_DATASTORE_BACKUP,
-    PRIV_DATASTORE_PRUNE, PRIV_REMOTE_READ, REMOTE_ID_SCHEMA, REMOVE_VANISHED_BACKUPS_SCHEMA,
-    TRANSFER_LAST_SCHEMA,
+    Authid, BackupNamespace, GroupFilter, HookJobType, Operation, RateLimitConfig, Remote,
+    SyncJobConfig, DATASTORE_SCHEMA, GROUP_FILTER_LIST_SCHEMA, NS_MAX_DEPTH_REDUCED_SCHEMA,
+    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_PRUNE, PRIV_REMOTE_READ, REMOTE_ID_SCHEMA,
+    REMOVE_VANISHED_BACKUPS_SCHEMA, TRANSFER_LAST_SCHEMA, VERIFY_SYNCED_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
+use pbs_datastore::DataStore;
 use proxmox_human_byte::HumanByte;
 use proxmox_rest_server::WorkerTask;
 
+use crate::server::hooks::{run_post_hooks, run_pre_hooks, HookContext};
 use crate::server::jobstate::Job;
-use crate::server::pull::{pull_store, PullParameters};
+use crate::server::log_forward::forward_task_result;
+use crate::server::pull::{
+    pull_store, verify_synced_snapshots, PullParameters, RemoteVerifySource,
+};
+
+/// Verify the snapshots synced into `store`, and their source if synced from a remote.
+#[allow(clippy::too_many_arguments)]
+async fn verify_synced(
+    worker: Arc<WorkerTask>,
+    store: &str,
+    ns: BackupNamespace,
+    remote: Option<&str>,
+    remote_store: &str,
+    remote_ns: BackupNamespace,
+    synced: &[pbs_datastore::BackupDir],
+    job_id: Option<&str>,
+) -> Result<(), Error> {
+    if synced.is_empty() {
+        return Ok(());
+    }
+    task_log!(worker, "verify {} synced snapshots", synced.len());
+
+    let datastore = DataStore::lookup_datastore(store, Some(Operation::Read))?;
+    let (source, remote) = match remote {
+        Some(remote) => {
+            let (remote_config, _digest) = pbs_config::remote::config()?;
+            let remote_config: Remote = remote_config.lookup("remote", remote)?;
+            let client = crate::api2::config::remote::remote_client(&remote_config, None).await?;
+            let remote_source = RemoteVerifySource {
+                client,
+                store: remote_store.to_string(),
+                ns: remote_ns,
+                target_ns: ns,
+            };
+            (format!("{remote}/{remote_store}"), Some(remote_source))
+        }
+        None => (remote_store.to_string(), None),
+    };
+
+    let failed = verify_synced_snapshots(
+        worker.clone(),
+        datastore,
+        synced,
+        remote.as_ref(),
+        &source,
+        job_id,
+    )
+    .await?;
+    if !failed.is_empty() {
+        task_log!(worker, "Failed to verify the following synced snapshots:");
+        for dir in failed {
+            task_log!(worker, "\t{}", dir);
+        }
+        bail!("verification of synced snapshots failed - please check the log for details");
+    }
+
+    Ok(())
+}
 
 pub fn check_pull_privs(
     auth_id: &Authid,
@@ -64,6 +127,7 @@ pub fn check_pull_privs(
             PRIV_DATASTORE_PRUNE,
             false,
         )?;
+        crate::backup::check_not_append_only(auth_id)?;
     }
 
     Ok(())
@@ -113,6 +177,7 @@ pub fn do_sync_job(
     if sync_job.remote.is_none() && sync_job.store == sync_job.remote_store {
         bail!("can't sync to same datastore");
     }
+    job.set_store(&sync_job.store);
 
     let upid_str = WorkerTask::spawn(
         &worker_type,
@@ -125,6 +190,19 @@ pub fn do_sync_job(
             let worker2 = worker.clone();
             let sync_job2 = sync_job.clone();
 
+            let hook_ctx = HookContext::new(
+                HookJobType::Sync,
+                &sync_job.store,
+                worker.upid().to_string(),
+                json!({
+                    "job-id": job.jobname(),
+                    "remote": sync_job.remote,
+                    "remote-store": sync_job.remote_store,
+                    "ns": sync_job.ns,
+                }),
+            );
+
+            let job_name = job.jobname().to_string();
             let worker_future = async move {
                 let pull_params = PullParameters::try_from(&sync_job)?;
 
@@ -169,20 +247,45 @@ pub fn do_sync_job(
                     );
                 }
 
+                if sync_job.verify_synced.unwrap_or(false) {
+                    verify_synced(
+                        worker.clone(),
+                        &sync_job.store,
+                        sync_job.ns.clone().unwrap_or_default(),
+                        sync_job.remote.as_deref(),
+                        &sync_job.remote_store,
+                        sync_job.remote_ns.clone().unwrap_or_default(),
+                        &pull_stats.synced,
+                        Some(&job_name),
+                    )
+                    .await?;
+                }
+
                 task_log!(worker, "sync job '{}' end", &job_id);
 
-                Ok(())
+                Ok(pull_stats.bytes as u64)
             };
 
             let mut abort_future = worker2
                 .abort_future()
                 .map(|_| Err(format_err!("sync aborted")));
 
-            let result = select! {
-                worker = worker_future.fuse() => worker,
-                abort = abort_future => abort,
+            let result = match block_in_place(|| run_pre_hooks(&*worker2, &hook_ctx)) {
+                Ok(()) => select! {
+                    worker = worker_future.fuse() => worker,
+                    abort = abort_future => abort,
+                },
+                Err(err) => Err(err),
             };
 
+            block_in_place(|| run_post_hooks(&*worker2, &hook_ctx, &result));
+
+            let upid = worker2.upid().to_string();
+            if let Err(err) = crate::server::send_sync_status(&sync_job2, &upid, &result) {
+                eprintln!("send sync notification failed: {err}");
+            }
+            let result = result.map(|_bytes| ());
+
             let status = worker2.create_state(&result);
 
             match job.finish(status) {
@@ -192,10 +295,6 @@ pub fn do_sync_job(
                 }
             }
 
-            if let Err(err) = crate::server::send_sync_status(&sync_job2, &result) {
-                eprintln!("send sync notification failed: {err}");
-            }
-
             result
         },
     )?;
@@ -244,6 +343,10 @@ pub fn do_sync_job(
                 schema: TRANSFER_LAST_SCHEMA,
                 optional: true,
             },
+            "verify-synced": {
+                schema: VERIFY_SYNCED_SCHEMA,
+                optional: true,
+            },
         },
     },
     access: {
@@ -268,6 +371,7 @@ async fn pull(
     group_filter: Option<Vec<GroupFilter>>,
     limit: RateLimitConfig,
     transfer_last: Option<usize>,
+    verify_synced: Option<bool>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<String, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
@@ -293,12 +397,17 @@ async fn pull(
         delete,
     )?;
 
+    let remote_ns = remote_ns.unwrap_or_default();
+    let verify_ns = verify_synced
+        .unwrap_or(false)
+        .then(|| (ns.clone(), remote_ns.clone()));
+
     let pull_params = PullParameters::new(
         &store,
         ns,
         remote.as_deref(),
         &remote_store,
-        remote_ns.unwrap_or_default(),
+        remote_ns,
         auth_id.clone(),
         remove_vanished,
         max_depth,
@@ -315,6 +424,19 @@ async fn pull(
         auth_id.to_string(),
         true,
         move |worker| async move {
+            let hook_ctx = HookContext::new(
+                HookJobType::Sync,
+                &store,
+                worker.upid().to_string(),
+                json!({
+                    "remote": remote,
+                    "remote-store": remote_store,
+                    "ns": ns_str,
+                }),
+            );
+
+            block_in_place(|| run_pre_hooks(&*worker, &hook_ctx))?;
+
             task_log!(
                 worker,
                 "pull datastore '{}' from '{}/{}'",
@@ -323,11 +445,32 @@ async fn pull(
                 remote_store,
             );
 
-            let pull_future = pull_store(&worker, pull_params);
-            (select! {
+            let pull_future = async {
+                let pull_stats = pull_store(&worker, pull_params).await?;
+                if let Some((ns, remote_ns)) = verify_ns {
+                    verify_synced(
+                        worker.clone(),
+                        &store,
+                        ns,
+                        remote.as_deref(),
+                        &remote_store,
+                        remote_ns,
+                        &pull_stats.synced,
+                        None,
+                    )
+                    .await?;
+                }
+                Ok::<_, Error>(pull_stats)
+            };
+            let result = select! {
                 success = pull_future.fuse() => success,
                 abort = worker.abort_future().map(|_| Err(format_err!("pull aborted"))) => abort,
-            })?;
+            };
+
+            block_in_place(|| run_post_hooks(&*worker, &hook_ctx, &result));
+            let bytes = result.as_ref().ok().map(|stats| stats.bytes as u64);
+            forward_task_result(&hook_ctx, &result, bytes);
+            result?;
 
             task_log!(worker, "pull datastore '{}' end", store);
 
@@ -11,6 +11,8 @@ use pbs_datastore::DataStore;
 use proxmox_rest_server::formatter::*;
 use proxmox_rest_server::WorkerTask;
 
+use crate::traffic_control_cache::SessionRateLimiter;
+
 /// `RpcEnvironmet` implementation for backup reader service
 #[derive(Clone)]
 pub struct ReaderEnvironment {
@@ -18,10 +20,13 @@ pub struct ReaderEnvironment {
     result_attributes: Value,
     auth_id: Authid,
     pub debug: bool,
+    /// Client can decode lz4 compressed chunks
+    pub lz4: bool,
     pub formatter: &'static dyn OutputFormatter,
     pub worker: Arc<WorkerTask>,
     pub datastore: Arc<DataStore>,
     pub backup_dir: BackupDir,
+    pub rate_limiter: SessionRateLimiter,
     allowed_chunks: Arc<RwLock<HashSet<[u8; 32]>>>,
 }
 
@@ -40,8 +45,10 @@ impl ReaderEnvironment {
             worker,
             datastore,
             debug: false,
+            lz4: false,
             formatter: JSON_FORMATTER,
             backup_dir,
+            rate_limiter: SessionRateLimiter::default(),
             allowed_chunks: Arc::new(RwLock::new(HashSet::new())),
         }
     }
@@ -1,5 +1,7 @@
 //! Backup reader/restore protocol (HTTP2 upgrade)
 
+use std::sync::Arc;
+
 use anyhow::{bail, format_err, Error};
 use futures::*;
 use hex::FromHex;
@@ -17,11 +19,12 @@ use proxmox_schema::{BooleanSchema, ObjectSchema};
 use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::{
-    Authid, Operation, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
-    BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA,
-    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ,
+    Authid, ChunkCompression, Operation, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA,
+    BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA,
+    DATASTORE_SCHEMA, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ,
 };
 use pbs_config::CachedUserInfo;
+use pbs_datastore::file_formats::LZ4_COMPRESSED_BLOB_MAGIC_1_0;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{archive_type, ArchiveType};
 use pbs_datastore::{DataStore, PROXMOX_BACKUP_READER_PROTOCOL_ID_V1};
@@ -31,10 +34,14 @@ use proxmox_sys::fs::lock_dir_noblock_shared;
 
 use crate::api2::backup::optional_ns_param;
 use crate::api2::helpers;
+use crate::traffic_control_cache::{SessionRateLimiter, TrafficClass};
 
 mod environment;
 use environment::*;
 
+pub(crate) mod session_limit;
+use session_limit::*;
+
 pub const ROUTER: Router = Router::new().upgrade(&API_METHOD_UPGRADE_BACKUP);
 
 #[sortable]
@@ -57,6 +64,14 @@ pub const API_METHOD_UPGRADE_BACKUP: ApiMethod = ApiMethod::new(
                 true,
                 &BooleanSchema::new("Enable verbose debug logging.").schema()
             ),
+            (
+                "lz4",
+                true,
+                &BooleanSchema::new(
+                    "Client can decode lz4 compressed chunks, otherwise they are sent zstd compressed."
+                )
+                .schema()
+            ),
         ]),
     ),
 )
@@ -75,6 +90,7 @@ fn upgrade_to_backup_reader_protocol(
 ) -> ApiResponseFuture {
     async move {
         let debug = param["debug"].as_bool().unwrap_or(false);
+        let lz4 = param["lz4"].as_bool().unwrap_or(false);
 
         let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
         let store = required_string_param(&param, "store")?.to_owned();
@@ -129,6 +145,23 @@ fn upgrade_to_backup_reader_protocol(
             bail!("snapshot {} does not exist.", backup_dir.dir());
         }
 
+        let limits = match crate::config::node::config() {
+            Ok((node_config, _)) => ReaderSessionLimits::from_node_config(&node_config),
+            Err(err) => {
+                log::error!("unable to read node config - {err}");
+                ReaderSessionLimits::default()
+            }
+        };
+        let snapshot_name = match backup_dir.backup_ns().is_root() {
+            true => backup_dir.dir().to_string(),
+            false => format!("{}/{}", backup_dir.backup_ns(), backup_dir.dir()),
+        };
+        let session_guard =
+            acquire_reader_session(&limits, &store, &snapshot_name, &auth_id, |msg| {
+                log::info!("reader session for '{store}:{snapshot_name}' by {auth_id}: {msg}")
+            })
+            .await?;
+
         let _guard = lock_dir_noblock_shared(
             &backup_dir.full_path(),
             "snapshot",
@@ -148,6 +181,13 @@ fn upgrade_to_backup_reader_protocol(
             backup_dir.backup_time(),
         );
 
+        let rate_limiter = SessionRateLimiter::lookup(
+            rpcenv.get_client_ip(),
+            &auth_id,
+            &store,
+            TrafficClass::Restore,
+        );
+
         WorkerTask::spawn(
             "reader",
             Some(worker_id),
@@ -155,6 +195,7 @@ fn upgrade_to_backup_reader_protocol(
             true,
             move |worker| async move {
                 let _guard = _guard;
+                let _session_guard = session_guard;
 
                 let mut env = ReaderEnvironment::new(
                     env_type,
@@ -165,12 +206,18 @@ fn upgrade_to_backup_reader_protocol(
                 );
 
                 env.debug = debug;
+                env.lz4 = lz4;
 
                 env.log(format!(
                     "starting new backup reader datastore '{}': {:?}",
                     store, path
                 ));
 
+                if let Some(rule) = rate_limiter.rule() {
+                    env.log(format!("applying traffic control rule '{rule}'"));
+                }
+                env.rate_limiter = rate_limiter;
+
                 let service =
                     H2Service::new(env.clone(), worker.clone(), &READER_API_ROUTER, debug);
 
@@ -325,16 +372,43 @@ fn download_chunk(
         }
 
         let (path, _) = env.datastore.chunk_path(&digest);
-        let path2 = path.clone();
 
         env.debug(format!("download chunk {:?}", path));
 
-        let data =
-            proxmox_async::runtime::block_in_place(|| std::fs::read(path)).map_err(move |err| {
-                http_err!(BAD_REQUEST, "reading file {:?} failed: {}", path2, err)
+        let chunk = env
+            .datastore
+            .load_chunk_cached(&digest)
+            .await
+            .map_err(move |err| {
+                http_err!(BAD_REQUEST, "reading file {:?} failed: {}", path, err)
             })?;
 
-        let body = Body::from(data);
+        // older clients cannot decode lz4 compressed chunks
+        let chunk = if !env.lz4 && chunk.magic() == &LZ4_COMPRESSED_BLOB_MAGIC_1_0 {
+            let zstd_chunk = proxmox_async::runtime::block_in_place(|| {
+                chunk.recompress(ChunkCompression::default())
+            })
+            .map_err(|err| {
+                env.log(format!(
+                    "converting lz4 compressed chunk {digest_str} for client failed: {err}"
+                ));
+                http_err!(
+                    NOT_ACCEPTABLE,
+                    "chunk {digest_str} is lz4 compressed and could not be converted for this \
+                    client ({err}) - please upgrade the client to a version supporting lz4"
+                )
+            })?;
+            match zstd_chunk {
+                Some(zstd_chunk) => Arc::new(zstd_chunk),
+                None => chunk,
+            }
+        } else {
+            chunk
+        };
+
+        env.rate_limiter.throttle_out(chunk.raw_size()).await;
+
+        let body = Body::from(chunk.raw_data().to_vec());
 
         // fixme: set other headers ?
         Ok(Response::builder()
@@ -0,0 +1,470 @@
+//! Limits for concurrent reader sessions
+//!
+//! Restoring many snapshots at once (for example during disaster recovery
+//! tests) can overload the server, so that all restores slow down together.
+//! Sessions exceeding one of the configured limits are queued until another
+//! session ends, or fail once the queue timeout is reached.
+//!
+//! Queued sessions start in the order of their priority, then in the order
+//! they were queued. The priority can be changed while a session is waiting,
+//! for example to let an urgent restore jump ahead of less important ones.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use lazy_static::lazy_static;
+use tokio::sync::Notify;
+
+use proxmox_router::{http_bail, http_err};
+
+use pbs_api_types::{Authid, QueuedReaderSession};
+
+use crate::config::node::NodeConfig;
+
+/// Default for the `reader-queue-timeout` node option (seconds)
+const DEFAULT_READER_QUEUE_TIMEOUT: u64 = 300;
+
+/// Log a status message every this often while a session is queued
+const QUEUE_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum LimitKind {
+    Snapshot,
+    Datastore,
+    User,
+}
+
+impl LimitKind {
+    fn describe(&self) -> &'static str {
+        match self {
+            LimitKind::Snapshot => "snapshot",
+            LimitKind::Datastore => "datastore",
+            LimitKind::User => "user",
+        }
+    }
+}
+
+lazy_static! {
+    static ref ACTIVE_SESSIONS: Mutex<HashMap<(LimitKind, String), usize>> =
+        Mutex::new(HashMap::new());
+    static ref SESSION_ENDED: Notify = Notify::new();
+    static ref QUEUED_SESSIONS: Mutex<BTreeMap<u64, QueuedSession>> = Mutex::new(BTreeMap::new());
+}
+
+static NEXT_QUEUE_ID: AtomicU64 = AtomicU64::new(1);
+
+struct QueuedSession {
+    store: String,
+    snapshot: String,
+    auth_id: Authid,
+    priority: i64,
+    queued_since: i64,
+    /// Limited keys of the session
+    keys: Vec<(LimitKind, String, usize)>,
+}
+
+impl QueuedSession {
+    // sessions with a lower order start first
+    fn order(&self, id: u64) -> (i64, u64) {
+        (-self.priority, id)
+    }
+}
+
+/// Removes a session from the queue when dropped
+struct QueueEntry(u64);
+
+impl Drop for QueueEntry {
+    fn drop(&mut self) {
+        QUEUED_SESSIONS.lock().unwrap().remove(&self.0);
+        // sessions queued behind this one might be able to start now
+        SESSION_ENDED.notify_waiters();
+    }
+}
+
+/// List the queued reader sessions, in the order they will start.
+pub fn list_queued_sessions() -> Vec<QueuedReaderSession> {
+    let queued = QUEUED_SESSIONS.lock().unwrap();
+    start_order(&queued)
+        .into_iter()
+        .enumerate()
+        .map(|(index, (id, session))| QueuedReaderSession {
+            id: *id,
+            position: index + 1,
+            store: session.store.clone(),
+            snapshot: session.snapshot.clone(),
+            auth_id: session.auth_id.clone(),
+            priority: session.priority,
+            queued_since: session.queued_since,
+        })
+        .collect()
+}
+
+// the queued sessions, sorted by the order they will start in
+fn start_order(queued: &BTreeMap<u64, QueuedSession>) -> Vec<(&u64, &QueuedSession)> {
+    let mut list: Vec<_> = queued.iter().collect();
+    list.sort_by_key(|(id, session)| session.order(**id));
+    list
+}
+
+/// Change the priority of a queued reader session.
+pub fn set_queued_session_priority(id: u64, priority: i64) -> Result<(), Error> {
+    let mut queued = QUEUED_SESSIONS.lock().unwrap();
+    let session = queued
+        .get_mut(&id)
+        .ok_or_else(|| http_err!(NOT_FOUND, "no queued reader session with ID {id}"))?;
+    session.priority = priority;
+    drop(queued);
+    SESSION_ENDED.notify_waiters();
+    Ok(())
+}
+
+fn within_limits(
+    active: &HashMap<(LimitKind, String), usize>,
+    keys: &[(LimitKind, String, usize)],
+) -> bool {
+    keys.iter().all(|(kind, key, limit)| {
+        *limit == 0 || active.get(&(*kind, key.clone())).copied().unwrap_or(0) < *limit
+    })
+}
+
+// Returns the ID of a queued session which is ahead of `queue_id` (or of a new
+// session), competes for one of the same limits and could start right now.
+fn queued_ahead(queue_id: Option<u64>, keys: &[(LimitKind, String, usize)]) -> Option<u64> {
+    let active = ACTIVE_SESSIONS.lock().unwrap();
+    let queued = QUEUED_SESSIONS.lock().unwrap();
+
+    find_queued_ahead(&active, &queued, queue_id, keys)
+}
+
+fn find_queued_ahead(
+    active: &HashMap<(LimitKind, String), usize>,
+    queued: &BTreeMap<u64, QueuedSession>,
+    queue_id: Option<u64>,
+    keys: &[(LimitKind, String, usize)],
+) -> Option<u64> {
+    let own_order = queue_id.and_then(|id| queued.get(&id).map(|session| session.order(id)));
+
+    queued
+        .iter()
+        .filter(|(id, session)| own_order.map_or(true, |own| session.order(**id) < own))
+        .filter(|(_, session)| {
+            session.keys.iter().any(|(kind, key, limit)| {
+                *limit > 0 && keys.iter().any(|(k, other, _)| k == kind && other == key)
+            })
+        })
+        .find(|(_, session)| within_limits(active, &session.keys))
+        .map(|(id, _)| *id)
+}
+
+/// Configured reader session limits, 0 means unlimited
+#[derive(Default)]
+pub struct ReaderSessionLimits {
+    per_snapshot: usize,
+    per_datastore: usize,
+    per_user: usize,
+    queue_timeout: u64,
+}
+
+impl ReaderSessionLimits {
+    pub fn from_node_config(config: &NodeConfig) -> Self {
+        Self {
+            per_snapshot: config.reader_max_sessions_per_snapshot.unwrap_or(0),
+            per_datastore: config.reader_max_sessions_per_datastore.unwrap_or(0),
+            per_user: config.reader_max_sessions_per_user.unwrap_or(0),
+            queue_timeout: config
+                .reader_queue_timeout
+                .unwrap_or(DEFAULT_READER_QUEUE_TIMEOUT),
+        }
+    }
+
+    fn unlimited(&self) -> bool {
+        self.per_snapshot == 0 && self.per_datastore == 0 && self.per_user == 0
+    }
+}
+
+/// Counts as an active reader session until dropped
+pub struct ReaderSessionGuard {
+    keys: Vec<(LimitKind, String)>,
+}
+
+impl Drop for ReaderSessionGuard {
+    fn drop(&mut self) {
+        if self.keys.is_empty() {
+            return;
+        }
+        let mut active = ACTIVE_SESSIONS.lock().unwrap();
+        for key in self.keys.drain(..) {
+            if let Some(count) = active.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    active.remove(&key);
+                }
+            }
+        }
+        drop(active);
+        SESSION_ENDED.notify_waiters();
+    }
+}
+
+// Returns the first exceeded limit, or registers the session.
+fn try_acquire(
+    keys: &[(LimitKind, String, usize)],
+) -> Result<ReaderSessionGuard, (LimitKind, String, usize)> {
+    let mut active = ACTIVE_SESSIONS.lock().unwrap();
+
+    for (kind, key, limit) in keys {
+        if !within_limits(&active, &[(*kind, key.clone(), *limit)]) {
+            return Err((*kind, key.clone(), *limit));
+        }
+    }
+
+    let mut guard = ReaderSessionGuard { keys: Vec::new() };
+    for (kind, key, limit) in keys {
+        if *limit > 0 {
+            *active.entry((*kind, key.clone())).or_insert(0) += 1;
+            guard.keys.push((*kind, key.clone()));
+        }
+    }
+
+    Ok(guard)
+}
+
+/// Wait until a new reader session for `snapshot` on `store` by `auth_id` is
+/// within the configured limits.
+///
+/// `log` is called with a status message while the session is queued.
+pub async fn acquire_reader_session(
+    limits: &ReaderSessionLimits,
+    store: &str,
+    snapshot: &str,
+    auth_id: &Authid,
+    log: impl Fn(String),
+) -> Result<ReaderSessionGuard, Error> {
+    if limits.unlimited() {
+        return Ok(ReaderSessionGuard { keys: Vec::new() });
+    }
+
+    let keys = [
+        (
+            LimitKind::Snapshot,
+            format!("{store}:{snapshot}"),
+            limits.per_snapshot,
+        ),
+        (
+            LimitKind::Datastore,
+            store.to_string(),
+            limits.per_datastore,
+        ),
+        (LimitKind::User, auth_id.user().to_string(), limits.per_user),
+    ];
+
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(limits.queue_timeout);
+    let mut next_log = start;
+    let mut queue_entry: Option<QueueEntry> = None;
+
+    loop {
+        // register for wake-ups before checking, so that no session end is missed
+        let session_ended = SESSION_ENDED.notified();
+
+        let queue_id = queue_entry.as_ref().map(|entry| entry.0);
+        // None means waiting for a queued session with higher priority
+        let acquired = match queued_ahead(queue_id, &keys) {
+            Some(_) => Err(None),
+            None => try_acquire(&keys).map_err(Some),
+        };
+
+        let exceeded = match acquired {
+            Ok(guard) => {
+                if start.elapsed() >= Duration::from_secs(1) {
+                    log(format!(
+                        "reader session started after waiting {}s in queue",
+                        start.elapsed().as_secs()
+                    ));
+                }
+                return Ok(guard);
+            }
+            Err(exceeded) => exceeded,
+        };
+
+        let queue_id = match queue_entry {
+            Some(ref entry) => entry.0,
+            None => {
+                let id = NEXT_QUEUE_ID.fetch_add(1, Ordering::SeqCst);
+                QUEUED_SESSIONS.lock().unwrap().insert(
+                    id,
+                    QueuedSession {
+                        store: store.to_string(),
+                        snapshot: snapshot.to_string(),
+                        auth_id: auth_id.clone(),
+                        priority: 0,
+                        queued_since: proxmox_time::epoch_i64(),
+                        keys: keys
+                            .iter()
+                            .filter(|(_, _, limit)| *limit > 0)
+                            .cloned()
+                            .collect(),
+                    },
+                );
+                queue_entry = Some(QueueEntry(id));
+                id
+            }
+        };
+
+        let now = Instant::now();
+        if now >= deadline {
+            match exceeded {
+                Some((kind, key, limit)) => http_bail!(
+                    TOO_MANY_REQUESTS,
+                    "too many concurrent reader sessions for {} '{}' (limit {}), gave up after \
+                    waiting {}s - please try again later",
+                    kind.describe(),
+                    key,
+                    limit,
+                    start.elapsed().as_secs(),
+                ),
+                None => http_bail!(
+                    TOO_MANY_REQUESTS,
+                    "reader sessions with higher priority are queued, gave up after waiting \
+                    {}s - please try again later",
+                    start.elapsed().as_secs(),
+                ),
+            }
+        }
+
+        if now >= next_log {
+            match exceeded {
+                Some((kind, key, limit)) => log(format!(
+                    "waiting for a free reader session slot (queue ID {queue_id}) - {} '{}' \
+                    reached its limit of {} concurrent sessions",
+                    kind.describe(),
+                    key,
+                    limit,
+                )),
+                None => log(format!(
+                    "waiting for a free reader session slot (queue ID {queue_id}) - sessions \
+                    with higher priority are queued"
+                )),
+            }
+            next_log = now + QUEUE_LOG_INTERVAL;
+        }
+
+        let wait = deadline.min(next_log) - now;
+        let _ = tokio::time::timeout(wait, session_ended).await;
+    }
+}
+
+#[test]
+fn test_reader_session_limits() {
+    let keys = [
+        (LimitKind::Snapshot, "test:vm/100/snap".to_string(), 1),
+        (LimitKind::Datastore, "test-limit".to_string(), 2),
+        (LimitKind::User, "nobody@pam".to_string(), 0),
+    ];
+
+    let first = try_acquire(&keys).expect("first session within limits");
+    let (kind, _, limit) = try_acquire(&keys).err().expect("snapshot limit reached");
+    assert_eq!((kind, limit), (LimitKind::Snapshot, 1));
+
+    let other = [
+        (LimitKind::Snapshot, "test:vm/101/snap".to_string(), 1),
+        (LimitKind::Datastore, "test-limit".to_string(), 2),
+    ];
+    let second = try_acquire(&other).expect("other snapshot within limits");
+    let (kind, _, _) = try_acquire(&other[1..])
+        .err()
+        .expect("datastore limit reached");
+    assert_eq!(kind, LimitKind::Datastore);
+
+    drop(first);
+    drop(second);
+    assert!(ACTIVE_SESSIONS
+        .lock()
+        .unwrap()
+        .keys()
+        .all(|(_, key)| key != "test-limit"));
+}
+
+#[cfg(test)]
+fn queued_session(priority: i64, keys: &[(LimitKind, &str, usize)]) -> QueuedSession {
+    QueuedSession {
+        store: "test".to_string(),
+        snapshot: "vm/100/snap".to_string(),
+        auth_id: Authid::root_auth_id().clone(),
+        priority,
+        queued_since: 0,
+        keys: keys
+            .iter()
+            .map(|(kind, key, limit)| (*kind, key.to_string(), *limit))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_reader_session_start_order() {
+    let store = [(LimitKind::Datastore, "store1", 1)];
+
+    let mut queued = BTreeMap::new();
+    queued.insert(1, queued_session(0, &store));
+    queued.insert(2, queued_session(10, &store));
+    queued.insert(3, queued_session(0, &store));
+    queued.insert(4, queued_session(-5, &store));
+
+    // higher priority first, then in queue order
+    let order = |queued: &BTreeMap<u64, QueuedSession>| -> Vec<u64> {
+        start_order(queued).into_iter().map(|(id, _)| *id).collect()
+    };
+    assert_eq!(order(&queued), [2, 1, 3, 4]);
+
+    // raising the priority of a waiting session lets it jump ahead
+    queued.get_mut(&3).unwrap().priority = 20;
+    assert_eq!(order(&queued), [3, 2, 1, 4]);
+}
+
+#[test]
+fn test_reader_session_queued_ahead() {
+    let store1 = [(LimitKind::Datastore, "store1", 1)];
+    let store2 = [(LimitKind::Datastore, "store2", 1)];
+    let keys = |limits: &[(LimitKind, &str, usize)]| -> Vec<(LimitKind, String, usize)> {
+        limits
+            .iter()
+            .map(|(kind, key, limit)| (*kind, key.to_string(), *limit))
+            .collect()
+    };
+
+    let mut active = HashMap::new();
+    let mut queued = BTreeMap::new();
+    queued.insert(1, queued_session(0, &store1));
+    queued.insert(2, queued_session(10, &store1));
+    queued.insert(3, queued_session(0, &store2));
+
+    // a new session waits for the queued ones competing for the same limit
+    assert_eq!(
+        find_queued_ahead(&active, &queued, None, &keys(&store1)),
+        Some(2)
+    );
+    // the session with the highest priority is first in line
+    assert_eq!(
+        find_queued_ahead(&active, &queued, Some(2), &keys(&store1)),
+        None
+    );
+    assert_eq!(
+        find_queued_ahead(&active, &queued, Some(1), &keys(&store1)),
+        Some(2)
+    );
+    // sessions for other limits are not in the way
+    assert_eq!(
+        find_queued_ahead(&active, &queued, Some(3), &keys(&store2)),
+        None
+    );
+
+    // a session ahead which cannot start itself does not block the others
+    active.insert((LimitKind::Datastore, "store1".to_string()), 1);
+    assert_eq!(
+        find_queued_ahead(&active, &queued, Some(1), &keys(&store1)),
+        None
+    );
+}
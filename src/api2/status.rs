@@ -7,8 +7,12 @@ use proxmox_router::list_subdirs_api_method;
 use proxmox_router::{ApiMethod, Permission, Router, RpcEnvironment, SubdirMap};
 use proxmox_schema::api;
 
+use proxmox_rest_server::TaskState;
+
 use pbs_api_types::{
-    Authid, DataStoreStatusListItem, Operation, RRDMode, RRDTimeFrame, PRIV_DATASTORE_AUDIT,
+    Authid, DataStoreConfig, DataStoreHealth, DataStoreHealthCheck, DataStoreHealthState,
+    DataStoreStatusListItem, MaintenanceType, Operation, PruneJobConfig, RRDMode, RRDTimeFrame,
+    SyncJobConfig, VerificationJobConfig, DATASTORE_SCHEMA, PRIV_DATASTORE_AUDIT,
     PRIV_DATASTORE_BACKUP,
 };
 
@@ -16,6 +20,7 @@ use pbs_config::CachedUserInfo;
 use pbs_datastore::DataStore;
 
 use crate::rrd_cache::extract_rrd_data;
+use crate::server::jobstate::{compute_schedule_status, splay_delay, JobState};
 use crate::tools::statistics::linear_regression;
 
 use crate::backup::can_access_any_namespace;
@@ -137,11 +142,345 @@ pub async fn datastore_status(
     Ok(list)
 }
 
-const SUBDIRS: SubdirMap = &[(
-    "datastore-usage",
-    &Router::new().get(&API_METHOD_DATASTORE_STATUS),
-)];
+/// Usage of the underlying storage (in percent) from which on a datastore gets a warning
+const SPACE_WARNING_PERCENT: f64 = 85.0;
+/// Usage of the underlying storage (in percent) from which on a datastore is critical
+const SPACE_CRITICAL_PERCENT: f64 = 95.0;
+/// Delay after which a scheduled job which did not run is considered overdue
+const JOB_GRACE_SECONDS: i64 = 3600;
+
+/// The jobs whose results and schedules are part of the datastore health.
+pub(crate) struct DataStoreJobs {
+    verify: Vec<VerificationJobConfig>,
+    sync: Vec<SyncJobConfig>,
+    prune: Vec<PruneJobConfig>,
+}
+
+impl DataStoreJobs {
+    pub(crate) fn load() -> Result<Self, Error> {
+        let (config, _digest) = pbs_config::verify::config()?;
+        let verify = config.convert_to_typed_array("verification")?;
+        let (config, _digest) = pbs_config::sync::config()?;
+        let sync = config.convert_to_typed_array("sync")?;
+        let (config, _digest) = pbs_config::prune::config()?;
+        let prune = config.convert_to_typed_array("prune")?;
+        Ok(Self {
+            verify,
+            sync,
+            prune,
+        })
+    }
+}
+
+// the error message of the last run of a job, if it failed
+fn last_job_error(jobtype: &str, id: &str) -> Option<String> {
+    match JobState::load(jobtype, id) {
+        Ok(JobState::Finished {
+            state: TaskState::Error { message, .. },
+            ..
+        }) => Some(message),
+        _ => None,
+    }
+}
+
+// the minutes a scheduled job is overdue, if it is more than the grace time
+fn job_overdue_minutes(
+    jobtype: &str,
+    id: &str,
+    schedule: Option<&str>,
+    splay: Option<u64>,
+    now: i64,
+) -> Option<i64> {
+    let schedule = schedule?;
+    let next_run = JobState::load(jobtype, id)
+        .and_then(|state| compute_schedule_status(&state, Some(schedule)))
+        .ok()?
+        .next_run?;
+    let next_run = next_run + splay_delay(jobtype, id, next_run, splay);
+    if now - next_run > JOB_GRACE_SECONDS {
+        Some((now - next_run) / 60)
+    } else {
+        None
+    }
+}
+
+/// The health state of a datastore in maintenance mode `ty`, and whether the datastore is still
+/// accessible, so the other checks make sense.
+fn maintenance_health(ty: MaintenanceType) -> (DataStoreHealthState, bool) {
+    match ty {
+        MaintenanceType::ReadOnly => (DataStoreHealthState::Warning, true),
+        MaintenanceType::Offline | MaintenanceType::Unmount | MaintenanceType::Delete => {
+            (DataStoreHealthState::Maintenance, false)
+        }
+    }
+}
+
+pub(crate) async fn check_datastore_health(
+    store: &str,
+    config: &DataStoreConfig,
+    jobs: &DataStoreJobs,
+) -> DataStoreHealth {
+    let now = proxmox_time::epoch_i64();
+    let mut health = DataStoreHealth::new(store);
+
+    if let Some(mode) = config.get_maintenance_mode() {
+        let (state, accessible) = maintenance_health(mode.ty);
+        health.add(
+            DataStoreHealthCheck::Maintenance,
+            state,
+            format!("datastore is in '{}' maintenance mode", mode.ty),
+        );
+        if !accessible {
+            // the storage may be detached on purpose and jobs fail, nothing to report about
+            return health;
+        }
+    }
+
+    let datastore = match DataStore::lookup_datastore(store, Some(Operation::Lookup)) {
+        Ok(datastore) => datastore,
+        Err(err) => {
+            health.add(
+                DataStoreHealthCheck::Io,
+                DataStoreHealthState::Critical,
+                format!("unable to open datastore - {err}"),
+            );
+            return health;
+        }
+    };
+
+    match crate::tools::fs::fs_info(datastore.base_path()).await {
+        Ok(status) if status.total > 0 => {
+            let usage = (status.used as f64) * 100.0 / (status.total as f64);
+            let state = if usage >= SPACE_CRITICAL_PERCENT {
+                DataStoreHealthState::Critical
+            } else if usage >= SPACE_WARNING_PERCENT {
+                DataStoreHealthState::Warning
+            } else {
+                DataStoreHealthState::Ok
+            };
+            if state != DataStoreHealthState::Ok {
+                health.add(
+                    DataStoreHealthCheck::Space,
+                    state,
+                    format!("storage is {usage:.1}% full"),
+                );
+            }
+        }
+        Ok(_) => (),
+        Err(err) => health.add(
+            DataStoreHealthCheck::Io,
+            DataStoreHealthState::Critical,
+            format!("unable to query storage usage - {err}"),
+        ),
+    }
+
+    if let Some(err) = last_job_error("garbage_collection", store) {
+        health.add(
+            DataStoreHealthCheck::GarbageCollection,
+            DataStoreHealthState::Warning,
+            format!("last garbage collection failed - {err}"),
+        );
+    }
+    let gc_schedule = config.gc_schedule.as_deref();
+    if let Some(minutes) = job_overdue_minutes(
+        "garbage_collection",
+        store,
+        gc_schedule,
+        config.gc_splay,
+        now,
+    ) {
+        health.add(
+            DataStoreHealthCheck::GarbageCollection,
+            DataStoreHealthState::Warning,
+            format!("garbage collection is overdue by {minutes} minutes"),
+        );
+    }
+    let gc_status = datastore.last_gc_status();
+    if gc_status.still_bad > 0 {
+        health.add(
+            DataStoreHealthCheck::Io,
+            DataStoreHealthState::Critical,
+            format!(
+                "garbage collection found {} corrupt chunks",
+                gc_status.still_bad
+            ),
+        );
+    }
+
+    for job in jobs.verify.iter().filter(|job| job.store == store) {
+        if let Some(err) = last_job_error("verificationjob", &job.id) {
+            health.add(
+                DataStoreHealthCheck::Verify,
+                DataStoreHealthState::Critical,
+                format!("verification job '{}' failed - {err}", job.id),
+            );
+        }
+        let schedule = job.schedule.as_deref();
+        if let Some(minutes) =
+            job_overdue_minutes("verificationjob", &job.id, schedule, job.splay, now)
+        {
+            health.add(
+                DataStoreHealthCheck::Verify,
+                DataStoreHealthState::Warning,
+                format!(
+                    "verification job '{}' is overdue by {minutes} minutes",
+                    job.id
+                ),
+            );
+        }
+    }
+
+    for job in jobs.sync.iter().filter(|job| job.store == store) {
+        if let Some(err) = last_job_error("syncjob", &job.id) {
+            health.add(
+                DataStoreHealthCheck::Sync,
+                DataStoreHealthState::Warning,
+                format!("sync job '{}' failed - {err}", job.id),
+            );
+        }
+        let schedule = job.schedule.as_deref();
+        if let Some(minutes) = job_overdue_minutes("syncjob", &job.id, schedule, job.splay, now) {
+            health.add(
+                DataStoreHealthCheck::Sync,
+                DataStoreHealthState::Warning,
+                format!("sync job '{}' is overdue by {minutes} minutes", job.id),
+            );
+        }
+    }
+
+    for job in jobs
+        .prune
+        .iter()
+        .filter(|job| !job.disable && job.store == store)
+    {
+        if let Some(err) = last_job_error("prunejob", &job.id) {
+            health.add(
+                DataStoreHealthCheck::Prune,
+                DataStoreHealthState::Warning,
+                format!("prune job '{}' failed - {err}", job.id),
+            );
+        }
+        let schedule = Some(job.schedule.as_str());
+        if let Some(minutes) = job_overdue_minutes("prunejob", &job.id, schedule, job.splay, now) {
+            health.add(
+                DataStoreHealthCheck::Prune,
+                DataStoreHealthState::Warning,
+                format!("prune job '{}' is overdue by {minutes} minutes", job.id),
+            );
+        }
+    }
+
+    health
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "The health status of the datastores.",
+        type: Array,
+        items: {
+            type: DataStoreHealth,
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Only datastores with Datastore.Audit are included.",
+    },
+)]
+/// Aggregated health status of the datastores
+///
+/// Combines storage usage, maintenance mode, the last garbage collection and the results and
+/// schedules of the garbage collection, verification, sync and prune jobs into a single state
+/// with the reasons for it.
+pub async fn datastore_health(
+    store: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<DataStoreHealth>, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let jobs = DataStoreJobs::load()?;
+
+    let mut list = Vec::new();
+
+    for (name, (_, data)) in &config.sections {
+        if store.as_ref().map_or(false, |store| store != name) {
+            continue;
+        }
+        let user_privs = user_info.lookup_privs(&auth_id, &["datastore", name]);
+        if user_privs & PRIV_DATASTORE_AUDIT == 0 {
+            continue;
+        }
+
+        let datastore_config: DataStoreConfig = serde_json::from_value(data.clone())?;
+
+        list.push(check_datastore_health(name, &datastore_config, &jobs).await);
+    }
+
+    Ok(list)
+}
+
+const SUBDIRS: SubdirMap = &[
+    (
+        "datastore-health",
+        &Router::new().get(&API_METHOD_DATASTORE_HEALTH),
+    ),
+    (
+        "datastore-usage",
+        &Router::new().get(&API_METHOD_DATASTORE_STATUS),
+    ),
+];
 
 pub const ROUTER: Router = Router::new()
     .get(&list_subdirs_api_method!(SUBDIRS))
     .subdirs(SUBDIRS);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance_health() {
+        assert_eq!(
+            maintenance_health(MaintenanceType::ReadOnly),
+            (DataStoreHealthState::Warning, true),
+        );
+        for ty in [
+            MaintenanceType::Offline,
+            MaintenanceType::Unmount,
+            MaintenanceType::Delete,
+        ] {
+            assert_eq!(
+                maintenance_health(ty),
+                (DataStoreHealthState::Maintenance, false),
+            );
+        }
+    }
+
+    #[test]
+    fn test_health_state() {
+        let mut health = DataStoreHealth::new("store1");
+        assert_eq!(health.state, DataStoreHealthState::Ok);
+
+        let (state, _) = maintenance_health(MaintenanceType::Offline);
+        health.add(DataStoreHealthCheck::Maintenance, state, String::new());
+        assert_eq!(health.state, DataStoreHealthState::Maintenance);
+
+        // any actual problem is more severe
+        health.add(
+            DataStoreHealthCheck::Space,
+            DataStoreHealthState::Warning,
+            String::new(),
+        );
+        assert_eq!(health.state, DataStoreHealthState::Warning);
+    }
+}
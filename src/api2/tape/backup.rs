@@ -9,27 +9,31 @@ use proxmox_schema::api;
 use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 
 use pbs_api_types::{
-    print_ns_and_snapshot, print_store_and_ns, Authid, MediaPoolConfig, Operation,
-    TapeBackupJobConfig, TapeBackupJobSetup, TapeBackupJobStatus, JOB_ID_SCHEMA,
+    print_ns_and_snapshot, print_store_and_ns, Authid, BackupType, MediaPoolConfig, MediaSetSlots,
+    Operation, TapeBackupJobConfig, TapeBackupJobSetup, TapeBackupJobStatus, JOB_ID_SCHEMA,
     PRIV_DATASTORE_READ, PRIV_TAPE_AUDIT, PRIV_TAPE_WRITE, UPID_SCHEMA,
 };
 
 use pbs_config::CachedUserInfo;
-use pbs_datastore::backup_info::{BackupDir, BackupInfo};
+use pbs_datastore::backup_info::{BackupDir, BackupGroup, BackupInfo};
 use pbs_datastore::{DataStore, StoreProgress};
 use proxmox_rest_server::WorkerTask;
 
 use crate::tape::TapeNotificationMode;
 use crate::{
     server::{
-        jobstate::{compute_schedule_status, Job, JobState},
+        jobstate::{compute_schedule_status, splay_delay, Job, JobState},
         TapeBackupJobSummary,
     },
     tape::{
         changer::update_changer_online_status,
-        drive::{lock_tape_device, media_changer, set_tape_device_state, TapeLockError},
-        Inventory, MediaPool, PoolWriter, TAPE_STATUS_DIR,
+        drive::{
+            lock_tape_device, lock_tape_device_cancellable, media_changer, set_tape_device_state,
+        },
+        media_set_slots::media_set_slot,
+        Inventory, MediaCatalog, MediaPool, MediaSetCatalog, PoolWriter, TAPE_STATUS_DIR,
     },
+    tools::async_io::worker_abort_signal,
 };
 
 const TAPE_BACKUP_JOB_ROUTER: Router = Router::new().post(&API_METHOD_RUN_TAPE_BACKUP_JOB);
@@ -99,7 +103,10 @@ pub fn list_tape_backup_jobs(
         let last_state = JobState::load("tape-backup-job", &job.id)
             .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
 
-        let status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        let mut status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        status.next_run = status
+            .next_run
+            .map(|next| next + splay_delay("tape-backup-job", &job.id, next, job.splay));
 
         let next_run = status.next_run.unwrap_or(current_time);
 
@@ -148,6 +155,7 @@ pub fn do_tape_backup_job(
     );
 
     let worker_type = job.jobtype().to_string();
+    job.set_store(&setup.store);
 
     let datastore = DataStore::lookup_datastore(&setup.store, Some(Operation::Read))?;
 
@@ -177,17 +185,12 @@ pub fn do_tape_backup_job(
                 if schedule.is_some() {
                     // for scheduled tape backup jobs, we wait indefinitely for the lock
                     task_log!(worker, "waiting for drive lock...");
-                    loop {
-                        worker.check_abort()?;
-                        match lock_tape_device(&drive_config, &setup.drive) {
-                            Ok(lock) => {
-                                drive_lock = Some(lock);
-                                break;
-                            }
-                            Err(TapeLockError::TimeOut) => continue,
-                            Err(TapeLockError::Other(err)) => return Err(err),
-                        }
-                    }
+                    let abort = worker_abort_signal(&worker);
+                    drive_lock = Some(lock_tape_device_cancellable(
+                        &drive_config,
+                        &setup.drive,
+                        &abort,
+                    )?);
                 }
                 set_tape_device_state(&setup.drive, &worker.upid().to_string())?;
 
@@ -211,6 +214,7 @@ pub fn do_tape_backup_job(
             if let Err(err) = crate::server::send_tape_backup_status(
                 Some(job.jobname()),
                 &setup,
+                &worker.upid().to_string(),
                 &job_result,
                 summary,
             ) {
@@ -338,9 +342,13 @@ pub fn backup(
                 force_media_set,
             );
 
-            if let Err(err) =
-                crate::server::send_tape_backup_status(None, &setup, &job_result, summary)
-            {
+            if let Err(err) = crate::server::send_tape_backup_status(
+                None,
+                &setup,
+                &worker.upid().to_string(),
+                &job_result,
+                summary,
+            ) {
                 eprintln!("send tape backup notification failed: {err}");
             }
 
@@ -359,6 +367,32 @@ enum SnapshotBackupResult {
     Ignored,
 }
 
+/// Load the catalogs of all media in the pool, to find snapshots written by previous runs.
+fn load_pool_catalog(worker: &WorkerTask, pool: &str) -> Result<MediaSetCatalog, Error> {
+    let inventory = Inventory::load(TAPE_STATUS_DIR)?;
+    let mut catalog = MediaSetCatalog::new();
+
+    for media_id in inventory.list_pool_media(pool) {
+        let uuid = &media_id.label.uuid;
+        if !MediaCatalog::exists(TAPE_STATUS_DIR, uuid) {
+            task_warn!(
+                worker,
+                "no catalog for media '{}', its snapshots are written again",
+                media_id.label.label_text
+            );
+            continue;
+        }
+        catalog.append_catalog(MediaCatalog::open(
+            TAPE_STATUS_DIR,
+            &media_id,
+            false,
+            false,
+        )?)?;
+    }
+
+    Ok(catalog)
+}
+
 fn backup_worker(
     worker: &WorkerTask,
     datastore: Arc<DataStore>,
@@ -368,6 +402,7 @@ fn backup_worker(
     force_media_set: bool,
 ) -> Result<(), Error> {
     let start = std::time::Instant::now();
+    let run_start = proxmox_time::epoch_i64();
 
     task_log!(worker, "update media online status");
     let changer_name = update_media_online_status(&setup.drive)?;
@@ -375,17 +410,12 @@ fn backup_worker(
     let root_namespace = setup.ns.clone().unwrap_or_default();
     let ns_magic = !root_namespace.is_root() || setup.max_depth != Some(0);
 
-    let pool = MediaPool::with_config(TAPE_STATUS_DIR, pool_config, changer_name, false)?;
     let notification_mode = TapeNotificationMode::from(setup);
 
-    let mut pool_writer = PoolWriter::new(
-        pool,
-        &setup.drive,
-        worker,
-        notification_mode,
-        force_media_set,
-        ns_magic,
-    )?;
+    let slots: MediaSetSlots = match pool_config.slots {
+        Some(ref slots) => slots.parse()?,
+        None => MediaSetSlots::default(),
+    };
 
     let mut group_list = Vec::new();
     let namespaces = datastore.recursive_iter_backup_ns_ok(root_namespace, setup.max_depth)?;
@@ -425,133 +455,221 @@ fn backup_worker(
 
     let datastore_name = datastore.name();
 
-    let mut errors = false;
-
-    let mut need_catalog = false; // avoid writing catalog for empty jobs
+    // the catalogs record what actually is on tape, independent of the (client chosen) backup
+    // time of the snapshots
+    let pool_catalog = if setup.since_last_run.unwrap_or(false) {
+        task_log!(
+            worker,
+            "since-last-run: true (skipping snapshots already on any media of pool '{}')",
+            setup.pool
+        );
+        Some(load_pool_catalog(worker, &setup.pool)?)
+    } else {
+        None
+    };
 
-    for (group_number, group) in group_list.into_iter().enumerate() {
-        progress.done_groups = group_number as u64;
-        progress.done_snapshots = 0;
-        progress.group_snapshots = 0;
+    let mut errors = false;
 
-        let snapshot_list = group.list_backups()?;
+    let mut group_number = 0;
+    let mut used_tapes = Some(Vec::new());
 
-        // filter out unfinished backups
-        let mut snapshot_list: Vec<_> = snapshot_list
-            .into_iter()
-            .filter(|item| item.is_finished())
-            .collect();
+    // pools with backup-type slots write each backup type to its own media set
+    for (backup_type, group_list) in partition_groups(group_list, slots.backup_type) {
+        let slot = media_set_slot(&slots, run_start, backup_type)?;
 
-        if snapshot_list.is_empty() {
-            task_log!(
-                worker,
-                "{}, group {} was empty",
-                print_store_and_ns(datastore_name, group.backup_ns()),
-                group.group()
-            );
-            continue;
+        let mut pool =
+            MediaPool::with_config(TAPE_STATUS_DIR, pool_config, changer_name.clone(), false)?;
+        if let Some(ref slot) = slot {
+            task_log!(worker, "using media set slot '{slot}'");
         }
+        pool.select_slot(slot)?;
 
-        BackupInfo::sort_list(&mut snapshot_list, true); // oldest first
-
-        if latest_only {
-            progress.group_snapshots = 1;
-            if let Some(info) = snapshot_list.pop() {
-                let rel_path =
-                    print_ns_and_snapshot(info.backup_dir.backup_ns(), info.backup_dir.as_ref());
-                if pool_writer.contains_snapshot(
-                    datastore_name,
-                    info.backup_dir.backup_ns(),
-                    info.backup_dir.as_ref(),
-                ) {
-                    task_log!(worker, "skip snapshot {}", rel_path);
-                    continue;
-                }
+        let mut pool_writer = PoolWriter::new(
+            pool,
+            &setup.drive,
+            worker,
+            notification_mode.clone(),
+            force_media_set,
+            ns_magic,
+        )?;
+
+        let mut need_catalog = false; // avoid writing catalog for empty jobs
+
+        for group in group_list {
+            progress.done_groups = group_number;
+            group_number += 1;
+            progress.done_snapshots = 0;
+            progress.group_snapshots = 0;
+
+            let snapshot_list = group.list_backups()?;
+
+            // filter out unfinished backups
+            let mut snapshot_list: Vec<_> = snapshot_list
+                .into_iter()
+                .filter(|item| item.is_finished())
+                .collect();
+
+            if snapshot_list.is_empty() {
+                task_log!(
+                    worker,
+                    "{}, group {} was empty",
+                    print_store_and_ns(datastore_name, group.backup_ns()),
+                    group.group()
+                );
+                continue;
+            }
 
-                need_catalog = true;
+            BackupInfo::sort_list(&mut snapshot_list, true); // oldest first
 
-                match backup_snapshot(worker, &mut pool_writer, datastore.clone(), info.backup_dir)?
-                {
-                    SnapshotBackupResult::Success => summary.snapshot_list.push(rel_path),
-                    SnapshotBackupResult::Error => errors = true,
-                    SnapshotBackupResult::Ignored => {}
-                }
-                progress.done_snapshots = 1;
-                task_log!(worker, "percentage done: {}", progress);
+            if let Some(ref pool_catalog) = pool_catalog {
+                snapshot_list.retain(|item| {
+                    !pool_catalog.contains_snapshot(
+                        datastore_name,
+                        item.backup_dir.backup_ns(),
+                        item.backup_dir.as_ref(),
+                    )
+                });
             }
-        } else {
-            progress.group_snapshots = snapshot_list.len() as u64;
-            for (snapshot_number, info) in snapshot_list.into_iter().enumerate() {
-                let rel_path =
-                    print_ns_and_snapshot(info.backup_dir.backup_ns(), info.backup_dir.as_ref());
-
-                if pool_writer.contains_snapshot(
-                    datastore_name,
-                    info.backup_dir.backup_ns(),
-                    info.backup_dir.as_ref(),
-                ) {
-                    task_log!(worker, "skip snapshot {}", rel_path);
-                    continue;
-                }
 
-                need_catalog = true;
+            if latest_only {
+                progress.group_snapshots = 1;
+                if let Some(info) = snapshot_list.pop() {
+                    let rel_path = print_ns_and_snapshot(
+                        info.backup_dir.backup_ns(),
+                        info.backup_dir.as_ref(),
+                    );
+                    if pool_writer.contains_snapshot(
+                        datastore_name,
+                        info.backup_dir.backup_ns(),
+                        info.backup_dir.as_ref(),
+                    ) {
+                        task_log!(worker, "skip snapshot {}", rel_path);
+                        continue;
+                    }
 
-                match backup_snapshot(worker, &mut pool_writer, datastore.clone(), info.backup_dir)?
-                {
-                    SnapshotBackupResult::Success => summary.snapshot_list.push(rel_path),
-                    SnapshotBackupResult::Error => errors = true,
-                    SnapshotBackupResult::Ignored => {}
+                    need_catalog = true;
+
+                    match backup_snapshot(
+                        worker,
+                        &mut pool_writer,
+                        datastore.clone(),
+                        info.backup_dir,
+                    )? {
+                        SnapshotBackupResult::Success => summary.snapshot_list.push(rel_path),
+                        SnapshotBackupResult::Error => errors = true,
+                        SnapshotBackupResult::Ignored => {}
+                    }
+                    progress.done_snapshots = 1;
+                    task_log!(worker, "percentage done: {}", progress);
+                }
+            } else {
+                progress.group_snapshots = snapshot_list.len() as u64;
+                for (snapshot_number, info) in snapshot_list.into_iter().enumerate() {
+                    let rel_path = print_ns_and_snapshot(
+                        info.backup_dir.backup_ns(),
+                        info.backup_dir.as_ref(),
+                    );
+
+                    if pool_writer.contains_snapshot(
+                        datastore_name,
+                        info.backup_dir.backup_ns(),
+                        info.backup_dir.as_ref(),
+                    ) {
+                        task_log!(worker, "skip snapshot {}", rel_path);
+                        continue;
+                    }
+
+                    need_catalog = true;
+
+                    match backup_snapshot(
+                        worker,
+                        &mut pool_writer,
+                        datastore.clone(),
+                        info.backup_dir,
+                    )? {
+                        SnapshotBackupResult::Success => summary.snapshot_list.push(rel_path),
+                        SnapshotBackupResult::Error => errors = true,
+                        SnapshotBackupResult::Ignored => {}
+                    }
+                    progress.done_snapshots = snapshot_number as u64 + 1;
+                    task_log!(worker, "percentage done: {}", progress);
                 }
-                progress.done_snapshots = snapshot_number as u64 + 1;
-                task_log!(worker, "percentage done: {}", progress);
             }
         }
-    }
 
-    pool_writer.commit()?;
+        pool_writer.commit()?;
 
-    if need_catalog {
-        task_log!(worker, "append media catalog");
+        if need_catalog {
+            task_log!(worker, "append media catalog");
 
-        let uuid = pool_writer.load_writable_media(worker)?;
-        let done = pool_writer.append_catalog_archive(worker)?;
-        if !done {
-            task_log!(
-                worker,
-                "catalog does not fit on tape, writing to next volume"
-            );
-            pool_writer.set_media_status_full(&uuid)?;
-            pool_writer.load_writable_media(worker)?;
+            let uuid = pool_writer.load_writable_media(worker)?;
             let done = pool_writer.append_catalog_archive(worker)?;
             if !done {
-                bail!("write_catalog_archive failed on second media");
+                task_log!(
+                    worker,
+                    "catalog does not fit on tape, writing to next volume"
+                );
+                pool_writer.set_media_status_full(&uuid)?;
+                pool_writer.load_writable_media(worker)?;
+                let done = pool_writer.append_catalog_archive(worker)?;
+                if !done {
+                    bail!("write_catalog_archive failed on second media");
+                }
             }
         }
-    }
 
-    if setup.export_media_set.unwrap_or(false) {
-        pool_writer.export_media_set(worker)?;
-    } else if setup.eject_media.unwrap_or(false) {
-        pool_writer.eject_media(worker)?;
+        if setup.export_media_set.unwrap_or(false) {
+            pool_writer.export_media_set(worker)?;
+        } else if setup.eject_media.unwrap_or(false) {
+            pool_writer.eject_media(worker)?;
+        }
+
+        match pool_writer.get_used_media_labels() {
+            Ok(tapes) => {
+                if let Some(ref mut used_tapes) = used_tapes {
+                    used_tapes.extend(tapes);
+                }
+            }
+            Err(err) => {
+                task_warn!(worker, "could not collect list of used tapes: {err}");
+                used_tapes = None;
+            }
+        }
     }
 
     if errors {
         bail!("Tape backup finished with some errors. Please check the task log.");
     }
 
-    summary.used_tapes = match pool_writer.get_used_media_labels() {
-        Ok(tapes) => Some(tapes),
-        Err(err) => {
-            task_warn!(worker, "could not collect list of used tapes: {err}");
-            None
-        }
-    };
+    summary.used_tapes = used_tapes;
 
     summary.duration = start.elapsed();
 
     Ok(())
 }
 
+// Split the group list into one partition per backup type (if requested)
+//
+// The group list is sorted, so groups of the same type are adjacent.
+fn partition_groups(
+    group_list: Vec<BackupGroup>,
+    by_backup_type: bool,
+) -> Vec<(Option<BackupType>, Vec<BackupGroup>)> {
+    if !by_backup_type {
+        return vec![(None, group_list)];
+    }
+
+    let mut partitions: Vec<(Option<BackupType>, Vec<BackupGroup>)> = Vec::new();
+    for group in group_list {
+        let backup_type = Some(group.backup_type());
+        match partitions.last_mut() {
+            Some((ty, list)) if *ty == backup_type => list.push(group),
+            _ => partitions.push((backup_type, vec![group])),
+        }
+    }
+    partitions
+}
+
 // Try to update the the media online status
 fn update_media_online_status(drive: &str) -> Result<Option<String>, Error> {
     let (config, _digest) = pbs_config::drive::config()?;
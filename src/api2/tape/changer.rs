@@ -13,6 +13,7 @@ use pbs_api_types::{
 use pbs_config::CachedUserInfo;
 use pbs_tape::{
     linux_list_drives::{linux_tape_changer_list, lookup_device_identification},
+    sgutils2::sg_submit,
     ElementStatus,
 };
 
@@ -21,6 +22,7 @@ use crate::tape::{
     drive::get_tape_device_state,
     Inventory, TAPE_STATUS_DIR,
 };
+use crate::tools::async_io::shutdown_abort_signal;
 
 #[api(
     input: {
@@ -52,7 +54,8 @@ pub async fn get_status(name: String, cache: bool) -> Result<Vec<MtxStatusEntry>
 
     let mut changer_config: ScsiTapeChanger = config.lookup("changer", &name)?;
 
-    let status = tokio::task::spawn_blocking(move || changer_config.status(cache)).await??;
+    let abort = shutdown_abort_signal();
+    let status = sg_submit(&name, Some(&abort), move || changer_config.status(cache)).await?;
 
     let mut inventory = Inventory::load(TAPE_STATUS_DIR)?;
 
@@ -144,11 +147,12 @@ pub async fn transfer(name: String, from: u64, to: u64) -> Result<(), Error> {
 
     let mut changer_config: ScsiTapeChanger = config.lookup("changer", &name)?;
 
-    tokio::task::spawn_blocking(move || {
+    let abort = shutdown_abort_signal();
+    sg_submit(&name, Some(&abort), move || {
         changer_config.transfer(from, to)?;
         Ok(())
     })
-    .await?
+    .await
 }
 
 #[api(
@@ -26,6 +26,7 @@ use pbs_config::CachedUserInfo;
 use pbs_tape::{
     linux_list_drives::{lookup_device_identification, lto_tape_device_list, open_lto_tape_device},
     sg_tape::tape_alert_flags_critical,
+    sgutils2::sg_submit,
     BlockReadError,
 };
 use proxmox_rest_server::WorkerTask;
@@ -43,6 +44,7 @@ use crate::{
         lock_media_pool, lock_media_set, lock_unassigned_media_pool, Inventory, MediaCatalog,
         MediaId, TAPE_STATUS_DIR,
     },
+    tools::async_io::{shutdown_abort_signal, worker_abort_signal},
 };
 
 fn run_drive_worker<F>(
@@ -85,7 +87,9 @@ where
     // early check/lock before starting worker
     let (config, _digest) = pbs_config::drive::config()?;
     let lock_guard = lock_tape_device(&config, &drive)?;
-    tokio::task::spawn_blocking(move || {
+    // SCSI commands may block for minutes, run them outside of the runtime worker threads
+    let abort = shutdown_abort_signal();
+    sg_submit(&drive.clone(), Some(&abort), move || {
         let _lock_guard = lock_guard;
         set_tape_device_state(&drive, &state)
             .map_err(|err| format_err!("could not set tape device state: {}", err))?;
@@ -94,7 +98,7 @@ where
             .map_err(|err| format_err!("could not unset tape device state: {}", err))?;
         result
     })
-    .await?
+    .await
 }
 
 #[api(
@@ -307,6 +311,7 @@ pub fn format_media(
             }
 
             let mut handle = open_drive(&config, &drive)?;
+            handle.set_abort_signal(Some(worker_abort_signal(&worker)));
 
             if !fast.unwrap_or(true) {
                 let drive_config: LtoTapeDrive = config.lookup("lto", &drive)?;
@@ -1,21 +1,33 @@
 use std::collections::HashSet;
 
 use anyhow::{bail, format_err, Error};
-
-use proxmox_router::{list_subdirs_api_method, Permission, Router, RpcEnvironment, SubdirMap};
-use proxmox_schema::{api, param_bail};
+use futures::FutureExt;
+use hyper::http::request::Parts;
+use hyper::{header, Body, Response, StatusCode};
+use serde_json::Value;
+
+use proxmox_router::{
+    list_subdirs_api_method, ApiHandler, ApiMethod, ApiResponseFuture, Permission, Router,
+    RpcEnvironment, SubdirMap,
+};
+use proxmox_schema::{api, param_bail, BooleanSchema, ObjectSchema};
+use proxmox_sortable_macro::sortable;
 use proxmox_uuid::Uuid;
 
 use pbs_api_types::{
-    Authid, MediaContentEntry, MediaContentListFilter, MediaListEntry, MediaPoolConfig,
-    MediaSetListEntry, MediaStatus, CHANGER_NAME_SCHEMA, MEDIA_LABEL_SCHEMA,
-    MEDIA_POOL_NAME_SCHEMA, MEDIA_UUID_SCHEMA, PRIV_TAPE_AUDIT, VAULT_NAME_SCHEMA,
+    Authid, BarcodeSymbology, LabelSheetLayout, MediaContentEntry, MediaContentListFilter,
+    MediaListEntry, MediaPoolConfig, MediaSetListEntry, MediaStatus, BARCODE_LABEL_COUNT_SCHEMA,
+    BARCODE_LABEL_PREFIX_SCHEMA, BARCODE_LABEL_START_SCHEMA, BARCODE_MEDIA_TYPE_SCHEMA,
+    CHANGER_NAME_SCHEMA, MEDIA_LABEL_SCHEMA, MEDIA_POOL_NAME_SCHEMA, MEDIA_UUID_SCHEMA,
+    PRIV_TAPE_AUDIT, VAULT_NAME_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
+use pbs_tools::json::{required_integer_param, required_string_param};
 
 use crate::tape::{
-    changer::update_online_status, media_catalog_snapshot_list, Inventory, MediaCatalog, MediaPool,
-    TAPE_STATUS_DIR,
+    barcode_labels::{barcode_label_sheet_pdf, barcode_label_texts, retain_unused_label_texts},
+    changer::update_online_status,
+    media_catalog_snapshot_list, Inventory, MediaCatalog, MediaPool, TAPE_STATUS_DIR,
 };
 
 #[api(
@@ -584,7 +596,84 @@ pub const MEDIA_LIST_ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_MEDIA)
     .match_all("uuid", &MEDIA_ROUTER);
 
+#[sortable]
+pub const API_METHOD_BARCODE_LABEL_SHEET: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&barcode_label_sheet),
+    &ObjectSchema::new(
+        "Generate a PDF with printable barcode labels for new media.",
+        &sorted!([
+            ("prefix", true, &BARCODE_LABEL_PREFIX_SCHEMA),
+            ("start", true, &BARCODE_LABEL_START_SCHEMA),
+            ("count", false, &BARCODE_LABEL_COUNT_SCHEMA),
+            ("media-type", false, &BARCODE_MEDIA_TYPE_SCHEMA),
+            ("symbology", true, &BarcodeSymbology::API_SCHEMA),
+            ("layout", true, &LabelSheetLayout::API_SCHEMA),
+            (
+                "skip-existing",
+                true,
+                &BooleanSchema::new("Skip label texts already used in the inventory.")
+                    .default(false)
+                    .schema()
+            ),
+        ]),
+    ),
+)
+.access(
+    None,
+    &Permission::Privilege(&["tape"], PRIV_TAPE_AUDIT, false),
+);
+
+fn barcode_label_sheet(
+    _parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    _rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        let prefix = param["prefix"].as_str().unwrap_or("");
+        let start = param["start"].as_u64().unwrap_or(1);
+        let count = required_integer_param(&param, "count")? as u64;
+        let media_type = required_string_param(&param, "media-type")?;
+        let symbology: BarcodeSymbology = match param.get("symbology") {
+            Some(value) => serde::Deserialize::deserialize(value)?,
+            None => BarcodeSymbology::default(),
+        };
+        let layout: LabelSheetLayout = match param.get("layout") {
+            Some(value) => serde::Deserialize::deserialize(value)?,
+            None => LabelSheetLayout::default(),
+        };
+
+        let mut label_texts = barcode_label_texts(prefix, start, count, media_type)?;
+
+        if param["skip-existing"].as_bool().unwrap_or(false) {
+            retain_unused_label_texts(&mut label_texts)?;
+        }
+
+        if label_texts.is_empty() {
+            bail!("all label texts are already in use");
+        }
+
+        let pdf = barcode_label_sheet_pdf(&label_texts, symbology, layout)?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/pdf")
+            .header(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"barcode-labels.pdf\"",
+            )
+            .body(Body::from(pdf))
+            .unwrap())
+    }
+    .boxed()
+}
+
 const SUBDIRS: SubdirMap = &[
+    (
+        "barcode-labels",
+        &Router::new().get(&API_METHOD_BARCODE_LABEL_SHEET),
+    ),
     ("content", &Router::new().get(&API_METHOD_LIST_CONTENT)),
     ("destroy", &Router::new().get(&API_METHOD_DESTROY_MEDIA)),
     ("list", &MEDIA_LIST_ROUTER),
@@ -59,6 +59,7 @@ const SUBDIRS: SubdirMap = &[
     ("drive", &drive::ROUTER),
     ("media", &media::ROUTER),
     ("restore", &restore::ROUTER),
+    ("restore-stream", &restore::STREAM_ROUTER),
     (
         "scan-changers",
         &Router::new().get(&API_METHOD_SCAN_CHANGERS),
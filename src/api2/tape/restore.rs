@@ -5,13 +5,21 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
+use futures::{FutureExt, TryStreamExt};
+use hyper::http::request::Parts;
+use hyper::{header, Body, Response, StatusCode};
 use serde_json::Value;
+use tokio_stream::wrappers::ReceiverStream;
 
 use proxmox_human_byte::HumanByte;
 use proxmox_io::ReadExt;
-use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
-use proxmox_schema::{api, ApiType};
+use proxmox_router::{
+    ApiHandler, ApiMethod, ApiResponseFuture, Permission, Router, RpcEnvironment,
+    RpcEnvironmentType,
+};
+use proxmox_schema::{api, ApiType, ObjectSchema, StringSchema};
 use proxmox_section_config::SectionConfigData;
+use proxmox_sortable_macro::sortable;
 use proxmox_sys::fs::{replace_file, CreateOptions};
 use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 use proxmox_uuid::Uuid;
@@ -20,9 +28,10 @@ use pbs_api_types::{
     parse_ns_and_snapshot, print_ns_and_snapshot, Authid, BackupDir, BackupNamespace, CryptMode,
     NotificationMode, Operation, TapeRestoreNamespace, Userid, DATASTORE_MAP_ARRAY_SCHEMA,
     DATASTORE_MAP_LIST_SCHEMA, DRIVE_NAME_SCHEMA, MAX_NAMESPACE_DEPTH, PRIV_DATASTORE_BACKUP,
-    PRIV_DATASTORE_MODIFY, PRIV_TAPE_READ, TAPE_RESTORE_NAMESPACE_SCHEMA,
+    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_READ, PRIV_TAPE_READ, TAPE_RESTORE_NAMESPACE_SCHEMA,
     TAPE_RESTORE_SNAPSHOT_SCHEMA, UPID_SCHEMA,
 };
+use pbs_client::tape_restore_stream::{encode_chunk_frame, encode_end_frame, encode_file_frame};
 use pbs_config::CachedUserInfo;
 use pbs_datastore::dynamic_index::DynamicIndexReader;
 use pbs_datastore::fixed_index::FixedIndexReader;
@@ -32,6 +41,7 @@ use pbs_datastore::{DataBlob, DataStore};
 use pbs_tape::{
     BlockReadError, MediaContentHeader, TapeRead, PROXMOX_BACKUP_CONTENT_HEADER_MAGIC_1_0,
 };
+use pbs_tools::json::required_string_param;
 use proxmox_rest_server::WorkerTask;
 
 use crate::backup::check_ns_modification_privs;
@@ -265,6 +275,8 @@ fn check_and_create_namespaces(
 
 pub const ROUTER: Router = Router::new().post(&API_METHOD_RESTORE);
 
+pub const STREAM_ROUTER: Router = Router::new().download(&API_METHOD_RESTORE_STREAM);
+
 #[api(
    input: {
         properties: {
@@ -1199,6 +1211,8 @@ fn restore_partial_chunk_archive<'a>(
                 }
 
                 datastore.insert_chunk(&chunk, &digest)?;
+                // restored snapshots are often verified or restored right away
+                datastore.cache_chunk(&digest, &chunk);
             }
             Ok(())
         },
@@ -1623,6 +1637,7 @@ fn restore_chunk_archive<'a>(
                 }
 
                 datastore.insert_chunk(&chunk, &digest)?;
+                datastore.cache_chunk(&digest, &chunk);
             } else if verbose {
                 task_log!(worker2, "Found existing chunk: {}", hex::encode(digest));
             }
@@ -1988,3 +2003,382 @@ pub fn fast_catalog_restore(
 
     Ok(found_catalog)
 }
+
+#[sortable]
+pub const API_METHOD_RESTORE_STREAM: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&restore_stream),
+    &ObjectSchema::new(
+        "Stream a snapshot from tape directly to the client, without restoring it to a datastore.",
+        &sorted!([
+            ("drive", false, &DRIVE_NAME_SCHEMA),
+            (
+                "media-set",
+                false,
+                &StringSchema::new("Media set UUID.").schema()
+            ),
+            ("snapshot", false, &TAPE_RESTORE_SNAPSHOT_SCHEMA),
+        ]),
+    ),
+)
+.access(
+    Some(
+        "The user needs Tape.Read privilege on /tape/pool/{pool} and /tape/drive/{drive}, \
+        and Datastore.Read privilege on /datastore/{store}/[{namespace}] of the source datastore.",
+    ),
+    &Permission::Anybody,
+);
+
+fn restore_stream(
+    _parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+        let user_info = CachedUserInfo::new()?;
+
+        let drive = required_string_param(&param, "drive")?.to_owned();
+        let media_set = required_string_param(&param, "media-set")?.to_owned();
+        let store_snapshot = required_string_param(&param, "snapshot")?;
+
+        // we can unwrap here because of the api format
+        let idx = store_snapshot.find(':').unwrap();
+        let store = store_snapshot[..idx].to_owned();
+        let snapshot = store_snapshot[idx + 1..].to_owned();
+
+        let (ns, _dir) = parse_ns_and_snapshot(&snapshot)?;
+        user_info.check_privs(&auth_id, &ns.acl_path(&store), PRIV_DATASTORE_READ, false)?;
+        user_info.check_privs(&auth_id, &["tape", "drive", &drive], PRIV_TAPE_READ, false)?;
+
+        let media_set_uuid: Uuid = media_set.parse()?;
+
+        let _lock = lock_media_set(TAPE_STATUS_DIR, &media_set_uuid, None)?;
+
+        let inventory = Inventory::load(TAPE_STATUS_DIR)?;
+
+        let pool = inventory.lookup_media_set_pool(&media_set_uuid)?;
+        user_info.check_privs(&auth_id, &["tape", "pool", &pool], PRIV_TAPE_READ, false)?;
+
+        let (drive_config, _digest) = pbs_config::drive::config()?;
+
+        // early check/lock before starting worker
+        let drive_lock = lock_tape_device(&drive_config, &drive)?;
+
+        let (sender, receiver) = tokio::sync::mpsc::channel::<Result<Vec<u8>, Error>>(16);
+
+        WorkerTask::new_thread(
+            "tape-restore-stream",
+            Some(store.clone()),
+            auth_id.to_string(),
+            false,
+            move |worker| {
+                let _drive_lock = drive_lock; // keep lock guard
+
+                set_tape_device_state(&drive, &worker.upid().to_string())?;
+
+                task_log!(worker, "Mediaset '{media_set}'");
+                task_log!(worker, "Pool: {pool}");
+
+                let res = restore_stream_worker(
+                    worker.clone(),
+                    inventory,
+                    media_set_uuid,
+                    drive_config,
+                    &drive,
+                    &store,
+                    &snapshot,
+                    &sender,
+                );
+
+                if let Err(err) = &res {
+                    // terminate the stream with an error, so the client notices
+                    let _ = sender.blocking_send(Err(format_err!("{err}")));
+                } else {
+                    task_log!(worker, "Streamed snapshot '{store}:{snapshot}'");
+                }
+                if let Err(err) = set_tape_device_state(&drive, "") {
+                    task_log!(worker, "could not unset drive state for {drive}: {err}");
+                }
+
+                res
+            },
+        )?;
+
+        let body = Body::wrap_stream(ReceiverStream::new(receiver).map_err(move |err| {
+            log::error!("error during tape restore stream - {err}");
+            err
+        }));
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .body(body)
+            .unwrap())
+    }
+    .boxed()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn restore_stream_worker(
+    worker: Arc<WorkerTask>,
+    inventory: Inventory,
+    media_set_uuid: Uuid,
+    drive_config: SectionConfigData,
+    drive_name: &str,
+    store: &str,
+    snapshot: &str,
+    sender: &tokio::sync::mpsc::Sender<Result<Vec<u8>, Error>>,
+) -> Result<(), Error> {
+    let send = |data: Vec<u8>| {
+        sender
+            .blocking_send(Ok(data))
+            .map_err(|_| format_err!("client closed the connection"))
+    };
+
+    let notification_mode = TapeNotificationMode::from((None, None));
+
+    let catalog = get_media_set_catalog(&inventory, &media_set_uuid)?;
+
+    let (media_uuid, file_num) = catalog
+        .lookup_snapshot(store, snapshot)
+        .ok_or_else(|| format_err!("did not find snapshot '{store}:{snapshot}' in media set"))?;
+    let media_id = inventory.lookup_media(media_uuid).unwrap();
+
+    task_log!(
+        worker,
+        "found snapshot {snapshot} on {}: file {file_num}",
+        media_id.label.label_text,
+    );
+
+    task_log!(worker, "Phase 1: stream snapshot archive");
+
+    let digests = {
+        let (mut drive, _info) = request_and_load_media(
+            &worker,
+            &drive_config,
+            drive_name,
+            &media_id.label,
+            &notification_mode,
+        )?;
+
+        drive.move_to_file(file_num)?;
+        let mut reader = drive.read_next_file()?;
+
+        let header: MediaContentHeader = unsafe { reader.read_le_value()? };
+        if header.magic != PROXMOX_BACKUP_CONTENT_HEADER_MAGIC_1_0 {
+            bail!("missing MediaContentHeader");
+        }
+
+        match header.content_magic {
+            PROXMOX_BACKUP_SNAPSHOT_ARCHIVE_MAGIC_1_1
+            | PROXMOX_BACKUP_SNAPSHOT_ARCHIVE_MAGIC_1_2 => {
+                let header_data = reader.read_exact_allocated(header.size as usize)?;
+                let archive_header: SnapshotArchiveHeader = serde_json::from_slice(&header_data)
+                    .map_err(|err| {
+                        format_err!("unable to parse snapshot archive header - {err}")
+                    })?;
+                if archive_header.store != store || archive_header.snapshot != snapshot {
+                    bail!(
+                        "found unexpected snapshot {}:{} in file {file_num}",
+                        archive_header.store,
+                        archive_header.snapshot,
+                    );
+                }
+            }
+            other => bail!("unexpected file type: {other:?}"),
+        }
+
+        let mut decoder = pxar::decoder::sync::Decoder::from_std(reader)?;
+        stream_snapshot_archive(&worker, &mut decoder, &send)?
+    };
+
+    // sorted media_uuid => (sorted file_num => (set of digests)))
+    let mut media_file_chunk_map: BTreeMap<Uuid, BTreeMap<u64, HashSet<[u8; 32]>>> =
+        BTreeMap::new();
+
+    for digest in digests.into_iter() {
+        match catalog.lookup_chunk(store, &digest) {
+            Some((uuid, nr)) => {
+                let file = media_file_chunk_map.entry(uuid.clone()).or_default();
+                file.entry(nr).or_default().insert(digest);
+            }
+            None => bail!("chunk {} is missing in media set", hex::encode(digest)),
+        }
+    }
+
+    // we do not need it anymore, saves memory
+    drop(catalog);
+
+    task_log!(worker, "Phase 2: stream chunks");
+    log_required_tapes(&worker, &inventory, media_file_chunk_map.keys());
+
+    for (media_uuid, file_chunk_map) in media_file_chunk_map.iter_mut() {
+        let media_id = inventory.lookup_media(media_uuid).unwrap();
+        let (mut drive, _info) = request_and_load_media(
+            &worker,
+            &drive_config,
+            drive_name,
+            &media_id.label,
+            &notification_mode,
+        )?;
+        stream_file_chunk_map(&worker, &mut drive, store, file_chunk_map, &send)?;
+    }
+
+    send(encode_end_frame())?;
+
+    Ok(())
+}
+
+// Sends all files of the snapshot archive, returns the digests of all
+// chunks referenced by the contained indexes.
+fn stream_snapshot_archive<R: pxar::decoder::SeqRead>(
+    worker: &WorkerTask,
+    decoder: &mut pxar::decoder::sync::Decoder<R>,
+    send: &dyn Fn(Vec<u8>) -> Result<(), Error>,
+) -> Result<HashSet<[u8; 32]>, Error> {
+    match decoder.next() {
+        None => bail!("missing root entry"),
+        Some(root) => match root?.kind() {
+            pxar::EntryKind::Directory => { /* Ok */ }
+            _ => bail!("wrong root entry type"),
+        },
+    }
+
+    let mut digests = HashSet::new();
+    let mut have_manifest = false;
+
+    loop {
+        worker.check_abort()?;
+
+        let entry = match decoder.next() {
+            None => break,
+            Some(entry) => entry?,
+        };
+
+        match entry.kind() {
+            pxar::EntryKind::File { .. } => { /* Ok */ }
+            _ => bail!("wrong entry type for {:?}", entry.path()),
+        }
+
+        let filename = entry
+            .file_name()
+            .to_str()
+            .ok_or_else(|| format_err!("invalid file name {:?}", entry.file_name()))?
+            .to_owned();
+
+        let mut contents = match decoder.contents() {
+            None => bail!("missing file content"),
+            Some(contents) => contents,
+        };
+        let mut data = Vec::new();
+        std::io::copy(&mut contents, &mut data)?;
+
+        if filename == MANIFEST_BLOB_NAME {
+            have_manifest = true;
+        } else {
+            let index: Option<Box<dyn IndexFile>> = match archive_type(&filename)? {
+                ArchiveType::DynamicIndex => {
+                    Some(Box::new(DynamicIndexReader::new(index_tmpfile(&data)?)?))
+                }
+                ArchiveType::FixedIndex => {
+                    Some(Box::new(FixedIndexReader::new(index_tmpfile(&data)?)?))
+                }
+                ArchiveType::Blob => None,
+            };
+            if let Some(index) = index {
+                for i in 0..index.index_count() {
+                    if let Some(digest) = index.index_digest(i) {
+                        digests.insert(*digest);
+                    }
+                }
+            }
+        }
+
+        task_log!(worker, "send {filename}");
+        send(encode_file_frame(&filename, &data)?)?;
+    }
+
+    if !have_manifest {
+        bail!("missing manifest");
+    }
+
+    Ok(digests)
+}
+
+// Index readers need a file, so use an anonymous temporary one
+fn index_tmpfile(data: &[u8]) -> Result<std::fs::File, Error> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .read(true)
+        .custom_flags(libc::O_TMPFILE)
+        .open("/tmp")?;
+    file.write_all(data)?;
+    Ok(file)
+}
+
+fn stream_file_chunk_map(
+    worker: &WorkerTask,
+    drive: &mut Box<dyn TapeDriver>,
+    store: &str,
+    file_chunk_map: &mut BTreeMap<u64, HashSet<[u8; 32]>>,
+    send: &dyn Fn(Vec<u8>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    for (nr, chunk_list) in file_chunk_map.iter_mut() {
+        let current_file_number = drive.current_file_number()?;
+        if current_file_number != *nr {
+            task_log!(worker, "was at file {current_file_number}, moving to {nr}");
+            drive.move_to_file(*nr)?;
+        }
+        let mut reader = drive.read_next_file()?;
+        let header: MediaContentHeader = unsafe { reader.read_le_value()? };
+        if header.magic != PROXMOX_BACKUP_CONTENT_HEADER_MAGIC_1_0 {
+            bail!("file is missing the MediaContentHeader");
+        }
+
+        match header.content_magic {
+            PROXMOX_BACKUP_CHUNK_ARCHIVE_MAGIC_1_1 => {
+                let header_data = reader.read_exact_allocated(header.size as usize)?;
+
+                let archive_header: ChunkArchiveHeader = serde_json::from_slice(&header_data)
+                    .map_err(|err| format_err!("unable to parse chunk archive header - {err}"))?;
+
+                if archive_header.store != store {
+                    bail!(
+                        "unexpected chunk archive for store: {}",
+                        archive_header.store
+                    );
+                }
+            }
+            _ => bail!("unexpected content magic {:?}", header.content_magic),
+        }
+
+        let mut decoder = ChunkArchiveDecoder::new(reader);
+        let mut count = 0;
+
+        while let Some((digest, blob)) = decoder.next_chunk()? {
+            worker.check_abort()?;
+
+            if chunk_list.remove(&digest) {
+                blob.verify_crc()?;
+                send(encode_chunk_frame(&digest, &blob))?;
+                count += 1;
+            }
+            if chunk_list.is_empty() {
+                break;
+            }
+        }
+
+        if !chunk_list.is_empty() {
+            bail!("file {nr} is missing {} chunks", chunk_list.len());
+        }
+
+        task_log!(worker, "File {nr}: sent {count} chunks");
+    }
+
+    Ok(())
+}
@@ -66,6 +66,9 @@ impl Authenticator for PbsAuthenticator {
             .group(nix::unistd::Gid::from_raw(0));
 
         let data = serde_json::to_vec_pretty(&data)?;
+        if !pbs_config::preview_config_change(SHADOW_CONFIG_FILENAME, &data, true)? {
+            return Ok(());
+        }
         proxmox_sys::fs::replace_file(SHADOW_CONFIG_FILENAME, &data, options, true)?;
 
         Ok(())
@@ -84,6 +87,9 @@ impl Authenticator for PbsAuthenticator {
             .group(nix::unistd::Gid::from_raw(0));
 
         let data = serde_json::to_vec_pretty(&data)?;
+        if !pbs_config::preview_config_change(SHADOW_CONFIG_FILENAME, &data, true)? {
+            return Ok(());
+        }
         proxmox_sys::fs::replace_file(SHADOW_CONFIG_FILENAME, &data, options, true)?;
 
         Ok(())
@@ -204,6 +204,9 @@ pub fn store_ldap_bind_password(
         .group(nix::unistd::Gid::from_raw(0));
 
     let data = serde_json::to_vec_pretty(&data)?;
+    if !pbs_config::preview_config_change(LDAP_PASSWORDS_FILENAME, &data, true)? {
+        return Ok(());
+    }
     proxmox_sys::fs::replace_file(LDAP_PASSWORDS_FILENAME, &data, options, true)?;
 
     Ok(())
@@ -224,6 +227,9 @@ pub fn remove_ldap_bind_password(realm: &str, _domain_lock: &BackupLockGuard) ->
         .group(nix::unistd::Gid::from_raw(0));
 
     let data = serde_json::to_vec_pretty(&data)?;
+    if !pbs_config::preview_config_change(LDAP_PASSWORDS_FILENAME, &data, true)? {
+        return Ok(());
+    }
     proxmox_sys::fs::replace_file(LDAP_PASSWORDS_FILENAME, &data, options, true)?;
 
     Ok(())
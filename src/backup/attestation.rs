@@ -0,0 +1,314 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{HasPrivate, PKey, PKeyRef};
+use openssl::sign::Signer;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use proxmox_sys::fs::{
+    create_path, file_get_contents, file_read_optional_string, replace_file, CreateOptions,
+};
+
+use pbs_api_types::{print_ns_and_snapshot, VerifyAttestationListItem, VerifyState, UPID};
+use pbs_buildcfg::configdir;
+use pbs_datastore::backup_info::BackupDir;
+use pbs_datastore::manifest::BackupManifest;
+use pbs_datastore::DataStore;
+
+/// Directory in the datastore containing the attestations.
+const ATTESTATION_DIR: &str = ".attestations";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A file of a verified snapshot, as recorded in its manifest.
+pub struct AttestedFile {
+    pub filename: String,
+    pub size: u64,
+    /// SHA-256 checksum of the index or blob file, hex encoded
+    pub csum: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A snapshot and the result of its verification.
+pub struct AttestedSnapshot {
+    /// Namespace and path of the snapshot
+    pub snapshot: String,
+    pub files: Vec<AttestedFile>,
+    pub state: VerifyState,
+}
+
+impl AttestedSnapshot {
+    pub fn new(backup_dir: &BackupDir, manifest: &BackupManifest, state: VerifyState) -> Self {
+        Self {
+            snapshot: print_ns_and_snapshot(backup_dir.backup_ns(), backup_dir.as_ref()),
+            files: manifest
+                .files()
+                .iter()
+                .map(|info| AttestedFile {
+                    filename: info.filename.clone(),
+                    size: info.size,
+                    csum: hex::encode(info.csum),
+                })
+                .collect(),
+            state,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// The signed part of an attestation.
+struct AttestationReport {
+    store: String,
+    upid: UPID,
+    time: i64,
+    snapshots: Vec<AttestedSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// An attestation as stored in the datastore.
+///
+/// The report is kept as the exact JSON string which was signed, so that it can be checked with
+/// the public key of the server certificate, for example with `openssl dgst -sha256 -verify`.
+struct SignedAttestation {
+    report: String,
+    /// Base64 encoded SHA-256 signature of `report`
+    signature: String,
+    /// Fingerprint of the server certificate
+    fingerprint: String,
+}
+
+fn attestation_dir(datastore: &DataStore) -> PathBuf {
+    datastore.base_path().join(ATTESTATION_DIR)
+}
+
+fn attestation_file_name(upid: &UPID) -> String {
+    // UPIDs are also used as task log file names, so they are safe to use as is
+    format!("{upid}.json")
+}
+
+fn sign_report<T: HasPrivate>(
+    report: &AttestationReport,
+    key: &PKeyRef<T>,
+    fingerprint: String,
+) -> Result<SignedAttestation, Error> {
+    let report = serde_json::to_string(report)?;
+
+    let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+    signer.update(report.as_bytes())?;
+    let signature = base64::encode(signer.sign_to_vec()?);
+
+    Ok(SignedAttestation {
+        report,
+        signature,
+        fingerprint,
+    })
+}
+
+fn store_attestation(
+    dir: &Path,
+    upid: &UPID,
+    attestation: &SignedAttestation,
+    options: CreateOptions,
+) -> Result<(), Error> {
+    create_path(dir, None, Some(options.clone()))?;
+    let path = dir.join(attestation_file_name(upid));
+    if path.exists() {
+        bail!("attestation for task {upid} already exists");
+    }
+
+    replace_file(
+        path,
+        serde_json::to_string_pretty(attestation)?.as_bytes(),
+        options.perm(nix::sys::stat::Mode::from_bits_truncate(0o640)),
+        true,
+    )
+}
+
+/// Signs a report of the `snapshots` verified by the task `upid` with the key of the server
+/// certificate and stores it in the datastore, identified by the UPID.
+pub fn create_attestation(
+    datastore: &DataStore,
+    upid: &UPID,
+    snapshots: Vec<AttestedSnapshot>,
+) -> Result<(), Error> {
+    let report = AttestationReport {
+        store: datastore.name().to_string(),
+        upid: upid.clone(),
+        time: proxmox_time::epoch_i64(),
+        snapshots,
+    };
+
+    let key_path = configdir!("/proxy.key");
+    let key = PKey::private_key_from_pem(&file_get_contents(key_path)?)
+        .map_err(|err| format_err!("unable to load server key {key_path} - {err}"))?;
+    let attestation = sign_report(&report, &key, crate::cert_info()?.fingerprint()?)?;
+
+    let backup_user = pbs_config::backup_user()?;
+    let options = CreateOptions::new()
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+
+    store_attestation(&attestation_dir(datastore), upid, &attestation, options)
+}
+
+fn list_attestations_in(dir: &Path) -> Result<Vec<VerifyAttestationListItem>, Error> {
+    let mut list = Vec::new();
+
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(list),
+        Err(err) => bail!("unable to read {dir:?} - {err}"),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "json") {
+            continue;
+        }
+        let item = proxmox_lang::try_block!({
+            let attestation: SignedAttestation =
+                serde_json::from_slice(&file_get_contents(&path)?)?;
+            let report: AttestationReport = serde_json::from_str(&attestation.report)?;
+            Ok::<_, Error>(VerifyAttestationListItem {
+                time: report.time,
+                upid: report.upid,
+                snapshots: report.snapshots.len() as u64,
+                failed: report
+                    .snapshots
+                    .iter()
+                    .filter(|snapshot| snapshot.state != VerifyState::Ok)
+                    .count() as u64,
+                fingerprint: attestation.fingerprint,
+            })
+        });
+        match item {
+            Ok(item) => list.push(item),
+            Err(err) => log::error!("unable to parse attestation {path:?} - {err}"),
+        }
+    }
+
+    list.sort_unstable_by_key(|item| item.time);
+
+    Ok(list)
+}
+
+/// Lists the attestations stored in the datastore, oldest first.
+pub fn list_attestations(datastore: &DataStore) -> Result<Vec<VerifyAttestationListItem>, Error> {
+    list_attestations_in(&attestation_dir(datastore))
+}
+
+fn read_attestation_in(dir: &Path, upid: &UPID) -> Result<Option<Value>, Error> {
+    match file_read_optional_string(dir.join(attestation_file_name(upid)))? {
+        Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the attestation of the verify task `upid`.
+pub fn read_attestation(datastore: &DataStore, upid: &UPID) -> Result<Value, Error> {
+    read_attestation_in(&attestation_dir(datastore), upid)?.ok_or_else(|| {
+        format_err!(
+            "no attestation for task {upid} in datastore '{}'",
+            datastore.name()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::sign::Verifier;
+
+    use super::*;
+
+    // test directory below ./target/testout, removed again when dropped, also if the test fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let mut path: PathBuf = String::from("./target/testout").into();
+            path.push(std::module_path!());
+            path.push(name);
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn test_upid(worker_id: &str) -> UPID {
+        format!(
+            "UPID:test:00000001:00000002:00000000:65000000:verificationjob:{worker_id}:root@pam:"
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_attestation_roundtrip() -> Result<(), Error> {
+        let test_dir = TestDir::new("roundtrip");
+        let dir = &test_dir.0;
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+        let upid = test_upid("store1\\x3ajob1");
+        let report = AttestationReport {
+            store: "store1".to_string(),
+            upid: upid.clone(),
+            time: 1700000000,
+            snapshots: vec![
+                AttestedSnapshot {
+                    snapshot: "vm/100/2023-11-14T22:13:20Z".to_string(),
+                    files: vec![AttestedFile {
+                        filename: "index.json.blob".to_string(),
+                        size: 42,
+                        csum: "00".repeat(32),
+                    }],
+                    state: VerifyState::Ok,
+                },
+                AttestedSnapshot {
+                    snapshot: "ns/a/ct/101/2023-11-14T22:13:20Z".to_string(),
+                    files: Vec::new(),
+                    state: VerifyState::Failed,
+                },
+            ],
+        };
+        let attestation = sign_report(&report, &key, "aa:bb".to_string())?;
+        store_attestation(dir, &upid, &attestation, CreateOptions::new())?;
+        // reports are never replaced
+        assert!(store_attestation(dir, &upid, &attestation, CreateOptions::new()).is_err());
+
+        let list = list_attestations_in(dir)?;
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].upid, upid);
+        assert_eq!(list[0].time, 1700000000);
+        assert_eq!((list[0].snapshots, list[0].failed), (2, 1));
+        assert_eq!(list[0].fingerprint, "aa:bb");
+
+        assert!(read_attestation_in(dir, &test_upid("other"))?.is_none());
+        let stored = read_attestation_in(dir, &upid)?.unwrap();
+        let signed = stored["report"].as_str().unwrap();
+        let signature = base64::decode(stored["signature"].as_str().unwrap())?;
+
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+        verifier.update(signed.as_bytes())?;
+        assert!(verifier.verify(&signature)?);
+
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+        verifier.update(signed.replace("failed", "ok").as_bytes())?;
+        assert!(!verifier.verify(&signature)?);
+
+        Ok(())
+    }
+}
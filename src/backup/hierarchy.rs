@@ -48,7 +48,25 @@ pub fn check_ns_privs_full(
     full_access_privs: u64,
     partial_access_privs: u64,
 ) -> Result<bool, Error> {
-    let user_info = CachedUserInfo::new()?;
+    check_user_ns_privs_full(
+        &CachedUserInfo::new()?,
+        store,
+        ns,
+        auth_id,
+        full_access_privs,
+        partial_access_privs,
+    )
+}
+
+/// Like [`check_ns_privs_full`], but uses an already loaded `user_info`.
+pub fn check_user_ns_privs_full(
+    user_info: &CachedUserInfo,
+    store: &str,
+    ns: &BackupNamespace,
+    auth_id: &Authid,
+    full_access_privs: u64,
+    partial_access_privs: u64,
+) -> Result<bool, Error> {
     let acl_path = ns.acl_path(store);
     let privs = user_info.lookup_privs(auth_id, &acl_path);
 
@@ -68,6 +86,27 @@ pub fn check_ns_privs_full(
     );
 }
 
+/// Asserts that `auth_id` is not restricted to append-only access.
+///
+/// Must be checked by all operations removing or modifying existing backups.
+pub fn check_not_append_only(auth_id: &Authid) -> Result<(), Error> {
+    check_user_not_append_only(&CachedUserInfo::new()?, auth_id)
+}
+
+/// Like [`check_not_append_only`], but uses an already loaded `user_info`.
+pub fn check_user_not_append_only(
+    user_info: &CachedUserInfo,
+    auth_id: &Authid,
+) -> Result<(), Error> {
+    if user_info.is_append_only(auth_id) {
+        proxmox_router::http_bail!(
+            FORBIDDEN,
+            "'{auth_id}' is append-only, removing or modifying existing backups is not allowed"
+        );
+    }
+    Ok(())
+}
+
 pub fn can_access_any_namespace(
     store: Arc<DataStore>,
     auth_id: &Authid,
@@ -6,5 +6,8 @@ pub const CATALOG_NAME: &str = "catalog.pcat1.didx";
 mod verify;
 pub use verify::*;
 
+mod attestation;
+pub use attestation::*;
+
 mod hierarchy;
 pub use hierarchy::*;
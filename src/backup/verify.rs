@@ -2,24 +2,25 @@ use nix::dir::Dir;
 use std::collections::HashSet;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, format_err, Error};
 
-use proxmox_sys::{task_log, WorkerTaskContext};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 
 use pbs_api_types::{
     print_ns_and_snapshot, print_store_and_ns, Authid, BackupNamespace, BackupType, CryptMode,
     SnapshotVerifyState, VerifyState, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_VERIFY, UPID,
 };
 use pbs_datastore::backup_info::{BackupDir, BackupGroup, BackupInfo};
-use pbs_datastore::index::IndexFile;
+use pbs_datastore::index::{IndexAccessPattern, IndexFile};
 use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest, FileInfo};
 use pbs_datastore::{DataBlob, DataStore, StoreProgress};
 use proxmox_sys::fs::lock_dir_noblock_shared;
 
 use crate::tools::parallel_handler::ParallelHandler;
 
+use crate::backup::attestation::{create_attestation, AttestedSnapshot};
 use crate::backup::hierarchy::ListAccessibleBackupGroups;
 
 /// A VerifyWorker encapsulates a task worker, datastore and information about which chunks have
@@ -29,6 +30,10 @@ pub struct VerifyWorker {
     datastore: Arc<DataStore>,
     verified_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
     corrupt_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    // snapshots and groups skipped because the datastore's removable device was detached
+    skipped_unavailable: Arc<Mutex<Vec<String>>>,
+    // snapshots with their new verify state, only collected for attestations
+    verified_snapshots: Option<Arc<Mutex<Vec<AttestedSnapshot>>>>,
 }
 
 impl VerifyWorker {
@@ -41,8 +46,48 @@ impl VerifyWorker {
             verified_chunks: Arc::new(Mutex::new(HashSet::with_capacity(16 * 1024))),
             // start with 64 chunks since we assume there are few corrupt ones
             corrupt_chunks: Arc::new(Mutex::new(HashSet::with_capacity(64))),
+            skipped_unavailable: Arc::new(Mutex::new(Vec::new())),
+            verified_snapshots: None,
         }
     }
+
+    /// Records the verified snapshots, so that an attestation can be created with
+    /// [VerifyWorker::attest].
+    pub fn with_attestation(mut self) -> Self {
+        self.verified_snapshots = Some(Arc::new(Mutex::new(Vec::new())));
+        self
+    }
+
+    /// Signs and stores a report of the snapshots verified so far, see [create_attestation].
+    /// The report is identified by the UPID of the verify task.
+    pub fn attest(&self, upid: &UPID) -> Result<(), Error> {
+        let snapshots = match self.verified_snapshots {
+            Some(ref snapshots) => std::mem::take(&mut *snapshots.lock().unwrap()),
+            None => bail!("verified snapshots were not recorded for an attestation"),
+        };
+        create_attestation(&self.datastore, upid, snapshots)
+    }
+
+    /// Returns the snapshots and groups which were skipped, because the datastore became
+    /// unavailable (i.e. its removable device was detached) while verifying.
+    pub fn skipped_unavailable(&self) -> Vec<String> {
+        self.skipped_unavailable.lock().unwrap().clone()
+    }
+
+    // Checks if the datastore is still available, records `what` as skipped otherwise.
+    fn check_available(&self, what: String) -> bool {
+        if self.datastore.is_available() {
+            return true;
+        }
+        task_warn!(
+            self.worker,
+            "SKIPPED: verify {}:{} - datastore not available, removable device detached",
+            self.datastore.name(),
+            what,
+        );
+        self.skipped_unavailable.lock().unwrap().push(what);
+        false
+    }
 }
 
 fn verify_blob(backup_dir: &BackupDir, info: &FileInfo) -> Result<(), Error> {
@@ -69,40 +114,27 @@ fn verify_blob(backup_dir: &BackupDir, info: &FileInfo) -> Result<(), Error> {
     }
 }
 
-fn rename_corrupted_chunk(
-    datastore: Arc<DataStore>,
-    digest: &[u8; 32],
-    worker: &dyn WorkerTaskContext,
-) {
-    let (path, digest_str) = datastore.chunk_path(digest);
-
-    let mut counter = 0;
-    let mut new_path = path.clone();
-    loop {
-        new_path.set_file_name(format!("{}.{}.bad", digest_str, counter));
-        if new_path.exists() && counter < 9 {
-            counter += 1;
-        } else {
-            break;
-        }
-    }
+/// Number of decoder threads a verification starts with
+const DECODER_THREADS: usize = 4;
 
-    match std::fs::rename(&path, &new_path) {
-        Ok(_) => {
-            task_log!(worker, "corrupted chunk renamed to {:?}", &new_path);
-        }
-        Err(err) => {
-            match err.kind() {
-                std::io::ErrorKind::NotFound => { /* ignored */ }
-                _ => task_log!(
-                    worker,
-                    "could not rename corrupted chunk {:?} - {}",
-                    &path,
-                    err
-                ),
-            }
-        }
-    };
+/// Number of chunks after which the decoder threads are adapted to the back-pressure
+const DECODER_SCALE_INTERVAL: u64 = 1024;
+
+/// The number of decoder threads for the next interval.
+///
+/// If reading had to wait for the decoders for more than a quarter of the last interval,
+/// decoding is the bottleneck and another thread is added, up to `max`.
+fn scale_decoder_threads(
+    threads: usize,
+    max: usize,
+    blocked: Duration,
+    interval: Duration,
+) -> usize {
+    if threads < max && blocked * 4 > interval {
+        threads + 1
+    } else {
+        threads
+    }
 }
 
 fn verify_index_chunks(
@@ -123,9 +155,9 @@ fn verify_index_chunks(
     let verified_chunks2 = Arc::clone(&verify_worker.verified_chunks);
     let errors2 = Arc::clone(&errors);
 
-    let decoder_pool = ParallelHandler::new(
+    let mut decoder_pool = ParallelHandler::new(
         "verify chunk decoder",
-        4,
+        DECODER_THREADS,
         move |(chunk, digest, size): (DataBlob, [u8; 32], u64)| {
             let chunk_crypt_mode = match chunk.crypt_mode() {
                 Err(err) => {
@@ -151,7 +183,7 @@ fn verify_index_chunks(
                 corrupt_chunks2.lock().unwrap().insert(digest);
                 task_log!(worker2, "{}", err);
                 errors2.fetch_add(1, Ordering::SeqCst);
-                rename_corrupted_chunk(datastore2.clone(), &digest, &worker2);
+                datastore2.rename_corrupted_chunk(&digest, &worker2);
             } else {
                 verified_chunks2.lock().unwrap().insert(digest);
             }
@@ -200,6 +232,17 @@ fn verify_index_chunks(
             .datastore
             .get_chunks_in_order(&*index, skip_chunk, check_abort)?;
 
+    // chunks are processed in on-disk order, not in index order
+    index.advise(IndexAccessPattern::Random);
+
+    let max_decoder_threads = std::thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(DECODER_THREADS)
+        .max(DECODER_THREADS);
+    let mut sent_chunks = 0u64;
+    let mut interval_start = Instant::now();
+    let mut interval_blocked = Duration::ZERO;
+
     for (pos, _) in chunk_list {
         verify_worker.worker.check_abort()?;
         verify_worker.worker.fail_on_shutdown()?;
@@ -211,6 +254,7 @@ fn verify_index_chunks(
             continue; // already verified or marked corrupt
         }
 
+        // never use the chunk cache here, verify has to detect corruption on disk
         match verify_worker.datastore.load_chunk(&info.digest) {
             Err(err) => {
                 verify_worker
@@ -224,21 +268,36 @@ fn verify_index_chunks(
                     err
                 );
                 errors.fetch_add(1, Ordering::SeqCst);
-                rename_corrupted_chunk(
-                    verify_worker.datastore.clone(),
-                    &info.digest,
-                    &verify_worker.worker,
-                );
+                verify_worker
+                    .datastore
+                    .rename_corrupted_chunk(&info.digest, &verify_worker.worker);
             }
             Ok(chunk) => {
                 let size = info.size();
                 read_bytes += chunk.raw_size();
                 decoder_pool.send((chunk, info.digest, size))?;
                 decoded_bytes += size;
+
+                sent_chunks += 1;
+                if sent_chunks % DECODER_SCALE_INTERVAL == 0 {
+                    let blocked = decoder_pool.stats().blocked_time;
+                    let threads = scale_decoder_threads(
+                        decoder_pool.threads(),
+                        max_decoder_threads,
+                        blocked - interval_blocked,
+                        interval_start.elapsed(),
+                    );
+                    if threads != decoder_pool.threads() {
+                        decoder_pool.set_threads(threads)?;
+                    }
+                    interval_start = Instant::now();
+                    interval_blocked = blocked;
+                }
             }
         }
     }
 
+    let decoder_stats = decoder_pool.stats();
     decoder_pool.complete()?;
 
     let elapsed = start_time.elapsed().as_secs_f64();
@@ -261,6 +320,7 @@ fn verify_index_chunks(
         decode_speed,
         error_count,
     );
+    task_log!(verify_worker.worker, "  decoder pool: {}", decoder_stats);
 
     if errors.load(Ordering::SeqCst) > 0 {
         bail!("chunks could not be verified");
@@ -328,6 +388,13 @@ pub fn verify_backup_dir(
     upid: UPID,
     filter: Option<&dyn Fn(&BackupManifest) -> bool>,
 ) -> Result<bool, Error> {
+    if !verify_worker.check_available(print_ns_and_snapshot(
+        backup_dir.backup_ns(),
+        backup_dir.as_ref(),
+    )) {
+        return Ok(true);
+    }
+
     if !backup_dir.full_path().exists() {
         task_log!(
             verify_worker.worker,
@@ -431,6 +498,16 @@ pub fn verify_backup_dir_with_lock(
         }
     }
 
+    // errors caused by a detached device say nothing about the snapshot, so don't record them
+    if error_count > 0
+        && !verify_worker.check_available(print_ns_and_snapshot(
+            backup_dir.backup_ns(),
+            backup_dir.as_ref(),
+        ))
+    {
+        return Ok(true);
+    }
+
     let verify_state = SnapshotVerifyState {
         state: verify_result,
         upid,
@@ -442,6 +519,13 @@ pub fn verify_backup_dir_with_lock(
         })
         .map_err(|err| format_err!("unable to update manifest blob - {}", err))?;
 
+    if let Some(ref snapshots) = verify_worker.verified_snapshots {
+        snapshots
+            .lock()
+            .unwrap()
+            .push(AttestedSnapshot::new(backup_dir, &manifest, verify_result));
+    }
+
     Ok(error_count == 0)
 }
 
@@ -580,6 +664,15 @@ pub fn verify_all_backups(
         progress.done_snapshots = 0;
         progress.group_snapshots = 0;
 
+        let group_str = if group.backup_ns().is_root() {
+            group.group().to_string()
+        } else {
+            format!("{}/{}", group.backup_ns().display_as_path(), group.group())
+        };
+        if !verify_worker.check_available(group_str) {
+            continue;
+        }
+
         let mut group_errors =
             verify_backup_group(verify_worker, &group, &mut progress, upid, filter)?;
         errors.append(&mut group_errors);
@@ -614,3 +707,30 @@ pub fn verify_filter(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_decoder_threads() {
+        let interval = Duration::from_secs(10);
+
+        // decoders keep up
+        assert_eq!(scale_decoder_threads(4, 8, Duration::ZERO, interval), 4);
+        assert_eq!(
+            scale_decoder_threads(4, 8, Duration::from_secs(2), interval),
+            4
+        );
+
+        // reading waits for the decoders
+        assert_eq!(
+            scale_decoder_threads(4, 8, Duration::from_secs(3), interval),
+            5
+        );
+        assert_eq!(scale_decoder_threads(7, 8, interval, interval), 8);
+
+        // never more than the CPUs
+        assert_eq!(scale_decoder_threads(8, 8, interval, interval), 8);
+    }
+}
@@ -13,6 +13,8 @@ fn main() {
         .insert("inspect", inspect::inspect_commands())
         .insert("recover", recover::recover_commands())
         .insert("api", api::api_commands())
+        .insert("bundle", bundle::bundle_commands())
+        .insert("chunk-refs", chunk_refs::chunk_refs_commands())
         .insert("diff", diff::diff_commands());
 
     let uid = nix::unistd::Uid::current();
@@ -1,8 +1,9 @@
 use std::collections::HashMap;
+use std::ffi::OsString;
 use std::io::{self, Write};
 use std::str::FromStr;
 
-use anyhow::{format_err, Error};
+use anyhow::{bail, format_err, Error};
 use serde_json::{json, Value};
 
 use proxmox_router::{cli::*, RpcEnvironment};
@@ -11,13 +12,14 @@ use proxmox_sys::fs::CreateOptions;
 
 use pbs_api_types::percent_encoding::percent_encode_component;
 use pbs_api_types::{
-    BackupNamespace, GroupFilter, RateLimitConfig, SyncJobConfig, DATASTORE_SCHEMA,
-    GROUP_FILTER_LIST_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA, NS_MAX_DEPTH_SCHEMA,
+    BackupNamespace, GroupFilter, JobResult, RateLimitConfig, SyncJobConfig, DATASTORE_SCHEMA,
+    GROUP_FILTER_LIST_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA, JOB_ID_SCHEMA, NS_MAX_DEPTH_SCHEMA,
     REMOTE_ID_SCHEMA, REMOVE_VANISHED_BACKUPS_SCHEMA, TRANSFER_LAST_SCHEMA, UPID_SCHEMA,
-    VERIFICATION_OUTDATED_AFTER_SCHEMA,
+    VERIFICATION_OUTDATED_AFTER_SCHEMA, VERIFY_SYNCED_SCHEMA,
 };
+use pbs_client::tools::completions::completions_cmd_def;
 use pbs_client::{display_task_log, view_task_result};
-use pbs_config::sync;
+use pbs_config::{sync, ConfigWriteMode};
 use pbs_tools::json::required_string_param;
 
 use proxmox_rest_server::wait_for_local_worker;
@@ -280,6 +282,81 @@ async fn task_stop(param: Value) -> Result<Value, Error> {
     Ok(Value::Null)
 }
 
+#[api(
+    input: {
+        properties: {
+            "job-type": {
+                type: String,
+                description: "Only list runs of this job type (e.g. 'syncjob' or 'garbage_collection').",
+                optional: true,
+            },
+            id: {
+                schema: JOB_ID_SCHEMA,
+                optional: true,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            result: {
+                type: JobResult,
+                optional: true,
+            },
+            since: {
+                type: Integer,
+                description: "Only list runs which finished at or after this time (UNIX epoch).",
+                optional: true,
+            },
+            until: {
+                type: Integer,
+                description: "Only list runs which finished before this time (UNIX epoch).",
+                optional: true,
+            },
+            limit: {
+                type: Integer,
+                description: "Maximum number of runs to list.",
+                optional: true,
+                minimum: 1,
+                default: 50,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List the results of finished job runs.
+async fn job_history(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let client = connect_to_localhost()?;
+
+    let mut result = client
+        .get("api2/json/admin/job-history", Some(param))
+        .await?;
+
+    let mut data = result["data"].take();
+    let return_type = &api2::admin::job_history::API_METHOD_LIST_JOB_HISTORY.returns;
+
+    use pbs_tools::format::render_epoch;
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("job-type"))
+        .column(ColumnConfig::new("job-id"))
+        .column(ColumnConfig::new("store"))
+        .column(
+            ColumnConfig::new("endtime")
+                .right_align(false)
+                .renderer(render_epoch),
+        )
+        .column(ColumnConfig::new("result"))
+        .column(ColumnConfig::new("message"));
+
+    format_and_print_result_full(&mut data, return_type, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
 fn task_mgmt_cli() -> CommandLineInterface {
     let task_log_cmd_def = CliCommand::new(&API_METHOD_TASK_LOG).arg_param(&["upid"]);
 
@@ -287,6 +364,11 @@ fn task_mgmt_cli() -> CommandLineInterface {
 
     let cmd_def = CliCommandMap::new()
         .insert("list", CliCommand::new(&API_METHOD_TASK_LIST))
+        .insert(
+            "history",
+            CliCommand::new(&API_METHOD_JOB_HISTORY)
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
         .insert("log", task_log_cmd_def)
         .insert("stop", task_stop_cmd_def);
 
@@ -338,6 +420,10 @@ fn task_mgmt_cli() -> CommandLineInterface {
                 schema: TRANSFER_LAST_SCHEMA,
                 optional: true,
             },
+            "verify-synced": {
+                schema: VERIFY_SYNCED_SCHEMA,
+                optional: true,
+            },
         }
    }
 )]
@@ -354,6 +440,7 @@ async fn pull_datastore(
     group_filter: Option<Vec<GroupFilter>>,
     limit: RateLimitConfig,
     transfer_last: Option<usize>,
+    verify_synced: Option<bool>,
     param: Value,
 ) -> Result<Value, Error> {
     let output_format = get_output_format(&param);
@@ -390,6 +477,10 @@ async fn pull_datastore(
         args["transfer-last"] = json!(transfer_last)
     }
 
+    if let Some(verify_synced) = verify_synced {
+        args["verify-synced"] = Value::from(verify_synced);
+    }
+
     let mut limit_json = json!(limit);
     let limit_map = limit_json
         .as_object_mut()
@@ -499,6 +590,10 @@ async fn run() -> Result<(), Error> {
         .insert("datastore", datastore_commands())
         .insert("disk", disk_commands())
         .insert("dns", dns_commands())
+        .insert("expectation", expectation_commands())
+        .insert("hook", hook_commands())
+        .insert("webhook", webhook_commands())
+        .insert("log-forward", log_forward_commands())
         .insert("ldap", ldap_commands())
         .insert("ad", ad_commands())
         .insert("network", network_commands())
@@ -507,10 +602,12 @@ async fn run() -> Result<(), Error> {
         .insert("user", user_commands())
         .insert("openid", openid_commands())
         .insert("remote", remote_commands())
+        .insert("reader-queue", reader_queue_commands())
         .insert("traffic-control", traffic_control_commands())
         .insert("garbage-collection", garbage_collection_commands())
         .insert("acme", acme_mgmt_cli())
         .insert("cert", cert_mgmt_cli())
+        .insert("check", check_commands())
         .insert("subscription", subscription_commands())
         .insert("sync-job", sync_job_commands())
         .insert("verify-job", verify_job_commands())
@@ -534,13 +631,16 @@ async fn run() -> Result<(), Error> {
                 .completion_cb("store", pbs_config::datastore::complete_datastore_name),
         )
         .insert("report", CliCommand::new(&API_METHOD_REPORT))
-        .insert("versions", CliCommand::new(&API_METHOD_GET_VERSIONS));
+        .insert("setup", setup_cmd_def())
+        .insert("versions", CliCommand::new(&API_METHOD_GET_VERSIONS))
+        .insert("completions", completions_cmd_def());
 
     let args: Vec<String> = std::env::args().take(2).collect();
     if args.len() >= 2 && args[1] == "update-to-prune-jobs-config" {
         return update_to_prune_jobs_config();
     }
-    let avoid_init = args.len() >= 2 && (args[1] == "bashcomplete" || args[1] == "printdoc");
+    let avoid_init = args.len() >= 2
+        && (args[1] == "bashcomplete" || args[1] == "printdoc" || args[1] == "completions");
 
     if !avoid_init {
         let backup_user = pbs_config::backup_user()?;
@@ -569,9 +669,99 @@ async fn run() -> Result<(), Error> {
     Ok(())
 }
 
+/// Passes the configuration write mode to the re-executed command.
+const CONFIG_WRITE_MODE_ENV: &str = "PBS_CONFIG_WRITE_MODE";
+
+/// Commands supporting '--dry-run' and '--diff', as they only change configuration files.
+///
+/// Everything else may have side effects which cannot be previewed, like wiping a disk or
+/// reloading the network, and is rejected.
+const CONFIG_WRITE_MODE_COMMANDS: &[(&str, &[&str])] = &[
+    ("acl", &["update"]),
+    ("ad", &["create", "update", "delete"]),
+    ("datastore", &["update"]),
+    ("dns", &["set"]),
+    ("expectation", &["create", "update", "remove"]),
+    ("hook", &["create", "update", "remove"]),
+    ("ldap", &["create", "update", "delete"]),
+    ("log-forward", &["create", "update", "remove"]),
+    ("network", &["create", "update", "remove"]),
+    ("node", &["update"]),
+    ("openid", &["create", "update", "delete"]),
+    ("prune-job", &["create", "update", "remove"]),
+    ("remote", &["create", "update", "remove"]),
+    ("sync-job", &["create", "update", "remove"]),
+    ("traffic-control", &["create", "update", "remove"]),
+    (
+        "user",
+        &[
+            "create",
+            "update",
+            "remove",
+            "generate-token",
+            "update-token",
+            "delete-token",
+        ],
+    ),
+    ("verify-job", &["create", "update", "remove"]),
+    ("webhook", &["create", "update", "remove"]),
+];
+
+/// Handle the global '--dry-run' and '--diff' options.
+///
+/// The command line parser only knows about the options of the individual commands, so strip
+/// them and re-execute the command with the mode passed via the environment.
+fn handle_config_write_mode() -> Result<(), Error> {
+    match std::env::var(CONFIG_WRITE_MODE_ENV).as_deref() {
+        Ok("dry-run") => pbs_config::set_config_write_mode(ConfigWriteMode::DryRun),
+        Ok("diff") => pbs_config::set_config_write_mode(ConfigWriteMode::Diff),
+        _ => (),
+    }
+
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    if args.len() < 2 || args[1] == "bashcomplete" || args[1] == "printdoc" {
+        return Ok(());
+    }
+
+    let mode = if args.iter().any(|arg| arg == "--dry-run") {
+        "dry-run"
+    } else if args.iter().any(|arg| arg == "--diff") {
+        "diff"
+    } else {
+        return Ok(());
+    };
+    args.retain(|arg| arg != "--dry-run" && arg != "--diff");
+
+    let mut command = args[1..]
+        .iter()
+        .filter_map(|arg| arg.to_str())
+        .filter(|arg| !arg.starts_with('-'));
+    let (group, subcommand) = (command.next(), command.next());
+    let supported = CONFIG_WRITE_MODE_COMMANDS
+        .iter()
+        .any(|(name, subcommands)| {
+            Some(*name) == group && subcommand.map_or(false, |sub| subcommands.contains(&sub))
+        });
+    if !supported {
+        bail!(
+            "'--{mode}' is not supported for command '{}'",
+            [group, subcommand]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+    }
+
+    let err = pbs_tools::reexec_with_env(&args, CONFIG_WRITE_MODE_ENV, mode);
+    bail!("unable to execute command in {mode} mode - {err}");
+}
+
 fn main() -> Result<(), Error> {
     proxmox_backup::tools::setup_safe_path_env();
 
+    handle_config_write_mode()?;
+
     proxmox_async::runtime::main(run())
 }
 
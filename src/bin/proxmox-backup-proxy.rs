@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
@@ -58,6 +59,8 @@ use proxmox_backup::tools::{
 
 use proxmox_backup::api2::pull::do_sync_job;
 use proxmox_backup::api2::tape::backup::do_tape_backup_job;
+use proxmox_backup::server::do_ephemeral_expiry;
+use proxmox_backup::server::do_expectation_check;
 use proxmox_backup::server::do_prune_job;
 use proxmox_backup::server::do_verification_job;
 
@@ -77,9 +80,17 @@ fn main() -> Result<(), Error> {
         );
     }
 
-    proxmox_async::runtime::main(run())
+    // the environment is inherited by the daemon re-executed on reload, so this tells a
+    // (re)start of the service apart from a reload
+    let initial_start = std::env::var_os(ENV_VAR_PROXY_STARTED).is_none();
+    std::env::set_var(ENV_VAR_PROXY_STARTED, "1");
+
+    proxmox_async::runtime::main(run(initial_start))
 }
 
+/// Set once the proxy started, see [main]
+const ENV_VAR_PROXY_STARTED: &str = "PROXMOX_BACKUP_PROXY_STARTED";
+
 /// check for a cookie with the user-preferred language, fallback to the config one if not set or
 /// not existing
 fn get_language(headers: &http::HeaderMap) -> String {
@@ -180,7 +191,7 @@ async fn get_index_future(env: RestEnvironment, parts: Parts) -> Response<Body>
     resp
 }
 
-async fn run() -> Result<(), Error> {
+async fn run(initial_start: bool) -> Result<(), Error> {
     // Note: To debug early connection error use
     // PROXMOX_DEBUG=1 ./target/release/proxmox-backup-proxy
     let debug = std::env::var("PROXMOX_DEBUG").is_ok();
@@ -300,6 +311,19 @@ async fn run() -> Result<(), Error> {
         Ok(Value::Null)
     })?;
 
+    // debug: compare tracked chunk references with the on-disk state
+    command_sock.register_command("chunk-ref-report".to_string(), |value| {
+        let store = value
+            .and_then(Value::as_str)
+            .ok_or_else(|| format_err!("missing datastore name"))?;
+        let datastore = DataStore::lookup_datastore(store, Some(Operation::Lookup))?;
+        // reads all tracked indexes and stats their chunks, keep that off the async runtime
+        let report = proxmox_async::runtime::block_in_place(|| {
+            pbs_datastore::chunk_ref_tracker::report(&datastore)
+        })?;
+        Ok(serde_json::to_value(report)?)
+    })?;
+
     let connections = proxmox_rest_server::connection::AcceptBuilder::new()
         .debug(debug)
         .rate_limiter_lookup(Arc::new(lookup_rate_limiter))
@@ -372,12 +396,20 @@ async fn run() -> Result<(), Error> {
     });
 
     start_task_scheduler();
+    let write_epoch_stores = start_write_epochs();
+    start_write_epoch_sync(write_epoch_stores.clone());
+    if initial_start {
+        // on reload, the backups of the old daemon may still be running
+        start_partial_snapshot_recovery();
+    }
     start_stat_generator();
     start_traffic_control_updater();
+    start_snmp_subagent();
 
     server.await?;
     log::info!("server shutting down, waiting for active workers to complete");
     proxmox_rest_server::last_worker_future().await?;
+    end_write_epochs(&write_epoch_stores);
     log::info!("done - exit server");
 
     Ok(())
@@ -419,6 +451,177 @@ fn start_task_scheduler() {
     tokio::spawn(task.map(|_| ()));
 }
 
+/// Interval of the file system syncs recorded in the write epoch markers
+const WRITE_EPOCH_SYNC_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Datastores with a write epoch started by this process
+type WriteEpochStores = Arc<Mutex<HashSet<String>>>;
+
+// detect unclean shutdowns and check the chunks written before, see pbs_datastore::write_epoch
+fn start_write_epochs() -> WriteEpochStores {
+    let stores = WriteEpochStores::default();
+    begin_write_epochs(&stores);
+    stores
+}
+
+// start the write epoch on all datastores which are not tracked yet, so that datastores which are
+// added, mounted or leave maintenance mode while the proxy runs are covered as well
+fn begin_write_epochs(stores: &WriteEpochStores) {
+    let config = match pbs_config::datastore::config() {
+        Err(err) => {
+            log::error!("unable to read datastore config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+
+    for store in config.sections.keys() {
+        if stores.lock().unwrap().contains(store) {
+            continue;
+        }
+
+        // fails for datastores in maintenance mode or not mounted, nothing to do there
+        let datastore = match DataStore::lookup_datastore(store, Some(Operation::Write)) {
+            Ok(datastore) => datastore,
+            Err(_) => continue,
+        };
+
+        let unclean = match datastore.begin_write_epoch() {
+            Ok(unclean) => unclean,
+            Err(err) => {
+                log::error!("unable to start write epoch on '{store}' - {err}");
+                continue;
+            }
+        };
+        stores.lock().unwrap().insert(store.clone());
+
+        let unclean = match unclean {
+            Some(unclean) => unclean,
+            None => continue,
+        };
+
+        if let Err(err) = WorkerTask::new_thread(
+            "check-chunks",
+            Some(store.clone()),
+            Authid::root_auth_id().to_string(),
+            false,
+            move |worker| {
+                task_warn!(
+                    worker,
+                    "unclean shutdown detected during write epoch {}, checking chunks written since {}",
+                    unclean.epoch,
+                    proxmox_time::epoch_to_rfc3339_utc(unclean.since)?,
+                );
+                let report = datastore.check_recent_chunks(unclean.since, &*worker)?;
+                task_log!(worker, "checked {} chunks", report.checked);
+
+                if !report.corrupt.is_empty() {
+                    bail!(
+                        "found {} corrupt chunks - verify the datastore to find affected snapshots",
+                        report.corrupt.len(),
+                    );
+                }
+
+                Ok(())
+            },
+        ) {
+            log::error!("unable to start chunk check on '{store}' - {err}");
+        }
+    }
+}
+
+fn start_write_epoch_sync(stores: WriteEpochStores) {
+    let abort_future = proxmox_rest_server::shutdown_future();
+    let future = Box::pin(run_write_epoch_sync(stores));
+    let task = futures::future::select(future, abort_future);
+    tokio::spawn(task.map(|_| ()));
+}
+
+async fn run_write_epoch_sync(stores: WriteEpochStores) {
+    loop {
+        tokio::time::sleep(WRITE_EPOCH_SYNC_INTERVAL).await;
+
+        proxmox_async::runtime::block_in_place(|| begin_write_epochs(&stores));
+
+        let list: Vec<String> = stores.lock().unwrap().iter().cloned().collect();
+        for store in list {
+            let datastore = match DataStore::lookup_datastore(&store, Some(Operation::Lookup)) {
+                Ok(datastore) => datastore,
+                Err(_) => continue,
+            };
+            let result = proxmox_async::runtime::block_in_place(|| datastore.sync_write_epoch());
+            if let Err(err) = result {
+                log::error!("unable to sync write epoch on '{store}' - {err}");
+            }
+        }
+    }
+}
+
+fn end_write_epochs(stores: &WriteEpochStores) {
+    let stores: Vec<String> = stores.lock().unwrap().iter().cloned().collect();
+    for store in stores {
+        let datastore = match DataStore::lookup_datastore(&store, Some(Operation::Lookup)) {
+            Ok(datastore) => datastore,
+            Err(_) => continue,
+        };
+        if let Err(err) = datastore.end_write_epoch() {
+            log::error!("unable to end write epoch on '{store}' - {err}");
+        }
+    }
+}
+
+// clean up after backups interrupted by a crash, see DataStore::recover_partial_snapshots
+fn start_partial_snapshot_recovery() {
+    let config = match pbs_config::datastore::config() {
+        Err(err) => {
+            log::error!("unable to read datastore config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+
+    for store in config.sections.keys() {
+        // fails for datastores in maintenance mode or not mounted, nothing to do there
+        let datastore = match DataStore::lookup_datastore(store, Some(Operation::Write)) {
+            Ok(datastore) => datastore,
+            Err(_) => continue,
+        };
+
+        if let Err(err) = WorkerTask::new_thread(
+            "recover-partial",
+            Some(store.clone()),
+            Authid::root_auth_id().to_string(),
+            false,
+            move |worker| {
+                task_log!(worker, "checking for partial snapshots");
+                let report = datastore.recover_partial_snapshots(&*worker)?;
+
+                if report.removed_snapshots.is_empty() && report.replayed_journals.is_empty() {
+                    task_log!(worker, "nothing to do");
+                    return Ok(());
+                }
+
+                task_warn!(
+                    worker,
+                    "removed {} partial snapshots, replayed {} interrupted index commits",
+                    report.removed_snapshots.len(),
+                    report.replayed_journals.len(),
+                );
+                if let Err(err) = server::notifications::send_partial_snapshot_notification(
+                    datastore.name(),
+                    &report,
+                ) {
+                    task_warn!(worker, "unable to send notification - {err}");
+                }
+
+                Ok(())
+            },
+        ) {
+            log::error!("unable to start partial snapshot recovery on '{store}' - {err}");
+        }
+    }
+}
+
 fn start_traffic_control_updater() {
     let abort_future = proxmox_rest_server::shutdown_future();
     let future = Box::pin(run_traffic_control_updater());
@@ -426,6 +629,13 @@ fn start_traffic_control_updater() {
     tokio::spawn(task.map(|_| ()));
 }
 
+fn start_snmp_subagent() {
+    let abort_future = proxmox_rest_server::shutdown_future();
+    let future = Box::pin(server::snmp::run_snmp_subagent());
+    let task = futures::future::select(future, abort_future);
+    tokio::spawn(task.map(|_| ()));
+}
+
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 fn next_minute() -> Instant {
@@ -459,12 +669,16 @@ async fn run_task_scheduler() {
 }
 
 async fn schedule_tasks() -> Result<(), Error> {
+    reset_job_start_limit();
+
     schedule_datastore_garbage_collection().await;
     schedule_datastore_prune_jobs().await;
     schedule_datastore_sync_jobs().await;
     schedule_datastore_verify_jobs().await;
     schedule_tape_backup_jobs().await;
     schedule_task_log_rotate().await;
+    schedule_backup_expectation_check().await;
+    schedule_ephemeral_expiry().await;
 
     Ok(())
 }
@@ -492,13 +706,15 @@ async fn schedule_datastore_garbage_collection() {
             None => continue,
         };
 
-        let event: CalendarEvent = match event_str.parse() {
-            Ok(event) => event,
-            Err(err) => {
-                eprintln!("unable to parse schedule '{event_str}' - {err}");
-                continue;
-            }
-        };
+        let worker_type = "garbage_collection";
+
+        if !check_schedule(worker_type, &event_str, &store, store_config.gc_splay) {
+            continue;
+        }
+
+        if datastore_offline(&store, worker_type) {
+            continue;
+        }
 
         {
             // limit datastore scope due to Op::Lookup
@@ -515,36 +731,15 @@ async fn schedule_datastore_garbage_collection() {
             }
         }
 
-        let worker_type = "garbage_collection";
-
-        let last = match jobstate::last_run_time(worker_type, &store) {
-            Ok(time) => time,
-            Err(err) => {
-                eprintln!("could not get last run time of {worker_type} {store}: {err}");
-                continue;
-            }
-        };
-
-        let next = match event.compute_next_event(last) {
-            Ok(Some(next)) => next,
-            Ok(None) => continue,
-            Err(err) => {
-                eprintln!("compute_next_event for '{event_str}' failed - {err}");
-                continue;
-            }
-        };
-
-        let now = proxmox_time::epoch_i64();
-
-        if next > now {
-            continue;
-        }
-
         let job = match Job::new(worker_type, &store) {
             Ok(job) => job,
             Err(_) => continue, // could not get lock
         };
 
+        if !job_start_allowed() {
+            continue;
+        }
+
         let datastore = match DataStore::lookup_datastore(&store, Some(Operation::Write)) {
             Ok(datastore) => datastore,
             Err(err) => {
@@ -594,11 +789,17 @@ async fn schedule_datastore_prune_jobs() {
 
         let worker_type = "prunejob";
         let auth_id = Authid::root_auth_id().clone();
-        if check_schedule(worker_type, &job_config.schedule, &job_id) {
+        if check_schedule(worker_type, &job_config.schedule, &job_id, job_config.splay) {
+            if datastore_offline(&job_config.store, worker_type) {
+                continue;
+            }
             let job = match Job::new(worker_type, &job_id) {
                 Ok(job) => job,
                 Err(_) => continue, // could not get lock
             };
+            if !job_start_allowed() {
+                continue;
+            }
             if let Err(err) = do_prune_job(
                 job,
                 job_config.options,
@@ -636,11 +837,20 @@ async fn schedule_datastore_sync_jobs() {
         };
 
         let worker_type = "syncjob";
-        if check_schedule(worker_type, &event_str, &job_id) {
+        if check_schedule(worker_type, &event_str, &job_id, job_config.splay) {
+            if datastore_offline(&job_config.store, worker_type)
+                || (job_config.remote.is_none()
+                    && datastore_offline(&job_config.remote_store, worker_type))
+            {
+                continue;
+            }
             let job = match Job::new(worker_type, &job_id) {
                 Ok(job) => job,
                 Err(_) => continue, // could not get lock
             };
+            if !job_start_allowed() {
+                continue;
+            }
 
             let auth_id = Authid::root_auth_id().clone();
             if let Err(err) = do_sync_job(job, job_config, &auth_id, Some(event_str), false) {
@@ -673,11 +883,17 @@ async fn schedule_datastore_verify_jobs() {
 
         let worker_type = "verificationjob";
         let auth_id = Authid::root_auth_id().clone();
-        if check_schedule(worker_type, &event_str, &job_id) {
+        if check_schedule(worker_type, &event_str, &job_id, job_config.splay) {
+            if datastore_offline(&job_config.store, worker_type) {
+                continue;
+            }
             let job = match Job::new(worker_type, &job_id) {
                 Ok(job) => job,
                 Err(_) => continue, // could not get lock
             };
+            if !job_start_allowed() {
+                continue;
+            }
             if let Err(err) = do_verification_job(job, job_config, &auth_id, Some(event_str), false)
             {
                 eprintln!("unable to start datastore verification job {job_id} - {err}");
@@ -709,11 +925,17 @@ async fn schedule_tape_backup_jobs() {
 
         let worker_type = "tape-backup-job";
         let auth_id = Authid::root_auth_id().clone();
-        if check_schedule(worker_type, &event_str, &job_id) {
+        if check_schedule(worker_type, &event_str, &job_id, job_config.splay) {
+            if datastore_offline(&job_config.setup.store, worker_type) {
+                continue;
+            }
             let job = match Job::new(worker_type, &job_id) {
                 Ok(job) => job,
                 Err(_) => continue, // could not get lock
             };
+            if !job_start_allowed() {
+                continue;
+            }
             if let Err(err) =
                 do_tape_backup_job(job, job_config.setup, &auth_id, Some(event_str), false)
             {
@@ -730,7 +952,7 @@ async fn schedule_task_log_rotate() {
     // schedule daily at 00:00 like normal logrotate
     let schedule = "00:00";
 
-    if !check_schedule(worker_type, schedule, job_id) {
+    if !check_schedule(worker_type, schedule, job_id, None) {
         // if we never ran the rotation, schedule instantly
         match jobstate::JobState::load(worker_type, job_id) {
             Ok(jobstate::JobState::Created { .. }) => {}
@@ -836,6 +1058,75 @@ async fn schedule_task_log_rotate() {
     }
 }
 
+async fn schedule_backup_expectation_check() {
+    let worker_type = "expectation-check";
+    let job_id = "backup-expectations";
+
+    // staleness is measured in hours or days, so checking hourly is precise enough
+    let schedule = "hourly";
+
+    match pbs_config::expectation::config() {
+        Ok((config, _digest)) if config.sections.is_empty() => return,
+        Ok(_) => {}
+        Err(err) => {
+            eprintln!("unable to read backup expectation config - {err}");
+            return;
+        }
+    }
+
+    if !check_schedule(worker_type, schedule, job_id, None) {
+        return;
+    }
+
+    let job = match Job::new(worker_type, job_id) {
+        Ok(job) => job,
+        Err(_) => return, // could not get lock
+    };
+
+    let auth_id = Authid::root_auth_id().clone();
+    if let Err(err) = do_expectation_check(job, &auth_id, Some(schedule.to_string()), false) {
+        eprintln!("unable to start backup expectation check - {err}");
+    }
+}
+
+async fn schedule_ephemeral_expiry() {
+    let worker_type = "ephemeral-expiry";
+    let job_id = "ephemeral-datastores";
+
+    // TTLs are usually days, so expiring hourly is precise enough
+    let schedule = "hourly";
+
+    match pbs_config::datastore::config() {
+        Ok((config, _digest)) => {
+            let any_ephemeral = config
+                .sections
+                .values()
+                .any(|(_, store)| store.get("ephemeral").is_some());
+            if !any_ephemeral {
+                return;
+            }
+        }
+        Err(err) => {
+            eprintln!("unable to read datastore config - {err}");
+            return;
+        }
+    }
+
+    if !check_schedule(worker_type, schedule, job_id, None) {
+        return;
+    }
+
+    let job = match Job::new(worker_type, job_id) {
+        Ok(job) => job,
+        Err(_) => return, // could not get lock
+    };
+
+    let auth_id = Authid::root_auth_id().clone();
+    if let Err(err) = do_ephemeral_expiry(job, &auth_id, Some(schedule.to_string()), false) {
+        eprintln!("unable to start ephemeral datastore expiry - {err}");
+    }
+}
+
 async fn command_reopen_access_logfiles() -> Result<(), Error> {
     // only care about the most recent daemon instance for each, proxy & api, as other older ones
     // should not respond to new requests anyway, but only finish their current one and then exit.
@@ -1221,7 +1512,7 @@ fn rrd_update_disk_stat(disk: &DiskStat, rrd_prefix: &str) {
     }
 }
 
-fn check_schedule(worker_type: &str, event_str: &str, id: &str) -> bool {
+fn check_schedule(worker_type: &str, event_str: &str, id: &str, splay: Option<u64>) -> bool {
     let event: CalendarEvent = match event_str.parse() {
         Ok(event) => event,
         Err(err) => {
@@ -1247,10 +1538,76 @@ fn check_schedule(worker_type: &str, event_str: &str, id: &str) -> bool {
         }
     };
 
+    let next = next + jobstate::splay_delay(worker_type, id, next, splay);
+
     let now = proxmox_time::epoch_i64();
     next <= now
 }
 
+/// Remaining number of scheduled jobs which may be started in the current scheduler round, no
+/// limit if `None`.
+static JOB_STARTS_REMAINING: Mutex<Option<usize>> = Mutex::new(None);
+
+fn reset_job_start_limit() {
+    let limit = match proxmox_backup::config::node::config() {
+        Ok((config, _digest)) => config.job_start_limit.filter(|limit| *limit > 0),
+        Err(err) => {
+            eprintln!("unable to read node config - {err}");
+            None
+        }
+    };
+    *JOB_STARTS_REMAINING.lock().unwrap() = limit;
+}
+
+/// Check if another scheduled job may be started in the current scheduler round.
+///
+/// Due jobs exceeding the `job-start-limit` node option are not started, so they stay due and
+/// run in one of the next rounds. Call this only after the job lock was acquired, so jobs which
+/// are already running do not use up the limit.
+fn job_start_allowed() -> bool {
+    match JOB_STARTS_REMAINING.lock().unwrap().as_mut() {
+        None => true,
+        Some(0) => false,
+        Some(remaining) => {
+            *remaining -= 1;
+            true
+        }
+    }
+}
+
+/// Datastores on removable devices, for which the operator was already asked to attach them.
+static OFFLINE_DATASTORES_NOTIFIED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Check if a datastore is located on a removable device, which is currently not mounted.
+///
+/// Due jobs on such a datastore are not started, so they stay due and run once the device is
+/// mounted again. The operator is notified once to attach the correct device.
+fn datastore_offline(store: &str, job_type: &str) -> bool {
+    let config: DataStoreConfig = match pbs_config::datastore::config()
+        .and_then(|(config, _digest)| config.lookup("datastore", store))
+    {
+        Ok(config) => config,
+        Err(_) => return false, // let the job itself fail
+    };
+
+    let mut notified = OFFLINE_DATASTORES_NOTIFIED.lock().unwrap();
+
+    if pbs_datastore::is_datastore_available(&config) {
+        notified.retain(|name| name != store);
+        return false;
+    }
+
+    if !notified.iter().any(|name| name == store) {
+        log::info!("datastore '{store}' is not mounted, delaying due {job_type} jobs");
+        notified.push(store.to_string());
+        if let Err(err) = server::send_datastore_offline_notification(&config, job_type) {
+            log::error!("could not send datastore offline notification for '{store}' - {err}");
+        }
+    }
+
+    true
+}
+
 fn gather_disk_stats(disk_manager: Arc<DiskManage>, path: &Path, name: &str) -> DiskStat {
     let usage = match proxmox_sys::fs::fs_info(path) {
         Ok(status) => Some(status),
@@ -5,7 +5,9 @@ use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_subscription::SubscriptionStatus;
 use proxmox_sys::fs::CreateOptions;
 
-use proxmox_backup::api2;
+use pbs_api_types::DataStoreConfig;
+
+use proxmox_backup::{api2, server};
 
 async fn wait_for_local_worker(upid_str: &str) -> Result<(), Error> {
     let upid: pbs_api_types::UPID = upid_str.parse()?;
@@ -59,6 +61,10 @@ async fn do_update(rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
         log::error!("error checking certificates: {err}");
     }
 
+    if let Err(err) = remove_empty_ephemeral_datastores(rpcenv).await {
+        log::error!("error removing empty ephemeral datastores: {err}");
+    }
+
     // TODO: cleanup tasks like in PVE?
 
     Ok(())
@@ -87,6 +93,75 @@ async fn check_acme_certificates(rpcenv: &mut dyn RpcEnvironment) -> Result<(),
     Ok(())
 }
 
+/// Remove ephemeral datastores with `remove-empty` set, which stayed empty for their TTL.
+///
+/// The proxy only expires snapshots, as removing a datastore requires root privileges.
+async fn remove_empty_ephemeral_datastores(rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let stores: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+
+    let empty_since = server::ephemeral_empty_since();
+    let now = proxmox_time::epoch_i64();
+
+    for store in stores {
+        let name = &store.name;
+        let ttl = match store.get_ephemeral() {
+            Ok(Some(ephemeral)) if ephemeral.remove_empty.unwrap_or(false) => {
+                match ephemeral.ttl_seconds() {
+                    Ok(ttl) => ttl,
+                    Err(err) => {
+                        log::error!("datastore '{name}': invalid ephemeral TTL - {err}");
+                        continue;
+                    }
+                }
+            }
+            Ok(_) => continue,
+            Err(err) => {
+                log::error!("datastore '{name}': invalid ephemeral settings - {err}");
+                continue;
+            }
+        };
+        let since = match empty_since.get(name) {
+            Some(since) => *since,
+            None => continue,
+        };
+        if now - since <= ttl {
+            continue;
+        }
+
+        // the state is only refreshed hourly, check again with new backups being blocked
+        match server::prepare_empty_datastore_removal(name).await {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(err) => {
+                log::error!("error checking datastore '{name}' before removal - {err}");
+                continue;
+            }
+        }
+
+        log::info!("removing ephemeral datastore '{name}', empty for more than {ttl} seconds");
+        let param = json!({
+            "name": name,
+            "destroy-data": true,
+        });
+        let method = &api2::config::datastore::API_METHOD_DELETE_DATASTORE;
+        match method.handler {
+            ApiHandler::Async(handler) => match (handler)(param, method, rpcenv).await {
+                Err(err) => {
+                    log::error!("error removing datastore '{name}' - {err}");
+                    if let Err(err) = server::cancel_empty_datastore_removal(name).await {
+                        log::error!("could not clear maintenance mode of '{name}' - {err}");
+                    }
+                }
+                Ok(upid) => wait_for_local_worker(upid.as_str().unwrap()).await?,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(())
+}
+
 async fn run(rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
     let backup_user = pbs_config::backup_user()?;
     let file_opts = CreateOptions::new()
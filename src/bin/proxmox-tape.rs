@@ -11,6 +11,7 @@ use proxmox_schema::api;
 use proxmox_section_config::SectionConfigData;
 use proxmox_time::strftime_local;
 
+use pbs_client::tools::completions::completions_cmd_def;
 use pbs_client::view_task_result;
 use pbs_tools::format::{render_bytes_human_readable, render_epoch};
 
@@ -841,6 +842,11 @@ async fn clean_drive(mut param: Value) -> Result<(), Error> {
                 type: bool,
                 optional: true,
             },
+            "since-last-run": {
+                description: "Only backup snapshots which are not on any media of the pool yet.",
+                type: bool,
+                optional: true,
+            },
             "notify-user": {
                 optional: true,
                 type: Userid,
@@ -1085,6 +1091,7 @@ fn main() {
         .insert("media", media_commands())
         .insert("key", encryption_key_commands())
         .insert("backup-job", backup_job_commands())
+        .insert("completions", completions_cmd_def())
         .insert(
             "load-media",
             CliCommand::new(&API_METHOD_LOAD_MEDIA)
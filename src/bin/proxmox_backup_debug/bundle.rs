@@ -0,0 +1,541 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_router::cli::{
+    complete_file_name, format_and_print_result, get_output_format, CliCommand, CliCommandMap,
+    CommandLineInterface, OUTPUT_FORMAT,
+};
+use proxmox_schema::api;
+use proxmox_sys::fs::{replace_file, CreateOptions};
+
+use pbs_api_types::{
+    parse_ns_and_snapshot, print_ns_and_snapshot, BackupNamespace, Operation, DATASTORE_SCHEMA,
+};
+use pbs_client::tools::key_source::load_and_decrypt_key;
+use pbs_datastore::dynamic_index::DynamicIndexReader;
+use pbs_datastore::fixed_index::FixedIndexReader;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest, MANIFEST_BLOB_NAME};
+use pbs_datastore::{DataBlob, DataStore, SnapshotReader};
+use pbs_tools::crypt_config::CryptConfig;
+
+const BUNDLE_FORMAT: &str = "proxmox-backup-bundle-1";
+const BUNDLE_INFO_NAME: &str = "bundle.json";
+const BUNDLE_README_NAME: &str = "README";
+const BUNDLE_CHUNK_DIR: &str = ".chunks";
+
+const BUNDLE_README: &str = "\
+Proxmox Backup Server archive bundle
+====================================
+
+This directory contains backup snapshots exported from a Proxmox Backup
+Server datastore, together with all chunks they reference. It uses the
+datastore directory layout:
+
+  bundle.json                     list of contained snapshots
+  .chunks/<prefix>/<digest>       chunks, <prefix> are the first 4 hex digits
+  [ns/<name>/]<type>/<id>/<time>/ snapshot files (index.json.blob, *.fidx,
+                                  *.didx, *.blob)
+
+To restore a snapshot, use
+
+  proxmox-backup-debug bundle extract <bundle> <snapshot> <target>
+
+or, for a single archive,
+
+  proxmox-backup-debug recover index <index file> <bundle>/.chunks
+
+Encrypted backups need the original encryption key (--keyfile).
+
+Chunk, blob and index formats are documented in the Proxmox Backup Server
+documentation (\"Technical Overview\" and \"File Formats\").
+";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Content description of an archive bundle (bundle.json)
+struct BundleInfo {
+    format: String,
+    /// Export time (epoch)
+    ctime: i64,
+    /// Name of the exported datastore
+    store: String,
+    /// Contained snapshots ('[ns/<name>/]<type>/<id>/<time>')
+    snapshots: Vec<String>,
+}
+
+fn load_bundle_info(bundle: &Path) -> Result<BundleInfo, Error> {
+    let path = bundle.join(BUNDLE_INFO_NAME);
+    let data =
+        std::fs::read(&path).map_err(|err| format_err!("unable to read {path:?} - {err}"))?;
+    let info: BundleInfo = serde_json::from_slice(&data)?;
+    if info.format != BUNDLE_FORMAT {
+        bail!("unsupported bundle format '{}'", info.format);
+    }
+    Ok(info)
+}
+
+/// Make sure a file name from a (possibly foreign) manifest stays inside the target directory.
+fn check_file_name(filename: &str) -> Result<(), Error> {
+    if filename.is_empty() || filename == "." || filename == ".." || filename.contains('/') {
+        bail!("invalid file name '{filename}' in manifest");
+    }
+    Ok(())
+}
+
+/// Copy a chunk via a temporary file, so that the bundle never contains partial chunks.
+fn copy_chunk(source_path: &Path, target_path: &Path) -> Result<u64, Error> {
+    let mut tmp_path = target_path.to_owned();
+    tmp_path.set_extension("tmp");
+
+    let mut source = File::open(source_path)
+        .map_err(|err| format_err!("unable to open chunk {source_path:?} - {err}"))?;
+    let mut tmp = File::create(&tmp_path)
+        .map_err(|err| format_err!("unable to create {tmp_path:?} - {err}"))?;
+    let result = std::io::copy(&mut source, &mut tmp)
+        .and_then(|bytes| tmp.sync_all().map(|()| bytes))
+        .and_then(|bytes| std::fs::rename(&tmp_path, target_path).map(|()| bytes));
+
+    match result {
+        Ok(bytes) => Ok(bytes),
+        Err(err) => {
+            let _ = std::fs::remove_file(&tmp_path);
+            bail!("unable to copy chunk {source_path:?} - {err}");
+        }
+    }
+}
+
+fn bundle_chunk_path(bundle: &Path, digest: &[u8; 32]) -> PathBuf {
+    let digest_str = hex::encode(digest);
+    bundle
+        .join(BUNDLE_CHUNK_DIR)
+        .join(&digest_str[0..4])
+        .join(digest_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            target: {
+                description: "Bundle directory (created if it does not exist).",
+                type: String,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            snapshots: {
+                description: "Snapshots to export ('[ns/<name>/]<type>/<id>/<time>'). \
+                    Defaults to all snapshots in the namespace (recursive).",
+                type: Array,
+                optional: true,
+                items: {
+                    description: "Snapshot path.",
+                    type: String,
+                },
+            },
+        }
+    }
+)]
+/// Export snapshots (manifests, indexes and referenced chunks) to a
+/// stand-alone archive bundle.
+///
+/// Exporting to an existing bundle adds the snapshots, already contained
+/// chunks are not copied again.
+fn export(
+    store: String,
+    target: String,
+    ns: Option<BackupNamespace>,
+    snapshots: Option<Vec<String>>,
+) -> Result<(), Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    let bundle = PathBuf::from(target);
+
+    let mut info = if bundle.join(BUNDLE_INFO_NAME).exists() {
+        let info = load_bundle_info(&bundle)?;
+        if info.store != store {
+            bail!("bundle contains snapshots from datastore '{}'", info.store);
+        }
+        info
+    } else {
+        std::fs::create_dir_all(bundle.join(BUNDLE_CHUNK_DIR))?;
+        BundleInfo {
+            format: BUNDLE_FORMAT.to_string(),
+            ctime: proxmox_time::epoch_i64(),
+            store: store.clone(),
+            snapshots: Vec::new(),
+        }
+    };
+
+    let mut snapshot_list = Vec::new();
+    match snapshots {
+        Some(snapshots) => {
+            for snapshot in snapshots {
+                let (ns, dir) = parse_ns_and_snapshot(&snapshot)?;
+                snapshot_list.push(datastore.backup_dir(ns, dir)?);
+            }
+        }
+        None => {
+            for ns in datastore.recursive_iter_backup_ns_ok(ns.unwrap_or_default(), None)? {
+                for group in datastore.list_backup_groups(ns)? {
+                    for snapshot in group.list_backups()? {
+                        if snapshot.is_finished() {
+                            snapshot_list.push(snapshot.backup_dir);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut copied_chunks = 0;
+    let mut copied_bytes = 0;
+
+    for snapshot in snapshot_list {
+        let name = print_ns_and_snapshot(snapshot.backup_ns(), snapshot.as_ref());
+        if info.snapshots.contains(&name) {
+            log::info!("skip snapshot {name} (already in bundle)");
+            continue;
+        }
+
+        let reader = SnapshotReader::new(
+            datastore.clone(),
+            snapshot.backup_ns().clone(),
+            snapshot.as_ref().clone(),
+        )?;
+
+        let chunks = reader.chunk_iterator(|digest| bundle_chunk_path(&bundle, digest).exists())?;
+        for digest in chunks {
+            let digest = digest?;
+            let target_path = bundle_chunk_path(&bundle, &digest);
+            if target_path.exists() {
+                continue; // referenced more than once
+            }
+            let (source_path, _digest_str) = datastore.chunk_path(&digest);
+
+            std::fs::create_dir_all(target_path.parent().unwrap())?;
+            copied_bytes += copy_chunk(&source_path, &target_path)?;
+            copied_chunks += 1;
+        }
+
+        // copy snapshot files last, so an interrupted export never contains
+        // snapshots with missing chunks
+        let snapshot_path = bundle.join(snapshot.relative_path());
+        std::fs::create_dir_all(&snapshot_path)?;
+        for filename in reader.file_list() {
+            let mut file = reader.open_file(filename)?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            replace_file(
+                snapshot_path.join(filename),
+                &data,
+                CreateOptions::new(),
+                true,
+            )?;
+        }
+
+        log::info!("exported snapshot {name}");
+        info.snapshots.push(name);
+
+        // update after each snapshot, so that the bundle stays usable
+        let raw = serde_json::to_vec_pretty(&info)?;
+        replace_file(
+            bundle.join(BUNDLE_INFO_NAME),
+            &raw,
+            CreateOptions::new(),
+            true,
+        )?;
+    }
+
+    replace_file(
+        bundle.join(BUNDLE_README_NAME),
+        BUNDLE_README.as_bytes(),
+        CreateOptions::new(),
+        false,
+    )?;
+
+    log::info!(
+        "bundle contains {} snapshots, copied {copied_chunks} chunks ({copied_bytes} bytes)",
+        info.snapshots.len()
+    );
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            bundle: {
+                description: "Bundle directory.",
+                type: String,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List the snapshots contained in an archive bundle.
+fn list(bundle: String, param: serde_json::Value) -> Result<(), Error> {
+    let info = load_bundle_info(Path::new(&bundle))?;
+    let output_format = get_output_format(&param);
+
+    if output_format == "text" {
+        println!("datastore: {}", info.store);
+        for snapshot in info.snapshots {
+            println!("{snapshot}");
+        }
+    } else {
+        format_and_print_result(&serde_json::to_value(info)?, &output_format);
+    }
+
+    Ok(())
+}
+
+fn restore_index(
+    bundle: &Path,
+    index: &dyn IndexFile,
+    crypt_config: Option<&CryptConfig>,
+    output_path: &Path,
+) -> Result<(), Error> {
+    use std::io::Write;
+
+    let mut output = File::create(output_path)
+        .map_err(|err| format_err!("unable to create {output_path:?} - {err}"))?;
+
+    for pos in 0..index.index_count() {
+        let digest = index.index_digest(pos).unwrap();
+        let chunk_path = bundle_chunk_path(bundle, digest);
+        let data = std::fs::read(&chunk_path)
+            .map_err(|err| format_err!("unable to read chunk {chunk_path:?} - {err}"))?;
+        let blob = DataBlob::from_raw(data)?;
+        blob.verify_crc()?;
+        let data = blob
+            .decode(crypt_config, Some(digest))
+            .map_err(|err| format_err!("unable to decode chunk {chunk_path:?} - {err}"))?;
+        output.write_all(&data)?;
+    }
+
+    output
+        .sync_all()
+        .map_err(|err| format_err!("unable to sync {output_path:?} - {err}"))?;
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            bundle: {
+                description: "Bundle directory.",
+                type: String,
+            },
+            snapshot: {
+                description: "Snapshot path ('[ns/<name>/]<type>/<id>/<time>').",
+                type: String,
+            },
+            target: {
+                description: "Target directory.",
+                type: String,
+            },
+            keyfile: {
+                description: "Path to a keyfile, needed for encrypted backups.",
+                type: String,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Extract all archives of a snapshot from an archive bundle.
+///
+/// Index archives are restored to their contents (e.g. 'drive-scsi0.img',
+/// 'root.pxar'), blobs are stored decoded. No running Proxmox Backup Server
+/// is needed.
+fn extract(
+    bundle: String,
+    snapshot: String,
+    target: String,
+    keyfile: Option<String>,
+) -> Result<(), Error> {
+    let bundle = Path::new(&bundle);
+    let target = Path::new(&target);
+
+    let info = load_bundle_info(bundle)?;
+    if !info.snapshots.contains(&snapshot) {
+        bail!("snapshot '{snapshot}' is not contained in bundle");
+    }
+
+    let (ns, dir) = parse_ns_and_snapshot(&snapshot)?;
+    let mut snapshot_path = bundle.to_owned();
+    for component in ns.components() {
+        snapshot_path.push("ns");
+        snapshot_path.push(component);
+    }
+    snapshot_path.push(dir.to_string());
+
+    let crypt_config = match keyfile {
+        Some(keyfile) => {
+            let (key, _created, _fingerprint) = load_and_decrypt_key(Path::new(&keyfile))?;
+            Some(CryptConfig::new(key)?)
+        }
+        None => None,
+    };
+
+    let mut manifest_file = File::open(snapshot_path.join(MANIFEST_BLOB_NAME))?;
+    let manifest = BackupManifest::try_from(DataBlob::load_from_reader(&mut manifest_file)?)?;
+
+    std::fs::create_dir_all(target)?;
+
+    let mut seen = HashSet::new();
+    for item in manifest.files() {
+        let filename = &item.filename;
+        check_file_name(filename)?;
+        if !seen.insert(filename.clone()) {
+            continue;
+        }
+        let source = snapshot_path.join(filename);
+
+        match archive_type(filename)? {
+            ArchiveType::FixedIndex => {
+                let index = FixedIndexReader::open(&source)?;
+                let output = target.join(filename.strip_suffix(".fidx").unwrap());
+                restore_index(bundle, &index, crypt_config.as_ref(), &output)?;
+            }
+            ArchiveType::DynamicIndex => {
+                let index = DynamicIndexReader::open(&source)?;
+                let output = target.join(filename.strip_suffix(".didx").unwrap());
+                restore_index(bundle, &index, crypt_config.as_ref(), &output)?;
+            }
+            ArchiveType::Blob => {
+                let mut file = File::open(&source)?;
+                let blob = DataBlob::load_from_reader(&mut file)?;
+                let data = blob.decode(crypt_config.as_ref(), None)?;
+                let output = target.join(filename.strip_suffix(".blob").unwrap());
+                replace_file(output, &data, CreateOptions::new(), true)?;
+            }
+        }
+
+        log::info!("extracted {filename}");
+    }
+
+    Ok(())
+}
+
+pub fn bundle_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert(
+            "export",
+            CliCommand::new(&API_METHOD_EXPORT)
+                .arg_param(&["store", "target"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("target", complete_file_name),
+        )
+        .insert(
+            "list",
+            CliCommand::new(&API_METHOD_LIST)
+                .arg_param(&["bundle"])
+                .completion_cb("bundle", complete_file_name),
+        )
+        .insert(
+            "extract",
+            CliCommand::new(&API_METHOD_EXTRACT)
+                .arg_param(&["bundle", "snapshot", "target"])
+                .completion_cb("bundle", complete_file_name)
+                .completion_cb("target", complete_file_name)
+                .completion_cb("keyfile", complete_file_name),
+        );
+    cmd_def.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test directory below ./target/testout, removed again when dropped, also if the test fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let mut path: PathBuf = String::from("./target/testout").into();
+            path.push(std::module_path!());
+            path.push(name);
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_check_file_name() {
+        assert!(check_file_name("index.json.blob").is_ok());
+        assert!(check_file_name("drive-scsi0.img.fidx").is_ok());
+        assert!(check_file_name("..data.blob").is_ok());
+
+        assert!(check_file_name("").is_err());
+        assert!(check_file_name(".").is_err());
+        assert!(check_file_name("..").is_err());
+        assert!(check_file_name("../../etc/passwd.blob").is_err());
+        assert!(check_file_name("/etc/passwd.blob").is_err());
+        assert!(check_file_name("sub/root.pxar.didx").is_err());
+    }
+
+    #[test]
+    fn test_copy_chunk() -> Result<(), Error> {
+        let test_dir = TestDir::new("copy_chunk");
+        let dir = &test_dir.0;
+
+        let digest = [0xab; 32];
+        let source = dir.join("source");
+        std::fs::write(&source, b"chunk data")?;
+        let target = bundle_chunk_path(dir, &digest);
+        assert_eq!(target, dir.join(".chunks/abab").join(hex::encode(digest)));
+
+        std::fs::create_dir_all(target.parent().unwrap())?;
+        assert_eq!(copy_chunk(&source, &target)?, 10);
+        assert_eq!(std::fs::read(&target)?, b"chunk data");
+
+        // no temporary file is left behind, also not on errors
+        let missing = dir.join("missing");
+        assert!(copy_chunk(&missing, &bundle_chunk_path(dir, &[0xcd; 32])).is_err());
+        let entries: Vec<_> = std::fs::read_dir(target.parent().unwrap())?.collect();
+        assert_eq!(entries.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_bundle_info() -> Result<(), Error> {
+        let test_dir = TestDir::new("load_bundle_info");
+        let dir = &test_dir.0;
+
+        let mut info = BundleInfo {
+            format: BUNDLE_FORMAT.to_string(),
+            ctime: 0,
+            store: "store1".to_string(),
+            snapshots: vec!["vm/100/2023-01-01T00:00:00Z".to_string()],
+        };
+        std::fs::write(dir.join(BUNDLE_INFO_NAME), serde_json::to_vec(&info)?)?;
+        assert_eq!(load_bundle_info(dir)?.snapshots, info.snapshots);
+
+        info.format = "proxmox-backup-bundle-2".to_string();
+        std::fs::write(dir.join(BUNDLE_INFO_NAME), serde_json::to_vec(&info)?)?;
+        assert!(load_bundle_info(dir).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use proxmox_router::cli::{
+    format_and_print_result, get_output_format, CliCommand, CliCommandMap, CommandLineInterface,
+    OUTPUT_FORMAT,
+};
+use proxmox_schema::api;
+
+use pbs_api_types::DATASTORE_SCHEMA;
+
+fn print_list(title: &str, list: &Value) {
+    let list = list.as_array().map(Vec::as_slice).unwrap_or_default();
+    println!("{title}: {}", list.len());
+    for item in list {
+        match item {
+            Value::String(path) => println!("  {path}"),
+            item => {
+                println!(
+                    "  {} {}",
+                    item["digest"].as_str().unwrap_or("-"),
+                    item["indexes"]
+                );
+                if let Some(time) = item["time"].as_i64() {
+                    let time = proxmox_time::epoch_to_rfc3339_utc(time).unwrap_or_default();
+                    println!(
+                        "    removed at {time}, vanished indexes: {}",
+                        item["vanished-indexes"]
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Compare the chunk references tracked by the running proxy with the on-disk
+/// state of a datastore.
+///
+/// Tracking must be enabled by setting PROXMOX_DEBUG_CHUNK_REFS in the
+/// environment of proxmox-backup-proxy.
+async fn report(store: String, param: Value) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+
+    let proxy_pid = proxmox_rest_server::read_pid(pbs_buildcfg::PROXMOX_BACKUP_PROXY_PID_FN)?;
+    let sock = proxmox_rest_server::ctrl_sock_from_pid(proxy_pid);
+    let command = json!({ "command": "chunk-ref-report", "args": store });
+    let report: Value =
+        proxmox_rest_server::send_raw_command(sock, &format!("{command}\n")).await?;
+
+    if output_format != "text" {
+        format_and_print_result(&report, &output_format);
+        return Ok(());
+    }
+
+    println!("datastore: {store}");
+    println!("tracked indexes: {}", report["tracked-indexes"]);
+    println!("tracked chunks: {}", report["tracked-chunks"]);
+    print_list(
+        "referenced chunks removed by GC",
+        &report["gc-removed-referenced"],
+    );
+    print_list("missing chunks", &report["missing-chunks"]);
+    print_list("vanished indexes", &report["vanished-indexes"]);
+    print_list("changed indexes", &report["changed-indexes"]);
+
+    Ok(())
+}
+
+pub fn chunk_refs_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new().insert(
+        "report",
+        CliCommand::new(&API_METHOD_REPORT)
+            .arg_param(&["store"])
+            .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+    );
+    cmd_def.into()
+}
@@ -15,7 +15,7 @@ use proxmox_schema::api;
 
 use pbs_api_types::{BackupNamespace, BackupPart};
 use pbs_client::tools::key_source::{
-    crypto_parameters, format_key_source, get_encryption_key_password, KEYFD_SCHEMA,
+    crypto_parameters, decrypt_key, format_key_source, KEYFD_SCHEMA,
 };
 use pbs_client::tools::{
     complete_archive_name, complete_group_or_snapshot, connect, extract_repository_from_value,
@@ -24,7 +24,6 @@ use pbs_client::tools::{
 use pbs_client::{BackupReader, BackupRepository, RemoteChunkReader};
 use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicIndexReader, LocalDynamicReadAt};
 use pbs_datastore::index::IndexFile;
-use pbs_key_config::decrypt_key;
 use pbs_tools::crypt_config::CryptConfig;
 use pxar::accessor::ReadAt;
 use pxar::EntryKind;
@@ -122,11 +121,10 @@ async fn diff_archive_cmd(
     let crypt_config = match crypto.enc_key {
         None => None,
         Some(key) => {
-            let (key, _created, _fingerprint) = decrypt_key(&key.key, &get_encryption_key_password)
-                .map_err(|err| {
-                    log::error!("{}", format_key_source(&key.source, "encryption"));
-                    err
-                })?;
+            let (key, _created, _fingerprint) = decrypt_key(&key.key).map_err(|err| {
+                log::error!("{}", format_key_source(&key.source, "encryption"));
+                err
+            })?;
             let crypt_config = CryptConfig::new(key)?;
             Some(Arc::new(crypt_config))
         }
@@ -14,16 +14,16 @@ use proxmox_router::cli::{
 };
 use proxmox_schema::api;
 
-use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::tools::key_source::load_and_decrypt_key;
 use pbs_datastore::dynamic_index::DynamicIndexReader;
 use pbs_datastore::file_formats::{
     COMPRESSED_BLOB_MAGIC_1_0, DYNAMIC_SIZED_CHUNK_INDEX_1_0, ENCRYPTED_BLOB_MAGIC_1_0,
-    ENCR_COMPR_BLOB_MAGIC_1_0, FIXED_SIZED_CHUNK_INDEX_1_0, UNCOMPRESSED_BLOB_MAGIC_1_0,
+    ENCR_COMPR_BLOB_MAGIC_1_0, FIXED_SIZED_CHUNK_INDEX_1_0, LZ4_COMPRESSED_BLOB_MAGIC_1_0,
+    UNCOMPRESSED_BLOB_MAGIC_1_0,
 };
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::DataBlob;
-use pbs_key_config::load_and_decrypt_key;
 use pbs_tools::crypt_config::CryptConfig;
 
 /// Decodes a blob and writes its content either to stdout or into a file
@@ -37,8 +37,7 @@ fn decode_blob(
     let crypt_conf;
 
     if blob.is_encrypted() && key_file.is_some() {
-        let (key, _created, _fingerprint) =
-            load_and_decrypt_key(key_file.unwrap(), &get_encryption_key_password)?;
+        let (key, _created, _fingerprint) = load_and_decrypt_key(key_file.unwrap())?;
         crypt_conf = CryptConfig::new(key)?;
         crypt_conf_opt = Some(&crypt_conf);
     }
@@ -259,6 +258,7 @@ fn inspect_file(
     let val = match magic {
         UNCOMPRESSED_BLOB_MAGIC_1_0
         | COMPRESSED_BLOB_MAGIC_1_0
+        | LZ4_COMPRESSED_BLOB_MAGIC_1_0
         | ENCRYPTED_BLOB_MAGIC_1_0
         | ENCR_COMPR_BLOB_MAGIC_1_0 => {
             let data_blob = DataBlob::load_from_reader(&mut file)?;
@@ -6,6 +6,8 @@ use std::{
 };
 
 pub mod api;
+pub mod bundle;
+pub mod chunk_refs;
 pub mod diff;
 pub mod inspect;
 pub mod recover;
@@ -7,13 +7,12 @@ use anyhow::{bail, format_err, Error};
 use proxmox_router::cli::{CliCommand, CliCommandMap, CommandLineInterface};
 use proxmox_schema::api;
 
-use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::tools::key_source::load_and_decrypt_key;
 use pbs_datastore::dynamic_index::DynamicIndexReader;
 use pbs_datastore::file_formats::{DYNAMIC_SIZED_CHUNK_INDEX_1_0, FIXED_SIZED_CHUNK_INDEX_1_0};
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::DataBlob;
-use pbs_key_config::load_and_decrypt_key;
 use pbs_tools::crypt_config::CryptConfig;
 
 #[api(
@@ -89,8 +88,7 @@ fn recover_index(
     };
 
     let crypt_conf_opt = if let Some(key_file_path) = key_file_path {
-        let (key, _created, _fingerprint) =
-            load_and_decrypt_key(key_file_path, &get_encryption_key_password)?;
+        let (key, _created, _fingerprint) = load_and_decrypt_key(key_file_path)?;
         Some(CryptConfig::new(key)?)
     } else {
         None
@@ -0,0 +1,277 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{DataStoreHealthState, DATASTORE_SCHEMA};
+
+use proxmox_backup::api2;
+
+const SECONDS_PER_DAY: i64 = 24 * 3600;
+
+/// Plugin states with their exit codes, see the Nagios plugin guidelines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PluginState {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl PluginState {
+    fn as_str(self) -> &'static str {
+        match self {
+            PluginState::Ok => "OK",
+            PluginState::Warning => "WARNING",
+            PluginState::Critical => "CRITICAL",
+            PluginState::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// Rank used to aggregate the states, which differs from the order of the exit codes: an
+    /// unknown state, like a datastore in maintenance mode, must not hide critical problems.
+    fn severity(self) -> u8 {
+        match self {
+            PluginState::Ok => 0,
+            PluginState::Unknown => 1,
+            PluginState::Warning => 2,
+            PluginState::Critical => 3,
+        }
+    }
+}
+
+impl From<DataStoreHealthState> for PluginState {
+    fn from(state: DataStoreHealthState) -> Self {
+        match state {
+            DataStoreHealthState::Ok => PluginState::Ok,
+            DataStoreHealthState::Maintenance => PluginState::Unknown,
+            DataStoreHealthState::Warning => PluginState::Warning,
+            DataStoreHealthState::Critical => PluginState::Critical,
+        }
+    }
+}
+
+#[derive(Default)]
+struct CheckResult {
+    messages: Vec<(PluginState, String)>,
+    perfdata: Vec<String>,
+}
+
+impl CheckResult {
+    fn add(&mut self, state: PluginState, message: String) {
+        self.messages.push((state, message));
+    }
+
+    fn state(&self) -> PluginState {
+        self.messages
+            .iter()
+            .map(|(state, _)| *state)
+            .max_by_key(|state| state.severity())
+            .unwrap_or(PluginState::Ok)
+    }
+
+    fn count(&self, state: PluginState) -> usize {
+        self.messages.iter().filter(|(s, _)| *s == state).count()
+    }
+
+    /// Print the plugin output: a status line with the performance data, followed by one line
+    /// per problem, the most severe first.
+    fn print(&mut self) {
+        let state = self.state();
+
+        let mut summary = Vec::new();
+        for state in [
+            PluginState::Critical,
+            PluginState::Warning,
+            PluginState::Unknown,
+        ] {
+            let count = self.count(state);
+            if count > 0 {
+                summary.push(format!("{count} {}", state.as_str().to_lowercase()));
+            }
+        }
+        let summary = if summary.is_empty() {
+            "all checks passed".to_string()
+        } else {
+            summary.join(", ")
+        };
+
+        if self.perfdata.is_empty() {
+            println!("PBS {} - {summary}", state.as_str());
+        } else {
+            println!(
+                "PBS {} - {summary} | {}",
+                state.as_str(),
+                self.perfdata.join(" ")
+            );
+        }
+
+        self.messages
+            .sort_by_key(|(state, _)| std::cmp::Reverse(state.severity()));
+        for (state, message) in &self.messages {
+            println!("{}: {message}", state.as_str());
+        }
+    }
+}
+
+fn check_certificate(result: &mut CheckResult, warning_days: i64, critical_days: i64) {
+    let not_after = match proxmox_backup::cert_info().and_then(|cert| cert.not_after_unix()) {
+        Ok(not_after) => not_after,
+        Err(err) => {
+            result.add(
+                PluginState::Unknown,
+                format!("unable to read the certificate - {err}"),
+            );
+            return;
+        }
+    };
+
+    let days = (not_after - proxmox_time::epoch_i64()).div_euclid(SECONDS_PER_DAY);
+    result
+        .perfdata
+        .push(format!("cert_days={days};{warning_days}:;{critical_days}:"));
+
+    let state = if days < critical_days {
+        PluginState::Critical
+    } else if days < warning_days {
+        PluginState::Warning
+    } else {
+        return;
+    };
+
+    let message = if days < 0 {
+        "certificate expired".to_string()
+    } else {
+        format!("certificate expires in {days} days")
+    };
+    result.add(state, message);
+}
+
+async fn check_datastores(
+    result: &mut CheckResult,
+    store: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let status = api2::status::datastore_status(
+        Value::Null,
+        &api2::status::API_METHOD_DATASTORE_STATUS,
+        rpcenv,
+    )
+    .await?;
+    for item in status {
+        if store.as_ref().map_or(false, |store| *store != item.store) {
+            continue;
+        }
+        if let (Some(used), Some(total)) = (item.used, item.total) {
+            result
+                .perfdata
+                .push(format!("'{}_used'={used}B;;;0;{total}", item.store));
+        }
+    }
+
+    for health in api2::status::datastore_health(store, rpcenv).await? {
+        for reason in health.reasons {
+            result.add(
+                reason.state.into(),
+                format!("datastore '{}': {}", health.store, reason.message),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            "cert-warning": {
+                description: "Warn if the certificate expires within this many days.",
+                type: Integer,
+                optional: true,
+                minimum: 0,
+                default: 30,
+            },
+            "cert-critical": {
+                description: "Critical if the certificate expires within this many days.",
+                type: Integer,
+                optional: true,
+                minimum: 0,
+                default: 7,
+            },
+        },
+    },
+)]
+/// Check the health of the datastores, including their scheduled jobs, and the certificate
+///
+/// The output and exit code follow the Nagios plugin guidelines (0: OK, 1: WARNING,
+/// 2: CRITICAL, 3: UNKNOWN), so this can be used directly by Nagios, Icinga and compatible
+/// monitoring systems.
+async fn check(
+    store: Option<String>,
+    cert_warning: Option<i64>,
+    cert_critical: Option<i64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let mut result = CheckResult::default();
+
+    if let Err(err) = check_datastores(&mut result, store, rpcenv).await {
+        result.add(
+            PluginState::Unknown,
+            format!("unable to check datastores - {err}"),
+        );
+    }
+
+    check_certificate(
+        &mut result,
+        cert_warning.unwrap_or(30),
+        cert_critical.unwrap_or(7),
+    );
+
+    result.print();
+
+    std::process::exit(result.state() as i32);
+}
+
+pub fn check_commands() -> CliCommand {
+    CliCommand::new(&API_METHOD_CHECK)
+        .arg_param(&["store"])
+        .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(states: &[PluginState]) -> CheckResult {
+        let mut result = CheckResult::default();
+        for state in states {
+            result.add(*state, state.as_str().to_string());
+        }
+        result
+    }
+
+    #[test]
+    fn test_state_aggregation() {
+        use PluginState::*;
+
+        assert_eq!(result(&[]).state(), Ok);
+        assert_eq!(result(&[Ok, Ok]).state(), Ok);
+        assert_eq!(result(&[Ok, Unknown]).state(), Unknown);
+        assert_eq!(result(&[Unknown, Warning]).state(), Warning);
+        // a datastore in maintenance mode must not hide a critical one
+        assert_eq!(result(&[Critical, Unknown]).state(), Critical);
+        assert_eq!(result(&[Unknown, Critical, Warning]).state(), Critical);
+
+        assert_eq!(Critical as i32, 2);
+        assert_eq!(Unknown as i32, 3);
+        assert_eq!(
+            PluginState::from(DataStoreHealthState::Maintenance),
+            Unknown
+        );
+    }
+}
@@ -1,11 +1,15 @@
-use anyhow::Error;
+use anyhow::{bail, format_err, Error};
 use serde_json::Value;
 
 use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_schema::api;
 
-use pbs_api_types::{DataStoreConfig, DATASTORE_SCHEMA, PROXMOX_CONFIG_DIGEST_SCHEMA};
+use pbs_api_types::{
+    BackupNamespace, DataStoreConfig, BACKING_DEVICE_SCHEMA, DATASTORE_SCHEMA,
+    GROUP_FILTER_LIST_SCHEMA, NS_MAX_DEPTH_REDUCED_SCHEMA, PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
 use pbs_client::view_task_result;
+use pbs_tools::json::required_string_param;
 
 use proxmox_backup::api2;
 use proxmox_backup::client_helpers::connect_to_localhost;
@@ -69,6 +73,58 @@ fn show_datastore(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value
     Ok(Value::Null)
 }
 
+fn render_health_reasons(value: &Value, _record: &Value) -> Result<String, Error> {
+    let reasons = match value.as_array() {
+        Some(reasons) => reasons,
+        None => return Ok(String::new()),
+    };
+    let lines: Vec<String> = reasons
+        .iter()
+        .map(|reason| {
+            format!(
+                "{}: {}",
+                reason["check"].as_str().unwrap_or("unknown"),
+                reason["message"].as_str().unwrap_or_default(),
+            )
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the aggregated health status of the datastores.
+async fn datastore_health(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::status::API_METHOD_DATASTORE_HEALTH;
+    let mut data = match info.handler {
+        ApiHandler::Async(handler) => (handler)(param, info, rpcenv).await?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("state"))
+        .column(ColumnConfig::new("reasons").renderer(render_health_reasons));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
 #[api(
     protected: true,
     input: {
@@ -139,6 +195,187 @@ async fn delete_datastore(mut param: Value, rpcenv: &mut dyn RpcEnvironment) ->
     Ok(())
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "source-store": {
+                schema: DATASTORE_SCHEMA,
+            },
+            "source-ns": {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_REDUCED_SCHEMA,
+                optional: true,
+            },
+            "group-filter": {
+                schema: GROUP_FILTER_LIST_SCHEMA,
+                optional: true,
+            },
+            "link-chunks": {
+                description: "Hard link chunks into the target datastore instead of copying them, if both datastores are on the same file system.",
+                type: bool,
+                optional: true,
+                default: true,
+            },
+            "reflink-chunks": {
+                description: "Copy chunks as reflinks, if not hard linked and the file system supports it (e.g. XFS or btrfs).",
+                type: bool,
+                optional: true,
+                default: true,
+            },
+            "remove-source": {
+                description: "Remove copied snapshots from the source datastore (move).",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Copy backup groups from another local datastore.
+async fn copy_datastore(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = param
+        .as_object_mut()
+        .and_then(|param| param.remove("store"))
+        .ok_or_else(|| format_err!("missing parameter 'store'"))?;
+    let store = store
+        .as_str()
+        .ok_or_else(|| format_err!("parameter 'store' is not a string"))?;
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/copy");
+    let result = client.post(&path, Some(param)).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Mount the removable device backing a datastore.
+async fn mount_datastore(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+    let store = required_string_param(&param, "store")?;
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/mount");
+    let result = client.post(&path, None).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Unmount the removable device backing a datastore, once it is not in use anymore.
+async fn unmount_datastore(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+    let store = required_string_param(&param, "store")?;
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/unmount");
+    let result = client.post(&path, None).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            uuid: {
+                schema: BACKING_DEVICE_SCHEMA,
+            },
+        },
+    },
+)]
+/// Mount all datastores backed by the removable device with the given filesystem UUID.
+///
+/// This is called by udev when a block device is attached.
+async fn uuid_mount(uuid: String) -> Result<Value, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let list: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+
+    let stores: Vec<String> = list
+        .into_iter()
+        .filter(|store| store.backing_device.as_deref() == Some(&uuid))
+        .map(|store| store.name)
+        .collect();
+
+    if stores.is_empty() {
+        return Ok(Value::Null);
+    }
+
+    let client = connect_to_localhost()?;
+
+    // one failing datastore must not keep the others on the device unmounted
+    let mut errors = Vec::new();
+    for store in stores {
+        let path = format!("api2/json/admin/datastore/{store}/mount");
+        let result = match client.post(&path, None).await {
+            Ok(result) => view_task_result(&client, result, "text").await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            eprintln!("mounting datastore '{store}' failed - {err}");
+            errors.push(store);
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("unable to mount datastores: {}", errors.join(", "));
+    }
+
+    Ok(Value::Null)
+}
+
 pub fn datastore_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
         .insert("list", CliCommand::new(&API_METHOD_LIST_DATASTORES))
@@ -148,6 +385,12 @@ pub fn datastore_commands() -> CommandLineInterface {
                 .arg_param(&["name"])
                 .completion_cb("name", pbs_config::datastore::complete_datastore_name),
         )
+        .insert(
+            "health",
+            CliCommand::new(&API_METHOD_DATASTORE_HEALTH)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
         .insert(
             "create",
             CliCommand::new(&API_METHOD_CREATE_DATASTORE).arg_param(&["name", "path"]),
@@ -171,6 +414,32 @@ pub fn datastore_commands() -> CommandLineInterface {
             CliCommand::new(&API_METHOD_DELETE_DATASTORE)
                 .arg_param(&["name"])
                 .completion_cb("name", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "copy",
+            CliCommand::new(&API_METHOD_COPY_DATASTORE)
+                .arg_param(&["source-store", "store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb(
+                    "source-store",
+                    pbs_config::datastore::complete_datastore_name,
+                ),
+        )
+        .insert(
+            "mount",
+            CliCommand::new(&API_METHOD_MOUNT_DATASTORE)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "unmount",
+            CliCommand::new(&API_METHOD_UNMOUNT_DATASTORE)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "uuid-mount",
+            CliCommand::new(&API_METHOD_UUID_MOUNT).arg_param(&["uuid"]),
         );
 
     cmd_def.into()
@@ -339,6 +339,13 @@ fn list_datastore_mounts(
                 type: FileSystemType,
                 optional: true,
             },
+            "removable-datastore": {
+                description: "Do not mount the filesystem on boot, but configure a datastore \
+                    which is mounted on demand, once the disk is attached. Requires 'add-datastore'.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
         },
    },
 )]
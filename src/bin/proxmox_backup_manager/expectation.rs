@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use anyhow::Error;
+use serde::Serialize;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::{api, ApiType, ArraySchema, ReturnType};
+
+use pbs_api_types::{BackupExpectationConfig, JOB_ID_SCHEMA};
+use pbs_config::expectation;
+
+use proxmox_backup::api2;
+use proxmox_backup::server::check_expectation;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List all backup expectations
+fn list_expectations(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::expectation::API_METHOD_LIST_EXPECTATIONS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("disable"))
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("ns"))
+        .column(ColumnConfig::new("backup-type"))
+        .column(ColumnConfig::new("backup-id"))
+        .column(ColumnConfig::new("max-age"))
+        .column(ColumnConfig::new("comment"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show backup expectation configuration
+fn show_expectation(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::expectation::API_METHOD_READ_EXPECTATION;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api]
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// A backup group which did not get a new snapshot in time.
+struct StaleGroupInfo {
+    /// Backup expectation ID
+    id: String,
+    /// Datastore
+    store: String,
+    /// Backup group, including its namespace
+    group: String,
+    /// Time of the newest finished snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_backup: Option<i64>,
+    /// Maximum age of the newest snapshot
+    max_age: String,
+}
+
+const CHECK_RETURN_TYPE: ReturnType = ReturnType {
+    optional: false,
+    schema: &ArraySchema::new("Stale backup groups.", &StaleGroupInfo::API_SCHEMA).schema(),
+};
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Check backup expectations now and list the stale groups. This does not send notifications.
+fn check_expectations(id: Option<String>, param: Value) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let (config, _digest) = expectation::config()?;
+    let expectations: Vec<BackupExpectationConfig> =
+        config.convert_to_typed_array("expectation")?;
+
+    let now = proxmox_time::epoch_i64();
+    let mut list = Vec::new();
+    for expectation in expectations {
+        if id.as_ref().map_or(false, |id| *id != expectation.id) {
+            continue;
+        }
+        for group in check_expectation(&expectation, now)? {
+            list.push(StaleGroupInfo {
+                id: expectation.id.clone(),
+                store: expectation.store.clone(),
+                group: group.group,
+                last_backup: group.last_backup,
+                max_age: expectation.max_age.clone(),
+            });
+        }
+    }
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("group"))
+        .column(ColumnConfig::new("last-backup").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("max-age"));
+
+    format_and_print_result_full(
+        &mut serde_json::to_value(list)?,
+        &CHECK_RETURN_TYPE,
+        &output_format,
+        &options,
+    );
+
+    Ok(Value::Null)
+}
+
+pub fn expectation_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_EXPECTATIONS))
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_EXPECTATION)
+                .arg_param(&["id"])
+                .completion_cb("id", expectation::complete_expectation_id),
+        )
+        .insert(
+            "check",
+            CliCommand::new(&API_METHOD_CHECK_EXPECTATIONS)
+                .arg_param(&["id"])
+                .completion_cb("id", expectation::complete_expectation_id),
+        )
+        .insert(
+            "create",
+            CliCommand::new(&api2::config::expectation::API_METHOD_CREATE_EXPECTATION)
+                .arg_param(&["id"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", complete_expectation_namespace),
+        )
+        .insert(
+            "update",
+            CliCommand::new(&api2::config::expectation::API_METHOD_UPDATE_EXPECTATION)
+                .arg_param(&["id"])
+                .completion_cb("id", expectation::complete_expectation_id)
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", complete_expectation_namespace),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::config::expectation::API_METHOD_DELETE_EXPECTATION)
+                .arg_param(&["id"])
+                .completion_cb("id", expectation::complete_expectation_id),
+        );
+
+    cmd_def.into()
+}
+
+// shell completion helper
+fn complete_expectation_namespace(_arg: &str, param: &HashMap<String, String>) -> Vec<String> {
+    let mut rpcenv = CliEnvironment::new();
+    rpcenv.set_auth_id(Some(String::from("root@pam")));
+
+    let store = param.get("store").cloned().or_else(|| {
+        let (config, _digest) = expectation::config().ok()?;
+        let id = param.get("id")?;
+        config
+            .lookup::<BackupExpectationConfig>("expectation", id)
+            .ok()
+            .map(|expectation| expectation.store)
+    });
+
+    match store {
+        Some(store) => {
+            crate::api2::admin::namespace::list_namespaces(store, None, None, &mut rpcenv)
+                .map(|list| list.into_iter().map(|item| item.ns.name()).collect())
+                .unwrap_or_default()
+        }
+        None => Vec::new(),
+    }
+}
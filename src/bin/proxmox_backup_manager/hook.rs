@@ -0,0 +1,105 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::HOOK_ID_SCHEMA;
+
+use proxmox_backup::api2;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List configured hook scripts.
+fn list_hooks(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::hook::API_METHOD_LIST_HOOKS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("script"))
+        .column(ColumnConfig::new("job-type"))
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("disable"))
+        .column(ColumnConfig::new("comment"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: HOOK_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show hook script configuration
+fn show_hook(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::hook::API_METHOD_READ_HOOK;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+pub fn hook_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_HOOKS))
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_HOOK)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::hook::complete_hook_id),
+        )
+        .insert(
+            "create",
+            CliCommand::new(&api2::config::hook::API_METHOD_CREATE_HOOK)
+                .arg_param(&["id"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("script", complete_file_name),
+        )
+        .insert(
+            "update",
+            CliCommand::new(&api2::config::hook::API_METHOD_UPDATE_HOOK)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::hook::complete_hook_id)
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("script", complete_file_name),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::config::hook::API_METHOD_DELETE_HOOK)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::hook::complete_hook_id),
+        );
+
+    cmd_def.into()
+}
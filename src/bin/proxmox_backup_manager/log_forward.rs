@@ -0,0 +1,105 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::LOG_FORWARD_ID_SCHEMA;
+
+use proxmox_backup::api2;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List configured log forwarders.
+fn list_log_forwarders(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::log_forward::API_METHOD_LIST_LOG_FORWARDERS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("name"))
+        .column(ColumnConfig::new("protocol"))
+        .column(ColumnConfig::new("target"))
+        .column(ColumnConfig::new("format"))
+        .column(ColumnConfig::new("job-type"))
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("disable"))
+        .column(ColumnConfig::new("comment"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: LOG_FORWARD_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show log forwarder configuration
+fn show_log_forward(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::log_forward::API_METHOD_READ_LOG_FORWARD;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+pub fn log_forward_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_LOG_FORWARDERS))
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_LOG_FORWARD)
+                .arg_param(&["name"])
+                .completion_cb("name", pbs_config::log_forward::complete_log_forward_name),
+        )
+        .insert(
+            "create",
+            CliCommand::new(&api2::config::log_forward::API_METHOD_CREATE_LOG_FORWARD)
+                .arg_param(&["name"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "update",
+            CliCommand::new(&api2::config::log_forward::API_METHOD_UPDATE_LOG_FORWARD)
+                .arg_param(&["name"])
+                .completion_cb("name", pbs_config::log_forward::complete_log_forward_name)
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::config::log_forward::API_METHOD_DELETE_LOG_FORWARD)
+                .arg_param(&["name"])
+                .completion_cb("name", pbs_config::log_forward::complete_log_forward_name),
+        );
+
+    cmd_def.into()
+}
@@ -6,22 +6,36 @@ mod ad;
 pub use ad::*;
 mod cert;
 pub use cert::*;
+mod check;
+pub use check::*;
 mod datastore;
 pub use datastore::*;
 mod dns;
 pub use dns::*;
+mod expectation;
+pub use expectation::*;
+mod hook;
+pub use hook::*;
 mod ldap;
 pub use ldap::*;
 mod network;
 pub use network::*;
 mod prune;
 pub use prune::*;
+mod reader_queue;
+pub use reader_queue::*;
 mod remote;
 pub use remote::*;
+mod setup;
+pub use setup::*;
 mod sync;
 pub use sync::*;
 mod verify;
 pub use verify::*;
+mod webhook;
+pub use webhook::*;
+mod log_forward;
+pub use log_forward::*;
 mod user;
 pub use user::*;
 mod subscription;
@@ -246,6 +246,7 @@ pub(crate) fn update_to_prune_jobs_config() -> Result<(), Error> {
             disable: false,
             comment: None,
             schedule,
+            splay: None,
             options,
         };
 
@@ -0,0 +1,88 @@
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use proxmox_router::cli::*;
+use proxmox_schema::api;
+
+use pbs_api_types::READER_QUEUE_PRIORITY_SCHEMA;
+
+use proxmox_backup::api2;
+use proxmox_backup::client_helpers::connect_to_localhost;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List reader sessions (restores) waiting for a free session slot.
+async fn list_reader_queue(param: Value) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    // the queue lives in the proxy
+    let client = connect_to_localhost()?;
+
+    let mut result = client
+        .get("api2/json/nodes/localhost/reader-queue", None)
+        .await?;
+    let mut data = result["data"].take();
+    let return_type = &api2::node::reader_queue::API_METHOD_LIST_QUEUED_READER_SESSIONS.returns;
+
+    use pbs_tools::format::render_epoch;
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("position"))
+        .column(ColumnConfig::new("priority"))
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("snapshot"))
+        .column(ColumnConfig::new("auth-id"))
+        .column(
+            ColumnConfig::new("queued-since")
+                .right_align(false)
+                .renderer(render_epoch),
+        );
+
+    format_and_print_result_full(&mut data, return_type, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                type: u64,
+                description: "Queue ID of the reader session.",
+            },
+            priority: {
+                schema: READER_QUEUE_PRIORITY_SCHEMA,
+            },
+        }
+    }
+)]
+/// Change the priority of a queued reader session.
+async fn set_reader_priority(id: u64, priority: i64) -> Result<Value, Error> {
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/nodes/localhost/reader-queue/{id}");
+    client
+        .put(&path, Some(json!({ "priority": priority })))
+        .await?;
+
+    Ok(Value::Null)
+}
+
+pub fn reader_queue_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_READER_QUEUE))
+        .insert(
+            "set-priority",
+            CliCommand::new(&API_METHOD_SET_READER_PRIORITY).arg_param(&["id", "priority"]),
+        );
+
+    cmd_def.into()
+}
@@ -0,0 +1,512 @@
+use std::io::{IsTerminal, Write};
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use proxmox_router::cli::*;
+use proxmox_schema::{api, ApiType, Schema};
+use proxmox_sys::fs::file_read_string;
+
+use pbs_api_types::{
+    KeepOptions, CIDR_SCHEMA, DATASTORE_SCHEMA, DIR_NAME_SCHEMA, EMAIL_SCHEMA, IP_SCHEMA,
+    NETWORK_INTERFACE_NAME_SCHEMA, PRUNE_SCHEDULE_SCHEMA, PRUNE_SCHEMA_KEEP_DAILY,
+    PRUNE_SCHEMA_KEEP_LAST, PRUNE_SCHEMA_KEEP_MONTHLY, PRUNE_SCHEMA_KEEP_WEEKLY,
+    PRUNE_SCHEMA_KEEP_YEARLY,
+};
+use pbs_client::view_task_result;
+
+use proxmox_backup::client_helpers::connect_to_localhost;
+
+#[api(
+    properties: {
+        email: {
+            schema: EMAIL_SCHEMA,
+            optional: true,
+        },
+        datastore: {
+            schema: DATASTORE_SCHEMA,
+            optional: true,
+        },
+        "datastore-path": {
+            schema: DIR_NAME_SCHEMA,
+            optional: true,
+        },
+        "prune-schedule": {
+            schema: PRUNE_SCHEDULE_SCHEMA,
+            optional: true,
+        },
+        keep: {
+            type: KeepOptions,
+        },
+        iface: {
+            schema: NETWORK_INTERFACE_NAME_SCHEMA,
+            optional: true,
+        },
+        cidr: {
+            schema: CIDR_SCHEMA,
+            optional: true,
+        },
+        gateway: {
+            schema: IP_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Answers for the initial setup. Steps without an answer are skipped.
+pub struct SetupAnswers {
+    /// E-Mail address of root@pam, used for notifications
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// Name of the first datastore
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datastore: Option<String>,
+    /// Path of the first datastore
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datastore_path: Option<String>,
+    /// Schedule of the datastore's default prune job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prune_schedule: Option<String>,
+    #[serde(flatten)]
+    pub keep: KeepOptions,
+    /// Network interface to configure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iface: Option<String>,
+    /// IPv4 or IPv6 address of the interface in CIDR notation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cidr: Option<String>,
+    /// Default gateway of the interface
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>,
+    /// Path to a PEM encoded certificate chain for the API and web interface
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<String>,
+    /// Path to the PEM encoded private key of the certificate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate_key: Option<String>,
+}
+
+/// Ask for a value, empty input selects `default`. Returns `None` if there is no default.
+fn prompt(
+    question: &str,
+    schema: &'static Schema,
+    default: Option<&str>,
+) -> Result<Option<Value>, Error> {
+    for _ in 0..3 {
+        match default {
+            Some(default) => print!("{question} [{default}]: "),
+            None => print!("{question} (leave empty to skip): "),
+        }
+        std::io::stdout().flush()?;
+
+        let mut input = String::new();
+        if std::io::stdin().read_line(&mut input)? == 0 {
+            bail!("Aborting.");
+        }
+
+        let input = match input.trim() {
+            "" => match default {
+                Some(default) => default,
+                None => return Ok(None),
+            },
+            input => input,
+        };
+
+        match schema.parse_simple_value(input) {
+            Ok(value) => return Ok(Some(value)),
+            Err(err) => eprintln!("Invalid value - {err}"),
+        }
+    }
+    bail!("Aborting.");
+}
+
+/// Ask a yes/no question, defaults to yes.
+fn confirm(question: &str) -> Result<bool, Error> {
+    print!("{question} [Y|n]: ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(!input.trim().eq_ignore_ascii_case("n"))
+}
+
+type Question = (
+    &'static str,
+    &'static str,
+    &'static Schema,
+    Option<&'static str>,
+);
+
+fn ask(answers: &mut Map<String, Value>, questions: &[Question]) -> Result<(), Error> {
+    for (key, question, schema, default) in questions {
+        if let Some(value) = prompt(question, schema, *default)? {
+            answers.insert(key.to_string(), value);
+        }
+    }
+    Ok(())
+}
+
+fn interactive_answers() -> Result<Value, Error> {
+    const PATH_SCHEMA: Schema = proxmox_schema::StringSchema::new("File path.").schema();
+
+    let mut answers = Map::new();
+
+    println!("This sets up the basic configuration of this Proxmox Backup Server.");
+
+    ask(
+        &mut answers,
+        &[
+            ("email", "E-Mail address of root@pam", &EMAIL_SCHEMA, None),
+            (
+                "datastore",
+                "Name of the first datastore",
+                &DATASTORE_SCHEMA,
+                None,
+            ),
+        ],
+    )?;
+
+    if let Some(store) = answers.get("datastore").and_then(Value::as_str) {
+        // the default path depends on the answer, so do not use `ask` here
+        let default_path = format!("/mnt/datastore/{store}");
+        if let Some(path) = prompt(
+            "Path of the datastore",
+            &DIR_NAME_SCHEMA,
+            Some(&default_path),
+        )? {
+            answers.insert("datastore-path".to_string(), path);
+        }
+
+        if confirm("Create a prune job for the datastore?")? {
+            ask(
+                &mut answers,
+                &[
+                    (
+                        "prune-schedule",
+                        "Prune schedule",
+                        &PRUNE_SCHEDULE_SCHEMA,
+                        Some("daily"),
+                    ),
+                    ("keep-last", "Keep last", &PRUNE_SCHEMA_KEEP_LAST, Some("3")),
+                    (
+                        "keep-daily",
+                        "Keep daily",
+                        &PRUNE_SCHEMA_KEEP_DAILY,
+                        Some("7"),
+                    ),
+                    (
+                        "keep-weekly",
+                        "Keep weekly",
+                        &PRUNE_SCHEMA_KEEP_WEEKLY,
+                        Some("4"),
+                    ),
+                    (
+                        "keep-monthly",
+                        "Keep monthly",
+                        &PRUNE_SCHEMA_KEEP_MONTHLY,
+                        Some("6"),
+                    ),
+                    (
+                        "keep-yearly",
+                        "Keep yearly",
+                        &PRUNE_SCHEMA_KEEP_YEARLY,
+                        None,
+                    ),
+                ],
+            )?;
+        }
+    }
+
+    ask(
+        &mut answers,
+        &[(
+            "iface",
+            "Network interface to configure",
+            &NETWORK_INTERFACE_NAME_SCHEMA,
+            None,
+        )],
+    )?;
+    if answers.contains_key("iface") {
+        ask(
+            &mut answers,
+            &[
+                ("cidr", "Address (CIDR)", &CIDR_SCHEMA, None),
+                ("gateway", "Gateway", &IP_SCHEMA, None),
+            ],
+        )?;
+    }
+
+    ask(
+        &mut answers,
+        &[(
+            "certificate",
+            "Path to a custom certificate chain (PEM)",
+            &PATH_SCHEMA,
+            None,
+        )],
+    )?;
+    if answers.contains_key("certificate") {
+        ask(
+            &mut answers,
+            &[(
+                "certificate-key",
+                "Path to its private key (PEM)",
+                &PATH_SCHEMA,
+                None,
+            )],
+        )?;
+    }
+
+    Ok(Value::Object(answers))
+}
+
+const KEEP_PROPERTIES: &[&str] = &[
+    "keep-last",
+    "keep-hourly",
+    "keep-daily",
+    "keep-weekly",
+    "keep-monthly",
+    "keep-yearly",
+];
+
+/// Parameters of the prune job created for `store`.
+fn prune_job_params(answers: &SetupAnswers, store: &str, schedule: &str) -> Result<Value, Error> {
+    if !answers.keep.keeps_something() {
+        bail!("prune schedule given, but no retention options");
+    }
+    let mut param = serde_json::to_value(&answers.keep)?;
+    param["store"] = store.into();
+    param["schedule"] = schedule.into();
+    Ok(param)
+}
+
+/// Parameters for updating the network interface, IPv6 addresses use the `*6` properties.
+fn network_params(answers: &SetupAnswers) -> Value {
+    let mut param = json!({});
+    if let Some(cidr) = &answers.cidr {
+        let key = if cidr.contains(':') { "cidr6" } else { "cidr" };
+        param[key] = cidr.as_str().into();
+    }
+    if let Some(gateway) = &answers.gateway {
+        let key = if gateway.contains(':') {
+            "gateway6"
+        } else {
+            "gateway"
+        };
+        param[key] = gateway.as_str().into();
+    }
+    param
+}
+
+/// Returns the entry with the `key` property `value` of a configuration list.
+fn find_entry<'a>(list: &'a Value, key: &str, value: &str) -> Option<&'a Value> {
+    list.as_array()?
+        .iter()
+        .find(|entry| entry[key].as_str() == Some(value))
+}
+
+async fn apply_answers(answers: &SetupAnswers, output_format: &str) -> Result<(), Error> {
+    let client = connect_to_localhost()?;
+
+    if let Some(email) = &answers.email {
+        println!("Setting E-Mail address of root@pam");
+        client
+            .put(
+                "api2/json/access/users/root@pam",
+                Some(json!({ "email": email })),
+            )
+            .await?;
+    }
+
+    if let Some(store) = &answers.datastore {
+        let path = answers
+            .datastore_path
+            .clone()
+            .unwrap_or_else(|| format!("/mnt/datastore/{store}"));
+        // re-running the setup keeps existing datastores and updates the prune job
+        let datastores = client.get("api2/json/config/datastore", None).await?;
+        match find_entry(&datastores["data"], "name", store) {
+            Some(existing) if existing["path"].as_str() == Some(&path) => {
+                println!("Datastore '{store}' already exists");
+            }
+            Some(existing) => bail!(
+                "datastore '{store}' already exists on {}",
+                existing["path"].as_str().unwrap_or("unknown path"),
+            ),
+            None => {
+                println!("Creating datastore '{store}' on {path}");
+                let result = client
+                    .post(
+                        "api2/json/config/datastore",
+                        Some(json!({ "name": store, "path": path })),
+                    )
+                    .await?;
+                view_task_result(&client, result, output_format).await?;
+            }
+        }
+
+        if let Some(schedule) = &answers.prune_schedule {
+            let mut param = prune_job_params(answers, store, schedule)?;
+            let id = format!("default-{store}");
+            let jobs = client.get("api2/json/config/prune", None).await?;
+            if find_entry(&jobs["data"], "id", &id).is_some() {
+                println!("Updating prune job '{id}' of datastore '{store}'");
+                // retention options not given anymore are removed
+                let delete: Vec<&str> = KEEP_PROPERTIES
+                    .iter()
+                    .copied()
+                    .filter(|key| param[*key].is_null())
+                    .collect();
+                if !delete.is_empty() {
+                    param["delete"] = delete.into();
+                }
+                client
+                    .put(&format!("api2/json/config/prune/{id}"), Some(param))
+                    .await?;
+            } else {
+                println!("Creating prune job '{id}' for datastore '{store}'");
+                param["id"] = id.into();
+                client.post("api2/json/config/prune", Some(param)).await?;
+            }
+        }
+    }
+
+    if let Some(iface) = &answers.iface {
+        println!("Configuring network interface '{iface}'");
+        client
+            .put(
+                &format!("api2/json/nodes/localhost/network/{iface}"),
+                Some(network_params(answers)),
+            )
+            .await?;
+        let result = client
+            .put("api2/json/nodes/localhost/network", None)
+            .await?;
+        view_task_result(&client, result, output_format).await?;
+    }
+
+    // last, the proxy reloads its certificate
+    if let Some(certificate) = &answers.certificate {
+        let mut param = json!({ "certificates": file_read_string(certificate)? });
+        if let Some(key) = &answers.certificate_key {
+            param["key"] = file_read_string(key)?.into();
+        }
+        println!("Uploading custom certificate");
+        client
+            .post("api2/json/nodes/localhost/certificates/custom", Some(param))
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            "answer-file": {
+                description: "Read the answers from this JSON file instead of asking for them.",
+                type: String,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Initial setup of the node: E-Mail address of root@pam, first datastore with a prune job,
+/// network interface and certificate.
+async fn setup(param: Value) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+
+    let answers = match param["answer-file"].as_str() {
+        Some(path) => serde_json::from_str(&file_read_string(path)?)
+            .map_err(|err| format_err!("unable to parse answer file {path:?} - {err}"))?,
+        None if std::io::stdin().is_terminal() => interactive_answers()?,
+        None => bail!("no terminal, use '--answer-file' for unattended setups"),
+    };
+
+    SetupAnswers::API_SCHEMA
+        .verify_json(&answers)
+        .map_err(|err| format_err!("invalid answers - {err}"))?;
+    let answers: SetupAnswers = serde_json::from_value(answers)?;
+
+    if param["answer-file"].is_null() {
+        println!("\n{}", serde_json::to_string_pretty(&answers)?);
+        if !confirm("Apply this configuration?")? {
+            bail!("Aborting.");
+        }
+    }
+
+    apply_answers(&answers, &output_format).await
+}
+
+pub fn setup_cmd_def() -> CliCommand {
+    CliCommand::new(&API_METHOD_SETUP).completion_cb("answer-file", complete_file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_params() {
+        let mut answers = SetupAnswers {
+            iface: Some("eth0".to_string()),
+            cidr: Some("192.168.0.10/24".to_string()),
+            gateway: Some("192.168.0.1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            network_params(&answers),
+            json!({ "cidr": "192.168.0.10/24", "gateway": "192.168.0.1" }),
+        );
+
+        answers.cidr = Some("fd00::10/64".to_string());
+        answers.gateway = Some("fd00::1".to_string());
+        assert_eq!(
+            network_params(&answers),
+            json!({ "cidr6": "fd00::10/64", "gateway6": "fd00::1" }),
+        );
+
+        answers.gateway = None;
+        assert_eq!(network_params(&answers), json!({ "cidr6": "fd00::10/64" }));
+    }
+
+    #[test]
+    fn test_prune_job_params() -> Result<(), Error> {
+        let mut answers = SetupAnswers::default();
+        assert!(prune_job_params(&answers, "store1", "daily").is_err());
+
+        answers.keep.keep_last = Some(3);
+        answers.keep.keep_daily = Some(7);
+        assert_eq!(
+            prune_job_params(&answers, "store1", "daily")?,
+            json!({
+                "keep-last": 3,
+                "keep-daily": 7,
+                "store": "store1",
+                "schedule": "daily",
+            }),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_entry() {
+        let list = json!([
+            { "name": "store1", "path": "/mnt/datastore/store1" },
+            { "name": "store2", "path": "/mnt/datastore/store2" },
+        ]);
+        assert_eq!(
+            find_entry(&list, "name", "store2").map(|entry| &entry["path"]),
+            Some(&json!("/mnt/datastore/store2")),
+        );
+        assert!(find_entry(&list, "name", "store3").is_none());
+        assert!(find_entry(&Value::Null, "name", "store1").is_none());
+    }
+}
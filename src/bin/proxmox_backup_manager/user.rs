@@ -223,6 +223,13 @@ pub fn user_commands() -> CommandLineInterface {
                 .arg_param(&["userid", "token-name"])
                 .completion_cb("userid", pbs_config::user::complete_userid),
         )
+        .insert(
+            "update-token",
+            CliCommand::new(&api2::access::user::API_METHOD_UPDATE_TOKEN)
+                .arg_param(&["userid", "token-name"])
+                .completion_cb("userid", pbs_config::user::complete_userid)
+                .completion_cb("token-name", pbs_config::user::complete_token_name),
+        )
         .insert(
             "delete-token",
             CliCommand::new(&api2::access::user::API_METHOD_DELETE_TOKEN)
@@ -0,0 +1,103 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::WEBHOOK_ID_SCHEMA;
+
+use proxmox_backup::api2;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List configured webhooks.
+fn list_webhooks(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::webhook::API_METHOD_LIST_WEBHOOKS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("name"))
+        .column(ColumnConfig::new("url"))
+        .column(ColumnConfig::new("trigger"))
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("disable"))
+        .column(ColumnConfig::new("comment"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: WEBHOOK_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show webhook configuration
+fn show_webhook(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::webhook::API_METHOD_READ_WEBHOOK;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+pub fn webhook_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_WEBHOOKS))
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_WEBHOOK)
+                .arg_param(&["name"])
+                .completion_cb("name", pbs_config::webhook::complete_webhook_name),
+        )
+        .insert(
+            "create",
+            CliCommand::new(&api2::config::webhook::API_METHOD_CREATE_WEBHOOK)
+                .arg_param(&["name"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "update",
+            CliCommand::new(&api2::config::webhook::API_METHOD_UPDATE_WEBHOOK)
+                .arg_param(&["name"])
+                .completion_cb("name", pbs_config::webhook::complete_webhook_name)
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::config::webhook::API_METHOD_DELETE_WEBHOOK)
+                .arg_param(&["name"])
+                .completion_cb("name", pbs_config::webhook::complete_webhook_name),
+        );
+
+    cmd_def.into()
+}
@@ -65,6 +65,12 @@ pub fn encryption_key_commands() -> CommandLineInterface {
                 type: PaperkeyFormat,
                 optional: true,
             },
+            envelope: {
+                description: "Protect the printed key with an additional passphrase.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
         },
     },
 )]
@@ -75,6 +81,7 @@ fn paper_key(
     fingerprint: Fingerprint,
     subject: Option<String>,
     output_format: Option<PaperkeyFormat>,
+    envelope: bool,
 ) -> Result<(), Error> {
     let (config_map, _digest) = load_key_configs()?;
 
@@ -85,7 +92,24 @@ fn paper_key(
 
     let data: String = serde_json::to_string_pretty(&key_config)?;
 
-    generate_paper_key(std::io::stdout(), &data, subject, output_format)
+    let passphrase = if envelope {
+        if !std::io::stdin().is_terminal() {
+            bail!("unable to read envelope passphrase - no tty");
+        }
+        Some(tty::read_and_verify_password(
+            "Paperkey Envelope Passphrase: ",
+        )?)
+    } else {
+        None
+    };
+
+    generate_paper_key(
+        std::io::stdout(),
+        &data,
+        subject,
+        output_format,
+        passphrase.as_deref(),
+    )
 }
 
 #[api(
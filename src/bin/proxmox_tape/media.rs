@@ -1,4 +1,4 @@
-use anyhow::Error;
+use anyhow::{bail, format_err, Error};
 use serde::Deserialize;
 use serde_json::Value;
 
@@ -6,15 +6,19 @@ use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_schema::api;
 
 use pbs_api_types::{
-    MediaContentListFilter, MediaListEntry, MediaStatus, CHANGER_NAME_SCHEMA,
-    MEDIA_POOL_NAME_SCHEMA,
+    BarcodeSymbology, LabelSheetLayout, MediaContentListFilter, MediaListEntry, MediaStatus,
+    BARCODE_LABEL_COUNT_SCHEMA, BARCODE_LABEL_PREFIX_SCHEMA, BARCODE_LABEL_START_SCHEMA,
+    BARCODE_MEDIA_TYPE_SCHEMA, CHANGER_NAME_SCHEMA, MEDIA_POOL_NAME_SCHEMA,
 };
 use pbs_config::drive::complete_changer_name;
 use pbs_config::media_pool::complete_pool_name;
 
 use proxmox_backup::{
     api2,
-    tape::{complete_media_label_text, complete_media_set_uuid, complete_media_uuid},
+    tape::{
+        barcode_labels::{barcode_label_sheet_pdf, barcode_label_texts, retain_unused_label_texts},
+        complete_media_label_text, complete_media_set_uuid, complete_media_uuid,
+    },
 };
 
 pub fn media_commands() -> CommandLineInterface {
@@ -31,6 +35,10 @@ pub fn media_commands() -> CommandLineInterface {
                 .arg_param(&["label-text"])
                 .completion_cb("label-text", complete_media_label_text),
         )
+        .insert(
+            "label-sheet",
+            CliCommand::new(&API_METHOD_LABEL_SHEET).arg_param(&["output"]),
+        )
         .insert(
             "content",
             CliCommand::new(&API_METHOD_LIST_CONTENT)
@@ -166,3 +174,86 @@ fn list_content(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Err
 
     Ok(())
 }
+
+#[api(
+    input: {
+        properties: {
+            output: {
+                description: "Output file name.",
+                type: String,
+            },
+            prefix: {
+                schema: BARCODE_LABEL_PREFIX_SCHEMA,
+                optional: true,
+            },
+            start: {
+                schema: BARCODE_LABEL_START_SCHEMA,
+                optional: true,
+            },
+            count: {
+                schema: BARCODE_LABEL_COUNT_SCHEMA,
+            },
+            "media-type": {
+                schema: BARCODE_MEDIA_TYPE_SCHEMA,
+            },
+            symbology: {
+                type: BarcodeSymbology,
+                optional: true,
+            },
+            layout: {
+                type: LabelSheetLayout,
+                optional: true,
+            },
+            "skip-existing": {
+                description: "Skip label texts already used in the inventory.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+)]
+/// Write a PDF with printable barcode labels for new media.
+#[allow(clippy::too_many_arguments)]
+fn label_sheet(
+    output: String,
+    prefix: Option<String>,
+    start: Option<u64>,
+    count: u64,
+    media_type: String,
+    symbology: Option<BarcodeSymbology>,
+    layout: Option<LabelSheetLayout>,
+    skip_existing: bool,
+) -> Result<(), Error> {
+    let mut label_texts = barcode_label_texts(
+        prefix.as_deref().unwrap_or(""),
+        start.unwrap_or(1),
+        count,
+        &media_type,
+    )?;
+
+    if skip_existing {
+        retain_unused_label_texts(&mut label_texts)?;
+        if label_texts.is_empty() {
+            bail!("all label texts are already in use");
+        }
+    }
+
+    let pdf = barcode_label_sheet_pdf(
+        &label_texts,
+        symbology.unwrap_or_default(),
+        layout.unwrap_or_default(),
+    )?;
+
+    std::fs::write(&output, pdf)
+        .map_err(|err| format_err!("unable to write '{output}' - {err}"))?;
+
+    println!(
+        "wrote {} labels ({} .. {}) to '{output}'",
+        label_texts.len(),
+        label_texts[0],
+        label_texts[label_texts.len() - 1],
+    );
+
+    Ok(())
+}
@@ -109,7 +109,8 @@ fn get_config(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error
         .column(ColumnConfig::new("allocation"))
         .column(ColumnConfig::new("retention"))
         .column(ColumnConfig::new("template"))
-        .column(ColumnConfig::new("encrypt"));
+        .column(ColumnConfig::new("encrypt"))
+        .column(ColumnConfig::new("slots"));
 
     format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
 
@@ -1,7 +1,8 @@
-use anyhow::Error;
+use anyhow::{bail, Error};
 
 use pbs_api_types::{Authid, Userid};
 use pbs_client::{HttpClient, HttpClientOptions};
+use pbs_config::ConfigWriteMode;
 
 use proxmox_auth_api::ticket::Ticket;
 
@@ -10,7 +11,15 @@ use crate::auth::private_auth_keyring;
 /// Connect to localhost:8007 as root@pam
 ///
 /// This automatically creates a ticket if run as 'root' user.
+///
+/// Changes done through this client are applied by the API daemon, so this fails in dry-run mode.
 pub fn connect_to_localhost() -> Result<pbs_client::HttpClient, Error> {
+    match pbs_config::config_write_mode() {
+        ConfigWriteMode::Write => (),
+        ConfigWriteMode::Diff => log::warn!("changes applied by the API daemon are not shown"),
+        ConfigWriteMode::DryRun => bail!("not supported in dry-run mode"),
+    }
+
     let options = if nix::unistd::Uid::current().is_root() {
         let ticket =
             Ticket::new("PBS", Userid::root_userid())?.sign(private_auth_keyring(), None)?;
@@ -4,12 +4,12 @@ use anyhow::{bail, Error};
 use openssl::ssl::{SslAcceptor, SslMethod};
 use serde::{Deserialize, Serialize};
 
-use proxmox_schema::{api, ApiStringFormat, ApiType, Updater};
+use proxmox_schema::{api, ApiStringFormat, ApiType, Schema, StringSchema, Updater};
 
 use proxmox_http::ProxyConfig;
 
 use pbs_api_types::{
-    EMAIL_SCHEMA, MULTI_LINE_COMMENT_SCHEMA, OPENSSL_CIPHERS_TLS_1_2_SCHEMA,
+    Translation, EMAIL_SCHEMA, MULTI_LINE_COMMENT_SCHEMA, OPENSSL_CIPHERS_TLS_1_2_SCHEMA,
     OPENSSL_CIPHERS_TLS_1_3_SCHEMA,
 };
 
@@ -60,68 +60,41 @@ pub struct AcmeConfig {
     account: AcmeAccountName,
 }
 
-/// All available languages in Proxmox. Taken from proxmox-i18n repository.
-/// pt_BR, zh_CN, and zh_TW use the same case in the translation files.
-// TODO: auto-generate from available translations
-#[api]
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum Translation {
-    /// Arabic
-    Ar,
-    /// Catalan
-    Ca,
-    /// Danish
-    Da,
-    /// German
-    De,
-    /// English
-    En,
-    /// Spanish
-    Es,
-    /// Euskera
-    Eu,
-    /// Persian (Farsi)
-    Fa,
-    /// French
-    Fr,
-    /// Galician
-    Gl,
-    /// Hebrew
-    He,
-    /// Hungarian
-    Hu,
-    /// Italian
-    It,
-    /// Japanese
-    Ja,
-    /// Korean
-    Kr,
-    /// Norwegian (Bokmal)
-    Nb,
-    /// Dutch
-    Nl,
-    /// Norwegian (Nynorsk)
-    Nn,
-    /// Polish
-    Pl,
-    /// Portuguese (Brazil)
-    #[serde(rename = "pt_BR")]
-    PtBr,
-    /// Russian
-    Ru,
-    /// Slovenian
-    Sl,
-    /// Swedish
-    Sv,
-    /// Turkish
-    Tr,
-    /// Chinese (simplified)
-    #[serde(rename = "zh_CN")]
-    ZhCn,
-    /// Chinese (traditional)
-    #[serde(rename = "zh_TW")]
-    ZhTw,
+pub const SNMP_OID_SCHEMA: Schema =
+    StringSchema::new("Object identifier, for example '1.3.6.1.4.1'.")
+        .format(&ApiStringFormat::VerifyFn(verify_oid))
+        .max_length(256)
+        .schema();
+
+fn verify_oid(oid: &str) -> Result<(), Error> {
+    let _: crate::tools::agentx::Oid = oid.parse()?;
+    Ok(())
+}
+
+#[api(
+    properties: {
+        socket: {
+            optional: true,
+            type: String,
+            max_length: 256,
+        },
+        oid: {
+            optional: true,
+            schema: SNMP_OID_SCHEMA,
+        },
+    }
+)]
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+/// The SNMP subagent configuration.
+///
+/// The proxy registers as AgentX subagent at the master agent while this is set.
+pub struct SnmpConfig {
+    /// Path of the AgentX socket of the master agent (default '/var/agentx/master').
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket: Option<String>,
+    /// Base OID of the exposed subtree (default '1.3.6.1.4.1.8072.9999.9999.48').
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oid: Option<String>,
 }
 
 #[api(
@@ -171,10 +144,19 @@ pub enum Translation {
             schema: Translation::API_SCHEMA,
             optional: true,
         },
+        "notification-lang" : {
+            schema: Translation::API_SCHEMA,
+            optional: true,
+        },
         "description" : {
             optional: true,
             schema: MULTI_LINE_COMMENT_SCHEMA,
-        }
+        },
+        snmp: {
+            optional: true,
+            type: String,
+            format: &ApiStringFormat::PropertyString(&SnmpConfig::API_SCHEMA),
+        },
     },
 )]
 #[derive(Deserialize, Serialize, Updater)]
@@ -218,6 +200,11 @@ pub struct NodeConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub default_lang: Option<String>,
 
+    /// Language of notifications, if a translation of the template is
+    /// installed (default English)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification_lang: Option<String>,
+
     /// Node description
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
@@ -225,6 +212,46 @@ pub struct NodeConfig {
     /// Maximum days to keep Task logs
     #[serde(skip_serializing_if = "Option::is_none")]
     pub task_log_max_days: Option<usize>,
+
+    /// Abort backup sessions whose client has been silent for this many
+    /// seconds (default 900, 0 disables). Only applies to clients which
+    /// announce sending heartbeats.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_session_timeout: Option<u64>,
+
+    /// Abort backup sessions if no chunk was uploaded to an open archive for
+    /// this many seconds (default 0, disabled)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_archive_timeout: Option<u64>,
+
+    /// Maximum number of concurrent reader sessions per snapshot (default 0,
+    /// unlimited)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reader_max_sessions_per_snapshot: Option<usize>,
+
+    /// Maximum number of concurrent reader sessions per datastore (default 0,
+    /// unlimited)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reader_max_sessions_per_datastore: Option<usize>,
+
+    /// Maximum number of concurrent reader sessions per user, including its
+    /// API tokens (default 0, unlimited)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reader_max_sessions_per_user: Option<usize>,
+
+    /// Seconds a reader session waits for a free slot before it is rejected
+    /// (default 300)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reader_queue_timeout: Option<u64>,
+
+    /// Maximum number of scheduled jobs started per minute, further due jobs
+    /// are started in the following minutes (default 0, unlimited)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_start_limit: Option<usize>,
+
+    /// Expose metrics via SNMP, as AgentX subagent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snmp: Option<String>,
 }
 
 impl NodeConfig {
@@ -234,6 +261,12 @@ impl NodeConfig {
         })
     }
 
+    pub fn snmp_config(&self) -> Option<Result<SnmpConfig, Error>> {
+        self.snmp.as_deref().map(|config| -> Result<_, Error> {
+            crate::tools::config::from_property_string(config, &SnmpConfig::API_SCHEMA)
+        })
+    }
+
     pub async fn acme_client(&self) -> Result<AcmeClient, Error> {
         let account = if let Some(cfg) = self.acme_config().transpose()? {
             cfg.account
@@ -70,6 +70,9 @@ pub fn write(data: &TfaConfig) -> Result<(), Error> {
     let options = CreateOptions::new().perm(Mode::from_bits_truncate(0o0600));
 
     let json = serde_json::to_vec(data)?;
+    if !pbs_config::preview_config_change(CONF_FILE, &json, true)? {
+        return Ok(());
+    }
     proxmox_sys::fs::replace_file(CONF_FILE, &json, options, true)
 }
 
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+use const_format::concatcp;
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn};
+
+use pbs_api_types::{
+    Authid, BackupNamespace, DataStoreConfig, MaintenanceMode, MaintenanceType, Operation,
+};
+use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
+use pbs_datastore::{task_tracking, BackupInfo, DataStore};
+use proxmox_rest_server::WorkerTask;
+
+use crate::server::jobstate::Job;
+
+/// Usage state of each ephemeral datastore, used to remove stores which stayed empty for their
+/// TTL.
+const EPHEMERAL_STATE_FN: &str = concatcp!(
+    pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR,
+    "/ephemeral-state.json"
+);
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct EphemeralState {
+    /// The datastore contained snapshots at some point, new stores are never removed.
+    used: bool,
+    /// Time since which the datastore is empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    empty_since: Option<i64>,
+}
+
+fn load_state() -> HashMap<String, EphemeralState> {
+    file_read_optional_string(EPHEMERAL_STATE_FN)
+        .ok()
+        .flatten()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Returns the time since which the ephemeral datastores are empty, by name.
+///
+/// Only datastores which contained snapshots before are included.
+pub fn ephemeral_empty_since() -> HashMap<String, i64> {
+    load_state()
+        .into_iter()
+        .filter(|(_, state)| state.used)
+        .filter_map(|(name, state)| Some((name, state.empty_since?)))
+        .collect()
+}
+
+fn save_state(state: &HashMap<String, EphemeralState>) -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let options = CreateOptions::new()
+        .perm(nix::sys::stat::Mode::from_bits_truncate(0o644))
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    replace_file(
+        EPHEMERAL_STATE_FN,
+        serde_json::to_string(state)?.as_bytes(),
+        options,
+        false,
+    )
+}
+
+/// Returns the time a snapshot arrived on this datastore.
+///
+/// The backup time is chosen by the client and is far in the past for synced or imported
+/// snapshots, so the newest modification time of the snapshot's files is used. The manifest is
+/// only considered as fallback, as it is rewritten on verification or note changes.
+fn arrival_time(path: &Path, files: &[String]) -> Option<i64> {
+    let mtime = |name: &str| std::fs::metadata(path.join(name)).ok().map(|m| m.mtime());
+
+    files
+        .iter()
+        .filter(|name| *name != MANIFEST_BLOB_NAME)
+        .filter_map(|name| mtime(name))
+        .max()
+        .or_else(|| mtime(MANIFEST_BLOB_NAME))
+}
+
+/// Checks whether a snapshot which arrived at `arrived` exceeded its TTL.
+///
+/// Unfinished snapshots never expire, they are either still running or cleaned up otherwise.
+fn is_expired(info: &BackupInfo, arrived: Option<i64>, ttl: i64, now: i64) -> bool {
+    match arrived {
+        Some(arrived) => info.is_finished() && now - arrived > ttl,
+        None => false,
+    }
+}
+
+/// Remove all finished, unprotected snapshots which arrived more than `ttl` seconds ago.
+///
+/// Groups without any snapshots left are removed as well. Returns the number of removed snapshots
+/// and whether the datastore is empty afterwards.
+fn expire_snapshots(
+    worker: &WorkerTask,
+    datastore: &Arc<DataStore>,
+    ttl: i64,
+    now: i64,
+) -> Result<(usize, bool), Error> {
+    let mut removed = 0;
+    let mut empty = true;
+
+    for ns in datastore.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+        for group in datastore.iter_backup_groups_ok(ns)? {
+            let mut remaining = 0;
+            for info in group.list_backups()? {
+                let backup_dir = &info.backup_dir;
+                let arrived = arrival_time(&backup_dir.full_path(), &info.files);
+                if !is_expired(&info, arrived, ttl, now) {
+                    remaining += 1;
+                    continue;
+                }
+                if info.protected {
+                    task_log!(worker, "keeping protected snapshot {backup_dir:?}");
+                    remaining += 1;
+                    continue;
+                }
+                match backup_dir.destroy(false) {
+                    Ok(()) => {
+                        task_log!(worker, "removed expired snapshot {backup_dir:?}");
+                        removed += 1;
+                    }
+                    Err(err) => {
+                        task_warn!(worker, "failed to remove snapshot {backup_dir:?} - {err}");
+                        remaining += 1;
+                    }
+                }
+            }
+
+            if remaining > 0 {
+                empty = false;
+            } else if let Err(err) = group.destroy() {
+                task_warn!(worker, "failed to remove empty group {group:?} - {err}");
+            }
+        }
+    }
+
+    Ok((removed, empty))
+}
+
+fn expire_all_ephemeral_datastores(worker: &WorkerTask) -> Result<(), Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let stores: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+
+    let now = proxmox_time::epoch_i64();
+    let mut old_state = load_state();
+    let mut state = HashMap::new();
+    let mut errors = false;
+
+    for store in stores {
+        let name = &store.name;
+        let ephemeral = match store.get_ephemeral() {
+            Ok(Some(ephemeral)) => ephemeral,
+            Ok(None) => continue,
+            Err(err) => {
+                task_warn!(
+                    worker,
+                    "datastore '{name}': invalid ephemeral settings - {err}"
+                );
+                errors = true;
+                continue;
+            }
+        };
+
+        let result = ephemeral.ttl_seconds().and_then(|ttl| {
+            let datastore = DataStore::lookup_datastore(name, Some(Operation::Write))?;
+            expire_snapshots(worker, &datastore, ttl, now)
+        });
+        match result {
+            Ok((removed, empty)) => {
+                task_log!(
+                    worker,
+                    "datastore '{name}': removed {removed} snapshots older than {}",
+                    ephemeral.ttl,
+                );
+                let old = old_state.remove(name).unwrap_or_default();
+                let used = old.used || removed > 0 || !empty;
+                let empty_since = match (empty && used, old.empty_since) {
+                    (true, Some(since)) => Some(since),
+                    (true, None) => Some(now),
+                    (false, _) => None,
+                };
+                state.insert(name.clone(), EphemeralState { used, empty_since });
+            }
+            Err(err) => {
+                task_warn!(
+                    worker,
+                    "datastore '{name}': expiring snapshots failed - {err}"
+                );
+                errors = true;
+                // keep the state, the datastore may be in maintenance mode
+                if let Some(old) = old_state.remove(name) {
+                    state.insert(name.clone(), old);
+                }
+            }
+        }
+    }
+
+    save_state(&state)?;
+
+    if errors {
+        bail!("expiring some ephemeral datastores failed");
+    }
+
+    Ok(())
+}
+
+/// Puts the datastore into read-only maintenance mode, unless some other maintenance mode is set.
+fn set_removal_maintenance(name: &str) -> Result<bool, Error> {
+    let _lock = pbs_config::datastore::lock_config()?;
+    let (mut config, _digest) = pbs_config::datastore::config()?;
+    let mut datastore: DataStoreConfig = config.lookup("datastore", name)?;
+
+    if datastore.get_maintenance_mode().is_some() {
+        return Ok(false);
+    }
+    datastore.set_maintenance_mode(Some(MaintenanceMode {
+        ty: MaintenanceType::ReadOnly,
+        message: None,
+    }))?;
+
+    config.set_data(name, "datastore", &datastore)?;
+    pbs_config::datastore::save_config(&config)?;
+
+    Ok(true)
+}
+
+/// Clears the maintenance mode set by [`prepare_empty_datastore_removal`].
+pub async fn cancel_empty_datastore_removal(name: &str) -> Result<(), Error> {
+    {
+        let _lock = pbs_config::datastore::lock_config()?;
+        let (mut config, _digest) = pbs_config::datastore::config()?;
+        let mut datastore: DataStoreConfig = config.lookup("datastore", name)?;
+
+        datastore.set_maintenance_mode(None)?;
+
+        config.set_data(name, "datastore", &datastore)?;
+        pbs_config::datastore::save_config(&config)?;
+    }
+    let _ = super::notify_datastore_cache_update(name).await;
+
+    Ok(())
+}
+
+fn has_backup_groups(datastore: &Arc<DataStore>) -> Result<bool, Error> {
+    for ns in datastore.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+        if datastore.iter_backup_groups_ok(ns)?.next().is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Prepares removing an ephemeral datastore which was found empty by the last expiry run.
+///
+/// Backups may have been added since then, so the datastore is put into read-only maintenance
+/// mode and checked again. Returns `true` if it is still empty, it then stays in maintenance mode
+/// until it is removed. Otherwise, the maintenance mode is cleared again.
+pub async fn prepare_empty_datastore_removal(name: &str) -> Result<bool, Error> {
+    if !set_removal_maintenance(name)? {
+        log::info!("datastore '{name}' is in maintenance mode, not removing it");
+        return Ok(false);
+    }
+    let _ = super::notify_datastore_cache_update(name).await;
+
+    let result = task_tracking::get_active_operations(name).and_then(|active| {
+        if active.write > 0 {
+            log::info!("datastore '{name}' has running write operations, not removing it");
+            return Ok(false);
+        }
+        let datastore = DataStore::lookup_datastore(name, Some(Operation::Read))?;
+        if has_backup_groups(&datastore)? {
+            log::info!("datastore '{name}' is not empty anymore, not removing it");
+            return Ok(false);
+        }
+        Ok(true)
+    });
+
+    if !matches!(result, Ok(true)) {
+        if let Err(err) = cancel_empty_datastore_removal(name).await {
+            log::warn!("could not clear maintenance mode of datastore '{name}' - {err}");
+        }
+    }
+
+    result
+}
+
+/// Remove expired snapshots of all ephemeral datastores.
+pub fn do_ephemeral_expiry(
+    mut job: Job,
+    auth_id: &Authid,
+    schedule: Option<String>,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    let worker_type = job.jobtype().to_string();
+    let upid_str = WorkerTask::new_thread(
+        &worker_type,
+        None,
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            job.start(&worker.upid().to_string())?;
+
+            task_log!(worker, "expiring snapshots of ephemeral datastores");
+            if let Some(event_str) = schedule {
+                task_log!(worker, "task triggered by schedule '{event_str}'");
+            }
+
+            let result = expire_all_ephemeral_datastores(&worker);
+
+            let status = worker.create_state(&result);
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {err}", job.jobtype());
+            }
+
+            result
+        },
+    )?;
+    Ok(upid_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use pbs_api_types::DatastoreEphemeral;
+    use pbs_datastore::BackupDir;
+
+    // test directory below ./target/testout, removed again when dropped, also if the test fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let mut path: PathBuf = String::from("./target/testout").into();
+            path.push(std::module_path!());
+            path.push(name);
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn create_info(snapshot: &str, finished: bool) -> BackupInfo {
+        let mut files = vec![String::from("root.pxar.didx")];
+        if finished {
+            files.push(String::from(MANIFEST_BLOB_NAME));
+        }
+        BackupInfo {
+            backup_dir: BackupDir::new_test(snapshot.parse().unwrap()),
+            files,
+            protected: false,
+        }
+    }
+
+    fn touch(path: &Path, mtime: i64) {
+        std::fs::write(path, b"").unwrap();
+        let time = nix::sys::time::TimeVal::new(mtime, 0);
+        nix::sys::stat::utimes(path, &time, &time).unwrap();
+    }
+
+    #[test]
+    fn test_ttl_seconds() -> Result<(), Error> {
+        let ttl = |ttl: &str| {
+            DatastoreEphemeral {
+                ttl: ttl.to_string(),
+                remove_empty: None,
+            }
+            .ttl_seconds()
+        };
+
+        assert_eq!(ttl("3d")?, 3 * 86400);
+        assert_eq!(ttl("1w 2d")?, 9 * 86400);
+        assert_eq!(ttl("90min")?, 5400);
+        assert!(ttl("soon").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expiry_uses_arrival_time() {
+        let now = 1_700_000_000;
+        let day = 86400;
+
+        // an old backup time does not matter, only when the snapshot arrived
+        let info = create_info("host/elsa/2019-11-15T09:39:15Z", true);
+        assert!(!is_expired(&info, Some(now - day), 3 * day, now));
+        assert!(is_expired(&info, Some(now - 4 * day), 3 * day, now));
+
+        let unfinished = create_info("host/elsa/2019-11-15T09:39:15Z", false);
+        assert!(!is_expired(&unfinished, Some(now - 4 * day), 3 * day, now));
+
+        assert!(!is_expired(&info, None, 3 * day, now));
+    }
+
+    #[test]
+    fn test_arrival_time() {
+        let test_dir = TestDir::new("arrival_time");
+        let dir = &test_dir.0;
+
+        touch(&dir.join("root.pxar.didx"), 1000);
+        touch(&dir.join("catalog.pcat1.didx"), 1200);
+        touch(&dir.join(MANIFEST_BLOB_NAME), 5000);
+
+        let files = vec![
+            String::from("root.pxar.didx"),
+            String::from("catalog.pcat1.didx"),
+            String::from(MANIFEST_BLOB_NAME),
+        ];
+        // a rewritten manifest does not extend the lifetime
+        assert_eq!(arrival_time(dir, &files), Some(1200));
+        assert_eq!(
+            arrival_time(dir, &[String::from(MANIFEST_BLOB_NAME)]),
+            Some(5000)
+        );
+        assert_eq!(arrival_time(dir, &[String::from("missing.blob")]), None);
+    }
+}
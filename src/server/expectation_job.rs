@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, format_err, Error};
+use const_format::concatcp;
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn};
+
+use pbs_api_types::{Authid, BackupExpectationConfig, BackupNamespace, Operation};
+use pbs_datastore::DataStore;
+use proxmox_rest_server::WorkerTask;
+
+use crate::server::jobstate::Job;
+
+/// Stale groups which were already notified about, so that a group going stale only causes a
+/// single notification.
+const EXPECTATION_STATE_FN: &str = concatcp!(
+    pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR,
+    "/expectation-stale.json"
+);
+
+/// A backup group which did not get a new snapshot in time.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StaleGroup {
+    /// The group, including its namespace
+    pub group: String,
+    /// Time of the newest finished snapshot, if any
+    pub last_backup: Option<i64>,
+}
+
+fn print_ns_and_group(ns: &BackupNamespace, group: &str) -> String {
+    if ns.is_root() {
+        group.to_string()
+    } else {
+        format!("{}/{}", ns.display_as_path(), group)
+    }
+}
+
+/// Check a single expectation, returns the stale groups.
+pub fn check_expectation(
+    expectation: &BackupExpectationConfig,
+    now: i64,
+) -> Result<Vec<StaleGroup>, Error> {
+    let max_age = expectation.max_age_seconds()?;
+    let ns = expectation.ns.clone().unwrap_or_default();
+    let datastore = DataStore::lookup_datastore(&expectation.store, Some(Operation::Read))?;
+
+    let mut stale = Vec::new();
+    let mut found = false;
+    for group in datastore.iter_backup_groups_ok(ns.clone())? {
+        if !expectation.matches(group.group()) {
+            continue;
+        }
+        found = true;
+
+        let last_backup = group
+            .last_backup(true)?
+            .map(|info| info.backup_dir.backup_time());
+        if last_backup.map_or(true, |time| now - time > max_age) {
+            stale.push(StaleGroup {
+                group: print_ns_and_group(&ns, &group.group().to_string()),
+                last_backup,
+            });
+        }
+    }
+
+    // an expected group which does not exist is stale as well
+    if !found {
+        if let (Some(ty), Some(id)) = (&expectation.backup_type, &expectation.backup_id) {
+            stale.push(StaleGroup {
+                group: print_ns_and_group(&ns, &format!("{ty}/{id}")),
+                last_backup: None,
+            });
+        }
+    }
+
+    Ok(stale)
+}
+
+/// Already notified stale groups, mapped to the time of their newest snapshot when notified.
+type NotifiedGroups = HashMap<String, Option<i64>>;
+
+fn notified_key(id: &str, store: &str, group: &str) -> String {
+    format!("{id}:{store}:{group}")
+}
+
+fn load_notified(path: &str) -> Result<NotifiedGroups, Error> {
+    match file_read_optional_string(path)? {
+        Some(data) => serde_json::from_str(&data)
+            .map_err(|err| format_err!("unable to parse '{path}' - {err}")),
+        None => Ok(NotifiedGroups::new()),
+    }
+}
+
+fn save_notified(notified: &NotifiedGroups) -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let options = CreateOptions::new()
+        .perm(nix::sys::stat::Mode::from_bits_truncate(0o644))
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    replace_file(
+        EXPECTATION_STATE_FN,
+        serde_json::to_string(notified)?.as_bytes(),
+        options,
+        false,
+    )
+}
+
+/// Returns the stale groups which were not notified about yet and records them as notified.
+///
+/// A group is notified about again if a snapshot was made in the meantime, but still not in
+/// time.
+fn filter_notified(
+    notified: &NotifiedGroups,
+    still_notified: &mut NotifiedGroups,
+    id: &str,
+    store: &str,
+    stale: Vec<StaleGroup>,
+) -> Vec<StaleGroup> {
+    let mut new_stale = Vec::new();
+    for group in stale {
+        let key = notified_key(id, store, &group.group);
+        if notified.get(&key) != Some(&group.last_backup) {
+            new_stale.push(group.clone());
+        }
+        still_notified.insert(key, group.last_backup);
+    }
+    new_stale
+}
+
+/// Keep the notified groups of an expectation which could not be checked.
+fn keep_notified(notified: &NotifiedGroups, still_notified: &mut NotifiedGroups, id: &str) {
+    let prefix = format!("{id}:");
+    for (key, last_backup) in notified {
+        if key.starts_with(&prefix) {
+            still_notified.insert(key.clone(), *last_backup);
+        }
+    }
+}
+
+fn check_all_expectations(worker: &WorkerTask) -> Result<(), Error> {
+    let (config, _digest) = pbs_config::expectation::config()?;
+    let expectations: Vec<BackupExpectationConfig> =
+        config.convert_to_typed_array("expectation")?;
+
+    let now = proxmox_time::epoch_i64();
+    let notified = load_notified(EXPECTATION_STATE_FN).unwrap_or_else(|err| {
+        task_warn!(worker, "{err}, notifying about all stale groups");
+        NotifiedGroups::new()
+    });
+    let mut still_notified = NotifiedGroups::new();
+    let mut errors = false;
+
+    for expectation in expectations {
+        if expectation.disable.unwrap_or(false) {
+            continue;
+        }
+        let id = &expectation.id;
+
+        let stale = match check_expectation(&expectation, now) {
+            Ok(stale) => stale,
+            Err(err) => {
+                task_warn!(worker, "expectation '{id}': check failed - {err}");
+                errors = true;
+                // e.g. the datastore is not available yet, do not notify again once it is
+                keep_notified(&notified, &mut still_notified, id);
+                continue;
+            }
+        };
+
+        if stale.is_empty() {
+            task_log!(worker, "expectation '{id}': ok");
+            continue;
+        }
+
+        for group in &stale {
+            task_warn!(
+                worker,
+                "expectation '{id}': no new snapshot of {} within {}",
+                group.group,
+                expectation.max_age,
+            );
+        }
+
+        let new_stale = filter_notified(
+            &notified,
+            &mut still_notified,
+            id,
+            &expectation.store,
+            stale,
+        );
+
+        if !new_stale.is_empty() {
+            if let Err(err) =
+                crate::server::send_backup_overdue_notification(&expectation, &new_stale)
+            {
+                task_warn!(worker, "sending notification failed - {err}");
+            }
+        }
+    }
+
+    save_notified(&still_notified)?;
+
+    if errors {
+        bail!("checking some expectations failed");
+    }
+
+    Ok(())
+}
+
+/// Check all backup expectations and notify about groups which went stale.
+pub fn do_expectation_check(
+    mut job: Job,
+    auth_id: &Authid,
+    schedule: Option<String>,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    let worker_type = job.jobtype().to_string();
+    let upid_str = WorkerTask::new_thread(
+        &worker_type,
+        None,
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            job.start(&worker.upid().to_string())?;
+
+            task_log!(worker, "checking backup expectations");
+            if let Some(event_str) = schedule {
+                task_log!(worker, "task triggered by schedule '{event_str}'");
+            }
+
+            let result = check_all_expectations(&worker);
+
+            let status = worker.create_state(&result);
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {err}", job.jobtype());
+            }
+
+            result
+        },
+    )?;
+    Ok(upid_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // test directory below ./target/testout, removed again when dropped, also if the test fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let mut path: PathBuf = String::from("./target/testout").into();
+            path.push(std::module_path!());
+            path.push(name);
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn stale(group: &str, last_backup: Option<i64>) -> StaleGroup {
+        StaleGroup {
+            group: group.to_string(),
+            last_backup,
+        }
+    }
+
+    fn groups(list: &[StaleGroup]) -> Vec<&str> {
+        list.iter().map(|group| group.group.as_str()).collect()
+    }
+
+    #[test]
+    fn test_filter_notified() {
+        let mut notified = NotifiedGroups::new();
+        notified.insert(notified_key("daily", "store1", "vm/100"), Some(1000));
+        notified.insert(notified_key("daily", "store1", "vm/101"), None);
+        notified.insert(notified_key("daily", "store1", "vm/102"), Some(1000));
+
+        let mut still_notified = NotifiedGroups::new();
+        let new_stale = filter_notified(
+            &notified,
+            &mut still_notified,
+            "daily",
+            "store1",
+            vec![
+                stale("vm/100", Some(1000)), // unchanged
+                stale("vm/101", None),       // still missing
+                stale("vm/102", Some(2000)), // newer, but still too old snapshot
+                stale("vm/103", Some(1000)), // newly stale
+            ],
+        );
+        assert_eq!(groups(&new_stale), ["vm/102", "vm/103"]);
+        assert_eq!(still_notified.len(), 4);
+        assert_eq!(
+            still_notified.get(&notified_key("daily", "store1", "vm/102")),
+            Some(&Some(2000)),
+        );
+
+        // once a group is fine again, it is notified about again when it goes stale
+        let notified = still_notified;
+        let mut still_notified = NotifiedGroups::new();
+        let new_stale = filter_notified(
+            &notified,
+            &mut still_notified,
+            "daily",
+            "store1",
+            vec![stale("vm/101", None)],
+        );
+        assert!(new_stale.is_empty());
+        let notified = still_notified;
+        let new_stale = filter_notified(
+            &notified,
+            &mut NotifiedGroups::new(),
+            "daily",
+            "store1",
+            vec![stale("vm/100", Some(1000))],
+        );
+        assert_eq!(groups(&new_stale), ["vm/100"]);
+    }
+
+    #[test]
+    fn test_keep_notified() {
+        let mut notified = NotifiedGroups::new();
+        notified.insert(notified_key("daily", "store1", "vm/100"), Some(1000));
+        notified.insert(notified_key("daily2", "store1", "vm/100"), Some(1000));
+
+        let mut still_notified = NotifiedGroups::new();
+        keep_notified(&notified, &mut still_notified, "daily");
+        assert_eq!(still_notified.len(), 1);
+
+        // after the check works again, the group is not notified about a second time
+        let new_stale = filter_notified(
+            &still_notified,
+            &mut NotifiedGroups::new(),
+            "daily",
+            "store1",
+            vec![stale("vm/100", Some(1000))],
+        );
+        assert!(new_stale.is_empty());
+    }
+
+    #[test]
+    fn test_load_notified() -> Result<(), Error> {
+        let test_dir = TestDir::new("load_notified");
+        let path = test_dir.0.join("notified.json");
+        let path = path.to_str().unwrap();
+
+        // no state yet
+        assert!(load_notified(path)?.is_empty());
+
+        let mut notified = NotifiedGroups::new();
+        notified.insert(notified_key("daily", "store1", "vm/100"), Some(1000));
+        notified.insert(notified_key("daily", "store1", "vm/101"), None);
+        std::fs::write(path, serde_json::to_string(&notified)?)?;
+        assert_eq!(load_notified(path)?, notified);
+
+        std::fs::write(path, "{ broken")?;
+        assert!(load_notified(path).is_err());
+
+        Ok(())
+    }
+}
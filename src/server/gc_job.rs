@@ -3,10 +3,11 @@ use std::sync::Arc;
 
 use proxmox_sys::task_log;
 
-use pbs_api_types::Authid;
+use pbs_api_types::{Authid, HookJobType};
 use pbs_datastore::DataStore;
 use proxmox_rest_server::WorkerTask;
 
+use crate::server::hooks::{with_hooks, HookContext};
 use crate::server::{jobstate::Job, send_gc_status};
 
 /// Runs a garbage collection job.
@@ -20,6 +21,7 @@ pub fn do_garbage_collection_job(
     let store = datastore.name().to_string();
 
     let worker_type = job.jobtype().to_string();
+    job.set_store(&store);
     let upid_str = WorkerTask::new_thread(
         &worker_type,
         Some(store.clone()),
@@ -33,7 +35,15 @@ pub fn do_garbage_collection_job(
                 task_log!(worker, "task triggered by schedule '{event_str}'");
             }
 
-            let result = datastore.garbage_collection(&*worker, worker.upid());
+            let hook_ctx = HookContext::new(
+                HookJobType::GarbageCollection,
+                &store,
+                worker.upid().to_string(),
+                serde_json::json!({ "job-id": job.jobname() }),
+            );
+            let result = with_hooks(&*worker, &hook_ctx, || {
+                datastore.garbage_collection(&*worker, worker.upid())
+            });
 
             let status = worker.create_state(&result);
 
@@ -42,7 +52,8 @@ pub fn do_garbage_collection_job(
             }
 
             let gc_status = datastore.last_gc_status();
-            if let Err(err) = send_gc_status(&store, &gc_status, &result) {
+            let upid = worker.upid().to_string();
+            if let Err(err) = send_gc_status(&store, &upid, &gc_status, &result) {
                 eprintln!("send gc notification failed: {err}");
             }
 
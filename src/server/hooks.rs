@@ -0,0 +1,334 @@
+//! Server side hook scripts
+//!
+//! Hooks configured in `/etc/proxmox-backup/hooks.cfg` are run before
+//! and after backup, sync, garbage collection and prune tasks. The task
+//! metadata is passed as JSON on stdin, the output of the script is
+//! copied into the task log.
+
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use serde_json::{json, Value};
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{HookConfig, HookJobType, HookPhase, HOOK_DEFAULT_TIMEOUT};
+
+/// Maximum output of a hook script copied into the task log, per stream (stdout and stderr)
+const HOOK_MAX_OUTPUT: usize = 64 * 1024;
+
+/// How long to wait for the output pipes to be closed after the script exited, children of the
+/// script may keep them open
+const HOOK_OUTPUT_GRACE: Duration = Duration::from_secs(5);
+
+/// Metadata of the task a hook is run for
+pub struct HookContext {
+    pub job_type: HookJobType,
+    pub store: String,
+    pub upid: String,
+    /// Additional, task type specific properties (e.g. job id, namespace)
+    pub info: Value,
+}
+
+impl HookContext {
+    pub fn new(job_type: HookJobType, store: &str, upid: String, info: Value) -> Self {
+        Self {
+            job_type,
+            store: store.to_string(),
+            upid,
+            info,
+        }
+    }
+
+    fn hook_data(&self, phase: HookPhase, result: Option<&Result<(), String>>) -> Value {
+        let mut data = json!({
+            "phase": phase,
+            "job-type": self.job_type,
+            "store": self.store,
+            "upid": self.upid,
+        });
+
+        if let Value::Object(ref info) = self.info {
+            for (key, value) in info {
+                data[key] = value.clone();
+            }
+        }
+
+        if let Some(result) = result {
+            match result {
+                Ok(()) => data["status"] = "ok".into(),
+                Err(err) => {
+                    data["status"] = "error".into();
+                    data["error"] = err.clone().into();
+                }
+            }
+        }
+
+        data
+    }
+}
+
+fn lookup_hooks(job_type: HookJobType, store: &str) -> Result<Vec<HookConfig>, Error> {
+    let (config, _digest) = pbs_config::hook::config()?;
+    let mut hooks: Vec<HookConfig> = config.convert_to_typed_array("hook")?;
+    hooks.retain(|hook| hook.applies_to(job_type, store));
+    hooks.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(hooks)
+}
+
+// only run scripts nobody but root can modify, they are run with the
+// privileges of the server
+fn check_script(path: &str) -> Result<(), Error> {
+    let metadata =
+        std::fs::metadata(path).map_err(|err| format_err!("unable to stat {path:?} - {err}"))?;
+    if !metadata.is_file() {
+        bail!("{path:?} is not a regular file");
+    }
+    if metadata.uid() != 0 {
+        bail!("{path:?} is not owned by root");
+    }
+    if metadata.mode() & 0o022 != 0 {
+        bail!("{path:?} is writable by group or others");
+    }
+    if metadata.mode() & 0o111 == 0 {
+        bail!("{path:?} is not executable");
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct PipeOutput {
+    data: Vec<u8>,
+    /// More than the limit was written, the rest got discarded
+    truncated: bool,
+}
+
+/// Reads the output of a script in the background, so that the script cannot block on a full
+/// pipe. Only the first `limit` bytes are kept.
+struct PipeReader {
+    output: Arc<Mutex<PipeOutput>>,
+    closed: Receiver<()>,
+}
+
+impl PipeReader {
+    fn new<R: Read + Send + 'static>(pipe: Option<R>, limit: usize) -> Self {
+        let output = Arc::new(Mutex::new(PipeOutput::default()));
+        let (sender, closed) = channel();
+
+        let thread_output = Arc::clone(&output);
+        std::thread::spawn(move || {
+            if let Some(mut pipe) = pipe {
+                let mut buf = [0u8; 4096];
+                loop {
+                    let len = match pipe.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(len) => len,
+                        Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                        Err(_) => break,
+                    };
+                    let mut output = thread_output.lock().unwrap();
+                    let room = limit.saturating_sub(output.data.len());
+                    output.data.extend_from_slice(&buf[..len.min(room)]);
+                    output.truncated |= len > room;
+                }
+            }
+            let _ = sender.send(());
+        });
+
+        Self { output, closed }
+    }
+
+    /// Returns the output read so far, after waiting until `deadline` at most for the pipe to be
+    /// closed, and whether it was.
+    fn finish(self, deadline: Instant) -> (PipeOutput, bool) {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let closed = self.closed.recv_timeout(timeout).is_ok();
+        let output = std::mem::take(&mut *self.output.lock().unwrap());
+        (output, closed)
+    }
+}
+
+fn run_hook(worker: &dyn WorkerTaskContext, hook: &HookConfig, data: &Value) -> Result<(), Error> {
+    check_script(&hook.script)?;
+
+    let mut child = Command::new(&hook.script)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format_err!("unable to execute {:?} - {err}", hook.script))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // ignore errors, the script does not need to read its input
+        let _ = stdin.write_all(serde_json::to_string(data)?.as_bytes());
+    }
+
+    let stdout = PipeReader::new(child.stdout.take(), HOOK_MAX_OUTPUT);
+    let stderr = PipeReader::new(child.stderr.take(), HOOK_MAX_OUTPUT);
+
+    let timeout = Duration::from_secs(hook.timeout.unwrap_or(HOOK_DEFAULT_TIMEOUT));
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        let abort_reason = if start.elapsed() >= timeout {
+            Some(format!("timed out after {} seconds", timeout.as_secs()))
+        } else if let Err(err) = worker.check_abort() {
+            Some(err.to_string())
+        } else {
+            None
+        };
+        if let Some(reason) = abort_reason {
+            let _ = child.kill();
+            let _ = child.wait();
+            // the output is not waited for, children of the script may keep the pipes open
+            bail!("hook '{}' killed - {reason}", hook.id);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+
+    let deadline = Instant::now() + HOOK_OUTPUT_GRACE;
+    let (stdout, stdout_closed) = stdout.finish(deadline);
+    let (stderr, stderr_closed) = stderr.finish(deadline);
+
+    for line in String::from_utf8_lossy(&stdout.data).lines() {
+        task_log!(worker, "hook '{}': {line}", hook.id);
+    }
+    for line in String::from_utf8_lossy(&stderr.data).lines() {
+        task_warn!(worker, "hook '{}': {line}", hook.id);
+    }
+    if stdout.truncated || stderr.truncated {
+        task_warn!(
+            worker,
+            "hook '{}': output truncated to {HOOK_MAX_OUTPUT} bytes",
+            hook.id
+        );
+    }
+    if !stdout_closed || !stderr_closed {
+        task_warn!(
+            worker,
+            "hook '{}': output still open after the script exited, ignoring further output",
+            hook.id
+        );
+    }
+
+    if !status.success() {
+        match status.code() {
+            Some(code) => bail!("hook '{}' failed with exit code {code}", hook.id),
+            None => bail!("hook '{}' terminated by signal", hook.id),
+        }
+    }
+
+    Ok(())
+}
+
+/// Run all pre hooks of a task.
+///
+/// Returns an error if a hook fails, the task must not be started then.
+pub fn run_pre_hooks(worker: &dyn WorkerTaskContext, ctx: &HookContext) -> Result<(), Error> {
+    let hooks = lookup_hooks(ctx.job_type, &ctx.store)?;
+    if hooks.is_empty() {
+        return Ok(());
+    }
+
+    let data = ctx.hook_data(HookPhase::Pre, None);
+    for hook in hooks.iter() {
+        task_log!(worker, "run pre hook '{}'", hook.id);
+        run_hook(worker, hook, &data)?;
+    }
+
+    Ok(())
+}
+
+/// Run all post hooks of a task.
+///
+/// Failing post hooks are only logged as warnings.
+pub fn run_post_hooks<T>(
+    worker: &dyn WorkerTaskContext,
+    ctx: &HookContext,
+    result: &Result<T, Error>,
+) {
+    let hooks = match lookup_hooks(ctx.job_type, &ctx.store) {
+        Ok(hooks) => hooks,
+        Err(err) => {
+            task_warn!(worker, "unable to load hook config - {err}");
+            return;
+        }
+    };
+    if hooks.is_empty() {
+        return;
+    }
+
+    let result = match result {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    };
+    let data = ctx.hook_data(HookPhase::Post, Some(&result));
+    for hook in hooks.iter() {
+        task_log!(worker, "run post hook '{}'", hook.id);
+        if let Err(err) = run_hook(worker, hook, &data) {
+            task_warn!(worker, "{err}");
+        }
+    }
+}
+
+/// Run `func` between the pre and post hooks of a task.
+pub fn with_hooks<T, F>(
+    worker: &dyn WorkerTaskContext,
+    ctx: &HookContext,
+    func: F,
+) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error>,
+{
+    let result = run_pre_hooks(worker, ctx).and_then(|_| func());
+    run_post_hooks(worker, ctx, &result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    #[test]
+    fn test_pipe_reader_limit() {
+        let data = vec![b'x'; 10000];
+        let reader = PipeReader::new(Some(std::io::Cursor::new(data)), 4096);
+        let (output, closed) = reader.finish(Instant::now() + Duration::from_secs(10));
+        assert!(closed);
+        assert_eq!(output.data.len(), 4096);
+        assert!(output.truncated);
+
+        let reader = PipeReader::new(Some(&b"short output\n"[..]), 4096);
+        let (output, closed) = reader.finish(Instant::now() + Duration::from_secs(10));
+        assert!(closed);
+        assert_eq!(output.data, b"short output\n");
+        assert!(!output.truncated);
+    }
+
+    #[test]
+    fn test_pipe_reader_kept_open() {
+        // e.g. a daemon started by the script inherited the pipe
+        let (mut writer, reader) = UnixStream::pair().unwrap();
+        writer.write_all(b"started\n").unwrap();
+
+        let reader = PipeReader::new(Some(reader), 4096);
+        // give the reader a moment to pick up the data written so far
+        std::thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        let (output, closed) = reader.finish(Instant::now() + Duration::from_millis(200));
+        assert!(!closed);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(output.data, b"started\n");
+
+        drop(writer);
+    }
+}
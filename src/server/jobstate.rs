@@ -46,9 +46,12 @@ use proxmox_sys::fs::{create_path, file_read_optional_string, replace_file, Crea
 
 use proxmox_time::CalendarEvent;
 
-use pbs_api_types::{JobScheduleStatus, UPID};
+use pbs_api_types::{
+    JobHistoryEntry, JobResult, JobScheduleStatus, PruneJobConfig, SyncJobConfig,
+    TapeBackupJobConfig, VerificationJobConfig, UPID,
+};
 use pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR_M;
-use pbs_config::{open_backup_lockfile, BackupLockGuard};
+use pbs_config::{open_backup_lockfile, BackupLockGuard, ConfigWriteMode};
 
 use proxmox_rest_server::{upid_read_status, worker_is_active_local, TaskState};
 
@@ -74,11 +77,16 @@ pub struct Job {
     jobname: String,
     /// The State of the job
     pub state: JobState,
+    // the datastore the job runs on, recorded in its history
+    store: Option<String>,
     _lock: BackupLockGuard,
 }
 
 const JOB_STATE_BASEDIR: &str = concat!(PROXMOX_BACKUP_STATE_DIR_M!(), "/jobstates");
 
+/// Number of finished runs kept in the history of each job
+const JOB_HISTORY_MAX_ENTRIES: usize = 100;
+
 /// Create jobstate stat dir with correct permission
 pub fn create_jobstate_dir() -> Result<(), Error> {
     let backup_user = pbs_config::backup_user()?;
@@ -99,6 +107,12 @@ fn get_path(jobtype: &str, jobname: &str) -> PathBuf {
     path
 }
 
+fn get_history_path(jobtype: &str, jobname: &str) -> PathBuf {
+    let mut path = PathBuf::from(JOB_STATE_BASEDIR);
+    path.push(format!("{jobtype}-{jobname}.history"));
+    path
+}
+
 fn get_lock<P>(path: P) -> Result<BackupLockGuard, Error>
 where
     P: AsRef<Path>,
@@ -110,6 +124,9 @@ where
 
 /// Removes the statefile of a job, this is useful if we delete a job
 pub fn remove_state_file(jobtype: &str, jobname: &str) -> Result<(), Error> {
+    if pbs_config::config_write_mode() == ConfigWriteMode::DryRun {
+        return Ok(());
+    }
     let mut path = get_path(jobtype, jobname);
     let _lock = get_lock(&path)?;
     if let Err(err) = std::fs::remove_file(&path) {
@@ -117,6 +134,11 @@ pub fn remove_state_file(jobtype: &str, jobname: &str) -> Result<(), Error> {
             bail!("cannot remove statefile for {jobtype} - {jobname}: {err}");
         }
     }
+    if let Err(err) = std::fs::remove_file(get_history_path(jobtype, jobname)) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            bail!("cannot remove history for {jobtype} - {jobname}: {err}");
+        }
+    }
     path.set_extension("lck");
     if let Err(err) = std::fs::remove_file(&path) {
         if err.kind() != std::io::ErrorKind::NotFound {
@@ -126,9 +148,115 @@ pub fn remove_state_file(jobtype: &str, jobname: &str) -> Result<(), Error> {
     Ok(())
 }
 
+// the datastore a job runs on, looked up in the job configuration
+fn job_store(jobtype: &str, jobname: &str) -> Option<String> {
+    match jobtype {
+        "garbage_collection" => Some(jobname.to_string()),
+        "syncjob" => {
+            let (config, _) = pbs_config::sync::config().ok()?;
+            let job: SyncJobConfig = config.lookup("sync", jobname).ok()?;
+            Some(job.store)
+        }
+        "verificationjob" => {
+            let (config, _) = pbs_config::verify::config().ok()?;
+            let job: VerificationJobConfig = config.lookup("verification", jobname).ok()?;
+            Some(job.store)
+        }
+        "prunejob" => {
+            let (config, _) = pbs_config::prune::config().ok()?;
+            let job: PruneJobConfig = config.lookup("prune", jobname).ok()?;
+            Some(job.store)
+        }
+        "tape-backup-job" => {
+            let (config, _) = pbs_config::tape_job::config().ok()?;
+            let job: TapeBackupJobConfig = config.lookup("backup", jobname).ok()?;
+            Some(job.setup.store)
+        }
+        _ => None,
+    }
+}
+
+fn history_entry(
+    jobtype: &str,
+    jobname: &str,
+    store: Option<String>,
+    upid: &str,
+    state: &TaskState,
+) -> Result<JobHistoryEntry, Error> {
+    let parsed: UPID = upid
+        .parse()
+        .map_err(|err| format_err!("error parsing upid: {err}"))?;
+
+    let (result, message) = match state {
+        TaskState::OK { .. } => (JobResult::Ok, None),
+        TaskState::Warning { .. } => (JobResult::Warning, Some(state.to_string())),
+        TaskState::Error { .. } => (JobResult::Error, Some(state.to_string())),
+        TaskState::Unknown { .. } => (JobResult::Unknown, None),
+    };
+
+    Ok(JobHistoryEntry {
+        job_type: jobtype.to_string(),
+        job_id: jobname.to_string(),
+        store,
+        upid: upid.to_string(),
+        starttime: parsed.starttime,
+        endtime: state.endtime(),
+        result,
+        message,
+    })
+}
+
+// one JSON entry per line, keeping the last JOB_HISTORY_MAX_ENTRIES entries
+fn format_history(history: &[JobHistoryEntry]) -> Result<String, Error> {
+    let skip = history.len().saturating_sub(JOB_HISTORY_MAX_ENTRIES);
+
+    let mut data = String::new();
+    for entry in &history[skip..] {
+        data.push_str(&serde_json::to_string(entry)?);
+        data.push('\n');
+    }
+    Ok(data)
+}
+
+fn parse_history(data: &str) -> Vec<JobHistoryEntry> {
+    // skip broken lines, e.g. from an interrupted write
+    data.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Returns the history of finished runs of a job, oldest first
+pub fn read_job_history(jobtype: &str, jobname: &str) -> Result<Vec<JobHistoryEntry>, Error> {
+    Ok(
+        file_read_optional_string(get_history_path(jobtype, jobname))?
+            .map(|data| parse_history(&data))
+            .unwrap_or_default(),
+    )
+}
+
+/// Returns the history of finished runs of all jobs
+pub fn read_all_job_history() -> Result<Vec<JobHistoryEntry>, Error> {
+    let mut list = Vec::new();
+
+    for entry in std::fs::read_dir(JOB_STATE_BASEDIR)? {
+        let path = entry?.path();
+        if path.extension() != Some(std::ffi::OsStr::new("history")) {
+            continue;
+        }
+        if let Some(data) = file_read_optional_string(&path)? {
+            list.extend(parse_history(&data));
+        }
+    }
+
+    Ok(list)
+}
+
 /// Creates the statefile with the state 'Created'
 /// overwrites if it exists already
 pub fn create_state_file(jobtype: &str, jobname: &str) -> Result<(), Error> {
+    if pbs_config::config_write_mode() == ConfigWriteMode::DryRun {
+        return Ok(());
+    }
     let mut job = Job::new(jobtype, jobname)?;
     job.write_state()
 }
@@ -137,6 +265,9 @@ pub fn create_state_file(jobtype: &str, jobname: &str) -> Result<(), Error> {
 /// if the job is currently running, does nothing.
 /// Intended for use when the schedule changes.
 pub fn update_job_last_run_time(jobtype: &str, jobname: &str) -> Result<(), Error> {
+    if pbs_config::config_write_mode() == ConfigWriteMode::DryRun {
+        return Ok(());
+    }
     let mut job = match Job::new(jobtype, jobname) {
         Ok(job) => job,
         Err(_) => return Ok(()), // was locked (running), so do not update
@@ -219,6 +350,16 @@ impl JobState {
             })
         }
     }
+
+    /// Resets the last run time of a finished job to `time`, so the schedule is computed from
+    /// there.
+    fn requeue(&mut self, time: i64) -> Result<(), Error> {
+        match self {
+            JobState::Finished { updated, .. } => *updated = Some(time),
+            _ => bail!("cannot requeue job which is not finished"),
+        }
+        Ok(())
+    }
 }
 
 impl Job {
@@ -238,10 +379,18 @@ impl Job {
             state: JobState::Created {
                 time: proxmox_time::epoch_i64(),
             },
+            store: None,
             _lock,
         })
     }
 
+    /// Set the datastore the job runs on, as recorded in the history of its runs
+    ///
+    /// Without this, the datastore is looked up in the job configuration when the job finishes.
+    pub fn set_store(&mut self, store: &str) {
+        self.store = Some(store.to_string());
+    }
+
     /// Start the job and update the statefile accordingly
     /// Fails if the job was already started
     pub fn start(&mut self, upid: &str) -> Result<(), Error> {
@@ -266,6 +415,14 @@ impl Job {
         }
         .to_string();
 
+        if let Err(err) = self.append_history(&upid, &state) {
+            log::warn!(
+                "could not update history of {} - {}: {err}",
+                self.jobtype,
+                self.jobname
+            );
+        }
+
         self.state = JobState::Finished {
             upid,
             state,
@@ -275,6 +432,15 @@ impl Job {
         self.write_state()
     }
 
+    /// Mark the finished job as due again, by resetting its last run time to `time`
+    ///
+    /// Used to re-run a job as soon as possible, e.g. after it could not process everything
+    /// because its datastore became unavailable.
+    pub fn requeue(&mut self, time: i64) -> Result<(), Error> {
+        self.state.requeue(time)?;
+        self.write_state()
+    }
+
     pub fn jobtype(&self) -> &str {
         &self.jobtype
     }
@@ -283,6 +449,32 @@ impl Job {
         &self.jobname
     }
 
+    fn append_history(&self, upid: &str, state: &TaskState) -> Result<(), Error> {
+        let store = match &self.store {
+            Some(store) => Some(store.clone()),
+            None => job_store(&self.jobtype, &self.jobname),
+        };
+        let entry = history_entry(&self.jobtype, &self.jobname, store, upid, state)?;
+
+        let mut history = read_job_history(&self.jobtype, &self.jobname)?;
+        history.push(entry);
+        let data = format_history(&history)?;
+
+        let backup_user = pbs_config::backup_user()?;
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
+        let options = CreateOptions::new()
+            .perm(mode)
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+
+        replace_file(
+            get_history_path(&self.jobtype, &self.jobname),
+            data.as_bytes(),
+            options,
+            false,
+        )
+    }
+
     fn write_state(&mut self) -> Result<(), Error> {
         let serialized = serde_json::to_string(&self.state)?;
         let path = get_path(&self.jobtype, &self.jobname);
@@ -341,3 +533,173 @@ pub fn compute_schedule_status(
 
     Ok(status)
 }
+
+/// Delay of the scheduled run at `event_time` of a job with a `splay` of up to
+/// this many seconds.
+///
+/// The delay is derived from the job and the event time, so it stays the same
+/// if the scheduler restarts, but differs between jobs sharing a schedule.
+pub fn splay_delay(jobtype: &str, jobname: &str, event_time: i64, splay: Option<u64>) -> i64 {
+    let splay = match splay {
+        Some(splay) if splay > 0 => splay,
+        _ => return 0,
+    };
+
+    let digest = openssl::sha::sha256(format!("{jobtype}:{jobname}:{event_time}").as_bytes());
+    let value = u64::from_le_bytes(digest[..8].try_into().unwrap());
+    (value % (splay + 1)) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    const UPID: &str = "UPID:elsa:00004F37:0039E469:00000000:5CA78B83:syncjob::root@pam:";
+
+    fn entry(state: &TaskState) -> JobHistoryEntry {
+        history_entry("syncjob", "s-1", Some("store1".to_string()), UPID, state).unwrap()
+    }
+
+    #[test]
+    fn test_history_entry() {
+        let ok = entry(&TaskState::OK {
+            endtime: 1554483200,
+        });
+        assert_eq!(ok.job_type, "syncjob");
+        assert_eq!(ok.job_id, "s-1");
+        assert_eq!(ok.store.as_deref(), Some("store1"));
+        assert_eq!(ok.starttime, 0x5CA78B83);
+        assert_eq!(ok.endtime, 1554483200);
+        assert_eq!(ok.result, JobResult::Ok);
+        assert_eq!(ok.message, None);
+
+        let warning = entry(&TaskState::Warning {
+            count: 2,
+            endtime: 1554483200,
+        });
+        assert_eq!(warning.result, JobResult::Warning);
+        assert!(warning.message.is_some());
+
+        let error = entry(&TaskState::Error {
+            message: "remote unreachable".to_string(),
+            endtime: 1554483200,
+        });
+        assert_eq!(error.result, JobResult::Error);
+        assert!(error.message.unwrap().contains("remote unreachable"));
+
+        assert!(history_entry(
+            "syncjob",
+            "s-1",
+            None,
+            "not-a-upid",
+            &TaskState::OK { endtime: 0 }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_history_roundtrip() {
+        let history: Vec<JobHistoryEntry> = (0..3)
+            .map(|i| {
+                entry(&TaskState::OK {
+                    endtime: 1554483200 + i,
+                })
+            })
+            .collect();
+
+        let data = format_history(&history).unwrap();
+        assert_eq!(data.lines().count(), 3);
+        assert_eq!(parse_history(&data), history);
+    }
+
+    #[test]
+    fn test_history_skips_broken_lines() {
+        let history = vec![entry(&TaskState::OK {
+            endtime: 1554483200,
+        })];
+        let mut data = format_history(&history).unwrap();
+        // e.g. an interrupted write
+        data.insert_str(0, "{\"job-type\": \"syncjob\"\n\ngarbage\n");
+        data.push_str("{\"job-type\":");
+
+        assert_eq!(parse_history(&data), history);
+    }
+
+    #[test]
+    fn test_requeue() {
+        let mut state = JobState::Finished {
+            upid: UPID.to_string(),
+            state: TaskState::OK {
+                endtime: 1554483200,
+            },
+            updated: None,
+        };
+
+        // schedule every 15 minutes, independent of the local time zone
+        let status = compute_schedule_status(&state, Some("*:0/15")).unwrap();
+        assert_eq!(status.next_run, Some(1554483600));
+
+        // requeued to the previous run, it is due at that run's next event
+        state.requeue(1554422400 - 60).unwrap();
+        let status = compute_schedule_status(&state, Some("*:0/15")).unwrap();
+        assert_eq!(status.next_run, Some(1554422400));
+        assert_eq!(status.last_run_endtime, Some(1554483200));
+
+        let mut created = JobState::Created { time: 0 };
+        assert!(created.requeue(1).is_err());
+        let mut started = JobState::Started {
+            upid: UPID.to_string(),
+        };
+        assert!(started.requeue(1).is_err());
+    }
+
+    #[test]
+    fn test_splay_delay() {
+        let event = 1554483600;
+
+        // no or a zero splay does not delay the job
+        assert_eq!(splay_delay("syncjob", "s-1", event, None), 0);
+        assert_eq!(splay_delay("syncjob", "s-1", event, Some(0)), 0);
+
+        // stable for the same job and event, e.g. across scheduler restarts
+        let delay = splay_delay("syncjob", "s-1", event, Some(3600));
+        assert_eq!(splay_delay("syncjob", "s-1", event, Some(3600)), delay);
+
+        // always within the splay, including its upper bound
+        for id in 0..1000 {
+            let delay = splay_delay("syncjob", &format!("s-{id}"), event, Some(60));
+            assert!((0..=60).contains(&delay));
+        }
+        for event in event..event + 100 {
+            assert!((0..=1).contains(&splay_delay("syncjob", "s-1", event, Some(1))));
+        }
+
+        // jobs sharing a schedule are spread out
+        let delays: HashSet<i64> = (0..100)
+            .map(|id| splay_delay("syncjob", &format!("s-{id}"), event, Some(3600)))
+            .collect();
+        assert!(delays.len() > 50);
+
+        // the job type is part of the identity
+        let types: HashSet<i64> = ["syncjob", "prunejob", "verificationjob", "tape-backup-job"]
+            .iter()
+            .map(|jobtype| splay_delay(jobtype, "s-1", event, Some(u32::MAX as u64)))
+            .collect();
+        assert!(types.len() > 1);
+    }
+
+    #[test]
+    fn test_history_max_entries() {
+        let history: Vec<JobHistoryEntry> = (0..JOB_HISTORY_MAX_ENTRIES as i64 + 5)
+            .map(|i| entry(&TaskState::OK { endtime: i }))
+            .collect();
+
+        let parsed = parse_history(&format_history(&history).unwrap());
+        assert_eq!(parsed.len(), JOB_HISTORY_MAX_ENTRIES);
+        // the oldest entries are dropped
+        assert_eq!(parsed[0].endtime, 5);
+        assert_eq!(parsed.last(), history.last());
+    }
+}
@@ -0,0 +1,427 @@
+//! Forwarding of task results to remote log collectors
+//!
+//! Log forwarders configured in `/etc/proxmox-backup/log-forward.cfg` get
+//! a structured event for each finished backup, sync, garbage collection,
+//! prune, verification and tape backup task, either as syslog message (UDP
+//! or TCP) or as HTTP POST request, formatted as JSON or CEF for ingestion
+//! into a SIEM.
+//!
+//! Jobs which send notifications get their results forwarded by the
+//! notification functions in [`crate::server::notifications`], tasks without
+//! notifications (backup sessions, manual prune and pull) call
+//! [`forward_task_result`] directly.
+
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use hyper::{Body, Request};
+use serde_json::{json, Value};
+
+use pbs_api_types::{
+    LogForwardConfig, LogForwardEventType, LogForwardFormat, LogForwardProtocol, UPID,
+};
+
+use crate::server::hooks::HookContext;
+use crate::tools::pbs_simple_http;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SYSLOG_PORT: u16 = 514;
+
+// facility 'daemon'
+const SYSLOG_FACILITY: u8 = 3;
+const SYSLOG_SEVERITY_ERR: u8 = 3;
+const SYSLOG_SEVERITY_INFO: u8 = 6;
+
+struct TaskEvent {
+    data: Value,
+    success: bool,
+}
+
+impl TaskEvent {
+    fn new(
+        event_type: LogForwardEventType,
+        store: &str,
+        upid: &str,
+        info: &Value,
+        result: &Result<(), String>,
+        bytes: Option<u64>,
+    ) -> Self {
+        let end_time = proxmox_time::epoch_i64();
+        let start_time = upid
+            .parse::<UPID>()
+            .map(|upid| upid.starttime)
+            .unwrap_or(end_time);
+
+        let mut data = json!({
+            "event": event_type,
+            "node": proxmox_sys::nodename(),
+            "store": store,
+            "upid": upid,
+            "start-time": start_time,
+            "end-time": end_time,
+            "duration": end_time - start_time,
+        });
+
+        if let Value::Object(info) = info {
+            for (key, value) in info {
+                data[key] = value.clone();
+            }
+        }
+
+        if let Some(bytes) = bytes {
+            data["bytes"] = bytes.into();
+        }
+
+        match result {
+            Ok(()) => data["status"] = "ok".into(),
+            Err(err) => {
+                data["status"] = "error".into();
+                data["error"] = err.clone().into();
+            }
+        }
+
+        Self {
+            data,
+            success: result.is_ok(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        self.data.to_string()
+    }
+
+    /// Format as ArcSight Common Event Format message.
+    fn to_cef(&self) -> String {
+        let data = &self.data;
+        let event = data["event"].as_str().unwrap_or("task");
+        let (name, severity) = if self.success {
+            (format!("{event} finished"), 3)
+        } else {
+            (format!("{event} failed"), 7)
+        };
+
+        let mut extension = Vec::new();
+        let mut add = |key: &str, value: &str| {
+            extension.push(format!("{key}={}", cef_escape_extension(value)));
+        };
+
+        // CEF timestamps are in milliseconds
+        let start = data["start-time"].as_i64().unwrap_or(0) * 1000;
+        let end = data["end-time"].as_i64().unwrap_or(0) * 1000;
+        add("rt", &end.to_string());
+        add("start", &start.to_string());
+        add("end", &end.to_string());
+        add("dvchost", data["node"].as_str().unwrap_or_default());
+        add("outcome", if self.success { "success" } else { "failure" });
+        add("cn1Label", "duration");
+        add("cn1", &data["duration"].to_string());
+        if let Some(bytes) = data["bytes"].as_u64() {
+            add("in", &bytes.to_string());
+        }
+
+        // custom string fields, numbered in order of appearance
+        let mut custom = 0;
+        for key in ["store", "upid", "job-id", "snapshot", "remote"] {
+            if let Some(value) = data[key].as_str() {
+                custom += 1;
+                add(&format!("cs{custom}Label"), key);
+                add(&format!("cs{custom}"), value);
+            }
+        }
+        if let Some(owner) = data["owner"].as_str() {
+            add("suser", owner);
+        }
+        if let Some(error) = data["error"].as_str() {
+            add("msg", error);
+        }
+
+        format!(
+            "CEF:0|Proxmox|Proxmox Backup Server|{}|{}|{}|{severity}|{}",
+            cef_escape_header(pbs_buildcfg::PROXMOX_PKG_VERSION),
+            cef_escape_header(event),
+            cef_escape_header(&name),
+            extension.join(" "),
+        )
+    }
+
+    fn format(&self, format: LogForwardFormat) -> String {
+        match format {
+            LogForwardFormat::Json => self.to_json(),
+            LogForwardFormat::Cef => self.to_cef(),
+        }
+    }
+
+    /// Format as RFC 5424 syslog message.
+    fn to_syslog(&self, format: LogForwardFormat) -> String {
+        let severity = if self.success {
+            SYSLOG_SEVERITY_INFO
+        } else {
+            SYSLOG_SEVERITY_ERR
+        };
+        let timestamp = proxmox_time::epoch_to_rfc3339_utc(proxmox_time::epoch_i64())
+            .unwrap_or_else(|_| "-".to_string());
+        format!(
+            "<{}>1 {timestamp} {} proxmox-backup - {} - {}",
+            SYSLOG_FACILITY * 8 + severity,
+            proxmox_sys::nodename(),
+            self.data["event"].as_str().unwrap_or("-"),
+            self.format(format),
+        )
+    }
+}
+
+fn cef_escape_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn cef_escape_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+// 'host[:port]', IPv6 addresses with port in brackets
+fn syslog_address(target: &str) -> Result<SocketAddr, Error> {
+    if let Ok(address) = target.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    let host = target.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, SYSLOG_PORT));
+    }
+
+    let mut addresses = if target.contains(':') {
+        target.to_socket_addrs()
+    } else {
+        (target, SYSLOG_PORT).to_socket_addrs()
+    }
+    .map_err(|err| format_err!("unable to resolve '{target}' - {err}"))?;
+
+    addresses
+        .next()
+        .ok_or_else(|| format_err!("unable to resolve '{target}'"))
+}
+
+fn send_syslog_udp(target: &str, message: &str) -> Result<(), Error> {
+    let address = syslog_address(target)?;
+    let bind = if address.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.send_to(message.as_bytes(), address)?;
+    Ok(())
+}
+
+fn send_syslog_tcp(target: &str, message: &str) -> Result<(), Error> {
+    let address = syslog_address(target)?;
+    let mut stream = TcpStream::connect_timeout(&address, SEND_TIMEOUT)?;
+    stream.set_write_timeout(Some(SEND_TIMEOUT))?;
+    // octet counting framing
+    stream.write_all(format!("{} {message}", message.len()).as_bytes())?;
+    Ok(())
+}
+
+async fn send_http(target: &str, format: LogForwardFormat, body: String) -> Result<(), Error> {
+    let proxy_config = match crate::config::node::config() {
+        Ok((node_config, _digest)) => node_config.http_proxy(),
+        Err(_) => None,
+    };
+    let client = pbs_simple_http(proxy_config);
+
+    let content_type = match format {
+        LogForwardFormat::Json => "application/json",
+        LogForwardFormat::Cef => "text/plain",
+    };
+
+    let request = Request::builder()
+        .method("POST")
+        .uri(target)
+        .header("Content-Type", content_type)
+        .body(Body::from(body))?;
+
+    let response = tokio::time::timeout(SEND_TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| format_err!("request timed out"))??;
+
+    let status = response.status();
+    if !status.is_success() {
+        bail!("server returned status {status}");
+    }
+
+    Ok(())
+}
+
+fn lookup_forwarders(
+    event_type: LogForwardEventType,
+    store: &str,
+    success: bool,
+) -> Result<Vec<LogForwardConfig>, Error> {
+    let (config, _digest) = pbs_config::log_forward::config()?;
+    let mut forwarders: Vec<LogForwardConfig> = config.convert_to_typed_array("log-forward")?;
+    forwarders.retain(|forwarder| forwarder.applies_to(event_type, store, success));
+    Ok(forwarders)
+}
+
+fn send_event(forwarder: &LogForwardConfig, event: &TaskEvent) -> Result<(), Error> {
+    let format = forwarder.format.unwrap_or_default();
+    match forwarder.protocol.unwrap_or_default() {
+        LogForwardProtocol::Udp => send_syslog_udp(&forwarder.target, &event.to_syslog(format)),
+        LogForwardProtocol::Tcp => send_syslog_tcp(&forwarder.target, &event.to_syslog(format)),
+        LogForwardProtocol::Http => proxmox_async::runtime::block_on(send_http(
+            &forwarder.target,
+            format,
+            event.format(format),
+        )),
+    }
+}
+
+/// Forward the result of a finished task with hooks to the configured collectors.
+///
+/// `bytes` is the amount of data processed by the task, if known.
+pub fn forward_task_result<T>(ctx: &HookContext, result: &Result<T, Error>, bytes: Option<u64>) {
+    let result = match result {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    };
+    forward_task_event(
+        ctx.job_type.into(),
+        &ctx.store,
+        &ctx.upid,
+        &ctx.info,
+        &result,
+        bytes,
+    );
+}
+
+/// Forward the result of a finished task to the configured collectors.
+///
+/// `info` holds additional, task type specific properties which are added to
+/// the event. Events are sent from a separate thread, so this never blocks the
+/// calling task.
+pub fn forward_task_event(
+    event_type: LogForwardEventType,
+    store: &str,
+    upid: &str,
+    info: &Value,
+    result: &Result<(), String>,
+    bytes: Option<u64>,
+) {
+    let forwarders = match lookup_forwarders(event_type, store, result.is_ok()) {
+        Ok(forwarders) => forwarders,
+        Err(err) => {
+            log::error!("unable to load log forwarding config - {err}");
+            return;
+        }
+    };
+    if forwarders.is_empty() {
+        return;
+    }
+
+    let event = TaskEvent::new(event_type, store, upid, info, result, bytes);
+
+    let spawn_result = std::thread::Builder::new()
+        .name("log-forward".to_string())
+        .spawn(move || {
+            for forwarder in forwarders {
+                if let Err(err) = send_event(&forwarder, &event) {
+                    log::warn!("log forwarder '{}': sending failed - {err}", forwarder.name);
+                }
+            }
+        });
+    if let Err(err) = spawn_result {
+        log::error!("unable to start log forwarding thread - {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_event(success: bool) -> TaskEvent {
+        let mut data = json!({
+            "event": "backup",
+            "node": "pbs1",
+            "store": "store1",
+            "upid": "UPID:pbs1:x",
+            "start-time": 100,
+            "end-time": 142,
+            "duration": 42,
+            "snapshot": "vm/100/2024-01-01T00:00:00Z",
+            "owner": "root@pam",
+            "bytes": 1024,
+        });
+        if success {
+            data["status"] = "ok".into();
+        } else {
+            data["status"] = "error".into();
+            data["error"] = "disk=full\nretry".into();
+        }
+        TaskEvent { data, success }
+    }
+
+    #[test]
+    fn test_cef_escape() {
+        assert_eq!(cef_escape_header(r"a|b\c"), r"a\|b\\c");
+        assert_eq!(cef_escape_header("a=b"), "a=b");
+        assert_eq!(cef_escape_extension(r"a=b\c|d"), r"a\=b\\c|d");
+        assert_eq!(cef_escape_extension("a\r\nb"), r"a\r\nb");
+    }
+
+    #[test]
+    fn test_to_cef() {
+        let cef = test_event(true).to_cef();
+        let header = format!(
+            "CEF:0|Proxmox|Proxmox Backup Server|{}|backup|backup finished|3|",
+            cef_escape_header(pbs_buildcfg::PROXMOX_PKG_VERSION),
+        );
+        assert!(cef.starts_with(&header), "unexpected header: {cef}");
+        let extension = &cef[header.len()..];
+        assert_eq!(
+            extension,
+            "rt=142000 start=100000 end=142000 dvchost=pbs1 outcome=success \
+             cn1Label=duration cn1=42 in=1024 cs1Label=store cs1=store1 \
+             cs2Label=upid cs2=UPID:pbs1:x cs3Label=snapshot \
+             cs3=vm/100/2024-01-01T00:00:00Z suser=root@pam",
+        );
+
+        let cef = test_event(false).to_cef();
+        assert!(cef.contains("|backup failed|7|"));
+        assert!(cef.contains(" outcome=failure "));
+        assert!(cef.ends_with(r" msg=disk\=full\nretry"));
+    }
+
+    #[test]
+    fn test_syslog_address() -> Result<(), Error> {
+        assert_eq!(
+            syslog_address("192.0.2.1")?,
+            "192.0.2.1:514".parse::<SocketAddr>()?,
+        );
+        assert_eq!(
+            syslog_address("192.0.2.1:6514")?,
+            "192.0.2.1:6514".parse::<SocketAddr>()?,
+        );
+        assert_eq!(
+            syslog_address("2001:db8::1")?,
+            "[2001:db8::1]:514".parse::<SocketAddr>()?,
+        );
+        assert_eq!(
+            syslog_address("[2001:db8::1]")?,
+            "[2001:db8::1]:514".parse::<SocketAddr>()?,
+        );
+        assert_eq!(
+            syslog_address("[2001:db8::1]:6514")?,
+            "[2001:db8::1]:6514".parse::<SocketAddr>()?,
+        );
+        assert_eq!(syslog_address("localhost:6514")?.port(), 6514);
+        assert_eq!(syslog_address("localhost")?.port(), SYSLOG_PORT);
+
+        Ok(())
+    }
+}
@@ -22,6 +22,12 @@ pub use prune_job::*;
 mod gc_job;
 pub use gc_job::*;
 
+mod expectation_job;
+pub use expectation_job::*;
+
+mod ephemeral_job;
+pub use ephemeral_job::*;
+
 mod realm_sync_job;
 pub use realm_sync_job::*;
 
@@ -33,6 +39,14 @@ pub use report::*;
 
 pub mod auth;
 
+pub mod hooks;
+
+pub mod webhook;
+
+pub mod log_forward;
+
+pub mod snmp;
+
 pub(crate) mod pull;
 
 pub(crate) async fn reload_proxy_certificate() -> Result<(), Error> {
@@ -53,6 +67,18 @@ pub(crate) async fn notify_datastore_removed() -> Result<(), Error> {
     Ok(())
 }
 
+/// Tell the proxy it might have to clear the cache entry of a datastore.
+pub(crate) async fn notify_datastore_cache_update(store: &str) -> Result<(), Error> {
+    let proxy_pid = proxmox_rest_server::read_pid(pbs_buildcfg::PROXMOX_BACKUP_PROXY_PID_FN)?;
+    let sock = proxmox_rest_server::ctrl_sock_from_pid(proxy_pid);
+    let _: Value = proxmox_rest_server::send_raw_command(
+        sock,
+        &format!("{{\"command\":\"update-datastore-cache\",\"args\":\"{store}\"}}\n"),
+    )
+    .await?;
+    Ok(())
+}
+
 /// Create the base run-directory.
 ///
 /// This exists to fixate the permissions for the run *base* directory while allowing intermediate
@@ -5,17 +5,21 @@ use std::time::{Duration, Instant};
 use anyhow::Error;
 use const_format::concatcp;
 use nix::unistd::Uid;
-use serde_json::json;
+use serde_json::{json, Value};
 
 use proxmox_notify::context::pbs::PBS_CONTEXT;
 use proxmox_schema::ApiType;
 use proxmox_sys::fs::{create_path, CreateOptions};
 
+use crate::server::log_forward::forward_task_event;
 use crate::tape::TapeNotificationMode;
+use crate::tools::i18n::localized_template;
 use pbs_api_types::{
-    APTUpdateInfo, DataStoreConfig, DatastoreNotify, GarbageCollectionStatus, NotificationMode,
-    Notify, SyncJobConfig, TapeBackupJobSetup, User, Userid, VerificationJobConfig,
+    APTUpdateInfo, BackupExpectationConfig, DataStoreConfig, DatastoreNotify,
+    GarbageCollectionStatus, LogForwardEventType, NotificationMode, Notify, SyncJobConfig,
+    TapeBackupJobSetup, User, Userid, VerificationJobConfig,
 };
+use pbs_datastore::PartialSnapshotReport;
 use proxmox_notify::endpoints::sendmail::{SendmailConfig, SendmailEndpoint};
 use proxmox_notify::{Endpoint, Notification, Severity};
 
@@ -117,6 +121,26 @@ fn send_notification(notification: Notification) -> Result<(), Error> {
     Ok(())
 }
 
+/// Create a notification from `template`, localized to `lang`, or to the
+/// configured notification language of the node if `lang` is not set.
+fn new_notification(
+    severity: Severity,
+    template: &str,
+    data: Value,
+    metadata: HashMap<String, String>,
+    lang: Option<&str>,
+) -> Notification {
+    let node_lang = match lang {
+        Some(_) => None,
+        None => crate::config::node::config()
+            .ok()
+            .and_then(|(config, _digest)| config.notification_lang),
+    };
+    let template = localized_template(template, lang.or(node_lang.as_deref()));
+
+    Notification::from_template(severity, &template, data, metadata)
+}
+
 fn send_sendmail_legacy_notification(notification: Notification, email: &str) -> Result<(), Error> {
     let endpoint = SendmailEndpoint {
         config: SendmailConfig {
@@ -141,11 +165,29 @@ pub struct TapeBackupJobSummary {
     pub used_tapes: Option<Vec<String>>,
 }
 
+/// Error message of a task result, for forwarding to log collectors
+fn forward_result<T>(result: &Result<T, Error>) -> Result<(), String> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
 pub fn send_gc_status(
     datastore: &str,
+    upid: &str,
     status: &GarbageCollectionStatus,
     result: &Result<(), Error>,
 ) -> Result<(), Error> {
+    forward_task_event(
+        LogForwardEventType::GarbageCollection,
+        datastore,
+        upid,
+        &json!({ "job-id": datastore }),
+        &forward_result(result),
+        result.is_ok().then_some(status.removed_bytes),
+    );
+
     let (fqdn, port) = get_server_url();
     let mut data = json!({
         "datastore": datastore,
@@ -177,9 +219,7 @@ pub fn send_gc_status(
         ("type".into(), "gc".into()),
     ]);
 
-    let notification = Notification::from_template(severity, template, data, metadata);
-
-    let (email, notify, mode) = lookup_datastore_notify_settings(datastore);
+    let (recipient, notify, mode) = lookup_datastore_notify_settings(datastore);
     match mode {
         NotificationMode::LegacySendmail => {
             let notify = notify.gc.unwrap_or(Notify::Always);
@@ -188,12 +228,14 @@ pub fn send_gc_status(
                 return Ok(());
             }
 
-            if let Some(email) = email {
-                send_sendmail_legacy_notification(notification, &email)?;
+            if let Some(recipient) = recipient {
+                let lang = recipient.lang.as_deref();
+                let notification = new_notification(severity, template, data, metadata, lang);
+                send_sendmail_legacy_notification(notification, &recipient.email)?;
             }
         }
         NotificationMode::NotificationSystem => {
-            send_notification(notification)?;
+            send_notification(new_notification(severity, template, data, metadata, None))?;
         }
     }
 
@@ -202,8 +244,25 @@ pub fn send_gc_status(
 
 pub fn send_verify_status(
     job: VerificationJobConfig,
+    upid: &str,
     result: &Result<Vec<String>, Error>,
 ) -> Result<(), Error> {
+    let forwarded = match result {
+        Ok(errors) if !errors.is_empty() => Err(format!(
+            "verification failed for {} snapshots",
+            errors.len()
+        )),
+        _ => forward_result(result),
+    };
+    forward_task_event(
+        LogForwardEventType::Verify,
+        &job.store,
+        upid,
+        &json!({ "job-id": job.id, "ns": job.ns.clone().unwrap_or_default() }),
+        &forwarded,
+        None,
+    );
+
     let (fqdn, port) = get_server_url();
     let mut data = json!({
         "job": job,
@@ -230,9 +289,7 @@ pub fn send_verify_status(
         ("type".into(), "verify".into()),
     ]);
 
-    let notification = Notification::from_template(severity, template, data, metadata);
-
-    let (email, notify, mode) = lookup_datastore_notify_settings(&job.store);
+    let (recipient, notify, mode) = lookup_datastore_notify_settings(&job.store);
     match mode {
         NotificationMode::LegacySendmail => {
             let notify = notify.verify.unwrap_or(Notify::Always);
@@ -241,12 +298,14 @@ pub fn send_verify_status(
                 return Ok(());
             }
 
-            if let Some(email) = email {
-                send_sendmail_legacy_notification(notification, &email)?;
+            if let Some(recipient) = recipient {
+                let lang = recipient.lang.as_deref();
+                let notification = new_notification(severity, template, data, metadata, lang);
+                send_sendmail_legacy_notification(notification, &recipient.email)?;
             }
         }
         NotificationMode::NotificationSystem => {
-            send_notification(notification)?;
+            send_notification(new_notification(severity, template, data, metadata, None))?;
         }
     }
 
@@ -256,8 +315,18 @@ pub fn send_verify_status(
 pub fn send_prune_status(
     store: &str,
     jobname: &str,
+    upid: &str,
     result: &Result<(), Error>,
 ) -> Result<(), Error> {
+    forward_task_event(
+        LogForwardEventType::Prune,
+        store,
+        upid,
+        &json!({ "job-id": jobname }),
+        &forward_result(result),
+        None,
+    );
+
     let (fqdn, port) = get_server_url();
     let mut data = json!({
         "jobname": jobname,
@@ -281,9 +350,7 @@ pub fn send_prune_status(
         ("type".into(), "prune".into()),
     ]);
 
-    let notification = Notification::from_template(severity, template, data, metadata);
-
-    let (email, notify, mode) = lookup_datastore_notify_settings(store);
+    let (recipient, notify, mode) = lookup_datastore_notify_settings(store);
     match mode {
         NotificationMode::LegacySendmail => {
             let notify = notify.prune.unwrap_or(Notify::Error);
@@ -292,19 +359,40 @@ pub fn send_prune_status(
                 return Ok(());
             }
 
-            if let Some(email) = email {
-                send_sendmail_legacy_notification(notification, &email)?;
+            if let Some(recipient) = recipient {
+                let lang = recipient.lang.as_deref();
+                let notification = new_notification(severity, template, data, metadata, lang);
+                send_sendmail_legacy_notification(notification, &recipient.email)?;
             }
         }
         NotificationMode::NotificationSystem => {
-            send_notification(notification)?;
+            send_notification(new_notification(severity, template, data, metadata, None))?;
         }
     }
 
     Ok(())
 }
 
-pub fn send_sync_status(job: &SyncJobConfig, result: &Result<(), Error>) -> Result<(), Error> {
+/// `result` holds the amount of transferred bytes of a successful sync.
+pub fn send_sync_status(
+    job: &SyncJobConfig,
+    upid: &str,
+    result: &Result<u64, Error>,
+) -> Result<(), Error> {
+    forward_task_event(
+        LogForwardEventType::Sync,
+        &job.store,
+        upid,
+        &json!({
+            "job-id": job.id,
+            "remote": job.remote,
+            "remote-store": job.remote_store,
+            "ns": job.ns.clone().unwrap_or_default(),
+        }),
+        &forward_result(result),
+        result.as_ref().ok().copied(),
+    );
+
     let (fqdn, port) = get_server_url();
     let mut data = json!({
         "job": job,
@@ -313,7 +401,7 @@ pub fn send_sync_status(job: &SyncJobConfig, result: &Result<(), Error>) -> Resu
     });
 
     let (template, severity) = match result {
-        Ok(()) => ("sync-ok", Severity::Info),
+        Ok(_) => ("sync-ok", Severity::Info),
         Err(err) => {
             data["error"] = err.to_string().into();
             ("sync-err", Severity::Error)
@@ -327,9 +415,7 @@ pub fn send_sync_status(job: &SyncJobConfig, result: &Result<(), Error>) -> Resu
         ("type".into(), "sync".into()),
     ]);
 
-    let notification = Notification::from_template(severity, template, data, metadata);
-
-    let (email, notify, mode) = lookup_datastore_notify_settings(&job.store);
+    let (recipient, notify, mode) = lookup_datastore_notify_settings(&job.store);
     match mode {
         NotificationMode::LegacySendmail => {
             let notify = notify.prune.unwrap_or(Notify::Error);
@@ -338,12 +424,14 @@ pub fn send_sync_status(job: &SyncJobConfig, result: &Result<(), Error>) -> Resu
                 return Ok(());
             }
 
-            if let Some(email) = email {
-                send_sendmail_legacy_notification(notification, &email)?;
+            if let Some(recipient) = recipient {
+                let lang = recipient.lang.as_deref();
+                let notification = new_notification(severity, template, data, metadata, lang);
+                send_sendmail_legacy_notification(notification, &recipient.email)?;
             }
         }
         NotificationMode::NotificationSystem => {
-            send_notification(notification)?;
+            send_notification(new_notification(severity, template, data, metadata, None))?;
         }
     }
 
@@ -353,9 +441,24 @@ pub fn send_sync_status(job: &SyncJobConfig, result: &Result<(), Error>) -> Resu
 pub fn send_tape_backup_status(
     id: Option<&str>,
     job: &TapeBackupJobSetup,
+    upid: &str,
     result: &Result<(), Error>,
     summary: TapeBackupJobSummary,
 ) -> Result<(), Error> {
+    forward_task_event(
+        LogForwardEventType::TapeBackup,
+        &job.store,
+        upid,
+        &json!({
+            "job-id": id,
+            "pool": job.pool,
+            "drive": job.drive,
+            "snapshots": summary.snapshot_list.len(),
+        }),
+        &forward_result(result),
+        None,
+    );
+
     let (fqdn, port) = get_server_url();
     let duration: proxmox_time::TimeSpan = summary.duration.into();
     let mut data = json!({
@@ -387,20 +490,20 @@ pub fn send_tape_backup_status(
         metadata.insert("job-id".into(), id.into());
     }
 
-    let notification = Notification::from_template(severity, template, data, metadata);
-
     let mode = TapeNotificationMode::from(job);
 
     match &mode {
         TapeNotificationMode::LegacySendmail { notify_user } => {
-            let email = lookup_user_email(notify_user);
+            let recipient = lookup_legacy_recipient(notify_user);
 
-            if let Some(email) = email {
-                send_sendmail_legacy_notification(notification, &email)?;
+            if let Some(recipient) = recipient {
+                let lang = recipient.lang.as_deref();
+                let notification = new_notification(severity, template, data, metadata, lang);
+                send_sendmail_legacy_notification(notification, &recipient.email)?;
             }
         }
         TapeNotificationMode::NotificationSystem => {
-            send_notification(notification)?;
+            send_notification(new_notification(severity, template, data, metadata, None))?;
         }
     }
 
@@ -429,18 +532,164 @@ pub fn send_load_media_notification(
         ("hostname".into(), proxmox_sys::nodename().into()),
         ("type".into(), "tape-load".into()),
     ]);
-    let notification = Notification::from_template(Severity::Notice, "tape-load", data, metadata);
 
     match mode {
         TapeNotificationMode::LegacySendmail { notify_user } => {
-            let email = lookup_user_email(notify_user);
+            let recipient = lookup_legacy_recipient(notify_user);
 
-            if let Some(email) = email {
-                send_sendmail_legacy_notification(notification, &email)?;
+            if let Some(recipient) = recipient {
+                let lang = recipient.lang.as_deref();
+                let notification =
+                    new_notification(Severity::Notice, "tape-load", data, metadata, lang);
+                send_sendmail_legacy_notification(notification, &recipient.email)?;
             }
         }
         TapeNotificationMode::NotificationSystem => {
-            send_notification(notification)?;
+            send_notification(new_notification(
+                Severity::Notice,
+                "tape-load",
+                data,
+                metadata,
+                None,
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Ask the operator to attach the removable device of a datastore, so a due job can run
+pub fn send_datastore_offline_notification(
+    datastore: &DataStoreConfig,
+    job: &str,
+) -> Result<(), Error> {
+    let data = json!({
+        "datastore": datastore.name,
+        "path": datastore.path,
+        "backing-device": datastore.backing_device,
+        "job": job,
+    });
+
+    let metadata = HashMap::from([
+        ("datastore".into(), datastore.name.clone()),
+        ("hostname".into(), proxmox_sys::nodename().into()),
+        ("type".into(), "datastore-offline".into()),
+    ]);
+
+    let (recipient, _notify, mode) = lookup_datastore_notify_settings(&datastore.name);
+    match mode {
+        NotificationMode::LegacySendmail => {
+            if let Some(recipient) = recipient {
+                let lang = recipient.lang.as_deref();
+                let notification =
+                    new_notification(Severity::Notice, "datastore-offline", data, metadata, lang);
+                send_sendmail_legacy_notification(notification, &recipient.email)?;
+            }
+        }
+        NotificationMode::NotificationSystem => {
+            send_notification(new_notification(
+                Severity::Notice,
+                "datastore-offline",
+                data,
+                metadata,
+                None,
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Report backup groups which did not get a new snapshot within the expected time
+pub fn send_backup_overdue_notification(
+    expectation: &BackupExpectationConfig,
+    stale: &[crate::server::StaleGroup],
+) -> Result<(), Error> {
+    let groups: Vec<Value> = stale
+        .iter()
+        .map(|group| {
+            json!({
+                "group": group.group,
+                "last-backup": group.last_backup.and_then(|time| {
+                    proxmox_time::strftime_local("%F %T", time).ok()
+                }),
+            })
+        })
+        .collect();
+
+    let data = json!({
+        "datastore": expectation.store,
+        "expectation": expectation.id,
+        "max-age": expectation.max_age,
+        "groups": groups,
+    });
+
+    let metadata = HashMap::from([
+        ("datastore".into(), expectation.store.clone()),
+        ("hostname".into(), proxmox_sys::nodename().into()),
+        ("job-id".into(), expectation.id.clone()),
+        ("type".into(), "backup-overdue".into()),
+    ]);
+
+    let (recipient, _notify, mode) = lookup_datastore_notify_settings(&expectation.store);
+    match mode {
+        NotificationMode::LegacySendmail => {
+            if let Some(recipient) = recipient {
+                let lang = recipient.lang.as_deref();
+                let notification =
+                    new_notification(Severity::Warning, "backup-overdue", data, metadata, lang);
+                send_sendmail_legacy_notification(notification, &recipient.email)?;
+            }
+        }
+        NotificationMode::NotificationSystem => {
+            send_notification(new_notification(
+                Severity::Warning,
+                "backup-overdue",
+                data,
+                metadata,
+                None,
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Report partial snapshots cleaned up after a crash
+pub fn send_partial_snapshot_notification(
+    datastore: &str,
+    report: &PartialSnapshotReport,
+) -> Result<(), Error> {
+    let data = json!({
+        "datastore": datastore,
+        "removed-snapshots": report.removed_snapshots,
+        "replayed-journals": report.replayed_journals,
+    });
+
+    let metadata = HashMap::from([
+        ("datastore".into(), datastore.into()),
+        ("hostname".into(), proxmox_sys::nodename().into()),
+        ("type".into(), "partial-snapshots".into()),
+    ]);
+
+    let (recipient, _notify, mode) = lookup_datastore_notify_settings(datastore);
+    match mode {
+        NotificationMode::LegacySendmail => {
+            if let Some(recipient) = recipient {
+                let lang = recipient.lang.as_deref();
+                let notification =
+                    new_notification(Severity::Warning, "partial-snapshots", data, metadata, lang);
+                send_sendmail_legacy_notification(notification, &recipient.email)?;
+            }
+        }
+        NotificationMode::NotificationSystem => {
+            send_notification(new_notification(
+                Severity::Warning,
+                "partial-snapshots",
+                data,
+                metadata,
+                None,
+            ))?;
         }
     }
 
@@ -481,10 +730,13 @@ pub fn send_updates_available(updates: &[&APTUpdateInfo]) -> Result<(), Error> {
         ("type".into(), "package-updates".into()),
     ]);
 
-    let notification =
-        Notification::from_template(Severity::Info, "package-updates", data, metadata);
-
-    send_notification(notification)?;
+    send_notification(new_notification(
+        Severity::Info,
+        "package-updates",
+        data,
+        metadata,
+        None,
+    ))?;
     Ok(())
 }
 
@@ -508,17 +760,31 @@ pub fn send_certificate_renewal_mail(result: &Result<(), Error>) -> Result<(), E
         ("type".into(), "acme".into()),
     ]);
 
-    let notification = Notification::from_template(Severity::Info, "acme-err", data, metadata);
-
-    send_notification(notification)?;
+    send_notification(new_notification(
+        Severity::Info,
+        "acme-err",
+        data,
+        metadata,
+        None,
+    ))?;
     Ok(())
 }
 
-/// Lookup users email address
-pub fn lookup_user_email(userid: &Userid) -> Option<String> {
+/// Recipient of notifications sent in legacy sendmail mode
+pub struct LegacyRecipient {
+    pub email: String,
+    /// Preferred language of the user, if set
+    pub lang: Option<String>,
+}
+
+/// Lookup users email address and preferred language
+pub fn lookup_legacy_recipient(userid: &Userid) -> Option<LegacyRecipient> {
     if let Ok(user_config) = pbs_config::user::cached_config() {
         if let Ok(user) = user_config.lookup::<User>("user", userid.as_str()) {
-            return user.email;
+            return user.email.map(|email| LegacyRecipient {
+                email,
+                lang: user.lang,
+            });
         }
     }
 
@@ -528,8 +794,8 @@ pub fn lookup_user_email(userid: &Userid) -> Option<String> {
 /// Lookup Datastore notify settings
 pub fn lookup_datastore_notify_settings(
     store: &str,
-) -> (Option<String>, DatastoreNotify, NotificationMode) {
-    let mut email = None;
+) -> (Option<LegacyRecipient>, DatastoreNotify, NotificationMode) {
+    let mut recipient = None;
 
     let notify = DatastoreNotify {
         gc: None,
@@ -540,17 +806,17 @@ pub fn lookup_datastore_notify_settings(
 
     let (config, _digest) = match pbs_config::datastore::config() {
         Ok(result) => result,
-        Err(_) => return (email, notify, NotificationMode::default()),
+        Err(_) => return (recipient, notify, NotificationMode::default()),
     };
 
     let config: DataStoreConfig = match config.lookup("datastore", store) {
         Ok(result) => result,
-        Err(_) => return (email, notify, NotificationMode::default()),
+        Err(_) => return (recipient, notify, NotificationMode::default()),
     };
 
-    email = match config.notify_user {
-        Some(ref userid) => lookup_user_email(userid),
-        None => lookup_user_email(Userid::root_userid()),
+    recipient = match config.notify_user {
+        Some(ref userid) => lookup_legacy_recipient(userid),
+        None => lookup_legacy_recipient(Userid::root_userid()),
     };
 
     let notification_mode = config.notification_mode.unwrap_or_default();
@@ -558,9 +824,9 @@ pub fn lookup_datastore_notify_settings(
 
     if let Ok(value) = DatastoreNotify::API_SCHEMA.parse_property_string(&notify_str) {
         if let Ok(notify) = serde_json::from_value(value) {
-            return (email, notify, notification_mode);
+            return (recipient, notify, notification_mode);
         }
     }
 
-    (email, notify, notification_mode)
+    (recipient, notify, notification_mode)
 }
@@ -1,18 +1,20 @@
 use std::sync::Arc;
 
 use anyhow::Error;
+use serde_json::json;
 
 use proxmox_sys::{task_log, task_warn};
 
 use pbs_api_types::{
-    print_store_and_ns, Authid, KeepOptions, Operation, PruneJobOptions, MAX_NAMESPACE_DEPTH,
-    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE,
+    print_store_and_ns, Authid, HookJobType, KeepOptions, Operation, PruneJobOptions,
+    MAX_NAMESPACE_DEPTH, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE,
 };
 use pbs_datastore::prune::compute_prune_info;
 use pbs_datastore::DataStore;
 use proxmox_rest_server::WorkerTask;
 
 use crate::backup::ListAccessibleBackupGroups;
+use crate::server::hooks::{with_hooks, HookContext};
 use crate::server::jobstate::Job;
 
 pub fn prune_datastore(
@@ -136,6 +138,7 @@ pub fn do_prune_job(
 
     let worker_type = job.jobtype().to_string();
     let auth_id = auth_id.clone();
+    job.set_store(&store);
     let worker_id = match &prune_options.ns {
         Some(ns) if ns.is_root() => store.clone(),
         Some(ns) => format!("{store}:{ns}"),
@@ -156,7 +159,18 @@ pub fn do_prune_job(
                 task_log!(worker, "task triggered by schedule '{event_str}'");
             }
 
-            let result = prune_datastore(worker.clone(), auth_id, prune_options, datastore, false);
+            let hook_ctx = HookContext::new(
+                HookJobType::Prune,
+                &store,
+                worker.upid().to_string(),
+                json!({
+                    "job-id": job.jobname(),
+                    "ns": prune_options.ns.clone().unwrap_or_default(),
+                }),
+            );
+            let result = with_hooks(&*worker, &hook_ctx, || {
+                prune_datastore(worker.clone(), auth_id, prune_options, datastore, false)
+            });
 
             let status = worker.create_state(&result);
 
@@ -164,7 +178,10 @@ pub fn do_prune_job(
                 eprintln!("could not finish job state for {}: {err}", job.jobtype());
             }
 
-            if let Err(err) = crate::server::send_prune_status(&store, job.jobname(), &result) {
+            let upid = worker.upid().to_string();
+            if let Err(err) =
+                crate::server::send_prune_status(&store, job.jobname(), &upid, &result)
+            {
                 log::error!("send prune notification failed: {err}");
             }
             result
@@ -15,9 +15,11 @@ use proxmox_router::HttpError;
 use proxmox_sys::{task_log, task_warn};
 use serde_json::json;
 
+use pbs_api_types::percent_encoding::percent_encode_component;
 use pbs_api_types::{
-    print_store_and_ns, Authid, BackupDir, BackupGroup, BackupNamespace, CryptMode, GroupFilter,
-    GroupListItem, Operation, RateLimitConfig, Remote, SnapshotListItem, MAX_NAMESPACE_DEPTH,
+    print_ns_and_snapshot, print_store_and_ns, Authid, BackupDir, BackupGroup, BackupNamespace,
+    CryptMode, GroupFilter, GroupListItem, Operation, RateLimitConfig, Remote, SnapshotListItem,
+    SnapshotReplication, SnapshotVerifyState, VerifyState, MAX_NAMESPACE_DEPTH,
     PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ,
 };
 use pbs_client::{BackupReader, BackupRepository, HttpClient, RemoteChunkReader};
@@ -35,7 +37,10 @@ use pbs_datastore::{
 };
 use pbs_tools::sha::sha256;
 
-use crate::backup::{check_ns_modification_privs, check_ns_privs, ListAccessibleBackupGroups};
+use crate::backup::{
+    check_ns_modification_privs, check_ns_privs, verify_backup_dir, ListAccessibleBackupGroups,
+    VerifyWorker,
+};
 use crate::tools::parallel_handler::ParallelHandler;
 
 struct RemoteReader {
@@ -47,6 +52,8 @@ struct LocalReader {
     _dir_lock: Arc<Mutex<proxmox_sys::fs::DirLockGuard>>,
     path: PathBuf,
     datastore: Arc<DataStore>,
+    link_chunks: bool,
+    reflink_chunks: bool,
 }
 
 pub(crate) struct PullTarget {
@@ -63,6 +70,18 @@ pub(crate) struct RemoteSource {
 pub(crate) struct LocalSource {
     store: Arc<DataStore>,
     ns: BackupNamespace,
+    /// Whether chunks should be hard linked into the target instead of copied, if possible
+    link_chunks: bool,
+    /// Whether chunks should be reflinked into the target instead of copied, if possible
+    reflink_chunks: bool,
+}
+
+/// Local datastore chunks can be transferred from without copying their data.
+#[derive(Clone)]
+struct ChunkLinkSource {
+    store: Arc<DataStore>,
+    hard_link: bool,
+    reflink: bool,
 }
 
 #[derive(Default)]
@@ -86,6 +105,8 @@ pub(crate) struct PullStats {
     pub(crate) bytes: usize,
     pub(crate) elapsed: Duration,
     pub(crate) removed: Option<RemovedVanishedStats>,
+    /// Snapshots which were (re-)synced with new contents
+    pub(crate) synced: Vec<pbs_datastore::BackupDir>,
 }
 
 impl From<RemovedVanishedStats> for PullStats {
@@ -102,6 +123,7 @@ impl PullStats {
         self.chunk_count += rhs.chunk_count;
         self.bytes += rhs.bytes;
         self.elapsed += rhs.elapsed;
+        self.synced.extend(rhs.synced);
 
         if let Some(rhs_removed) = rhs.removed {
             if let Some(ref mut removed) = self.removed {
@@ -367,6 +389,8 @@ impl PullSource for LocalSource {
             _dir_lock: Arc::new(Mutex::new(dir_lock)),
             path: dir.full_path(),
             datastore: dir.datastore().clone(),
+            link_chunks: self.link_chunks,
+            reflink_chunks: self.reflink_chunks,
         }))
     }
 }
@@ -396,6 +420,10 @@ trait PullReader: Send + Sync {
     ) -> Result<(), Error>;
 
     fn skip_chunk_sync(&self, target_store_name: &str) -> bool;
+
+    /// Returns the local datastore chunks may be hard linked or reflinked from instead of
+    /// copying them.
+    fn chunk_link_source(&self) -> Option<ChunkLinkSource>;
 }
 
 #[async_trait::async_trait]
@@ -478,6 +506,10 @@ impl PullReader for RemoteReader {
     fn skip_chunk_sync(&self, _target_store_name: &str) -> bool {
         false
     }
+
+    fn chunk_link_source(&self) -> Option<ChunkLinkSource> {
+        None
+    }
 }
 
 #[async_trait::async_trait]
@@ -520,6 +552,18 @@ impl PullReader for LocalReader {
     fn skip_chunk_sync(&self, target_store_name: &str) -> bool {
         self.datastore.name() == target_store_name
     }
+
+    fn chunk_link_source(&self) -> Option<ChunkLinkSource> {
+        if self.link_chunks || self.reflink_chunks {
+            Some(ChunkLinkSource {
+                store: self.datastore.clone(),
+                hard_link: self.link_chunks,
+                reflink: self.reflink_chunks,
+            })
+        } else {
+            None
+        }
+    }
 }
 
 /// Parameters for a pull operation.
@@ -581,6 +625,8 @@ impl PullParameters {
             Arc::new(LocalSource {
                 store: DataStore::lookup_datastore(remote_store, Some(Operation::Read))?,
                 ns: remote_ns,
+                link_chunks: false,
+                reflink_chunks: false,
             })
         };
         let target = PullTarget {
@@ -600,11 +646,57 @@ impl PullParameters {
             transfer_last,
         })
     }
+
+    /// Creates a new instance of `PullParameters` for copying groups between local datastores.
+    ///
+    /// If `link_chunks` is set, chunks are hard linked into the target datastore instead of
+    /// being copied, as long as both datastores are located on the same file system. Otherwise,
+    /// if `reflink_chunks` is set, chunks are copied as reflinks if the file system supports it
+    /// (e.g. XFS or btrfs).
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new_local_copy(
+        store: &str,
+        ns: BackupNamespace,
+        source_store: &str,
+        source_ns: BackupNamespace,
+        owner: Authid,
+        max_depth: Option<usize>,
+        group_filter: Option<Vec<GroupFilter>>,
+        link_chunks: bool,
+        reflink_chunks: bool,
+    ) -> Result<Self, Error> {
+        if let Some(max_depth) = max_depth {
+            ns.check_max_depth(max_depth)?;
+            source_ns.check_max_depth(max_depth)?;
+        };
+
+        let source = Arc::new(LocalSource {
+            store: DataStore::lookup_datastore(source_store, Some(Operation::Read))?,
+            ns: source_ns,
+            link_chunks,
+            reflink_chunks,
+        });
+        let target = PullTarget {
+            store: DataStore::lookup_datastore(store, Some(Operation::Write))?,
+            ns,
+        };
+
+        Ok(Self {
+            source,
+            target,
+            owner,
+            remove_vanished: false,
+            max_depth,
+            group_filter: group_filter.unwrap_or_default(),
+            transfer_last: None,
+        })
+    }
 }
 
 async fn pull_index_chunks<I: IndexFile>(
     worker: &WorkerTask,
     chunk_reader: Arc<dyn AsyncReadChunk>,
+    link_source: Option<ChunkLinkSource>,
     target: Arc<DataStore>,
     index: I,
     downloaded_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
@@ -635,6 +727,7 @@ async fn pull_index_chunks<I: IndexFile>(
         move |(chunk, digest, size): (DataBlob, [u8; 32], u64)| {
             // println!("verify and write {}", hex::encode(&digest));
             chunk.verify_unencrypted(size as usize, &digest)?;
+            let chunk = target2.recompress_chunk(chunk, &digest)?;
             target2.insert_chunk(&chunk, &digest)?;
             Ok(())
         },
@@ -644,13 +737,18 @@ async fn pull_index_chunks<I: IndexFile>(
 
     let bytes = Arc::new(AtomicUsize::new(0));
     let chunk_count = Arc::new(AtomicUsize::new(0));
+    let linked_count = Arc::new(AtomicUsize::new(0));
+    let cloned_count = Arc::new(AtomicUsize::new(0));
 
     stream
         .map(|info| {
             let target = Arc::clone(&target);
             let chunk_reader = chunk_reader.clone();
+            let link_source = link_source.clone();
             let bytes = Arc::clone(&bytes);
             let chunk_count = Arc::clone(&chunk_count);
+            let linked_count = Arc::clone(&linked_count);
+            let cloned_count = Arc::clone(&cloned_count);
             let verify_and_write_channel = verify_and_write_channel.clone();
 
             Ok::<_, Error>(async move {
@@ -661,6 +759,28 @@ async fn pull_index_chunks<I: IndexFile>(
                     //task_log!(worker, "chunk {} exists {}", pos, hex::encode(digest));
                     return Ok::<_, Error>(());
                 }
+                if let Some(link_source) = link_source {
+                    if link_source.hard_link {
+                        let linked = proxmox_async::runtime::block_in_place(|| {
+                            target.link_chunk_from(&link_source.store, &info.digest)
+                        })?;
+                        if linked {
+                            linked_count.fetch_add(1, Ordering::SeqCst);
+                            chunk_count.fetch_add(1, Ordering::SeqCst);
+                            return Ok(());
+                        }
+                    }
+                    if link_source.reflink {
+                        let cloned = proxmox_async::runtime::block_in_place(|| {
+                            target.clone_chunk_from(&link_source.store, &info.digest)
+                        })?;
+                        if cloned {
+                            cloned_count.fetch_add(1, Ordering::SeqCst);
+                            chunk_count.fetch_add(1, Ordering::SeqCst);
+                            return Ok(());
+                        }
+                    }
+                }
                 //task_log!(worker, "sync {} chunk {}", pos, hex::encode(digest));
                 let chunk = chunk_reader.read_raw_chunk(&info.digest).await?;
                 let raw_size = chunk.raw_size() as usize;
@@ -688,6 +808,8 @@ async fn pull_index_chunks<I: IndexFile>(
 
     let bytes = bytes.load(Ordering::SeqCst);
     let chunk_count = chunk_count.load(Ordering::SeqCst);
+    let linked_count = linked_count.load(Ordering::SeqCst);
+    let cloned_count = cloned_count.load(Ordering::SeqCst);
 
     task_log!(
         worker,
@@ -696,6 +818,17 @@ async fn pull_index_chunks<I: IndexFile>(
         HumanByte::new_binary(bytes as f64 / elapsed.as_secs_f64()),
     );
 
+    if linked_count > 0 {
+        task_log!(worker, "linked {linked_count} chunks from source datastore");
+    }
+
+    if cloned_count > 0 {
+        task_log!(
+            worker,
+            "reflinked {cloned_count} chunks from source datastore"
+        );
+    }
+
     Ok(PullStats {
         chunk_count,
         bytes,
@@ -767,6 +900,7 @@ async fn pull_single_archive<'a>(
                 let stats = pull_index_chunks(
                     worker,
                     reader.chunk_reader(archive_info.crypt_mode),
+                    reader.chunk_link_source(),
                     snapshot.datastore().clone(),
                     index,
                     downloaded_chunks,
@@ -788,6 +922,7 @@ async fn pull_single_archive<'a>(
                 let stats = pull_index_chunks(
                     worker,
                     reader.chunk_reader(archive_info.crypt_mode),
+                    reader.chunk_link_source(),
                     snapshot.datastore().clone(),
                     index,
                     downloaded_chunks,
@@ -805,6 +940,7 @@ async fn pull_single_archive<'a>(
     if let Err(err) = std::fs::rename(&tmp_path, &path) {
         bail!("Atomic rename file {:?} failed - {}", path, err);
     }
+    pbs_datastore::chunk_ref_tracker::track_index(snapshot, archive_name);
     Ok(pull_stats)
 }
 
@@ -932,6 +1068,10 @@ async fn pull_snapshot<'a>(
         .cleanup_unreferenced_files(&manifest)
         .map_err(|err| format_err!("failed to cleanup unreferenced files - {err}"))?;
 
+    pbs_datastore::chunk_ref_tracker::track_snapshot(snapshot);
+
+    pull_stats.synced.push(snapshot.clone());
+
     Ok(pull_stats)
 }
 
@@ -1584,3 +1724,198 @@ pub(crate) async fn pull_ns(
 
     Ok((progress, pull_stats, errors))
 }
+
+/// Removes snapshots from the source of a local copy which are available in the target.
+///
+/// Only snapshots with a finished manifest in the target are removed, so snapshots created in
+/// the source datastore while copying are kept. Groups left empty are removed as well.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn remove_copied_source_snapshots(
+    worker: &WorkerTask,
+    source: &Arc<DataStore>,
+    source_ns: &BackupNamespace,
+    target: &DataStore,
+    target_ns: &BackupNamespace,
+    max_depth: Option<usize>,
+    group_filter: &[GroupFilter],
+    owner: &Authid,
+) -> Result<RemovedVanishedStats, Error> {
+    let mut stats = RemovedVanishedStats::default();
+
+    let namespaces = ListNamespacesRecursive::new_max_depth(
+        source.clone(),
+        source_ns.clone(),
+        max_depth.unwrap_or(MAX_NAMESPACE_DEPTH),
+    )?;
+
+    for namespace in namespaces {
+        let namespace = namespace?;
+        let copied_ns = namespace.map_prefix(source_ns, target_ns)?;
+
+        for group in source.iter_backup_groups_ok(namespace.clone())? {
+            if !group.group().apply_filters(group_filter) {
+                continue;
+            }
+            let group_owner = source.get_owner(&namespace, group.group())?;
+            if check_backup_owner(&group_owner, owner).is_err() {
+                continue;
+            }
+
+            for info in group.list_backups()? {
+                let snapshot = info.backup_dir;
+                let mut manifest_path = target.snapshot_path(&copied_ns, snapshot.as_ref());
+                manifest_path.push(MANIFEST_BLOB_NAME);
+                if !manifest_path.exists() {
+                    continue;
+                }
+                if snapshot.is_protected() {
+                    task_log!(
+                        worker,
+                        "don't delete copied snapshot {} (protected)",
+                        snapshot.dir()
+                    );
+                    continue;
+                }
+                task_log!(worker, "delete copied snapshot {}", snapshot.dir());
+                source.remove_backup_dir(&namespace, snapshot.as_ref(), false)?;
+                stats.snapshots += 1;
+            }
+
+            if group.list_backups()?.is_empty() {
+                task_log!(worker, "delete empty group {}", group.group());
+                source.remove_backup_group(&namespace, group.group())?;
+                stats.groups += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Source of synced snapshots on a remote, which are verified there as well.
+pub(crate) struct RemoteVerifySource {
+    pub(crate) client: HttpClient,
+    pub(crate) store: String,
+    /// Namespace on the remote which is synced into `target_ns`
+    pub(crate) ns: BackupNamespace,
+    pub(crate) target_ns: BackupNamespace,
+}
+
+/// Start a verification of `dir` on the remote and wait for it to finish.
+async fn verify_remote_snapshot(
+    worker: &WorkerTask,
+    remote: &RemoteVerifySource,
+    ns: &BackupNamespace,
+    dir: &BackupDir,
+) -> Result<VerifyState, Error> {
+    let mut args = json!({
+        "backup-type": dir.ty(),
+        "backup-id": dir.id(),
+        "backup-time": dir.time,
+    });
+    if !ns.is_root() {
+        args["ns"] = serde_json::to_value(ns)?;
+    }
+
+    let path = format!("api2/json/admin/datastore/{}/verify", remote.store);
+    let result = remote.client.post(&path, Some(args)).await?;
+    let upid = result["data"]
+        .as_str()
+        .ok_or_else(|| format_err!("remote did not return a task ID"))?;
+
+    let status_path = format!(
+        "api2/json/nodes/localhost/tasks/{}/status",
+        percent_encode_component(upid)
+    );
+    loop {
+        let status = remote.client.get(&status_path, None).await?;
+        let status = &status["data"];
+        if status["status"].as_str() == Some("stopped") {
+            return Ok(match status["exitstatus"].as_str() {
+                Some("OK") => VerifyState::Ok,
+                _ => VerifyState::Failed,
+            });
+        }
+        worker.check_abort()?;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+/// Verify the snapshots synced by the job `job_id`, locally and, if `remote` is set, their
+/// source on the remote, and record the combined replication and verification state in the
+/// unprotected part of their manifests.
+///
+/// Returns the snapshots which failed to verify.
+pub(crate) async fn verify_synced_snapshots(
+    worker: Arc<WorkerTask>,
+    datastore: Arc<DataStore>,
+    snapshots: &[pbs_datastore::BackupDir],
+    remote: Option<&RemoteVerifySource>,
+    source: &str,
+    job_id: Option<&str>,
+) -> Result<Vec<String>, Error> {
+    let upid = worker.upid().clone();
+    let verify_worker = VerifyWorker::new(worker.clone(), datastore);
+    let mut failed = Vec::new();
+
+    for snapshot in snapshots {
+        tokio::task::block_in_place(|| {
+            verify_backup_dir(&verify_worker, snapshot, upid.clone(), None)
+        })?;
+
+        // snapshots skipped by the verification, e.g. because they vanished in the meantime, have
+        // no verify state of this task
+        let verify_state = match snapshot.load_manifest() {
+            Ok((manifest, _)) => serde_json::from_value::<SnapshotVerifyState>(
+                manifest.unprotected["verify_state"].clone(),
+            )
+            .ok()
+            .filter(|state| state.upid == upid),
+            Err(_) => None,
+        };
+        let verify_state = match verify_state {
+            Some(verify_state) => verify_state,
+            None => continue,
+        };
+
+        let snapshot_name = print_ns_and_snapshot(snapshot.backup_ns(), snapshot.as_ref());
+        if verify_state.state != VerifyState::Ok {
+            failed.push(snapshot_name.clone());
+        }
+
+        let remote_verify_state = match remote {
+            Some(remote) => {
+                let ns = snapshot
+                    .backup_ns()
+                    .map_prefix(&remote.target_ns, &remote.ns)?;
+                task_log!(worker, "verify {snapshot_name} on remote");
+                let state =
+                    match verify_remote_snapshot(&worker, remote, &ns, snapshot.as_ref()).await {
+                        Ok(state) => state,
+                        Err(err) => {
+                            task_warn!(worker, "verify {snapshot_name} on remote failed - {err}");
+                            VerifyState::Failed
+                        }
+                    };
+                if state != VerifyState::Ok {
+                    failed.push(format!("{snapshot_name} (remote)"));
+                }
+                Some(state)
+            }
+            None => None,
+        };
+
+        let replication = serde_json::to_value(SnapshotReplication {
+            source: source.to_string(),
+            job: job_id.map(str::to_string),
+            time: proxmox_time::epoch_i64(),
+            verify_state,
+            remote_verify_state,
+        })?;
+        snapshot.update_manifest(|manifest| {
+            manifest.unprotected["replication"] = replication;
+        })?;
+    }
+
+    Ok(failed)
+}
@@ -317,6 +317,7 @@ impl LdapRealmSyncJob {
                 .and_then(|o| o.enable)
                 .or(Some(self.general_sync_settings.enable_new)),
             expire: existing_user.and_then(|u| u.expire).or(Some(0)),
+            append_only: existing_user.and_then(|u| u.append_only),
             firstname: lookup(
                 "firstname",
                 self.ldap_sync_settings.firstname_attr.as_ref(),
@@ -353,6 +354,7 @@ impl LdapRealmSyncJob {
                     None
                 }
             }),
+            lang: existing_user.and_then(|u| u.lang.clone()),
         }
     }
 
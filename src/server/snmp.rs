@@ -0,0 +1,435 @@
+//! SNMP subagent exposing datastore, job and tape metrics
+//!
+//! The proxy connects to the AgentX socket of a master agent (for example `snmpd` with `master
+//! agentx`) and registers the configured subtree, see [SnmpConfig]. The objects below it are
+//! described in the SNMP section of the maintenance documentation:
+//!
+//! ```text
+//! <base>.1.1.0        version
+//! <base>.2.1.<col>.i  datastore table: name, total, used, avail, health, reasons, maintenance
+//! <base>.3.1.<col>.i  job table: type, id, store, last state, last run, next run
+//! <base>.4.1.<col>.i  tape drive table: name, changer, busy, state
+//! <base>.5.1.<col>.i  tape changer table: name, path, drives
+//! ```
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+use pbs_api_types::{
+    DataStoreConfig, DataStoreHealthState, LtoTapeDrive, Operation, PruneJobConfig,
+    ScsiTapeChanger, SyncJobConfig, TapeBackupJobConfig, VerificationJobConfig,
+};
+use pbs_datastore::DataStore;
+use proxmox_rest_server::TaskState;
+
+use crate::config::node::SnmpConfig;
+use crate::server::jobstate::{compute_schedule_status, JobState};
+use crate::tools::agentx::{self, Header, Mib, Oid, Value, HEADER_SIZE};
+
+/// Default path of the AgentX socket of the master agent
+pub const DEFAULT_AGENTX_SOCKET: &str = "/var/agentx/master";
+
+/// Default base OID, in the Net-SNMP playpen subtree reserved for local use
+pub const DEFAULT_SNMP_OID: &str = "1.3.6.1.4.1.8072.9999.9999.48";
+
+/// Collected values are reused for requests within this time, so that walking the tree does
+/// not query the datastores for every object
+const MIB_CACHE_TIME: Duration = Duration::from_secs(30);
+
+/// Interval for checking the node configuration for changes while connected
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Delay before connecting again after the master agent went away
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+// values of the job last state column
+const JOB_STATE_OK: i32 = 1;
+const JOB_STATE_WARNING: i32 = 2;
+const JOB_STATE_ERROR: i32 = 3;
+const JOB_STATE_UNKNOWN: i32 = 4;
+const JOB_STATE_RUNNING: i32 = 5;
+const JOB_STATE_NEVER: i32 = 6;
+
+fn health_value(state: DataStoreHealthState) -> i32 {
+    match state {
+        DataStoreHealthState::Ok => 1,
+        DataStoreHealthState::Warning => 2,
+        DataStoreHealthState::Critical => 3,
+        DataStoreHealthState::Maintenance => 4,
+    }
+}
+
+/// Adds the rows of a table below `table` (`<table>.1.<column>.<index>`).
+struct TableBuilder<'a> {
+    mib: &'a mut Mib,
+    entry: Oid,
+    index: u32,
+}
+
+impl<'a> TableBuilder<'a> {
+    fn new(mib: &'a mut Mib, table: Oid) -> Self {
+        Self {
+            mib,
+            entry: table.with(&[1]),
+            index: 0,
+        }
+    }
+
+    /// Add a row, the first column is the index.
+    fn row(&mut self, columns: Vec<Value>) {
+        self.index += 1;
+        self.mib.insert(
+            self.entry.with(&[1, self.index]),
+            Value::Integer(self.index as i32),
+        );
+        for (column, value) in columns.into_iter().enumerate() {
+            self.mib
+                .insert(self.entry.with(&[column as u32 + 2, self.index]), value);
+        }
+    }
+}
+
+async fn collect_datastores(mib: &mut Mib, base: &Oid) -> Result<(), Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let jobs = crate::api2::status::DataStoreJobs::load()?;
+
+    let mut table = TableBuilder::new(mib, base.with(&[2]));
+    for (name, (_, data)) in &config.sections {
+        let datastore_config: DataStoreConfig = serde_json::from_value(data.clone())?;
+
+        let (total, used, avail) = match DataStore::lookup_datastore(name, Some(Operation::Lookup))
+        {
+            Ok(datastore) => match crate::tools::fs::fs_info(datastore.base_path()).await {
+                Ok(info) => (info.total, info.used, info.available),
+                Err(_) => (0, 0, 0),
+            },
+            Err(_) => (0, 0, 0),
+        };
+
+        let health =
+            crate::api2::status::check_datastore_health(name, &datastore_config, &jobs).await;
+        let reasons: Vec<&str> = health
+            .reasons
+            .iter()
+            .map(|reason| reason.message.as_str())
+            .collect();
+
+        let maintenance = datastore_config
+            .get_maintenance_mode()
+            .map(|mode| mode.ty.to_string())
+            .unwrap_or_default();
+
+        table.row(vec![
+            Value::from(name.as_str()),
+            Value::Counter64(total),
+            Value::Counter64(used),
+            Value::Counter64(avail),
+            Value::Integer(health_value(health.state)),
+            Value::from(reasons.join("; ").as_str()),
+            Value::from(maintenance.as_str()),
+        ]);
+    }
+
+    Ok(())
+}
+
+fn job_row(jobtype: &str, id: &str, store: &str, schedule: Option<&str>) -> Vec<Value> {
+    let job_state = JobState::load(jobtype, id).ok();
+
+    let (state, last_run) = match job_state {
+        Some(JobState::Created { .. }) | None => (JOB_STATE_NEVER, 0),
+        Some(JobState::Started { .. }) => (JOB_STATE_RUNNING, 0),
+        Some(JobState::Finished { ref state, .. }) => {
+            let value = match state {
+                TaskState::OK { .. } => JOB_STATE_OK,
+                TaskState::Warning { .. } => JOB_STATE_WARNING,
+                TaskState::Error { .. } => JOB_STATE_ERROR,
+                TaskState::Unknown { .. } => JOB_STATE_UNKNOWN,
+            };
+            (value, state.endtime())
+        }
+    };
+
+    let next_run = match (&job_state, schedule) {
+        (Some(job_state), Some(schedule)) => compute_schedule_status(job_state, Some(schedule))
+            .ok()
+            .and_then(|status| status.next_run)
+            .unwrap_or(0),
+        _ => 0,
+    };
+
+    vec![
+        Value::from(jobtype),
+        Value::from(id),
+        Value::from(store),
+        Value::Integer(state),
+        Value::Counter64(last_run.max(0) as u64),
+        Value::Counter64(next_run.max(0) as u64),
+    ]
+}
+
+fn collect_jobs(mib: &mut Mib, base: &Oid) -> Result<(), Error> {
+    let mut table = TableBuilder::new(mib, base.with(&[3]));
+
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let datastores: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+    for datastore in datastores {
+        if let Some(ref schedule) = datastore.gc_schedule {
+            table.row(job_row(
+                "garbage_collection",
+                &datastore.name,
+                &datastore.name,
+                Some(schedule),
+            ));
+        }
+    }
+
+    let (config, _digest) = pbs_config::sync::config()?;
+    for job in config.convert_to_typed_array::<SyncJobConfig>("sync")? {
+        table.row(job_row(
+            "syncjob",
+            &job.id,
+            &job.store,
+            job.schedule.as_deref(),
+        ));
+    }
+
+    let (config, _digest) = pbs_config::verify::config()?;
+    for job in config.convert_to_typed_array::<VerificationJobConfig>("verification")? {
+        table.row(job_row(
+            "verificationjob",
+            &job.id,
+            &job.store,
+            job.schedule.as_deref(),
+        ));
+    }
+
+    let (config, _digest) = pbs_config::prune::config()?;
+    for job in config.convert_to_typed_array::<PruneJobConfig>("prune")? {
+        let schedule = (!job.disable).then_some(job.schedule.as_str());
+        table.row(job_row("prunejob", &job.id, &job.store, schedule));
+    }
+
+    let (config, _digest) = pbs_config::tape_job::config()?;
+    for job in config.convert_to_typed_array::<TapeBackupJobConfig>("backup")? {
+        table.row(job_row(
+            "tape-backup-job",
+            &job.id,
+            &job.setup.store,
+            job.schedule.as_deref(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn collect_tape(mib: &mut Mib, base: &Oid) -> Result<(), Error> {
+    let (config, _digest) = pbs_config::drive::config()?;
+
+    let drives: Vec<LtoTapeDrive> = config.convert_to_typed_array("lto")?;
+    let changers: Vec<ScsiTapeChanger> = config.convert_to_typed_array("changer")?;
+
+    let mut table = TableBuilder::new(mib, base.with(&[4]));
+    for drive in &drives {
+        let state = crate::tape::drive::get_tape_device_state(&config, &drive.name)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        table.row(vec![
+            Value::from(drive.name.as_str()),
+            Value::from(drive.changer.as_deref().unwrap_or_default()),
+            Value::Integer(if state.is_empty() { 1 } else { 2 }),
+            Value::from(state.as_str()),
+        ]);
+    }
+
+    let mut table = TableBuilder::new(mib, base.with(&[5]));
+    for changer in &changers {
+        let drive_count = drives
+            .iter()
+            .filter(|drive| drive.changer.as_deref() == Some(changer.name.as_str()))
+            .count();
+        table.row(vec![
+            Value::from(changer.name.as_str()),
+            Value::from(changer.path.as_str()),
+            Value::Gauge32(drive_count as u32),
+        ]);
+    }
+
+    Ok(())
+}
+
+/// Collect the current values of all objects below `base`.
+async fn collect_mib(base: &Oid) -> Mib {
+    let mut mib = Mib::new();
+
+    let version = format!(
+        "{}.{}",
+        pbs_buildcfg::PROXMOX_PKG_VERSION,
+        pbs_buildcfg::PROXMOX_PKG_RELEASE
+    );
+    mib.insert(base.with(&[1, 1, 0]), Value::from(version.as_str()));
+
+    if let Err(err) = collect_datastores(&mut mib, base).await {
+        log::error!("SNMP: unable to collect datastore metrics - {err}");
+    }
+    if let Err(err) = collect_jobs(&mut mib, base) {
+        log::error!("SNMP: unable to collect job metrics - {err}");
+    }
+    if let Err(err) = collect_tape(&mut mib, base) {
+        log::error!("SNMP: unable to collect tape metrics - {err}");
+    }
+
+    mib
+}
+
+/// An AgentX connection, reading PDUs is cancel safe.
+struct Connection {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    async fn send(&mut self, pdu: &[u8]) -> Result<(), Error> {
+        self.stream.write_all(pdu).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<(Header, Vec<u8>), Error> {
+        loop {
+            if self.buffer.len() >= HEADER_SIZE {
+                let header = Header::parse(&self.buffer)?;
+                let length = HEADER_SIZE + header.payload_length as usize;
+                if self.buffer.len() >= length {
+                    let payload = self.buffer[HEADER_SIZE..length].to_vec();
+                    self.buffer.drain(..length);
+                    return Ok((header, payload));
+                }
+            }
+
+            let mut data = [0u8; 4096];
+            let count = self.stream.read(&mut data).await?;
+            if count == 0 {
+                bail!("connection closed by master agent");
+            }
+            self.buffer.extend_from_slice(&data[..count]);
+        }
+    }
+
+    /// Send a request and wait for its response, returns the session ID.
+    async fn request(&mut self, pdu: &[u8], what: &str) -> Result<u32, Error> {
+        self.send(pdu).await?;
+        let (header, payload) = self.receive().await?;
+        if header.ty != agentx::PDU_RESPONSE {
+            bail!("{what} failed - unexpected PDU type {}", header.ty);
+        }
+        let error = agentx::response_error(&header, &payload)?;
+        if error != 0 {
+            bail!("{what} failed - error {error}");
+        }
+        Ok(header.session_id)
+    }
+}
+
+fn read_snmp_config() -> Result<Option<SnmpConfig>, Error> {
+    let (config, _digest) = crate::config::node::config()?;
+    config.snmp_config().transpose()
+}
+
+async fn run_session(config: &SnmpConfig) -> Result<(), Error> {
+    let socket = config.socket.as_deref().unwrap_or(DEFAULT_AGENTX_SOCKET);
+    let base: Oid = config.oid.as_deref().unwrap_or(DEFAULT_SNMP_OID).parse()?;
+
+    let stream = UnixStream::connect(socket)
+        .await
+        .map_err(|err| format_err!("unable to connect to {socket} - {err}"))?;
+    let mut conn = Connection {
+        stream,
+        buffer: Vec::new(),
+    };
+
+    let session_id = conn
+        .request(
+            &agentx::open_pdu(1, &base, "Proxmox Backup Server"),
+            "opening session",
+        )
+        .await?;
+    conn.request(
+        &agentx::register_pdu(session_id, 2, &base),
+        "registering subtree",
+    )
+    .await?;
+    log::info!("SNMP: registered subtree {base} at {socket}");
+
+    let mut mib: Option<(Instant, Mib)> = None;
+    let mut config_check = tokio::time::interval(CONFIG_CHECK_INTERVAL);
+    config_check.tick().await;
+
+    loop {
+        let (header, payload) = tokio::select! {
+            request = conn.receive() => request?,
+            _ = config_check.tick() => {
+                if read_snmp_config().ok().flatten().as_ref() != Some(config) {
+                    log::info!("SNMP: configuration changed, closing session");
+                    let close = agentx::close_pdu(session_id, 3, agentx::CLOSE_REASON_SHUTDOWN);
+                    let _ = conn.send(&close).await;
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+
+        match header.ty {
+            agentx::PDU_CLOSE => bail!("session closed by master agent"),
+            agentx::PDU_RESPONSE => continue, // e.g. for a ping, nothing to do
+            agentx::PDU_GET | agentx::PDU_GET_NEXT | agentx::PDU_GET_BULK => {
+                let outdated = mib
+                    .as_ref()
+                    .map_or(true, |(time, _)| time.elapsed() > MIB_CACHE_TIME);
+                if outdated {
+                    mib = Some((Instant::now(), collect_mib(&base).await));
+                }
+            }
+            _ => (),
+        }
+
+        let empty = Mib::new();
+        let values = mib.as_ref().map(|(_, mib)| mib).unwrap_or(&empty);
+        if let Some(response) = agentx::handle_request(&header, &payload, values, &base)? {
+            conn.send(&response).await?;
+        }
+    }
+}
+
+/// Run the SNMP subagent while it is enabled in the node configuration, reconnecting if the
+/// master agent goes away.
+pub async fn run_snmp_subagent() {
+    let mut last_error = None;
+    loop {
+        let result = match read_snmp_config() {
+            Ok(Some(config)) => run_session(&config).await,
+            Ok(None) => {
+                last_error = None;
+                tokio::time::sleep(CONFIG_CHECK_INTERVAL).await;
+                continue;
+            }
+            Err(err) => Err(err),
+        };
+
+        // only log changed errors, the master agent may be down for a while
+        if let Err(err) = result {
+            let err = err.to_string();
+            if last_error.as_ref() != Some(&err) {
+                log::error!("SNMP: {err}");
+                last_error = Some(err);
+            }
+        } else {
+            last_error = None;
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
@@ -3,11 +3,11 @@ use anyhow::{format_err, Error};
 use pbs_api_types::{Authid, Operation, VerificationJobConfig};
 use pbs_datastore::DataStore;
 use proxmox_rest_server::WorkerTask;
-use proxmox_sys::task_log;
+use proxmox_sys::{task_log, task_warn};
 
 use crate::{
     backup::{verify_all_backups, verify_filter},
-    server::jobstate::Job,
+    server::jobstate::{last_run_time, Job},
 };
 
 /// Runs a verification job.
@@ -20,12 +20,17 @@ pub fn do_verification_job(
 ) -> Result<String, Error> {
     let datastore = DataStore::lookup_datastore(&verification_job.store, Some(Operation::Read))?;
 
+    // used to make the job due again if it gets interrupted by a detached removable device
+    let last_run = last_run_time(job.jobtype(), job.jobname()).unwrap_or(0);
+
     let outdated_after = verification_job.outdated_after;
     let ignore_verified_snapshots = verification_job.ignore_verified.unwrap_or(true);
+    let attest = verification_job.attest.unwrap_or(false);
 
     // FIXME encode namespace here for filter/ACL check?
     let job_id = format!("{}:{}", &verification_job.store, job.jobname());
     let worker_type = job.jobtype().to_string();
+    job.set_store(&verification_job.store);
     let upid_str = WorkerTask::new_thread(
         &worker_type,
         Some(job_id.clone()),
@@ -44,7 +49,10 @@ pub fn do_verification_job(
                 None => Default::default(),
             };
 
-            let verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
+            let mut verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
+            if attest {
+                verify_worker = verify_worker.with_attestation();
+            }
             let result = verify_all_backups(
                 &verify_worker,
                 worker.upid(),
@@ -70,13 +78,43 @@ pub fn do_verification_job(
                 Err(_) => Err(format_err!("verification failed - job aborted")),
             };
 
+            // failed snapshots are part of the report too, only an aborted job has none
+            let job_result = if attest && result.is_ok() {
+                match verify_worker.attest(worker.upid()) {
+                    Ok(()) => {
+                        task_log!(worker, "stored signed verification report");
+                        job_result
+                    }
+                    Err(err) => job_result.and(Err(format_err!(
+                        "creating signed verification report failed - {err}"
+                    ))),
+                }
+            } else {
+                job_result
+            };
+
+            let skipped = verify_worker.skipped_unavailable();
+            if !skipped.is_empty() {
+                task_warn!(
+                    worker,
+                    "skipped {} snapshots/groups, datastore became unavailable - \
+                     job will be re-run once the removable device is attached again",
+                    skipped.len(),
+                );
+            }
+
             let status = worker.create_state(&job_result);
 
             if let Err(err) = job.finish(status) {
                 eprintln!("could not finish job state for {}: {}", job.jobtype(), err);
+            } else if !skipped.is_empty() {
+                if let Err(err) = job.requeue(last_run) {
+                    eprintln!("could not requeue job {}: {}", job.jobname(), err);
+                }
             }
 
-            if let Err(err) = crate::server::send_verify_status(verification_job, &result) {
+            let upid = worker.upid().to_string();
+            if let Err(err) = crate::server::send_verify_status(verification_job, &upid, &result) {
                 eprintln!("send verify notification failed: {err}");
             }
 
@@ -0,0 +1,171 @@
+//! Outbound webhooks for finished and failed backups
+//!
+//! Webhooks configured in `/etc/proxmox-backup/webhook.cfg` get a JSON
+//! summary of each backup snapshot via HTTP POST. Requests are sent in the
+//! background and retried according to the webhook configuration,
+//! independent of the notification system.
+
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use hyper::{Body, Request};
+use serde_json::{json, Value};
+
+use pbs_api_types::{Authid, WebhookConfig};
+use pbs_datastore::BackupDir;
+
+use crate::tools::pbs_simple_http;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn lookup_webhooks(store: &str, success: bool) -> Result<Vec<WebhookConfig>, Error> {
+    let (config, _digest) = pbs_config::webhook::config()?;
+    let mut webhooks: Vec<WebhookConfig> = config.convert_to_typed_array("webhook")?;
+    webhooks.retain(|webhook| webhook.applies_to(store, success));
+    Ok(webhooks)
+}
+
+fn backup_summary(
+    backup_dir: &BackupDir,
+    owner: &Authid,
+    upid: &str,
+    duration: i64,
+    result: &Result<(), Error>,
+) -> Value {
+    let mut data = json!({
+        "event": "backup",
+        "store": backup_dir.datastore().name(),
+        "ns": backup_dir.backup_ns(),
+        "backup-type": backup_dir.backup_type(),
+        "backup-id": backup_dir.backup_id(),
+        "backup-time": backup_dir.backup_time(),
+        "snapshot": backup_dir.dir().to_string(),
+        "owner": owner,
+        "upid": upid,
+        "duration": duration,
+    });
+
+    match result {
+        Ok(()) => data["status"] = "ok".into(),
+        Err(err) => {
+            data["status"] = "error".into();
+            data["error"] = err.to_string().into();
+            return data;
+        }
+    }
+
+    match backup_dir.load_manifest() {
+        Ok((manifest, _)) => {
+            let files: Vec<Value> = manifest
+                .files()
+                .iter()
+                .map(|file| {
+                    json!({
+                        "filename": file.filename,
+                        "size": file.size,
+                        "crypt-mode": file.crypt_mode,
+                    })
+                })
+                .collect();
+            data["size"] = manifest
+                .files()
+                .iter()
+                .map(|file| file.size)
+                .sum::<u64>()
+                .into();
+            data["files"] = files.into();
+            data["verify-state"] = match manifest.unprotected["verify_state"]["state"].as_str() {
+                Some(state) => state.into(),
+                None => "none".into(),
+            };
+        }
+        Err(err) => log::warn!("webhook: unable to load manifest - {err}"),
+    }
+
+    data
+}
+
+async fn post_webhook(webhook: &WebhookConfig, body: &str) -> Result<(), Error> {
+    let proxy_config = match crate::config::node::config() {
+        Ok((node_config, _digest)) => node_config.http_proxy(),
+        Err(_) => None,
+    };
+    let client = pbs_simple_http(proxy_config);
+
+    let mut request = Request::builder()
+        .method("POST")
+        .uri(&webhook.url)
+        .header("Content-Type", "application/json");
+
+    for header in webhook.header.iter().flatten() {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| format_err!("invalid header '{header}'"))?;
+        request = request.header(name.trim(), value.trim());
+    }
+
+    let request = request.body(Body::from(body.to_string()))?;
+
+    let response = tokio::time::timeout(WEBHOOK_TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| format_err!("request timed out"))??;
+
+    let status = response.status();
+    if !status.is_success() {
+        bail!("server returned status {status}");
+    }
+
+    Ok(())
+}
+
+async fn send_webhook(webhook: WebhookConfig, body: String) {
+    let retries = webhook.retries.unwrap_or(3);
+    let delay = Duration::from_secs(webhook.retry_delay.unwrap_or(30));
+
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            tokio::time::sleep(delay).await;
+        }
+        match post_webhook(&webhook, &body).await {
+            Ok(()) => return,
+            Err(err) => log::warn!(
+                "webhook '{}': attempt {} of {} failed - {err}",
+                webhook.name,
+                attempt + 1,
+                retries + 1,
+            ),
+        }
+    }
+
+    log::error!("webhook '{}': giving up", webhook.name);
+}
+
+/// Send the configured webhooks for a finished or failed backup, which took
+/// `duration` seconds.
+///
+/// Requests are sent (and retried) in the background, so this never blocks
+/// the calling task. Must be called from within the tokio runtime.
+pub fn send_backup_webhooks(
+    backup_dir: &BackupDir,
+    owner: &Authid,
+    upid: &str,
+    duration: i64,
+    result: &Result<(), Error>,
+) {
+    let webhooks = match lookup_webhooks(backup_dir.datastore().name(), result.is_ok()) {
+        Ok(webhooks) => webhooks,
+        Err(err) => {
+            log::error!("unable to load webhook config - {err}");
+            return;
+        }
+    };
+    if webhooks.is_empty() {
+        return;
+    }
+
+    let body = backup_summary(backup_dir, owner, upid, duration, result).to_string();
+
+    for webhook in webhooks {
+        tokio::spawn(send_webhook(webhook, body.clone()));
+    }
+}
@@ -0,0 +1,363 @@
+//! Printable barcode label sheets for tape cartridges
+//!
+//! Labels follow the IBM LTO Ultrium Cartridge Label Specification: a six
+//! character volume serial followed by the two character media type
+//! identifier (e.g. `PROX01L8`), which is what changers report as
+//! barcode and what we use as label-text.
+
+use anyhow::{bail, Error};
+
+use pbs_api_types::{BarcodeSymbology, LabelSheetLayout};
+use pbs_tools::pdf::{
+    escape_text, PdfWriter, COURIER_GLYPH_WIDTH, FONT_COURIER_BOLD, POINTS_PER_MM,
+};
+
+use crate::tape::{Inventory, TAPE_STATUS_DIR};
+
+/// LTO label size (mm)
+const LABEL_WIDTH: f64 = 70.0;
+const LABEL_HEIGHT: f64 = 16.9;
+/// Height of the bar code (mm)
+const BARCODE_HEIGHT: f64 = 12.0;
+/// Ratio between wide and narrow elements for Code 39
+const CODE39_WIDE_RATIO: f64 = 2.75;
+
+// Code 39 element patterns: 'B'/'b' wide/narrow bar, 'S'/'s' wide/narrow space
+const CODE39_CHARS: &[(char, &str)] = &[
+    ('0', "bsbSBsBsb"),
+    ('1', "BsbSbsbsB"),
+    ('2', "bsBSbsbsB"),
+    ('3', "BsBSbsbsb"),
+    ('4', "bsbSBsbsB"),
+    ('5', "BsbSBsbsb"),
+    ('6', "bsBSBsbsb"),
+    ('7', "bsbSbsBsB"),
+    ('8', "BsbSbsBsb"),
+    ('9', "bsBSbsBsb"),
+    ('A', "BsbsbSbsB"),
+    ('B', "bsBsbSbsB"),
+    ('C', "BsBsbSbsb"),
+    ('D', "bsbsBSbsB"),
+    ('E', "BsbsBSbsb"),
+    ('F', "bsBsBSbsb"),
+    ('G', "bsbsbSBsB"),
+    ('H', "BsbsbSBsb"),
+    ('I', "bsBsbSBsb"),
+    ('J', "bsbsBSBsb"),
+    ('K', "BsbsbsbSB"),
+    ('L', "bsBsbsbSB"),
+    ('M', "BsBsbsbSb"),
+    ('N', "bsbsBsbSB"),
+    ('O', "BsbsBsbSb"),
+    ('P', "bsBsBsbSb"),
+    ('Q', "bsbsbsBSB"),
+    ('R', "BsbsbsBSb"),
+    ('S', "bsBsbsBSb"),
+    ('T', "bsbsBsBSb"),
+    ('U', "BSbsbsbsB"),
+    ('V', "bSBsbsbsB"),
+    ('W', "BSBsbsbsb"),
+    ('X', "bSbsBsbsB"),
+    ('Y', "BSbsBsbsb"),
+    ('Z', "bSBsBsbsb"),
+    ('-', "bSbsbsBsB"),
+    ('.', "BSbsbsBsb"),
+    (' ', "bSBsbsBsb"),
+    ('*', "bSbsBsBsb"),
+];
+
+// Code 128 element widths (bar, space, bar, ...) for symbol values 0..=106
+const CODE128_PATTERNS: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212",
+    "221213", "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221",
+    "223211", "221132", "221231", "213212", "223112", "312131", "311222", "321122", "321221",
+    "312212", "322112", "322211", "212123", "212321", "232121", "111323", "131123", "131321",
+    "112313", "132113", "132311", "211313", "231113", "231311", "112133", "112331", "132131",
+    "113123", "113321", "133121", "313121", "211331", "231131", "213113", "213311", "213131",
+    "311123", "311321", "331121", "312113", "312311", "332111", "314111", "221411", "431111",
+    "111224", "111422", "121124", "121421", "141122", "141221", "112214", "112412", "122114",
+    "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111", "111242",
+    "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311",
+    "113141", "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+
+const CODE128_START_B: usize = 104;
+const CODE128_STOP: usize = 106;
+
+/// Generate `count` label texts, starting with label number `start`.
+///
+/// The label number is zero-padded to fill up the six character volume
+/// serial after the `prefix`.
+pub fn barcode_label_texts(
+    prefix: &str,
+    start: u64,
+    count: u64,
+    media_type: &str,
+) -> Result<Vec<String>, Error> {
+    if prefix.len() > 5 {
+        bail!("label prefix '{prefix}' is too long (max. 5 characters)");
+    }
+    let digits = 6 - prefix.len();
+    let end = start + count;
+    if end > 10u64.pow(digits as u32) {
+        bail!(
+            "label numbers {start}..{end} do not fit into {digits} digits after prefix '{prefix}'"
+        );
+    }
+
+    Ok((start..end)
+        .map(|nr| format!("{prefix}{nr:0digits$}{media_type}"))
+        .collect())
+}
+
+/// Remove label texts already used by media in the inventory.
+pub fn retain_unused_label_texts(label_texts: &mut Vec<String>) -> Result<(), Error> {
+    let inventory = Inventory::load(TAPE_STATUS_DIR)?;
+    label_texts
+        .retain(|label_text| matches!(inventory.find_media_by_label_text(label_text), Ok(None)));
+    Ok(())
+}
+
+/// Encode `text` as Code 39 (including start/stop characters).
+///
+/// Returns the widths of alternating bars and spaces, starting with a bar.
+pub fn code39_encode(text: &str) -> Result<Vec<f64>, Error> {
+    let mut widths = Vec::new();
+
+    for c in format!("*{text}*").chars() {
+        let pattern = match CODE39_CHARS.iter().find(|(code, _)| *code == c) {
+            Some((_, pattern)) => pattern,
+            None => bail!("unable to encode character '{c}' with code 39"),
+        };
+        if !widths.is_empty() {
+            widths.push(1.0); // inter-character gap
+        }
+        for element in pattern.chars() {
+            widths.push(if element.is_ascii_uppercase() {
+                CODE39_WIDE_RATIO
+            } else {
+                1.0
+            });
+        }
+    }
+
+    Ok(widths)
+}
+
+/// Encode `text` as Code 128 (code set B, including checksum).
+///
+/// Returns the widths of alternating bars and spaces, starting with a bar.
+pub fn code128_encode(text: &str) -> Result<Vec<f64>, Error> {
+    let mut symbols = vec![CODE128_START_B];
+
+    for c in text.chars() {
+        if !(' '..='~').contains(&c) {
+            bail!("unable to encode character '{c}' with code 128");
+        }
+        symbols.push(c as usize - 32);
+    }
+
+    let checksum = symbols
+        .iter()
+        .enumerate()
+        .fold(0, |sum, (pos, symbol)| sum + pos.max(1) * symbol)
+        % 103;
+    symbols.push(checksum);
+    symbols.push(CODE128_STOP);
+
+    Ok(symbols
+        .into_iter()
+        .flat_map(|symbol| CODE128_PATTERNS[symbol].bytes())
+        .map(|width| (width - b'0') as f64)
+        .collect())
+}
+
+struct PageLayout {
+    page_width: f64,
+    page_height: f64,
+    margin_left: f64,
+    margin_top: f64,
+    column_spacing: f64,
+    row_spacing: f64,
+    borders: bool,
+}
+
+impl From<LabelSheetLayout> for PageLayout {
+    fn from(layout: LabelSheetLayout) -> Self {
+        match layout {
+            LabelSheetLayout::Avery3420 => PageLayout {
+                page_width: 210.0,
+                page_height: 297.0,
+                margin_left: 0.0,
+                margin_top: 5.0,
+                column_spacing: 0.0,
+                row_spacing: 0.0,
+                borders: false,
+            },
+            LabelSheetLayout::A4 => PageLayout {
+                page_width: 210.0,
+                page_height: 297.0,
+                margin_left: 0.0,
+                margin_top: 5.0,
+                column_spacing: 0.0,
+                row_spacing: 0.0,
+                borders: true,
+            },
+            LabelSheetLayout::Letter => PageLayout {
+                page_width: 215.9,
+                page_height: 279.4,
+                margin_left: 2.95,
+                margin_top: 4.5,
+                column_spacing: 0.0,
+                row_spacing: 0.0,
+                borders: true,
+            },
+        }
+    }
+}
+
+impl PageLayout {
+    /// Label positions (left, top) in mm, in reading order.
+    fn label_positions(&self) -> Vec<(f64, f64)> {
+        let mut positions = Vec::new();
+
+        let mut top = self.margin_top;
+        while top + LABEL_HEIGHT <= self.page_height {
+            let mut left = self.margin_left;
+            while left + LABEL_WIDTH <= self.page_width {
+                positions.push((left, top));
+                left += LABEL_WIDTH + self.column_spacing;
+            }
+            top += LABEL_HEIGHT + self.row_spacing;
+        }
+
+        positions
+    }
+}
+
+/// Render a single label at the given position (mm from top left of the page).
+fn render_label(
+    content: &mut String,
+    layout: &PageLayout,
+    label_text: &str,
+    widths: &[f64],
+    left: f64,
+    top: f64,
+) {
+    // PDF coordinates start at the bottom left
+    let bottom = layout.page_height - top - LABEL_HEIGHT;
+
+    if layout.borders {
+        content.push_str(&format!(
+            "0.1 w {left:.3} {bottom:.3} {LABEL_WIDTH:.3} {LABEL_HEIGHT:.3} re S\n"
+        ));
+    }
+
+    // human readable text above the bar code
+    let font_size = 3.5;
+    let text_width = label_text.len() as f64 * font_size * COURIER_GLYPH_WIDTH;
+    content.push_str(&format!(
+        "BT /{FONT_COURIER_BOLD} {font_size} Tf {:.3} {:.3} Td ({}) Tj ET\n",
+        left + (LABEL_WIDTH - text_width) / 2.0,
+        bottom + BARCODE_HEIGHT + 1.2,
+        escape_text(label_text),
+    ));
+
+    // bar code, using 10/12 of the label width (leaving quiet zones on both sides)
+    let barcode_width = LABEL_WIDTH * 10.0 / 12.0;
+    let unit = barcode_width / widths.iter().sum::<f64>();
+    let mut x = left + (LABEL_WIDTH - barcode_width) / 2.0;
+    for (i, width) in widths.iter().enumerate() {
+        let width = width * unit;
+        if i % 2 == 0 {
+            content.push_str(&format!(
+                "{x:.3} {:.3} {width:.3} {BARCODE_HEIGHT:.3} re\n",
+                bottom + 0.5,
+            ));
+        }
+        x += width;
+    }
+    content.push_str("f\n");
+}
+
+/// Generate a PDF with barcode label sheets for the given label texts.
+pub fn barcode_label_sheet_pdf(
+    label_texts: &[String],
+    symbology: BarcodeSymbology,
+    layout: LabelSheetLayout,
+) -> Result<Vec<u8>, Error> {
+    let layout = PageLayout::from(layout);
+    let positions = layout.label_positions();
+
+    let mut pdf = PdfWriter::new();
+
+    for page_labels in label_texts.chunks(positions.len()) {
+        // work in mm, black fill and stroke
+        let mut content = format!("q {POINTS_PER_MM:.6} 0 0 {POINTS_PER_MM:.6} 0 0 cm 0 g 0 G\n");
+
+        for (label_text, (left, top)) in page_labels.iter().zip(positions.iter()) {
+            let widths = match symbology {
+                BarcodeSymbology::Code39 => code39_encode(label_text)?,
+                BarcodeSymbology::Code128 => code128_encode(label_text)?,
+            };
+            render_label(&mut content, &layout, label_text, &widths, *left, *top);
+        }
+
+        content.push_str("Q\n");
+
+        pdf.add_page(
+            layout.page_width * POINTS_PER_MM,
+            layout.page_height * POINTS_PER_MM,
+            &content,
+        );
+    }
+
+    Ok(pdf.finish())
+}
+
+#[test]
+fn test_barcode_label_texts() -> Result<(), Error> {
+    assert_eq!(
+        barcode_label_texts("PROX", 9, 2, "L8")?,
+        vec!["PROX09L8".to_string(), "PROX10L8".to_string()],
+    );
+    assert_eq!(barcode_label_texts("", 1, 1, "CU")?, vec!["000001CU"]);
+    assert!(barcode_label_texts("PROX", 99, 2, "L8").is_err());
+    assert!(barcode_label_texts("PROXMO", 0, 1, "L8").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_barcode_encoding() -> Result<(), Error> {
+    for (_, pattern) in CODE39_CHARS {
+        assert_eq!(pattern.len(), 9);
+        // each code 39 character has exactly three wide elements
+        assert_eq!(
+            pattern.chars().filter(|c| c.is_ascii_uppercase()).count(),
+            3
+        );
+    }
+    for (value, pattern) in CODE128_PATTERNS.iter().enumerate() {
+        let modules: u32 = pattern.bytes().map(|b| (b - b'0') as u32).sum();
+        assert_eq!(modules, if value == CODE128_STOP { 13 } else { 11 });
+    }
+
+    // start + 8 characters + stop, 9 elements each plus inter-character gaps
+    assert_eq!(code39_encode("PROX01L8")?.len(), 10 * 9 + 9);
+    assert!(code39_encode("prox01l8").is_err());
+
+    // (104 + 48*1 + 42*2 + 42*3 + 17*4 + 18*5 + 19*6 + 35*7) % 103 = 55
+    let widths = code128_encode("PJJ123C")?;
+    let checksum_pattern: Vec<f64> = CODE128_PATTERNS[55]
+        .bytes()
+        .map(|b| (b - b'0') as f64)
+        .collect();
+    assert_eq!(
+        &widths[widths.len() - 13..widths.len() - 7],
+        &checksum_pattern[..]
+    );
+
+    Ok(())
+}
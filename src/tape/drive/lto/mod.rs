@@ -27,6 +27,7 @@ use pbs_tape::{
     sg_tape::{SgTape, TapeAlertFlags},
     BlockReadError, MediaContentHeader, TapeRead, TapeWrite,
 };
+use pbs_tools::async_io::AbortSignal;
 use proxmox_sys::command::run_command;
 
 use crate::tape::{
@@ -268,6 +269,10 @@ impl TapeDriver for LtoTapeHandle {
         }
         Ok(())
     }
+
+    fn set_abort_signal(&mut self, abort: Option<AbortSignal>) {
+        self.sg_tape.set_abort_signal(abort);
+    }
 }
 
 fn run_sg_tape_cmd(subcmd: &str, args: &[&str], fd: RawFd) -> Result<String, Error> {
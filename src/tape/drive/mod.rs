@@ -24,6 +24,7 @@ use proxmox_uuid::Uuid;
 
 use pbs_api_types::{Fingerprint, LtoTapeDrive, VirtualTapeDrive};
 use pbs_key_config::KeyConfig;
+use pbs_tools::async_io::{is_timeout, lock_file_cancellable, AbortSignal};
 
 use pbs_tape::{sg_tape::TapeAlertFlags, BlockReadError, MediaContentHeader, TapeRead, TapeWrite};
 
@@ -242,6 +243,11 @@ pub trait TapeDriver {
         }
         Ok(())
     }
+
+    /// Set a signal to interrupt long waits for the drive
+    ///
+    /// Drives which never wait for the hardware can simply ignore this (default).
+    fn set_abort_signal(&mut self, _abort: Option<AbortSignal>) {}
 }
 
 /// A boxed implementor of [`MediaChange`].
@@ -544,7 +550,7 @@ pub fn lock_tape_device(
     drive: &str,
 ) -> Result<DeviceLockGuard, TapeLockError> {
     let path = tape_device_path(config, drive)?;
-    lock_device_path(&path).map_err(|err| match err {
+    lock_device_path(&path, Some(std::time::Duration::new(10, 0)), None).map_err(|err| match err {
         TapeLockError::Other(err) => {
             TapeLockError::Other(format_err!("unable to lock drive '{}' - {}", drive, err))
         }
@@ -552,6 +558,20 @@ pub fn lock_tape_device(
     })
 }
 
+/// Acquires an exclusive lock for the tape device, waiting until it is available or `abort` is
+/// triggered.
+pub fn lock_tape_device_cancellable(
+    config: &SectionConfigData,
+    drive: &str,
+    abort: &AbortSignal,
+) -> Result<DeviceLockGuard, Error> {
+    let path = tape_device_path(config, drive)?;
+    lock_device_path(&path, None, Some(abort)).map_err(|err| match err {
+        TapeLockError::Other(err) => format_err!("unable to lock drive '{}' - {}", drive, err),
+        TapeLockError::TimeOut => format_err!("unable to lock drive '{}' - got timeout", drive),
+    })
+}
+
 /// Writes the given state for the specified drive
 ///
 /// This function does not lock, so make sure the drive is locked
@@ -628,13 +648,16 @@ fn open_device_lock(device_path: &str) -> Result<std::fs::File, Error> {
     )
 }
 
-// Acquires an exclusive lock on `device_path`
-//
-fn lock_device_path(device_path: &str) -> Result<DeviceLockGuard, TapeLockError> {
+// Acquires an exclusive lock on `device_path`, waiting at most `timeout`
+// or until `abort` is triggered.
+fn lock_device_path(
+    device_path: &str,
+    timeout: Option<std::time::Duration>,
+    abort: Option<&AbortSignal>,
+) -> Result<DeviceLockGuard, TapeLockError> {
     let mut file = open_device_lock(device_path)?;
-    let timeout = std::time::Duration::new(10, 0);
-    if let Err(err) = lock_file(&mut file, true, Some(timeout)) {
-        if err.kind() == std::io::ErrorKind::Interrupted {
+    if let Err(err) = lock_file_cancellable(&mut file, true, timeout, abort) {
+        if is_timeout(&err) {
             return Err(TapeLockError::TimeOut);
         } else {
             return Err(err.into());
@@ -405,13 +405,22 @@ impl Inventory {
 
     /// Returns the latest media set for a pool
     pub fn latest_media_set(&self, pool: &str) -> Option<Uuid> {
+        self.latest_media_set_filtered(pool, &|_| true)
+    }
+
+    /// Like latest_media_set(), but only considers media sets accepted by `filter`
+    pub fn latest_media_set_filtered(
+        &self,
+        pool: &str,
+        filter: &dyn Fn(&Uuid) -> bool,
+    ) -> Option<Uuid> {
         let mut last_set: Option<(Uuid, i64)> = None;
 
         let set_list = self
             .map
             .values()
             .filter_map(|entry| entry.id.media_set_label.as_ref())
-            .filter(|set| set.pool == pool && !set.unassigned());
+            .filter(|set| set.pool == pool && !set.unassigned() && filter(&set.uuid));
 
         for set in set_list {
             match last_set {
@@ -436,7 +445,7 @@ impl Inventory {
             .map
             .values()
             .filter_map(|entry| entry.id.media_set_label.as_ref())
-            .filter(|set| set.pool == pool && !set.unassigned());
+            .filter(|set| set.pool == pool && !set.unassigned() && filter(&set.uuid));
 
         for set in set_list {
             if set.uuid != uuid && set.ctime >= ctime {
@@ -452,9 +461,13 @@ impl Inventory {
         Some(uuid)
     }
 
-    // Test if there is a media set (in the same pool) newer than this one.
-    // Return the ctime of the nearest media set
-    fn media_set_next_start_time(&self, media_set_uuid: &Uuid) -> Option<i64> {
+    // Test if there is a media set (in the same pool, accepted by `filter`)
+    // newer than this one. Return the ctime of the nearest media set
+    fn media_set_next_start_time(
+        &self,
+        media_set_uuid: &Uuid,
+        filter: &dyn Fn(&Uuid) -> bool,
+    ) -> Option<i64> {
         let (pool, ctime) = match self
             .map
             .values()
@@ -474,7 +487,7 @@ impl Inventory {
             .map
             .values()
             .filter_map(|entry| entry.id.media_set_label.as_ref())
-            .filter(|set| (&set.uuid != media_set_uuid) && (set.pool == pool));
+            .filter(|set| (&set.uuid != media_set_uuid) && (set.pool == pool) && filter(&set.uuid));
 
         let mut next_ctime = None;
 
@@ -501,6 +514,18 @@ impl Inventory {
         media: &MediaId,
         media_set_policy: &MediaSetPolicy,
         retention_policy: &RetentionPolicy,
+    ) -> i64 {
+        self.media_expire_time_filtered(media, media_set_policy, retention_policy, &|_| true)
+    }
+
+    /// Like media_expire_time(), but only media sets accepted by `filter`
+    /// count as successors (used for pools with media set slots).
+    pub fn media_expire_time_filtered(
+        &self,
+        media: &MediaId,
+        media_set_policy: &MediaSetPolicy,
+        retention_policy: &RetentionPolicy,
+        filter: &dyn Fn(&Uuid) -> bool,
     ) -> i64 {
         if let RetentionPolicy::KeepForever = retention_policy {
             return i64::MAX;
@@ -520,7 +545,7 @@ impl Inventory {
             Some(time) => time,
         };
 
-        let max_use_time = match self.media_set_next_start_time(&set.uuid) {
+        let max_use_time = match self.media_set_next_start_time(&set.uuid, filter) {
             Some(next_start_time) => match media_set_policy {
                 MediaSetPolicy::AlwaysCreate => set_start_time,
                 _ => next_start_time,
@@ -15,14 +15,16 @@ use serde::{Deserialize, Serialize};
 use proxmox_uuid::Uuid;
 
 use pbs_api_types::{
-    Fingerprint, MediaLocation, MediaPoolConfig, MediaSetPolicy, MediaStatus, RetentionPolicy,
+    Fingerprint, MediaLocation, MediaPoolConfig, MediaSetPolicy, MediaSetSlots, MediaStatus,
+    RetentionPolicy,
 };
 use pbs_config::BackupLockGuard;
 
 use crate::tape::{
     file_formats::{MediaLabel, MediaSetLabel},
-    lock_media_pool, lock_media_set, lock_unassigned_media_pool, Inventory, MediaCatalog, MediaId,
-    MediaSet,
+    lock_media_pool, lock_media_set, lock_unassigned_media_pool,
+    media_set_slots::MediaSetSlotMap,
+    Inventory, MediaCatalog, MediaId, MediaSet,
 };
 
 /// Media Pool
@@ -46,6 +48,10 @@ pub struct MediaPool {
 
     current_media_set: MediaSet,
     current_media_set_lock: Option<BackupLockGuard>,
+
+    // only set for pools using media set slots
+    slot_map: Option<MediaSetSlotMap>,
+    slot: Option<String>,
 }
 
 impl MediaPool {
@@ -96,6 +102,8 @@ impl MediaPool {
             encrypt_fingerprint,
             force_media_availability: false,
             no_media_set_locking,
+            slot_map: None,
+            slot: None,
         })
     }
 
@@ -135,15 +143,60 @@ impl MediaPool {
             None => None,
         };
 
-        MediaPool::new(
+        let slots: MediaSetSlots = match config.slots {
+            Some(ref slots) => slots.parse()?,
+            None => MediaSetSlots::default(),
+        };
+
+        let mut pool = MediaPool::new(
             &config.name,
-            state_path,
+            &state_path,
             allocation,
             retention,
             changer_name,
             encrypt_fingerprint,
             no_media_set_locking,
-        )
+        )?;
+
+        if !slots.is_empty() {
+            pool.slot_map = Some(MediaSetSlotMap::load(&state_path, &config.name)?);
+        }
+
+        Ok(pool)
+    }
+
+    /// Select the media set slot used for writing
+    ///
+    /// The current media set becomes the latest media set of that slot.
+    /// This is a no-op for pools without media set slots.
+    pub fn select_slot(&mut self, slot: Option<String>) -> Result<(), Error> {
+        let slot_map = match self.slot_map {
+            Some(ref slot_map) => slot_map,
+            None => return Ok(()),
+        };
+
+        let current_media_set = match self
+            .inventory
+            .latest_media_set_filtered(&self.name, &|uuid| slot_map.in_slot(uuid, slot.as_deref()))
+        {
+            Some(set_uuid) => self.inventory.compute_media_set_members(&set_uuid)?,
+            None => MediaSet::new(),
+        };
+
+        // release the old lock first, it may refer to the same media set
+        self.current_media_set_lock = None;
+        if !self.no_media_set_locking {
+            self.current_media_set_lock = Some(lock_media_set(
+                &self.state_path,
+                current_media_set.uuid(),
+                None,
+            )?);
+        }
+
+        self.current_media_set = current_media_set;
+        self.slot = slot;
+
+        Ok(())
     }
 
     /// Returns the pool name
@@ -259,6 +312,9 @@ impl MediaPool {
         };
 
         self.inventory.reload()?;
+        if self.slot_map.is_some() {
+            self.slot_map = Some(MediaSetSlotMap::load(&self.state_path, &self.name)?);
+        }
 
         let mut create_new_set = if force {
             Some(String::from("forced"))
@@ -300,6 +356,12 @@ impl MediaPool {
                 Some(lock_media_set(&self.state_path, media_set.uuid(), None)?)
             };
 
+            if !self.no_media_set_locking {
+                if let (Some(slot_map), Some(slot)) = (&mut self.slot_map, &self.slot) {
+                    slot_map.register(media_set.uuid(), slot, &self.inventory)?;
+                }
+            }
+
             self.current_media_set_lock = current_media_set_lock;
             self.current_media_set = media_set;
         }
@@ -325,9 +387,23 @@ impl MediaPool {
             return false;
         }
 
-        let expire_time =
-            self.inventory
-                .media_expire_time(media.id(), &self.media_set_policy, &self.retention);
+        let expire_time = match (&self.slot_map, media.media_set_label()) {
+            (Some(slot_map), Some(set)) => {
+                // only media sets from the same slot replace this one
+                let slot = slot_map.slot(&set.uuid);
+                self.inventory.media_expire_time_filtered(
+                    media.id(),
+                    &self.media_set_policy,
+                    &self.retention,
+                    &|uuid| slot_map.slot(uuid) == slot,
+                )
+            }
+            _ => self.inventory.media_expire_time(
+                media.id(),
+                &self.media_set_policy,
+                &self.retention,
+            ),
+        };
 
         current_time >= expire_time
     }
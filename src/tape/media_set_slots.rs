@@ -0,0 +1,118 @@
+//! Media set slots
+//!
+//! Pools configured with `slots` keep a separate chain of media sets for
+//! each slot (for example one per weekday). The inventory does not know
+//! about slots, so we remember the slot of each media set we create in
+//! a small per-pool state file.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use serde_json::json;
+
+use proxmox_sys::fs::{file_get_json, replace_file, CreateOptions};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{BackupType, MediaSetSlots};
+
+use crate::tape::Inventory;
+
+/// Compute the slot name for a backup started at `backup_time`.
+///
+/// Returns `None` if the pool does not use slots. Weekday slots use the
+/// local time zone, like calendar events do.
+pub fn media_set_slot(
+    slots: &MediaSetSlots,
+    backup_time: i64,
+    backup_type: Option<BackupType>,
+) -> Result<Option<String>, Error> {
+    let mut parts = Vec::new();
+
+    if slots.weekday {
+        parts.push(proxmox_time::strftime_local("%a", backup_time)?.to_lowercase());
+    }
+
+    if slots.backup_type {
+        if let Some(backup_type) = backup_type {
+            parts.push(backup_type.to_string());
+        }
+    }
+
+    if parts.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(parts.join("/")))
+}
+
+/// Maps media set UUIDs to slot names (for a single pool)
+pub struct MediaSetSlotMap {
+    path: PathBuf,
+    map: BTreeMap<String, String>,
+}
+
+impl MediaSetSlotMap {
+    /// Load the slot map of `pool` (callers must hold the pool lock for updates)
+    pub fn load<P: AsRef<Path>>(state_path: P, pool: &str) -> Result<Self, Error> {
+        let mut path = state_path.as_ref().to_owned();
+        path.push(format!("{pool}.media-set-slots.json"));
+
+        let data = file_get_json(&path, Some(json!({})))?;
+        let map = serde_json::from_value(data)?;
+
+        Ok(Self { path, map })
+    }
+
+    /// Returns the slot of a media set, if it was created for a slot
+    pub fn slot(&self, media_set_uuid: &Uuid) -> Option<&str> {
+        self.map
+            .get(&media_set_uuid.to_string())
+            .map(String::as_str)
+    }
+
+    /// Test if a media set belongs to `slot` (`None` matches sets without slot)
+    pub fn in_slot(&self, media_set_uuid: &Uuid, slot: Option<&str>) -> bool {
+        self.slot(media_set_uuid) == slot
+    }
+
+    /// Remember the slot of a newly created media set
+    ///
+    /// Entries of media sets no longer found in the `inventory` (all their
+    /// media got relabeled or removed) are dropped at the same time, so the
+    /// state file does not grow forever.
+    pub fn register(
+        &mut self,
+        media_set_uuid: &Uuid,
+        slot: &str,
+        inventory: &Inventory,
+    ) -> Result<(), Error> {
+        let media_sets = inventory.compute_media_set_list()?;
+        self.map.retain(|uuid, _| match uuid.parse::<Uuid>() {
+            Ok(uuid) => media_sets.contains_key(&uuid),
+            Err(_) => false,
+        });
+
+        self.map
+            .insert(media_set_uuid.to_string(), slot.to_string());
+
+        let raw = serde_json::to_string_pretty(&self.map)?;
+
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0640);
+
+        let options = if cfg!(test) {
+            // We cannot use chown inside test environment (no permissions)
+            CreateOptions::new().perm(mode)
+        } else {
+            let backup_user = pbs_config::backup_user()?;
+            CreateOptions::new()
+                .perm(mode)
+                .owner(backup_user.uid)
+                .group(backup_user.gid)
+        };
+
+        replace_file(&self.path, raw.as_bytes(), options, true)?;
+
+        Ok(())
+    }
+}
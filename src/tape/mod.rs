@@ -10,6 +10,8 @@ use pbs_buildcfg::{PROXMOX_BACKUP_RUN_DIR_M, PROXMOX_BACKUP_STATE_DIR_M};
 #[cfg(test)]
 mod test;
 
+pub mod barcode_labels;
+
 pub mod file_formats;
 
 mod media_set;
@@ -25,6 +27,8 @@ pub mod encryption_keys;
 mod media_pool;
 pub use media_pool::*;
 
+pub mod media_set_slots;
+
 mod media_catalog;
 pub use media_catalog::*;
 
@@ -56,6 +56,7 @@ pub struct PoolWriter {
     notification_mode: TapeNotificationMode,
     ns_magic: bool,
     used_tapes: HashSet<Uuid>,
+    read_tuning: Arc<ChunkReaderTuning>,
 }
 
 impl PoolWriter {
@@ -94,6 +95,7 @@ impl PoolWriter {
             notification_mode,
             ns_magic,
             used_tapes: HashSet::new(),
+            read_tuning: Arc::new(ChunkReaderTuning::new()),
         })
     }
 
@@ -526,6 +528,10 @@ impl PoolWriter {
             (bytes_written as f64) / (1_000_000.0 * elapsed),
         );
 
+        if let Some(msg) = self.read_tuning.adjust() {
+            task_log!(worker, "{}", msg);
+        }
+
         let request_sync = status.bytes_written >= COMMIT_BLOCK_SIZE;
 
         // register chunks in media_catalog
@@ -548,7 +554,12 @@ impl PoolWriter {
         datastore: Arc<DataStore>,
         snapshot_reader: Arc<Mutex<SnapshotReader>>,
     ) -> Result<(std::thread::JoinHandle<()>, NewChunksIterator), Error> {
-        NewChunksIterator::spawn(datastore, snapshot_reader, Arc::clone(&self.catalog_set))
+        NewChunksIterator::spawn(
+            datastore,
+            snapshot_reader,
+            Arc::clone(&self.catalog_set),
+            Arc::clone(&self.read_tuning),
+        )
     }
 
     pub(crate) fn catalog_version(&self) -> [u8; 8] {
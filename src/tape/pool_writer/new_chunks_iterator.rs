@@ -1,19 +1,213 @@
 use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::{format_err, Error};
+use anyhow::{bail, format_err, Error};
 
 use pbs_datastore::{DataBlob, DataStore, SnapshotReader};
 
 use crate::tape::CatalogSet;
 
-/// Chunk iterator which use a separate thread to read chunks
+/// Number of chunks read ahead of the tape writer at job start
+const INITIAL_READ_AHEAD: usize = 3;
+/// Upper limit for the read-ahead (chunks are up to 4 MiB)
+const MAX_READ_AHEAD: usize = 32;
+/// Upper limit for the number of chunk reader threads
+const MAX_READER_THREADS: usize = 8;
+/// Minimal measurement duration before the pipeline gets adjusted
+const TUNING_INTERVAL: Duration = Duration::from_secs(10);
+/// Extend the pipeline if the writer waits for chunks more than this fraction of the time
+const STARVATION_HIGH: f64 = 0.10;
+/// Shrink the pipeline if the writer waits for chunks less than this fraction of the time ..
+const STARVATION_LOW: f64 = 0.01;
+/// .. for this many consecutive measurements
+const STARVATION_LOW_COUNT: usize = 6;
+
+struct TuningState {
+    read_ahead: usize,
+    threads: usize,
+    // start of the current measurement
+    since: Instant,
+    // time the tape writer waited for chunks since then
+    starved: Duration,
+    // consecutive measurements without noticeable starvation
+    low_count: usize,
+}
+
+/// Adaptive limits of the chunk reader pipeline
+///
+/// The LTO volume statistics do not include drive buffer underruns, so
+/// the time the tape writer has to wait for chunks is used instead. If
+/// the writer starves, the drive runs out of data and has to reposition
+/// the tape (shoe-shining), so read-ahead and reader threads are
+/// increased. The state is shared by all snapshots of a job.
+pub struct ChunkReaderTuning {
+    state: Mutex<TuningState>,
+}
+
+impl ChunkReaderTuning {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(TuningState {
+                read_ahead: INITIAL_READ_AHEAD,
+                threads: 1,
+                since: Instant::now(),
+                starved: Duration::ZERO,
+                low_count: 0,
+            }),
+        }
+    }
+
+    /// Number of chunks read ahead of the tape writer
+    pub fn read_ahead(&self) -> usize {
+        self.state.lock().unwrap().read_ahead
+    }
+
+    /// Number of chunk reader threads
+    pub fn threads(&self) -> usize {
+        self.state.lock().unwrap().threads
+    }
+
+    fn add_starvation(&self, waited: Duration) {
+        self.state.lock().unwrap().starved += waited;
+    }
+
+    /// Adjust the pipeline to the measured starvation of the tape writer.
+    ///
+    /// Returns a message describing the change, if any.
+    pub fn adjust(&self) -> Option<String> {
+        let elapsed = self.state.lock().unwrap().since.elapsed();
+        self.adjust_after(elapsed)
+    }
+
+    // adjust with `elapsed` as duration of the current measurement
+    fn adjust_after(&self, elapsed: Duration) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+
+        if elapsed < TUNING_INTERVAL {
+            return None;
+        }
+        let starvation = state.starved.as_secs_f64() / elapsed.as_secs_f64();
+        state.since = Instant::now();
+        state.starved = Duration::ZERO;
+
+        if starvation > STARVATION_HIGH {
+            state.low_count = 0;
+            if state.read_ahead >= MAX_READ_AHEAD && state.threads >= MAX_READER_THREADS {
+                return None;
+            }
+            state.read_ahead = (state.read_ahead * 2).min(MAX_READ_AHEAD);
+            state.threads = (state.threads + 1).min(MAX_READER_THREADS);
+        } else if starvation < STARVATION_LOW {
+            state.low_count += 1;
+            if state.low_count < STARVATION_LOW_COUNT || state.threads <= 1 {
+                return None;
+            }
+            state.low_count = 0;
+            state.threads -= 1;
+        } else {
+            state.low_count = 0;
+            return None;
+        }
+
+        Some(format!(
+            "tape writer waited for data {:.1}% of the time - using read-ahead of {} chunks with {} reader threads",
+            starvation * 100.0,
+            state.read_ahead,
+            state.threads,
+        ))
+    }
+}
+
+impl Default for ChunkReaderTuning {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// number of chunks dispatched to the reader threads, but not yet consumed
+#[derive(Default)]
+struct InFlight {
+    state: Mutex<(usize, bool)>,
+    cond: Condvar,
+}
+
+impl InFlight {
+    fn acquire(&self, tuning: &ChunkReaderTuning) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.1 {
+                bail!("chunk iterator closed");
+            }
+            if state.0 < tuning.read_ahead() {
+                state.0 += 1;
+                return Ok(());
+            }
+            state = self.cond.wait(state).unwrap();
+        }
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = state.0.saturating_sub(1);
+        self.cond.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.cond.notify_all();
+    }
+}
+
+type ChunkResult = Result<([u8; 32], DataBlob), Error>;
+
+fn chunk_loader(
+    datastore: Arc<DataStore>,
+    work_rx: Arc<Mutex<std::sync::mpsc::Receiver<[u8; 32]>>>,
+    tx: std::sync::mpsc::Sender<ChunkResult>,
+    tuning: Arc<ChunkReaderTuning>,
+    active: Arc<AtomicUsize>,
+) {
+    loop {
+        // stop if the number of reader threads was lowered, but keep at least one
+        let count = active.load(Ordering::SeqCst);
+        if count > tuning.threads()
+            && active
+                .compare_exchange(count, count - 1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        {
+            return;
+        }
+
+        let digest = match work_rx.lock().unwrap().recv() {
+            Ok(digest) => digest,
+            Err(_) => break, // all chunks dispatched
+        };
+
+        let result = datastore.load_chunk(&digest).map(|blob| (digest, blob));
+        let failed = result.is_err();
+        if let Err(err) = tx.send(result) {
+            eprintln!("could not send chunk to reader thread: {err}");
+            break;
+        }
+        if failed {
+            break;
+        }
+    }
+    active.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// Chunk iterator which use separate threads to read chunks
 ///
 /// The iterator skips duplicate chunks and chunks already in the
-/// catalog.
+/// catalog. Chunks are read by a varying number of threads, see
+/// `ChunkReaderTuning`, so they are not returned in index order.
 pub struct NewChunksIterator {
-    #[allow(clippy::type_complexity)]
-    rx: std::sync::mpsc::Receiver<Result<Option<([u8; 32], DataBlob)>, Error>>,
+    rx: std::sync::mpsc::Receiver<ChunkResult>,
+    in_flight: Arc<InFlight>,
+    tuning: Arc<ChunkReaderTuning>,
+    done: Arc<AtomicBool>,
 }
 
 impl NewChunksIterator {
@@ -24,57 +218,91 @@ impl NewChunksIterator {
         datastore: Arc<DataStore>,
         snapshot_reader: Arc<Mutex<SnapshotReader>>,
         catalog_set: Arc<Mutex<CatalogSet>>,
+        tuning: Arc<ChunkReaderTuning>,
     ) -> Result<(std::thread::JoinHandle<()>, Self), Error> {
-        let (tx, rx) = std::sync::mpsc::sync_channel(3);
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let in_flight = Arc::new(InFlight::default());
+        let done = Arc::new(AtomicBool::new(false));
+
+        let reader_thread = {
+            let in_flight = Arc::clone(&in_flight);
+            let tuning = Arc::clone(&tuning);
+            let done = Arc::clone(&done);
+
+            std::thread::spawn(move || {
+                let snapshot_reader = snapshot_reader.lock().unwrap();
 
-        let reader_thread = std::thread::spawn(move || {
-            let snapshot_reader = snapshot_reader.lock().unwrap();
+                let mut chunk_index: HashSet<[u8; 32]> = HashSet::new();
 
-            let mut chunk_index: HashSet<[u8; 32]> = HashSet::new();
+                let datastore_name = snapshot_reader.datastore_name().to_string();
 
-            let datastore_name = snapshot_reader.datastore_name().to_string();
+                let (work_tx, work_rx) = std::sync::mpsc::channel();
+                let work_rx = Arc::new(Mutex::new(work_rx));
+                let active = Arc::new(AtomicUsize::new(0));
+                let mut loaders = Vec::new();
 
-            let result: Result<(), Error> = proxmox_lang::try_block!({
-                let mut chunk_iter = snapshot_reader.chunk_iterator(move |digest| {
-                    catalog_set
-                        .lock()
-                        .unwrap()
-                        .contains_chunk(&datastore_name, digest)
-                })?;
+                let result: Result<(), Error> = proxmox_lang::try_block!({
+                    let chunk_iter = snapshot_reader.chunk_iterator(move |digest| {
+                        catalog_set
+                            .lock()
+                            .unwrap()
+                            .contains_chunk(&datastore_name, digest)
+                    })?;
 
-                loop {
-                    let digest = match chunk_iter.next() {
-                        None => {
-                            let _ = tx.send(Ok(None)); // ignore send error
-                            break;
+                    for digest in chunk_iter {
+                        let digest = digest?;
+
+                        if !chunk_index.insert(digest) {
+                            continue;
                         }
-                        Some(digest) => digest?,
-                    };
 
-                    if chunk_index.contains(&digest) {
-                        continue;
-                    }
+                        while active.load(Ordering::SeqCst) < tuning.threads() {
+                            active.fetch_add(1, Ordering::SeqCst);
+                            let datastore = Arc::clone(&datastore);
+                            let work_rx = Arc::clone(&work_rx);
+                            let tx = tx.clone();
+                            let tuning = Arc::clone(&tuning);
+                            let active = Arc::clone(&active);
+                            loaders.push(std::thread::spawn(move || {
+                                chunk_loader(datastore, work_rx, tx, tuning, active)
+                            }));
+                        }
 
-                    let blob = datastore.load_chunk(&digest)?;
-                    //println!("LOAD CHUNK {}", hex::encode(&digest));
-                    if let Err(err) = tx.send(Ok(Some((digest, blob)))) {
-                        eprintln!("could not send chunk to reader thread: {err}");
-                        break;
+                        in_flight.acquire(&tuning)?;
+                        work_tx
+                            .send(digest)
+                            .map_err(|_| format_err!("chunk reader threads failed"))?;
                     }
 
-                    chunk_index.insert(digest);
+                    Ok(())
+                });
+
+                drop(work_tx);
+                for loader in loaders {
+                    let _ = loader.join();
                 }
 
-                Ok(())
-            });
-            if let Err(err) = result {
-                if let Err(err) = tx.send(Err(err)) {
-                    eprintln!("error sending result to reader thread: {}", err);
+                match result {
+                    Ok(()) => done.store(true, Ordering::SeqCst),
+                    Err(err) => {
+                        if let Err(err) = tx.send(Err(err)) {
+                            eprintln!("error sending result to reader thread: {}", err);
+                        }
+                    }
                 }
-            }
-        });
+            })
+        };
 
-        Ok((reader_thread, Self { rx }))
+        Ok((
+            reader_thread,
+            Self {
+                rx,
+                in_flight,
+                tuning,
+                done,
+            },
+        ))
     }
 }
 
@@ -84,11 +312,99 @@ impl Iterator for NewChunksIterator {
     type Item = Result<([u8; 32], DataBlob), Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.rx.recv() {
-            Ok(Ok(None)) => None,
-            Ok(Ok(Some((digest, blob)))) => Some(Ok((digest, blob))),
-            Ok(Err(err)) => Some(Err(err)),
+        let start = Instant::now();
+        let result = self.rx.recv();
+        self.tuning.add_starvation(start.elapsed());
+
+        match result {
+            Ok(result) => {
+                self.in_flight.release();
+                Some(result)
+            }
+            // all senders are gone, which is fine once all chunks were read
+            Err(_) if self.done.load(Ordering::SeqCst) => None,
             Err(_) => Some(Err(format_err!("reader thread failed"))),
         }
     }
 }
+
+impl Drop for NewChunksIterator {
+    fn drop(&mut self) {
+        // do not leave the reader thread waiting for free slots
+        self.in_flight.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one measurement in which the writer waited `starved` of TUNING_INTERVAL
+    fn measure(tuning: &ChunkReaderTuning, starved: f64) -> Option<String> {
+        tuning.add_starvation(TUNING_INTERVAL.mul_f64(starved));
+        tuning.adjust_after(TUNING_INTERVAL)
+    }
+
+    #[test]
+    fn test_tuning_interval() {
+        let tuning = ChunkReaderTuning::new();
+        tuning.add_starvation(TUNING_INTERVAL);
+        assert!(tuning
+            .adjust_after(TUNING_INTERVAL - Duration::from_millis(1))
+            .is_none());
+        assert_eq!(tuning.read_ahead(), INITIAL_READ_AHEAD);
+        assert_eq!(tuning.threads(), 1);
+    }
+
+    #[test]
+    fn test_tuning_grow() {
+        let tuning = ChunkReaderTuning::new();
+
+        assert!(measure(&tuning, 0.5).is_some());
+        assert_eq!(tuning.read_ahead(), INITIAL_READ_AHEAD * 2);
+        assert_eq!(tuning.threads(), 2);
+
+        // moderate starvation keeps the current limits
+        assert!(measure(&tuning, 0.05).is_none());
+        assert_eq!(tuning.read_ahead(), INITIAL_READ_AHEAD * 2);
+        assert_eq!(tuning.threads(), 2);
+
+        for _ in 0..20 {
+            measure(&tuning, 0.5);
+        }
+        assert_eq!(tuning.read_ahead(), MAX_READ_AHEAD);
+        assert_eq!(tuning.threads(), MAX_READER_THREADS);
+        // nothing left to change
+        assert!(measure(&tuning, 0.5).is_none());
+    }
+
+    #[test]
+    fn test_tuning_shrink() {
+        let tuning = ChunkReaderTuning::new();
+        measure(&tuning, 0.5);
+        measure(&tuning, 0.5);
+        assert_eq!(tuning.threads(), 3);
+
+        for _ in 1..STARVATION_LOW_COUNT {
+            assert!(measure(&tuning, 0.0).is_none());
+        }
+        assert!(measure(&tuning, 0.0).is_some());
+        // only the threads shrink, read-ahead is kept
+        assert_eq!(tuning.threads(), 2);
+        assert_eq!(tuning.read_ahead(), INITIAL_READ_AHEAD * 4);
+
+        // starvation in between restarts the count
+        for _ in 1..STARVATION_LOW_COUNT {
+            measure(&tuning, 0.0);
+        }
+        measure(&tuning, 0.05);
+        assert!(measure(&tuning, 0.0).is_none());
+        assert_eq!(tuning.threads(), 2);
+
+        // never below one thread
+        for _ in 0..(STARVATION_LOW_COUNT * 3) {
+            measure(&tuning, 0.0);
+        }
+        assert_eq!(tuning.threads(), 1);
+    }
+}
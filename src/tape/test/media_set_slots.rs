@@ -0,0 +1,111 @@
+// Tape Media Pool tests - test media set slots
+//
+// # cargo test --release tape::test::media_set_slots
+
+use anyhow::Error;
+use std::path::PathBuf;
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{BackupType, MediaSetSlots};
+
+use crate::tape::{
+    file_formats::MediaSetLabel,
+    media_set_slots::{media_set_slot, MediaSetSlotMap},
+    Inventory,
+};
+
+fn create_testdir(name: &str) -> Result<PathBuf, Error> {
+    let mut testdir: PathBuf = String::from("./target/testout").into();
+    testdir.push(std::module_path!());
+    testdir.push(name);
+
+    let _ = std::fs::remove_dir_all(&testdir);
+    let _ = std::fs::create_dir_all(&testdir);
+
+    Ok(testdir)
+}
+
+#[test]
+fn test_media_set_slot_name() -> Result<(), Error> {
+    let weekday = MediaSetSlots {
+        weekday: true,
+        ..Default::default()
+    };
+    let backup_type = MediaSetSlots {
+        backup_type: true,
+        ..Default::default()
+    };
+    let both = MediaSetSlots {
+        weekday: true,
+        backup_type: true,
+    };
+
+    // noon, so the weekday is the same in all time zones near UTC
+    let time = proxmox_time::parse_rfc3339("2021-01-04T12:00:00Z")?; // monday
+
+    assert_eq!(media_set_slot(&MediaSetSlots::default(), time, None)?, None);
+    assert_eq!(
+        media_set_slot(&weekday, time, None)?.as_deref(),
+        Some("mon")
+    );
+    assert_eq!(
+        media_set_slot(&backup_type, time, Some(BackupType::Vm))?.as_deref(),
+        Some("vm")
+    );
+    assert_eq!(media_set_slot(&backup_type, time, None)?, None);
+    assert_eq!(
+        media_set_slot(&both, time, Some(BackupType::Ct))?.as_deref(),
+        Some("mon/ct")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_media_set_slot_map() -> Result<(), Error> {
+    let testdir = create_testdir("test_media_set_slot_map")?;
+
+    let ctime = 0;
+
+    let mut inventory = Inventory::load(&testdir)?;
+
+    let sl1 = MediaSetLabel::with_data("p1", Uuid::generate(), 0, ctime, None);
+    let set1 = sl1.uuid.clone();
+    inventory.generate_used_tape("tape1", sl1, ctime);
+
+    let set2 = Uuid::generate();
+    let mut map = MediaSetSlotMap::load(&testdir, "p1")?;
+    map.register(&set1, "mon", &inventory)?;
+    map.register(&set2, "tue", &inventory)?;
+
+    // the new set is kept even though it has no media yet
+    let map = MediaSetSlotMap::load(&testdir, "p1")?;
+    assert_eq!(map.slot(&set1), Some("mon"));
+    assert_eq!(map.slot(&set2), Some("tue"));
+    assert!(map.in_slot(&set1, Some("mon")));
+    assert!(!map.in_slot(&set1, Some("tue")));
+    assert!(map.in_slot(&Uuid::generate(), None));
+
+    // set2 never got any media and the only media of set1 got removed
+    let tape1 = inventory
+        .find_media_by_label_text("tape1")?
+        .unwrap()
+        .label
+        .uuid
+        .clone();
+    inventory.remove_media(&tape1)?;
+
+    let set3 = Uuid::generate();
+    let mut map = MediaSetSlotMap::load(&testdir, "p1")?;
+    map.register(&set3, "wed", &inventory)?;
+
+    let map = MediaSetSlotMap::load(&testdir, "p1")?;
+    assert_eq!(map.slot(&set1), None);
+    assert_eq!(map.slot(&set2), None);
+    assert_eq!(map.slot(&set3), Some("wed"));
+
+    let _ = std::fs::remove_dir_all(&testdir);
+
+    Ok(())
+}
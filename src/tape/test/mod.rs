@@ -2,3 +2,4 @@ mod alloc_writable_media;
 mod compute_media_state;
 mod current_set_usable;
 mod inventory;
+mod media_set_slots;
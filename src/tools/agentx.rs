@@ -0,0 +1,603 @@
+//! Minimal AgentX (RFC 2741) subagent protocol implementation
+//!
+//! Only covers what a read-only subagent needs: opening a session, registering a subtree and
+//! answering `Get`, `GetNext` and `GetBulk` requests from a [Mib]. Set requests are rejected as
+//! not writable.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+use std::str::FromStr;
+
+use anyhow::{bail, format_err, Error};
+
+pub const HEADER_SIZE: usize = 20;
+
+const VERSION: u8 = 1;
+
+const FLAG_NON_DEFAULT_CONTEXT: u8 = 0x08;
+const FLAG_NETWORK_BYTE_ORDER: u8 = 0x10;
+
+pub const PDU_OPEN: u8 = 1;
+pub const PDU_CLOSE: u8 = 2;
+pub const PDU_REGISTER: u8 = 3;
+pub const PDU_GET: u8 = 5;
+pub const PDU_GET_NEXT: u8 = 6;
+pub const PDU_GET_BULK: u8 = 7;
+pub const PDU_TEST_SET: u8 = 8;
+pub const PDU_COMMIT_SET: u8 = 9;
+pub const PDU_UNDO_SET: u8 = 10;
+pub const PDU_CLEANUP_SET: u8 = 11;
+pub const PDU_PING: u8 = 13;
+pub const PDU_RESPONSE: u8 = 18;
+
+/// `notWritable` error of a set request
+const ERROR_NOT_WRITABLE: u16 = 17;
+
+/// Reason of a `Close` PDU
+pub const CLOSE_REASON_SHUTDOWN: u8 = 5;
+
+/// Upper bound for the number of repetitions in a `GetBulk` response
+const MAX_REPETITIONS: u16 = 256;
+
+/// An object identifier, ordered lexicographically like in SNMP.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Oid(pub Vec<u32>);
+
+impl Oid {
+    pub fn is_null(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn starts_with(&self, prefix: &Oid) -> bool {
+        self.0.starts_with(&prefix.0)
+    }
+
+    /// This OID with `ids` appended.
+    pub fn with(&self, ids: &[u32]) -> Oid {
+        let mut oid = self.0.clone();
+        oid.extend_from_slice(ids);
+        Oid(oid)
+    }
+}
+
+impl FromStr for Oid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let ids = s
+            .trim_start_matches('.')
+            .split('.')
+            .map(|id| id.parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| format_err!("invalid object identifier '{s}'"))?;
+        if ids.len() < 2 || ids.len() > 100 {
+            bail!("invalid object identifier length '{s}'");
+        }
+        Ok(Oid(ids))
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ids: Vec<String> = self.0.iter().map(|id| id.to_string()).collect();
+        f.write_str(&ids.join("."))
+    }
+}
+
+/// Variable binding values.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Integer(i32),
+    OctetString(Vec<u8>),
+    Gauge32(u32),
+    Counter64(u64),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+impl Value {
+    fn type_code(&self) -> u16 {
+        match self {
+            Value::Integer(_) => 2,
+            Value::OctetString(_) => 4,
+            Value::Gauge32(_) => 66,
+            Value::Counter64(_) => 70,
+            Value::NoSuchObject => 128,
+            Value::NoSuchInstance => 129,
+            Value::EndOfMibView => 130,
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::OctetString(s.as_bytes().to_vec())
+    }
+}
+
+/// The objects served by a subagent, with their current values.
+pub type Mib = BTreeMap<Oid, Value>;
+
+/// Header of an AgentX PDU.
+#[derive(Clone, Debug, Default)]
+pub struct Header {
+    pub ty: u8,
+    pub flags: u8,
+    pub session_id: u32,
+    pub transaction_id: u32,
+    pub packet_id: u32,
+    pub payload_length: u32,
+}
+
+impl Header {
+    /// Parse a header, the byte order is determined by its flags.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < HEADER_SIZE {
+            bail!("short AgentX header");
+        }
+        if data[0] != VERSION {
+            bail!("unsupported AgentX version {}", data[0]);
+        }
+        let mut reader = PduReader::new(&data[4..HEADER_SIZE], data[2]);
+        Ok(Self {
+            ty: data[1],
+            flags: data[2],
+            session_id: reader.u32()?,
+            transaction_id: reader.u32()?,
+            packet_id: reader.u32()?,
+            payload_length: reader.u32()?,
+        })
+    }
+
+    fn big_endian(&self) -> bool {
+        self.flags & FLAG_NETWORK_BYTE_ORDER != 0
+    }
+}
+
+/// Encodes the payload of a PDU.
+pub struct PduWriter {
+    buf: Vec<u8>,
+    big_endian: bool,
+}
+
+impl PduWriter {
+    pub fn new(big_endian: bool) -> Self {
+        Self {
+            buf: Vec::new(),
+            big_endian,
+        }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        if self.big_endian {
+            self.buf.extend_from_slice(&value.to_be_bytes());
+        } else {
+            self.buf.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        if self.big_endian {
+            self.buf.extend_from_slice(&value.to_be_bytes());
+        } else {
+            self.buf.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        if self.big_endian {
+            self.buf.extend_from_slice(&value.to_be_bytes());
+        } else {
+            self.buf.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    pub fn oid(&mut self, oid: &Oid, include: bool) {
+        self.u8(oid.0.len() as u8);
+        self.u8(0); // no prefix compression
+        self.u8(include as u8);
+        self.u8(0);
+        for id in &oid.0 {
+            self.u32(*id);
+        }
+    }
+
+    pub fn octets(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.buf.extend_from_slice(data);
+        let padding = (4 - data.len() % 4) % 4;
+        self.buf.extend(std::iter::repeat(0).take(padding));
+    }
+
+    pub fn varbind(&mut self, name: &Oid, value: &Value) {
+        self.u16(value.type_code());
+        self.u16(0);
+        self.oid(name, false);
+        match value {
+            Value::Integer(value) => self.u32(*value as u32),
+            Value::OctetString(data) => self.octets(data),
+            Value::Gauge32(value) => self.u32(*value),
+            Value::Counter64(value) => self.u64(*value),
+            Value::NoSuchObject | Value::NoSuchInstance | Value::EndOfMibView => (),
+        }
+    }
+
+    /// Build the complete PDU with its header.
+    pub fn finish(self, ty: u8, session_id: u32, transaction_id: u32, packet_id: u32) -> Vec<u8> {
+        let flags = if self.big_endian {
+            FLAG_NETWORK_BYTE_ORDER
+        } else {
+            0
+        };
+        let mut pdu = PduWriter::new(self.big_endian);
+        pdu.buf.extend_from_slice(&[VERSION, ty, flags, 0]);
+        pdu.u32(session_id);
+        pdu.u32(transaction_id);
+        pdu.u32(packet_id);
+        pdu.u32(self.buf.len() as u32);
+        pdu.buf.extend_from_slice(&self.buf);
+        pdu.buf
+    }
+}
+
+/// Decodes the payload of a PDU.
+pub struct PduReader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> PduReader<'a> {
+    pub fn new(data: &'a [u8], flags: u8) -> Self {
+        Self {
+            data,
+            big_endian: flags & FLAG_NETWORK_BYTE_ORDER != 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        if self.data.len() < N {
+            bail!("truncated AgentX PDU");
+        }
+        let (bytes, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.take()?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.take()?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// Read an OID and its `include` flag.
+    pub fn oid(&mut self) -> Result<(Oid, bool), Error> {
+        let n_subid = self.u8()?;
+        let prefix = self.u8()?;
+        let include = self.u8()? != 0;
+        self.u8()?;
+
+        let mut ids = Vec::with_capacity(n_subid as usize + 5);
+        if prefix != 0 {
+            ids.extend_from_slice(&[1, 3, 6, 1, prefix as u32]);
+        }
+        for _ in 0..n_subid {
+            ids.push(self.u32()?);
+        }
+        Ok((Oid(ids), include))
+    }
+
+    pub fn octets(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u32()? as usize;
+        let padded = len + (4 - len % 4) % 4;
+        if self.data.len() < padded {
+            bail!("truncated AgentX PDU");
+        }
+        let (bytes, rest) = self.data.split_at(padded);
+        self.data = rest;
+        Ok(&bytes[..len])
+    }
+}
+
+/// Build an `Open` PDU, `id` and `descr` identify the subagent.
+pub fn open_pdu(packet_id: u32, id: &Oid, descr: &str) -> Vec<u8> {
+    let mut pdu = PduWriter::new(true);
+    pdu.u8(0); // default timeout
+    pdu.buf.extend_from_slice(&[0, 0, 0]);
+    pdu.oid(id, false);
+    pdu.octets(descr.as_bytes());
+    pdu.finish(PDU_OPEN, 0, 0, packet_id)
+}
+
+/// Build a `Register` PDU for `subtree` in the default context.
+pub fn register_pdu(session_id: u32, packet_id: u32, subtree: &Oid) -> Vec<u8> {
+    let mut pdu = PduWriter::new(true);
+    pdu.u8(0); // default timeout
+    pdu.u8(127); // default priority
+    pdu.u8(0); // no range
+    pdu.u8(0);
+    pdu.oid(subtree, false);
+    pdu.finish(PDU_REGISTER, session_id, 0, packet_id)
+}
+
+/// Build a `Close` PDU.
+pub fn close_pdu(session_id: u32, packet_id: u32, reason: u8) -> Vec<u8> {
+    let mut pdu = PduWriter::new(true);
+    pdu.u8(reason);
+    pdu.buf.extend_from_slice(&[0, 0, 0]);
+    pdu.finish(PDU_CLOSE, session_id, 0, packet_id)
+}
+
+/// Parse the error status of a `Response` PDU.
+pub fn response_error(header: &Header, payload: &[u8]) -> Result<u16, Error> {
+    let mut reader = PduReader::new(payload, header.flags);
+    let _sys_up_time = reader.u32()?;
+    reader.u16()
+}
+
+// the first object after `start` (or at `start` if `include` is set), before `end` unless it
+// is the null OID
+fn get_next(mib: &Mib, start: &Oid, include: bool, end: &Oid) -> (Oid, Value) {
+    let lower = if include {
+        Bound::Included(start)
+    } else {
+        Bound::Excluded(start)
+    };
+    match mib.range((lower, Bound::Unbounded)).next() {
+        Some((oid, value)) if end.is_null() || oid < end => (oid.clone(), value.clone()),
+        _ => (start.clone(), Value::EndOfMibView),
+    }
+}
+
+/// Answer a request from the master agent for the objects in `mib` below `subtree`.
+///
+/// Returns the `Response` PDU to send, if any.
+pub fn handle_request(
+    header: &Header,
+    payload: &[u8],
+    mib: &Mib,
+    subtree: &Oid,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut reader = PduReader::new(payload, header.flags);
+    if header.flags & FLAG_NON_DEFAULT_CONTEXT != 0 {
+        reader.octets()?; // only registered in the default context
+    }
+
+    let mut response = PduWriter::new(header.big_endian());
+    response.u32(0); // sysUpTime, filled in by the master agent
+
+    match header.ty {
+        PDU_GET | PDU_GET_NEXT | PDU_GET_BULK => {
+            let (non_repeaters, max_repetitions) = if header.ty == PDU_GET_BULK {
+                (reader.u16()? as usize, reader.u16()?.min(MAX_REPETITIONS))
+            } else {
+                (0, 0)
+            };
+
+            let mut ranges = Vec::new();
+            while !reader.is_empty() {
+                let (start, include) = reader.oid()?;
+                let (end, _) = reader.oid()?;
+                ranges.push((start, include, end));
+            }
+
+            response.u16(0); // noError
+            response.u16(0);
+
+            match header.ty {
+                PDU_GET => {
+                    for (oid, _, _) in ranges {
+                        let value = match mib.get(&oid) {
+                            Some(value) => value.clone(),
+                            None if oid.starts_with(subtree) => Value::NoSuchInstance,
+                            None => Value::NoSuchObject,
+                        };
+                        response.varbind(&oid, &value);
+                    }
+                }
+                PDU_GET_NEXT => {
+                    for (start, include, end) in ranges {
+                        let (oid, value) = get_next(mib, &start, include, &end);
+                        response.varbind(&oid, &value);
+                    }
+                }
+                _ => {
+                    let non_repeaters = non_repeaters.min(ranges.len());
+                    for (start, include, end) in &ranges[..non_repeaters] {
+                        let (oid, value) = get_next(mib, start, *include, end);
+                        response.varbind(&oid, &value);
+                    }
+
+                    let mut repeaters: Vec<(Oid, bool, Oid)> = ranges[non_repeaters..].to_vec();
+                    for _ in 0..max_repetitions {
+                        let mut all_done = true;
+                        for (start, include, end) in repeaters.iter_mut() {
+                            let (oid, value) = get_next(mib, start, *include, end);
+                            if value != Value::EndOfMibView {
+                                all_done = false;
+                            }
+                            response.varbind(&oid, &value);
+                            *start = oid;
+                            *include = false;
+                        }
+                        if all_done {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        PDU_TEST_SET => {
+            response.u16(ERROR_NOT_WRITABLE);
+            response.u16(1);
+        }
+        PDU_COMMIT_SET | PDU_UNDO_SET => {
+            response.u16(0);
+            response.u16(0);
+        }
+        // no response expected for cleanup, and nothing else is sent by a master agent
+        _ => return Ok(None),
+    }
+
+    Ok(Some(response.finish(
+        PDU_RESPONSE,
+        header.session_id,
+        header.transaction_id,
+        header.packet_id,
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_mib() -> (Oid, Mib) {
+        let base: Oid = "1.3.6.1.4.1.8072.9999.9999.1".parse().unwrap();
+        let mut mib = Mib::new();
+        mib.insert(base.with(&[1, 1, 0]), Value::from("1.0"));
+        mib.insert(base.with(&[2, 1, 2, 1]), Value::from("store1"));
+        mib.insert(base.with(&[2, 1, 3, 1]), Value::Counter64(1 << 40));
+        (base, mib)
+    }
+
+    fn request(ty: u8, big_endian: bool, build: impl FnOnce(&mut PduWriter)) -> (Header, Vec<u8>) {
+        let mut pdu = PduWriter::new(big_endian);
+        build(&mut pdu);
+        let data = pdu.finish(ty, 7, 8, 9);
+        let header = Header::parse(&data).unwrap();
+        (header, data[HEADER_SIZE..].to_vec())
+    }
+
+    fn parse_varbinds(data: &[u8]) -> Vec<(Oid, u16)> {
+        let header = Header::parse(data).unwrap();
+        assert_eq!(header.ty, PDU_RESPONSE);
+        assert_eq!(
+            (header.session_id, header.transaction_id, header.packet_id),
+            (7, 8, 9)
+        );
+        let mut reader = PduReader::new(&data[HEADER_SIZE..], header.flags);
+        reader.u32().unwrap();
+        assert_eq!(reader.u16().unwrap(), 0);
+        reader.u16().unwrap();
+
+        let mut list = Vec::new();
+        while !reader.is_empty() {
+            let ty = reader.u16().unwrap();
+            reader.u16().unwrap();
+            let (oid, _) = reader.oid().unwrap();
+            match ty {
+                2 | 66 => {
+                    reader.u32().unwrap();
+                }
+                4 => {
+                    reader.octets().unwrap();
+                }
+                70 => {
+                    reader.u32().unwrap();
+                    reader.u32().unwrap();
+                }
+                _ => (),
+            }
+            list.push((oid, ty));
+        }
+        list
+    }
+
+    #[test]
+    fn test_oid_parse() {
+        let oid: Oid = ".1.3.6.1.4.1".parse().unwrap();
+        assert_eq!(oid.to_string(), "1.3.6.1.4.1");
+        assert!("1.3.x".parse::<Oid>().is_err());
+        assert!("1".parse::<Oid>().is_err());
+    }
+
+    #[test]
+    fn test_get() {
+        let (base, mib) = test_mib();
+        for big_endian in [true, false] {
+            let (header, payload) = request(PDU_GET, big_endian, |pdu| {
+                pdu.oid(&base.with(&[1, 1, 0]), false);
+                pdu.oid(&Oid::default(), false);
+                pdu.oid(&base.with(&[1, 2, 0]), false);
+                pdu.oid(&Oid::default(), false);
+                pdu.oid(&"1.3.6.1.2.1.1.1.0".parse().unwrap(), false);
+                pdu.oid(&Oid::default(), false);
+            });
+            let response = handle_request(&header, &payload, &mib, &base)
+                .unwrap()
+                .unwrap();
+            let types: Vec<u16> = parse_varbinds(&response).into_iter().map(|v| v.1).collect();
+            assert_eq!(types, vec![4, 129, 128]);
+        }
+    }
+
+    #[test]
+    fn test_get_next_and_bulk() {
+        let (base, mib) = test_mib();
+
+        let (header, payload) = request(PDU_GET_NEXT, true, |pdu| {
+            pdu.oid(&base, false);
+            pdu.oid(&Oid::default(), false);
+            pdu.oid(&base.with(&[2, 1, 3, 1]), true);
+            pdu.oid(&Oid::default(), false);
+            pdu.oid(&base.with(&[2, 1, 3, 1]), false);
+            pdu.oid(&Oid::default(), false);
+        });
+        let response = handle_request(&header, &payload, &mib, &base)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            parse_varbinds(&response),
+            vec![
+                (base.with(&[1, 1, 0]), 4),
+                (base.with(&[2, 1, 3, 1]), 70),
+                (base.with(&[2, 1, 3, 1]), 130),
+            ]
+        );
+
+        let (header, payload) = request(PDU_GET_BULK, true, |pdu| {
+            pdu.u16(0);
+            pdu.u16(10);
+            pdu.oid(&base, false);
+            pdu.oid(&Oid::default(), false);
+        });
+        let response = handle_request(&header, &payload, &mib, &base)
+            .unwrap()
+            .unwrap();
+        let oids: Vec<Oid> = parse_varbinds(&response).into_iter().map(|v| v.0).collect();
+        assert_eq!(
+            oids,
+            vec![
+                base.with(&[1, 1, 0]),
+                base.with(&[2, 1, 2, 1]),
+                base.with(&[2, 1, 3, 1]),
+                base.with(&[2, 1, 3, 1]),
+            ]
+        );
+    }
+}
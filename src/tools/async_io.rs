@@ -0,0 +1,57 @@
+//! Connect cancellable waits (see [`pbs_tools::async_io`]) to worker tasks and daemon shutdown.
+
+use once_cell::sync::OnceCell;
+
+use proxmox_rest_server::WorkerTask;
+
+pub use pbs_tools::async_io::*;
+
+/// Resolves once the daemon is shutting down, never resolves if the shutdown is for a reload.
+///
+/// On a reload, running workers are left to finish in the old process, so their I/O must not be
+/// aborted. Such workers can still be stopped by aborting them.
+async fn real_shutdown_future() {
+    proxmox_rest_server::shutdown_future().await;
+    if proxmox_rest_server::is_reload_request() {
+        futures::future::pending::<()>().await;
+    }
+}
+
+/// Create an [`AbortSignal`] which is triggered once `worker` is aborted or the daemon is shutting
+/// down (but not reloading).
+pub fn worker_abort_signal(worker: &WorkerTask) -> AbortSignal {
+    let signal = AbortSignal::new();
+
+    let abort_future = worker.abort_future();
+    let abort = signal.clone();
+    proxmox_async::runtime::get_runtime().spawn(async move {
+        tokio::select! {
+            result = abort_future => {
+                if result.is_err() {
+                    return; // worker finished
+                }
+            }
+            _ = real_shutdown_future() => {}
+        }
+        abort.abort();
+    });
+
+    signal
+}
+
+/// An [`AbortSignal`] which is triggered once the daemon is shutting down (but not reloading).
+pub fn shutdown_abort_signal() -> AbortSignal {
+    static SIGNAL: OnceCell<AbortSignal> = OnceCell::new();
+
+    SIGNAL
+        .get_or_init(|| {
+            let signal = AbortSignal::new();
+            let abort = signal.clone();
+            proxmox_async::runtime::get_runtime().spawn(async move {
+                real_shutdown_future().await;
+                abort.abort();
+            });
+            signal
+        })
+        .clone()
+}
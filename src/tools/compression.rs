@@ -0,0 +1,325 @@
+//! Content-Encoding negotiation and streaming compression for HTTP responses
+//!
+//! Used by the file download handlers, and by [`encoded_api_response`] for API methods with
+//! large JSON results, like catalogs and task lists.
+
+use std::io::Write;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use anyhow::{bail, Error};
+use flate2::write::GzEncoder;
+use futures::{Stream, TryStreamExt};
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::http::request::Parts;
+use hyper::{Body, Response, StatusCode};
+use serde_json::{json, Value};
+
+use proxmox_router::{ApiHandler, ApiMethod, RpcEnvironment};
+
+use proxmox_compression::zstd::ZstdEncoder;
+
+/// Content encodings supported for streamed responses, in order of preference.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Zstd,
+    Gzip,
+}
+
+impl ContentEncoding {
+    /// The value used in the `Content-Encoding` header.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Zstd => "zstd",
+            ContentEncoding::Gzip => "gzip",
+        }
+    }
+
+    /// Select the preferred encoding accepted by the client via `Accept-Encoding`.
+    ///
+    /// Encodings with a quality value of zero are treated as not acceptable. Between the
+    /// acceptable ones, zstd is preferred over gzip, unless the client explicitly ranks gzip
+    /// higher.
+    pub fn from_accept_encoding(value: &str) -> Option<Self> {
+        let mut best: Option<(Self, u32)> = None;
+
+        for item in value.split(',') {
+            let mut parts = item.split(';');
+            let name = parts.next().unwrap_or("").trim();
+            let mut quality = 1000;
+            for param in parts {
+                if let Some(q) = param.trim().strip_prefix("q=") {
+                    quality = parse_quality(q.trim());
+                }
+            }
+            if quality == 0 {
+                continue;
+            }
+
+            let encoding = match name.to_ascii_lowercase().as_str() {
+                "zstd" => ContentEncoding::Zstd,
+                "gzip" | "x-gzip" => ContentEncoding::Gzip,
+                "*" => ContentEncoding::Zstd,
+                _ => continue,
+            };
+
+            best = match best {
+                Some((current, current_q)) if current_q > quality => Some((current, current_q)),
+                Some((ContentEncoding::Zstd, current_q)) if current_q == quality => {
+                    Some((ContentEncoding::Zstd, current_q))
+                }
+                _ => Some((encoding, quality)),
+            };
+        }
+
+        best.map(|(encoding, _)| encoding)
+    }
+
+    /// Select the preferred encoding from request headers.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::from_accept_encoding)
+    }
+
+    /// Add the `Content-Encoding` and `Vary` headers for this encoding.
+    pub fn add_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(self.as_str()),
+        );
+        headers.insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+}
+
+/// Parse a quality value into thousandths, so it can be compared without floats.
+fn parse_quality(value: &str) -> u32 {
+    let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+    let int: u32 = match int.parse() {
+        Ok(int) => int,
+        Err(_) => return 0,
+    };
+    if int >= 1 {
+        return 1000;
+    }
+    let mut result = 0;
+    let mut scale = 100;
+    for digit in frac.chars().take(3) {
+        match digit.to_digit(10) {
+            Some(digit) => result += digit * scale,
+            None => return 0,
+        }
+        scale /= 10;
+    }
+    result
+}
+
+/// Streaming gzip encoder for byte streams.
+pub struct GzipEncoderStream<S> {
+    inner: S,
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl<S> GzipEncoderStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            encoder: Some(GzEncoder::new(Vec::new(), flate2::Compression::default())),
+        }
+    }
+}
+
+impl<S, O, E> Stream for GzipEncoderStream<S>
+where
+    S: Stream<Item = Result<O, E>> + Unpin,
+    O: AsRef<[u8]>,
+    E: Into<Error>,
+{
+    type Item = Result<Vec<u8>, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let encoder = match this.encoder.as_mut() {
+                Some(encoder) => encoder,
+                None => return Poll::Ready(None),
+            };
+
+            match futures::ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(data)) => {
+                    if let Err(err) = encoder.write_all(data.as_ref()) {
+                        this.encoder = None;
+                        return Poll::Ready(Some(Err(err.into())));
+                    }
+                    let output = std::mem::take(encoder.get_mut());
+                    if !output.is_empty() {
+                        return Poll::Ready(Some(Ok(output)));
+                    }
+                }
+                Some(Err(err)) => {
+                    this.encoder = None;
+                    return Poll::Ready(Some(Err(err.into())));
+                }
+                None => {
+                    // unwrap: checked above
+                    let encoder = this.encoder.take().unwrap();
+                    return Poll::Ready(Some(encoder.finish().map_err(Error::from)));
+                }
+            }
+        }
+    }
+}
+
+/// Wrap a byte stream into a response body, compressed with `encoding` if set.
+pub fn encode_body<S, O, E>(stream: S, encoding: Option<ContentEncoding>) -> Result<Body, Error>
+where
+    S: Stream<Item = Result<O, E>> + Send + Unpin + 'static,
+    O: AsRef<[u8]> + Into<bytes::Bytes> + Send + 'static,
+    E: Into<Error> + Send + 'static,
+{
+    Ok(match encoding {
+        Some(ContentEncoding::Zstd) => Body::wrap_stream(ZstdEncoder::new(stream)?),
+        Some(ContentEncoding::Gzip) => Body::wrap_stream(GzipEncoderStream::new(stream)),
+        None => Body::wrap_stream(stream.map_err(Into::<Error>::into)),
+    })
+}
+
+/// JSON responses smaller than this are not compressed, it would not pay off.
+const MIN_ENCODED_JSON_SIZE: usize = 4096;
+
+/// Build the JSON response body the REST server's formatters produce for `data`.
+///
+/// The `extjs` format used by the web interface additionally reports success, the result
+/// attributes set by the handler, like the total count of a paged list, are added to both.
+fn format_api_result(data: Value, extjs: bool, attributes: &Value) -> Value {
+    let mut result = json!({ "data": data });
+    if extjs {
+        result["success"] = Value::Bool(true);
+    }
+    if let Value::Object(attributes) = attributes {
+        for (key, value) in attributes {
+            result[key] = value.clone();
+        }
+    }
+    result
+}
+
+/// Serialize `result` into a JSON response, compressed with `encoding` if it is large enough.
+fn json_response(
+    result: &Value,
+    encoding: Option<ContentEncoding>,
+) -> Result<Response<Body>, Error> {
+    let data = serde_json::to_vec(result)?;
+    let encoding = encoding.filter(|_| data.len() >= MIN_ENCODED_JSON_SIZE);
+
+    let body = encode_body(futures::stream::iter([Ok::<_, Error>(data)]), encoding)?;
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json;charset=UTF-8")
+        .body(body)?;
+    match encoding {
+        Some(encoding) => encoding.add_headers(response.headers_mut()),
+        None => {
+            response
+                .headers_mut()
+                .insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+        }
+    }
+
+    Ok(response)
+}
+
+/// Call the `Sync` or `Async` handler of `method` and return its result as JSON response,
+/// compressed with the encoding negotiated from the `Accept-Encoding` header of the request.
+///
+/// The REST server only compresses JSON responses with deflate, this is used by `AsyncHttp`
+/// wrappers of API methods with large results to offer zstd and gzip as well.
+pub async fn encoded_api_response(
+    parts: &Parts,
+    param: Value,
+    method: &'static ApiMethod,
+    mut rpcenv: Box<dyn RpcEnvironment>,
+) -> Result<Response<Body>, Error> {
+    let data = match method.handler {
+        ApiHandler::Sync(handler) => {
+            proxmox_async::runtime::block_in_place(|| handler(param, method, &mut *rpcenv))?
+        }
+        ApiHandler::Async(handler) => handler(param, method, &mut *rpcenv).await?,
+        _ => bail!("encoded responses are only supported for sync and async API handlers"),
+    };
+
+    let extjs = parts.uri.path().starts_with("/api2/extjs/");
+    let result = format_api_result(data, extjs, rpcenv.result_attrib());
+
+    json_response(&result, ContentEncoding::from_headers(&parts.headers))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_accept_encoding() {
+        use ContentEncoding::*;
+
+        assert_eq!(ContentEncoding::from_accept_encoding("gzip"), Some(Gzip));
+        assert_eq!(
+            ContentEncoding::from_accept_encoding("gzip, zstd"),
+            Some(Zstd)
+        );
+        assert_eq!(
+            ContentEncoding::from_accept_encoding("zstd;q=0, gzip"),
+            Some(Gzip)
+        );
+        assert_eq!(
+            ContentEncoding::from_accept_encoding("zstd;q=0.5, gzip;q=0.8"),
+            Some(Gzip)
+        );
+        assert_eq!(ContentEncoding::from_accept_encoding("deflate, br"), None);
+        assert_eq!(ContentEncoding::from_accept_encoding("identity"), None);
+        assert_eq!(ContentEncoding::from_accept_encoding("*"), Some(Zstd));
+        assert_eq!(ContentEncoding::from_accept_encoding(""), None);
+    }
+
+    #[test]
+    fn test_format_api_result() {
+        let data = json!([{ "upid": "UPID:..." }]);
+        let attributes = json!({ "total": 42 });
+
+        assert_eq!(
+            format_api_result(data.clone(), false, &attributes),
+            json!({ "data": data, "total": 42 }),
+        );
+        assert_eq!(
+            format_api_result(data.clone(), true, &json!({})),
+            json!({ "data": data, "success": true }),
+        );
+        assert_eq!(
+            format_api_result(Value::Null, false, &Value::Null),
+            json!({ "data": null }),
+        );
+    }
+
+    #[test]
+    fn test_json_response_encoding() -> Result<(), Error> {
+        let small = json!({ "data": "small" });
+        let response = json_response(&small, Some(ContentEncoding::Zstd))?;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+
+        let large = json!({ "data": vec!["entry"; 2048] });
+        let response = json_response(&large, Some(ContentEncoding::Gzip))?;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::VARY], "Accept-Encoding");
+
+        let body = proxmox_async::runtime::block_on(hyper::body::to_bytes(response.into_body()))?;
+        let mut decoded = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&body[..]), &mut decoded)?;
+        assert_eq!(serde_json::from_slice::<Value>(&decoded)?, large);
+
+        let response = json_response(&large, None)?;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+
+        Ok(())
+    }
+}
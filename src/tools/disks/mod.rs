@@ -33,6 +33,10 @@ mod lvm;
 pub use lvm::*;
 mod smart;
 pub use smart::*;
+mod multipath;
+pub use multipath::*;
+mod removable;
+pub use removable::*;
 
 lazy_static::lazy_static! {
     static ref ISCSI_PATH_REGEX: regex::Regex =
@@ -680,6 +684,8 @@ pub enum DiskUsageType {
     Partitions,
     /// Disk contains a file system label
     FileSystem,
+    /// Disk is a path of a multipath device
+    Multipath,
 }
 
 #[api()]
@@ -719,7 +725,13 @@ pub struct PartitionInfo {
             items: {
                 type: PartitionInfo
             }
-        }
+        },
+        paths: {
+            optional: true,
+            items: {
+                type: DiskPathInfo
+            }
+        },
     }
 )]
 #[derive(Debug, Serialize, Deserialize)]
@@ -751,6 +763,10 @@ pub struct DiskUsageInfo {
     pub gpt: bool,
     /// RPM
     pub rpm: Option<u64>,
+    /// Paths of a multipath device or NVMe namespace
+    pub paths: Option<Vec<DiskPathInfo>>,
+    /// NVMe subsystem NQN
+    pub nvme_subsystem: Option<String>,
 }
 
 fn scan_partitions(
@@ -943,20 +959,32 @@ fn get_disks(
 
     let file_system_devices = get_file_system_devices(&lsblk_info)?;
 
+    let multipath_devices = get_multipath_devices().unwrap_or_else(|err| {
+        eprintln!("error getting multipath devices: {}", err);
+        Vec::new()
+    });
+
+    let multipath_members = multipath_members(&multipath_devices);
+
     // fixme: ceph journals/volumes
 
     let mut result = HashMap::new();
 
+    let mut disk_names = Vec::new();
     for item in proxmox_sys::fs::scan_subdir(libc::AT_FDCWD, "/sys/block", &BLOCKDEVICE_NAME_REGEX)?
     {
         let item = item?;
+        disk_names.push(item.file_name().to_str().unwrap().to_string());
+    }
+    disk_names.extend(multipath_devices.iter().map(|dev| dev.sysname.clone()));
 
-        let name = item.file_name().to_str().unwrap().to_string();
-
+    for name in disk_names {
         if let Some(ref disks) = disks {
             if !disks.contains(&name) {
                 continue;
             }
+        } else if multipath_members.contains_key(&name) {
+            continue; // listed once as part of the multipath device
         }
 
         let sys_path = format!("/sys/block/{}", name);
@@ -999,21 +1027,40 @@ fn get_disks(
             usage = DiskUsageType::ZFS;
         }
 
-        let vendor = disk
+        let multipath = multipath_devices.iter().find(|dev| dev.sysname == name);
+
+        // device mapper devices have no hardware info, so use the first path for that
+        let path_disk = multipath
+            .and_then(|dev| dev.paths.first())
+            .and_then(|path| disk_manager.clone().disk_by_name(&path.name).ok());
+        let hw_disk = path_disk.as_ref().unwrap_or(&disk);
+
+        let vendor = hw_disk
             .vendor()
             .unwrap_or(None)
             .map(|s| s.to_string_lossy().trim().to_string());
 
-        let model = disk.model().map(|s| s.to_string_lossy().into_owned());
+        let model = hw_disk.model().map(|s| s.to_string_lossy().into_owned());
+
+        let serial = hw_disk.serial().map(|s| s.to_string_lossy().into_owned());
 
-        let serial = disk.serial().map(|s| s.to_string_lossy().into_owned());
+        let devpath = match multipath {
+            Some(dev) => Some(dev.device_path().to_string_lossy().to_string()),
+            None => disk
+                .device_path()
+                .map(|p| p.to_owned())
+                .map(|p| p.to_string_lossy().to_string()),
+        };
 
-        let devpath = disk
-            .device_path()
-            .map(|p| p.to_owned())
-            .map(|p| p.to_string_lossy().to_string());
+        let wwn = match multipath {
+            Some(dev) => Some(dev.wwid.clone()),
+            None => disk.wwn().map(|s| s.to_string_lossy().into_owned()),
+        };
 
-        let wwn = disk.wwn().map(|s| s.to_string_lossy().into_owned());
+        let paths = match multipath {
+            Some(dev) => Some(dev.paths.clone()),
+            None => get_nvme_namespace_paths(&name).unwrap_or(None),
+        };
 
         let partitions: Option<Vec<PartitionInfo>> = if include_partitions {
             disk.partitions().map_or(None, |parts| {
@@ -1047,11 +1094,16 @@ fn get_disks(
             usage = DiskUsageType::DeviceMapper;
         }
 
+        if multipath_members.contains_key(&name) {
+            // never allow using a single path of a multipath device directly
+            usage = DiskUsageType::Multipath;
+        }
+
         let mut status = SmartStatus::Unknown;
         let mut wearout = None;
 
         if !no_smart {
-            if let Ok(smart) = get_smart_data(&disk, false) {
+            if let Ok(smart) = get_smart_data(hw_disk, false) {
                 status = smart.status;
                 wearout = smart.wearout;
             }
@@ -1071,7 +1123,9 @@ fn get_disks(
             wearout,
             used: usage,
             gpt: disk.has_gpt(),
-            rpm: disk.ata_rotation_rate_rpm(),
+            rpm: hw_disk.ata_rotation_rate_rpm(),
+            nvme_subsystem: get_nvme_subsystem_nqn(&name),
+            paths,
         };
 
         result.insert(name, info);
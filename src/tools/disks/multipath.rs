@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::api;
+
+const SYS_BLOCK_PATH: &str = "/sys/block";
+
+#[api()]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// Health of a single path to a multipath device.
+pub enum DiskPathState {
+    /// The path is usable.
+    Active,
+    /// The path exists, but is currently not usable (offline, blocked, resetting, ...).
+    Failed,
+    /// We were not able to read the path state.
+    Unknown,
+}
+
+#[api()]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A single path of a dm-multipath device or an NVMe namespace.
+pub struct DiskPathInfo {
+    /// The path name (`/sys/block/<name>`)
+    pub name: String,
+    /// The state of the path.
+    pub state: DiskPathState,
+    /// The raw state as reported by the kernel.
+    pub kernel_state: Option<String>,
+}
+
+/// Information about a device mapper multipath device (`/sys/block/dm-<N>`).
+#[derive(Debug, Clone)]
+pub struct MultipathDevice {
+    /// The `dm-<N>` name in `/sys/block`.
+    pub sysname: String,
+    /// The device mapper name (`/dev/mapper/<name>`).
+    pub name: String,
+    /// The WWID taken from the device mapper UUID.
+    pub wwid: String,
+    /// The block devices which are paths of this device.
+    pub paths: Vec<DiskPathInfo>,
+}
+
+impl MultipathDevice {
+    /// The device node in `/dev/mapper`.
+    pub fn device_path(&self) -> PathBuf {
+        Path::new("/dev/mapper").join(&self.name)
+    }
+}
+
+fn read_sys_string<P: AsRef<Path>>(path: P) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+fn list_sys_dir<P: AsRef<Path>>(path: P) -> Result<Vec<String>, Error> {
+    let mut list = Vec::new();
+    let dir = match std::fs::read_dir(path) {
+        Ok(dir) => dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(list),
+        Err(err) => return Err(err.into()),
+    };
+    for entry in dir {
+        if let Some(name) = entry?.file_name().to_str() {
+            list.push(name.to_string());
+        }
+    }
+    list.sort();
+    Ok(list)
+}
+
+/// Map a SCSI device state (`device/state`) to a path state.
+fn scsi_path_state(state: Option<&str>) -> DiskPathState {
+    match state {
+        Some("running") => DiskPathState::Active,
+        Some(_) => DiskPathState::Failed,
+        None => DiskPathState::Unknown,
+    }
+}
+
+/// Map an NVMe controller state (`device/state`) to a path state.
+fn nvme_path_state(state: Option<&str>) -> DiskPathState {
+    match state {
+        Some("live") => DiskPathState::Active,
+        Some(_) => DiskPathState::Failed,
+        None => DiskPathState::Unknown,
+    }
+}
+
+/// Scan `/sys/block` for dm-multipath devices.
+///
+/// Devices are identified by their device mapper UUID, which is prefixed with `mpath-` by
+/// multipathd.
+pub fn get_multipath_devices() -> Result<Vec<MultipathDevice>, Error> {
+    let mut list = Vec::new();
+
+    for sysname in list_sys_dir(SYS_BLOCK_PATH)? {
+        if !sysname.starts_with("dm-") {
+            continue;
+        }
+
+        let sys_path = Path::new(SYS_BLOCK_PATH).join(&sysname);
+
+        let wwid = match read_sys_string(sys_path.join("dm/uuid")) {
+            Some(uuid) => match uuid.strip_prefix("mpath-") {
+                Some(wwid) => wwid.to_string(),
+                None => continue,
+            },
+            None => continue,
+        };
+
+        let name = read_sys_string(sys_path.join("dm/name")).unwrap_or_else(|| sysname.clone());
+
+        let paths = list_sys_dir(sys_path.join("slaves"))?
+            .into_iter()
+            .map(|path| {
+                let kernel_state =
+                    read_sys_string(Path::new(SYS_BLOCK_PATH).join(&path).join("device/state"));
+                DiskPathInfo {
+                    state: scsi_path_state(kernel_state.as_deref()),
+                    name: path,
+                    kernel_state,
+                }
+            })
+            .collect();
+
+        list.push(MultipathDevice {
+            sysname,
+            name,
+            wwid,
+            paths,
+        });
+    }
+
+    Ok(list)
+}
+
+/// Returns a map from path device names to the name of the multipath device they belong to.
+pub fn multipath_members(devices: &[MultipathDevice]) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    for device in devices {
+        for path in device.paths.iter() {
+            map.insert(path.name.clone(), device.sysname.clone());
+        }
+    }
+    map
+}
+
+/// Get the paths of an NVMe namespace using native NVMe multipathing.
+///
+/// Returns `None` if the namespace is not a multipath head (`/sys/block/<name>/multipath`).
+pub fn get_nvme_namespace_paths(name: &str) -> Result<Option<Vec<DiskPathInfo>>, Error> {
+    let multipath_dir = Path::new(SYS_BLOCK_PATH).join(name).join("multipath");
+    if !multipath_dir.is_dir() {
+        return Ok(None);
+    }
+
+    let paths = list_sys_dir(&multipath_dir)?
+        .into_iter()
+        .map(|path| {
+            let kernel_state = read_sys_string(multipath_dir.join(&path).join("device/state"));
+            let mut state = nvme_path_state(kernel_state.as_deref());
+            if state == DiskPathState::Active {
+                // a live controller may still report an inaccessible ANA group
+                if let Some(ana_state) =
+                    read_sys_string(multipath_dir.join(&path).join("ana_state"))
+                {
+                    if ana_state != "optimized" && ana_state != "non-optimized" {
+                        state = DiskPathState::Failed;
+                    }
+                }
+            }
+            DiskPathInfo {
+                name: path,
+                state,
+                kernel_state,
+            }
+        })
+        .collect();
+
+    Ok(Some(paths))
+}
+
+/// Get the NVMe subsystem NQN for an NVMe namespace, if any.
+pub fn get_nvme_subsystem_nqn(name: &str) -> Option<String> {
+    if !name.starts_with("nvme") {
+        return None;
+    }
+    read_sys_string(
+        Path::new(SYS_BLOCK_PATH)
+            .join(name)
+            .join("device/subsysnqn"),
+    )
+}
@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_api_types::DataStoreConfig;
+use pbs_datastore::is_datastore_available;
+
+/// The device node of a removable device, by filesystem UUID.
+pub fn removable_device_path(uuid: &str) -> PathBuf {
+    Path::new("/dev/disk/by-uuid").join(uuid)
+}
+
+/// Mount the removable device backing a datastore on the datastore path.
+///
+/// Returns `false` if the device was already mounted.
+pub fn mount_datastore_device(config: &DataStoreConfig) -> Result<bool, Error> {
+    let uuid = match config.backing_device.as_deref() {
+        Some(uuid) => uuid,
+        None => bail!(
+            "datastore '{}' is not located on a removable device",
+            config.name
+        ),
+    };
+
+    if is_datastore_available(config) {
+        return Ok(false);
+    }
+
+    let device = removable_device_path(uuid);
+    if !device.exists() {
+        bail!(
+            "removable device with filesystem UUID '{}' for datastore '{}' is not attached",
+            uuid,
+            config.name
+        );
+    }
+
+    let path = Path::new(&config.path);
+    std::fs::create_dir_all(path)
+        .map_err(|err| format_err!("unable to create mount point {path:?} - {err}"))?;
+
+    let mut command = std::process::Command::new("mount");
+    command.args(["-o", "defaults"]);
+    command.arg(&device);
+    command.arg(path);
+
+    proxmox_sys::command::run_command(command, None)?;
+
+    if !is_datastore_available(config) {
+        bail!("mounting {device:?} on {path:?} failed - device not found on mount point");
+    }
+
+    Ok(true)
+}
+
+/// Unmount the removable device backing a datastore.
+///
+/// The datastore must not be in use anymore, see the `unmount` maintenance mode.
+pub fn unmount_datastore_device(config: &DataStoreConfig) -> Result<(), Error> {
+    if !config.is_removable() {
+        bail!(
+            "datastore '{}' is not located on a removable device",
+            config.name
+        );
+    }
+
+    if !is_datastore_available(config) {
+        return Ok(());
+    }
+
+    let mut command = std::process::Command::new("umount");
+    command.arg(&config.path);
+
+    proxmox_sys::command::run_command(command, None)
+        .map_err(|err| format_err!("unable to unmount '{}' - {err}", config.path))?;
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! Localization of notifications and GUI-facing labels
+//!
+//! A notification template is localized by installing a variant with the
+//! language code appended to the template name, for example
+//! `gc-ok-de-body.txt.hbs` next to `gc-ok-body.txt.hbs`. Templates without a
+//! variant for the requested language fall back to English.
+//!
+//! Labels of API enums are translated with the message catalogs in `i18n/`,
+//! which map the English description of an enum value to its translation.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use serde_json::{Map, Value};
+
+use proxmox_schema::{ApiStringFormat, ApiType, EnumEntry, Schema};
+
+use pbs_api_types::{
+    CryptMode, MaintenanceType, NotificationMode, Notify, TaskStateType, VerifyState,
+};
+
+const TEMPLATE_DIR: &str = "/usr/share/proxmox-backup/templates/default";
+
+/// Language of the untranslated templates and labels
+pub const DEFAULT_LANG: &str = "en";
+
+const CATALOGS: &[(&str, &str)] = &[("de", include_str!("i18n/de.json"))];
+
+/// API enums whose value labels can be translated, by name
+pub const LABELED_ENUMS: &[(&str, &Schema)] = &[
+    ("crypt-mode", &CryptMode::API_SCHEMA),
+    ("maintenance-type", &MaintenanceType::API_SCHEMA),
+    ("notification-mode", &NotificationMode::API_SCHEMA),
+    ("notify", &Notify::API_SCHEMA),
+    ("task-state", &TaskStateType::API_SCHEMA),
+    ("verify-state", &VerifyState::API_SCHEMA),
+];
+
+// descriptions generated from multi-line doc comments keep their line breaks
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+lazy_static! {
+    static ref MESSAGES: HashMap<&'static str, HashMap<String, String>> = {
+        let mut messages = HashMap::new();
+        for (lang, data) in CATALOGS {
+            match serde_json::from_str::<HashMap<String, String>>(data) {
+                Ok(catalog) => {
+                    let catalog = catalog
+                        .into_iter()
+                        .map(|(text, translation)| (normalize(&text), translation))
+                        .collect();
+                    messages.insert(*lang, catalog);
+                }
+                Err(err) => log::error!("unable to parse message catalog '{lang}' - {err}"),
+            }
+        }
+        messages
+    };
+}
+
+/// Translate `text` to `lang`, returns `text` itself if there is no translation.
+pub fn translate(lang: &str, text: &str) -> String {
+    MESSAGES
+        .get(lang)
+        .and_then(|catalog| catalog.get(&normalize(text)))
+        .cloned()
+        .unwrap_or_else(|| text.to_string())
+}
+
+fn enum_entries(schema: &Schema) -> &'static [EnumEntry] {
+    match schema {
+        Schema::String(string_schema) => match string_schema.format {
+            Some(ApiStringFormat::Enum(entries)) => entries,
+            _ => &[],
+        },
+        _ => &[],
+    }
+}
+
+/// Labels of the values of all [LABELED_ENUMS], translated to `lang`.
+pub fn enum_labels(lang: &str) -> Map<String, Value> {
+    let mut result = Map::new();
+    for (name, schema) in LABELED_ENUMS {
+        let labels: Map<String, Value> = enum_entries(schema)
+            .iter()
+            .map(|entry| {
+                let label = translate(lang, entry.description);
+                (entry.value.to_string(), Value::from(label))
+            })
+            .collect();
+        result.insert(name.to_string(), labels.into());
+    }
+    result
+}
+
+/// Name of the notification template to use for `lang`. Falls back to the
+/// untranslated template if no localized variant is installed.
+pub fn localized_template(template: &str, lang: Option<&str>) -> String {
+    let lang = match lang {
+        Some(lang) if lang != DEFAULT_LANG => lang,
+        _ => return template.to_string(),
+    };
+
+    let localized = format!("{template}-{lang}");
+    let installed = ["subject", "body"].iter().all(|part| {
+        Path::new(TEMPLATE_DIR)
+            .join(format!("{localized}-{part}.txt.hbs"))
+            .exists()
+    });
+
+    if installed {
+        localized
+    } else {
+        template.to_string()
+    }
+}
+
+#[test]
+fn test_message_catalogs_complete() {
+    for (lang, _) in CATALOGS {
+        assert!(
+            MESSAGES.contains_key(lang),
+            "catalog '{lang}' did not parse"
+        );
+        for (name, schema) in LABELED_ENUMS {
+            for entry in enum_entries(schema) {
+                assert_ne!(
+                    translate(lang, entry.description),
+                    entry.description,
+                    "missing '{lang}' translation for {name} value '{}'",
+                    entry.value,
+                );
+            }
+        }
+    }
+}
@@ -6,10 +6,15 @@ use anyhow::{bail, Error};
 
 use proxmox_http::{client::Client, HttpOptions, ProxyConfig};
 
+pub mod agentx;
 pub mod apt;
+pub mod async_io;
+pub mod compression;
 pub mod config;
 pub mod disks;
 pub mod fs;
+pub mod i18n;
+pub mod serde_filter;
 
 mod shared_rate_limiter;
 pub use shared_rate_limiter::SharedRateLimiter;
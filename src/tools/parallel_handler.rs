@@ -1,15 +1,21 @@
 //! A thread pool which run a closure in parallel.
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, format_err, Error};
-use crossbeam_channel::{bounded, Sender};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+
+// how often idle threads check whether they should exit after the pool was scaled down
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
 
 /// A handle to send data to the worker thread (implements clone)
 pub struct SendHandle<I> {
     input: Sender<I>,
     abort: Arc<Mutex<Option<String>>>,
+    stats: Arc<Mutex<ParallelHandlerStats>>,
 }
 
 /// Returns the first error happened, if any
@@ -25,25 +31,131 @@ impl<I: Send> SendHandle<I> {
     /// Send data to the worker threads
     pub fn send(&self, input: I) -> Result<(), Error> {
         check_abort(&self.abort)?;
-        match self.input.send(input) {
+        let input = match self.input.try_send(input) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Disconnected(_)) => bail!("send failed - channel closed"),
+            Err(TrySendError::Full(input)) => input,
+        };
+
+        // all workers are busy, account the time we have to wait as back-pressure
+        let start = Instant::now();
+        let result = self.input.send(input);
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.blocked_sends += 1;
+            stats.blocked_time += start.elapsed();
+        }
+
+        match result {
             Ok(()) => Ok(()),
             Err(_) => bail!("send failed - channel closed"),
         }
     }
 }
 
+/// Processing statistics of a [`ParallelHandler`].
+#[derive(Clone, Debug, Default)]
+pub struct ParallelHandlerStats {
+    /// Number of currently running worker threads.
+    pub threads: usize,
+    /// Number of items waiting to be processed.
+    pub queued: usize,
+    /// Number of processed items.
+    pub processed: u64,
+    /// Total time spent in the handler.
+    pub busy_time: Duration,
+    /// Shortest time spent processing a single item.
+    pub min_time: Option<Duration>,
+    /// Longest time spent processing a single item.
+    pub max_time: Option<Duration>,
+    /// Number of sends which had to wait because all workers were busy.
+    pub blocked_sends: u64,
+    /// Total time senders had to wait for a free worker.
+    pub blocked_time: Duration,
+}
+
+impl ParallelHandlerStats {
+    /// Average time spent processing a single item.
+    pub fn average_time(&self) -> Option<Duration> {
+        if self.processed == 0 {
+            return None;
+        }
+        Some(self.busy_time.div_f64(self.processed as f64))
+    }
+
+    fn record(&mut self, elapsed: Duration) {
+        self.processed += 1;
+        self.busy_time += elapsed;
+        self.min_time = Some(self.min_time.map_or(elapsed, |min| min.min(elapsed)));
+        self.max_time = Some(self.max_time.map_or(elapsed, |max| max.max(elapsed)));
+    }
+}
+
+impl std::fmt::Display for ParallelHandlerStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} threads, {} items processed, {} queued",
+            self.threads, self.processed, self.queued
+        )?;
+        if let (Some(avg), Some(min), Some(max)) =
+            (self.average_time(), self.min_time, self.max_time)
+        {
+            write!(
+                f,
+                ", time per item {avg:.2?} (min {min:.2?}, max {max:.2?})"
+            )?;
+        }
+        write!(
+            f,
+            ", {} blocked sends ({:.2?})",
+            self.blocked_sends, self.blocked_time
+        )
+    }
+}
+
+// State shared between the pool and its worker threads
+struct PoolState {
+    name: String,
+    // number of threads which should be running
+    target_threads: AtomicUsize,
+    // number of threads which are currently running
+    running_threads: AtomicUsize,
+    // used to give every thread a unique name
+    next_thread_id: AtomicUsize,
+    abort: Arc<Mutex<Option<String>>>,
+    stats: Arc<Mutex<ParallelHandlerStats>>,
+}
+
+impl PoolState {
+    // Returns true if the calling thread should exit because the pool was scaled down.
+    fn should_exit(&self) -> bool {
+        let target = self.target_threads.load(Ordering::SeqCst);
+        self.running_threads
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+                (running > target).then_some(running - 1)
+            })
+            .is_ok()
+    }
+}
+
 /// A thread pool which run the supplied closure
 ///
 /// The send command sends data to the worker threads. If one handler
 /// returns an error, we mark the channel as failed and it is no
 /// longer possible to send data.
 ///
+/// The number of worker threads can be adjusted at runtime with
+/// 'set_threads()', processing statistics are available with 'stats()'.
+///
 /// When done, the 'complete()' method needs to be called to check for
 /// outstanding errors.
 pub struct ParallelHandler<I> {
     handles: Vec<JoinHandle<()>>,
     name: String,
     input: Option<SendHandle<I>>,
+    state: Arc<PoolState>,
+    spawn_fn: Box<dyn Fn(Arc<PoolState>) -> JoinHandle<()> + Send>,
 }
 
 impl<I> Clone for SendHandle<I> {
@@ -51,10 +163,48 @@ impl<I> Clone for SendHandle<I> {
         Self {
             input: self.input.clone(),
             abort: Arc::clone(&self.abort),
+            stats: Arc::clone(&self.stats),
         }
     }
 }
 
+fn spawn_worker<I, F>(input_rx: Receiver<I>, handler_fn: F, state: Arc<PoolState>) -> JoinHandle<()>
+where
+    I: Send + 'static,
+    F: Fn(I) -> Result<(), Error> + Send + 'static,
+{
+    let id = state.next_thread_id.fetch_add(1, Ordering::SeqCst);
+    state.running_threads.fetch_add(1, Ordering::SeqCst);
+
+    std::thread::Builder::new()
+        .name(format!("{} ({})", state.name, id))
+        .spawn(move || loop {
+            if state.should_exit() {
+                return;
+            }
+            let data = match input_rx.recv_timeout(IDLE_CHECK_INTERVAL) {
+                Ok(data) => data,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    state.running_threads.fetch_sub(1, Ordering::SeqCst);
+                    return;
+                }
+            };
+
+            let start = Instant::now();
+            let result = (handler_fn)(data);
+            state.stats.lock().unwrap().record(start.elapsed());
+
+            if let Err(err) = result {
+                let mut guard = state.abort.lock().unwrap();
+                if guard.is_none() {
+                    *guard = Some(err.to_string());
+                }
+            }
+        })
+        .unwrap()
+}
+
 impl<I: Send + 'static> ParallelHandler<I> {
     /// Create a new thread pool, each thread processing incoming data
     /// with 'handler_fn'.
@@ -62,41 +212,38 @@ impl<I: Send + 'static> ParallelHandler<I> {
     where
         F: Fn(I) -> Result<(), Error> + Send + Clone + 'static,
     {
-        let mut handles = Vec::new();
         let (input_tx, input_rx) = bounded::<I>(threads);
 
         let abort = Arc::new(Mutex::new(None));
+        let stats = Arc::new(Mutex::new(ParallelHandlerStats::default()));
+
+        let state = Arc::new(PoolState {
+            name: name.to_string(),
+            target_threads: AtomicUsize::new(threads),
+            running_threads: AtomicUsize::new(0),
+            next_thread_id: AtomicUsize::new(0),
+            abort: Arc::clone(&abort),
+            stats: Arc::clone(&stats),
+        });
 
-        for i in 0..threads {
-            let input_rx = input_rx.clone();
-            let abort = Arc::clone(&abort);
-            let handler_fn = handler_fn.clone();
-
-            handles.push(
-                std::thread::Builder::new()
-                    .name(format!("{} ({})", name, i))
-                    .spawn(move || loop {
-                        let data = match input_rx.recv() {
-                            Ok(data) => data,
-                            Err(_) => return,
-                        };
-                        if let Err(err) = (handler_fn)(data) {
-                            let mut guard = abort.lock().unwrap();
-                            if guard.is_none() {
-                                *guard = Some(err.to_string());
-                            }
-                        }
-                    })
-                    .unwrap(),
-            );
+        let spawn_fn: Box<dyn Fn(Arc<PoolState>) -> JoinHandle<()> + Send> =
+            Box::new(move |state| spawn_worker(input_rx.clone(), handler_fn.clone(), state));
+
+        let mut handles = Vec::new();
+        for _ in 0..threads {
+            handles.push(spawn_fn(Arc::clone(&state)));
         }
+
         Self {
             handles,
             name: name.to_string(),
             input: Some(SendHandle {
                 input: input_tx,
                 abort,
+                stats,
             }),
+            state,
+            spawn_fn,
         }
     }
 
@@ -111,6 +258,52 @@ impl<I: Send + 'static> ParallelHandler<I> {
         Ok(())
     }
 
+    /// Change the number of worker threads.
+    ///
+    /// Additional threads are started immediately, surplus threads exit
+    /// once they finished processing their current item.
+    pub fn set_threads(&mut self, threads: usize) -> Result<(), Error> {
+        if threads == 0 {
+            bail!("{} - need at least one worker thread", self.name);
+        }
+
+        self.state.target_threads.store(threads, Ordering::SeqCst);
+
+        let running = self.state.running_threads.load(Ordering::SeqCst);
+        for _ in running..threads {
+            self.handles.push((self.spawn_fn)(Arc::clone(&self.state)));
+        }
+
+        // forget about threads which already exited due to earlier scale downs
+        let (finished, running): (Vec<_>, Vec<_>) = self
+            .handles
+            .drain(..)
+            .partition(|handle| handle.is_finished());
+        self.handles = running;
+        for handle in finished {
+            let _ = handle.join();
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of worker threads the pool is scaled to.
+    pub fn threads(&self) -> usize {
+        self.state.target_threads.load(Ordering::SeqCst)
+    }
+
+    /// Returns a snapshot of the processing statistics.
+    pub fn stats(&self) -> ParallelHandlerStats {
+        let mut stats = self.state.stats.lock().unwrap().clone();
+        stats.threads = self.state.running_threads.load(Ordering::SeqCst);
+        stats.queued = self
+            .input
+            .as_ref()
+            .map(|input| input.input.len())
+            .unwrap_or(0);
+        stats
+    }
+
     /// Wait for worker threads to complete and check for errors
     pub fn complete(mut self) -> Result<(), Error> {
         let input = self.input.take().unwrap();
@@ -158,3 +351,37 @@ impl<I> Drop for ParallelHandler<I> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_threads() -> Result<(), Error> {
+        let sum = Arc::new(AtomicUsize::new(0));
+        let sum2 = Arc::clone(&sum);
+        let mut pool = ParallelHandler::new("test pool", 2, move |value: usize| {
+            sum2.fetch_add(value, Ordering::SeqCst);
+            Ok(())
+        });
+
+        for value in 1..=10 {
+            pool.send(value)?;
+        }
+        pool.set_threads(4)?;
+        assert_eq!(pool.threads(), 4);
+        for value in 11..=20 {
+            pool.send(value)?;
+        }
+        pool.set_threads(1)?;
+        assert_eq!(pool.threads(), 1);
+        assert!(pool.set_threads(0).is_err());
+        for value in 21..=30 {
+            pool.send(value)?;
+        }
+
+        pool.complete()?;
+        assert_eq!(sum.load(Ordering::SeqCst), (1..=30).sum());
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! Reduce serialized API results to a set of requested fields.
+//!
+//! List endpoints accept an optional `fields` parameter (see
+//! [`FIELD_MASK_SCHEMA`](pbs_api_types::FIELD_MASK_SCHEMA)), e.g. `backup-id,backup-time,size`.
+//! Nested fields are selected with dots, e.g. `verification.state`.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Error};
+use serde::Serialize;
+use serde_json::Value;
+
+/// A set of (possibly nested) field names to keep.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FieldMask {
+    // an empty map for a field means the whole value is kept
+    fields: BTreeMap<String, FieldMask>,
+}
+
+impl FieldMask {
+    /// Parse a comma separated list of field paths.
+    pub fn parse(list: &str) -> Result<Self, Error> {
+        let mut mask = Self::default();
+
+        for path in list.split(',') {
+            let path = path.trim();
+            if path.is_empty() {
+                bail!("empty field name in field list '{list}'");
+            }
+
+            let mut current = &mut mask;
+            for name in path.split('.') {
+                if name.is_empty() {
+                    bail!("empty field name in field path '{path}'");
+                }
+                current = current.fields.entry(name.to_string()).or_default();
+            }
+        }
+
+        Ok(mask)
+    }
+
+    /// Remove all fields not part of the mask. Arrays are filtered element wise.
+    pub fn apply(&self, value: &mut Value) {
+        if self.fields.is_empty() {
+            return;
+        }
+
+        match value {
+            Value::Array(list) => list.iter_mut().for_each(|item| self.apply(item)),
+            Value::Object(map) => {
+                map.retain(|key, _| self.fields.contains_key(key));
+                for (key, value) in map.iter_mut() {
+                    if let Some(mask) = self.fields.get(key) {
+                        mask.apply(value);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Serialize `data`, keeping only the fields listed in `fields`, if set.
+pub fn filter_fields<T: Serialize>(data: T, fields: Option<&str>) -> Result<Value, Error> {
+    let mut value = serde_json::to_value(data)?;
+
+    if let Some(fields) = fields {
+        FieldMask::parse(fields)?.apply(&mut value);
+    }
+
+    Ok(value)
+}
+
+#[test]
+fn test_field_mask() -> Result<(), Error> {
+    let data = serde_json::json!([
+        { "backup-id": "100", "size": 10, "verification": { "state": "ok", "upid": "x" } },
+        { "backup-id": "101", "files": [] },
+    ]);
+
+    let filtered = filter_fields(&data, Some("backup-id,verification.state"))?;
+    assert_eq!(
+        filtered,
+        serde_json::json!([
+            { "backup-id": "100", "verification": { "state": "ok" } },
+            { "backup-id": "101" },
+        ])
+    );
+
+    assert_eq!(filter_fields(&data, None)?, data);
+    assert!(FieldMask::parse("a,,b").is_err());
+
+    Ok(())
+}
@@ -2,8 +2,9 @@
 
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::Error;
 use cidr::IpInet;
@@ -13,7 +14,7 @@ use proxmox_section_config::SectionConfigData;
 
 use proxmox_time::{parse_daily_duration, DailyDuration, TmEditor};
 
-use pbs_api_types::TrafficControlRule;
+use pbs_api_types::{Authid, RateLimitConfig, TrafficControlRule};
 
 use pbs_config::ConfigVersionCache;
 
@@ -45,6 +46,42 @@ pub struct TrafficStat {
     pub rate_out: u64,
 }
 
+/// Traffic class of a backup or reader session
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrafficClass {
+    /// Backup sessions (chunk uploads)
+    Backup,
+    /// Restore (reader) sessions (chunk downloads)
+    Restore,
+}
+
+/// Number of active sessions per traffic class of a rule
+#[derive(Default)]
+struct TrafficClassCounter {
+    backup: AtomicUsize,
+    restore: AtomicUsize,
+}
+
+impl TrafficClassCounter {
+    fn count(&self, class: TrafficClass) -> &AtomicUsize {
+        match class {
+            TrafficClass::Backup => &self.backup,
+            TrafficClass::Restore => &self.restore,
+        }
+    }
+}
+
+/// Weighted limits of a rule with `restore-share` set
+///
+/// Restore sessions are limited to `restore-share` of the rule's rate,
+/// backup sessions to the remaining share (see [weighted_rates]), but only
+/// while sessions of the other class are active.
+struct WeightedLimits {
+    counter: Arc<TrafficClassCounter>,
+    backup_limiter: Option<SharedRateLimit>,
+    restore_limiter: Option<SharedRateLimit>,
+}
+
 /// Cache rules from `/etc/proxmox-backup/traffic-control.cfg`
 /// together with corresponding rate limiter implementation.
 pub struct TrafficControlCache {
@@ -56,6 +93,7 @@ pub struct TrafficControlCache {
     last_traffic_control_generation: usize,
     rules: Vec<ParsedTcRule>,
     limiter_map: HashMap<String, (Option<SharedRateLimit>, Option<SharedRateLimit>)>,
+    weighted_map: HashMap<String, WeightedLimits>,
     use_utc: bool, // currently only used for testing
 }
 
@@ -92,6 +130,39 @@ fn network_match_len(networks: &[IpInet], ip: &IpAddr) -> Option<u8> {
     match_len
 }
 
+impl ParsedTcRule {
+    /// Rules restricted to users or datastores cannot be applied on
+    /// connection level, because we do not know those at that time.
+    fn is_session_rule(&self) -> bool {
+        self.config.is_session_rule()
+    }
+
+    /// Returns the number of matched session properties (user, datastore),
+    /// or `None` if the rule does not apply to the session.
+    fn session_match(&self, auth_id: &Authid, store: &str) -> Option<u8> {
+        let mut matched = 0;
+
+        if let Some(ref users) = self.config.users {
+            let user_match = users
+                .iter()
+                .any(|user| user == auth_id || (!user.is_token() && user.user() == auth_id.user()));
+            if !user_match {
+                return None;
+            }
+            matched += 1;
+        }
+
+        if let Some(ref datastores) = self.config.datastores {
+            if !datastores.iter().any(|name| name == store) {
+                return None;
+            }
+            matched += 1;
+        }
+
+        Some(matched)
+    }
+}
+
 fn cannonical_ip(ip: IpAddr) -> IpAddr {
     // TODO: use std::net::IpAddr::to_cananical once stable
     match ip {
@@ -105,6 +176,40 @@ fn cannonical_ip(ip: IpAddr) -> IpAddr {
     }
 }
 
+fn share_of(rate: u64, percent: u8) -> u64 {
+    (rate * percent as u64) / 100
+}
+
+/// Splits the bandwidth of a rule between backup and restore sessions.
+///
+/// Both classes compete for the same disk and network, so the shares are taken from a single
+/// rate, the lower of `rate-in` and `rate-out` (bursts likewise). Returns the `(rate, burst)`
+/// of backups and restores, or `None` if the rule has no rate limit at all.
+fn weighted_rates(limit: &RateLimitConfig, restore_share: u8) -> Option<((u64, u64), (u64, u64))> {
+    let rate_in = limit.rate_in.map(|rate| rate.as_u64());
+    let rate_out = limit.rate_out.map(|rate| rate.as_u64());
+    let burst_in = limit.burst_in.map(|burst| burst.as_u64()).or(rate_in);
+    let burst_out = limit.burst_out.map(|burst| burst.as_u64()).or(rate_out);
+
+    let lower = |a: Option<u64>, b: Option<u64>| match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    let rate = lower(rate_in, rate_out)?;
+    let burst = lower(burst_in, burst_out).unwrap_or(rate);
+
+    Some((
+        (
+            share_of(rate, 100 - restore_share),
+            share_of(burst, 100 - restore_share),
+        ),
+        (
+            share_of(rate, restore_share),
+            share_of(burst, restore_share),
+        ),
+    ))
+}
+
 fn create_limiter(
     use_shared_memory: bool,
     name: &str,
@@ -125,6 +230,7 @@ impl TrafficControlCache {
             use_shared_memory: true,
             rules: Vec::new(),
             limiter_map: HashMap::new(),
+            weighted_map: HashMap::new(),
             last_traffic_control_generation: 0,
             last_update: 0,
             use_utc: false,
@@ -226,6 +332,8 @@ impl TrafficControlCache {
     fn update_config(&mut self, config: &SectionConfigData) -> Result<(), Error> {
         self.limiter_map
             .retain(|key, _value| config.sections.contains_key(key));
+        self.weighted_map
+            .retain(|key, _value| config.sections.contains_key(key));
 
         let rules: Vec<TrafficControlRule> = config.convert_to_typed_array("rule")?;
 
@@ -286,6 +394,8 @@ impl TrafficControlCache {
                 }
             }
 
+            self.update_weighted_limits(&rule)?;
+
             let mut timeframe = Vec::new();
 
             if let Some(ref timefram_list) = rule.timeframe {
@@ -320,6 +430,46 @@ impl TrafficControlCache {
         Ok(())
     }
 
+    fn update_weighted_limits(&mut self, rule: &TrafficControlRule) -> Result<(), Error> {
+        let restore_share = match rule.restore_share {
+            Some(share) if rule.is_session_rule() => share,
+            _ => {
+                self.weighted_map.remove(&rule.name);
+                return Ok(());
+            }
+        };
+
+        let (backup_limit, restore_limit) = match weighted_rates(&rule.limit, restore_share) {
+            Some((backup, restore)) => (Some(backup), Some(restore)),
+            None => (None, None),
+        };
+
+        let entry = self
+            .weighted_map
+            .entry(rule.name.clone())
+            .or_insert_with(|| WeightedLimits {
+                counter: Arc::new(TrafficClassCounter::default()),
+                backup_limiter: None,
+                restore_limiter: None,
+            });
+
+        for (limiter, limit, suffix) in [
+            (&mut entry.backup_limiter, backup_limit, "backup"),
+            (&mut entry.restore_limiter, restore_limit, "restore"),
+        ] {
+            match (limiter.as_ref(), limit) {
+                (Some(limiter), Some((rate, burst))) => limiter.update_rate(rate, burst),
+                (None, Some((rate, burst))) => {
+                    let name = format!("{}.{suffix}", rule.name);
+                    *limiter = Some(create_limiter(self.use_shared_memory, &name, rate, burst)?);
+                }
+                (_, None) => *limiter = None,
+            }
+        }
+
+        Ok(())
+    }
+
     /// Returns the rate limiter (if any) for the specified peer address.
     ///
     /// - Rules where timeframe does not match are skipped.
@@ -331,6 +481,30 @@ impl TrafficControlCache {
         &self,
         peer: SocketAddr,
         now: i64,
+    ) -> (&str, Option<SharedRateLimit>, Option<SharedRateLimit>) {
+        self.lookup_rate_limiter_impl(peer, None, now)
+    }
+
+    /// Returns the rate limiter (if any) for a backup or reader session.
+    ///
+    /// Only considers rules restricted to users or datastores. Rules
+    /// matching more session properties have higher priority, then the
+    /// same selection as in [Self::lookup_rate_limiter] applies.
+    pub fn lookup_session_rate_limiter(
+        &self,
+        peer: SocketAddr,
+        auth_id: &Authid,
+        store: &str,
+        now: i64,
+    ) -> (&str, Option<SharedRateLimit>, Option<SharedRateLimit>) {
+        self.lookup_rate_limiter_impl(peer, Some((auth_id, store)), now)
+    }
+
+    fn lookup_rate_limiter_impl(
+        &self,
+        peer: SocketAddr,
+        session: Option<(&Authid, &str)>,
+        now: i64,
     ) -> (&str, Option<SharedRateLimit>, Option<SharedRateLimit>) {
         let peer_ip = cannonical_ip(peer.ip());
 
@@ -351,7 +525,18 @@ impl TrafficControlCache {
                 continue;
             }
 
+            let session_match = match session {
+                None if rule.is_session_rule() => continue,
+                None => 0,
+                Some(_) if !rule.is_session_rule() => continue,
+                Some((auth_id, store)) => match rule.session_match(auth_id, store) {
+                    Some(matched) => matched,
+                    None => continue,
+                },
+            };
+
             if let Some(match_len) = network_match_len(&rule.networks, &peer_ip) {
+                let match_len = (session_match, match_len);
                 match last_rule_match {
                     None => last_rule_match = Some((rule, match_len)),
                     Some((_, last_len)) => {
@@ -379,8 +564,153 @@ impl TrafficControlCache {
     }
 }
 
+/// Registers an active session of a weighted rule (see [WeightedLimits]).
+struct WeightedSession {
+    class: TrafficClass,
+    counter: Arc<TrafficClassCounter>,
+    limiter: Option<SharedRateLimit>,
+}
+
+impl WeightedSession {
+    fn new(class: TrafficClass, limits: &WeightedLimits) -> Self {
+        limits.counter.count(class).fetch_add(1, Ordering::SeqCst);
+        let limiter = match class {
+            TrafficClass::Backup => limits.backup_limiter.clone(),
+            TrafficClass::Restore => limits.restore_limiter.clone(),
+        };
+        Self {
+            class,
+            counter: Arc::clone(&limits.counter),
+            limiter,
+        }
+    }
+
+    /// Returns the share limiter if sessions of the other class are active.
+    fn competing_limiter(&self) -> Option<&SharedRateLimit> {
+        let other = match self.class {
+            TrafficClass::Backup => TrafficClass::Restore,
+            TrafficClass::Restore => TrafficClass::Backup,
+        };
+        if self.counter.count(other).load(Ordering::SeqCst) > 0 {
+            self.limiter.as_ref()
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for WeightedSession {
+    fn drop(&mut self) {
+        self.counter
+            .count(self.class)
+            .fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Rate limiter for chunk transfers of a backup or reader session
+///
+/// Connection level limits are applied by the REST server. This is used
+/// for rules restricted to users or datastores.
+#[derive(Clone, Default)]
+pub struct SessionRateLimiter {
+    rule: Option<String>,
+    read_limiter: Option<SharedRateLimit>,
+    write_limiter: Option<SharedRateLimit>,
+    weighted: Option<Arc<WeightedSession>>,
+}
+
+impl SessionRateLimiter {
+    /// Lookup the session rate limits in the [TRAFFIC_CONTROL_CACHE].
+    ///
+    /// If the matching rule has a `restore-share`, the session counts as
+    /// active session of its traffic class until this (and all clones) are
+    /// dropped.
+    pub fn lookup(
+        peer: Option<SocketAddr>,
+        auth_id: &Authid,
+        store: &str,
+        class: TrafficClass,
+    ) -> Self {
+        let peer = match peer {
+            Some(peer) => peer,
+            None => return Self::default(),
+        };
+
+        let mut cache = TRAFFIC_CONTROL_CACHE.lock().unwrap();
+
+        let now = proxmox_time::epoch_i64();
+
+        cache.reload(now);
+
+        let (rule, read_limiter, write_limiter) =
+            cache.lookup_session_rate_limiter(peer, auth_id, store, now);
+
+        if read_limiter.is_none() && write_limiter.is_none() {
+            return Self::default();
+        }
+
+        let weighted = cache
+            .weighted_map
+            .get(rule)
+            .map(|limits| Arc::new(WeightedSession::new(class, limits)));
+
+        Self {
+            rule: Some(rule.to_string()),
+            read_limiter,
+            write_limiter,
+            weighted,
+        }
+    }
+
+    /// Name of the matching traffic control rule.
+    pub fn rule(&self) -> Option<&str> {
+        self.rule.as_deref()
+    }
+
+    /// Account incoming data, waiting if the rate limit is exceeded.
+    pub async fn throttle_in(&self, data_len: usize) {
+        self.throttle(&self.read_limiter, TrafficClass::Backup, data_len)
+            .await
+    }
+
+    /// Account outgoing data, waiting if the rate limit is exceeded.
+    pub async fn throttle_out(&self, data_len: usize) {
+        self.throttle(&self.write_limiter, TrafficClass::Restore, data_len)
+            .await
+    }
+
+    async fn throttle(
+        &self,
+        limiter: &Option<SharedRateLimit>,
+        direction: TrafficClass,
+        data_len: usize,
+    ) {
+        let now = Instant::now();
+        let mut delay = Duration::ZERO;
+
+        if let Some(limiter) = limiter {
+            delay = limiter.register_traffic(now, data_len as u64);
+        }
+
+        // backups are weighted on ingress, restores on egress
+        if let Some(ref weighted) = self.weighted {
+            if weighted.class == direction {
+                if let Some(limiter) = weighted.competing_limiter() {
+                    delay = delay.max(limiter.register_traffic(now, data_len as u64));
+                }
+            }
+        }
+
+        if delay > Duration::ZERO {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use proxmox_human_byte::HumanByte;
+
     use super::*;
 
     const fn make_test_time(mday: i32, hour: i32, min: i32) -> i64 {
@@ -486,4 +816,132 @@ rule: somewhere
 
         Ok(())
     }
+
+    #[test]
+    fn test_session_rule_match() -> Result<(), Error> {
+        let config_data = "
+rule: somewhere
+	network 0.0.0.0/0
+	rate-in 100000000
+	rate-out 100000000
+
+rule: store1
+	network 0.0.0.0/0
+	datastores store1
+	rate-in 50000000
+
+rule: user1
+	network 0.0.0.0/0
+	users user1@pbs
+	rate-out 20000000
+
+rule: user1-store1
+	network 192.168.2.0/24
+	users user1@pbs
+	datastores store1
+	rate-in 10000000
+";
+        let config = pbs_config::traffic_control::CONFIG.parse("testconfig", config_data)?;
+
+        let mut cache = TrafficControlCache::new();
+        cache.use_utc = true;
+        cache.use_shared_memory = false; // avoid permission problems in test environment
+
+        cache.update_config(&config)?;
+
+        const NOW: i64 = make_test_time(0, 8, 0);
+
+        let private = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 2, 35)), 1234);
+        let somewhere = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 1234);
+
+        let user1: Authid = "user1@pbs".parse()?;
+        let user1_token: Authid = "user1@pbs!backup".parse()?;
+        let user2: Authid = "user2@pbs".parse()?;
+
+        // session rules are never used on connection level
+        let (rule, _, _) = cache.lookup_rate_limiter(private, NOW);
+        assert_eq!(rule, "somewhere");
+
+        let (rule, read_limiter, write_limiter) =
+            cache.lookup_session_rate_limiter(somewhere, &user2, "store1", NOW);
+        assert_eq!(rule, "store1");
+        assert!(read_limiter.is_some());
+        assert!(write_limiter.is_none());
+
+        let (rule, _, _) = cache.lookup_session_rate_limiter(somewhere, &user2, "store2", NOW);
+        assert_eq!(rule, "");
+
+        let (rule, _, _) =
+            cache.lookup_session_rate_limiter(somewhere, &user1_token, "store2", NOW);
+        assert_eq!(rule, "user1");
+
+        let (rule, _, _) = cache.lookup_session_rate_limiter(private, &user1, "store1", NOW);
+        assert_eq!(rule, "user1-store1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_weighted_sessions() -> Result<(), Error> {
+        let config_data = "
+rule: store1
+	network 0.0.0.0/0
+	datastores store1
+	rate-in 100000000
+	rate-out 100000000
+	restore-share 70
+";
+        let config = pbs_config::traffic_control::CONFIG.parse("testconfig", config_data)?;
+
+        let mut cache = TrafficControlCache::new();
+        cache.use_shared_memory = false; // avoid permission problems in test environment
+
+        cache.update_config(&config)?;
+
+        let limits = cache.weighted_map.get("store1").unwrap();
+
+        let backup = WeightedSession::new(TrafficClass::Backup, limits);
+        assert!(backup.competing_limiter().is_none());
+
+        let restore = WeightedSession::new(TrafficClass::Restore, limits);
+        assert!(backup.competing_limiter().is_some());
+        assert!(restore.competing_limiter().is_some());
+
+        drop(backup);
+        assert!(restore.competing_limiter().is_none());
+        assert_eq!(limits.counter.backup.load(Ordering::SeqCst), 0);
+        assert_eq!(limits.counter.restore.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_weighted_rates() {
+        let limit =
+            |rate_in: Option<u64>, rate_out: Option<u64>, burst_in: Option<u64>| RateLimitConfig {
+                rate_in: rate_in.map(HumanByte::from),
+                burst_in: burst_in.map(HumanByte::from),
+                rate_out: rate_out.map(HumanByte::from),
+                burst_out: None,
+            };
+
+        // both shares are taken from the same rate, so they add up to it
+        assert_eq!(
+            weighted_rates(&limit(Some(1000), Some(1000), None), 70),
+            Some(((300, 300), (700, 700)))
+        );
+        assert_eq!(
+            weighted_rates(&limit(Some(2000), Some(1000), None), 70),
+            Some(((300, 300), (700, 700)))
+        );
+        assert_eq!(
+            weighted_rates(&limit(None, Some(1000), None), 30),
+            Some(((700, 700), (300, 300)))
+        );
+        assert_eq!(
+            weighted_rates(&limit(Some(1000), None, Some(5000)), 50),
+            Some(((500, 2500), (500, 2500)))
+        );
+        assert_eq!(weighted_rates(&limit(None, None, None), 50), None);
+    }
 }
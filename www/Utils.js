@@ -403,12 +403,21 @@ Ext.define('PBS.Utils', {
 	    'acme-revoke-cert': ['', gettext('Revoke Certificate')],
 	    backup: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Backup')),
 	    'barcode-label-media': [gettext('Drive'), gettext('Barcode-Label Media')],
+	    'bulk-delete': ['Datastore', gettext('Remove Snapshots')],
+	    'bulk-protect': ['Datastore', gettext('Protect Snapshots')],
+	    'bulk-unprotect': ['Datastore', gettext('Unprotect Snapshots')],
+	    'bulk-verify': ['Datastore', gettext('Verify Snapshots')],
 	    'catalog-media': [gettext('Drive'), gettext('Catalog Media')],
+	    'check-chunks': ['Datastore', gettext('Check Recent Chunks')],
+	    compressionstats: ['Datastore', gettext('Compression Statistics')],
 	    'delete-datastore': [gettext('Datastore'), gettext('Remove Datastore')],
 	    'delete-namespace': [gettext('Namespace'), gettext('Remove Namespace')],
 	    dircreate: [gettext('Directory Storage'), gettext('Create')],
+	    dedupstats: ['Datastore', gettext('Deduplication Statistics')],
 	    dirremove: [gettext('Directory'), gettext('Remove')],
 	    'eject-media': [gettext('Drive'), gettext('Eject Media')],
+	    'ephemeral-expiry': [null, gettext('Expire Ephemeral Datastores')],
+	    'expectation-check': [null, gettext('Backup Expectation Check')],
 	    "format-media": [gettext('Drive'), gettext('Format media')],
 	    "forget-group": [gettext('Group'), gettext('Remove Group')],
 	    garbage_collection: ['Datastore', gettext('Garbage Collect')],
@@ -420,6 +429,7 @@ Ext.define('PBS.Utils', {
 	    prune: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Prune')),
 	    prunejob: (type, id) => PBS.Utils.render_prune_job_worker_id(id, gettext('Prune Job')),
 	    reader: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Read Objects')),
+	    'recover-partial': ['Datastore', gettext('Recover Partial Snapshots')],
 	    'rewind-media': [gettext('Drive'), gettext('Rewind Media')],
 	    sync: ['Datastore', gettext('Remote Sync')],
 	    syncjob: [gettext('Sync Job'), gettext('Remote Sync')],
@@ -443,8 +453,10 @@ Ext.define('PBS.Utils', {
 
 	Proxmox.Utils.overrideNotificationFieldValue({
 	    'acme': gettext('ACME certificate renewal'),
+	    'backup-overdue': gettext('Backups overdue'),
 	    'gc': gettext('Garbage collection'),
 	    'package-updates': gettext('Package updates are available'),
+	    'partial-snapshots': gettext('Partial snapshot cleanup'),
 	    'prune': gettext('Prune job'),
 	    'sync': gettext('Sync job'),
 	    'tape-backup': gettext('Tape backup notifications'),
@@ -748,6 +760,8 @@ Ext.define('PBS.Utils', {
 		break;
 	    case 'offline': modeText = gettext("Offline");
 		break;
+	    case 'unmount': modeText = gettext("Unmounting");
+		break;
 	}
 	return `${modeText} ${extra}`;
     },
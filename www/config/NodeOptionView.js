@@ -54,5 +54,70 @@ Ext.define('PBS.NodeOptionView', {
 	    deleteEmpty: true,
 	    renderer: Proxmox.Utils.render_language,
 	},
+	{
+	    xtype: 'combobox',
+	    name: 'notification-lang',
+	    text: gettext('Notification Language'),
+	    defaultValue: '__default__',
+	    comboItems: Proxmox.Utils.language_array(),
+	    deleteEmpty: true,
+	    renderer: Proxmox.Utils.render_language,
+	},
+	{
+	    xtype: 'integer',
+	    name: 'backup-session-timeout',
+	    text: gettext('Backup Session Timeout (s)'),
+	    defaultValue: 900,
+	    minValue: 0,
+	    deleteEmpty: true,
+	},
+	{
+	    xtype: 'integer',
+	    name: 'backup-archive-timeout',
+	    text: gettext('Backup Archive Timeout (s)'),
+	    defaultValue: 0,
+	    minValue: 0,
+	    deleteEmpty: true,
+	},
+	{
+	    xtype: 'integer',
+	    name: 'reader-max-sessions-per-snapshot',
+	    text: gettext('Max. Reader Sessions per Snapshot'),
+	    defaultValue: 0,
+	    minValue: 0,
+	    deleteEmpty: true,
+	},
+	{
+	    xtype: 'integer',
+	    name: 'reader-max-sessions-per-datastore',
+	    text: gettext('Max. Reader Sessions per Datastore'),
+	    defaultValue: 0,
+	    minValue: 0,
+	    deleteEmpty: true,
+	},
+	{
+	    xtype: 'integer',
+	    name: 'reader-max-sessions-per-user',
+	    text: gettext('Max. Reader Sessions per User'),
+	    defaultValue: 0,
+	    minValue: 0,
+	    deleteEmpty: true,
+	},
+	{
+	    xtype: 'integer',
+	    name: 'reader-queue-timeout',
+	    text: gettext('Reader Queue Timeout (s)'),
+	    defaultValue: 300,
+	    minValue: 0,
+	    deleteEmpty: true,
+	},
+	{
+	    xtype: 'integer',
+	    name: 'job-start-limit',
+	    text: gettext('Max. Scheduled Job Starts per Minute'),
+	    defaultValue: 0,
+	    minValue: 0,
+	    deleteEmpty: true,
+	},
     ],
 });
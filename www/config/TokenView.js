@@ -3,6 +3,7 @@ Ext.define('pbs-tokens', {
     fields: [
 	'tokenid', 'tokenname', 'user', 'comment',
 	{ type: 'boolean', name: 'enable', defaultValue: true },
+	{ type: 'boolean', name: 'append-only', defaultValue: false },
 	{ type: 'date', dateFormat: 'timestamp', name: 'expire' },
     ],
     idProperty: 'tokenid',
@@ -13,6 +14,7 @@ Ext.define('pbs-users-with-tokens', {
     fields: [
 	'userid', 'firstname', 'lastname', 'email', 'comment',
 	{ type: 'boolean', name: 'enable', defaultValue: true },
+	{ type: 'boolean', name: 'append-only', defaultValue: false },
 	{ type: 'date', dateFormat: 'timestamp', name: 'expire' },
 	'tokens',
     ],
@@ -64,6 +66,7 @@ Ext.define('PBS.config.TokenView', {
 		    r.comment = token.comment;
 		    r.expire = token.expire;
 		    r.enable = token.enable;
+		    r['append-only'] = token['append-only'];
 		    records.push(r);
 		});
 	    });
@@ -202,6 +205,13 @@ Ext.define('PBS.config.TokenView', {
 	    renderer: Proxmox.Utils.format_boolean,
 	    dataIndex: 'enable',
 	},
+	{
+	    header: gettext('Append-only'),
+	    width: 100,
+	    sortable: true,
+	    renderer: Proxmox.Utils.format_boolean,
+	    dataIndex: 'append-only',
+	},
 	{
 	    header: gettext('Expire'),
 	    width: 80,
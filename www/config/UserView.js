@@ -3,6 +3,7 @@ Ext.define('pmx-users', {
     fields: [
 	'userid', 'firstname', 'lastname', 'email', 'comment', 'totp-locked',
 	{ type: 'boolean', name: 'enable', defaultValue: true },
+	{ type: 'boolean', name: 'append-only', defaultValue: false },
 	{ type: 'date', dateFormat: 'timestamp', name: 'expire' },
     ],
     idProperty: 'userid',
@@ -217,6 +218,13 @@ Ext.define('PBS.config.UserView', {
 	    renderer: Proxmox.Utils.format_boolean,
 	    dataIndex: 'enable',
 	},
+	{
+	    header: gettext('Append-only'),
+	    width: 100,
+	    sortable: true,
+	    renderer: Proxmox.Utils.format_boolean,
+	    dataIndex: 'append-only',
+	},
 	{
 	    header: gettext('Expire'),
 	    width: 80,
@@ -12,6 +12,7 @@ Ext.define('pbs-data-store-snapshots', {
 	'files',
 	'owner',
 	'verification',
+	'replication',
 	'fingerprint',
 	{ name: 'size', type: 'int', allowNull: true },
 	{ name: 'sortWeight', type: 'int', allowNull: true },
@@ -765,6 +766,28 @@ Ext.define('PBS.DataStoreContent', {
 	    atag.click();
 	},
 
+	showClientLog: function(tV, rI, cI, item, e, rec) {
+	    let me = this;
+	    let view = me.getView();
+	    if (rec.data.ty !== 'file') return;
+
+	    let snapshot = rec.parentNode.data;
+	    let params = {
+		'backup-id': snapshot['backup-id'],
+		'backup-type': snapshot['backup-type'],
+		'backup-time': (snapshot['backup-time'].getTime()/1000).toFixed(0),
+	    };
+	    if (view.namespace && view.namespace !== '') {
+		params.ns = view.namespace;
+	    }
+
+	    Ext.create('PBS.window.ClientLog', {
+		datastore: view.datastore,
+		params,
+		autoShow: true,
+	    });
+	},
+
 	// opens either a namespace or a pxar file-browser
 	openBrowser: function(tv, rI, Ci, item, e, rec) {
 	    let me = this;
@@ -1045,6 +1068,14 @@ Ext.define('PBS.DataStoreContent', {
 		    getClass: (v, m, { data }) => data.ty === 'file' ? 'fa fa-download' : 'pmx-hidden',
 		    isActionDisabled: (v, r, c, i, rec) => rec.data.ty !== 'file' || rec.data['crypt-mode'] > 2,
 		},
+		{
+		    handler: 'showClientLog',
+		    tooltip: gettext('Show Client Log'),
+		    getClass: (v, m, { data }) => data.ty === 'file' && data.filename === 'client.log.blob'
+			? 'fa fa-file-text-o' : 'pmx-hidden',
+		    isActionDisabled: (v, r, c, i, { data }) =>
+			data.ty !== 'file' || data.filename !== 'client.log.blob' || data['crypt-mode'] > 2,
+		},
 		{
 		    handler: 'openBrowser',
 		    tooltip: gettext('Browse'),
@@ -1201,6 +1232,24 @@ Ext.define('PBS.DataStoreContent', {
 			}
 		    }
 		}
+		let replication = record.data.replication;
+		if (record.data.ty === 'dir' && replication) {
+		    let state = replication['verify-state']?.state;
+		    let remoteState = replication['remote-verify-state'];
+		    let sync_time = Proxmox.Utils.render_timestamp(replication.time);
+		    tip += '<br>' + Ext.String.format(
+			gettext('Replicated from {0} on {1}, verify after sync: {2}'),
+			Ext.htmlEncode(replication.source),
+			sync_time,
+			state,
+		    );
+		    if (remoteState !== undefined) {
+			tip += ', ' + Ext.String.format(gettext('on remote: {0}'), remoteState);
+		    }
+		    if (state === 'ok' && (remoteState === undefined || remoteState === 'ok')) {
+			txt = `${txt} (${gettext('replicated')})`;
+		    }
+		}
 		return `<span data-qtip="${tip}">
 		    <i class="fa fa-fw fa-${iconCls}"></i> ${txt}
 		</span>`;
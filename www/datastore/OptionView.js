@@ -218,6 +218,46 @@ Ext.define('PBS.Datastore.Options', {
 		},
 	    },
 	},
+	"known-chunk-query": {
+	    required: true,
+	    header: gettext('Known Chunk Query'),
+	    defaultValue: false,
+	    renderer: Proxmox.Utils.format_boolean,
+	    editor: {
+		xtype: 'proxmoxWindowEdit',
+		title: gettext('Known Chunk Query'),
+		onlineHelp: 'datastore_known_chunk_query',
+		width: 350,
+		items: {
+		    xtype: 'proxmoxcheckbox',
+		    name: 'known-chunk-query',
+		    boxLabel: gettext("Allow clients to query chunks of the whole datastore"),
+		    defaultValue: false,
+		    deleteDefaultValue: true,
+		    deleteEmpty: true,
+		},
+	    },
+	},
+	"min-client-version": {
+	    required: true,
+	    header: gettext('Minimum Client Version'),
+	    renderer: v => v ? Ext.htmlEncode(v) : Proxmox.Utils.noneText,
+	    editor: {
+		xtype: 'proxmoxWindowEdit',
+		title: gettext('Minimum Client Version'),
+		onlineHelp: 'datastore_min_client_version',
+		width: 350,
+		items: {
+		    xtype: 'proxmoxtextfield',
+		    name: 'min-client-version',
+		    fieldLabel: gettext('Version'),
+		    emptyText: Proxmox.Utils.noneText,
+		    regex: /^\d+\.\d+(\.\d+)?$/,
+		    regexText: 'major.minor[.release]',
+		    deleteEmpty: true,
+		},
+	    },
+	},
 	"maintenance-mode": {
 	    required: true,
 	    header: gettext('Maintenance mode'),
@@ -5,6 +5,7 @@ Ext.define('pbs-tape-backup-job-status', {
 	{ name: 'eject-media', type: 'boolean' },
 	{ name: 'export-media-set', type: 'boolean' },
 	{ name: 'latest-only', type: 'boolean' },
+	{ name: 'since-last-run', type: 'boolean' },
 	'next-run', 'next-media-label', 'last-run-upid', 'last-run-state', 'last-run-endtime',
 	{
 	    name: 'duration',
@@ -221,6 +222,12 @@ Ext.define('PBS.config.TapeBackupJobView', {
 	    renderer: Proxmox.Utils.format_boolean,
 	    sortable: false,
 	},
+	{
+	    header: gettext('Since Last Run'),
+	    dataIndex: 'since-last-run',
+	    renderer: Proxmox.Utils.format_boolean,
+	    sortable: false,
+	},
 	{
 	    header: gettext('Backup Groups'),
 	    dataIndex: 'group-filter',
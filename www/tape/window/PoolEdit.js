@@ -70,6 +70,21 @@ Ext.define('PBS.TapeManagement.PoolEditWindow', {
 		    deleteEmpty: '{!isCreate}',
 		},
 	    },
+	    {
+		fieldLabel: gettext('Media Set Slots'),
+		xtype: 'proxmoxKVComboBox',
+		name: 'slots',
+		value: '__default__',
+		comboItems: [
+		    ['__default__', Proxmox.Utils.noneText],
+		    ['weekday', gettext('Per Weekday')],
+		    ['backup-type', gettext('Per Backup Type')],
+		    ['weekday,backup-type', gettext('Per Weekday and Backup Type')],
+		],
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+	    },
 	],
 
 	columnB: [
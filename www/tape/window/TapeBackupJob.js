@@ -198,6 +198,14 @@ Ext.define('PBS.TapeManagement.BackupJobEdit', {
 			    deleteEmpty: '{!isCreate}',
 			},
 		    },
+		    {
+			fieldLabel: gettext('Since Last Run'),
+			xtype: 'proxmoxcheckbox',
+			name: 'since-last-run',
+			cbind: {
+			    deleteEmpty: '{!isCreate}',
+			},
+		    },
 		    {
 			xtype: 'pbsNamespaceMaxDepth',
 			name: 'max-depth',
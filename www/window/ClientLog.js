@@ -0,0 +1,165 @@
+Ext.define('pbs-client-log-skipped', {
+    extend: 'Ext.data.Model',
+    fields: ['archive', 'path', 'reason'],
+});
+
+Ext.define('PBS.window.ClientLog', {
+    extend: 'Ext.window.Window',
+    alias: 'widget.pbsClientLog',
+
+    title: gettext('Client Log'),
+
+    width: 900,
+    height: 600,
+    resizable: true,
+    modal: true,
+    layout: 'fit',
+
+    // required: datastore, params
+    datastore: undefined,
+    params: {},
+
+    reasonText: {
+	'access-denied': gettext('Access denied'),
+	'vanished': gettext('Vanished while reading'),
+	'mount-point': gettext('Mount point'),
+	'cache-directory': gettext('Cache directory'),
+	'nodump': gettext('nodump attribute'),
+    },
+
+    items: [
+	{
+	    xtype: 'tabpanel',
+	    items: [
+		{
+		    title: gettext('Log'),
+		    itemId: 'log',
+		    xtype: 'textarea',
+		    readOnly: true,
+		    fieldStyle: 'font-family: monospace; white-space: pre;',
+		},
+		{
+		    title: gettext('Statistics'),
+		    itemId: 'stats',
+		    xtype: 'proxmoxObjectGrid',
+		    rows: {
+			'client-version': {
+			    header: gettext('Client Version'),
+			},
+			'start-time': {
+			    header: gettext('Start Time'),
+			    renderer: Proxmox.Utils.render_timestamp,
+			},
+			'duration': {
+			    header: gettext('Duration'),
+			    renderer: Proxmox.Utils.render_duration,
+			},
+			'read': {
+			    header: gettext('Read'),
+			    renderer: Proxmox.Utils.render_size,
+			},
+			'uploaded': {
+			    header: gettext('Uploaded'),
+			    renderer: Proxmox.Utils.render_size,
+			},
+			'files': {
+			    header: gettext('Files'),
+			    renderer: files => (files || [])
+				.map(file => Ext.htmlEncode(`${file.filename} (${Proxmox.Utils.format_size(file.size)})`))
+				.join('<br>'),
+			},
+		    },
+		},
+		{
+		    title: gettext('Skipped Files'),
+		    itemId: 'skipped',
+		    xtype: 'grid',
+		    store: {
+			model: 'pbs-client-log-skipped',
+			sorters: ['archive', 'path'],
+		    },
+		    emptyText: gettext('No files were skipped'),
+		    columns: [
+			{
+			    header: gettext('Archive'),
+			    dataIndex: 'archive',
+			    width: 150,
+			    renderer: Ext.htmlEncode,
+			},
+			{
+			    header: gettext('Path'),
+			    dataIndex: 'path',
+			    flex: 1,
+			    renderer: Ext.htmlEncode,
+			},
+			{
+			    header: gettext('Reason'),
+			    dataIndex: 'reason',
+			    width: 200,
+			    renderer: function(value) {
+				let win = this.up('window');
+				return win.reasonText[value] || Ext.htmlEncode(value);
+			    },
+			},
+		    ],
+		},
+	    ],
+	},
+    ],
+
+    loadLog: function() {
+	let me = this;
+
+	Proxmox.Utils.API2Request({
+	    url: `/admin/datastore/${me.datastore}/client-log`,
+	    method: 'GET',
+	    params: me.params,
+	    waitMsgTarget: me,
+	    failure: response => Ext.Msg.alert(gettext('Error'), response.htmlStatus),
+	    success: function(response) {
+		let data = response.result.data;
+
+		me.down('#log').setValue((data.log || []).join('\n'));
+
+		let stats = me.down('#stats');
+		let progress = data.progress || {};
+		stats.getStore().loadData(Object.entries({
+		    'client-version': data['client-version'],
+		    'start-time': data['start-time'],
+		    'duration': data.duration,
+		    'read': progress.read,
+		    'uploaded': progress.uploaded,
+		    'files': data.files,
+		})
+		    .filter(([_key, value]) => value !== undefined)
+		    .map(([key, value]) => ({ key, value })));
+
+		let skipped = [];
+		for (const [archive, entries] of Object.entries(data.skipped || {})) {
+		    for (const entry of entries) {
+			skipped.push({ archive, path: `/${entry.path}`, reason: entry.reason });
+		    }
+		}
+		for (const [archive, count] of Object.entries(data['skipped-dropped'] || {})) {
+		    skipped.push({
+			archive,
+			path: Ext.String.format(gettext('{0} more entries'), count),
+			reason: gettext('not recorded'),
+		    });
+		}
+		me.down('#skipped').getStore().setData(skipped);
+	    },
+	});
+    },
+
+    initComponent: function() {
+	let me = this;
+
+	if (!me.datastore) {
+	    throw "no datastore given";
+	}
+
+	me.callParent();
+	me.loadLog();
+    },
+});
@@ -160,6 +160,21 @@ Ext.define('PBS.window.PruneJobEdit', {
 		    editable: '{isCreate}',
 		},
 	    },
+	    {
+		xtype: 'proxmoxintegerfield',
+		fieldLabel: gettext('Splay (s)'),
+		name: 'splay',
+		minValue: 0,
+		maxValue: 86400,
+		emptyText: '0',
+		autoEl: {
+		    tag: 'div',
+		    'data-qtip': gettext('Delay scheduled runs by a random time of up to this many seconds'),
+		},
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+	    },
 	],
     },
 });
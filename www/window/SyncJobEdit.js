@@ -284,6 +284,17 @@ Ext.define('PBS.window.SyncJobEdit', {
 			uncheckedValue: false,
 			value: false,
 		    },
+		    {
+			fieldLabel: gettext('Verify synced'),
+			xtype: 'proxmoxcheckbox',
+			name: 'verify-synced',
+			autoEl: {
+			    tag: 'div',
+			    'data-qtip': gettext('Verify the synced snapshots in the local datastore after the sync'),
+			},
+			uncheckedValue: false,
+			value: false,
+		    },
 		],
 
 		columnB: [
@@ -308,6 +319,21 @@ Ext.define('PBS.window.SyncJobEdit', {
 			    editable: '{isCreate}',
 			},
 		    },
+		    {
+			xtype: 'proxmoxintegerfield',
+			fieldLabel: gettext('Splay (s)'),
+			name: 'splay',
+			minValue: 0,
+			maxValue: 86400,
+			emptyText: '0',
+			autoEl: {
+			    tag: 'div',
+			    'data-qtip': gettext('Delay scheduled runs by a random time of up to this many seconds'),
+			},
+			cbind: {
+			    deleteEmpty: '{!isCreate}',
+			},
+		    },
 		    {
 			fieldLabel: gettext('Transfer Last'),
 			xtype: 'pbsPruneKeepInput',
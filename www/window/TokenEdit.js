@@ -62,6 +62,13 @@ Ext.define('PBS.window.TokenEdit', {
 		defaultValue: 1,
 		checked: true,
 	    },
+	    {
+		xtype: 'proxmoxcheckbox',
+		fieldLabel: gettext('Append-only'),
+		name: 'append-only',
+		uncheckedValue: 0,
+		defaultValue: 0,
+	    },
 	],
 
 	columnB: [
@@ -200,6 +200,14 @@ Ext.define('PBS.window.TrafficControlEdit', {
 		values.network = [...new Set(values.network.split(/\s*,\s*/))];
 	    }
 
+	    for (const prop of ['users', 'datastores']) {
+		if (values[prop]) {
+		    values[prop] = [...new Set(values[prop].split(/\s*,\s*/))];
+		} else {
+		    PBS.Utils.delete_if_default(values, prop, '', isCreate);
+		}
+	    }
+
 	    if ('timeframe' in values && !values.timeframe) {
 		delete values.timeframe;
 	    }
@@ -284,6 +292,41 @@ Ext.define('PBS.window.TrafficControlEdit', {
 		    'data-qtip': gettext('A comma-separated list of networks to apply the (shared) limit.'),
 		},
 	    },
+	    {
+		xtype: 'proxmoxtextfield',
+		fieldLabel: gettext('Users'),
+		name: 'users',
+		emptyText: gettext('All'),
+		autoEl: {
+		    tag: 'div',
+		    'data-qtip': gettext('A comma-separated list of users or API tokens. Limits their backup and restore sessions.'),
+		},
+	    },
+	    {
+		xtype: 'proxmoxtextfield',
+		fieldLabel: gettext('Datastores'),
+		name: 'datastores',
+		emptyText: gettext('All'),
+		autoEl: {
+		    tag: 'div',
+		    'data-qtip': gettext('A comma-separated list of datastores. Limits backup and restore sessions on them.'),
+		},
+	    },
+	    {
+		xtype: 'proxmoxintegerfield',
+		fieldLabel: gettext('Restore Share (%)'),
+		name: 'restore-share',
+		minValue: 1,
+		maxValue: 99,
+		emptyText: gettext('None'),
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+		autoEl: {
+		    tag: 'div',
+		    'data-qtip': gettext('Bandwidth share of restore sessions while backups run at the same time. Requires users or datastores.'),
+		},
+	    },
 	    {
 		xtype: 'displayfield',
 		fieldLabel: gettext('Timeframes'),
@@ -440,6 +483,12 @@ Ext.define('PBS.window.TrafficControlEdit', {
 	    data.network = data.network.join(', ');
 	}
 
+	for (const prop of ['users', 'datastores']) {
+	    if (Ext.isArray(data[prop])) {
+		data[prop] = data[prop].join(', ');
+	    }
+	}
+
 	if (Ext.isArray(data.timeframe)) {
 	    data.timeframe = data.timeframe.join(';');
 	}
@@ -137,6 +137,13 @@ Ext.define('PBS.window.UserEdit', {
 		defaultValue: 1,
 		checked: true,
 	    },
+	    {
+		xtype: 'proxmoxcheckbox',
+		fieldLabel: gettext('Append-only'),
+		name: 'append-only',
+		uncheckedValue: 0,
+		defaultValue: 0,
+	    },
 	],
 
 	column2: [
@@ -165,6 +172,16 @@ Ext.define('PBS.window.UserEdit', {
 		    deleteEmpty: '{!isCreate}',
 		},
 	    },
+	    {
+		xtype: 'proxmoxKVComboBox',
+		name: 'lang',
+		fieldLabel: gettext('Language'),
+		value: '__default__',
+		comboItems: Proxmox.Utils.language_array(),
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+	    },
 	],
 
 	columnB: [
@@ -165,6 +165,32 @@ Ext.define('PBS.window.VerifyJobEdit', {
 		    editable: '{isCreate}',
 		},
 	    },
+	    {
+		xtype: 'proxmoxintegerfield',
+		fieldLabel: gettext('Splay (s)'),
+		name: 'splay',
+		minValue: 0,
+		maxValue: 86400,
+		emptyText: '0',
+		autoEl: {
+		    tag: 'div',
+		    'data-qtip': gettext('Delay scheduled runs by a random time of up to this many seconds'),
+		},
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+	    },
+	    {
+		xtype: 'proxmoxcheckbox',
+		fieldLabel: gettext('Signed Report'),
+		name: 'attest',
+		uncheckedValue: false,
+		value: false,
+		autoEl: {
+		    tag: 'div',
+		    'data-qtip': gettext('Store a report of the verified snapshots, signed with the server key'),
+		},
+	    },
 	],
     },
 });